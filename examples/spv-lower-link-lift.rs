@@ -75,6 +75,10 @@ fn main() -> std::io::Result<()> {
             eprintln!("legalize::structurize_func_cfgs");
             after_pass("structurize_func_cfgs", &module)?;
 
+            eprint_duration(|| spirt::passes::legalize::simplify_variable_ptrs(&mut module));
+            eprintln!("legalize::simplify_variable_ptrs");
+            after_pass("simplify_variable_ptrs", &module)?;
+
             eprint_duration(|| spirt::passes::link::resolve_imports(&mut module));
             eprintln!("link::resolve_imports");
             after_pass("resolve_imports", &module)?;
@@ -0,0 +1,293 @@
+//! Alias analysis, i.e. determining whether two pointers (in a function) may
+//! refer to overlapping memory, for memory optimizations (e.g. load forwarding
+//! or dead store elimination) to query.
+
+use crate::visit::Visitor;
+use crate::{
+    spv, Attr, AttrSet, Const, ConstCtor, Context, DataInst, DataInstKind, Func, FuncDefBody,
+    GlobalVar, Module, Type, Value,
+};
+use rustc_hash::{FxHashMap, FxHashSet};
+use smallvec::SmallVec;
+use std::rc::Rc;
+
+/// The result of an [`AliasAnalysis`] query about two pointers.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum AliasResult {
+    /// The pointers never refer to overlapping memory.
+    NoAlias,
+
+    /// The pointers may (or may not) refer to overlapping memory.
+    MayAlias,
+
+    /// The pointers always refer to the exact same memory.
+    MustAlias,
+}
+
+/// Alias analysis for the pointers in a function, based on the "memory object"
+/// each pointer was derived from (using `OpAccessChain`/`OpInBoundsAccessChain`),
+/// and the indices used to derive it, with these rules:
+/// * pointers into distinct [`GlobalVar`]s (or function-local `OpVariable`s)
+///   never alias, unless either of the [`GlobalVar`]s is decorated `Aliased`
+/// * pointers into the same memory object, through access chains with distinct
+///   constant indices (at the same depth), never alias
+/// * pointer parameters may alias anything other than function-local
+///   `OpVariable`s, unless they're decorated `Restrict`
+/// * any other pointers (e.g. loaded from memory, or `Select`/`Loop` outputs)
+///   may alias anything
+///
+/// Queries take the function body, as the [`DataInst`]s pointers are derived
+/// through are only inspected on demand, which allows them to be interleaved
+/// with transformations of the function body (as long as those transformations
+/// don't change the memory object any existing pointer is derived from).
+//
+// FIXME(eddyb) support `qptr` (i.e. `QPtrOp::Offset`/`QPtrOp::DynOffset`), and
+// `OpPtrAccessChain` (with pointers into arrays, or physical pointers).
+pub struct AliasAnalysis {
+    cx: Rc<Context>,
+
+    /// Decorations of the [`GlobalVar`]s used in the function.
+    global_var_aliasing: FxHashMap<GlobalVar, Aliasing>,
+
+    /// Decorations of the function's parameters.
+    param_aliasing: SmallVec<[Aliasing; 2]>,
+}
+
+/// Aliasing-related decoration of a memory object declaration.
+#[derive(Copy, Clone, Default, PartialEq, Eq)]
+enum Aliasing {
+    #[default]
+    Default,
+
+    /// Decorated with `Restrict`, i.e. its memory is only accessed through
+    /// pointers derived from it.
+    Restrict,
+
+    /// Decorated with `Aliased`, i.e. its memory may be accessed through other
+    /// memory object declarations.
+    Aliased,
+}
+
+impl Aliasing {
+    fn from_attrs(cx: &Context, attrs: AttrSet) -> Self {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        let mut aliasing = Aliasing::Default;
+        for attr in &cx[attrs].attrs {
+            if let Attr::SpvAnnotation(spv::Inst { opcode, imms }) = attr {
+                if *opcode != wk.OpDecorate {
+                    continue;
+                }
+                if imms[..] == [spv::Imm::Short(wk.Decoration, wk.Restrict)] {
+                    aliasing = Aliasing::Restrict;
+                } else if imms[..] == [spv::Imm::Short(wk.Decoration, wk.Aliased)] {
+                    aliasing = Aliasing::Aliased;
+                }
+            }
+        }
+        aliasing
+    }
+}
+
+/// The "memory object" a pointer was derived from.
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum MemoryObject {
+    GlobalVar(GlobalVar),
+
+    /// Function-local `OpVariable`.
+    LocalVar(DataInst),
+
+    /// Pointer parameter of the function (i.e. an input of its body).
+    Param(u32),
+
+    /// Any other pointer, which can't be traced back to its memory object.
+    Unknown(Value),
+}
+
+impl AliasAnalysis {
+    /// Prepare for alias queries about pointers in the body of `func`.
+    pub fn compute(module: &Module, func: Func) -> Self {
+        let cx = module.cx();
+        let func_decl = &module.funcs[func];
+
+        let mut collector = GlobalVarCollector {
+            cx: &cx,
+            global_vars: FxHashSet::default(),
+        };
+        collector.visit_func_decl(func_decl);
+        let global_var_aliasing = collector
+            .global_vars
+            .into_iter()
+            .map(|gv| (gv, Aliasing::from_attrs(&cx, module.global_vars[gv].attrs)))
+            .collect();
+
+        let param_aliasing = func_decl
+            .params
+            .iter()
+            .map(|param| Aliasing::from_attrs(&cx, param.attrs))
+            .collect();
+
+        AliasAnalysis {
+            cx,
+            global_var_aliasing,
+            param_aliasing,
+        }
+    }
+
+    /// Determine whether the pointers `a` and `b` (both used in `func_def_body`,
+    /// i.e. the body of the function this analysis was computed for) may refer
+    /// to overlapping memory.
+    pub fn alias(&self, func_def_body: &FuncDefBody, a: Value, b: Value) -> AliasResult {
+        if a == b {
+            return AliasResult::MustAlias;
+        }
+
+        let (a_object, a_indices) = self.memory_object_and_indices(func_def_body, a);
+        let (b_object, b_indices) = self.memory_object_and_indices(func_def_body, b);
+        if a_object != b_object {
+            return if self.memory_objects_may_alias(a_object, b_object) {
+                AliasResult::MayAlias
+            } else {
+                AliasResult::NoAlias
+            };
+        }
+
+        for (&a_idx, &b_idx) in a_indices.iter().zip(&b_indices) {
+            if a_idx == b_idx {
+                continue;
+            }
+            return match (self.const_index(a_idx), self.const_index(b_idx)) {
+                (Some(a_idx), Some(b_idx)) if a_idx != b_idx => AliasResult::NoAlias,
+                _ => AliasResult::MayAlias,
+            };
+        }
+
+        // NOTE(eddyb) if one pointer has more indices, it points into (part of)
+        // the memory the other one points to, which is only a partial overlap.
+        if a_indices.len() == b_indices.len() {
+            AliasResult::MustAlias
+        } else {
+            AliasResult::MayAlias
+        }
+    }
+
+    /// Returns `true` unless the pointers `a` and `b` never refer to overlapping
+    /// memory (i.e. [`AliasAnalysis::alias`] returns [`AliasResult::NoAlias`]).
+    pub fn may_alias(&self, func_def_body: &FuncDefBody, a: Value, b: Value) -> bool {
+        self.alias(func_def_body, a, b) != AliasResult::NoAlias
+    }
+
+    /// Trace `ptr` back to its memory object, through access chains, collecting
+    /// all of their indices (in order, starting from the memory object).
+    fn memory_object_and_indices(
+        &self,
+        func_def_body: &FuncDefBody,
+        mut ptr: Value,
+    ) -> (MemoryObject, SmallVec<[Value; 4]>) {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        // NOTE(eddyb) access chains are visited in reverse (i.e. starting with
+        // the innermost one), so their indices are collected in reverse, too.
+        let mut rev_indices = SmallVec::<[_; 4]>::new();
+        let memory_object = loop {
+            match ptr {
+                Value::Const(ct) => match self.cx[ct].ctor {
+                    ConstCtor::PtrToGlobalVar(gv) => break MemoryObject::GlobalVar(gv),
+                    _ => break MemoryObject::Unknown(ptr),
+                },
+                Value::ControlRegionInput { region, input_idx } if region == func_def_body.body => {
+                    break MemoryObject::Param(input_idx);
+                }
+                Value::DataInstOutput(inst) => {
+                    let inst_def = &func_def_body.data_insts[inst];
+                    let opcode = match &inst_def.kind {
+                        DataInstKind::SpvInst(spv_inst) => spv_inst.opcode,
+                        _ => break MemoryObject::Unknown(ptr),
+                    };
+                    if opcode == wk.OpVariable {
+                        break MemoryObject::LocalVar(inst);
+                    }
+                    if ![wk.OpAccessChain, wk.OpInBoundsAccessChain].contains(&opcode) {
+                        break MemoryObject::Unknown(ptr);
+                    }
+                    rev_indices.extend(inst_def.inputs[1..].iter().rev().copied());
+                    ptr = inst_def.inputs[0];
+                }
+                _ => break MemoryObject::Unknown(ptr),
+            }
+        };
+        rev_indices.reverse();
+        (memory_object, rev_indices)
+    }
+
+    /// Returns `false` only if the distinct memory objects `a` and `b` can never
+    /// have any memory in common.
+    fn memory_objects_may_alias(&self, a: MemoryObject, b: MemoryObject) -> bool {
+        let param_aliasing = |param_idx: u32| {
+            self.param_aliasing
+                .get(param_idx as usize)
+                .copied()
+                .unwrap_or_default()
+        };
+        match (a, b) {
+            (MemoryObject::Unknown(_), _) | (_, MemoryObject::Unknown(_)) => true,
+
+            // NOTE(eddyb) function-local variables can only be accessed through
+            // pointers derived from them, within the function (or its callees),
+            // so no other memory object (or pointer parameter) can alias them.
+            (MemoryObject::LocalVar(_), _) | (_, MemoryObject::LocalVar(_)) => false,
+
+            (MemoryObject::GlobalVar(a), MemoryObject::GlobalVar(b)) => [a, b].iter().any(|gv| {
+                self.global_var_aliasing
+                    .get(gv)
+                    .copied()
+                    .unwrap_or_default()
+                    == Aliasing::Aliased
+            }),
+
+            (MemoryObject::Param(a), MemoryObject::Param(b)) => {
+                param_aliasing(a) != Aliasing::Restrict && param_aliasing(b) != Aliasing::Restrict
+            }
+            (MemoryObject::Param(param_idx), MemoryObject::GlobalVar(_))
+            | (MemoryObject::GlobalVar(_), MemoryObject::Param(param_idx)) => {
+                param_aliasing(param_idx) != Aliasing::Restrict
+            }
+        }
+    }
+
+    /// Get the value of `idx`, if it's an integer constant.
+    fn const_index(&self, idx: Value) -> Option<u64> {
+        match idx {
+            Value::Const(ct) => match spv::fold::const_splat_value(&self.cx, ct)? {
+                spv::fold::ScalarValue::Int { bits, .. } => Some(bits),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+/// [`Visitor`] collecting every [`GlobalVar`] used (as a pointer constant).
+struct GlobalVarCollector<'a> {
+    cx: &'a Context,
+
+    global_vars: FxHashSet<GlobalVar>,
+}
+
+impl<'a> Visitor<'a> for GlobalVarCollector<'a> {
+    fn visit_attr_set_use(&mut self, _attrs: AttrSet) {}
+    fn visit_type_use(&mut self, _ty: Type) {}
+    fn visit_const_use(&mut self, ct: Const) {
+        let ct_def = &self.cx[ct];
+        match ct_def.ctor {
+            ConstCtor::PtrToGlobalVar(gv) => {
+                self.global_vars.insert(gv);
+            }
+            _ => self.visit_const_def(ct_def),
+        }
+    }
+    fn visit_global_var_use(&mut self, gv: GlobalVar) {
+        self.global_vars.insert(gv);
+    }
+    fn visit_func_use(&mut self, _func: Func) {}
+}
@@ -0,0 +1,18 @@
+//! Assembler: parses SPIR-T (in the plain text form printed by `spirt-dis`),
+//! and lifts it to a SPIR-V module.
+
+use std::rc::Rc;
+
+fn main() -> std::io::Result<()> {
+    match &std::env::args().collect::<Vec<_>>()[..] {
+        [_, in_file, out_file] => {
+            let module =
+                spirt::Module::parse_from_spirt_file(Rc::new(spirt::Context::new()), in_file)?;
+            module.lift_to_spv_file(out_file)
+        }
+        args => {
+            eprintln!("Usage: {} IN OUT", args[0]);
+            std::process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,87 @@
+//! Disassembler: lowers a SPIR-V module to SPIR-T, and prints it (as plain
+//! text, which `spirt-as` can read back, or as HTML).
+
+use std::fs;
+use std::rc::Rc;
+
+fn usage(arg0: &str) -> ! {
+    eprintln!("Usage: {arg0} [OPTIONS] IN [OUT]");
+    eprintln!();
+    eprintln!("Prints the SPIR-V module IN as SPIR-T, to OUT (or stdout, by default).");
+    eprintln!();
+    eprintln!("Options:");
+    eprintln!("  --html                 output an HTML document, instead of plain text");
+    eprintln!("  --markdown             output a Markdown document, instead of plain text");
+    eprintln!("  --show-original-ids    show the original SPIR-V IDs of all definitions");
+    eprintln!("  --sort-by-export-name  group definitions by kind, and sort exports by name");
+    eprintln!("  --show-lifted-insts    show the SPIR-V instructions each definition lifts to");
+    eprintln!("  --permissive           keep going after unsupported SPIR-V (where possible)");
+    std::process::exit(1);
+}
+
+fn main() -> std::io::Result<()> {
+    let args = std::env::args().collect::<Vec<_>>();
+
+    let mut html = false;
+    let mut markdown = false;
+    let mut show_lifted_insts = false;
+    let mut lower_options = spirt::spv::lower::LowerOptions::default();
+    let mut options = spirt::print::PrintOptions::default();
+    let mut paths = vec![];
+    for arg in &args[1..] {
+        match &arg[..] {
+            "--html" => html = true,
+            "--markdown" => markdown = true,
+            "--show-original-ids" => options.show_spv_original_ids = true,
+            "--sort-by-export-name" => {
+                options.node_order = spirt::print::NodeOrder::SortedByExportName;
+            }
+            "--show-lifted-insts" => show_lifted_insts = true,
+            "--permissive" => lower_options.permissive = true,
+            _ if arg.starts_with('-') => usage(&args[0]),
+            _ => paths.push(arg),
+        }
+    }
+    if html && markdown {
+        usage(&args[0]);
+    }
+    // NOTE(eddyb) only the HTML/Markdown outputs (meant for viewing) elide the
+    // elements of large constant aggregates, as plain text output has to remain
+    // parseable by `spirt-as`, which can't handle elided elements.
+    options.max_const_aggregate_elements = if html || markdown { Some(64) } else { None };
+    let (in_file, out_file) = match paths[..] {
+        [in_file] => (in_file, None),
+        [in_file, out_file] => (in_file, Some(out_file)),
+        _ => usage(&args[0]),
+    };
+
+    let module = spirt::Module::lower_from_spv_module_parser_with_options(
+        Rc::new(spirt::Context::new()),
+        spirt::spv::read::ModuleParser::read_from_spv_file(in_file)?,
+        &lower_options,
+    )?;
+    if show_lifted_insts {
+        let (_, source_map) = module.lift_to_spv_module_emitter_with_source_map()?;
+        options.spv_source_map = Some(Rc::new(source_map));
+    }
+    let plan = spirt::print::Plan::for_module(&module);
+
+    // FIXME(eddyb) don't allocate whole `String`s here.
+    let output = if markdown {
+        plan.pretty_print_to_markdown(&options)
+    } else if html {
+        plan.pretty_print_with_options(&options)
+            .render_to_html()
+            .with_dark_mode_support()
+            .to_html_doc()
+    } else {
+        plan.pretty_print_with_options(&options).to_string()
+    };
+    match out_file {
+        Some(out_file) => fs::write(out_file, output),
+        None => {
+            println!("{output}");
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,201 @@
+//! Call graph, i.e. which [`Func`]s call which other [`Func`]s (through
+//! [`DataInstKind::FuncCall`]), in a whole [`Module`].
+
+use crate::cfg::strongly_connected_components;
+use crate::func_at::FuncAt;
+use crate::passes::legalize::reachable_funcs;
+use crate::{
+    ControlNodeKind, ControlRegion, DataInstKind, DeclDef, ExportKey, Exportee, Func, FuncDefBody,
+    FxIndexMap, FxIndexSet, Import, Module,
+};
+use rustc_hash::FxHashMap;
+use smallvec::SmallVec;
+
+/// The call graph of a [`Module`], with one node for every [`Func`] reachable
+/// from the module's exports, and an edge for each (unique) caller-callee pair.
+///
+/// Callees which are imports (using [`Import::LinkName`]) are resolved to the
+/// definition exported from the same module with that name, if there is one
+/// (i.e. the same way [`inline_calls`](crate::passes::inline::inline_calls) does).
+///
+/// Additionally, the strongly-connected components (SCCs) of the call graph
+/// are computed (using Tarjan's algorithm), which correspond to sets of
+/// mutually recursive functions, and provide a "bottom-up" order (where each
+/// function comes after all of its callees, other than those in its own SCC).
+pub struct CallGraph {
+    /// Callees of each function, in the order of their first call.
+    callees: FxIndexMap<Func, FxIndexSet<Func>>,
+
+    /// Callers of each function (only present for functions with any callers).
+    callers: FxHashMap<Func, FxIndexSet<Func>>,
+
+    /// SCCs, in bottom-up order (i.e. all callees before their callers).
+    sccs: Vec<SmallVec<[Func; 1]>>,
+
+    /// Index (into `sccs`) of the SCC each function belongs to.
+    scc_of: FxHashMap<Func, usize>,
+}
+
+impl CallGraph {
+    pub fn compute(module: &Module) -> Self {
+        let callees: FxIndexMap<_, FxIndexSet<_>> = reachable_funcs(module)
+            .into_iter()
+            .map(|func| {
+                let callees = match &module.funcs[func].def {
+                    DeclDef::Present(func_def_body) => calls_in_func(func_def_body)
+                        .map(|callee| resolve_callee(module, callee))
+                        .collect(),
+                    DeclDef::Imported(_) => FxIndexSet::default(),
+                };
+                (func, callees)
+            })
+            .collect();
+
+        let mut callers: FxHashMap<_, FxIndexSet<_>> = FxHashMap::default();
+        for (&caller, callees) in &callees {
+            for &callee in callees {
+                callers.entry(callee).or_default().insert(caller);
+            }
+        }
+
+        // FIXME(eddyb) `strongly_connected_components` requires slices of
+        // targets, which `FxIndexSet` can't provide (with this `indexmap` version).
+        let nodes: Vec<_> = callees.keys().copied().collect();
+        let targets: FxHashMap<_, Vec<_>> = callees
+            .iter()
+            .map(|(&func, callees)| (func, callees.iter().copied().collect()))
+            .collect();
+
+        // NOTE(eddyb) Tarjan's algorithm produces SCCs in reverse topological
+        // order, i.e. all the callees of a SCC are in earlier SCCs.
+        let sccs: Vec<SmallVec<[_; 1]>> = strongly_connected_components(&nodes, &|func| {
+            targets.get(&func).map_or(&[][..], |targets| &targets[..])
+        })
+        .into_iter()
+        .map(|mut scc| {
+            // NOTE(eddyb) Tarjan's algorithm pops SCC members off of a stack,
+            // so this restores the order they were first reached in.
+            scc.reverse();
+            scc.into_iter().collect()
+        })
+        .collect();
+
+        let scc_of = sccs
+            .iter()
+            .enumerate()
+            .flat_map(|(scc_idx, scc)| scc.iter().map(move |&func| (func, scc_idx)))
+            .collect();
+
+        CallGraph {
+            callees,
+            callers,
+            sccs,
+            scc_of,
+        }
+    }
+
+    /// Iterate over all the functions in the call graph (i.e. all those
+    /// reachable from the module's exports).
+    pub fn funcs(&self) -> impl ExactSizeIterator<Item = Func> + '_ {
+        self.callees.keys().copied()
+    }
+
+    /// Returns `true` if `func` is in the call graph (i.e. it's reachable from
+    /// the module's exports).
+    pub fn contains(&self, func: Func) -> bool {
+        self.callees.contains_key(&func)
+    }
+
+    /// Iterate over all the (unique) functions called by `func`.
+    pub fn callees(&self, func: Func) -> impl Iterator<Item = Func> + '_ {
+        self.callees.get(&func).into_iter().flatten().copied()
+    }
+
+    /// Iterate over all the (unique) functions calling `func`.
+    pub fn callers(&self, func: Func) -> impl Iterator<Item = Func> + '_ {
+        self.callers.get(&func).into_iter().flatten().copied()
+    }
+
+    /// Returns `true` if `caller` calls `callee` (directly).
+    pub fn calls(&self, caller: Func, callee: Func) -> bool {
+        self.callees
+            .get(&caller)
+            .is_some_and(|callees| callees.contains(&callee))
+    }
+
+    /// Iterate over all the strongly-connected components (SCCs), in bottom-up
+    /// order (i.e. each SCC comes after the SCCs of all of its callees).
+    pub fn sccs_bottom_up(&self) -> impl DoubleEndedIterator<Item = &[Func]> + '_ {
+        self.sccs.iter().map(|scc| &scc[..])
+    }
+
+    /// Get the strongly-connected component (SCC) containing `func`, i.e. the
+    /// set of functions mutually recursive with it (including `func` itself).
+    pub fn scc_of(&self, func: Func) -> Option<&[Func]> {
+        self.scc_of
+            .get(&func)
+            .map(|&scc_idx| &self.sccs[scc_idx][..])
+    }
+
+    /// Returns `true` if `func` can (directly or indirectly) call itself.
+    pub fn is_recursive(&self, func: Func) -> bool {
+        self.scc_of(func).is_some_and(|scc| scc.len() > 1) || self.calls(func, func)
+    }
+
+    /// Iterate over all the functions in the call graph, in bottom-up order,
+    /// i.e. each function comes after all of its callees (other than those
+    /// which are mutually recursive with it, see [`CallGraph::sccs_bottom_up`]).
+    pub fn bottom_up(&self) -> impl DoubleEndedIterator<Item = Func> + '_ {
+        self.sccs.iter().flatten().copied()
+    }
+}
+
+/// Get the definition `callee` resolves to (i.e. the function exported with
+/// the same name as its [`Import::LinkName`], if it's such an import).
+pub(crate) fn resolve_callee(module: &Module, callee: Func) -> Func {
+    match module.funcs[callee].def {
+        DeclDef::Imported(Import::LinkName(name)) => {
+            match module.exports.get(&ExportKey::LinkName(name)) {
+                Some(&Exportee::Func(def_func)) => def_func,
+                _ => callee,
+            }
+        }
+        DeclDef::Present(_) => callee,
+    }
+}
+
+/// Get all the functions called from `func_def_body` (as [`DataInstKind::FuncCall`]).
+pub(crate) fn calls_in_func(func_def_body: &FuncDefBody) -> impl Iterator<Item = Func> + '_ {
+    let regions = match &func_def_body.unstructured_cfg {
+        None => vec![func_def_body.body],
+        Some(cfg) => cfg.rev_post_order(func_def_body).collect(),
+    };
+    let mut calls = vec![];
+    for region in regions {
+        collect_calls_in_region(func_def_body.at(region), &mut calls);
+    }
+    calls.into_iter()
+}
+
+fn collect_calls_in_region(func_at_region: FuncAt<'_, ControlRegion>, calls: &mut Vec<Func>) {
+    for func_at_control_node in func_at_region.at_children() {
+        match &func_at_control_node.def().kind {
+            &ControlNodeKind::Block { insts } => {
+                for func_at_inst in func_at_control_node.at(insts) {
+                    if let DataInstKind::FuncCall(callee) = func_at_inst.def().kind {
+                        calls.push(callee);
+                    }
+                }
+            }
+            ControlNodeKind::Select { cases, .. } => {
+                for &case in cases {
+                    collect_calls_in_region(func_at_control_node.at(case), calls);
+                }
+            }
+            &ControlNodeKind::Loop { body, .. } => {
+                collect_calls_in_region(func_at_control_node.at(body), calls);
+            }
+            ControlNodeKind::ExitInvocation { .. } => {}
+        }
+    }
+}
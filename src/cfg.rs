@@ -1,12 +1,17 @@
 //! Control-flow graph (CFG) abstractions and utilities.
 
 use crate::func_at::FuncAt;
+use crate::transform::{InnerInPlaceTransform, ReplaceValueWith};
 use crate::{
-    spv, AttrSet, Const, ConstCtor, ConstDef, Context, ControlNode, ControlNodeDef,
-    ControlNodeKind, ControlNodeOutputDecl, ControlRegion, ControlRegionDef, EntityList,
-    EntityOrientedDenseMap, FuncDefBody, FxIndexMap, SelectionKind, Type, TypeCtor, TypeDef, Value,
+    spv, Attr, AttrSet, AttrSetDef, Const, ConstCtor, ConstDef, Context, ControlNode,
+    ControlNodeDef, ControlNodeKind, ControlNodeOutputDecl, ControlRegion, ControlRegionDef,
+    ControlRegionInputDecl, EntityList, EntityOrientedDenseMap, FuncDefBody, FxIndexMap,
+    FxIndexSet, SelectionKind, Type, TypeCtor, TypeDef, Value,
 };
+use rustc_hash::{FxHashMap, FxHashSet};
 use smallvec::SmallVec;
+use std::hash::Hash;
+use std::iter;
 use std::mem;
 
 /// The control-flow graph (CFG) of a function, as control-flow instructions
@@ -65,6 +70,12 @@ pub enum ControlInstKind {
 #[derive(Clone)]
 pub enum ExitInvocationKind {
     SpvInst(spv::Inst),
+
+    /// Task shader launching a grid of mesh shader workgroups, which replaces
+    /// the task shader (i.e. SPIR-V `OpEmitMeshTasksEXT`), with the group counts
+    /// (along each of the X, Y and Z dimensions) as the first three inputs,
+    /// followed by an optional pointer to the payload (read by the mesh shaders).
+    EmitMeshTasks,
 }
 
 impl ControlFlowGraph {
@@ -183,6 +194,1068 @@ impl ControlFlowGraph {
     }
 }
 
+/// Dominator tree of the [`ControlRegion`]s in a function body, i.e. for each
+/// region, its "immediate dominator": the closest other region that has to be
+/// entered before it, on every path from the entry of the function body.
+///
+/// For unstructured CFGs (see [`ControlFlowGraph`]), this is computed with the
+/// iterative algorithm from "A Simple, Fast Dominance Algorithm" (by Cooper,
+/// Harvey and Kennedy), while regions nested in structured control-flow (i.e.
+/// `Select` cases and `Loop` bodies) are always immediately dominated by the
+/// region containing them (so for structured function bodies, which have no
+/// CFG, the dominator tree is the same as the region nesting tree).
+///
+/// Regions unreachable from the function body's entry have no dominators, and
+/// are not part of the tree (i.e. they neither dominate, nor are dominated).
+#[derive(Clone)]
+pub struct DominatorTree {
+    root: ControlRegion,
+    tree: RegionTree,
+}
+
+impl DominatorTree {
+    pub fn compute(func_def_body: &FuncDefBody) -> Self {
+        let mut idoms = SmallVec::<[(ControlRegion, Option<ControlRegion>); 8]>::new();
+        match &func_def_body.unstructured_cfg {
+            None => idoms.push((func_def_body.body, None)),
+            Some(cfg) => {
+                let rpo: SmallVec<[_; 8]> = cfg.rev_post_order(func_def_body).collect();
+                let mut rpo_idx = EntityOrientedDenseMap::new();
+                for (i, &region) in rpo.iter().enumerate() {
+                    rpo_idx.insert(region, i);
+                }
+                let mut preds: SmallVec<[SmallVec<[usize; 2]>; 8]> =
+                    rpo.iter().map(|_| SmallVec::new()).collect();
+                for (i, &region) in rpo.iter().enumerate() {
+                    for &target in &cfg.control_inst_on_exit_from[region].targets {
+                        preds[rpo_idx[target]].push(i);
+                    }
+                }
+
+                let idom_idx = compute_idoms(&preds);
+                idoms.extend(rpo.iter().enumerate().map(|(i, &region)| {
+                    (
+                        region,
+                        if i == 0 {
+                            None
+                        } else {
+                            idom_idx[i].map(|j| rpo[j])
+                        },
+                    )
+                }));
+            }
+        }
+
+        // Regions nested in structured control-flow are added last, as they're
+        // always immediately dominated by the region containing them.
+        let outer_regions: SmallVec<[_; 8]> = idoms.iter().map(|&(region, _)| region).collect();
+        idoms.extend(
+            nested_regions(func_def_body, &outer_regions)
+                .into_iter()
+                .map(|(region, parent, _)| (region, Some(parent))),
+        );
+
+        Self {
+            root: func_def_body.body,
+            tree: RegionTree::new(&idoms),
+        }
+    }
+
+    /// Get the root of the tree, i.e. the function body's entry region (which
+    /// dominates all the regions reachable from it).
+    pub fn root(&self) -> ControlRegion {
+        self.root
+    }
+
+    /// Returns `true` if `region` is reachable from the function body's entry
+    /// (i.e. it's part of the tree at all).
+    pub fn is_reachable(&self, region: ControlRegion) -> bool {
+        self.tree.contains(region)
+    }
+
+    /// Get the immediate dominator of `region`, i.e. its parent in the tree
+    /// (only `None` for the root, and for unreachable regions).
+    pub fn immediate_dominator(&self, region: ControlRegion) -> Option<ControlRegion> {
+        self.tree.parent(region)
+    }
+
+    /// Get the regions immediately dominated by `region`, i.e. its children in the tree.
+    pub fn children(&self, region: ControlRegion) -> &[ControlRegion] {
+        self.tree.children(region)
+    }
+
+    /// Returns `true` if `a` dominates `b`, i.e. every path from the entry to
+    /// `b` has to go through `a` (which includes `a == b`, for reachable `a`).
+    pub fn dominates(&self, a: ControlRegion, b: ControlRegion) -> bool {
+        self.tree.is_ancestor(a, b)
+    }
+
+    /// Returns `true` if `a` dominates `b` (see [`DominatorTree::dominates`]),
+    /// and they're not the same region.
+    pub fn strictly_dominates(&self, a: ControlRegion, b: ControlRegion) -> bool {
+        a != b && self.dominates(a, b)
+    }
+
+    /// Iterate over all the dominators of `region` (starting with `region`
+    /// itself, then its immediate dominator, and so on, up to the root).
+    pub fn dominators(&self, region: ControlRegion) -> impl Iterator<Item = ControlRegion> + '_ {
+        self.tree.ancestors(region)
+    }
+
+    /// Iterate over all the regions in the tree, in pre-order (i.e. each
+    /// region before any of the regions it dominates).
+    pub fn pre_order(&self) -> impl DoubleEndedIterator<Item = ControlRegion> + '_ {
+        self.tree.pre_order.iter().copied()
+    }
+}
+
+/// Post-dominator tree of the [`ControlRegion`]s in a function body, i.e. for
+/// each region, its "immediate post-dominator": the closest other region that
+/// has to be entered after it, on every path from it to the function's exit
+/// (i.e. the dominator tree of the reverse CFG, see also [`DominatorTree`]).
+///
+/// Every region without any successors in the CFG (e.g. one that returns) is
+/// considered an exit, so the tree is really a forest, with one root for each
+/// exit (and no region post-dominating all of them). Regions nested in structured
+/// control-flow are always immediately post-dominated by the region containing
+/// them (and for structured function bodies, the body is the only root).
+///
+/// Regions from which no exit can be reached (e.g. infinite loops), or which
+/// are unreachable from the function body's entry, are not part of the tree.
+//
+// FIXME(eddyb) structured regions containing `ExitInvocation`s are treated
+// the same as the ones that don't, even if they can never be exited normally.
+#[derive(Clone)]
+pub struct PostDominatorTree {
+    tree: RegionTree,
+}
+
+impl PostDominatorTree {
+    pub fn compute(func_def_body: &FuncDefBody) -> Self {
+        let mut ipdoms = SmallVec::<[(ControlRegion, Option<ControlRegion>); 8]>::new();
+        match &func_def_body.unstructured_cfg {
+            None => ipdoms.push((func_def_body.body, None)),
+            Some(cfg) => {
+                let regions: SmallVec<[_; 8]> = cfg.rev_post_order(func_def_body).collect();
+                let mut region_idx = EntityOrientedDenseMap::new();
+                for (i, &region) in regions.iter().enumerate() {
+                    region_idx.insert(region, i);
+                }
+
+                // NOTE(eddyb) this uses indices into `regions`, offset by `1`,
+                // with `0` being a "virtual exit", the single successor of all
+                // exit regions, and the root of the reverse CFG.
+                let mut succs: SmallVec<[SmallVec<[usize; 2]>; 8]> = iter::once(SmallVec::new())
+                    .chain(regions.iter().map(|_| SmallVec::new()))
+                    .collect();
+                let mut reverse_succs = succs.clone();
+                for (i, &region) in regions.iter().enumerate() {
+                    let targets = &cfg.control_inst_on_exit_from[region].targets;
+                    if targets.is_empty() {
+                        succs[1 + i].push(0);
+                        reverse_succs[0].push(1 + i);
+                    }
+                    for &target in targets {
+                        succs[1 + i].push(1 + region_idx[target]);
+                        reverse_succs[1 + region_idx[target]].push(1 + i);
+                    }
+                }
+
+                let mut reverse_post_order = SmallVec::<[usize; 8]>::new();
+                post_order_dfs(
+                    0,
+                    &reverse_succs,
+                    &mut vec![false; reverse_succs.len()],
+                    &mut reverse_post_order,
+                );
+                let reverse_rpo: SmallVec<[usize; 8]> =
+                    reverse_post_order.into_iter().rev().collect();
+                let mut reverse_rpo_idx = vec![None; succs.len()];
+                for (i, &node) in reverse_rpo.iter().enumerate() {
+                    reverse_rpo_idx[node] = Some(i);
+                }
+
+                // The predecessors of a node in the reverse CFG are its successors.
+                let reverse_preds: SmallVec<[SmallVec<[usize; 2]>; 8]> = reverse_rpo
+                    .iter()
+                    .map(|&node| {
+                        succs[node]
+                            .iter()
+                            .filter_map(|&succ| reverse_rpo_idx[succ])
+                            .collect()
+                    })
+                    .collect();
+                let ipdom_idx = compute_idoms(&reverse_preds);
+                ipdoms.extend(reverse_rpo.iter().enumerate().skip(1).map(|(i, &node)| {
+                    let ipdom = ipdom_idx[i]
+                        .map(|j| reverse_rpo[j])
+                        .filter(|&ipdom_node| ipdom_node != 0)
+                        .map(|ipdom_node| regions[ipdom_node - 1]);
+                    (regions[node - 1], ipdom)
+                }));
+            }
+        }
+
+        let outer_regions: SmallVec<[_; 8]> = ipdoms.iter().map(|&(region, _)| region).collect();
+        ipdoms.extend(
+            nested_regions(func_def_body, &outer_regions)
+                .into_iter()
+                .map(|(region, parent, _)| (region, Some(parent))),
+        );
+
+        Self {
+            tree: RegionTree::new(&ipdoms),
+        }
+    }
+
+    /// Returns `true` if `region` is part of the tree, i.e. it's reachable from
+    /// the function body's entry, and it can reach an exit.
+    pub fn contains(&self, region: ControlRegion) -> bool {
+        self.tree.contains(region)
+    }
+
+    /// Get the immediate post-dominator of `region`, i.e. its parent in the tree
+    /// (only `None` for the roots, i.e. exits, and regions not in the tree).
+    pub fn immediate_post_dominator(&self, region: ControlRegion) -> Option<ControlRegion> {
+        self.tree.parent(region)
+    }
+
+    /// Get the regions immediately post-dominated by `region`, i.e. its children in the tree.
+    pub fn children(&self, region: ControlRegion) -> &[ControlRegion] {
+        self.tree.children(region)
+    }
+
+    /// Returns `true` if `a` post-dominates `b`, i.e. every path from `b` to
+    /// an exit has to go through `a` (which includes `a == b`, if in the tree).
+    pub fn post_dominates(&self, a: ControlRegion, b: ControlRegion) -> bool {
+        self.tree.is_ancestor(a, b)
+    }
+
+    /// Returns `true` if `a` post-dominates `b` (see [`PostDominatorTree::post_dominates`]),
+    /// and they're not the same region.
+    pub fn strictly_post_dominates(&self, a: ControlRegion, b: ControlRegion) -> bool {
+        a != b && self.post_dominates(a, b)
+    }
+
+    /// Iterate over all the post-dominators of `region` (starting with `region`
+    /// itself, then its immediate post-dominator, and so on, up to an exit).
+    pub fn post_dominators(
+        &self,
+        region: ControlRegion,
+    ) -> impl Iterator<Item = ControlRegion> + '_ {
+        self.tree.ancestors(region)
+    }
+}
+
+/// Control dependence graph of the [`ControlRegion`]s in a function body, i.e.
+/// for each region, the regions whose choice of successor (i.e. branch, in an
+/// unstructured CFG, or `Select`/`Loop` control-flow, when structured) decides
+/// whether the region executes (in the sense of Ferrante, Ottenstein and Warren).
+///
+/// For regions nested in structured control-flow, that is:
+/// * `Select` cases are control dependent on the region containing the `Select`
+/// * `Loop` bodies are control dependent on themselves (as they decide, through
+///   the `repeat_condition`, whether they execute again), in addition to all
+///   the regions the region containing the `Loop` is control dependent on
+#[derive(Clone, Default)]
+pub struct ControlDependenceGraph {
+    dependencies: EntityOrientedDenseMap<ControlRegion, SmallVec<[ControlRegion; 2]>>,
+}
+
+impl ControlDependenceGraph {
+    pub fn compute(func_def_body: &FuncDefBody, post_dom_tree: &PostDominatorTree) -> Self {
+        let mut cdg = Self::default();
+
+        let mut outer_regions = SmallVec::<[_; 8]>::new();
+        match &func_def_body.unstructured_cfg {
+            None => outer_regions.push(func_def_body.body),
+            Some(cfg) => {
+                outer_regions.extend(cfg.rev_post_order(func_def_body));
+                for &region in &outer_regions {
+                    // NOTE(eddyb) every region on the post-dominator tree path,
+                    // from a target (inclusive) to the immediate post-dominator
+                    // of `region` (exclusive), is control dependent on `region`.
+                    let stop = post_dom_tree.immediate_post_dominator(region);
+                    for &target in &cfg.control_inst_on_exit_from[region].targets {
+                        let mut dependent = Some(target).filter(|&t| post_dom_tree.contains(t));
+                        while let Some(r) = dependent {
+                            if Some(r) == stop {
+                                break;
+                            }
+                            cdg.add_dependency(r, region);
+                            dependent = post_dom_tree.immediate_post_dominator(r);
+                        }
+                    }
+                }
+            }
+        }
+
+        for (region, parent, is_loop_body) in nested_regions(func_def_body, &outer_regions) {
+            if is_loop_body {
+                let parent_dependencies = cdg.dependencies(parent).to_vec();
+                for dependency in parent_dependencies {
+                    cdg.add_dependency(region, dependency);
+                }
+                cdg.add_dependency(region, region);
+            } else {
+                cdg.add_dependency(region, parent);
+            }
+        }
+
+        cdg
+    }
+
+    fn add_dependency(&mut self, region: ControlRegion, dependency: ControlRegion) {
+        match self.dependencies.get_mut(region) {
+            Some(dependencies) => {
+                if !dependencies.contains(&dependency) {
+                    dependencies.push(dependency);
+                }
+            }
+            None => {
+                self.dependencies
+                    .insert(region, [dependency].into_iter().collect());
+            }
+        }
+    }
+
+    /// Get the regions `region` is control dependent on (empty for regions
+    /// which execute whenever the function does, e.g. the entry).
+    pub fn dependencies(&self, region: ControlRegion) -> &[ControlRegion] {
+        self.dependencies
+            .get(region)
+            .map_or(&[], |dependencies| &dependencies[..])
+    }
+}
+
+/// Natural loop in an unstructured CFG (see [`LoopForest`]), i.e. a cycle with
+/// a single entry (its `header`), which dominates all the regions in the loop.
+#[derive(Clone)]
+pub struct NaturalLoop {
+    /// The single entry of the loop, which dominates every region in `body`.
+    pub header: ControlRegion,
+
+    /// Regions with "back edges" (i.e. edges to `header`, which it dominates).
+    pub latches: SmallVec<[ControlRegion; 2]>,
+
+    /// All the regions in the loop, including `header`, and all the regions
+    /// in nested loops (in reverse post-order, so `header` is always first).
+    pub body: FxIndexSet<ControlRegion>,
+
+    /// Edges from regions in the loop (`body`) to regions outside of it.
+    pub exit_edges: SmallVec<[(ControlRegion, ControlRegion); 2]>,
+
+    /// The `header` of the innermost loop containing this one, if any.
+    pub parent: Option<ControlRegion>,
+
+    /// The `header`s of the outermost loops nested in this one.
+    pub children: SmallVec<[ControlRegion; 2]>,
+}
+
+/// All the natural loops in the unstructured CFG of a function body (see also
+/// [`NaturalLoop`]), organized by nesting (i.e. as a "loop nesting forest").
+///
+/// All back edges (edges to a region which dominates their source) with the
+/// same target are part of one loop (so there is always one loop per header),
+/// and cycles without a single entry (i.e. irreducible control-flow, see also
+/// `make_cfg_reducible`) aren't natural loops, so they're ignored.
+///
+/// Structured function bodies (i.e. without a CFG) have no natural loops, as
+/// they can only contain `Loop` control nodes.
+#[derive(Clone, Default)]
+pub struct LoopForest {
+    /// All the loops, keyed by their header, with outer loops before any loops
+    /// nested in them (and otherwise in reverse post-order of their headers).
+    loops: FxIndexMap<ControlRegion, NaturalLoop>,
+
+    /// The `header` of the innermost loop containing each region, if any.
+    innermost_loop_header: EntityOrientedDenseMap<ControlRegion, ControlRegion>,
+}
+
+impl LoopForest {
+    pub fn compute(func_def_body: &FuncDefBody, dom_tree: &DominatorTree) -> Self {
+        let mut loop_forest = Self::default();
+
+        let cfg = match &func_def_body.unstructured_cfg {
+            Some(cfg) => cfg,
+            None => return loop_forest,
+        };
+
+        let rpo: SmallVec<[_; 8]> = cfg.rev_post_order(func_def_body).collect();
+        let mut rpo_idx = EntityOrientedDenseMap::new();
+        let mut preds = EntityOrientedDenseMap::new();
+        for (i, &region) in rpo.iter().enumerate() {
+            rpo_idx.insert(region, i);
+            preds.insert(region, SmallVec::<[ControlRegion; 2]>::new());
+        }
+        for &region in &rpo {
+            for &target in &cfg.control_inst_on_exit_from[region].targets {
+                if !preds[target].contains(&region) {
+                    preds[target].push(region);
+                }
+            }
+        }
+
+        let mut loops = SmallVec::<[NaturalLoop; 4]>::new();
+        for &header in &rpo {
+            let latches: SmallVec<[_; 2]> = preds[header]
+                .iter()
+                .copied()
+                .filter(|&pred| dom_tree.dominates(header, pred))
+                .collect();
+            if latches.is_empty() {
+                continue;
+            }
+
+            // NOTE(eddyb) the loop body is everything that can reach a latch
+            // (backwards from it) without going through the header.
+            let mut body = FxIndexSet::default();
+            body.insert(header);
+            let mut queue: SmallVec<[_; 8]> = latches.iter().copied().collect();
+            while let Some(region) = queue.pop() {
+                if body.insert(region) {
+                    queue.extend(preds[region].iter().copied());
+                }
+            }
+            body.sort_by(|&a, &b| rpo_idx[a].cmp(&rpo_idx[b]));
+
+            let exit_edges = body
+                .iter()
+                .flat_map(|&region| {
+                    let targets = &cfg.control_inst_on_exit_from[region].targets;
+                    targets.iter().map(move |&target| (region, target))
+                })
+                .filter(|(_, target)| !body.contains(target))
+                .collect();
+
+            loops.push(NaturalLoop {
+                header,
+                latches,
+                body,
+                exit_edges,
+                parent: None,
+                children: SmallVec::new(),
+            });
+        }
+
+        // NOTE(eddyb) natural loops (with different headers) are either nested,
+        // or disjoint, so sorting them by size (outer loops first) allows using
+        // `innermost_loop_header` to find the parent of each loop.
+        loops.sort_by_key(|natural_loop| std::cmp::Reverse(natural_loop.body.len()));
+        for mut natural_loop in loops {
+            let header = natural_loop.header;
+            natural_loop.parent = loop_forest.innermost_loop_header.get(header).copied();
+            if let Some(parent) = natural_loop.parent {
+                loop_forest.loops[&parent].children.push(header);
+            }
+            for &region in &natural_loop.body {
+                loop_forest.innermost_loop_header.insert(region, header);
+            }
+            loop_forest.loops.insert(header, natural_loop);
+        }
+
+        loop_forest
+    }
+
+    /// Iterate over all the loops, with outer loops before any loops nested in them.
+    pub fn loops(&self) -> impl Iterator<Item = &NaturalLoop> {
+        self.loops.values()
+    }
+
+    /// Iterate over all the outermost loops (i.e. those not nested in any loop).
+    pub fn roots(&self) -> impl Iterator<Item = &NaturalLoop> {
+        self.loops()
+            .filter(|natural_loop| natural_loop.parent.is_none())
+    }
+
+    /// Get the loop with the header `header`, if `header` is a loop header.
+    pub fn get(&self, header: ControlRegion) -> Option<&NaturalLoop> {
+        self.loops.get(&header)
+    }
+
+    /// Get the innermost loop containing `region`, if any.
+    pub fn innermost_loop_containing(&self, region: ControlRegion) -> Option<&NaturalLoop> {
+        Some(&self.loops[self.innermost_loop_header.get(region)?])
+    }
+
+    /// Get the number of loops containing `region` (i.e. `0` outside all loops).
+    pub fn loop_depth(&self, region: ControlRegion) -> usize {
+        iter::successors(self.innermost_loop_containing(region), |natural_loop| {
+            Some(&self.loops[&natural_loop.parent?])
+        })
+        .count()
+    }
+}
+
+/// Compute the immediate dominators of all the nodes in a graph, given the
+/// predecessors of each node, with nodes being indices in reverse post-order
+/// (i.e. the root is `0`, and it's the only node without any predecessors),
+/// where the root is treated as its own immediate dominator.
+fn compute_idoms(preds: &[SmallVec<[usize; 2]>]) -> SmallVec<[Option<usize>; 8]> {
+    let mut idoms: SmallVec<[Option<usize>; 8]> = preds.iter().map(|_| None).collect();
+    idoms[0] = Some(0);
+
+    let intersect = |idoms: &[Option<usize>], mut a: usize, mut b: usize| {
+        while a != b {
+            while a > b {
+                a = idoms[a].unwrap();
+            }
+            while b > a {
+                b = idoms[b].unwrap();
+            }
+        }
+        a
+    };
+
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for i in 1..preds.len() {
+            let mut new_idom = None;
+            for &pred in &preds[i] {
+                if idoms[pred].is_none() {
+                    continue;
+                }
+                new_idom = Some(match new_idom {
+                    None => pred,
+                    Some(other) => intersect(&idoms, pred, other),
+                });
+            }
+            if idoms[i] != new_idom {
+                idoms[i] = new_idom;
+                changed = true;
+            }
+        }
+    }
+    idoms
+}
+
+fn post_order_dfs(
+    node: usize,
+    succs: &[SmallVec<[usize; 2]>],
+    visited: &mut [bool],
+    post_order: &mut SmallVec<[usize; 8]>,
+) {
+    if mem::replace(&mut visited[node], true) {
+        return;
+    }
+    for &succ in &succs[node] {
+        post_order_dfs(succ, succs, visited, post_order);
+    }
+    post_order.push(node);
+}
+
+/// Collect all the regions nested (at any depth) in structured control-flow
+/// in `outer_regions`, as `(region, parent, is_loop_body)`, with `parent` the
+/// region containing `region` (and always appearing before it, if also nested).
+fn nested_regions(
+    func_def_body: &FuncDefBody,
+    outer_regions: &[ControlRegion],
+) -> SmallVec<[(ControlRegion, ControlRegion, bool); 8]> {
+    let mut nested = SmallVec::<[_; 8]>::new();
+    let collect_nested_in = |nested: &mut SmallVec<[_; 8]>, region| {
+        for func_at_control_node in func_def_body.at(region).at_children() {
+            match &func_at_control_node.def().kind {
+                ControlNodeKind::Block { .. } | ControlNodeKind::ExitInvocation { .. } => {}
+                ControlNodeKind::Select { cases, .. } => {
+                    nested.extend(cases.iter().map(|&case| (case, region, false)));
+                }
+                &ControlNodeKind::Loop { body, .. } => nested.push((body, region, true)),
+            }
+        }
+    };
+    for &region in outer_regions {
+        collect_nested_in(&mut nested, region);
+    }
+    let mut i = 0;
+    while i < nested.len() {
+        let region = nested[i].0;
+        collect_nested_in(&mut nested, region);
+        i += 1;
+    }
+    nested
+}
+
+/// Tree (or forest) of [`ControlRegion`]s, shared by [`DominatorTree`] and
+/// [`PostDominatorTree`].
+#[derive(Clone)]
+struct RegionTree {
+    nodes: EntityOrientedDenseMap<ControlRegion, RegionTreeNode>,
+
+    /// All the regions in the tree, in pre-order (i.e. each region before
+    /// any of its descendants).
+    pre_order: SmallVec<[ControlRegion; 8]>,
+}
+
+#[derive(Clone)]
+struct RegionTreeNode {
+    parent: Option<ControlRegion>,
+    children: SmallVec<[ControlRegion; 4]>,
+
+    // NOTE(eddyb) a region is an ancestor of all (and only) the regions with
+    // indices (into `RegionTree::pre_order`) in its `pre_order_idx..subtree_end`.
+    pre_order_idx: usize,
+    subtree_end: usize,
+}
+
+impl RegionTree {
+    /// Build a tree from `(region, parent)` pairs (with `parent == None` for roots).
+    fn new(parents: &[(ControlRegion, Option<ControlRegion>)]) -> Self {
+        let mut nodes = EntityOrientedDenseMap::new();
+        for &(region, parent) in parents {
+            nodes.insert(
+                region,
+                RegionTreeNode {
+                    parent,
+                    children: SmallVec::new(),
+                    pre_order_idx: 0,
+                    subtree_end: 0,
+                },
+            );
+        }
+        for &(region, parent) in parents {
+            if let Some(parent) = parent {
+                nodes[parent].children.push(region);
+            }
+        }
+
+        let mut tree = Self {
+            nodes,
+            pre_order: SmallVec::with_capacity(parents.len()),
+        };
+        for &(region, parent) in parents {
+            if parent.is_none() {
+                tree.assign_pre_order_indices(region);
+            }
+        }
+        tree
+    }
+
+    fn assign_pre_order_indices(&mut self, region: ControlRegion) {
+        self.nodes[region].pre_order_idx = self.pre_order.len();
+        self.pre_order.push(region);
+        for i in 0..self.nodes[region].children.len() {
+            self.assign_pre_order_indices(self.nodes[region].children[i]);
+        }
+        self.nodes[region].subtree_end = self.pre_order.len();
+    }
+
+    fn contains(&self, region: ControlRegion) -> bool {
+        self.nodes.get(region).is_some()
+    }
+
+    fn parent(&self, region: ControlRegion) -> Option<ControlRegion> {
+        self.nodes.get(region)?.parent
+    }
+
+    fn children(&self, region: ControlRegion) -> &[ControlRegion] {
+        self.nodes
+            .get(region)
+            .map_or(&[], |node| &node.children[..])
+    }
+
+    fn is_ancestor(&self, a: ControlRegion, b: ControlRegion) -> bool {
+        match (self.nodes.get(a), self.nodes.get(b)) {
+            (Some(a), Some(b)) => (a.pre_order_idx..a.subtree_end).contains(&b.pre_order_idx),
+            _ => false,
+        }
+    }
+
+    fn ancestors(&self, region: ControlRegion) -> impl Iterator<Item = ControlRegion> + '_ {
+        let start = Some(region).filter(|&region| self.contains(region));
+        iter::successors(start, |&region| self.parent(region))
+    }
+}
+
+/// Make the unstructured CFG of `func_def_body` reducible, i.e. ensure every
+/// cycle in the CFG has a single entry (its "loop header"), as [`Structurizer`]
+/// can only turn such single-entry cycles into `Loop`s.
+///
+/// Each multi-entry cycle found (see [`find_irreducible_cycle`]) gets a new
+/// "dispatcher" [`ControlRegion`], which becomes its single entry (see also
+/// [`insert_cycle_dispatcher`]), and this is repeated until none remain (e.g.
+/// multi-entry cycles nested in the body of a newly-created single-entry one).
+//
+// FIXME(eddyb) controlled node splitting (i.e. duplicating some of the entries
+// of a cycle) could result in better code for small irreducible cycles, but it
+// has exponential worst-case behavior, while dispatching is always linear.
+fn make_cfg_reducible(cx: &Context, func_def_body: &mut FuncDefBody) {
+    while let Some(entries) = find_irreducible_cycle(func_def_body) {
+        if entries.contains(&func_def_body.body) {
+            // NOTE(eddyb) the function body can't be redirected to (as it's
+            // entered by calls, not branches), but it can be split in two,
+            // with the second half (which takes its place in the cycle) no
+            // longer being special in any way (and the cycle will be found
+            // again, in the next iteration).
+            split_func_body_entry(cx, func_def_body);
+        } else {
+            insert_cycle_dispatcher(cx, func_def_body, &entries);
+        }
+    }
+}
+
+/// Find a cycle (in the unstructured CFG of `func_def_body`) with more than one
+/// entry (i.e. a [`ControlRegion`] with predecessors from outside the cycle,
+/// or the function body itself), returning all of its entries (in RPO order).
+///
+/// Cycles nested in a single-entry cycle (i.e. a loop) are also found, as the
+/// cycles in the loop body, when not going through the loop header again
+/// (mirroring how [`Structurizer`] would structurize that loop).
+fn find_irreducible_cycle(func_def_body: &FuncDefBody) -> Option<SmallVec<[ControlRegion; 4]>> {
+    let cfg = func_def_body.unstructured_cfg.as_ref().unwrap();
+    let targets_of = |region| &cfg.control_inst_on_exit_from[region].targets[..];
+
+    let rpo: Vec<_> = cfg.rev_post_order(func_def_body).collect();
+    let rpo_idx: FxHashMap<_, _> = rpo.iter().enumerate().map(|(i, &r)| (r, i)).collect();
+
+    let mut preds = FxHashMap::<_, SmallVec<[_; 4]>>::default();
+    for &region in &rpo {
+        for &target in targets_of(region) {
+            preds.entry(target).or_default().push(region);
+        }
+    }
+
+    let mut subgraphs = vec![rpo];
+    while let Some(subgraph) = subgraphs.pop() {
+        for mut scc in strongly_connected_components(&subgraph, &targets_of) {
+            let is_cycle = scc.len() > 1 || targets_of(scc[0]).contains(&scc[0]);
+            if !is_cycle {
+                continue;
+            }
+            scc.sort_by_key(|region| rpo_idx[region]);
+
+            let in_scc: FxHashSet<_> = scc.iter().copied().collect();
+            let entries: SmallVec<[_; 4]> = scc
+                .iter()
+                .copied()
+                .filter(|&region| {
+                    region == func_def_body.body
+                        || preds
+                            .get(&region)
+                            .is_some_and(|preds| preds.iter().any(|p| !in_scc.contains(p)))
+                })
+                .collect();
+            if entries.len() > 1 {
+                return Some(entries);
+            }
+
+            let header = entries[0];
+            subgraphs.push(scc.into_iter().filter(|&region| region != header).collect());
+        }
+    }
+    None
+}
+
+/// Find the strongly connected components (SCCs) of the subgraph (e.g. of the CFG)
+/// made out of just the `nodes`, using Tarjan's algorithm.
+pub(crate) fn strongly_connected_components<'a, N: Copy + Eq + Hash + 'a>(
+    nodes: &[N],
+    targets_of: &impl Fn(N) -> &'a [N],
+) -> Vec<Vec<N>> {
+    struct Tarjan<'b, N, F> {
+        targets_of: &'b F,
+        in_subgraph: FxHashSet<N>,
+        indices: FxHashMap<N, usize>,
+        stack: Vec<N>,
+        on_stack: FxHashSet<N>,
+        sccs: Vec<Vec<N>>,
+    }
+
+    impl<'a, N: Copy + Eq + Hash + 'a, F: Fn(N) -> &'a [N]> Tarjan<'_, N, F> {
+        /// Visit `node` (and everything reachable from it), returning its
+        /// "low-link" (smallest index reachable while still on the stack).
+        fn visit(&mut self, node: N) -> usize {
+            let index = self.indices.len();
+            self.indices.insert(node, index);
+            self.stack.push(node);
+            self.on_stack.insert(node);
+
+            let mut low_link = index;
+            for &target in (self.targets_of)(node) {
+                if !self.in_subgraph.contains(&target) {
+                    continue;
+                }
+                match self.indices.get(&target) {
+                    None => low_link = low_link.min(self.visit(target)),
+                    Some(&target_index) if self.on_stack.contains(&target) => {
+                        low_link = low_link.min(target_index);
+                    }
+                    Some(_) => {}
+                }
+            }
+
+            if low_link == index {
+                let mut scc = vec![];
+                loop {
+                    let scc_node = self.stack.pop().unwrap();
+                    self.on_stack.remove(&scc_node);
+                    scc.push(scc_node);
+                    if scc_node == node {
+                        break;
+                    }
+                }
+                self.sccs.push(scc);
+            }
+
+            low_link
+        }
+    }
+
+    let mut tarjan = Tarjan {
+        targets_of,
+        in_subgraph: nodes.iter().copied().collect(),
+        indices: FxHashMap::default(),
+        stack: vec![],
+        on_stack: FxHashSet::default(),
+        sccs: vec![],
+    };
+    for &node in nodes {
+        if !tarjan.indices.contains_key(&node) {
+            tarjan.visit(node);
+        }
+    }
+    tarjan.sccs
+}
+
+/// Move the contents (and `ControlInst`) of the function body into a new
+/// [`ControlRegion`] (which takes the function parameters as its inputs), and
+/// redirect all branches targeting the function body to that new region.
+fn split_func_body_entry(cx: &Context, func_def_body: &mut FuncDefBody) {
+    let body = func_def_body.body;
+    let rpo: SmallVec<[_; 8]> = func_def_body
+        .unstructured_cfg
+        .as_ref()
+        .unwrap()
+        .rev_post_order(func_def_body)
+        .collect();
+
+    let body_def = &mut func_def_body.control_regions[body];
+    let new_region = ControlRegionDef {
+        inputs: body_def.inputs.clone(),
+        children: mem::take(&mut body_def.children),
+        outputs: [].into_iter().collect(),
+    };
+    let new_region = func_def_body.control_regions.define(cx, new_region);
+
+    func_def_body.inner_in_place_transform_with(&mut ReplaceValueWith(|v| match v {
+        Value::ControlRegionInput { region, input_idx } if region == body => {
+            Some(Value::ControlRegionInput {
+                region: new_region,
+                input_idx,
+            })
+        }
+        _ => None,
+    }));
+
+    let cfg = func_def_body.unstructured_cfg.as_mut().unwrap();
+    for region in rpo {
+        let control_inst = &mut cfg.control_inst_on_exit_from[region];
+        if !control_inst.targets.contains(&body) {
+            continue;
+        }
+        for target in &mut control_inst.targets {
+            if *target == body {
+                *target = new_region;
+            }
+        }
+        control_inst.target_inputs = mem::take(&mut control_inst.target_inputs)
+            .into_iter()
+            .map(|(target, inputs)| (if target == body { new_region } else { target }, inputs))
+            .collect();
+    }
+
+    let body_control_inst = cfg.control_inst_on_exit_from.remove(body).unwrap();
+    cfg.control_inst_on_exit_from
+        .insert(new_region, body_control_inst);
+
+    let body_inputs: SmallVec<[_; 2]> = (0..func_def_body.control_regions[body].inputs.len())
+        .map(|input_idx| Value::ControlRegionInput {
+            region: body,
+            input_idx: input_idx.try_into().unwrap(),
+        })
+        .collect();
+    cfg.control_inst_on_exit_from.insert(
+        body,
+        ControlInst {
+            attrs: AttrSet::default(),
+            kind: ControlInstKind::Branch,
+            inputs: [].into_iter().collect(),
+            targets: [new_region].into_iter().collect(),
+            target_inputs: [(new_region, body_inputs)]
+                .into_iter()
+                .filter(|(_, inputs)| !inputs.is_empty())
+                .collect(),
+        },
+    );
+}
+
+/// Make a new "dispatcher" [`ControlRegion`] the single entry of a cycle, by
+/// redirecting all edges into any of the cycle's `entries` to the dispatcher,
+/// which then `OpSwitch`es (on a `u32` "selector", identifying the original
+/// entry) to the original entry.
+///
+/// The dispatcher takes the selector as its first input, followed by the inputs
+/// of every entry, with each redirected edge passing along the original inputs
+/// (and `undef` for those of all the other entries) - as it can't distinguish
+/// between edges coming from the same [`ControlRegion`], each redirected edge
+/// also gets its own new (empty) [`ControlRegion`] to branch from.
+fn insert_cycle_dispatcher(
+    cx: &Context,
+    func_def_body: &mut FuncDefBody,
+    entries: &[ControlRegion],
+) {
+    let wk = &spv::spec::Spec::get().well_known;
+
+    let u32_type = cx.intern(TypeDef {
+        attrs: AttrSet::default(),
+        ctor: TypeCtor::SpvInst(spv::Inst {
+            opcode: wk.OpTypeInt,
+            imms: [
+                spv::Imm::Short(wk.LiteralInteger, 32),
+                spv::Imm::Short(wk.LiteralInteger, 0),
+            ]
+            .into_iter()
+            .collect(),
+        }),
+        ctor_args: [].into_iter().collect(),
+    });
+    let selector_const = |entry_idx: usize| {
+        Value::Const(
+            cx.intern(ConstDef {
+                attrs: AttrSet::default(),
+                ty: u32_type,
+                ctor: ConstCtor::SpvInst(spv::Inst {
+                    opcode: wk.OpConstant,
+                    imms: [spv::Imm::Short(
+                        wk.LiteralContextDependentNumber,
+                        entry_idx.try_into().unwrap(),
+                    )]
+                    .into_iter()
+                    .collect(),
+                }),
+                ctor_args: [].into_iter().collect(),
+            }),
+        )
+    };
+
+    let mut dispatcher_inputs: SmallVec<[_; 2]> = [ControlRegionInputDecl {
+        attrs: AttrSet::default(),
+        ty: u32_type,
+    }]
+    .into_iter()
+    .collect();
+    let entry_input_ranges: SmallVec<[_; 4]> = entries
+        .iter()
+        .map(|&entry| {
+            let start = dispatcher_inputs.len();
+            dispatcher_inputs.extend(func_def_body.at(entry).def().inputs.iter().cloned());
+            start..dispatcher_inputs.len()
+        })
+        .collect();
+    let undef_inputs: SmallVec<[_; 2]> = dispatcher_inputs
+        .iter()
+        .map(|input| {
+            Value::Const(cx.intern(ConstDef {
+                attrs: AttrSet::default(),
+                ty: input.ty,
+                ctor: ConstCtor::Undef,
+                ctor_args: [].into_iter().collect(),
+            }))
+        })
+        .collect();
+
+    let dispatcher = func_def_body.control_regions.define(
+        cx,
+        ControlRegionDef {
+            inputs: dispatcher_inputs,
+            children: EntityList::empty(),
+            outputs: [].into_iter().collect(),
+        },
+    );
+
+    let rpo: SmallVec<[_; 8]> = func_def_body
+        .unstructured_cfg
+        .as_ref()
+        .unwrap()
+        .rev_post_order(func_def_body)
+        .collect();
+    let cfg = func_def_body.unstructured_cfg.as_mut().unwrap();
+    let mut edge_regions = vec![];
+    for region in rpo {
+        let control_inst = &mut cfg.control_inst_on_exit_from[region];
+        for (entry_idx, &entry) in entries.iter().enumerate() {
+            if !control_inst.targets.contains(&entry) {
+                continue;
+            }
+
+            let edge_region = func_def_body.control_regions.define(
+                cx,
+                ControlRegionDef {
+                    inputs: [].into_iter().collect(),
+                    children: EntityList::empty(),
+                    outputs: [].into_iter().collect(),
+                },
+            );
+            for target in &mut control_inst.targets {
+                if *target == entry {
+                    *target = edge_region;
+                }
+            }
+
+            let mut inputs = undef_inputs.clone();
+            inputs[0] = selector_const(entry_idx);
+            if let Some(entry_inputs) = control_inst.target_inputs.shift_remove(&entry) {
+                inputs[entry_input_ranges[entry_idx].clone()].copy_from_slice(&entry_inputs);
+            }
+            edge_regions.push((
+                edge_region,
+                ControlInst {
+                    attrs: AttrSet::default(),
+                    kind: ControlInstKind::Branch,
+                    inputs: [].into_iter().collect(),
+                    targets: [dispatcher].into_iter().collect(),
+                    target_inputs: [(dispatcher, inputs)].into_iter().collect(),
+                },
+            ));
+        }
+    }
+    for (edge_region, control_inst) in edge_regions {
+        cfg.control_inst_on_exit_from
+            .insert(edge_region, control_inst);
+    }
+
+    let dispatcher_input = |input_idx: usize| Value::ControlRegionInput {
+        region: dispatcher,
+        input_idx: input_idx.try_into().unwrap(),
+    };
+    cfg.control_inst_on_exit_from.insert(
+        dispatcher,
+        ControlInst {
+            attrs: AttrSet::default(),
+            kind: ControlInstKind::SelectBranch(SelectionKind::SpvInst(spv::Inst {
+                opcode: wk.OpSwitch,
+                // NOTE(eddyb) the first entry is the `OpSwitch` default target.
+                imms: (1..entries.len())
+                    .map(|entry_idx| {
+                        spv::Imm::Short(
+                            wk.LiteralContextDependentNumber,
+                            entry_idx.try_into().unwrap(),
+                        )
+                    })
+                    .collect(),
+            })),
+            inputs: [dispatcher_input(0)].into_iter().collect(),
+            targets: entries.iter().copied().collect(),
+            target_inputs: entries
+                .iter()
+                .zip(entry_input_ranges)
+                .filter(|(_, range)| !range.is_empty())
+                .map(|(&entry, range)| (entry, range.map(dispatcher_input).collect()))
+                .collect(),
+        },
+    );
+}
+
 #[allow(rustdoc::private_intra_doc_links)]
 /// Control-flow "structurizer", which attempts to convert as much of the CFG
 /// as possible into structural control-flow (regions).
@@ -195,20 +1268,18 @@ impl ControlFlowGraph {
 //   1. absorb any deferred exits that finally have 100% refcount
 //   2. absorb a single backedge deferred exit to the same region
 //
-//   What we could add is a third step, to handle irreducible controlflow:
+//   Irreducible controlflow (i.e. cycles with more than one entry) is handled
+//   ahead of time, by `make_cfg_reducible`, which gives every such cycle a
+//   single "loop header" (dispatching to the original entries), so that it
+//   can be structurized like any other loop (by stage 2. above).
+//
+//   FIXME(eddyb) a third stage could instead handle irreducible cycles here:
 //   3. check for groups of exits that have fully satisfied refcounts iff the
 //     rest of the exits in the group are all added together - if so, the group
 //     is *irreducible* and a single "loop header" can be created, that gets
 //     the group of deferred exits, and any other occurrence of the deferred
 //     exits (in either the original region, or amongst themselves) can be
 //     replaced with the "loop header" with appropriate selector inputs
-//
-//   Sadly 3. requires a bunch of tests that are hard to craft (can rustc MIR
-//   even end up in the right shape?).
-//   OpenCL has `goto` so maybe it can also be used for this worse-than-diamond
-//   example: `entry -> a,b,d` `a,b -> c` `a,b,c -> d` `a,b,c,d <-> a,b,c,d`
-//   (the goal is avoiding a "flat group", i.e. where there is only one step
-//   between every exit in the group and another exit)
 pub struct Structurizer<'a> {
     cx: &'a Context,
 
@@ -237,6 +1308,14 @@ pub struct Structurizer<'a> {
     /// with `control_region_input_replacements[region][input_idx]`, as
     /// the original `region` cannot have be directly reused.
     control_region_input_replacements: EntityOrientedDenseMap<ControlRegion, SmallVec<[Value; 2]>>,
+
+    /// Attributes (i.e. `LoopControl` hints, see `structurize_region_from`)
+    /// taken from the [`ControlInst`] of a potential loop header, to be applied
+    /// to the `Loop` [`ControlNode`], if one ends up using it as its body start.
+    loop_header_attrs: EntityOrientedDenseMap<ControlRegion, AttrSet>,
+
+    /// Return type of the function (see [`Structurizer::with_func_ret_type`]).
+    func_ret_type: Option<Type>,
 }
 
 /// The state of one `structurize_region_from` invocation (keyed on its start
@@ -381,6 +1460,12 @@ impl<'a> Structurizer<'a> {
             ctor_args: [].into_iter().collect(),
         });
 
+        // NOTE(eddyb) this has to happen before counting incoming edges, as it
+        // changes the CFG (by redirecting edges into irreducible cycles).
+        if func_def_body.unstructured_cfg.is_some() {
+            make_cfg_reducible(cx, func_def_body);
+        }
+
         let mut incoming_edge_counts = EntityOrientedDenseMap::new();
         if let Some(cfg) = &func_def_body.unstructured_cfg {
             cfg.traverse_whole_func(
@@ -402,24 +1487,40 @@ impl<'a> Structurizer<'a> {
 
             structurize_region_state: FxIndexMap::default(),
             control_region_input_replacements: EntityOrientedDenseMap::new(),
+            loop_header_attrs: EntityOrientedDenseMap::new(),
+
+            func_ret_type: None,
         }
     }
 
+    /// Provide the return type of the function, allowing its body to be fully
+    /// structurized even if it can never return (e.g. task shaders, which always
+    /// end in [`ExitInvocationKind::EmitMeshTasks`]), by "returning" undef
+    /// constants instead (or nothing at all, for `OpTypeVoid`).
+    pub fn with_func_ret_type(mut self, ret_type: Type) -> Self {
+        self.func_ret_type = Some(ret_type);
+        self
+    }
+
     pub fn structurize_func(mut self) {
         // Don't even try to re-structurize functions.
         if self.func_def_body.unstructured_cfg.is_none() {
             return;
         }
 
-        let body_region = self.claim_or_defer_single_edge(self.func_def_body.body, SmallVec::new());
+        let mut body_region =
+            self.claim_or_defer_single_edge(self.func_def_body.body, SmallVec::new());
 
         if body_region.deferred_edges.target_to_deferred.is_empty() {
             // Structured return, the function is fully structurized.
             //
-            // FIXME(eddyb) also support structured return when the whole body
-            // is divergent, by generating undef constants (needs access to the
-            // whole `FuncDecl`, not just `FuncDefBody`, to get the right types).
-            if let Some(return_values) = body_region.deferred_return {
+            // NOTE(eddyb) when the whole body is divergent, this requires the
+            // return type, to generate undef constants (see `with_func_ret_type`).
+            let return_values = body_region
+                .deferred_return
+                .take()
+                .or_else(|| self.undef_return_values());
+            if let Some(return_values) = return_values {
                 let body_def = self.func_def_body.at_mut_body().def();
                 body_def.children = body_region.children;
                 body_def.outputs = return_values;
@@ -464,18 +1565,34 @@ impl<'a> Structurizer<'a> {
         self.apply_value_replacements();
     }
 
+    /// Values to "return" from a divergent function body (which can't ever be
+    /// observed), if the return type of the function is known.
+    fn undef_return_values(&self) -> Option<SmallVec<[Value; 2]>> {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        let ret_type = self.func_ret_type?;
+        let is_void = match &self.cx[ret_type].ctor {
+            TypeCtor::SpvInst(spv_inst) => spv_inst.opcode == wk.OpTypeVoid,
+            _ => false,
+        };
+        if is_void {
+            return Some(SmallVec::new());
+        }
+        Some(
+            [Value::Const(self.cx.intern(ConstDef {
+                attrs: AttrSet::default(),
+                ty: ret_type,
+                ctor: ConstCtor::Undef,
+                ctor_args: [].into_iter().collect(),
+            }))]
+            .into_iter()
+            .collect(),
+        )
+    }
+
     /// The last step of structurization is processing bulk replacements
     /// collected while structurizing (like `control_region_input_replacements`).
     fn apply_value_replacements(self) {
-        // FIXME(eddyb) maybe this should be provided by `transform`.
-        use crate::transform::*;
-        struct ReplaceValueWith<F>(F);
-        impl<F: Fn(Value) -> Option<Value>> Transformer for ReplaceValueWith<F> {
-            fn transform_value_use(&mut self, v: &Value) -> Transformed<Value> {
-                self.0(*v).map_or(Transformed::Unchanged, Transformed::Changed)
-            }
-        }
-
         self.func_def_body
             .inner_in_place_transform_with(&mut ReplaceValueWith(|v| match v {
                 Value::ControlRegionInput { region, input_idx } => {
@@ -610,6 +1727,7 @@ impl<'a> Structurizer<'a> {
             let loop_node = self.func_def_body.control_nodes.define(
                 self.cx,
                 ControlNodeDef {
+                    attrs: self.loop_header_attrs.remove(target).unwrap_or_default(),
                     kind: ControlNodeKind::Loop {
                         initial_inputs,
                         body,
@@ -674,20 +1792,42 @@ impl<'a> Structurizer<'a> {
                    `ControlInst` (CFG wasn't unstructured in the first place?)",
             );
 
-        /// Marker error type for unhandled [`ControlInst`]s below.
-        struct UnsupportedControlInst(ControlInst);
-
         let region_from_control_inst = {
             let ControlInst {
-                attrs,
+                mut attrs,
                 kind,
                 inputs,
                 targets,
                 target_inputs,
             } = control_inst;
 
-            // FIXME(eddyb) this loses `attrs`.
-            let _ = attrs;
+            // `LoopControl` hints (from `OpLoopMerge`) only make sense on a
+            // `Loop`, which can only be created later (if `unstructured_region`
+            // ends up being the start of its body), while any other `attrs`
+            // are kept on the `Select` (if any) built from this `ControlInst`.
+            // FIXME(eddyb) this still loses `attrs` for `Branch`/`Return`/etc.
+            let wk = &spv::spec::Spec::get().well_known;
+            let (loop_control_attrs, other_attrs): (Vec<_>, Vec<_>) = self.cx[attrs]
+                .attrs
+                .iter()
+                .cloned()
+                .partition(|attr| match attr {
+                    Attr::SpvBitflagsOperand(imms) => {
+                        matches!(imms[..], [spv::Imm::Short(kind, _), ..] if kind == wk.LoopControl)
+                    }
+                    _ => false,
+                });
+            if !loop_control_attrs.is_empty() {
+                self.loop_header_attrs.insert(
+                    unstructured_region,
+                    self.cx.intern(AttrSetDef {
+                        attrs: loop_control_attrs.into_iter().collect(),
+                    }),
+                );
+                attrs = self.cx.intern(AttrSetDef {
+                    attrs: other_attrs.into_iter().collect(),
+                });
+            }
 
             let child_regions: SmallVec<[_; 8]> = targets
                 .iter()
@@ -707,44 +1847,56 @@ impl<'a> Structurizer<'a> {
                     // actually encoding it in `ControlNode`/`ControlRegion`
                     // (e.g. a new `ControlKind`, or replacing region `outputs`),
                     // but it's simpler to handle it like this.
-                    Ok(PartialControlRegion {
+                    PartialControlRegion {
                         children: EntityList::empty(),
                         deferred_edges: DeferredEdgeBundleSet {
                             target_to_deferred: [].into_iter().collect(),
                         },
                         deferred_return: None,
-                    })
+                    }
                 }
 
-                ControlInstKind::ExitInvocation(_) => {
+                ControlInstKind::ExitInvocation(kind) => {
                     assert_eq!(child_regions.len(), 0);
 
-                    // FIXME(eddyb) introduce equivalent `ControlNodeKind` for these.
-                    Err(UnsupportedControlInst(ControlInst {
-                        attrs,
-                        kind,
-                        inputs,
-                        targets,
-                        target_inputs,
-                    }))
+                    let exit_invocation_node = self.func_def_body.control_nodes.define(
+                        self.cx,
+                        ControlNodeDef {
+                            attrs,
+                            kind: ControlNodeKind::ExitInvocation { kind, inputs },
+                            outputs: [].into_iter().collect(),
+                        }
+                        .into(),
+                    );
+                    let mut children = EntityList::empty();
+                    children
+                        .insert_last(exit_invocation_node, &mut self.func_def_body.control_nodes);
+
+                    PartialControlRegion {
+                        children,
+                        deferred_edges: DeferredEdgeBundleSet {
+                            target_to_deferred: [].into_iter().collect(),
+                        },
+                        deferred_return: None,
+                    }
                 }
 
                 ControlInstKind::Return => {
                     assert_eq!(child_regions.len(), 0);
 
-                    Ok(PartialControlRegion {
+                    PartialControlRegion {
                         children: EntityList::empty(),
                         deferred_edges: DeferredEdgeBundleSet {
                             target_to_deferred: [].into_iter().collect(),
                         },
                         deferred_return: Some(inputs),
-                    })
+                    }
                 }
 
                 ControlInstKind::Branch => {
                     assert_eq!((inputs.len(), child_regions.len()), (0, 1));
 
-                    Ok(child_regions.into_iter().next().unwrap())
+                    child_regions.into_iter().next().unwrap()
                 }
 
                 ControlInstKind::SelectBranch(kind) => {
@@ -752,58 +1904,11 @@ impl<'a> Structurizer<'a> {
 
                     let scrutinee = inputs[0];
 
-                    Ok(self.structurize_select(kind, scrutinee, child_regions))
+                    self.structurize_select(attrs, kind, scrutinee, child_regions)
                 }
             }
         };
 
-        let region_from_control_inst =
-            region_from_control_inst.unwrap_or_else(|UnsupportedControlInst(control_inst)| {
-                // HACK(eddyb) this only remains used for `ExitInvocation`.
-                assert!(control_inst.targets.is_empty());
-
-                // HACK(eddyb) attach the unsupported `ControlInst` to a fresh
-                // new "proxy" `ControlRegion`, that can then be the target of
-                // a deferred edge, specially crafted to be unclaimable.
-                let proxy = self.func_def_body.control_regions.define(
-                    self.cx,
-                    ControlRegionDef {
-                        inputs: [].into_iter().collect(),
-                        children: EntityList::empty(),
-                        outputs: [].into_iter().collect(),
-                    },
-                );
-                self.func_def_body
-                    .unstructured_cfg
-                    .as_mut()
-                    .unwrap()
-                    .control_inst_on_exit_from
-                    .insert(proxy, control_inst);
-                self.structurize_region_state
-                    .insert(proxy, StructurizeRegionState::InProgress);
-                self.incoming_edge_counts
-                    .insert(proxy, IncomingEdgeCount::ONE);
-                let deferred_proxy = DeferredEdgeBundle {
-                    condition: Value::Const(self.const_true),
-                    edge_bundle: IncomingEdgeBundle {
-                        target: proxy,
-                        accumulated_count: IncomingEdgeCount::default(),
-                        target_inputs: [].into_iter().collect(),
-                    },
-                };
-
-                PartialControlRegion {
-                    children: EntityList::empty(),
-                    deferred_edges: DeferredEdgeBundleSet {
-                        target_to_deferred: [deferred_proxy]
-                            .into_iter()
-                            .map(|d| (d.edge_bundle.target, d))
-                            .collect(),
-                    },
-                    deferred_return: None,
-                }
-            });
-
         // Prepend `unstructured_region`'s children to `region_from_control_inst`.
         let mut region = {
             let mut children = self.func_def_body.at(unstructured_region).def().children;
@@ -873,6 +1978,7 @@ impl<'a> Structurizer<'a> {
                 then_region
             } else {
                 self.structurize_select(
+                    AttrSet::default(),
                     SelectionKind::BoolCond,
                     condition,
                     [then_region, else_region].into_iter().collect(),
@@ -915,6 +2021,7 @@ impl<'a> Structurizer<'a> {
     /// merging all of their `deferred_{edges,returns}` together.
     fn structurize_select(
         &mut self,
+        attrs: AttrSet,
         kind: SelectionKind,
         scrutinee: Value,
         cases: SmallVec<[PartialControlRegion; 8]>,
@@ -1111,6 +2218,7 @@ impl<'a> Structurizer<'a> {
         let select_node = self.func_def_body.control_nodes.define(
             self.cx,
             ControlNodeDef {
+                attrs,
                 kind,
                 outputs: output_decls,
             }
@@ -1310,12 +2418,10 @@ impl<'a> Structurizer<'a> {
     /// Create an undefined constant (as a placeholder where a value needs to be
     /// present, but won't actually be used), of type `ty`.
     fn const_undef(&self, ty: Type) -> Const {
-        // FIXME(eddyb) SPIR-T should have native undef itself.
-        let wk = &spv::spec::Spec::get().well_known;
         self.cx.intern(ConstDef {
             attrs: AttrSet::default(),
             ty,
-            ctor: ConstCtor::SpvInst(wk.OpUndef.into()),
+            ctor: ConstCtor::Undef,
             ctor_args: [].into_iter().collect(),
         })
     }
@@ -240,6 +240,13 @@ impl<E: sealed::Entity> EntityDefs<E> {
         entity
     }
 
+    /// Get the definition of `entity`, or `None` if `entity` wasn't defined
+    /// in this [`EntityDefs`] (e.g. it was defined in another function body).
+    pub fn get(&self, entity: E) -> Option<&E::Def> {
+        self.entity_to_flattened(entity)
+            .and_then(|i| self.flattened.get(i))
+    }
+
     fn entity_to_flattened(&self, entity: E) -> Option<usize> {
         let (chunk_start, intra_chunk_idx) = entity.to_chunk_start_and_intra_chunk_idx();
         let flattened_base = match self.incomplete_chunk_start_and_flattened_base {
@@ -260,9 +267,7 @@ impl<E: sealed::Entity> std::ops::Index<E> for EntityDefs<E> {
     type Output = E::Def;
 
     fn index(&self, entity: E) -> &Self::Output {
-        self.entity_to_flattened(entity)
-            .and_then(|i| self.flattened.get(i))
-            .unwrap()
+        self.get(entity).unwrap()
     }
 }
 
@@ -558,6 +563,53 @@ impl<E: sealed::Entity<Def = EntityListNode<E, D>>, D> EntityList<E> {
         });
     }
 
+    /// Remove `node` (defined in `defs`) from `self`.
+    #[track_caller]
+    pub fn remove(&mut self, node: E, defs: &mut EntityDefs<E>) {
+        let (prev, next) = {
+            let node_def = &mut defs[node];
+            (node_def.prev.take(), node_def.next.take())
+        };
+
+        // FIXME(eddyb) the assertions below should be impossible to trigger,
+        // as they involve the `EntityListNode`s links, which should be unforgeable.
+        match prev {
+            Some(prev) => {
+                let prev_def = &mut defs[prev];
+                assert!(
+                    prev_def.next == Some(node),
+                    "invalid EntityListNode: `node->prev->next != node`"
+                );
+                prev_def.next = next;
+            }
+            None => assert!(
+                self.0.map(|this| this.first) == Some(node),
+                "EntityList::remove: node not in this list (or `node->prev` missing)"
+            ),
+        }
+        match next {
+            Some(next) => {
+                let next_def = &mut defs[next];
+                assert!(
+                    next_def.prev == Some(node),
+                    "invalid EntityListNode: `node->next->prev != node`"
+                );
+                next_def.prev = prev;
+            }
+            None => assert!(
+                self.0.map(|this| this.last) == Some(node),
+                "EntityList::remove: node not in this list (or `node->next` missing)"
+            ),
+        }
+
+        self.0 = self.0.and_then(|this| {
+            Some(FirstLast {
+                first: if prev.is_some() { this.first } else { next? },
+                last: if next.is_some() { this.last } else { prev? },
+            })
+        });
+    }
+
     /// Insert all of `list_to_prepend`'s nodes at the start of `self`.
     #[track_caller]
     pub fn prepend(&mut self, list_to_prepend: Self, defs: &mut EntityDefs<E>) {
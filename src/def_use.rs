@@ -0,0 +1,375 @@
+//! Def-use chains, i.e. an index of all the uses of each [`Value`] in a function.
+
+use crate::{
+    ControlNode, ControlNodeKind, ControlRegion, DataInst, FuncDefBody, FxIndexSet, Value,
+};
+use rustc_hash::FxHashMap;
+
+/// A single use of a [`Value`] (i.e. an operand), in some function body.
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+pub enum UseSite {
+    /// `inputs[input_idx]` of a [`DataInstDef`](crate::DataInstDef).
+    DataInstInput { inst: DataInst, input_idx: u32 },
+
+    /// `outputs[output_idx]` of a [`ControlRegionDef`](crate::ControlRegionDef).
+    ControlRegionOutput {
+        region: ControlRegion,
+        output_idx: u32,
+    },
+
+    /// `scrutinee` of a [`ControlNodeKind::Select`].
+    SelectScrutinee(ControlNode),
+
+    /// `initial_inputs[input_idx]` of a [`ControlNodeKind::Loop`].
+    LoopInitialInput {
+        loop_node: ControlNode,
+        input_idx: u32,
+    },
+
+    /// `repeat_condition` of a [`ControlNodeKind::Loop`].
+    LoopRepeatCondition(ControlNode),
+
+    /// `inputs[input_idx]` of a [`ControlNodeKind::ExitInvocation`].
+    ExitInvocationInput {
+        control_node: ControlNode,
+        input_idx: u32,
+    },
+
+    /// `inputs[input_idx]` of the [`ControlInst`](crate::cfg::ControlInst)
+    /// on exit from `region` (in an unstructured CFG).
+    ControlInstInput {
+        region: ControlRegion,
+        input_idx: u32,
+    },
+
+    /// `target_inputs[target][input_idx]` of the [`ControlInst`](crate::cfg::ControlInst)
+    /// on exit from `region` (in an unstructured CFG).
+    ControlInstTargetInput {
+        region: ControlRegion,
+        target: ControlRegion,
+        input_idx: u32,
+    },
+}
+
+impl UseSite {
+    /// Get the [`Value`] used at this site, in `func_def_body`.
+    pub fn get(self, func_def_body: &FuncDefBody) -> Value {
+        let control_inst_on_exit_from = |region| {
+            &func_def_body
+                .unstructured_cfg
+                .as_ref()
+                .expect("def_use: CFG use site, in a function without a CFG")
+                .control_inst_on_exit_from[region]
+        };
+        match self {
+            Self::DataInstInput { inst, input_idx } => {
+                func_def_body.data_insts[inst].inputs[input_idx as usize]
+            }
+            Self::ControlRegionOutput { region, output_idx } => {
+                func_def_body.control_regions[region].outputs[output_idx as usize]
+            }
+            Self::SelectScrutinee(control_node) => {
+                match &func_def_body.control_nodes[control_node].kind {
+                    &ControlNodeKind::Select { scrutinee, .. } => scrutinee,
+                    _ => unreachable!(),
+                }
+            }
+            Self::LoopInitialInput {
+                loop_node,
+                input_idx,
+            } => match &func_def_body.control_nodes[loop_node].kind {
+                ControlNodeKind::Loop { initial_inputs, .. } => initial_inputs[input_idx as usize],
+                _ => unreachable!(),
+            },
+            Self::LoopRepeatCondition(loop_node) => {
+                match &func_def_body.control_nodes[loop_node].kind {
+                    &ControlNodeKind::Loop {
+                        repeat_condition, ..
+                    } => repeat_condition,
+                    _ => unreachable!(),
+                }
+            }
+            Self::ExitInvocationInput {
+                control_node,
+                input_idx,
+            } => match &func_def_body.control_nodes[control_node].kind {
+                ControlNodeKind::ExitInvocation { inputs, .. } => inputs[input_idx as usize],
+                _ => unreachable!(),
+            },
+            Self::ControlInstInput { region, input_idx } => {
+                control_inst_on_exit_from(region).inputs[input_idx as usize]
+            }
+            Self::ControlInstTargetInput {
+                region,
+                target,
+                input_idx,
+            } => control_inst_on_exit_from(region).target_inputs[&target][input_idx as usize],
+        }
+    }
+
+    /// Get mutable access to the [`Value`] used at this site, in `func_def_body`.
+    ///
+    /// **Note**: if the [`Value`] is replaced, any [`DefUse`] for `func_def_body`
+    /// also has to be updated (or [`DefUse::replace_use`] used instead).
+    pub fn get_mut(self, func_def_body: &mut FuncDefBody) -> &mut Value {
+        let control_inst_on_exit_from = match &mut func_def_body.unstructured_cfg {
+            Some(cfg) => Some(&mut cfg.control_inst_on_exit_from),
+            None => None,
+        };
+        let control_inst_on_exit_from = |region| {
+            &mut control_inst_on_exit_from
+                .expect("def_use: CFG use site, in a function without a CFG")[region]
+        };
+        match self {
+            Self::DataInstInput { inst, input_idx } => {
+                &mut func_def_body.data_insts[inst].inputs[input_idx as usize]
+            }
+            Self::ControlRegionOutput { region, output_idx } => {
+                &mut func_def_body.control_regions[region].outputs[output_idx as usize]
+            }
+            Self::SelectScrutinee(control_node) => {
+                match &mut func_def_body.control_nodes[control_node].kind {
+                    ControlNodeKind::Select { scrutinee, .. } => scrutinee,
+                    _ => unreachable!(),
+                }
+            }
+            Self::LoopInitialInput {
+                loop_node,
+                input_idx,
+            } => match &mut func_def_body.control_nodes[loop_node].kind {
+                ControlNodeKind::Loop { initial_inputs, .. } => {
+                    &mut initial_inputs[input_idx as usize]
+                }
+                _ => unreachable!(),
+            },
+            Self::LoopRepeatCondition(loop_node) => {
+                match &mut func_def_body.control_nodes[loop_node].kind {
+                    ControlNodeKind::Loop {
+                        repeat_condition, ..
+                    } => repeat_condition,
+                    _ => unreachable!(),
+                }
+            }
+            Self::ExitInvocationInput {
+                control_node,
+                input_idx,
+            } => match &mut func_def_body.control_nodes[control_node].kind {
+                ControlNodeKind::ExitInvocation { inputs, .. } => &mut inputs[input_idx as usize],
+                _ => unreachable!(),
+            },
+            Self::ControlInstInput { region, input_idx } => {
+                &mut control_inst_on_exit_from(region).inputs[input_idx as usize]
+            }
+            Self::ControlInstTargetInput {
+                region,
+                target,
+                input_idx,
+            } => &mut control_inst_on_exit_from(region).target_inputs[&target][input_idx as usize],
+        }
+    }
+}
+
+/// Index of all the uses (see [`UseSite`]) of each [`Value`] in a function body,
+/// including constants (i.e. [`Value::Const`]), allowing queries such as "what
+/// uses this value?" without visiting the whole function body.
+///
+/// When the function body is modified, the [`DefUse`] has to be kept up to date,
+/// either by using the methods that both modify the function body and update
+/// the [`DefUse`] (e.g. [`DefUse::replace_all_uses_with`]), or manually (e.g.
+/// with [`DefUse::add_data_inst_uses`] for newly inserted instructions).
+//
+// FIXME(eddyb) uses in unreachable regions of an unstructured CFG aren't found.
+#[derive(Clone, Default)]
+pub struct DefUse {
+    uses: FxHashMap<Value, FxIndexSet<UseSite>>,
+}
+
+impl DefUse {
+    pub fn compute(func_def_body: &FuncDefBody) -> Self {
+        let mut def_use = Self::default();
+        match &func_def_body.unstructured_cfg {
+            None => def_use.add_region_uses(func_def_body, func_def_body.body),
+            Some(cfg) => {
+                for region in cfg.rev_post_order(func_def_body) {
+                    def_use.add_region_uses(func_def_body, region);
+
+                    let control_inst = &cfg.control_inst_on_exit_from[region];
+                    for (input_idx, &v) in control_inst.inputs.iter().enumerate() {
+                        def_use.add_use(
+                            v,
+                            UseSite::ControlInstInput {
+                                region,
+                                input_idx: input_idx.try_into().unwrap(),
+                            },
+                        );
+                    }
+                    for (&target, inputs) in &control_inst.target_inputs {
+                        for (input_idx, &v) in inputs.iter().enumerate() {
+                            def_use.add_use(
+                                v,
+                                UseSite::ControlInstTargetInput {
+                                    region,
+                                    target,
+                                    input_idx: input_idx.try_into().unwrap(),
+                                },
+                            );
+                        }
+                    }
+                }
+            }
+        }
+        def_use
+    }
+
+    /// Add all the uses in `region` (including in nested regions), but not those
+    /// in its [`ControlInst`](crate::cfg::ControlInst), if it's part of a CFG.
+    fn add_region_uses(&mut self, func_def_body: &FuncDefBody, region: ControlRegion) {
+        for func_at_control_node in func_def_body.at(region).at_children() {
+            let control_node = func_at_control_node.position;
+            match &func_at_control_node.def().kind {
+                &ControlNodeKind::Block { insts } => {
+                    for func_at_inst in func_def_body.at(insts) {
+                        self.add_data_inst_uses(func_def_body, func_at_inst.position);
+                    }
+                }
+                ControlNodeKind::Select {
+                    kind: _,
+                    scrutinee,
+                    cases,
+                } => {
+                    self.add_use(*scrutinee, UseSite::SelectScrutinee(control_node));
+                    for &case in cases {
+                        self.add_region_uses(func_def_body, case);
+                    }
+                }
+                ControlNodeKind::Loop {
+                    initial_inputs,
+                    body,
+                    repeat_condition,
+                } => {
+                    for (input_idx, &v) in initial_inputs.iter().enumerate() {
+                        self.add_use(
+                            v,
+                            UseSite::LoopInitialInput {
+                                loop_node: control_node,
+                                input_idx: input_idx.try_into().unwrap(),
+                            },
+                        );
+                    }
+                    self.add_region_uses(func_def_body, *body);
+                    self.add_use(
+                        *repeat_condition,
+                        UseSite::LoopRepeatCondition(control_node),
+                    );
+                }
+                ControlNodeKind::ExitInvocation { kind: _, inputs } => {
+                    for (input_idx, &v) in inputs.iter().enumerate() {
+                        self.add_use(
+                            v,
+                            UseSite::ExitInvocationInput {
+                                control_node,
+                                input_idx: input_idx.try_into().unwrap(),
+                            },
+                        );
+                    }
+                }
+            }
+        }
+
+        let outputs = &func_def_body.control_regions[region].outputs;
+        for (output_idx, &v) in outputs.iter().enumerate() {
+            self.add_use(
+                v,
+                UseSite::ControlRegionOutput {
+                    region,
+                    output_idx: output_idx.try_into().unwrap(),
+                },
+            );
+        }
+    }
+
+    /// Iterate over all the uses of `v` (in the order they were added).
+    pub fn uses(&self, v: Value) -> impl Iterator<Item = UseSite> + '_ {
+        self.uses.get(&v).into_iter().flatten().copied()
+    }
+
+    /// Get the number of uses of `v`.
+    pub fn use_count(&self, v: Value) -> usize {
+        self.uses.get(&v).map_or(0, |uses| uses.len())
+    }
+
+    /// Returns `true` if `v` has any uses.
+    pub fn has_uses(&self, v: Value) -> bool {
+        self.use_count(v) > 0
+    }
+
+    /// Record a new use of `v` at `use_site`.
+    pub fn add_use(&mut self, v: Value, use_site: UseSite) {
+        self.uses.entry(v).or_default().insert(use_site);
+    }
+
+    /// Forget the use of `v` at `use_site` (if it was recorded).
+    pub fn remove_use(&mut self, v: Value, use_site: UseSite) {
+        if let Some(uses) = self.uses.get_mut(&v) {
+            uses.shift_remove(&use_site);
+            if uses.is_empty() {
+                self.uses.remove(&v);
+            }
+        }
+    }
+
+    /// Replace the [`Value`] used at `use_site` (in `func_def_body`) with `new_v`.
+    pub fn replace_use(
+        &mut self,
+        func_def_body: &mut FuncDefBody,
+        use_site: UseSite,
+        new_v: Value,
+    ) {
+        let v = use_site.get_mut(func_def_body);
+        let old_v = std::mem::replace(v, new_v);
+        self.remove_use(old_v, use_site);
+        self.add_use(new_v, use_site);
+    }
+
+    /// Replace all the uses of `old_v` (in `func_def_body`) with uses of `new_v`.
+    pub fn replace_all_uses_with(
+        &mut self,
+        func_def_body: &mut FuncDefBody,
+        old_v: Value,
+        new_v: Value,
+    ) {
+        if old_v == new_v {
+            return;
+        }
+        for use_site in self.uses.remove(&old_v).into_iter().flatten() {
+            *use_site.get_mut(func_def_body) = new_v;
+            self.add_use(new_v, use_site);
+        }
+    }
+
+    /// Record all the uses in the inputs of `inst` (e.g. after inserting it).
+    pub fn add_data_inst_uses(&mut self, func_def_body: &FuncDefBody, inst: DataInst) {
+        for (input_idx, &v) in func_def_body.data_insts[inst].inputs.iter().enumerate() {
+            self.add_use(
+                v,
+                UseSite::DataInstInput {
+                    inst,
+                    input_idx: input_idx.try_into().unwrap(),
+                },
+            );
+        }
+    }
+
+    /// Forget all the uses in the inputs of `inst` (e.g. before removing it).
+    pub fn remove_data_inst_uses(&mut self, func_def_body: &FuncDefBody, inst: DataInst) {
+        for (input_idx, &v) in func_def_body.data_insts[inst].inputs.iter().enumerate() {
+            self.remove_use(
+                v,
+                UseSite::DataInstInput {
+                    inst,
+                    input_idx: input_idx.try_into().unwrap(),
+                },
+            );
+        }
+    }
+}
@@ -0,0 +1,264 @@
+//! Diagnostics attached to the IR (see [`Attr::Diagnostic`]), and collecting
+//! them from a whole [`Module`] (see [`ModuleDiagnostics`]).
+
+use crate::func_at::FuncAt;
+use crate::visit::{InnerVisit, Visitor};
+use crate::{
+    Attr, AttrSet, AttrSetDef, Const, Context, ControlNode, ControlNodeKind, ControlRegion,
+    DataInst, DiagnosticSeverity, Func, GlobalVar, Module, Type,
+};
+use rustc_hash::FxHashSet;
+use std::fmt;
+use std::mem;
+
+/// Return `attrs` with an added [`Attr::Diagnostic`] (e.g. for a pass to report
+/// an error or warning about the definition using `attrs`).
+pub fn with_diagnostic(
+    cx: &Context,
+    attrs: AttrSet,
+    severity: DiagnosticSeverity,
+    message: impl Into<String>,
+    related: Vec<String>,
+) -> AttrSet {
+    let mut attrs = cx[attrs].attrs.clone();
+    attrs.insert(Attr::Diagnostic {
+        severity,
+        message: message.into(),
+        related,
+    });
+    cx.intern(AttrSetDef { attrs })
+}
+
+/// The definition (in a [`Module`]) an [`AttachedDiagnostic`] was attached to.
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+pub enum AttachedTo {
+    /// Anything not part of any other definition (e.g. the module's exports).
+    Module,
+
+    Type(Type),
+    Const(Const),
+    GlobalVar(GlobalVar),
+    Func(Func),
+    ControlRegion(Func, ControlRegion),
+    ControlNode(Func, ControlNode),
+    DataInst(Func, DataInst),
+}
+
+impl fmt::Display for AttachedTo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Module => "module",
+            Self::Type(_) => "type",
+            Self::Const(_) => "constant",
+            Self::GlobalVar(_) => "global variable",
+            Self::Func(_) => "function",
+            Self::ControlRegion(..) => "control region",
+            Self::ControlNode(..) => "control node",
+            Self::DataInst(..) => "instruction",
+        })
+    }
+}
+
+/// An [`Attr::Diagnostic`], alongside the definition it was attached to.
+#[derive(Clone)]
+pub struct AttachedDiagnostic {
+    pub attached_to: AttachedTo,
+
+    pub severity: DiagnosticSeverity,
+    pub message: String,
+    pub related: Vec<String>,
+}
+
+impl fmt::Display for AttachedDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} (on {})",
+            self.severity, self.message, self.attached_to
+        )?;
+        for related in &self.related {
+            write!(f, "\n  note: {related}")?;
+        }
+        Ok(())
+    }
+}
+
+/// All the [`Attr::Diagnostic`]s in a [`Module`] (see [`ModuleDiagnostics::collect`]).
+#[derive(Clone, Default)]
+pub struct ModuleDiagnostics {
+    pub diagnostics: Vec<AttachedDiagnostic>,
+}
+
+impl fmt::Display for ModuleDiagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, diag) in self.diagnostics.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{diag}")?;
+        }
+        Ok(())
+    }
+}
+
+impl ModuleDiagnostics {
+    /// Collect all the [`Attr::Diagnostic`]s in the definitions reachable from
+    /// `module`'s exports (in the order they're first encountered).
+    ///
+    /// Diagnostics on types and constants are only collected once (attached to
+    /// the type/constant itself), regardless of how many times they're used,
+    /// while those on any other definitions are collected for each definition
+    /// (even when the same [`AttrSet`] is shared between several of them).
+    pub fn collect(module: &Module) -> Self {
+        let mut collector = DiagnosticCollector {
+            cx: module.cx_ref(),
+            module,
+
+            attached_to: AttachedTo::Module,
+
+            seen_types: FxHashSet::default(),
+            seen_consts: FxHashSet::default(),
+            seen_global_vars: FxHashSet::default(),
+            seen_funcs: FxHashSet::default(),
+
+            diagnostics: vec![],
+        };
+        collector.visit_module(module);
+        Self {
+            diagnostics: collector.diagnostics,
+        }
+    }
+
+    /// Get the number of diagnostics with the given `severity`.
+    pub fn count(&self, severity: DiagnosticSeverity) -> usize {
+        self.diagnostics
+            .iter()
+            .filter(|diag| diag.severity == severity)
+            .count()
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.count(DiagnosticSeverity::Error) > 0
+    }
+}
+
+struct DiagnosticCollector<'a> {
+    cx: &'a Context,
+    module: &'a Module,
+
+    /// The definition currently being visited, that any diagnostics found in
+    /// [`AttrSet`]s (used by it, directly) are attached to.
+    attached_to: AttachedTo,
+
+    // FIXME(eddyb) build some automation to avoid ever repeating these.
+    seen_types: FxHashSet<Type>,
+    seen_consts: FxHashSet<Const>,
+    seen_global_vars: FxHashSet<GlobalVar>,
+    seen_funcs: FxHashSet<Func>,
+
+    diagnostics: Vec<AttachedDiagnostic>,
+}
+
+impl DiagnosticCollector<'_> {
+    /// Get the function currently being visited (only used when inside one).
+    fn current_func(&self) -> Func {
+        match self.attached_to {
+            AttachedTo::Func(func)
+            | AttachedTo::ControlRegion(func, _)
+            | AttachedTo::ControlNode(func, _)
+            | AttachedTo::DataInst(func, _) => func,
+            AttachedTo::Module
+            | AttachedTo::Type(_)
+            | AttachedTo::Const(_)
+            | AttachedTo::GlobalVar(_) => unreachable!("not inside a function"),
+        }
+    }
+}
+
+impl<'a> Visitor<'a> for DiagnosticCollector<'a> {
+    fn visit_attr_set_use(&mut self, attrs: AttrSet) {
+        self.visit_attr_set_def(&self.cx[attrs]);
+    }
+    fn visit_type_use(&mut self, ty: Type) {
+        if self.seen_types.insert(ty) {
+            let outer = mem::replace(&mut self.attached_to, AttachedTo::Type(ty));
+            self.visit_type_def(&self.cx[ty]);
+            self.attached_to = outer;
+        }
+    }
+    fn visit_const_use(&mut self, ct: Const) {
+        if self.seen_consts.insert(ct) {
+            let outer = mem::replace(&mut self.attached_to, AttachedTo::Const(ct));
+            self.visit_const_def(&self.cx[ct]);
+            self.attached_to = outer;
+        }
+    }
+    fn visit_global_var_use(&mut self, gv: GlobalVar) {
+        if self.seen_global_vars.insert(gv) {
+            let outer = mem::replace(&mut self.attached_to, AttachedTo::GlobalVar(gv));
+            self.visit_global_var_decl(&self.module.global_vars[gv]);
+            self.attached_to = outer;
+        }
+    }
+    fn visit_func_use(&mut self, func: Func) {
+        if self.seen_funcs.insert(func) {
+            let outer = mem::replace(&mut self.attached_to, AttachedTo::Func(func));
+            self.visit_func_decl(&self.module.funcs[func]);
+            self.attached_to = outer;
+        }
+    }
+
+    fn visit_attr(&mut self, attr: &'a Attr) {
+        if let Attr::Diagnostic {
+            severity,
+            message,
+            related,
+        } = attr
+        {
+            self.diagnostics.push(AttachedDiagnostic {
+                attached_to: self.attached_to,
+                severity: *severity,
+                message: message.clone(),
+                related: related.clone(),
+            });
+        }
+        attr.inner_visit_with(self);
+    }
+
+    fn visit_control_region_def(&mut self, func_at_control_region: FuncAt<'a, ControlRegion>) {
+        let attached_to =
+            AttachedTo::ControlRegion(self.current_func(), func_at_control_region.position);
+        let outer = mem::replace(&mut self.attached_to, attached_to);
+        func_at_control_region.inner_visit_with(self);
+        self.attached_to = outer;
+    }
+
+    fn visit_control_node_def(&mut self, func_at_control_node: FuncAt<'a, ControlNode>) {
+        let func = self.current_func();
+        let outer = mem::replace(
+            &mut self.attached_to,
+            AttachedTo::ControlNode(func, func_at_control_node.position),
+        );
+        let control_node_def = func_at_control_node.def();
+        match control_node_def.kind {
+            // NOTE(eddyb) `DataInst`s are visited here (instead of by the
+            // `inner_visit_with` below), to keep track of their positions.
+            ControlNodeKind::Block { insts } => {
+                self.visit_attr_set_use(control_node_def.attrs);
+                for func_at_inst in func_at_control_node.at(insts) {
+                    let node = mem::replace(
+                        &mut self.attached_to,
+                        AttachedTo::DataInst(func, func_at_inst.position),
+                    );
+                    self.visit_data_inst_def(func_at_inst.def());
+                    self.attached_to = node;
+                }
+                for output in &control_node_def.outputs {
+                    output.inner_visit_with(self);
+                }
+            }
+            _ => func_at_control_node.inner_visit_with(self),
+        }
+        self.attached_to = outer;
+    }
+}
@@ -0,0 +1,39 @@
+//! Controlling the visibility of definitions, i.e. which of them are exported
+//! (see [`Module::exports`]), without having to manipulate `exports` directly.
+
+use crate::{ExportKey, Exportee, Module};
+
+impl Module {
+    /// Demote the export with the key `export_key` to an internal definition,
+    /// i.e. remove it from [`Module::exports`] (returning its [`Exportee`],
+    /// if it was present), without removing the definition itself.
+    ///
+    /// The definition can still be used from the rest of the module, but if
+    /// it's not, it becomes "dead", and can be removed afterwards, using
+    /// [`prune_unreachable`](crate::passes::prune::prune_unreachable).
+    pub fn demote_export(&mut self, export_key: &ExportKey) -> Option<Exportee> {
+        // NOTE(eddyb) `shift_remove` keeps the order of the remaining exports.
+        self.exports.shift_remove(export_key)
+    }
+
+    /// Promote `exportee` to an export, with the link name `name` (i.e. using
+    /// [`ExportKey::LinkName`]), returning the previous [`Exportee`] for that
+    /// link name, if any (which is replaced, but remains defined, as an
+    /// internal definition, like with [`Module::demote_export`]).
+    //
+    // FIXME(eddyb) consider disallowing (or at least warning about) exporting
+    // imports, which is only useful for re-exporting them under another name.
+    pub fn promote_to_export(&mut self, name: &str, exportee: Exportee) -> Option<Exportee> {
+        let export_key = ExportKey::LinkName(self.cx_ref().intern(name));
+        self.exports.insert(export_key, exportee)
+    }
+
+    /// Keep only the exports for which `keep(export_key, exportee)` returns
+    /// `true`, demoting all others to internal definitions (see also
+    /// [`Module::demote_export`], and [`minimize_exports`](crate::passes::link::minimize_exports)
+    /// for keeping exports used by other exports, through imports).
+    pub fn retain_exports(&mut self, mut keep: impl FnMut(&ExportKey, Exportee) -> bool) {
+        self.exports
+            .retain(|export_key, &mut exportee| keep(export_key, exportee));
+    }
+}
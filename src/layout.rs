@@ -0,0 +1,687 @@
+//! Memory layout computation, i.e. the sizes and alignments of types (and the
+//! offsets of struct members) in memory, following the standard Vulkan layout
+//! rules (see [`LayoutRules`]), and taking into account any explicit layout
+//! decorations (i.e. `Offset`, `ArrayStride`, `MatrixStride` and `RowMajor`).
+
+use crate::{
+    spv, Attr, AttrSet, AttrSetDef, Context, Type, TypeCtor, TypeCtorArg, TypeDef,
+};
+use smallvec::SmallVec;
+use std::fmt;
+
+/// Vulkan layout rules, which determine the alignment of each type, and so
+/// the offsets (and strides) that can be used (or are assigned) for it.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum LayoutRules {
+    /// "Standard uniform buffer layout" (also known as "std140"), i.e. the
+    /// "extended alignment" of arrays and structs is rounded up to 16 bytes.
+    Std140,
+
+    /// "Standard storage buffer layout" (also known as "std430"), i.e. the
+    /// "base alignment" of vectors is that of 2 or 4 of their components.
+    Std430,
+
+    /// "Scalar layout" (requiring `VK_EXT_scalar_block_layout`), i.e. the
+    /// "scalar alignment" of all types is that of their scalar components.
+    Scalar,
+}
+
+/// The size and alignment of a type, in memory.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct TypeLayout {
+    /// Size in bytes, or `None` for dynamically-sized types (i.e. runtime
+    /// arrays, and structs with a runtime array as their last member).
+    pub size: Option<u32>,
+
+    /// Alignment in bytes (always a power of two).
+    pub align: u32,
+}
+
+/// Error returned when a type doesn't have a layout (e.g. it's not allowed in
+/// memory, or has an array length which isn't a constant), or when its explicit
+/// layout decorations don't follow the [`LayoutRules`].
+#[derive(Debug)]
+pub struct LayoutError {
+    pub message: String,
+}
+
+impl fmt::Display for LayoutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid memory layout: {}", self.message)
+    }
+}
+
+impl std::error::Error for LayoutError {}
+
+fn err<T>(message: impl Into<String>) -> Result<T, LayoutError> {
+    Err(LayoutError {
+        message: message.into(),
+    })
+}
+
+/// Layout of matrices, from the (`MatrixStride`, `RowMajor` and `ColMajor`)
+/// decorations of the struct member containing them (possibly in arrays).
+#[derive(Copy, Clone, Default)]
+struct MatrixLayout {
+    row_major: bool,
+    stride: Option<u32>,
+}
+
+impl MatrixLayout {
+    fn from_member_attrs(cx: &Context, attrs: AttrSet) -> Self {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        MatrixLayout {
+            row_major: find_decoration(cx, attrs, wk.RowMajor).is_some(),
+            stride: find_decoration(cx, attrs, wk.MatrixStride).and_then(literal_u32),
+        }
+    }
+}
+
+/// Helper for computing the layout of types, following some [`LayoutRules`].
+pub struct LayoutCx<'a> {
+    cx: &'a Context,
+    rules: LayoutRules,
+}
+
+impl<'a> LayoutCx<'a> {
+    pub fn new(cx: &'a Context, rules: LayoutRules) -> Self {
+        Self { cx, rules }
+    }
+
+    /// Compute the layout of `ty`, using its explicit layout decorations,
+    /// where present, and the [`LayoutRules`] for everything else.
+    pub fn layout_of(&self, ty: Type) -> Result<TypeLayout, LayoutError> {
+        self.layout_of_with_matrix_layout(ty, MatrixLayout::default())
+    }
+
+    /// Compute the offsets of all the members of the struct type `ty`, using
+    /// their `Offset` decorations, where present, and the [`LayoutRules`]
+    /// for everything else (i.e. each member without an `Offset` is placed
+    /// right after the previous one, with only the padding needed to align it).
+    pub fn member_offsets(&self, ty: Type) -> Result<SmallVec<[u32; 4]>, LayoutError> {
+        match &self.cx[ty].ctor {
+            TypeCtor::Struct { .. } => Ok(self
+                .struct_layout(&self.cx[ty])?
+                .1
+                .into_iter()
+                .map(|member_layout| member_layout.offset)
+                .collect()),
+            _ => err("`member_offsets` requires a struct type"),
+        }
+    }
+
+    fn layout_of_with_matrix_layout(
+        &self,
+        ty: Type,
+        matrix_layout: MatrixLayout,
+    ) -> Result<TypeLayout, LayoutError> {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        let ty_def = &self.cx[ty];
+        match (&ty_def.ctor, &ty_def.ctor_args[..]) {
+            (TypeCtor::SpvInst(inst), [])
+                if [wk.OpTypeInt, wk.OpTypeFloat].contains(&inst.opcode) =>
+            {
+                let width = match inst.imms.first() {
+                    Some(&spv::Imm::Short(_, width)) if width.is_multiple_of(8) && width > 0 => {
+                        width
+                    }
+                    _ => return err("scalar type with unsupported width"),
+                };
+                Ok(TypeLayout {
+                    size: Some(width / 8),
+                    align: width / 8,
+                })
+            }
+            (TypeCtor::SpvInst(inst), []) if inst.opcode == wk.OpTypeBool => {
+                err("`OpTypeBool` isn't allowed in memory")
+            }
+            (TypeCtor::SpvInst(inst), &[TypeCtorArg::Type(elem_type)])
+                if inst.opcode == wk.OpTypeVector =>
+            {
+                let count = match inst.imms[..] {
+                    [spv::Imm::Short(_, count)] => count,
+                    _ => return err("vector type with unsupported component count"),
+                };
+                let elem_size = self.sized_layout_of(elem_type, MatrixLayout::default())?.0;
+                Ok(self.vector_layout(elem_size, count))
+            }
+            (TypeCtor::SpvInst(inst), [TypeCtorArg::Type(_)])
+                if inst.opcode == wk.OpTypePointer
+                    && inst.imms[..]
+                        == [spv::Imm::Short(wk.StorageClass, wk.PhysicalStorageBuffer)] =>
+            {
+                Ok(TypeLayout {
+                    size: Some(8),
+                    align: 8,
+                })
+            }
+            (&TypeCtor::RecursivePtr { storage_class }, _)
+                if storage_class == wk.PhysicalStorageBuffer =>
+            {
+                Ok(TypeLayout {
+                    size: Some(8),
+                    align: 8,
+                })
+            }
+            (&TypeCtor::Matrix { column_count }, &[TypeCtorArg::Type(column_type)]) => {
+                let matrix = self.matrix_layout(column_type, column_count, matrix_layout)?;
+                Ok(TypeLayout {
+                    size: Some(matrix.stride * matrix.count),
+                    align: matrix.align,
+                })
+            }
+            (TypeCtor::Array, &[TypeCtorArg::Type(elem_type), TypeCtorArg::Const(len)]) => {
+                let len = match spv::fold::const_splat_value(self.cx, len) {
+                    Some(spv::fold::ScalarValue::Int { bits, .. }) => {
+                        u32::try_from(bits).ok().filter(|&len| len > 0)
+                    }
+                    _ => None,
+                };
+                let len = match len {
+                    Some(len) => len,
+                    None => return err("array length isn't a (valid) constant integer"),
+                };
+                let (stride, align) =
+                    self.array_stride_and_align(ty_def, elem_type, matrix_layout)?;
+                match stride.checked_mul(len) {
+                    Some(size) => Ok(TypeLayout {
+                        size: Some(size),
+                        align,
+                    }),
+                    None => err("array size overflows `u32`"),
+                }
+            }
+            (TypeCtor::RuntimeArray, &[TypeCtorArg::Type(elem_type)]) => {
+                let (_, align) = self.array_stride_and_align(ty_def, elem_type, matrix_layout)?;
+                Ok(TypeLayout { size: None, align })
+            }
+            (TypeCtor::Struct { .. }, _) => Ok(self.struct_layout(ty_def)?.0),
+            _ => err("type isn't allowed in memory (or has no known layout)"),
+        }
+    }
+
+    /// Compute the layout of `ty`, like [`LayoutCx::layout_of`], but also
+    /// requiring that it's sized (returning its size alongside its alignment).
+    fn sized_layout_of(
+        &self,
+        ty: Type,
+        matrix_layout: MatrixLayout,
+    ) -> Result<(u32, u32), LayoutError> {
+        let layout = self.layout_of_with_matrix_layout(ty, matrix_layout)?;
+        match layout.size {
+            Some(size) => Ok((size, layout.align)),
+            None => err("dynamically-sized type used where a sized one is required"),
+        }
+    }
+
+    /// Compute the layout of a vector with `count` components of `elem_size`
+    /// bytes each (also used for the columns, or rows, of matrices).
+    fn vector_layout(&self, elem_size: u32, count: u32) -> TypeLayout {
+        let align = match self.rules {
+            LayoutRules::Scalar => elem_size,
+            LayoutRules::Std140 | LayoutRules::Std430 => {
+                if count == 2 {
+                    2 * elem_size
+                } else {
+                    4 * elem_size
+                }
+            }
+        };
+        TypeLayout {
+            size: Some(elem_size * count),
+            align,
+        }
+    }
+
+    /// Get the alignment of an array (or struct) containing elements (or members)
+    /// with at most `elem_align` alignment (only differs for [`LayoutRules::Std140`]).
+    fn aggregate_align(&self, elem_align: u32) -> u32 {
+        match self.rules {
+            LayoutRules::Std140 => elem_align.max(16),
+            LayoutRules::Std430 | LayoutRules::Scalar => elem_align,
+        }
+    }
+
+    /// Compute the layout of a matrix (with `column_count` columns of type
+    /// `column_type`), as an array of its columns (or rows, if `RowMajor`).
+    fn matrix_layout(
+        &self,
+        column_type: Type,
+        column_count: u32,
+        matrix_layout: MatrixLayout,
+    ) -> Result<MatrixArrayLayout, LayoutError> {
+        let (elem_type, row_count) = match spv::fold::vector_type(self.cx, column_type) {
+            Some(vector_type) => vector_type,
+            None => return err("matrix column type isn't a vector type"),
+        };
+        let elem_size = self.sized_layout_of(elem_type, MatrixLayout::default())?.0;
+
+        // NOTE(eddyb) matrices are laid out like arrays of their columns, or
+        // their rows (as vectors), for column-major and row-major, respectively.
+        let (vector_len, count) = if matrix_layout.row_major {
+            (column_count, row_count)
+        } else {
+            (row_count, column_count)
+        };
+        let vector_layout = self.vector_layout(elem_size, vector_len);
+        let vector_size = vector_layout.size.unwrap();
+        let align = self.aggregate_align(vector_layout.align);
+        Ok(MatrixArrayLayout {
+            vector_size,
+            stride: matrix_layout
+                .stride
+                .unwrap_or_else(|| round_up(vector_size, align)),
+            align,
+            count,
+        })
+    }
+
+    /// Get the stride (from the `ArrayStride` decoration, if present), and the
+    /// alignment, of the (possibly runtime) array type `array_type_def`.
+    fn array_stride_and_align(
+        &self,
+        array_type_def: &TypeDef,
+        elem_type: Type,
+        matrix_layout: MatrixLayout,
+    ) -> Result<(u32, u32), LayoutError> {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        let (elem_size, elem_align) = self.sized_layout_of(elem_type, matrix_layout)?;
+        let align = self.aggregate_align(elem_align);
+        let stride = find_decoration(self.cx, array_type_def.attrs, wk.ArrayStride)
+            .and_then(literal_u32)
+            .unwrap_or_else(|| round_up(elem_size, align));
+        Ok((stride, align))
+    }
+
+    /// Compute the layout of the struct type `struct_type_def`, alongside the
+    /// layouts (and offsets) of all of its members (see [`LayoutCx::member_offsets`]).
+    fn struct_layout(
+        &self,
+        struct_type_def: &TypeDef,
+    ) -> Result<(TypeLayout, SmallVec<[MemberLayout; 4]>), LayoutError> {
+        let members = match &struct_type_def.ctor {
+            TypeCtor::Struct { members } => members,
+            _ => unreachable!(),
+        };
+
+        let mut member_layouts = SmallVec::<[MemberLayout; 4]>::with_capacity(members.len());
+        let mut max_member_align = 1;
+        let mut end = 0;
+        for (member_idx, (member, member_type)) in
+            members.iter().zip(&struct_type_def.ctor_args).enumerate()
+        {
+            let member_type = match *member_type {
+                TypeCtorArg::Type(ty) => ty,
+                TypeCtorArg::Const(_) => unreachable!(),
+            };
+
+            let layout = self.layout_of_with_matrix_layout(
+                member_type,
+                MatrixLayout::from_member_attrs(self.cx, member.attrs),
+            )?;
+            let min_offset = match member_layouts.last() {
+                Some(prev) => match prev.min_next_offset() {
+                    Some(min_next_offset) => min_next_offset,
+                    None => {
+                        return err(format!(
+                            "struct member #{member_idx} follows a dynamically-sized member"
+                        ));
+                    }
+                },
+                None => 0,
+            };
+            let member_layout = MemberLayout {
+                offset: member
+                    .offset
+                    .unwrap_or_else(|| round_up(min_offset, layout.align)),
+                layout,
+                pads_to_align: self.rules != LayoutRules::Scalar
+                    && matches!(
+                        self.cx[member_type].ctor,
+                        TypeCtor::Struct { .. } | TypeCtor::Array | TypeCtor::Matrix { .. }
+                    ),
+            };
+            max_member_align = max_member_align.max(layout.align);
+            end = end.max(member_layout.min_next_offset().unwrap_or(0));
+            member_layouts.push(member_layout);
+        }
+
+        let align = self.aggregate_align(max_member_align);
+        let is_sized = match member_layouts.last() {
+            Some(last) => last.layout.size.is_some(),
+            None => true,
+        };
+        let layout = TypeLayout {
+            size: is_sized.then(|| round_up(end, align)),
+            align,
+        };
+        Ok((layout, member_layouts))
+    }
+
+    /// Check that all the explicit layout decorations of `ty` (and of all the
+    /// types nested in it) are present (i.e. `Offset` for all struct members,
+    /// `ArrayStride` for all arrays, and `MatrixStride` for all matrices), and
+    /// that they follow the [`LayoutRules`] (i.e. offsets and strides must be
+    /// multiples of the alignment, and struct members can't overlap).
+    pub fn validate_explicit_layout(&self, ty: Type) -> Result<(), LayoutError> {
+        self.validate_explicit_layout_with_matrix_layout(ty, None)
+    }
+
+    fn validate_explicit_layout_with_matrix_layout(
+        &self,
+        ty: Type,
+        matrix_layout: Option<MatrixLayout>,
+    ) -> Result<(), LayoutError> {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        let ty_def = &self.cx[ty];
+        match (&ty_def.ctor, &ty_def.ctor_args[..]) {
+            (&TypeCtor::Matrix { column_count }, &[TypeCtorArg::Type(column_type)]) => {
+                let matrix_layout = match matrix_layout {
+                    Some(matrix_layout) if matrix_layout.stride.is_some() => matrix_layout,
+                    _ => return err("matrix is missing a `MatrixStride` decoration"),
+                };
+                let matrix = self.matrix_layout(column_type, column_count, matrix_layout)?;
+                check_stride(
+                    "MatrixStride",
+                    matrix.stride,
+                    matrix.align,
+                    matrix.vector_size,
+                )
+            }
+            (TypeCtor::Array | TypeCtor::RuntimeArray, ctor_args) => {
+                let elem_type = match ctor_args[0] {
+                    TypeCtorArg::Type(ty) => ty,
+                    TypeCtorArg::Const(_) => unreachable!(),
+                };
+                if find_decoration(self.cx, ty_def.attrs, wk.ArrayStride).is_none() {
+                    return err("array is missing an `ArrayStride` decoration");
+                }
+                let (stride, align) = self.array_stride_and_align(
+                    ty_def,
+                    elem_type,
+                    matrix_layout.unwrap_or_default(),
+                )?;
+                let (elem_size, _) =
+                    self.sized_layout_of(elem_type, matrix_layout.unwrap_or_default())?;
+                check_stride("ArrayStride", stride, align, elem_size)?;
+                self.validate_explicit_layout_with_matrix_layout(elem_type, matrix_layout)
+            }
+            (TypeCtor::Struct { members }, ctor_args) => {
+                let (_, member_layouts) = self.struct_layout(ty_def)?;
+
+                let mut members_by_offset = SmallVec::<[_; 4]>::new();
+                for (member_idx, member) in members.iter().enumerate() {
+                    let member_layout = &member_layouts[member_idx];
+                    if member.offset.is_none() {
+                        return err(format!(
+                            "struct member #{member_idx} is missing an `Offset` decoration"
+                        ));
+                    }
+                    if !member_layout
+                        .offset
+                        .is_multiple_of(member_layout.layout.align)
+                    {
+                        return err(format!(
+                            "struct member #{member_idx} has `Offset` {}, \
+                             which isn't a multiple of its alignment ({})",
+                            member_layout.offset, member_layout.layout.align
+                        ));
+                    }
+                    members_by_offset.push((member_layout.offset, member_idx));
+
+                    let member_type = match ctor_args[member_idx] {
+                        TypeCtorArg::Type(ty) => ty,
+                        TypeCtorArg::Const(_) => unreachable!(),
+                    };
+                    self.validate_explicit_layout_with_matrix_layout(
+                        member_type,
+                        Some(MatrixLayout::from_member_attrs(self.cx, member.attrs)),
+                    )?;
+                }
+
+                members_by_offset.sort();
+                for pair in members_by_offset.windows(2) {
+                    let [(_, prev_idx), (offset, member_idx)] = [pair[0], pair[1]];
+                    match member_layouts[prev_idx].min_next_offset() {
+                        Some(min_offset) if offset >= min_offset => {}
+                        _ => {
+                            return err(format!(
+                                "struct member #{member_idx} (with `Offset` {offset}) \
+                                 overlaps struct member #{prev_idx}"
+                            ));
+                        }
+                    }
+                }
+                Ok(())
+            }
+            _ => {
+                self.layout_of(ty)?;
+                Ok(())
+            }
+        }
+    }
+
+    /// Get a type equivalent to `ty`, but with all the missing explicit layout
+    /// decorations (see [`LayoutCx::validate_explicit_layout`]) added to it,
+    /// and to all the types nested in it, following the [`LayoutRules`]
+    /// (any existing explicit layout decorations are kept unchanged).
+    pub fn with_explicit_layout(&self, ty: Type) -> Result<Type, LayoutError> {
+        self.with_explicit_layout_and_matrix_layout(ty, MatrixLayout::default())
+    }
+
+    fn with_explicit_layout_and_matrix_layout(
+        &self,
+        ty: Type,
+        matrix_layout: MatrixLayout,
+    ) -> Result<Type, LayoutError> {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        let ty_def = &self.cx[ty];
+        let new_ty_def = match (&ty_def.ctor, &ty_def.ctor_args[..]) {
+            (TypeCtor::Array | TypeCtor::RuntimeArray, ctor_args) => {
+                let elem_type = match ctor_args[0] {
+                    TypeCtorArg::Type(ty) => ty,
+                    TypeCtorArg::Const(_) => unreachable!(),
+                };
+                let elem_type =
+                    self.with_explicit_layout_and_matrix_layout(elem_type, matrix_layout)?;
+                let mut new_ty_def = TypeDef {
+                    attrs: ty_def.attrs,
+                    ctor: ty_def.ctor.clone(),
+                    ctor_args: [TypeCtorArg::Type(elem_type)]
+                        .into_iter()
+                        .chain(ctor_args[1..].iter().copied())
+                        .collect(),
+                };
+                if find_decoration(self.cx, new_ty_def.attrs, wk.ArrayStride).is_none() {
+                    let (stride, _) =
+                        self.array_stride_and_align(&new_ty_def, elem_type, matrix_layout)?;
+                    new_ty_def.attrs = with_decoration(
+                        self.cx,
+                        new_ty_def.attrs,
+                        wk.ArrayStride,
+                        &[spv::Imm::Short(wk.LiteralInteger, stride)],
+                    );
+                }
+                new_ty_def
+            }
+            (TypeCtor::Struct { members }, ctor_args) => {
+                let mut new_members = members.clone();
+                let mut new_ctor_args = SmallVec::with_capacity(ctor_args.len());
+                for (member, &member_type) in new_members.iter_mut().zip(ctor_args) {
+                    let member_type = match member_type {
+                        TypeCtorArg::Type(ty) => ty,
+                        TypeCtorArg::Const(_) => unreachable!(),
+                    };
+                    let matrix_layout = MatrixLayout::from_member_attrs(self.cx, member.attrs);
+                    let member_type =
+                        self.with_explicit_layout_and_matrix_layout(member_type, matrix_layout)?;
+                    new_ctor_args.push(TypeCtorArg::Type(member_type));
+
+                    // NOTE(eddyb) matrix decorations are on the struct member
+                    // containing the matrix (even if nested in arrays).
+                    let (column_type, column_count) = match self.matrix_in_arrays(member_type) {
+                        Some(matrix) => matrix,
+                        None => continue,
+                    };
+                    if matrix_layout.stride.is_none() {
+                        let stride = self
+                            .matrix_layout(column_type, column_count, matrix_layout)?
+                            .stride;
+                        member.attrs = with_decoration(
+                            self.cx,
+                            member.attrs,
+                            wk.MatrixStride,
+                            &[spv::Imm::Short(wk.LiteralInteger, stride)],
+                        );
+                    }
+                    if !matrix_layout.row_major
+                        && find_decoration(self.cx, member.attrs, wk.ColMajor).is_none()
+                    {
+                        member.attrs = with_decoration(self.cx, member.attrs, wk.ColMajor, &[]);
+                    }
+                }
+
+                let mut new_ty_def = TypeDef {
+                    attrs: ty_def.attrs,
+                    ctor: TypeCtor::Struct {
+                        members: new_members,
+                    },
+                    ctor_args: new_ctor_args,
+                };
+                let (_, member_layouts) = self.struct_layout(&new_ty_def)?;
+                if let TypeCtor::Struct { members } = &mut new_ty_def.ctor {
+                    for (member, member_layout) in members.iter_mut().zip(member_layouts) {
+                        member.offset = Some(member_layout.offset);
+                    }
+                }
+                new_ty_def
+            }
+            _ => return Ok(ty),
+        };
+        Ok(self.cx.intern(new_ty_def))
+    }
+
+    /// Get the column type and count of `ty`, if it's a matrix type, or an
+    /// array (or runtime array) of them (including arrays of arrays etc.).
+    fn matrix_in_arrays(&self, ty: Type) -> Option<(Type, u32)> {
+        let ty_def = &self.cx[ty];
+        match (&ty_def.ctor, ty_def.ctor_args.first()) {
+            (&TypeCtor::Matrix { column_count }, Some(&TypeCtorArg::Type(column_type))) => {
+                Some((column_type, column_count))
+            }
+            (TypeCtor::Array | TypeCtor::RuntimeArray, Some(&TypeCtorArg::Type(elem_type))) => {
+                self.matrix_in_arrays(elem_type)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Layout of a matrix, as an array of `count` vectors (see [`LayoutCx::matrix_layout`]).
+struct MatrixArrayLayout {
+    vector_size: u32,
+    stride: u32,
+    align: u32,
+    count: u32,
+}
+
+/// Layout of a struct member, and its offset (see [`LayoutCx::struct_layout`]).
+#[derive(Copy, Clone)]
+struct MemberLayout {
+    offset: u32,
+    layout: TypeLayout,
+
+    /// Whether the padding needed to round up the member's size to a multiple
+    /// of its alignment is reserved (i.e. no other member can be placed in it),
+    /// which is the case for structs, arrays and matrices, except with
+    /// [`LayoutRules::Scalar`].
+    pads_to_align: bool,
+}
+
+impl MemberLayout {
+    /// Get the smallest offset another member can be placed at, after this one,
+    /// or `None` if this member is dynamically-sized.
+    fn min_next_offset(&self) -> Option<u32> {
+        let end = self.offset + self.layout.size?;
+        Some(if self.pads_to_align {
+            round_up(end, self.layout.align)
+        } else {
+            end
+        })
+    }
+}
+
+/// Check that the explicit `stride` (from the `decoration_name` decoration) is
+/// a multiple of `align`, and large enough for `elem_size` bytes.
+fn check_stride(
+    decoration_name: &str,
+    stride: u32,
+    align: u32,
+    elem_size: u32,
+) -> Result<(), LayoutError> {
+    if !stride.is_multiple_of(align) {
+        return err(format!(
+            "`{decoration_name}` {stride} isn't a multiple of the alignment ({align})"
+        ));
+    }
+    if stride < elem_size {
+        return err(format!(
+            "`{decoration_name}` {stride} is smaller than the element size ({elem_size})"
+        ));
+    }
+    Ok(())
+}
+
+/// Round `x` up to the next multiple of `align`.
+fn round_up(x: u32, align: u32) -> u32 {
+    x.div_ceil(align) * align
+}
+
+/// Find the `OpDecorate` of `decoration` in `attrs`, returning its operands
+/// (other than the decoration itself), if present.
+fn find_decoration(cx: &Context, attrs: AttrSet, decoration: u32) -> Option<&[spv::Imm]> {
+    let wk = &spv::spec::Spec::get().well_known;
+
+    cx[attrs].attrs.iter().find_map(|attr| match attr {
+        Attr::SpvAnnotation(spv::Inst { opcode, imms })
+            if *opcode == wk.OpDecorate
+                && imms.first() == Some(&spv::Imm::Short(wk.Decoration, decoration)) =>
+        {
+            Some(&imms[1..])
+        }
+        _ => None,
+    })
+}
+
+/// Add an `OpDecorate` of `decoration` (with `operands`) to `attrs`.
+fn with_decoration(
+    cx: &Context,
+    attrs: AttrSet,
+    decoration: u32,
+    operands: &[spv::Imm],
+) -> AttrSet {
+    let wk = &spv::spec::Spec::get().well_known;
+
+    let mut attrs = cx[attrs].attrs.clone();
+    attrs.insert(Attr::SpvAnnotation(spv::Inst {
+        opcode: wk.OpDecorate,
+        imms: [spv::Imm::Short(wk.Decoration, decoration)]
+            .into_iter()
+            .chain(operands.iter().copied())
+            .collect(),
+    }));
+    cx.intern(AttrSetDef { attrs })
+}
+
+/// Get the value of a single `LiteralInteger` operand (e.g. from [`find_decoration`]).
+fn literal_u32(imms: &[spv::Imm]) -> Option<u32> {
+    match *imms {
+        [spv::Imm::Short(_, x)] => Some(x),
+        _ => None,
+    }
+}
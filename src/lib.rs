@@ -152,24 +152,69 @@
 
 // NOTE(eddyb) all the modules are declared here, but they're documented "inside"
 // (i.e. using inner doc comments).
+pub mod alias;
+pub mod call_graph;
 pub mod cfg;
 mod context;
+pub mod def_use;
+pub mod diagnostics;
+mod exports;
 pub mod func_at;
+pub mod layout;
+pub mod liveness;
+pub mod parse;
 pub mod print;
+pub mod qptr;
+#[cfg(feature = "serde")]
+pub mod serialize;
+pub mod testing;
 pub mod transform;
+mod transplant;
+pub mod value_range;
+pub mod verify;
 pub mod visit;
 pub mod passes {
     //! IR transformations (typically whole-[`Module`](crate::Module)).
     //
     // NOTE(eddyb) inline `mod` to avoid adding APIs here, it's just namespacing.
 
+    pub mod binding_remap;
+    pub mod bounds_check;
+    pub mod cfg_simplify;
+    pub mod const_fold;
+    pub mod cse;
+    pub mod dce;
+    pub mod debug_printf;
+    pub mod if_convert;
+    pub mod image_split;
+    pub mod inline;
+    pub mod int64_emulation;
+    pub mod interface_prune;
+    pub mod io_locations;
     pub mod legalize;
     pub mod link;
+    pub mod manager;
+    pub mod mem2reg;
+    pub mod peephole;
+    pub mod prune;
+    pub mod relaxed_precision;
+    pub mod scalarize;
+    pub mod specialize;
+    pub mod strength_reduce;
+    pub mod switch_to_if;
+    pub mod unreachable;
+    pub mod unroll;
+    pub mod vectorize;
+    pub mod workgroup_size;
+    pub mod zero_init;
+
+    pub use manager::PassManager;
 }
 pub mod spv;
 
 use smallvec::SmallVec;
 use std::collections::BTreeSet;
+use std::fmt;
 
 // HACK(eddyb) work around the lack of `FxIndex{Map,Set}` type aliases elsewhere.
 #[doc(hidden)]
@@ -300,6 +345,13 @@ pub struct AttrSetDef {
 pub enum Attr {
     SpvAnnotation(spv::Inst),
 
+    /// SPIR-V `OpExecutionModeId` (e.g. `LocalSizeId`), which, unlike other
+    /// annotations, has constants as operands (other than its implicit target).
+    SpvExecutionModeId {
+        inst: spv::Inst,
+        consts: SmallVec<[OrdAssertEq<Const>; 3]>,
+    },
+
     SpvDebugLine {
         file_path: OrdAssertEq<InternedStr>,
         line: u32,
@@ -308,8 +360,162 @@ pub enum Attr {
 
     /// Some SPIR-V instructions, like `OpFunction`, take a bitflags operand
     /// that is effectively an optimization over using `OpDecorate`.
-    // FIXME(eddyb) handle flags having further operands as parameters.
-    SpvBitflagsOperand(spv::Imm),
+    ///
+    /// The immediates include any further operands the flags take as parameters
+    /// (e.g. `DependencyLength` in `LoopControl`), in SPIR-V operand order.
+    SpvBitflagsOperand(SmallVec<[spv::Imm; 1]>),
+
+    /// The result ID of the SPIR-V instruction this definition was lowered from,
+    /// only kept around for debugging purposes (e.g. to correlate with the
+    /// output of other SPIR-V tools), and therefore never lifted back to SPIR-V
+    /// (other than as the result ID, with [`spv::lift::LiftOptions::reuse_original_ids`]).
+    ///
+    /// Not used for types or constants, as those are deduplicated by interning,
+    /// and ignored when comparing other definitions (see [`Attr::is_non_semantic`]).
+    SpvOriginalId(spv::Id),
+
+    /// Diagnostic recorded by permissive lowering (see [`spv::lower::LowerOptions`]),
+    /// for a definition that couldn't be fully lowered from SPIR-V (e.g. due to
+    /// unsupported instructions or operands), and is therefore likely incomplete.
+    ///
+    /// Never lifted back to SPIR-V (unlike the definition itself, which may be
+    /// lifted, but likely not into valid SPIR-V).
+    SpvUnsupported(String),
+
+    /// `NonSemantic.Shader.DebugInfo.100` `DebugScope` in effect for the SPIR-V
+    /// instruction this definition was lowered from (i.e. set by a `DebugScope`
+    /// earlier in the same block, without any `DebugNoScope` in between).
+    ///
+    /// Both `scope` and `inlined_at` are module-level instructions of the same
+    /// extended instruction set (see [`spv::shader_debuginfo`]).
+    SpvShaderDebugScope {
+        scope: OrdAssertEq<Const>,
+        inlined_at: Option<OrdAssertEq<Const>>,
+    },
+
+    /// `NonSemantic.Shader.DebugInfo.100` `DebugLine` in effect for the SPIR-V
+    /// instruction this definition was lowered from (like [`Attr::SpvDebugLine`],
+    /// but with `source` being a `DebugSource`, and with line/column ranges).
+    SpvShaderDebugLine {
+        source: OrdAssertEq<Const>,
+        line_start: u32,
+        line_end: u32,
+        col_start: u32,
+        col_end: u32,
+    },
+
+    /// `QPtr`-specific attributes (see [`qptr::QPtrAttr`]).
+    QPtr(qptr::QPtrAttr),
+
+    /// Diagnostic (error or warning) attached to the exact definition it concerns
+    /// (e.g. by a pass), to be collected later (see [`diagnostics::ModuleDiagnostics`]).
+    ///
+    /// Never lifted back to SPIR-V.
+    Diagnostic {
+        severity: DiagnosticSeverity,
+        message: String,
+
+        /// Additional notes, e.g. describing other definitions involved.
+        related: Vec<String>,
+    },
+}
+
+impl Attr {
+    /// Whether this attribute is debuginfo describing the source location of
+    /// a definition (i.e. [`Attr::SpvDebugLine`], [`Attr::SpvShaderDebugScope`]
+    /// and [`Attr::SpvShaderDebugLine`]), which remains accurate for any new
+    /// definitions a pass might derive from it (see also
+    /// [`AttrSet::with_debug_locations_from`]).
+    pub fn is_debug_location(&self) -> bool {
+        matches!(
+            self,
+            Attr::SpvDebugLine { .. }
+                | Attr::SpvShaderDebugScope { .. }
+                | Attr::SpvShaderDebugLine { .. }
+        )
+    }
+
+    /// Whether this attribute can't affect semantics, i.e. it's either debuginfo
+    /// (source locations, or SPIR-V `OpName`/`OpMemberName`), or [`Attr::SpvOriginalId`].
+    ///
+    /// Definitions differing only in such attributes are interchangeable, and
+    /// should be compared using [`AttrSet::semantic_subset`] (e.g. to deduplicate
+    /// definitions lowered from SPIR-V, which all have unique original IDs).
+    pub fn is_non_semantic(&self) -> bool {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        match self {
+            Attr::SpvOriginalId(_) => true,
+            Attr::SpvAnnotation(inst) => [wk.OpName, wk.OpMemberName].contains(&inst.opcode),
+            _ => self.is_debug_location(),
+        }
+    }
+}
+
+impl AttrSet {
+    /// Return the subset of these attributes which can affect semantics (i.e.
+    /// excluding those for which [`Attr::is_non_semantic`] returns `true`).
+    pub fn semantic_subset(self, cx: &Context) -> AttrSet {
+        let attrs = &cx[self].attrs;
+        if !attrs.iter().any(|attr| attr.is_non_semantic()) {
+            return self;
+        }
+        cx.intern(AttrSetDef {
+            attrs: attrs
+                .iter()
+                .filter(|attr| !attr.is_non_semantic())
+                .cloned()
+                .collect(),
+        })
+    }
+
+    /// Return these attributes with the source locations of `source` added
+    /// (see [`Attr::is_debug_location`]), for a new definition derived from
+    /// the one with `source` as its attributes (no other attributes are copied,
+    /// as they may not apply to the new definition, e.g. [`Attr::SpvOriginalId`]).
+    pub fn with_debug_locations_from(self, cx: &Context, source: AttrSet) -> AttrSet {
+        let mut debug_locations = cx[source]
+            .attrs
+            .iter()
+            .filter(|attr| attr.is_debug_location())
+            .peekable();
+        if debug_locations.peek().is_none() {
+            return self;
+        }
+        let mut attrs = cx[self].attrs.clone();
+        attrs.extend(debug_locations.cloned());
+        cx.intern(AttrSetDef { attrs })
+    }
+}
+
+/// Severity of an [`Attr::Diagnostic`].
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub enum DiagnosticSeverity {
+    Error,
+    Warning,
+}
+
+impl DiagnosticSeverity {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "error" => Some(Self::Error),
+            "warning" => Some(Self::Warning),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Error => "error",
+            Self::Warning => "warning",
+        }
+    }
+}
+
+impl fmt::Display for DiagnosticSeverity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
 }
 
 /// Wrapper to limit `Ord` for interned index types (e.g. [`InternedStr`])
@@ -357,17 +563,150 @@ pub struct TypeDef {
 pub enum TypeCtor {
     SpvInst(spv::Inst),
 
+    /// Matrix type (SPIR-V `OpTypeMatrix`), with `column_count` columns, all
+    /// of the (vector) type given as its only [`TypeCtorArg`].
+    Matrix {
+        column_count: u32,
+    },
+
+    /// Fixed-length array type (SPIR-V `OpTypeArray`), with the element type
+    /// and the length (a [`Const`]) as its [`TypeCtorArg`]s (in that order).
+    Array,
+
+    /// Array type with a length only known at runtime (SPIR-V `OpTypeRuntimeArray`),
+    /// with the element type as its only [`TypeCtorArg`].
+    RuntimeArray,
+
+    /// Aggregate type with a fixed sequence of (heterogeneous) members, i.e.
+    /// SPIR-V `OpTypeStruct`, where the type of the `i`th member is taken
+    /// from the `i`th [`TypeCtorArg`] (which is always a [`TypeCtorArg::Type`]).
+    Struct {
+        members: SmallVec<[StructMember; 4]>,
+    },
+
+    /// Pointer type which can (indirectly) point to values of its own type,
+    /// i.e. a SPIR-V `OpTypePointer` declared ahead of time (by an
+    /// `OpTypeForwardPointer`), with the pointee type as its only [`TypeCtorArg`].
+    ///
+    /// To keep interned types acyclic, uses of this pointer type nested within
+    /// its own pointee type are replaced with [`TypeCtor::RecursivePtrSelf`]
+    /// (i.e. the pointee type is "folded", and must be "unrolled" by replacing
+    /// those [`TypeCtor::RecursivePtrSelf`]s back with this pointer type).
+    //
+    // FIXME(eddyb) this is a limited form of fixpoint (aka "μ" aka "mu") types,
+    // which may need to be generalized beyond pointers in the future.
+    RecursivePtr {
+        /// SPIR-V `StorageClass` of the pointer type.
+        storage_class: u32,
+    },
+
+    /// Recursive use of an enclosing [`TypeCtor::RecursivePtr`] type (only valid
+    /// within that type's pointee type), specifically the `depth`-th closest one
+    /// (i.e. `depth` is the number of other [`TypeCtor::RecursivePtr`]s found
+    /// in between, also known as a "de Bruijn index").
+    RecursivePtrSelf {
+        depth: u32,
+    },
+
+    /// Untyped pointer (i.e. "quasi-pointer", see [`qptr`]), which SPIR-V
+    /// pointer types are replaced with by [`qptr::lower`] (and restored from,
+    /// by [`qptr::lift`]), with its uses carrying the types instead.
+    QPtr,
+
+    /// Image type (SPIR-V `OpTypeImage`), with the sampled type (i.e. the
+    /// type of the components read/written through the image) as its only
+    /// [`TypeCtorArg`].
+    Image(ImageType),
+
+    /// Opaque sampler type (SPIR-V `OpTypeSampler`).
+    Sampler,
+
+    /// Combined image and sampler type (SPIR-V `OpTypeSampledImage`), with
+    /// the image type (always a [`TypeCtor::Image`]) as its only [`TypeCtorArg`].
+    SampledImage,
+
     /// The type of a [`ConstCtor::SpvStringLiteralForExtInst`] constant, i.e.
     /// a SPIR-V `OpString` with no actual type in SPIR-V.
     SpvStringLiteralForExtInst,
 }
 
+/// Per-member information (other than its type) for a [`TypeCtor::Struct`].
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+pub struct StructMember {
+    /// Member decorations (and names), i.e. SPIR-V `OpMemberDecorate` (and
+    /// `OpMemberName`), but stored as if they were `OpDecorate` (and `OpName`)
+    /// annotations, as the member index is implied by the member position.
+    pub attrs: AttrSet,
+
+    /// Byte offset of this member, from the start of the struct, if it has an
+    /// explicit layout (i.e. SPIR-V `OpMemberDecorate ... Offset`).
+    pub offset: Option<u32>,
+}
+
+/// The properties of an image type (other than its sampled type), i.e. the
+/// immediate operands of SPIR-V `OpTypeImage`, for a [`TypeCtor::Image`].
+//
+// FIXME(eddyb) consider replacing the raw SPIR-V enumerands with proper enums.
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+pub struct ImageType {
+    /// SPIR-V `Dim` enumerand (e.g. `2D`, `Cube`, `Buffer`, `SubpassData`).
+    pub dim: u32,
+
+    /// Whether this is a depth image, or `None` if not known ahead of use
+    /// (i.e. SPIR-V `Depth` operand values `0`/`1`/`2`).
+    pub depth: Option<bool>,
+
+    pub arrayed: bool,
+    pub multisampled: bool,
+
+    /// Whether the image is used with a sampler (`Some(true)`) or only for
+    /// read/write operations (`Some(false)`), or `None` if only known at
+    /// runtime (i.e. SPIR-V `Sampled` operand values `1`/`2`/`0`).
+    pub sampled: Option<bool>,
+
+    /// SPIR-V `ImageFormat` enumerand (with `0` being `Unknown`).
+    pub format: u32,
+
+    /// SPIR-V `AccessQualifier` enumerand, if any (only allowed for kernels).
+    pub access_qualifier: Option<u32>,
+}
+
 #[derive(Copy, Clone, PartialEq, Eq, Hash)]
 pub enum TypeCtorArg {
     Type(Type),
     Const(Const),
 }
 
+impl Type {
+    /// Get the pointee type of this type, if it's a SPIR-V pointer type, i.e.
+    /// either `OpTypePointer`, or [`TypeCtor::RecursivePtr`] (in which case the
+    /// pointee type is "unrolled", i.e. with its [`TypeCtor::RecursivePtrSelf`]
+    /// uses replaced by this pointer type itself, as SPIR-V would refer to it).
+    pub fn pointee_type(self, cx: &Context) -> Option<Type> {
+        use transform::Transformer;
+
+        let wk = &spv::spec::Spec::get().well_known;
+
+        let ty_def = &cx[self];
+        let mut pointee = match (&ty_def.ctor, &ty_def.ctor_args[..]) {
+            (TypeCtor::SpvInst(inst), &[TypeCtorArg::Type(pointee)])
+                if inst.opcode == wk.OpTypePointer =>
+            {
+                return Some(pointee);
+            }
+            (TypeCtor::RecursivePtr { .. }, &[TypeCtorArg::Type(pointee)]) => pointee,
+            _ => return None,
+        };
+        transform::SubstTypes::new(cx, |ty, depth| match cx[ty].ctor {
+            TypeCtor::RecursivePtrSelf { depth: self_depth } if self_depth == depth => Some(self),
+            _ => None,
+        })
+        .transform_type_use(pointee)
+        .apply_to(&mut pointee);
+        Some(pointee)
+    }
+}
+
 /// Interned handle for a [`ConstDef`](crate::ConstDef) (a constant value).
 pub use context::Const;
 
@@ -387,12 +726,48 @@ pub struct ConstDef {
 pub enum ConstCtor {
     PtrToGlobalVar(GlobalVar),
 
+    /// Undefined value (SPIR-V `OpUndef`), i.e. an arbitrary value of the type,
+    /// which may differ between uses (so it can't be relied upon in any way).
+    Undef,
+
     SpvInst(spv::Inst),
 
+    /// Specialization constant (SPIR-V `OpSpecConstant{True,False,}` decorated
+    /// with `SpecId`), i.e. a scalar constant that can be overriden (via its
+    /// `spec_id`) when creating a pipeline, but otherwise has the `default` value.
+    SpecConst {
+        spec_id: u32,
+
+        /// The non-specialized equivalent of this constant (i.e. one of
+        /// `OpConstantTrue`, `OpConstantFalse`, or `OpConstant` with the same
+        /// literal operand), which is used when `spec_id` isn't overriden.
+        default: spv::Inst,
+    },
+
+    /// Specialization constant operation (SPIR-V `OpSpecConstantOp`), i.e. the
+    /// result of applying an operation (the `opcode` of the [`spv::Inst`], with
+    /// its immediate operands) to the `ctor_args` constants, which can only be
+    /// evaluated once the values of all the [`ConstCtor::SpecConst`]s involved
+    /// are known (see also [`spv::fold`]).
+    SpecConstOp(spv::Inst),
+
+    /// Module-level SPIR-V `OpExtInst` (only allowed for non-semantic extended
+    /// instruction sets, e.g. [`spv::shader_debuginfo`]), with `ctor_args` as
+    /// its operands (see also [`DataInstKind::SpvExtInst`]).
+    SpvExtInst {
+        ext_set: InternedStr,
+        inst: u32,
+    },
+
     /// SPIR-V `OpString`, but only when used as an operand for an `OpExtInst`,
     /// which can't have literals itself - for non-string literals `OpConstant*`
     /// are readily usable, but only `OpString` is supported for string literals.
     SpvStringLiteralForExtInst(InternedStr),
+
+    /// SPIR-V type (i.e. the `ty` of this constant), but only when used as an
+    /// operand for one of the few instructions which take the ID of a type
+    /// itself, not of a value (e.g. `OpCooperativeMatrixLengthKHR`).
+    SpvTypeOperand,
 }
 
 /// Declarations ([`GlobalVarDecl`], [`FuncDecl`]) can contain a full definition,
@@ -433,9 +808,17 @@ pub struct GlobalVarDecl {
     pub def: DeclDef<GlobalVarDefBody>,
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq, Eq)]
 pub enum AddrSpace {
     SpvStorageClass(u32),
+
+    /// SPIR-V `PhysicalStorageBuffer` storage class, i.e. memory accessed through
+    /// "buffer device addresses" (with `PhysicalStorageBuffer64` addressing),
+    /// where pointers are "physical" (plain 64-bit integer addresses).
+    ///
+    /// This is never used with `SpvStorageClass`, see also
+    /// [`AddrSpace::from_spv_storage_class`].
+    PhysicalStorageBuffer,
 }
 
 /// The body of a [`GlobalVar`] definition.
@@ -618,6 +1001,8 @@ pub use context::ControlNode;
 /// See [`ControlRegion`] docs for more on control-flow in SPIR-T.
 #[derive(Clone)]
 pub struct ControlNodeDef {
+    pub attrs: AttrSet,
+
     pub kind: ControlNodeKind,
 
     /// Outputs from this [`ControlNode`]:
@@ -679,6 +1064,19 @@ pub enum ControlNodeKind {
         // have any ambiguity as to whether it can see `body`-computed values)
         repeat_condition: Value,
     },
+
+    /// Leave the current invocation, similar to returning from every function
+    /// call in the stack (up to and including the entry-point), but potentially
+    /// indicating a fatal error as well (see also [`cfg::ControlInstKind::ExitInvocation`]).
+    ///
+    /// This never completes, so it has no `outputs`, and no other [`ControlNode`]
+    /// can follow it in the same [`ControlRegion`], which therefore cannot exit
+    /// (e.g. SPIR-V `OpTerminateRayKHR` and `OpIgnoreIntersectionKHR`, in ray
+    /// tracing any-hit shaders, implicitly return control to ray traversal).
+    ExitInvocation {
+        kind: cfg::ExitInvocationKind,
+        inputs: SmallVec<[Value; 2]>,
+    },
 }
 
 #[derive(Clone)]
@@ -706,17 +1104,304 @@ pub struct DataInstDef {
     pub inputs: SmallVec<[Value; 2]>,
 }
 
-#[derive(Clone, PartialEq, Eq)]
+#[derive(Clone, PartialEq, Eq, Hash)]
 pub enum DataInstKind {
     // FIXME(eddyb) try to split this into recursive and non-recursive calls,
     // to avoid needing special handling for recursion where it's impossible.
     FuncCall(Func),
 
     SpvInst(spv::Inst),
-    SpvExtInst { ext_set: InternedStr, inst: u32 },
+    SpvExtInst {
+        ext_set: InternedStr,
+        inst: u32,
+    },
+
+    /// `OpExtInst` using the `GLSL.std.450` extended instruction set (which is
+    /// decoded during lowering, see [`spv::glsl_std_450`]).
+    SpvGlslStd450(spv::glsl_std_450::Op),
+
+    /// Atomic memory operation (i.e. SPIR-V `OpAtomic*`), with its memory scope
+    /// and semantics decoded from the constants SPIR-V uses for them.
+    ///
+    /// The pointer is always the first input, followed by any other operands
+    /// (e.g. the value to store, or to combine with the loaded value).
+    Atomic {
+        op: AtomicOp,
+
+        /// SPIR-V `Scope` enumerand (i.e. the set of invocations this
+        /// operation is atomic with respect to).
+        scope: u32,
+
+        /// SPIR-V `MemorySemantics` bitflags (for compare-and-swap operations,
+        /// only used when the comparison succeeds, see [`AtomicOp::CompareExchange`]).
+        semantics: u32,
+    },
+
+    /// Execution and/or memory barrier (i.e. SPIR-V `OpControlBarrier` or
+    /// `OpMemoryBarrier`), with its scopes and semantics decoded from the
+    /// constants SPIR-V uses for them (and therefore without any inputs).
+    Barrier {
+        /// SPIR-V `Scope` enumerand for the invocations that must all reach
+        /// this barrier before any of them can continue past it (`None` for
+        /// `OpMemoryBarrier`, which only orders memory accesses).
+        execution_scope: Option<u32>,
+
+        /// SPIR-V `Scope` enumerand for the invocations memory accesses are
+        /// ordered with respect to.
+        memory_scope: u32,
+
+        /// SPIR-V `MemorySemantics` bitflags.
+        semantics: u32,
+    },
+
+    /// Group operation (i.e. SPIR-V `OpGroupNonUniform*`), combining values
+    /// across the invocations in `scope` (which are currently active), with its
+    /// scope decoded from the constant SPIR-V uses for it.
+    ///
+    /// All group operations are "convergent" (see [`DataInstKind::is_convergent`]).
+    Group {
+        op: GroupOp,
+
+        /// SPIR-V `Scope` enumerand (i.e. the group of invocations involved,
+        /// almost always `Subgroup`).
+        scope: u32,
+
+        /// SPIR-V `GroupOperation` enumerand (e.g. reduction vs scan), present
+        /// if and only if [`GroupOp::takes_group_operation`] returns `true`.
+        group_operation: Option<u32>,
+    },
+
+    /// Operation on untyped pointers (see [`qptr::QPtrOp`]).
+    QPtr(qptr::QPtrOp),
 }
 
-#[derive(Copy, Clone, PartialEq, Eq)]
+impl DataInstKind {
+    /// Whether this instruction acts as a fence, i.e. it has to be kept even
+    /// if its outputs are unused, and memory accesses (or, for execution
+    /// barriers, any instructions with side-effects) can't be moved across it.
+    ///
+    /// This is the case for all barriers, and for atomic operations which
+    /// aren't `Relaxed` (i.e. with any of the ordering `MemorySemantics` bits).
+    pub fn is_fence(&self) -> bool {
+        // NOTE(eddyb) these are the `Acquire`, `Release`, `AcquireRelease`
+        // and `SequentiallyConsistent` bits of SPIR-V `MemorySemantics`.
+        const ORDERING_SEMANTICS_MASK: u32 = 0x2 | 0x4 | 0x8 | 0x10;
+
+        match *self {
+            DataInstKind::Barrier { .. } => true,
+            DataInstKind::Atomic { semantics, op, .. } => {
+                let unequal_semantics = match op {
+                    AtomicOp::CompareExchange {
+                        unequal_semantics, ..
+                    } => unequal_semantics,
+                    _ => 0,
+                };
+                (semantics | unequal_semantics) & ORDERING_SEMANTICS_MASK != 0
+            }
+            DataInstKind::FuncCall(_)
+            | DataInstKind::SpvInst(_)
+            | DataInstKind::SpvExtInst { .. }
+            | DataInstKind::SpvGlslStd450(_)
+            | DataInstKind::Group { .. }
+            | DataInstKind::QPtr(_) => false,
+        }
+    }
+}
+
+macro_rules! def_group_ops {
+    ($($name:ident $(<$group_operation:ident>)?),+ $(,)?) => {
+        /// Operation performed by a [`DataInstKind::Group`] (one per SPIR-V
+        /// `OpGroupNonUniform*`, e.g. `GroupOp::IAdd` for `OpGroupNonUniformIAdd`).
+        #[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+        pub enum GroupOp {
+            $($name),+
+        }
+
+        impl GroupOp {
+            pub const ALL: &'static [GroupOp] = &[$(GroupOp::$name),+];
+
+            pub fn from_name(name: &str) -> Option<Self> {
+                match name {
+                    $(stringify!($name) => Some(GroupOp::$name),)+
+                    _ => None,
+                }
+            }
+
+            pub fn name(self) -> &'static str {
+                match self {
+                    $(GroupOp::$name => stringify!($name)),+
+                }
+            }
+
+            /// Whether this operation takes a SPIR-V `GroupOperation` operand
+            /// (i.e. it's an arithmetic reduction/scan, or `BallotBitCount`).
+            pub fn takes_group_operation(self) -> bool {
+                match self {
+                    $(GroupOp::$name => def_group_ops!(@takes $($group_operation)?)),+
+                }
+            }
+        }
+    };
+    (@takes GroupOperation) => { true };
+    (@takes) => { false };
+}
+
+def_group_ops! {
+    Elect,
+    All,
+    Any,
+    AllEqual,
+    Broadcast,
+    BroadcastFirst,
+    Ballot,
+    InverseBallot,
+    BallotBitExtract,
+    BallotBitCount<GroupOperation>,
+    BallotFindLSB,
+    BallotFindMSB,
+    Shuffle,
+    ShuffleXor,
+    ShuffleUp,
+    ShuffleDown,
+    IAdd<GroupOperation>,
+    FAdd<GroupOperation>,
+    IMul<GroupOperation>,
+    FMul<GroupOperation>,
+    SMin<GroupOperation>,
+    UMin<GroupOperation>,
+    FMin<GroupOperation>,
+    SMax<GroupOperation>,
+    UMax<GroupOperation>,
+    FMax<GroupOperation>,
+    BitwiseAnd<GroupOperation>,
+    BitwiseOr<GroupOperation>,
+    BitwiseXor<GroupOperation>,
+    LogicalAnd<GroupOperation>,
+    LogicalOr<GroupOperation>,
+    LogicalXor<GroupOperation>,
+    QuadBroadcast,
+    QuadSwap,
+}
+
+/// Operation performed by a [`DataInstKind::Atomic`] (one per SPIR-V `OpAtomic*`).
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+pub enum AtomicOp {
+    Load,
+    Store,
+    Exchange,
+
+    /// Compare-and-swap, with its own SPIR-V `MemorySemantics` bitflags for
+    /// when the comparison fails (and no value is stored).
+    CompareExchange {
+        weak: bool,
+        unequal_semantics: u32,
+    },
+
+    IIncrement,
+    IDecrement,
+    IAdd,
+    ISub,
+    SMin,
+    UMin,
+    SMax,
+    UMax,
+    And,
+    Or,
+    Xor,
+
+    FlagTestAndSet,
+    FlagClear,
+
+    FAdd,
+    FMin,
+    FMax,
+}
+
+impl AtomicOp {
+    /// All operations without any extra data (i.e. all but `CompareExchange`).
+    pub const ALL_SIMPLE: &'static [AtomicOp] = &[
+        AtomicOp::Load,
+        AtomicOp::Store,
+        AtomicOp::Exchange,
+        AtomicOp::IIncrement,
+        AtomicOp::IDecrement,
+        AtomicOp::IAdd,
+        AtomicOp::ISub,
+        AtomicOp::SMin,
+        AtomicOp::UMin,
+        AtomicOp::SMax,
+        AtomicOp::UMax,
+        AtomicOp::And,
+        AtomicOp::Or,
+        AtomicOp::Xor,
+        AtomicOp::FlagTestAndSet,
+        AtomicOp::FlagClear,
+        AtomicOp::FAdd,
+        AtomicOp::FMin,
+        AtomicOp::FMax,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            AtomicOp::Load => "Load",
+            AtomicOp::Store => "Store",
+            AtomicOp::Exchange => "Exchange",
+            AtomicOp::CompareExchange { weak: false, .. } => "CompareExchange",
+            AtomicOp::CompareExchange { weak: true, .. } => "CompareExchangeWeak",
+            AtomicOp::IIncrement => "IIncrement",
+            AtomicOp::IDecrement => "IDecrement",
+            AtomicOp::IAdd => "IAdd",
+            AtomicOp::ISub => "ISub",
+            AtomicOp::SMin => "SMin",
+            AtomicOp::UMin => "UMin",
+            AtomicOp::SMax => "SMax",
+            AtomicOp::UMax => "UMax",
+            AtomicOp::And => "And",
+            AtomicOp::Or => "Or",
+            AtomicOp::Xor => "Xor",
+            AtomicOp::FlagTestAndSet => "FlagTestAndSet",
+            AtomicOp::FlagClear => "FlagClear",
+            AtomicOp::FAdd => "FAdd",
+            AtomicOp::FMin => "FMin",
+            AtomicOp::FMax => "FMax",
+        }
+    }
+
+    /// Inverse of [`AtomicOp::name`], with `unequal_semantics` being required
+    /// for (and only for) `CompareExchange`/`CompareExchangeWeak`.
+    pub fn from_name(name: &str, unequal_semantics: Option<u32>) -> Option<Self> {
+        match (name, unequal_semantics) {
+            ("CompareExchange", Some(unequal_semantics)) => Some(AtomicOp::CompareExchange {
+                weak: false,
+                unequal_semantics,
+            }),
+            ("CompareExchangeWeak", Some(unequal_semantics)) => Some(AtomicOp::CompareExchange {
+                weak: true,
+                unequal_semantics,
+            }),
+            (_, Some(_)) => None,
+            (_, None) => Self::ALL_SIMPLE
+                .iter()
+                .copied()
+                .find(|op| op.name() == name),
+        }
+    }
+
+    /// Whether this operation reads the memory it accesses (i.e. everything
+    /// other than atomic stores, including read-modify-write operations).
+    pub fn reads_memory(self) -> bool {
+        !matches!(self, AtomicOp::Store | AtomicOp::FlagClear)
+    }
+
+    /// Whether this operation can write to the memory it accesses (i.e.
+    /// everything other than atomic loads, with compare-and-swap operations
+    /// only writing when the comparison succeeds).
+    pub fn may_write_memory(self) -> bool {
+        !matches!(self, AtomicOp::Load)
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
 pub enum Value {
     Const(Const),
 
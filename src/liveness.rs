@@ -0,0 +1,210 @@
+//! Liveness analysis, i.e. which [`Value`]s are "live" (defined earlier, and
+//! still used later), on entry into, and on exit from, each [`ControlRegion`].
+
+use crate::{
+    ControlNodeKind, ControlRegion, EntityOrientedDenseMap, FuncDefBody, FxIndexSet, Value,
+};
+use smallvec::SmallVec;
+
+/// Liveness information for all the [`ControlRegion`]s in a function body, i.e.
+/// the sets of [`Value`]s "live-in" (i.e. used in the region, or after it, but
+/// defined before it) and "live-out" (i.e. defined before the region exits,
+/// and used after it), for each region.
+///
+/// Constants (i.e. [`Value::Const`]) are never considered live, and neither are
+/// a region's own inputs live-in (as they're defined on entry into the region).
+///
+/// For `Loop` bodies, everything live-in is also live-out (as another iteration
+/// may follow), including the values used to compute the `outputs` (which are
+/// the next iteration's inputs, i.e. loop-carried values) and `repeat_condition`
+/// (this is also the case for unstructured CFG loops, as the values passed to
+/// a loop header's inputs are uses in the back edge's [`ControlInst`](crate::cfg::ControlInst)).
+//
+// FIXME(eddyb) regions unreachable in an unstructured CFG are not included.
+#[derive(Clone, Default)]
+pub struct Liveness {
+    live_in: EntityOrientedDenseMap<ControlRegion, FxIndexSet<Value>>,
+    live_out: EntityOrientedDenseMap<ControlRegion, FxIndexSet<Value>>,
+}
+
+impl Liveness {
+    pub fn compute(func_def_body: &FuncDefBody) -> Self {
+        let mut liveness = Self::default();
+        match &func_def_body.unstructured_cfg {
+            None => {
+                liveness.compute_region(func_def_body, func_def_body.body, FxIndexSet::default());
+            }
+            Some(cfg) => {
+                // NOTE(eddyb) this iterates (in post-order, to reduce the number
+                // of iterations needed) until no live-in set changes anymore.
+                let post_order: Vec<_> = cfg.rev_post_order(func_def_body).rev().collect();
+                let mut changed = true;
+                while changed {
+                    changed = false;
+                    for &region in &post_order {
+                        let control_inst = &cfg.control_inst_on_exit_from[region];
+
+                        let mut live_out = FxIndexSet::default();
+                        for &target in &control_inst.targets {
+                            if let Some(target_live_in) = liveness.live_in.get(target) {
+                                live_out.extend(target_live_in.iter().copied());
+                            }
+                        }
+                        liveness.live_out.insert(region, live_out.clone());
+
+                        // NOTE(eddyb) the `ControlInst` uses are at the very
+                        // end of the region, after everything else in it.
+                        let mut live = live_out;
+                        add_uses(&mut live, &control_inst.inputs);
+                        for inputs in control_inst.target_inputs.values() {
+                            add_uses(&mut live, inputs);
+                        }
+
+                        let old_live_in_len =
+                            liveness.live_in.get(region).map(|live_in| live_in.len());
+                        liveness.compute_region(func_def_body, region, live);
+                        if Some(liveness.live_in[region].len()) != old_live_in_len {
+                            changed = true;
+                        }
+                    }
+                }
+            }
+        }
+        liveness
+    }
+
+    /// Compute (and record) the liveness for `region` (and all the regions
+    /// nested in it), given the values `live` right before it exits (i.e.
+    /// after any uses at the very end of it, e.g. its `outputs`).
+    ///
+    /// Only the `live_out` sets of nested regions are recorded (as the one for
+    /// `region` itself may differ from `live`, e.g. due to `ControlInst` uses).
+    fn compute_region(
+        &mut self,
+        func_def_body: &FuncDefBody,
+        region: ControlRegion,
+        mut live: FxIndexSet<Value>,
+    ) {
+        let region_def = &func_def_body.control_regions[region];
+        add_uses(&mut live, &region_def.outputs);
+
+        // FIXME(eddyb) `EntityList` should support iterating in reverse.
+        let children: SmallVec<[_; 8]> = func_def_body
+            .at(region_def.children)
+            .into_iter()
+            .map(|func_at_control_node| func_at_control_node.position)
+            .collect();
+        for control_node in children.into_iter().rev() {
+            let control_node_def = &func_def_body.control_nodes[control_node];
+
+            for output_idx in 0..control_node_def.outputs.len() {
+                live.shift_remove(&Value::ControlNodeOutput {
+                    control_node,
+                    output_idx: output_idx.try_into().unwrap(),
+                });
+            }
+
+            match control_node_def.kind {
+                ControlNodeKind::Block { insts } => {
+                    let insts: SmallVec<[_; 8]> = func_def_body
+                        .at(insts)
+                        .into_iter()
+                        .map(|func_at_inst| func_at_inst.position)
+                        .collect();
+                    for inst in insts.into_iter().rev() {
+                        live.shift_remove(&Value::DataInstOutput(inst));
+                        add_uses(&mut live, &func_def_body.data_insts[inst].inputs);
+                    }
+                }
+                ControlNodeKind::Select {
+                    kind: _,
+                    scrutinee,
+                    ref cases,
+                } => {
+                    let live_after = live;
+                    live = FxIndexSet::default();
+                    for &case in cases {
+                        self.live_out.insert(case, live_after.clone());
+                        self.compute_region(func_def_body, case, live_after.clone());
+                        live.extend(self.live_in[case].iter().copied());
+                    }
+                    add_uses(&mut live, [&scrutinee]);
+                }
+                ControlNodeKind::Loop {
+                    ref initial_inputs,
+                    body,
+                    repeat_condition,
+                } => {
+                    // NOTE(eddyb) everything live-in is also live-out (for the
+                    // next iteration), so this iterates until that's stable.
+                    let mut body_live_out = live;
+                    loop {
+                        let mut body_live = body_live_out.clone();
+                        add_uses(&mut body_live, [&repeat_condition]);
+                        self.compute_region(func_def_body, body, body_live);
+
+                        let old_len = body_live_out.len();
+                        body_live_out.extend(self.live_in[body].iter().copied());
+                        if body_live_out.len() == old_len {
+                            break;
+                        }
+                    }
+                    self.live_out.insert(body, body_live_out);
+
+                    live = self.live_in[body].clone();
+                    add_uses(&mut live, initial_inputs);
+                }
+                ControlNodeKind::ExitInvocation {
+                    kind: _,
+                    ref inputs,
+                } => {
+                    // NOTE(eddyb) nothing after this can execute, so nothing
+                    // that was live after it is relevant anymore.
+                    live.clear();
+                    add_uses(&mut live, inputs);
+                }
+            }
+        }
+
+        for input_idx in 0..region_def.inputs.len() {
+            live.shift_remove(&Value::ControlRegionInput {
+                region,
+                input_idx: input_idx.try_into().unwrap(),
+            });
+        }
+        self.live_in.insert(region, live);
+    }
+
+    /// Iterate over all the values live on entry into `region`.
+    pub fn live_in(&self, region: ControlRegion) -> impl Iterator<Item = Value> + '_ {
+        self.live_in.get(region).into_iter().flatten().copied()
+    }
+
+    /// Iterate over all the values live on exit from `region`.
+    pub fn live_out(&self, region: ControlRegion) -> impl Iterator<Item = Value> + '_ {
+        self.live_out.get(region).into_iter().flatten().copied()
+    }
+
+    /// Returns `true` if `v` is live on entry into `region`.
+    pub fn is_live_in(&self, region: ControlRegion, v: Value) -> bool {
+        self.live_in
+            .get(region)
+            .is_some_and(|live_in| live_in.contains(&v))
+    }
+
+    /// Returns `true` if `v` is live on exit from `region`.
+    pub fn is_live_out(&self, region: ControlRegion, v: Value) -> bool {
+        self.live_out
+            .get(region)
+            .is_some_and(|live_out| live_out.contains(&v))
+    }
+}
+
+/// Add all the non-constant values in `uses` to `live`.
+fn add_uses<'a>(live: &mut FxIndexSet<Value>, uses: impl IntoIterator<Item = &'a Value>) {
+    live.extend(
+        uses.into_iter()
+            .copied()
+            .filter(|v| !matches!(v, Value::Const(_))),
+    );
+}
@@ -0,0 +1,3200 @@
+//! Parsing SPIR-T from its textual form (i.e. the output of [`print`](crate::print)).
+//!
+//! The accepted syntax is the plain-text output of [`Plan::pretty_print`], for
+//! a whole [`Module`] (i.e. a [`Plan`] created by [`Plan::for_module`]), with
+//! only a few details that can't be recovered from the text:
+//! * comments are ignored, other than the ones printed for [`Attr`]s
+//!   (i.e. `// at file:line:col` and `// originally %123`)
+//! * the `interface_global_vars` of [`ExportKey::SpvEntryPoint`] aren't printed,
+//!   and are left empty (as `spv::lift` should recompute them anyway)
+//! * constant aggregates can't have any elements elided (see
+//!   [`PrintOptions::max_const_aggregate_elements`](crate::print::PrintOptions::max_const_aggregate_elements))
+//! * only one version can be printed (i.e. [`Plan::for_versions`] isn't supported)
+//!
+//! The main use of parsing is writing SPIR-T by hand (e.g. for testing passes),
+//! which is why anything that can be printed (e.g. definitions in any order,
+//! and inline vs named interned types/consts) is accepted as input.
+//!
+//! [`Plan`]: crate::print::Plan
+//! [`Plan::pretty_print`]: crate::print::Plan::pretty_print
+//! [`Plan::for_module`]: crate::print::Plan::for_module
+//! [`Plan::for_versions`]: crate::print::Plan::for_versions
+
+use crate::qptr::{QPtrAttr, QPtrOp};
+use crate::spv::{self, spec};
+use crate::{
+    cfg, AddrSpace, AtomicOp, Attr, AttrSet, AttrSetDef, Const, ConstCtor, ConstDef, Context,
+    ControlNodeDef, ControlNodeKind, ControlNodeOutputDecl, ControlRegion, ControlRegionDef,
+    ControlRegionInputDecl, DataInstDef, DataInstKind, DeclDef, DiagnosticSeverity, EntityDefs,
+    EntityList, ExportKey, Exportee, Func, FuncDecl, FuncDefBody, FuncParam, FxIndexMap, GlobalVar,
+    GlobalVarDecl, GlobalVarDefBody, GroupOp, ImageType, Import, InternedStr, Module,
+    ModuleDebugInfo, ModuleDialect, OrdAssertEq, SelectionKind, StructMember, Type, TypeCtor,
+    TypeCtorArg, TypeDef, Value,
+};
+use rustc_hash::{FxHashMap, FxHashSet};
+use smallvec::SmallVec;
+use std::collections::{BTreeMap, BTreeSet};
+use std::num::NonZeroU32;
+use std::ops::Range;
+use std::path::Path;
+use std::rc::Rc;
+use std::{fmt, fs, io};
+
+/// Line and column (both 1-based, and the latter counted in `char`s) in the text.
+#[derive(Copy, Clone)]
+struct Pos {
+    line: usize,
+    col: usize,
+}
+
+// FIXME(eddyb) stop abusing `io::Error` for error reporting.
+fn invalid(pos: Pos, reason: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!(
+            "malformed SPIR-T text (at {}:{}: {reason})",
+            pos.line, pos.col
+        ),
+    )
+}
+
+impl Module {
+    pub fn parse_from_spirt_file(cx: Rc<Context>, path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::parse_from_spirt_text(cx, &fs::read_to_string(path)?)
+    }
+
+    pub fn parse_from_spirt_text(cx: Rc<Context>, text: &str) -> io::Result<Self> {
+        let tokens = tokenize(&cx, text)?;
+        let end = tokens.len();
+        Parser {
+            cx,
+            tokens,
+            cursor: 0,
+            end,
+            lazy_items: FxIndexMap::default(),
+            in_progress: FxHashSet::default(),
+            interned: FxHashMap::default(),
+            global_var_defs: EntityDefs::new(),
+            global_vars: FxHashMap::default(),
+            pending_global_var_initializers: vec![],
+            funcs: FxHashMap::default(),
+        }
+        .parse_module()
+    }
+
+    /// Like [`Module::parse_from_spirt_text`], but ignoring any indentation
+    /// common to all (non-empty) lines of `text`, which allows SPIR-T text to be
+    /// indented along with the Rust code around it (see also [`ir!`](crate::ir)).
+    pub fn parse_from_indented_spirt_text(cx: Rc<Context>, text: &str) -> io::Result<Self> {
+        let indent = text
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| line.len() - line.trim_start().len())
+            .min()
+            .unwrap_or(0);
+        let text: String = text
+            .lines()
+            .map(|line| {
+                let line = line.get(indent..).unwrap_or_else(|| line.trim_start());
+                line.to_string() + "\n"
+            })
+            .collect();
+        Self::parse_from_spirt_text(cx, &text)
+    }
+}
+
+/// Construct a [`Module`] from SPIR-T text written inline (e.g. in tests, to
+/// avoid needing SPIR-V binaries as inputs), panicking on any parsing errors.
+///
+/// The text is parsed with [`Module::parse_from_indented_spirt_text`], and
+/// can contain anything [`print`](crate::print) can produce for a module, e.g.:
+/// ```text
+/// let module = spirt::ir!(cx, r#"
+///     module.dialect = SPIR-V {
+///       version: 1.3,
+///       extensions: {},
+///       capabilities: {spv.Capability.Shader},
+///       addressing_model: spv.AddressingModel.Logical,
+///       memory_model: spv.MemoryModel.GLSL450,
+///     }
+///
+///     func0(v0: s32, v1: s32) -> s32 {
+///       v2 = spv.OpIAdd(v0, v1): s32
+///       v2
+///     }
+///
+///     export { "add": func0 }
+/// "#);
+/// ```
+/// (where functions can then be found through [`Module::exports`]).
+#[macro_export]
+macro_rules! ir {
+    ($cx:expr, $text:expr $(,)?) => {
+        match $crate::Module::parse_from_indented_spirt_text(::std::rc::Rc::clone(&$cx), $text) {
+            Ok(module) => module,
+            Err(e) => panic!("ir!: {e}"),
+        }
+    };
+}
+
+#[derive(Clone, PartialEq)]
+enum Token<'a> {
+    /// Identifier-like word, e.g. `type3`, `spv`, `OpIAdd` or `SPIR-V`.
+    Word(&'a str),
+
+    /// Numeric literal, including any type suffix, e.g. `123`, `-1s32` or `1.5f32`.
+    Number(&'a str),
+
+    /// String literal (in Rust `{:?}` syntax), already unescaped.
+    Str(String),
+
+    Punct(&'static str),
+
+    /// [`Attr`] printed as a comment (e.g. `// at file.rs:1:2`), as opposed to
+    /// all other comments, which are ignored.
+    CommentAttr(Attr),
+}
+
+impl fmt::Display for Token<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Word(s) | Token::Number(s) | Token::Punct(s) => write!(f, "`{s}`"),
+            Token::Str(s) => write!(f, "`{s:?}`"),
+            Token::CommentAttr(_) => f.write_str("attribute comment"),
+        }
+    }
+}
+
+struct Spanned<'a> {
+    token: Token<'a>,
+    pos: Pos,
+}
+
+// NOTE(eddyb) multi-character punctuation has to come before any of its prefixes.
+const PUNCTUATION: &[&str] = &[
+    "->", "<-", "=>", "#", "{", "}", "(", ")", "<", ">", "[", "]", ",", ":", ";", "=", ".", "&",
+    "×", "…",
+];
+
+fn tokenize<'a>(cx: &Context, text: &'a str) -> io::Result<Vec<Spanned<'a>>> {
+    let mut tokens = vec![];
+
+    let mut line = 1;
+    let mut line_start = 0;
+    let mut i = 0;
+    while let Some(c) = text[i..].chars().next() {
+        let pos = Pos {
+            line,
+            col: text[line_start..i].chars().count() + 1,
+        };
+        let rest = &text[i..];
+
+        let (token, len) = if c == '\n' {
+            line += 1;
+            line_start = i + 1;
+            i += 1;
+            continue;
+        } else if c.is_whitespace() {
+            i += c.len_utf8();
+            continue;
+        } else if rest.starts_with("//") {
+            let len = rest.find('\n').unwrap_or(rest.len());
+            match comment_attr(cx, &rest[..len]) {
+                Some(attr) => (Token::CommentAttr(attr.map_err(|e| invalid(pos, e))?), len),
+                None => {
+                    i += len;
+                    continue;
+                }
+            }
+        } else if rest.starts_with("/*") {
+            let len = rest
+                .find("*/")
+                .ok_or_else(|| invalid(pos, "unterminated block comment"))?
+                + 2;
+            for (j, _) in rest[..len].match_indices('\n') {
+                line += 1;
+                line_start = i + j + 1;
+            }
+            i += len;
+            continue;
+        } else if let Some(rest) = rest.strip_prefix('"') {
+            let (s, len) = unescape_str(rest).map_err(|e| invalid(pos, e))?;
+            (Token::Str(s), 1 + len)
+        } else if rest.starts_with("SPIR-V") {
+            (Token::Word("SPIR-V"), "SPIR-V".len())
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let len = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            (Token::Word(&rest[..len]), len)
+        } else if c.is_ascii_digit()
+            || (c == '-' && rest[1..].starts_with(|c: char| c.is_ascii_digit() || c == 'i'))
+        {
+            // NOTE(eddyb) this has to allow e.g. `-1s32`, `1.5e-10f32` and `-inff32`.
+            let is_hex = rest.trim_start_matches('-').starts_with("0x");
+            let bytes = rest.as_bytes();
+            let mut len = 1;
+            while let Some(&b) = bytes.get(len) {
+                let is_exponent_sign =
+                    !is_hex && matches!(b, b'-' | b'+') && matches!(bytes[len - 1], b'e' | b'E');
+                if !(b.is_ascii_alphanumeric() || b == b'_' || b == b'.' || is_exponent_sign) {
+                    break;
+                }
+                len += 1;
+            }
+            (Token::Number(&rest[..len]), len)
+        } else if let Some(&p) = PUNCTUATION.iter().find(|&&p| rest.starts_with(p)) {
+            (Token::Punct(p), p.len())
+        } else {
+            return Err(invalid(pos, &format!("unexpected character {c:?}")));
+        };
+        tokens.push(Spanned { token, pos });
+        i += len;
+    }
+
+    Ok(tokens)
+}
+
+/// Parse the [`Attr`] in `comment` (the inverse of its `AttrStyle::Comment`
+/// printing), returning `None` if `comment` isn't an attribute at all.
+fn comment_attr(cx: &Context, comment: &str) -> Option<Result<Attr, &'static str>> {
+    let comment = comment.trim_end();
+    if let Some(location) = comment.strip_prefix("// at ") {
+        Some((|| {
+            let mut parts = location.rsplitn(3, ':');
+            let (col, line, file_path) = (parts.next(), parts.next(), parts.next());
+            let malformed = "malformed `// at file:line:col` comment";
+            let col: u32 = col.and_then(|col| col.parse().ok()).ok_or(malformed)?;
+            let line = line.and_then(|line| line.parse().ok()).ok_or(malformed)?;
+            let file_path = file_path.ok_or(malformed)?;
+            let file_path = match file_path.strip_prefix('"') {
+                Some(quoted) => match unescape_str(quoted)? {
+                    (file_path, len) if len == quoted.len() => file_path,
+                    _ => return Err(malformed),
+                },
+                None => file_path.to_string(),
+            };
+            Ok(Attr::SpvDebugLine {
+                file_path: OrdAssertEq(cx.intern(file_path)),
+                line,
+                // HACK(eddyb) undoing the `+ 1` done by printing.
+                col: col.checked_sub(1).ok_or(malformed)?,
+            })
+        })())
+    } else if let Some((severity, rest)) = comment
+        .strip_prefix("// ")
+        .and_then(|rest| rest.split_once(": \""))
+        .and_then(|(severity, rest)| Some((DiagnosticSeverity::from_name(severity)?, rest)))
+    {
+        Some((|| {
+            let malformed = "malformed `// severity: \"...\"` diagnostic comment";
+            let (message, len) = unescape_str(rest)?;
+            let mut rest = &rest[len..];
+            let mut related = vec![];
+            if let Some(list) = rest.strip_prefix(", related: [") {
+                rest = list;
+                while let Some(quoted) = rest.strip_prefix('"') {
+                    let (note, len) = unescape_str(quoted)?;
+                    related.push(note);
+                    rest = &quoted[len..];
+                    rest = rest.strip_prefix(", ").unwrap_or(rest);
+                }
+                rest = rest.strip_prefix(']').ok_or(malformed)?;
+            }
+            if !rest.is_empty() {
+                return Err(malformed);
+            }
+            Ok(Attr::Diagnostic {
+                severity,
+                message,
+                related,
+            })
+        })())
+    } else if let Some(quoted) = comment.strip_prefix("// unsupported SPIR-V: \"") {
+        Some(match unescape_str(quoted) {
+            Ok((message, len)) if len == quoted.len() => Ok(Attr::SpvUnsupported(message)),
+            _ => Err("malformed `// unsupported SPIR-V: \"...\"` comment"),
+        })
+    } else {
+        comment.strip_prefix("// originally %").map(|id| {
+            id.parse()
+                .ok()
+                .map(Attr::SpvOriginalId)
+                .ok_or("malformed `// originally %ID` comment")
+        })
+    }
+}
+
+/// Unescape the contents of a string literal (in Rust `{:?}` syntax), with
+/// `s` starting just after the opening `"`, returning the unescaped string,
+/// and the length (in bytes) of `s` consumed (including the closing `"`).
+fn unescape_str(s: &str) -> Result<(String, usize), &'static str> {
+    let mut out = String::new();
+    let mut chars = s.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Ok((out, i + 1)),
+            '\\' => out.push(match chars.next().map(|(_, c)| c) {
+                Some('n') => '\n',
+                Some('r') => '\r',
+                Some('t') => '\t',
+                Some('0') => '\0',
+                Some(c @ ('\\' | '"' | '\'')) => c,
+                Some('u') => {
+                    let rest = chars.as_str();
+                    let hex = rest
+                        .strip_prefix('{')
+                        .and_then(|rest| Some(&rest[..rest.find('}')?]))
+                        .ok_or("malformed `\\u{...}` escape")?;
+                    let c = u32::from_str_radix(hex, 16)
+                        .ok()
+                        .and_then(char::from_u32)
+                        .ok_or("invalid `\\u{...}` escape")?;
+                    for _ in 0..hex.len() + 2 {
+                        chars.next();
+                    }
+                    c
+                }
+                _ => return Err("unknown escape in string literal"),
+            }),
+            _ => out.push(c),
+        }
+    }
+    Err("unterminated string literal")
+}
+
+/// Split a name like `type123` into its "category" (e.g. `type`) and index.
+fn split_name_idx(name: &str) -> Option<(&str, u32)> {
+    let category = name.trim_end_matches(|c: char| c.is_ascii_digit());
+    if category.len() == name.len() {
+        return None;
+    }
+    Some((category, name[category.len()..].parse().ok()?))
+}
+
+/// Whether `name` can be used as the name of a value definition (e.g. `v123`),
+/// including `_` (used when the definition has no uses).
+fn is_value_def_name(name: &str) -> bool {
+    name == "_" || matches!(split_name_idx(name), Some(("v", _)))
+}
+
+/// Split a name like `s32` into its signedness/kind (`s`, `u` or `f`) and width.
+fn compact_scalar_type_name(name: &str) -> Option<(char, u32)> {
+    let kind = name
+        .chars()
+        .next()
+        .filter(|c| matches!(c, 's' | 'u' | 'f'))?;
+    let width = &name[1..];
+    if width.is_empty() || !width.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    Some((kind, width.parse().ok()?))
+}
+
+/// Parse an integer literal (in either decimal or `0x` hexadecimal notation).
+fn parse_int_literal(s: &str) -> Option<u128> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u128::from_str_radix(&hex.replace('_', ""), 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+/// Split `tokens` into top-level items (each being a token range that includes
+/// any leading attributes), paired with the index of each item's name token.
+///
+/// Top-level items are separated using indentation (as `print` always produces
+/// it): a new item starts at any token in the first column, other than closing
+/// brackets (or the name following some attributes in the first column).
+fn split_top_level_items(tokens: &[Spanned<'_>]) -> Vec<(Option<usize>, Range<usize>)> {
+    let mut items = vec![];
+
+    let mut depth = 0_usize;
+    let mut current_name_idx = None;
+    let mut current_start = 0;
+    let mut after_hash = false;
+    for (i, Spanned { token, pos }) in tokens.iter().enumerate() {
+        let is_closing = matches!(token, Token::Punct(")" | "]" | "}"));
+        if depth == 0 && !is_closing && pos.col == 1 && current_name_idx.is_some() {
+            items.push((current_name_idx.take(), current_start..i));
+            current_start = i;
+        }
+
+        let is_attr = after_hash || matches!(token, Token::Punct("#") | Token::CommentAttr(_));
+        if depth == 0 && !is_attr && current_name_idx.is_none() {
+            current_name_idx = Some(i);
+        }
+        after_hash = depth == 0 && matches!(token, Token::Punct("#"));
+
+        match token {
+            Token::Punct("(" | "[" | "{") => depth += 1,
+            Token::Punct(")" | "]" | "}") => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+    if current_start < tokens.len() {
+        items.push((current_name_idx, current_start..tokens.len()));
+    }
+
+    items
+}
+
+/// Result of parsing SPIR-V immediate operands, which may be incomplete due
+/// to `LiteralContextDependentNumber` operands, whose size depends on a type
+/// (which is only known after the immediates are parsed, e.g. `: T` suffix).
+#[derive(Default)]
+struct PendingImms {
+    imms: SmallVec<[spv::Imm; 2]>,
+
+    /// Contextually-sized literals (with their position in `imms`, as a
+    /// placeholder [`spv::Imm::Short`]), and their full value.
+    contextual_literals: SmallVec<[(usize, u128, Pos); 1]>,
+}
+
+impl PendingImms {
+    fn append(&mut self, other: PendingImms) {
+        let base = self.imms.len();
+        self.imms.extend(other.imms);
+        self.contextual_literals.extend(
+            other
+                .contextual_literals
+                .into_iter()
+                .map(|(i, value, pos)| (base + i, value, pos)),
+        );
+    }
+
+    /// Complete the immediates, using `contextual_type` (if needed) to
+    /// determine the size of all `LiteralContextDependentNumber` operands.
+    fn finish(
+        self,
+        cx: &Context,
+        contextual_type: Option<Type>,
+    ) -> io::Result<SmallVec<[spv::Imm; 2]>> {
+        let wk = &spec::Spec::get().well_known;
+
+        let (first_idx, _, first_pos) = match self.contextual_literals.first() {
+            Some(&first) => first,
+            None => return Ok(self.imms),
+        };
+        let _ = first_idx;
+
+        let width = contextual_type
+            .and_then(|ty| match &cx[ty].ctor {
+                TypeCtor::SpvInst(inst)
+                    if [wk.OpTypeInt, wk.OpTypeFloat].contains(&inst.opcode) =>
+                {
+                    match inst.imms[..] {
+                        [spv::Imm::Short(_, width), ..] if width > 0 => Some(width),
+                        _ => None,
+                    }
+                }
+                _ => None,
+            })
+            .ok_or_else(|| {
+                invalid(
+                    first_pos,
+                    "literal requires an integer or floating-point type, to determine its size",
+                )
+            })?;
+        // HACK(eddyb) `(width + 31) / 32 - 1` but without overflow.
+        let extra_word_count = (width - 1) / 32;
+
+        let mut contextual_literals = self.contextual_literals.into_iter().peekable();
+        let mut imms = SmallVec::with_capacity(self.imms.len());
+        for (i, imm) in self.imms.into_iter().enumerate() {
+            let (value, pos) = match contextual_literals.peek() {
+                Some(&(idx, value, pos)) if idx == i => {
+                    contextual_literals.next();
+                    (value, pos)
+                }
+                _ => {
+                    imms.push(imm);
+                    continue;
+                }
+            };
+            let kind = match imm {
+                spv::Imm::Short(kind, _) => kind,
+                _ => unreachable!(),
+            };
+
+            let word = |word_idx: u32| value.checked_shr(word_idx * 32).unwrap_or(0) as u32;
+            if value.checked_shr((extra_word_count + 1) * 32).unwrap_or(0) != 0 {
+                return Err(invalid(
+                    pos,
+                    &format!("literal doesn't fit in {width} bits"),
+                ));
+            }
+            if extra_word_count == 0 {
+                imms.push(spv::Imm::Short(kind, word(0)));
+            } else {
+                imms.push(spv::Imm::LongStart(kind, word(0)));
+                imms.extend((1..=extra_word_count).map(|i| spv::Imm::LongCont(kind, word(i))));
+            }
+        }
+        Ok(imms)
+    }
+}
+
+/// Definition of an interned name (e.g. `attrs0`, `type1` or `const2`).
+#[derive(Copy, Clone)]
+enum Interned {
+    AttrSet(AttrSet),
+    Type(Type),
+    Const(Const),
+}
+
+struct Parser<'a> {
+    cx: Rc<Context>,
+
+    tokens: Vec<Spanned<'a>>,
+
+    /// Index (in `tokens`) of the next token to be parsed.
+    cursor: usize,
+
+    /// Index (in `tokens`) just past the end of the item currently being
+    /// parsed, i.e. no tokens at, or after, `end` can be seen by the parser.
+    end: usize,
+
+    /// Top-level items that are parsed on demand (i.e. when first used), which
+    /// allows them to be referenced before their definition (in the text).
+    lazy_items: FxIndexMap<&'a str, Range<usize>>,
+
+    /// Names of `lazy_items` currently being parsed (to detect cycles).
+    in_progress: FxHashSet<&'a str>,
+
+    interned: FxHashMap<&'a str, Interned>,
+
+    global_var_defs: EntityDefs<GlobalVar>,
+    global_vars: FxHashMap<&'a str, GlobalVar>,
+
+    /// Initializers of `global_var_defs` are only parsed at the very end,
+    /// as they may refer to `GlobalVar`s (and to the `GlobalVar` itself).
+    pending_global_var_initializers: Vec<(GlobalVar, Range<usize>)>,
+
+    funcs: FxHashMap<&'a str, Func>,
+}
+
+/// Name of a value definition (`None` for `_`), and its position in the text.
+type ValueDefName<'a> = (Option<&'a str>, Pos);
+
+/// Per-function parsing state (see `Parser::parse_func_body`).
+struct FuncBodyState<'a, 'b> {
+    func_def_body: &'b mut FuncDefBody,
+
+    /// All the values defined so far in the function (with their types).
+    values: FxHashMap<&'a str, (Value, Type)>,
+
+    /// All the labels used so far in the function, with the position of their
+    /// first use, and whether they've been defined yet.
+    labels: FxIndexMap<&'a str, (ControlRegion, Pos, bool)>,
+}
+
+impl<'a> FuncBodyState<'a, '_> {
+    fn define_value(
+        &mut self,
+        (name, pos): ValueDefName<'a>,
+        value: Value,
+        ty: Type,
+    ) -> io::Result<()> {
+        if let Some(name) = name {
+            if self.values.insert(name, (value, ty)).is_some() {
+                return Err(invalid(pos, &format!("`{name}` defined more than once")));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'a> Parser<'a> {
+    fn tok(&self, idx: usize) -> Option<&Token<'a>> {
+        if idx < self.end {
+            Some(&self.tokens[idx].token)
+        } else {
+            None
+        }
+    }
+
+    fn peek(&self) -> Option<&Token<'a>> {
+        self.tok(self.cursor)
+    }
+
+    fn pos(&self) -> Pos {
+        let idx = if self.cursor < self.end {
+            self.cursor
+        } else {
+            self.end.saturating_sub(1)
+        };
+        self.tokens
+            .get(idx)
+            .map_or(Pos { line: 1, col: 1 }, |t| t.pos)
+    }
+
+    fn err(&self, reason: &str) -> io::Error {
+        invalid(self.pos(), reason)
+    }
+
+    fn expected(&self, what: &str) -> io::Error {
+        self.err(&match self.peek() {
+            Some(token) => format!("expected {what}, found {token}"),
+            None => format!("expected {what}, found end of definition"),
+        })
+    }
+
+    fn is_punct_at(&self, idx: usize, punct: &str) -> bool {
+        matches!(self.tok(idx), Some(&Token::Punct(p)) if p == punct)
+    }
+
+    fn is_word_at(&self, idx: usize, word: &str) -> bool {
+        matches!(self.tok(idx), Some(&Token::Word(w)) if w == word)
+    }
+
+    fn is_punct(&self, punct: &str) -> bool {
+        self.is_punct_at(self.cursor, punct)
+    }
+
+    fn is_word(&self, word: &str) -> bool {
+        self.is_word_at(self.cursor, word)
+    }
+
+    fn eat_punct(&mut self, punct: &str) -> bool {
+        let found = self.is_punct(punct);
+        if found {
+            self.cursor += 1;
+        }
+        found
+    }
+
+    fn eat_word(&mut self, word: &str) -> bool {
+        let found = self.is_word(word);
+        if found {
+            self.cursor += 1;
+        }
+        found
+    }
+
+    fn expect_punct(&mut self, punct: &str) -> io::Result<()> {
+        if self.eat_punct(punct) {
+            Ok(())
+        } else {
+            Err(self.expected(&format!("`{punct}`")))
+        }
+    }
+
+    fn expect_word(&mut self, word: &str) -> io::Result<()> {
+        if self.eat_word(word) {
+            Ok(())
+        } else {
+            Err(self.expected(&format!("`{word}`")))
+        }
+    }
+
+    fn expect_any_word(&mut self, what: &str) -> io::Result<&'a str> {
+        match self.peek() {
+            Some(&Token::Word(word)) => {
+                self.cursor += 1;
+                Ok(word)
+            }
+            _ => Err(self.expected(what)),
+        }
+    }
+
+    fn expect_number(&mut self) -> io::Result<&'a str> {
+        match self.peek() {
+            Some(&Token::Number(number)) => {
+                self.cursor += 1;
+                Ok(number)
+            }
+            _ => Err(self.expected("number")),
+        }
+    }
+
+    fn expect_u32(&mut self) -> io::Result<u32> {
+        let pos = self.pos();
+        let number = self.expect_number()?;
+        parse_int_literal(number)
+            .and_then(|x| x.try_into().ok())
+            .ok_or_else(|| invalid(pos, &format!("`{number}` is not a valid 32-bit integer")))
+    }
+
+    fn expect_str(&mut self) -> io::Result<String> {
+        match self.peek() {
+            Some(Token::Str(s)) => {
+                let s = s.clone();
+                self.cursor += 1;
+                Ok(s)
+            }
+            _ => Err(self.expected("string literal")),
+        }
+    }
+
+    /// Parse a comma-separated list (which may have a trailing comma), ending
+    /// in `close` (with the opening bracket having been already parsed).
+    fn comma_sep(
+        &mut self,
+        close: &str,
+        mut each: impl FnMut(&mut Self) -> io::Result<()>,
+    ) -> io::Result<()> {
+        loop {
+            if self.eat_punct(close) {
+                return Ok(());
+            }
+            each(self)?;
+            if !self.eat_punct(",") {
+                return self.expect_punct(close);
+            }
+        }
+    }
+
+    /// Run `f` with the parser limited to the token `range`, which must be
+    /// entirely consumed by `f`, before the previous parser state is restored.
+    fn with_range<R>(
+        &mut self,
+        range: Range<usize>,
+        f: impl FnOnce(&mut Self) -> io::Result<R>,
+    ) -> io::Result<R> {
+        let saved = (self.cursor, self.end);
+        self.cursor = range.start;
+        self.end = range.end;
+        let result = f(self).and_then(|x| {
+            if self.cursor < self.end {
+                Err(self.expected("end of definition"))
+            } else {
+                Ok(x)
+            }
+        });
+        (self.cursor, self.end) = saved;
+        result
+    }
+
+    fn parse_module(mut self) -> io::Result<Module> {
+        let mut dialect_item = None;
+        let mut debug_info_item = None;
+        let mut func_items = vec![];
+        let mut exports_items = vec![];
+        for (name_idx, range) in split_top_level_items(&self.tokens) {
+            let name_idx = name_idx.ok_or_else(|| {
+                invalid(
+                    self.tokens[range.start].pos,
+                    "attributes must be followed by a definition",
+                )
+            })?;
+            let pos = self.tokens[name_idx].pos;
+            let name = match self.tokens[name_idx].token {
+                Token::Word(name) => name,
+                ref token => {
+                    return Err(invalid(
+                        pos,
+                        &format!("expected top-level definition, found {token}"),
+                    ));
+                }
+            };
+
+            let duplicate = match name {
+                "module" => {
+                    let item = match self.tok(name_idx + 2) {
+                        Some(Token::Word("dialect")) => &mut dialect_item,
+                        Some(Token::Word("debug_info")) => &mut debug_info_item,
+                        _ => {
+                            return Err(invalid(
+                                pos,
+                                "expected `module.dialect` or `module.debug_info`",
+                            ));
+                        }
+                    };
+                    item.replace(range).is_some()
+                }
+                "export" => {
+                    exports_items.push(range);
+                    false
+                }
+                _ => match split_name_idx(name) {
+                    Some(("attrs" | "type" | "const" | "global_var", _)) => {
+                        self.lazy_items.insert(name, range).is_some()
+                    }
+                    Some(("func", _)) => {
+                        let duplicate = func_items.iter().any(|&(other, _)| other == name);
+                        func_items.push((name, range));
+                        duplicate
+                    }
+                    _ => {
+                        return Err(invalid(
+                            pos,
+                            &format!("unknown kind of top-level definition `{name}`"),
+                        ));
+                    }
+                },
+            };
+            if duplicate {
+                return Err(invalid(pos, &format!("`{name}` defined more than once")));
+            }
+        }
+
+        let dialect = match dialect_item {
+            Some(range) => self.with_range(range, |p| p.parse_module_dialect())?,
+            None => return Err(invalid(Pos { line: 1, col: 1 }, "missing `module.dialect`")),
+        };
+        let debug_info = match debug_info_item {
+            Some(range) => self.with_range(range, |p| p.parse_module_debug_info())?,
+            None => ModuleDebugInfo::Spv(spv::ModuleDebugInfo {
+                original_generator_magic: None,
+                source_languages: BTreeMap::new(),
+                source_extensions: vec![],
+                module_processes: vec![],
+            }),
+        };
+        let mut module = Module::new(self.cx.clone(), dialect, debug_info);
+
+        // Function declarations are parsed first (in order to allow calls to
+        // any function), with all the bodies being parsed only afterwards.
+        let mut pending_func_bodies = vec![];
+        for (name, range) in func_items {
+            let (func_decl, body) = self.with_range(range, |p| p.parse_func_decl(name))?;
+            let func = module.funcs.define(&self.cx, func_decl);
+            self.funcs.insert(name, func);
+            pending_func_bodies.extend(body.map(|body| (func, body)));
+        }
+        for (func, (body_range, param_names)) in pending_func_bodies {
+            let func_def_body = match &mut module.funcs[func].def {
+                DeclDef::Present(func_def_body) => func_def_body,
+                DeclDef::Imported(_) => unreachable!(),
+            };
+            self.with_range(body_range, |p| {
+                p.parse_func_body(func_def_body, &param_names)
+            })?;
+        }
+
+        for range in exports_items {
+            self.with_range(range, |p| {
+                p.expect_word("export")?;
+                p.expect_punct("{")?;
+                p.comma_sep("}", |p| {
+                    let pos = p.pos();
+                    let key = p.parse_export_key()?;
+                    p.expect_punct(":")?;
+                    let exportee = p.parse_exportee()?;
+                    if module.exports.insert(key, exportee).is_some() {
+                        return Err(invalid(pos, "duplicate export"));
+                    }
+                    Ok(())
+                })
+            })?;
+        }
+
+        // Unused definitions still have to be valid (and global variables
+        // have to be kept, as they may be used for their side-effects).
+        for i in 0..self.lazy_items.len() {
+            let (&name, _) = self.lazy_items.get_index(i).unwrap();
+            if let Some(("global_var", _)) = split_name_idx(name) {
+                self.global_var_named(name)?;
+            } else {
+                self.interned_named(name)?;
+            }
+        }
+
+        // NOTE(eddyb) initializers can refer to new global variables, which
+        // adds more entries to `pending_global_var_initializers`.
+        let mut i = 0;
+        while let Some((gv, range)) = self.pending_global_var_initializers.get(i).cloned() {
+            let initializer = self.with_range(range, |p| p.parse_const())?;
+            match &mut self.global_var_defs[gv].def {
+                DeclDef::Present(def) => def.initializer = Some(initializer),
+                DeclDef::Imported(_) => unreachable!(),
+            }
+            i += 1;
+        }
+
+        module.global_vars = self.global_var_defs;
+
+        Ok(module)
+    }
+
+    fn parse_module_dialect(&mut self) -> io::Result<ModuleDialect> {
+        let wk = &spec::Spec::get().well_known;
+
+        self.expect_word("module")?;
+        self.expect_punct(".")?;
+        self.expect_word("dialect")?;
+        self.expect_punct("=")?;
+        self.expect_word("SPIR-V")?;
+        self.expect_punct("{")?;
+
+        let mut version = None;
+        let mut extensions = BTreeSet::new();
+        let mut capabilities = BTreeSet::new();
+        let mut addressing_model = None;
+        let mut memory_model = None;
+        let end_pos = self.tokens[self.end - 1].pos;
+        self.comma_sep("}", |p| {
+            let pos = p.pos();
+            let field = p.expect_any_word("field name")?;
+            p.expect_punct(":")?;
+            match field {
+                "version" => {
+                    let number = p.expect_number()?;
+                    version = Some(
+                        number
+                            .split_once('.')
+                            .and_then(|(major, minor)| {
+                                Some((major.parse().ok()?, minor.parse().ok()?))
+                            })
+                            .ok_or_else(|| invalid(pos, "malformed version"))?,
+                    );
+                }
+                "extensions" => {
+                    p.expect_punct("{")?;
+                    p.comma_sep("}", |p| {
+                        extensions.insert(p.expect_str()?);
+                        Ok(())
+                    })?;
+                }
+                "capabilities" => {
+                    p.expect_punct("{")?;
+                    p.comma_sep("}", |p| {
+                        capabilities.insert(p.parse_spv_single_imm_operand(wk.Capability)?);
+                        Ok(())
+                    })?;
+                }
+                "addressing_model" => {
+                    addressing_model = Some(p.parse_spv_single_imm_operand(wk.AddressingModel)?);
+                }
+                "memory_model" => {
+                    memory_model = Some(p.parse_spv_single_imm_operand(wk.MemoryModel)?);
+                }
+                _ => return Err(invalid(pos, &format!("unknown field `{field}`"))),
+            }
+            Ok(())
+        })?;
+
+        let missing = |field| invalid(end_pos, &format!("missing field `{field}`"));
+        let (version_major, version_minor) = version.ok_or_else(|| missing("version"))?;
+        Ok(ModuleDialect::Spv(spv::Dialect {
+            version_major,
+            version_minor,
+            capabilities,
+            extensions,
+            addressing_model: addressing_model.ok_or_else(|| missing("addressing_model"))?,
+            memory_model: memory_model.ok_or_else(|| missing("memory_model"))?,
+        }))
+    }
+
+    fn parse_module_debug_info(&mut self) -> io::Result<ModuleDebugInfo> {
+        let wk = &spec::Spec::get().well_known;
+
+        self.expect_word("module")?;
+        self.expect_punct(".")?;
+        self.expect_word("debug_info")?;
+        self.expect_punct("=")?;
+        self.expect_word("SPIR-V")?;
+        self.expect_punct("{")?;
+
+        let mut original_generator_magic = None;
+        let mut source_languages = BTreeMap::new();
+        let mut source_extensions = vec![];
+        let mut module_processes = vec![];
+        self.comma_sep("}", |p| {
+            let pos = p.pos();
+            let field = p.expect_any_word("field name")?;
+            p.expect_punct(":")?;
+            match field {
+                "generator" => {
+                    if !p.eat_word("unknown") {
+                        p.expect_punct("{")?;
+                        p.expect_word("tool_id")?;
+                        p.expect_punct(":")?;
+                        let tool_id = p.expect_u32()?;
+                        p.expect_punct(",")?;
+                        p.expect_word("version")?;
+                        p.expect_punct(":")?;
+                        let tool_version = p.expect_u32()?;
+                        p.eat_punct(",");
+                        p.expect_punct("}")?;
+
+                        if tool_id > 0xffff || tool_version > 0xffff {
+                            return Err(invalid(pos, "generator tool ID/version out of range"));
+                        }
+                        original_generator_magic = NonZeroU32::new(tool_id << 16 | tool_version);
+                    }
+                }
+                "source_languages" => {
+                    p.expect_punct("{")?;
+                    p.comma_sep("}", |p| {
+                        let lang = p.parse_spv_single_imm_operand(wk.SourceLanguage)?;
+                        p.expect_punct("{")?;
+                        p.expect_word("version")?;
+                        p.expect_punct(":")?;
+                        let version = p.expect_u32()?;
+                        p.eat_punct(",");
+                        p.expect_punct("}")?;
+                        p.expect_punct(":")?;
+
+                        let spv::DebugSources { file_contents } = source_languages
+                            .entry(spv::DebugSourceLang { lang, version })
+                            .or_default();
+                        p.expect_punct("{")?;
+                        p.comma_sep("}", |p| {
+                            let file = p.expect_str()?;
+                            let file = p.cx.intern(file);
+                            p.expect_punct(":")?;
+                            file_contents.insert(file, p.expect_str()?);
+                            Ok(())
+                        })
+                    })?;
+                }
+                "source_extensions" | "module_processes" => {
+                    let list = if field == "source_extensions" {
+                        &mut source_extensions
+                    } else {
+                        &mut module_processes
+                    };
+                    p.expect_punct("[")?;
+                    p.comma_sep("]", |p| {
+                        list.push(p.expect_str()?);
+                        Ok(())
+                    })?;
+                }
+                _ => return Err(invalid(pos, &format!("unknown field `{field}`"))),
+            }
+            Ok(())
+        })?;
+
+        Ok(ModuleDebugInfo::Spv(spv::ModuleDebugInfo {
+            original_generator_magic,
+            source_languages,
+            source_extensions,
+            module_processes,
+        }))
+    }
+
+    fn parse_export_key(&mut self) -> io::Result<ExportKey> {
+        let wk = &spec::Spec::get().well_known;
+
+        if let Some(Token::Str(_)) = self.peek() {
+            let name = self.expect_str()?;
+            return Ok(ExportKey::LinkName(self.cx.intern(name)));
+        }
+
+        let pos = self.pos();
+        match self.try_parse_spv_opcode()? {
+            Some(opcode) if opcode == wk.OpEntryPoint => Ok(ExportKey::SpvEntryPoint {
+                imms: self.parse_spv_imms(opcode)?.finish(&self.cx, None)?,
+                // FIXME(eddyb) these aren't printed, but `spv::lift` should
+                // be recomputing them anyway.
+                interface_global_vars: SmallVec::new(),
+            }),
+            Some(_) => Err(invalid(
+                pos,
+                "only `spv.OpEntryPoint` can be used as an export",
+            )),
+            None => Err(self.expected("export key (string or `spv.OpEntryPoint`)")),
+        }
+    }
+
+    fn parse_exportee(&mut self) -> io::Result<Exportee> {
+        let pos = self.pos();
+        let name = self.expect_any_word("global variable or function name")?;
+        match split_name_idx(name) {
+            Some(("global_var", _)) => Ok(Exportee::GlobalVar(self.global_var_named(name)?)),
+            Some(("func", _)) => Ok(Exportee::Func(self.func_named(name, pos)?)),
+            _ => Err(invalid(
+                pos,
+                &format!("expected global variable or function name, found `{name}`"),
+            )),
+        }
+    }
+
+    fn parse_import(&mut self) -> io::Result<Import> {
+        self.expect_word("import")?;
+        let name = self.expect_str()?;
+        Ok(Import::LinkName(self.cx.intern(name)))
+    }
+
+    /// Get the range (of tokens) for the lazily-parsed item `name`, marking it
+    /// as "in progress" (which must be undone by the caller, when done parsing).
+    fn start_lazy_item(&mut self, name: &'a str) -> io::Result<Range<usize>> {
+        let range = self
+            .lazy_items
+            .get(name)
+            .cloned()
+            .ok_or_else(|| self.err(&format!("`{name}` is not defined")))?;
+        if !self.in_progress.insert(name) {
+            return Err(self.err(&format!("`{name}` is defined in terms of itself")));
+        }
+        Ok(range)
+    }
+
+    /// Parse the start of a named (top-level) definition, i.e. `#attrs name`.
+    fn parse_def_name(&mut self, name: &str) -> io::Result<AttrSet> {
+        let attrs = self.parse_attrs()?;
+        self.expect_word(name)?;
+        Ok(attrs)
+    }
+
+    fn interned_named(&mut self, name: &'a str) -> io::Result<Interned> {
+        if let Some(&interned) = self.interned.get(name) {
+            return Ok(interned);
+        }
+
+        let range = self.start_lazy_item(name)?;
+        let result = self.with_range(range, |p| {
+            let attrs = p.parse_def_name(name)?;
+            p.expect_punct("=")?;
+            match split_name_idx(name) {
+                Some(("attrs", _)) => {
+                    if attrs != AttrSet::default() {
+                        return Err(p.err("attribute sets cannot have attributes"));
+                    }
+                    Ok(Interned::AttrSet(p.parse_attrs()?))
+                }
+                Some(("type", _)) => Ok(Interned::Type(p.parse_type_with_attrs(attrs)?)),
+                Some(("const", _)) => Ok(Interned::Const(p.parse_const_with_attrs(attrs)?)),
+                _ => unreachable!(),
+            }
+        });
+        self.in_progress.remove(name);
+
+        let interned = result?;
+        self.interned.insert(name, interned);
+        Ok(interned)
+    }
+
+    fn global_var_named(&mut self, name: &'a str) -> io::Result<GlobalVar> {
+        if let Some(&gv) = self.global_vars.get(name) {
+            return Ok(gv);
+        }
+
+        let range = self.start_lazy_item(name)?;
+        let result = self.with_range(range, |p| p.parse_global_var_decl(name));
+        self.in_progress.remove(name);
+
+        let (gv_decl, initializer) = result?;
+        let gv = self.global_var_defs.define(&self.cx, gv_decl);
+        self.global_vars.insert(name, gv);
+        self.pending_global_var_initializers
+            .extend(initializer.map(|range| (gv, range)));
+        Ok(gv)
+    }
+
+    fn func_named(&self, name: &str, pos: Pos) -> io::Result<Func> {
+        self.funcs
+            .get(name)
+            .copied()
+            .ok_or_else(|| invalid(pos, &format!("`{name}` is not defined")))
+    }
+
+    fn parse_attrs(&mut self) -> io::Result<AttrSet> {
+        let mut attrs = BTreeSet::new();
+        loop {
+            if let Some(Token::CommentAttr(attr)) = self.peek() {
+                attrs.insert(attr.clone());
+                self.cursor += 1;
+            } else if self.eat_punct("#") {
+                if self.eat_punct("{") {
+                    self.comma_sep("}", |p| {
+                        attrs.insert(p.parse_attr()?);
+                        Ok(())
+                    })?;
+                } else {
+                    let pos = self.pos();
+                    let name = self.expect_any_word("`{` or attribute set name")?;
+                    match split_name_idx(name).map(|_| self.interned_named(name)) {
+                        Some(Ok(Interned::AttrSet(named))) => {
+                            attrs.extend(self.cx[named].attrs.iter().cloned());
+                        }
+                        Some(Err(e)) => return Err(e),
+                        _ => {
+                            return Err(invalid(
+                                pos,
+                                &format!("expected attribute set name, found `{name}`"),
+                            ));
+                        }
+                    }
+                }
+            } else {
+                break;
+            }
+        }
+        Ok(self.cx.intern(AttrSetDef { attrs }))
+    }
+
+    fn parse_attr(&mut self) -> io::Result<Attr> {
+        let pos = self.pos();
+        if self.eat_word("debug_scope") {
+            self.expect_punct("(")?;
+            let scope = self.parse_const()?;
+            let inlined_at = if self.eat_punct(",") && !self.is_punct(")") {
+                self.expect_word("inlined_at")?;
+                self.expect_punct(":")?;
+                Some(OrdAssertEq(self.parse_const()?))
+            } else {
+                None
+            };
+            self.eat_punct(",");
+            self.expect_punct(")")?;
+            return Ok(Attr::SpvShaderDebugScope {
+                scope: OrdAssertEq(scope),
+                inlined_at,
+            });
+        }
+        if self.eat_word("debug_line") {
+            self.expect_punct("(")?;
+            let source = self.parse_const()?;
+            let mut lines_and_cols = [0; 4];
+            for x in &mut lines_and_cols {
+                self.expect_punct(",")?;
+                *x = self.expect_u32()?;
+            }
+            self.eat_punct(",");
+            self.expect_punct(")")?;
+            let [line_start, line_end, col_start, col_end] = lines_and_cols;
+            return Ok(Attr::SpvShaderDebugLine {
+                source: OrdAssertEq(source),
+                line_start,
+                line_end,
+                col_start,
+                col_end,
+            });
+        }
+        if self.is_word("qptr") && self.is_punct_at(self.cursor + 1, ".") {
+            let wk = &spec::Spec::get().well_known;
+
+            self.cursor += 2;
+            let attr = if self.eat_word("from_spv_ptr") {
+                self.expect_punct("(")?;
+                let storage_class = self.parse_spv_single_imm_operand(wk.StorageClass)?;
+                self.expect_punct(",")?;
+                let pointee = self.parse_type()?;
+                QPtrAttr::FromSpvPtr {
+                    storage_class,
+                    pointee: OrdAssertEq(pointee),
+                }
+            } else if self.eat_word("to_spv_ptr_input") {
+                self.expect_punct("(")?;
+                let input_idx = self.expect_u32()?;
+                self.expect_punct(",")?;
+                let pointee = self.parse_type()?;
+                QPtrAttr::ToSpvPtrInput {
+                    input_idx,
+                    pointee: OrdAssertEq(pointee),
+                }
+            } else {
+                return Err(self.expected("`from_spv_ptr` or `to_spv_ptr_input`"));
+            };
+            self.eat_punct(",");
+            self.expect_punct(")")?;
+            return Ok(Attr::QPtr(attr));
+        }
+        if let Some(opcode) = self.try_parse_spv_opcode()? {
+            let imms = self.parse_spv_imms(opcode)?.finish(&self.cx, None)?;
+
+            if opcode == spec::Spec::get().well_known.OpExecutionModeId {
+                let mut consts = SmallVec::new();
+                self.expect_punct("(")?;
+                self.comma_sep(")", |p| {
+                    consts.push(OrdAssertEq(p.parse_const()?));
+                    Ok(())
+                })?;
+                return Ok(Attr::SpvExecutionModeId {
+                    inst: spv::Inst { opcode, imms },
+                    consts,
+                });
+            }
+
+            // NOTE(eddyb) the target ID of annotations is always implicit.
+            if self.is_punct("(") {
+                return Err(self.err("annotations cannot have explicit ID operands"));
+            }
+            return Ok(Attr::SpvAnnotation(spv::Inst { opcode, imms }));
+        }
+
+        // Any other `spv.` prefix should be a (bitflags) operand kind.
+        let kind = match (self.tok(self.cursor + 2), self.is_word("spv")) {
+            (Some(&Token::Word(kind_name)), true) => {
+                spec::Spec::get().operand_kinds.lookup(kind_name)
+            }
+            _ => None,
+        }
+        .ok_or_else(|| self.expected("attribute"))?;
+        let imms = self.parse_spv_imm_operand(kind)?.imms;
+        if imms.is_empty() {
+            return Err(invalid(pos, "bitflags operand attributes cannot be empty"));
+        }
+        Ok(Attr::SpvBitflagsOperand(imms.into_iter().collect()))
+    }
+
+    /// Peek at a `spv.OpFoo` opcode starting at `idx`, if there is one.
+    fn spv_opcode_at(&self, idx: usize) -> Option<spec::Opcode> {
+        match (self.tok(idx + 2), self.is_punct_at(idx + 3, ".")) {
+            (Some(&Token::Word(name)), false)
+                if self.is_word_at(idx, "spv")
+                    && self.is_punct_at(idx + 1, ".")
+                    && name.starts_with("Op") =>
+            {
+                spec::Spec::get().instructions.lookup(name)
+            }
+            _ => None,
+        }
+    }
+
+    fn try_parse_spv_opcode(&mut self) -> io::Result<Option<spec::Opcode>> {
+        match (
+            self.tok(self.cursor + 2),
+            self.is_punct_at(self.cursor + 3, "."),
+        ) {
+            (Some(&Token::Word(name)), false)
+                if self.is_word("spv")
+                    && self.is_punct_at(self.cursor + 1, ".")
+                    && name.starts_with("Op") =>
+            {
+                let opcode = spec::Spec::get()
+                    .instructions
+                    .lookup(name)
+                    .ok_or_else(|| self.err(&format!("unknown SPIR-V opcode `{name}`")))?;
+                self.cursor += 3;
+                Ok(Some(opcode))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Parse the `<...>` immediate operands of a SPIR-V instruction (which may be
+    /// missing entirely, if `opcode` has no non-ID operands that are required).
+    ///
+    /// ID operands (printed separately, i.e. `(...)`) are always left to the caller.
+    fn parse_spv_imms(&mut self, opcode: spec::Opcode) -> io::Result<PendingImms> {
+        let mut pending = PendingImms::default();
+
+        let in_angle_brackets = self.eat_punct("<");
+        let mut first = true;
+        for (mode, kind) in opcode.def().all_operands() {
+            let at_end = !in_angle_brackets
+                || self.is_punct(">")
+                || (self.is_punct(",") && self.is_punct_at(self.cursor + 1, ">"));
+            let is_id = matches!(kind.def(), spec::OperandKindDef::Id);
+            if at_end {
+                if mode == spec::OperandMode::Optional {
+                    break;
+                }
+                if is_id {
+                    continue;
+                }
+                return Err(self.expected(&format!("`{}` operand", kind.name())));
+            }
+            if is_id {
+                // NOTE(eddyb) this avoids an infinite loop, as optional ID
+                // operands are only ever followed by more ID operands.
+                if mode == spec::OperandMode::Optional {
+                    return Err(self.expected("`>`"));
+                }
+                continue;
+            }
+
+            if !first {
+                self.expect_punct(",")?;
+            }
+            first = false;
+
+            let operand = self.parse_spv_imm_operand(kind)?;
+            pending.append(operand);
+        }
+        if in_angle_brackets {
+            self.eat_punct(",");
+            self.expect_punct(">")?;
+        }
+
+        Ok(pending)
+    }
+
+    /// Parse a single SPIR-V (non-ID) operand of kind `kind`, potentially
+    /// composed of an enumerand with parameters (which produce more immediates).
+    fn parse_spv_imm_operand(&mut self, kind: spec::OperandKind) -> io::Result<PendingImms> {
+        let wk = &spec::Spec::get().well_known;
+
+        let mut pending = PendingImms::default();
+
+        let (name, def) = kind.name_and_def();
+        match def {
+            spec::OperandKindDef::BitEnum { empty_name, bits } => {
+                self.expect_spv_operand_kind_prefix(name)?;
+
+                let mut word = 0;
+                let mut params = PendingImms::default();
+                let mut parse_bit = |p: &mut Self| {
+                    let pos = p.pos();
+                    let bit_name = p.expect_any_word(&format!("`{name}` flag name"))?;
+                    if bit_name == *empty_name {
+                        return Ok(());
+                    }
+                    let bit_idx = bits.lookup(bit_name).ok_or_else(|| {
+                        invalid(pos, &format!("unknown `{name}` flag `{bit_name}`"))
+                    })?;
+                    word |= 1 << bit_idx.0;
+                    params.append(p.parse_spv_enumerant_params(&bits[bit_idx])?);
+                    Ok(())
+                };
+                if self.eat_punct("{") {
+                    self.comma_sep("}", parse_bit)?;
+                } else {
+                    parse_bit(self)?;
+                }
+
+                pending.imms.push(spv::Imm::Short(kind, word));
+                pending.append(params);
+            }
+            spec::OperandKindDef::ValueEnum { variants } => {
+                self.expect_spv_operand_kind_prefix(name)?;
+
+                let pos = self.pos();
+                // NOTE(eddyb) some enumerands start with a digit (e.g. `Dim`'s
+                // `1D`/`2D`/`3D`), and so get tokenized as numbers.
+                let variant_name = match self.peek() {
+                    Some(&Token::Number(variant_name)) => {
+                        self.cursor += 1;
+                        variant_name
+                    }
+                    _ => self.expect_any_word(&format!("`{name}` name"))?,
+                };
+                let variant = variants
+                    .lookup(variant_name)
+                    .ok_or_else(|| invalid(pos, &format!("unknown `{name}` `{variant_name}`")))?;
+
+                pending.imms.push(spv::Imm::Short(kind, variant.into()));
+                let params = self.parse_spv_enumerant_params(&variants[variant])?;
+                pending.append(params);
+            }
+            spec::OperandKindDef::Id => unreachable!(),
+            spec::OperandKindDef::Literal { size } => match size {
+                spec::LiteralSize::Word => {
+                    pending.imms.push(spv::Imm::Short(kind, self.expect_u32()?));
+                }
+                spec::LiteralSize::NulTerminated => {
+                    let pos = self.pos();
+                    let s = self.expect_str()?;
+                    if kind != wk.LiteralString || s.contains('\0') {
+                        return Err(invalid(pos, "unsupported string literal"));
+                    }
+                    pending.imms.extend(spv::encode_literal_string(&s));
+                }
+                spec::LiteralSize::FromContextualType => {
+                    let pos = self.pos();
+                    let number = self.expect_number()?;
+                    let value = parse_int_literal(number).ok_or_else(|| {
+                        invalid(pos, &format!("`{number}` is not a valid integer literal"))
+                    })?;
+
+                    // NOTE(eddyb) the placeholder is replaced by `finish`.
+                    pending
+                        .contextual_literals
+                        .push((pending.imms.len(), value, pos));
+                    pending.imms.push(spv::Imm::Short(kind, 0));
+                }
+            },
+        }
+
+        Ok(pending)
+    }
+
+    /// Parse a single SPIR-V operand that's always exactly one immediate.
+    fn parse_spv_single_imm_operand(&mut self, kind: spec::OperandKind) -> io::Result<u32> {
+        let pos = self.pos();
+        let operand = self.parse_spv_imm_operand(kind)?;
+        match operand.imms[..] {
+            [spv::Imm::Short(_, word)] if operand.contextual_literals.is_empty() => Ok(word),
+            _ => Err(invalid(pos, "expected a single immediate operand")),
+        }
+    }
+
+    fn expect_spv_operand_kind_prefix(&mut self, kind_name: &str) -> io::Result<()> {
+        if self.is_word("spv")
+            && self.is_punct_at(self.cursor + 1, ".")
+            && self.is_word_at(self.cursor + 2, kind_name)
+            && self.is_punct_at(self.cursor + 3, ".")
+        {
+            self.cursor += 4;
+            Ok(())
+        } else {
+            Err(self.expected(&format!("`spv.{kind_name}.`")))
+        }
+    }
+
+    fn parse_spv_enumerant_params(
+        &mut self,
+        enumerant: &spec::Enumerant,
+    ) -> io::Result<PendingImms> {
+        let mut pending = PendingImms::default();
+
+        let mut all_params = enumerant.all_params().peekable();
+        let first_mode = match all_params.peek() {
+            Some(&(mode, _)) => mode,
+            None => return Ok(pending),
+        };
+        if !self.eat_punct("(") {
+            if first_mode == spec::OperandMode::Optional {
+                return Ok(pending);
+            }
+            return Err(self.expected("`(`"));
+        }
+
+        let mut first = true;
+        for (mode, kind) in all_params {
+            if mode == spec::OperandMode::Optional && self.is_punct(")") {
+                break;
+            }
+            if !first {
+                self.expect_punct(",")?;
+            }
+            first = false;
+
+            // NOTE(eddyb) ID parameters are printed separately (in parens, with
+            // all other ID operands), leaving only a comment in their place.
+            if let spec::OperandKindDef::Id = kind.def() {
+                continue;
+            }
+            let param = self.parse_spv_imm_operand(kind)?;
+            pending.append(param);
+        }
+        self.expect_punct(")")?;
+
+        Ok(pending)
+    }
+
+    fn spv_type(&self, opcode: spec::Opcode, imms: &[spv::Imm], ctor_args: &[Type]) -> Type {
+        self.cx.intern(TypeDef {
+            attrs: AttrSet::default(),
+            ctor: TypeCtor::SpvInst(spv::Inst {
+                opcode,
+                imms: imms.iter().copied().collect(),
+            }),
+            ctor_args: ctor_args.iter().copied().map(TypeCtorArg::Type).collect(),
+        })
+    }
+
+    /// Get the type for e.g. `s32` (i.e. `kind` is `s`, and `width` is `32`).
+    fn compact_scalar_type(&self, kind: char, width: u32) -> Type {
+        let wk = &spec::Spec::get().well_known;
+
+        let width_imm = spv::Imm::Short(wk.LiteralInteger, width);
+        match kind {
+            's' | 'u' => self.spv_type(
+                wk.OpTypeInt,
+                &[
+                    width_imm,
+                    spv::Imm::Short(wk.LiteralInteger, (kind == 's') as u32),
+                ],
+                &[],
+            ),
+            'f' => self.spv_type(wk.OpTypeFloat, &[width_imm], &[]),
+            _ => unreachable!(),
+        }
+    }
+
+    fn parse_type(&mut self) -> io::Result<Type> {
+        let attrs = self.parse_attrs()?;
+        self.parse_type_with_attrs(attrs)
+    }
+
+    fn parse_type_with_attrs(&mut self, attrs: AttrSet) -> io::Result<Type> {
+        let wk = &spec::Spec::get().well_known;
+
+        let pos = self.pos();
+        let mut ty = if let Some(opcode) = self.try_parse_spv_opcode()? {
+            let imms = self.parse_spv_imms(opcode)?.finish(&self.cx, None)?;
+            let mut ctor_args = SmallVec::new();
+            if self.eat_punct("(") {
+                self.comma_sep(")", |p| {
+                    ctor_args.push(if p.is_type_at(p.cursor) {
+                        TypeCtorArg::Type(p.parse_type()?)
+                    } else {
+                        TypeCtorArg::Const(p.parse_const()?)
+                    });
+                    Ok(())
+                })?;
+            }
+            self.cx.intern(TypeDef {
+                attrs: AttrSet::default(),
+                ctor: TypeCtor::SpvInst(spv::Inst { opcode, imms }),
+                ctor_args,
+            })
+        } else if self.eat_punct("[") {
+            let elem_type = TypeCtorArg::Type(self.parse_type()?);
+            let (ctor, ctor_args) = if self.eat_punct(";") {
+                let len = TypeCtorArg::Const(self.parse_const()?);
+                (TypeCtor::Array, [elem_type, len].into_iter().collect())
+            } else {
+                (TypeCtor::RuntimeArray, [elem_type].into_iter().collect())
+            };
+            self.expect_punct("]")?;
+            self.cx.intern(TypeDef {
+                attrs: AttrSet::default(),
+                ctor,
+                ctor_args,
+            })
+        } else {
+            let name = self.expect_any_word("type")?;
+            if name == "bool" {
+                self.spv_type(wk.OpTypeBool, &[], &[])
+            } else if name == "struct" {
+                let mut members = SmallVec::new();
+                let mut ctor_args = SmallVec::new();
+                self.expect_punct("{")?;
+                self.comma_sep("}", |p| {
+                    let attrs = p.parse_attrs()?;
+                    let offset = if matches!(p.peek(), Some(Token::Number(_)))
+                        && p.is_punct_at(p.cursor + 1, ":")
+                    {
+                        let offset = p.expect_u32()?;
+                        p.expect_punct(":")?;
+                        Some(offset)
+                    } else {
+                        None
+                    };
+                    members.push(StructMember { attrs, offset });
+                    ctor_args.push(TypeCtorArg::Type(p.parse_type()?));
+                    Ok(())
+                })?;
+                self.cx.intern(TypeDef {
+                    attrs: AttrSet::default(),
+                    ctor: TypeCtor::Struct { members },
+                    ctor_args,
+                })
+            } else if name == "image" {
+                self.expect_punct("(")?;
+                let sampled_type = self.parse_type()?;
+                let field = |p: &mut Self, name: &str| {
+                    p.expect_punct(",")?;
+                    if !p.is_word(name) {
+                        return Err(p.expected(&format!("`{name}`")));
+                    }
+                    p.cursor += 1;
+                    p.expect_punct(":")
+                };
+                let parse_bool = |p: &mut Self, name: &str, allow_unknown: bool| {
+                    field(p, name)?;
+                    match p.expect_any_word("`true` or `false`")? {
+                        "false" => Ok(Some(false)),
+                        "true" => Ok(Some(true)),
+                        "unknown" if allow_unknown => Ok(None),
+                        _ => Err(invalid(p.pos(), &format!("invalid `{name}` value"))),
+                    }
+                };
+                let parse_enumerand = |p: &mut Self, name: &str, kind: spec::OperandKind| {
+                    field(p, name)?;
+                    match p.parse_spv_imm_operand(kind)?.imms[..] {
+                        [spv::Imm::Short(_, word)] => Ok(word),
+                        _ => Err(invalid(p.pos(), &format!("invalid `{name}` value"))),
+                    }
+                };
+                let dim = parse_enumerand(self, "dim", wk.Dim)?;
+                let depth = parse_bool(self, "depth", true)?;
+                let arrayed = parse_bool(self, "arrayed", false)?.unwrap();
+                let multisampled = parse_bool(self, "multisampled", false)?.unwrap();
+                let sampled = parse_bool(self, "sampled", true)?;
+                let format = parse_enumerand(self, "format", wk.ImageFormat)?;
+                let access_qualifier =
+                    if self.is_punct(",") && self.is_word_at(self.cursor + 1, "access") {
+                        Some(parse_enumerand(self, "access", wk.AccessQualifier)?)
+                    } else {
+                        None
+                    };
+                // NOTE(eddyb) fields printed on separate lines end in a comma.
+                self.eat_punct(",");
+                self.expect_punct(")")?;
+                self.cx.intern(TypeDef {
+                    attrs: AttrSet::default(),
+                    ctor: TypeCtor::Image(ImageType {
+                        dim,
+                        depth,
+                        arrayed,
+                        multisampled,
+                        sampled,
+                        format,
+                        access_qualifier,
+                    }),
+                    ctor_args: [TypeCtorArg::Type(sampled_type)].into_iter().collect(),
+                })
+            } else if name == "recursive" {
+                let pos = self.pos();
+                let ptr_ty = self.parse_type()?;
+                let ptr_ty_def = &self.cx[ptr_ty];
+                let storage_class = match (&ptr_ty_def.ctor, &ptr_ty_def.ctor_args[..]) {
+                    (TypeCtor::SpvInst(inst), [TypeCtorArg::Type(_)])
+                        if inst.opcode == wk.OpTypePointer
+                            && ptr_ty_def.attrs == AttrSet::default() =>
+                    {
+                        match inst.imms[..] {
+                            [spv::Imm::Short(_, storage_class)] => storage_class,
+                            _ => unreachable!(),
+                        }
+                    }
+                    _ => {
+                        return Err(invalid(
+                            pos,
+                            "expected `spv.OpTypePointer` after `recursive`",
+                        ));
+                    }
+                };
+                self.cx.intern(TypeDef {
+                    attrs: AttrSet::default(),
+                    ctor: TypeCtor::RecursivePtr { storage_class },
+                    ctor_args: ptr_ty_def.ctor_args.clone(),
+                })
+            } else if name == "recursive_self" {
+                let depth = if self.eat_punct("(") {
+                    let depth = self.expect_u32()?;
+                    self.expect_punct(")")?;
+                    depth
+                } else {
+                    0
+                };
+                self.cx.intern(TypeDef {
+                    attrs: AttrSet::default(),
+                    ctor: TypeCtor::RecursivePtrSelf { depth },
+                    ctor_args: SmallVec::new(),
+                })
+            } else if name == "qptr" {
+                self.cx.intern(TypeDef {
+                    attrs: AttrSet::default(),
+                    ctor: TypeCtor::QPtr,
+                    ctor_args: SmallVec::new(),
+                })
+            } else if name == "sampler" {
+                self.cx.intern(TypeDef {
+                    attrs: AttrSet::default(),
+                    ctor: TypeCtor::Sampler,
+                    ctor_args: SmallVec::new(),
+                })
+            } else if name == "sampled_image" {
+                self.expect_punct("(")?;
+                let image_type = self.parse_type()?;
+                self.expect_punct(")")?;
+                self.cx.intern(TypeDef {
+                    attrs: AttrSet::default(),
+                    ctor: TypeCtor::SampledImage,
+                    ctor_args: [TypeCtorArg::Type(image_type)].into_iter().collect(),
+                })
+            } else if name == "type_of" {
+                self.expect_punct("(")?;
+                if self.try_parse_spv_opcode()? != Some(wk.OpString) {
+                    return Err(self.expected("`spv.OpString`"));
+                }
+                self.expect_punct(")")?;
+                self.cx.intern(TypeDef {
+                    attrs: AttrSet::default(),
+                    ctor: TypeCtor::SpvStringLiteralForExtInst,
+                    ctor_args: SmallVec::new(),
+                })
+            } else if let Some((kind, width)) = compact_scalar_type_name(name) {
+                self.compact_scalar_type(kind, width)
+            } else if let Some(("type", _)) = split_name_idx(name) {
+                if attrs != AttrSet::default() {
+                    return Err(invalid(pos, "cannot add attributes to a named type"));
+                }
+                match self.interned_named(name)? {
+                    Interned::Type(ty) => return Ok(ty),
+                    _ => unreachable!(),
+                }
+            } else {
+                return Err(invalid(pos, &format!("expected type, found `{name}`")));
+            }
+        };
+
+        // NOTE(eddyb) `T×N` is a vector, unless `T` is itself a vector, in which
+        // case it's a matrix (with `N` columns, each of them of type `T`).
+        while self.eat_punct("×") {
+            let count = self.expect_u32()?;
+            let is_vector = matches!(
+                &self.cx[ty].ctor,
+                TypeCtor::SpvInst(inst) if inst.opcode == wk.OpTypeVector
+            );
+            ty = if is_vector {
+                self.cx.intern(TypeDef {
+                    attrs: AttrSet::default(),
+                    ctor: TypeCtor::Matrix {
+                        column_count: count,
+                    },
+                    ctor_args: [TypeCtorArg::Type(ty)].into_iter().collect(),
+                })
+            } else {
+                self.spv_type(
+                    wk.OpTypeVector,
+                    &[spv::Imm::Short(wk.LiteralInteger, count)],
+                    &[ty],
+                )
+            };
+        }
+
+        if attrs != AttrSet::default() {
+            let ty_def = &self.cx[ty];
+            ty = self.cx.intern(TypeDef {
+                attrs,
+                ctor: ty_def.ctor.clone(),
+                ctor_args: ty_def.ctor_args.clone(),
+            });
+        }
+
+        Ok(ty)
+    }
+
+    /// Check whether the tokens starting at `idx` look like a type (as opposed
+    /// to a constant), which is needed to parse [`TypeCtorArg`]s.
+    fn is_type_at(&self, mut idx: usize) -> bool {
+        // Skip past any attributes, first.
+        loop {
+            match self.tok(idx) {
+                Some(Token::CommentAttr(_)) => idx += 1,
+                Some(Token::Punct("#")) => {
+                    idx += 1;
+                    if self.is_punct_at(idx, "{") {
+                        let mut depth = 0;
+                        while let Some(token) = self.tok(idx) {
+                            idx += 1;
+                            match token {
+                                Token::Punct("{") => depth += 1,
+                                Token::Punct("}") => depth -= 1,
+                                _ => {}
+                            }
+                            if depth == 0 {
+                                break;
+                            }
+                        }
+                    } else {
+                        idx += 1;
+                    }
+                }
+                _ => break,
+            }
+        }
+
+        match self.tok(idx) {
+            Some(Token::Punct("[")) => true,
+            Some(&Token::Word(word)) => {
+                [
+                    "bool",
+                    "type_of",
+                    "struct",
+                    "recursive",
+                    "recursive_self",
+                    "qptr",
+                    "image",
+                    "sampler",
+                    "sampled_image",
+                ]
+                .contains(&word)
+                    || compact_scalar_type_name(word).is_some()
+                    || matches!(split_name_idx(word), Some(("type", _)))
+                    || matches!(
+                        self.spv_opcode_at(idx).map(|opcode| opcode.def().category),
+                        Some(spec::InstructionCategory::Type)
+                    )
+            }
+            _ => false,
+        }
+    }
+
+    fn parse_const(&mut self) -> io::Result<Const> {
+        let attrs = self.parse_attrs()?;
+        self.parse_const_with_attrs(attrs)
+    }
+
+    /// Parse the "header" of a SPIR-V `OpExtInst`, i.e. its extended instruction
+    /// set and instruction number (see also `Printer::pretty_spv_ext_inst_header`).
+    fn parse_spv_ext_inst_header(&mut self) -> io::Result<(InternedStr, u32)> {
+        let wk = &spec::Spec::get().well_known;
+
+        self.expect_punct("(")?;
+        if self.try_parse_spv_opcode()? != Some(wk.OpExtInstImport) {
+            return Err(self.expected("`spv.OpExtInstImport`"));
+        }
+        self.expect_punct("<")?;
+        let ext_set = self.expect_str()?;
+        let ext_set = self.cx.intern(ext_set);
+        self.expect_punct(">")?;
+        self.expect_punct(")")?;
+        self.expect_punct(".")?;
+        if self.try_parse_spv_opcode()? != Some(wk.OpExtInst) {
+            return Err(self.expected("`spv.OpExtInst`"));
+        }
+        self.expect_punct("<")?;
+        let inst = self.expect_u32()?;
+        self.expect_punct(">")?;
+        Ok((ext_set, inst))
+    }
+
+    fn parse_const_with_attrs(&mut self, attrs: AttrSet) -> io::Result<Const> {
+        let wk = &spec::Spec::get().well_known;
+
+        let pos = self.pos();
+        let (ty, ctor, ctor_args) = match self.peek() {
+            Some(Token::Punct("&")) => {
+                self.cursor += 1;
+                let pos = self.pos();
+                let name = self.expect_any_word("global variable name")?;
+                if !matches!(split_name_idx(name), Some(("global_var", _))) {
+                    return Err(invalid(
+                        pos,
+                        &format!("expected global variable name, found `{name}`"),
+                    ));
+                }
+                let gv = self.global_var_named(name)?;
+                (
+                    self.global_var_defs[gv].type_of_ptr_to,
+                    ConstCtor::PtrToGlobalVar(gv),
+                    SmallVec::new(),
+                )
+            }
+            Some(Token::Word("undef")) => {
+                self.cursor += 1;
+                self.expect_punct(":")?;
+                (self.parse_type()?, ConstCtor::Undef, SmallVec::new())
+            }
+            Some(Token::Word("type_operand")) => {
+                self.cursor += 1;
+                self.expect_punct(":")?;
+                (
+                    self.parse_type()?,
+                    ConstCtor::SpvTypeOperand,
+                    SmallVec::new(),
+                )
+            }
+            Some(Token::Punct("(")) => {
+                let (ext_set, inst) = self.parse_spv_ext_inst_header()?;
+                let mut ctor_args = SmallVec::new();
+                self.expect_punct("(")?;
+                self.comma_sep(")", |p| {
+                    ctor_args.push(p.parse_const()?);
+                    Ok(())
+                })?;
+                self.expect_punct(":")?;
+                (
+                    self.parse_type()?,
+                    ConstCtor::SpvExtInst { ext_set, inst },
+                    ctor_args,
+                )
+            }
+            Some(Token::Word("spec")) => {
+                self.cursor += 1;
+                self.expect_punct("(")?;
+                let spec_id = self.expect_u32()?;
+                self.expect_punct(")")?;
+                self.expect_punct("=")?;
+
+                let pos = self.pos();
+                let default = self.parse_const_with_attrs(AttrSet::default())?;
+                let default_def = &self.cx[default];
+                match &default_def.ctor {
+                    ConstCtor::SpvInst(default)
+                        if [wk.OpConstantTrue, wk.OpConstantFalse, wk.OpConstant]
+                            .contains(&default.opcode)
+                            && default_def.ctor_args.is_empty() =>
+                    {
+                        (
+                            default_def.ty,
+                            ConstCtor::SpecConst {
+                                spec_id,
+                                default: default.clone(),
+                            },
+                            SmallVec::new(),
+                        )
+                    }
+                    _ => {
+                        return Err(invalid(
+                            pos,
+                            "expected scalar constant as the default of a spec constant",
+                        ));
+                    }
+                }
+            }
+            Some(Token::Word("spec_op")) => {
+                self.cursor += 1;
+
+                // NOTE(eddyb) the operation is parsed as if it were a regular
+                // `ConstCtor::SpvInst` constant, then its `ConstCtor` replaced.
+                let pos = self.pos();
+                let op = self.parse_const_with_attrs(AttrSet::default())?;
+                let op_def = &self.cx[op];
+                match &op_def.ctor {
+                    ConstCtor::SpvInst(inst)
+                        if inst.opcode.def().category != spec::InstructionCategory::Const =>
+                    {
+                        (
+                            op_def.ty,
+                            ConstCtor::SpecConstOp(inst.clone()),
+                            op_def.ctor_args.clone(),
+                        )
+                    }
+                    _ => {
+                        return Err(invalid(
+                            pos,
+                            "expected SPIR-V instruction as the operation of a spec constant",
+                        ));
+                    }
+                }
+            }
+            Some(&Token::Word(word @ ("true" | "false"))) => {
+                self.cursor += 1;
+                (
+                    self.spv_type(wk.OpTypeBool, &[], &[]),
+                    ConstCtor::SpvInst(
+                        if word == "true" {
+                            wk.OpConstantTrue
+                        } else {
+                            wk.OpConstantFalse
+                        }
+                        .into(),
+                    ),
+                    SmallVec::new(),
+                )
+            }
+            Some(&Token::Number(literal)) => {
+                self.cursor += 1;
+                let (ty, ctor) = self.compact_literal_const(literal, pos)?;
+                (ty, ctor, SmallVec::new())
+            }
+            // HACK(eddyb) `inf`/`NaN` float literals lex as words.
+            Some(&Token::Word(literal))
+                if literal.starts_with("inf") || literal.starts_with("NaN") =>
+            {
+                self.cursor += 1;
+                let (ty, ctor) = self.compact_literal_const(literal, pos)?;
+                (ty, ctor, SmallVec::new())
+            }
+            Some(&Token::Word(name)) if matches!(split_name_idx(name), Some(("const", _))) => {
+                self.cursor += 1;
+                if attrs != AttrSet::default() {
+                    return Err(invalid(pos, "cannot add attributes to a named constant"));
+                }
+                match self.interned_named(name)? {
+                    Interned::Const(ct) => return Ok(ct),
+                    _ => unreachable!(),
+                }
+            }
+            _ => match self.try_parse_spv_opcode()? {
+                Some(opcode) if opcode == wk.OpString => {
+                    self.expect_punct("<")?;
+                    let s = self.expect_str()?;
+                    self.expect_punct(">")?;
+                    (
+                        self.cx.intern(TypeDef {
+                            attrs: AttrSet::default(),
+                            ctor: TypeCtor::SpvStringLiteralForExtInst,
+                            ctor_args: SmallVec::new(),
+                        }),
+                        ConstCtor::SpvStringLiteralForExtInst(self.cx.intern(s)),
+                        SmallVec::new(),
+                    )
+                }
+                Some(opcode) => {
+                    let imms = self.parse_spv_imms(opcode)?;
+                    let mut ctor_args = SmallVec::new();
+                    if self.eat_punct("(") {
+                        self.comma_sep(")", |p| {
+                            if p.is_punct("…") {
+                                return Err(p.err(
+                                    "constant aggregate has elided elements \
+                                     (see `PrintOptions::max_const_aggregate_elements`)",
+                                ));
+                            }
+                            ctor_args.push(p.parse_const()?);
+                            Ok(())
+                        })?;
+                    }
+                    self.expect_punct(":")?;
+                    let ty = self.parse_type()?;
+                    let imms = imms.finish(&self.cx, Some(ty))?;
+                    (
+                        ty,
+                        ConstCtor::SpvInst(spv::Inst { opcode, imms }),
+                        ctor_args,
+                    )
+                }
+                None => return Err(self.expected("constant")),
+            },
+        };
+
+        Ok(self.cx.intern(ConstDef {
+            attrs,
+            ty,
+            ctor,
+            ctor_args,
+        }))
+    }
+
+    /// Parse a "compact" literal constant (e.g. `123u32`, `-1s8` or `1.5f32`).
+    fn compact_literal_const(&self, literal: &str, pos: Pos) -> io::Result<(Type, ConstCtor)> {
+        let wk = &spec::Spec::get().well_known;
+
+        let (value, kind, width) = literal
+            .rfind(['s', 'u', 'f'])
+            .and_then(|i| {
+                let (kind, width) = compact_scalar_type_name(&literal[i..])?;
+                Some((&literal[..i], kind, width))
+            })
+            .filter(|&(_, _, width)| (1..=64).contains(&width))
+            .ok_or_else(|| {
+                invalid(
+                    pos,
+                    &format!("`{literal}` is missing a type suffix (e.g. `u32`)"),
+                )
+            })?;
+
+        let out_of_range = || invalid(pos, &format!("`{literal}` is out of range"));
+        let mask = u64::MAX >> (64 - width);
+        let bits = match kind {
+            'u' => value
+                .parse::<u64>()
+                .ok()
+                .filter(|&x| x & !mask == 0)
+                .ok_or_else(out_of_range)?,
+            's' => {
+                value
+                    .parse::<i64>()
+                    .ok()
+                    .filter(|&x| {
+                        let (min, max) = (-1_i64 << (width - 1), mask as i64 >> 1);
+                        (min..=max).contains(&x)
+                    })
+                    .ok_or_else(out_of_range)? as u64
+                    & mask
+            }
+            'f' => match width {
+                32 => value.parse::<f32>().ok().map(|x| x.to_bits().into()),
+                64 => value.parse::<f64>().ok().map(f64::to_bits),
+                _ => None,
+            }
+            .ok_or_else(|| invalid(pos, &format!("`{literal}` is not a valid float literal")))?,
+            _ => unreachable!(),
+        };
+
+        let imm_kind = wk.LiteralContextDependentNumber;
+        let imms = if width <= 32 {
+            [spv::Imm::Short(imm_kind, bits as u32)]
+                .into_iter()
+                .collect()
+        } else {
+            [
+                spv::Imm::LongStart(imm_kind, bits as u32),
+                spv::Imm::LongCont(imm_kind, (bits >> 32) as u32),
+            ]
+            .into_iter()
+            .collect()
+        };
+        Ok((
+            self.compact_scalar_type(kind, width),
+            ConstCtor::SpvInst(spv::Inst {
+                opcode: wk.OpConstant,
+                imms,
+            }),
+        ))
+    }
+
+    fn parse_global_var_decl(
+        &mut self,
+        name: &str,
+    ) -> io::Result<(GlobalVarDecl, Option<Range<usize>>)> {
+        let wk = &spec::Spec::get().well_known;
+
+        let attrs = self.parse_def_name(name)?;
+        self.expect_word("in")?;
+        let storage_class = self.parse_spv_single_imm_operand(wk.StorageClass)?;
+        self.expect_punct(":")?;
+
+        // HACK(eddyb) printing only shows the pointee type, when possible.
+        let type_of_ptr_to = if self.eat_word("pointee_type_of") {
+            self.expect_punct("(")?;
+            let type_of_ptr_to = self.parse_type()?;
+            self.expect_punct(")")?;
+            type_of_ptr_to
+        } else {
+            let pointee_type = self.parse_type()?;
+            self.spv_type(
+                wk.OpTypePointer,
+                &[spv::Imm::Short(wk.StorageClass, storage_class)],
+                &[pointee_type],
+            )
+        };
+
+        let mut initializer = None;
+        let def = if self.eat_punct("=") {
+            DeclDef::Imported(self.parse_import()?)
+        } else {
+            if self.eat_word("init") {
+                self.expect_punct("=")?;
+
+                // NOTE(eddyb) the initializer is parsed later (see `parse_module`).
+                initializer = Some(self.cursor..self.end);
+                self.cursor = self.end;
+            }
+            DeclDef::Present(GlobalVarDefBody { initializer: None })
+        };
+
+        Ok((
+            GlobalVarDecl {
+                attrs,
+                type_of_ptr_to,
+                addr_space: AddrSpace::from_spv_storage_class(storage_class),
+                def,
+            },
+            initializer,
+        ))
+    }
+
+    /// Parse a function declaration, returning it alongside the token range of
+    /// its body (if it has one) and names of its parameters (for the body to use).
+    #[allow(clippy::type_complexity)]
+    fn parse_func_decl(
+        &mut self,
+        name: &str,
+    ) -> io::Result<(FuncDecl, Option<(Range<usize>, Vec<Option<&'a str>>)>)> {
+        let attrs = self.parse_def_name(name)?;
+
+        let mut params = SmallVec::<[_; 2]>::new();
+        let mut param_names = vec![];
+        self.expect_punct("(")?;
+        self.comma_sep(")", |p| {
+            let attrs = p.parse_attrs()?;
+            param_names.push(p.parse_value_def_name()?.0);
+            p.expect_punct(":")?;
+            let ty = p.parse_type()?;
+            params.push(FuncParam { attrs, ty });
+            Ok(())
+        })?;
+        self.expect_punct("->")?;
+        let ret_type = self.parse_type()?;
+
+        let (def, body) = if self.eat_punct("=") {
+            (DeclDef::Imported(self.parse_import()?), None)
+        } else {
+            if !self.is_punct("{") {
+                return Err(self.expected("`{` or `=`"));
+            }
+
+            // NOTE(eddyb) the body is parsed later (see `parse_module`).
+            let body_range = self.cursor..self.end;
+            self.cursor = self.end;
+
+            let mut control_regions = EntityDefs::default();
+            let body = control_regions.define(
+                &self.cx,
+                ControlRegionDef {
+                    inputs: params
+                        .iter()
+                        .map(|&FuncParam { attrs, ty }| ControlRegionInputDecl { attrs, ty })
+                        .collect(),
+                    children: EntityList::empty(),
+                    outputs: SmallVec::new(),
+                },
+            );
+            (
+                DeclDef::Present(FuncDefBody {
+                    control_regions,
+                    control_nodes: Default::default(),
+                    data_insts: Default::default(),
+                    body,
+                    unstructured_cfg: None,
+                }),
+                Some((body_range, param_names)),
+            )
+        };
+
+        Ok((
+            FuncDecl {
+                attrs,
+                ret_type,
+                params,
+                def,
+            },
+            body,
+        ))
+    }
+
+    /// Parse the name of a value definition, returning `None` for `_`.
+    fn parse_value_def_name(&mut self) -> io::Result<ValueDefName<'a>> {
+        let pos = self.pos();
+        match self.peek() {
+            Some(&Token::Word(name)) if is_value_def_name(name) => {
+                self.cursor += 1;
+                Ok((Some(name).filter(|&name| name != "_"), pos))
+            }
+            _ => Err(self.expected("value name (e.g. `v0` or `_`)")),
+        }
+    }
+
+    fn parse_value(&mut self, func: &FuncBodyState<'a, '_>) -> io::Result<Value> {
+        Ok(self.parse_value_and_type(func)?.0)
+    }
+
+    fn parse_value_and_type(&mut self, func: &FuncBodyState<'a, '_>) -> io::Result<(Value, Type)> {
+        if let Some(&Token::Word(name)) = self.peek() {
+            if let Some(("v", _)) = split_name_idx(name) {
+                let pos = self.pos();
+                self.cursor += 1;
+                return func
+                    .values
+                    .get(name)
+                    .copied()
+                    .ok_or_else(|| invalid(pos, &format!("`{name}` is not defined")));
+            }
+        }
+        let ct = self.parse_const()?;
+        Ok((Value::Const(ct), self.cx[ct].ty))
+    }
+
+    fn label_region(
+        &self,
+        func: &mut FuncBodyState<'a, '_>,
+        name: &'a str,
+        pos: Pos,
+    ) -> io::Result<ControlRegion> {
+        if !matches!(split_name_idx(name), Some(("label", _))) {
+            return Err(invalid(pos, &format!("expected label, found `{name}`")));
+        }
+        let func_def_body = &mut *func.func_def_body;
+        Ok(func
+            .labels
+            .entry(name)
+            .or_insert_with(|| {
+                let region = func_def_body.control_regions.define(
+                    &self.cx,
+                    ControlRegionDef {
+                        inputs: SmallVec::new(),
+                        children: EntityList::empty(),
+                        outputs: SmallVec::new(),
+                    },
+                );
+                (region, pos, false)
+            })
+            .0)
+    }
+
+    fn is_label_header_at(&self, idx: usize) -> bool {
+        matches!(
+            self.tok(idx),
+            Some(&Token::Word(name)) if matches!(split_name_idx(name), Some(("label", _)))
+        ) && (self.is_punct_at(idx + 1, "(") || self.is_punct_at(idx + 1, ":"))
+    }
+
+    /// Check whether the end of a (structured) region has been reached, i.e.
+    /// what follows is either the end of the function, or another label.
+    fn at_region_end(&self) -> bool {
+        self.peek().is_none() || self.is_punct("}") || self.is_label_header_at(self.cursor)
+    }
+
+    /// Find the first `{` at the same depth as `idx` (ignoring e.g. `{` in `<...>`).
+    fn find_open_brace_from(&self, mut idx: usize) -> Option<usize> {
+        let mut depth = 0_usize;
+        while let Some(token) = self.tok(idx) {
+            match token {
+                Token::Punct("{") if depth == 0 => return Some(idx),
+                Token::Punct("(" | "[" | "<" | "{") => depth += 1,
+                Token::Punct(")" | "]" | ">" | "}") => depth = depth.checked_sub(1)?,
+                _ => {}
+            }
+            idx += 1;
+        }
+        None
+    }
+
+    /// Skip past a bracketed group starting at `idx` (if there is one there).
+    fn skip_brackets_at(&self, idx: usize, open: &str, close: &str) -> usize {
+        if !self.is_punct_at(idx, open) {
+            return idx;
+        }
+        let mut depth = 0_usize;
+        let mut idx = idx;
+        while let Some(token) = self.tok(idx) {
+            idx += 1;
+            match *token {
+                Token::Punct(p) if p == open => depth += 1,
+                Token::Punct(p) if p == close => {
+                    depth -= 1;
+                    if depth == 0 {
+                        break;
+                    }
+                }
+                _ => {}
+            }
+        }
+        idx
+    }
+
+    /// Check whether a control-flow instruction (i.e. a terminator of a region
+    /// in an unstructured CFG, see [`cfg::ControlInst`]) starts at the cursor.
+    fn at_control_inst(&self) -> bool {
+        let wk = &spec::Spec::get().well_known;
+
+        let branches_inside_braces_at =
+            |idx| self.is_punct_at(idx, "{") && self.is_word_at(idx + 1, "branch");
+        match self.peek() {
+            Some(Token::Word("branch" | "return" | "unreachable")) => true,
+            Some(Token::Word("mesh")) => self.is_punct_at(self.cursor + 1, "."),
+            Some(Token::Word("if")) => matches!(
+                self.find_open_brace_from(self.cursor + 1),
+                Some(idx) if branches_inside_braces_at(idx)
+            ),
+            _ => match self.spv_opcode_at(self.cursor) {
+                Some(opcode) if opcode.def().category == spec::InstructionCategory::ControlFlow => {
+                    let after_imms = self.skip_brackets_at(self.cursor + 3, "<", ">");
+                    let after_inputs = self.skip_brackets_at(after_imms, "(", ")");
+                    if self.is_punct_at(after_inputs, "{") {
+                        self.is_word_at(after_inputs + 1, "case")
+                            && self.is_punct_at(after_inputs + 2, "=>")
+                            && branches_inside_braces_at(after_inputs + 3)
+                    } else {
+                        // FIXME(eddyb) this is ambiguous with control-flow
+                        // instructions that aren't terminators (and so they
+                        // can appear as `DataInst`s), if they're last in a
+                        // region, but only `OpLifetime{Start,Stop}` exist.
+                        ![wk.OpPhi, wk.OpSelectionMerge, wk.OpLoopMerge, wk.OpLabel]
+                            .contains(&opcode)
+                            && {
+                                let (cursor, end) = (after_inputs, self.end);
+                                cursor >= end
+                                    || self.is_punct_at(cursor, "}")
+                                    || self.is_label_header_at(cursor)
+                            }
+                    }
+                }
+                _ => false,
+            },
+        }
+    }
+
+    fn parse_func_body(
+        &mut self,
+        func_def_body: &mut FuncDefBody,
+        param_names: &[Option<&'a str>],
+    ) -> io::Result<()> {
+        let body = func_def_body.body;
+        let mut func = FuncBodyState {
+            func_def_body,
+            values: FxHashMap::default(),
+            labels: FxIndexMap::default(),
+        };
+
+        let body_pos = self.pos();
+        self.expect_punct("{")?;
+        for (i, &name) in param_names.iter().enumerate() {
+            let input_idx = i.try_into().unwrap();
+            let ty = func.func_def_body.control_regions[body].inputs[i].ty;
+            func.define_value(
+                (name, body_pos),
+                Value::ControlRegionInput {
+                    region: body,
+                    input_idx,
+                },
+                ty,
+            )?;
+        }
+
+        // NOTE(eddyb) the body only has a label if it's targeted by branches,
+        // but its inputs are the function parameters (i.e. already defined).
+        if self.is_label_header_at(self.cursor) {
+            let pos = self.pos();
+            let name = self.expect_any_word("label")?;
+            func.labels.insert(name, (body, pos, true));
+            if self.eat_punct("(") {
+                self.comma_sep(")", |p| {
+                    p.parse_attrs()?;
+                    p.parse_value_def_name()?;
+                    p.expect_punct(":")?;
+                    p.parse_type()?;
+                    Ok(())
+                })?;
+            }
+            self.expect_punct(":")?;
+        }
+
+        self.parse_region_children(&mut func, body)?;
+
+        if self.at_control_inst() {
+            func.func_def_body.unstructured_cfg = Some(cfg::ControlFlowGraph::default());
+
+            let mut region = body;
+            loop {
+                let control_inst = self.parse_control_inst(&mut func)?;
+                func.func_def_body
+                    .unstructured_cfg
+                    .as_mut()
+                    .unwrap()
+                    .control_inst_on_exit_from
+                    .insert(region, control_inst);
+
+                if self.is_punct("}") {
+                    break;
+                }
+
+                region = self.parse_label_header(&mut func)?;
+                self.parse_region_children(&mut func, region)?;
+                if !self.at_control_inst() {
+                    return Err(self.expected("control-flow instruction (e.g. `branch`)"));
+                }
+            }
+
+            if let Some((name, &(_, pos, _))) =
+                func.labels.iter().find(|(_, &(_, _, defined))| !defined)
+            {
+                return Err(invalid(pos, &format!("`{name}` is not defined")));
+            }
+        }
+
+        self.expect_punct("}")
+    }
+
+    fn parse_label_header(
+        &mut self,
+        func: &mut FuncBodyState<'a, '_>,
+    ) -> io::Result<ControlRegion> {
+        let pos = self.pos();
+        let name = self.expect_any_word("label")?;
+        let region = self.label_region(func, name, pos)?;
+        let defined = &mut func.labels[name].2;
+        if *defined {
+            return Err(invalid(pos, &format!("`{name}` defined more than once")));
+        }
+        *defined = true;
+
+        if self.eat_punct("(") {
+            self.comma_sep(")", |p| {
+                let attrs = p.parse_attrs()?;
+                let name = p.parse_value_def_name()?;
+                p.expect_punct(":")?;
+                let ty = p.parse_type()?;
+
+                let inputs = &mut func.func_def_body.control_regions[region].inputs;
+                let input_idx = inputs.len().try_into().unwrap();
+                inputs.push(ControlRegionInputDecl { attrs, ty });
+                func.define_value(name, Value::ControlRegionInput { region, input_idx }, ty)
+            })?;
+        }
+        self.expect_punct(":")?;
+
+        Ok(region)
+    }
+
+    fn parse_control_inst(
+        &mut self,
+        func: &mut FuncBodyState<'a, '_>,
+    ) -> io::Result<cfg::ControlInst> {
+        let attrs = self.parse_attrs()?;
+
+        let mut inputs = SmallVec::new();
+        let mut targets = SmallVec::new();
+        let mut target_inputs = FxIndexMap::default();
+        let mut parse_branch = |p: &mut Self, func: &mut FuncBodyState<'a, '_>| -> io::Result<()> {
+            p.expect_word("branch")?;
+            let pos = p.pos();
+            let name = p.expect_any_word("label")?;
+            let target = p.label_region(func, name, pos)?;
+            if p.eat_punct("(") {
+                let mut values = SmallVec::new();
+                p.comma_sep(")", |p| {
+                    values.push(p.parse_value(func)?);
+                    Ok(())
+                })?;
+                target_inputs.entry(target).or_insert(values);
+            }
+            targets.push(target);
+            Ok(())
+        };
+
+        let kind = if self.eat_word("unreachable") {
+            cfg::ControlInstKind::Unreachable
+        } else if self.eat_word("return") {
+            if !self.at_region_end() {
+                inputs.push(self.parse_value(func)?);
+            }
+            cfg::ControlInstKind::Return
+        } else if self.is_word("branch") {
+            parse_branch(self, func)?;
+            cfg::ControlInstKind::Branch
+        } else if self.eat_word("if") {
+            inputs.push(self.parse_value(func)?);
+            self.expect_punct("{")?;
+            parse_branch(self, func)?;
+            self.expect_punct("}")?;
+            self.expect_word("else")?;
+            self.expect_punct("{")?;
+            parse_branch(self, func)?;
+            self.expect_punct("}")?;
+            cfg::ControlInstKind::SelectBranch(SelectionKind::BoolCond)
+        } else if self.is_word("mesh") {
+            let (kind, exit_inputs) = self.parse_exit_invocation(func)?;
+            inputs = exit_inputs;
+            cfg::ControlInstKind::ExitInvocation(kind)
+        } else if let Some(opcode) = self.try_parse_spv_opcode()? {
+            let imms = self.parse_spv_imms(opcode)?;
+            let mut input_types = SmallVec::<[_; 2]>::new();
+            if self.eat_punct("(") {
+                self.comma_sep(")", |p| {
+                    let (v, ty) = p.parse_value_and_type(func)?;
+                    inputs.push(v);
+                    input_types.push(ty);
+                    Ok(())
+                })?;
+            }
+            if self.eat_punct("{") {
+                let imms = imms.finish(&self.cx, input_types.first().copied())?;
+                while self.eat_word("case") {
+                    self.expect_punct("=>")?;
+                    self.expect_punct("{")?;
+                    parse_branch(self, func)?;
+                    self.expect_punct("}")?;
+                }
+                self.expect_punct("}")?;
+                cfg::ControlInstKind::SelectBranch(SelectionKind::SpvInst(spv::Inst {
+                    opcode,
+                    imms,
+                }))
+            } else {
+                let imms = imms.finish(&self.cx, None)?;
+                cfg::ControlInstKind::ExitInvocation(cfg::ExitInvocationKind::SpvInst(spv::Inst {
+                    opcode,
+                    imms,
+                }))
+            }
+        } else {
+            return Err(self.expected("control-flow instruction (e.g. `branch`)"));
+        };
+
+        Ok(cfg::ControlInst {
+            attrs,
+            kind,
+            inputs,
+            targets,
+            target_inputs,
+        })
+    }
+
+    /// Parse an invocation exit (without any `exit` prefix), and its inputs,
+    /// shared between [`cfg::ControlInstKind::ExitInvocation`] terminators and
+    /// [`ControlNodeKind::ExitInvocation`] nodes.
+    fn parse_exit_invocation(
+        &mut self,
+        func: &FuncBodyState<'a, '_>,
+    ) -> io::Result<(cfg::ExitInvocationKind, SmallVec<[Value; 2]>)> {
+        let mut inputs = SmallVec::new();
+        if self.is_word("mesh") && self.is_punct_at(self.cursor + 1, ".") {
+            self.cursor += 2;
+            self.expect_word("emit_tasks")?;
+            self.expect_punct("(")?;
+            self.comma_sep(")", |p| {
+                inputs.push(p.parse_value(func)?);
+                Ok(())
+            })?;
+            return Ok((cfg::ExitInvocationKind::EmitMeshTasks, inputs));
+        }
+
+        let opcode = self
+            .try_parse_spv_opcode()?
+            .ok_or_else(|| self.expected("invocation exit (e.g. `spv.OpKill`)"))?;
+        let imms = self.parse_spv_imms(opcode)?;
+        if self.eat_punct("(") {
+            self.comma_sep(")", |p| {
+                inputs.push(p.parse_value_and_type(func)?.0);
+                Ok(())
+            })?;
+        }
+        let imms = imms.finish(&self.cx, None)?;
+        Ok((
+            cfg::ExitInvocationKind::SpvInst(spv::Inst { opcode, imms }),
+            inputs,
+        ))
+    }
+
+    /// Parse a `{...}` region (used for e.g. `Select` cases).
+    fn parse_braced_region(
+        &mut self,
+        func: &mut FuncBodyState<'a, '_>,
+    ) -> io::Result<ControlRegion> {
+        let region = func.func_def_body.control_regions.define(
+            &self.cx,
+            ControlRegionDef {
+                inputs: SmallVec::new(),
+                children: EntityList::empty(),
+                outputs: SmallVec::new(),
+            },
+        );
+        self.expect_punct("{")?;
+        self.parse_region_children(func, region)?;
+        self.expect_punct("}")?;
+        Ok(region)
+    }
+
+    /// Parse the contents of `region`, i.e. its `children` (and `outputs`), until
+    /// either its end (`}`, or `->` for loop bodies), or a control-flow instruction.
+    fn parse_region_children(
+        &mut self,
+        func: &mut FuncBodyState<'a, '_>,
+        region: ControlRegion,
+    ) -> io::Result<()> {
+        loop {
+            if self.peek().is_none() || self.is_punct("}") || self.is_punct("->") {
+                return Ok(());
+            }
+
+            let start = self.cursor;
+            let attrs = self.parse_attrs()?;
+            if self.at_control_inst() {
+                self.cursor = start;
+                return Ok(());
+            }
+
+            let next_is_value_def_name = |p: &Self, idx| matches!(p.tok(idx), Some(&Token::Word(name)) if is_value_def_name(name));
+            let next_is_outputs = self.is_punct("(")
+                && match self.tok(self.cursor + 1) {
+                    Some(Token::Punct("#") | Token::CommentAttr(_)) => true,
+                    _ => {
+                        next_is_value_def_name(self, self.cursor + 1)
+                            && self.is_punct_at(self.cursor + 2, ":")
+                    }
+                };
+            if next_is_value_def_name(self, self.cursor) && self.is_punct_at(self.cursor + 1, ":") {
+                let output = self.parse_control_node_output_decl(attrs)?;
+                self.expect_punct("=")?;
+                self.parse_control_node(func, region, [output].into_iter().collect())?;
+            } else if next_is_value_def_name(self, self.cursor)
+                && self.is_punct_at(self.cursor + 1, "=")
+            {
+                let name = self.parse_value_def_name()?;
+                self.expect_punct("=")?;
+                self.parse_data_inst(func, region, attrs, Some(name))?;
+            } else if next_is_outputs {
+                if attrs != AttrSet::default() {
+                    return Err(self.err("unexpected attributes before outputs"));
+                }
+                self.expect_punct("(")?;
+                let mut outputs = SmallVec::new();
+                self.comma_sep(")", |p| {
+                    let attrs = p.parse_attrs()?;
+                    outputs.push(p.parse_control_node_output_decl(attrs)?);
+                    Ok(())
+                })?;
+                self.expect_punct("=")?;
+                self.parse_control_node(func, region, outputs)?;
+            } else if self.is_word("if") || self.is_word("loop") || self.is_word("exit") {
+                // NOTE(eddyb) attributes are parsed again by `parse_control_node`.
+                self.cursor = start;
+                self.parse_control_node(func, region, SmallVec::new())?;
+            } else if self.is_word("call")
+                || (self.is_punct("(") && self.is_word_at(self.cursor + 1, "spv"))
+                || matches!(
+                    self.spv_opcode_at(self.cursor)
+                        .map(|opcode| opcode.def().category),
+                    Some(spec::InstructionCategory::Other)
+                )
+            {
+                self.parse_data_inst(func, region, attrs, None)?;
+            } else if let Some(spec::InstructionCategory::ControlFlow) = self
+                .spv_opcode_at(self.cursor)
+                .map(|opcode| opcode.def().category)
+            {
+                // NOTE(eddyb) attributes are parsed again by `parse_control_node`.
+                self.cursor = start;
+                self.parse_control_node(func, region, SmallVec::new())?;
+            } else {
+                // Anything else has to be the region's outputs.
+                if attrs != AttrSet::default() {
+                    return Err(self.err("unexpected attributes before region outputs"));
+                }
+                let mut outputs = SmallVec::new();
+                if self.eat_punct("(") {
+                    self.comma_sep(")", |p| {
+                        outputs.push(p.parse_value(func)?);
+                        Ok(())
+                    })?;
+                } else {
+                    outputs.push(self.parse_value(func)?);
+                }
+                func.func_def_body.control_regions[region].outputs = outputs;
+
+                if !(self.peek().is_none() || self.is_punct("}") || self.is_punct("->")) {
+                    return Err(self.expected("`}` (after region outputs)"));
+                }
+            }
+        }
+    }
+
+    fn parse_control_node_output_decl(
+        &mut self,
+        attrs: AttrSet,
+    ) -> io::Result<(ValueDefName<'a>, ControlNodeOutputDecl)> {
+        let name = self.parse_value_def_name()?;
+        self.expect_punct(":")?;
+        let ty = self.parse_type()?;
+        Ok((name, ControlNodeOutputDecl { attrs, ty }))
+    }
+
+    fn parse_control_node(
+        &mut self,
+        func: &mut FuncBodyState<'a, '_>,
+        region: ControlRegion,
+        outputs: SmallVec<[(ValueDefName<'a>, ControlNodeOutputDecl); 2]>,
+    ) -> io::Result<()> {
+        let attrs = self.parse_attrs()?;
+        let pos = self.pos();
+        let kind = if self.eat_word("if") {
+            let scrutinee = self.parse_value(func)?;
+            let then_case = self.parse_braced_region(func)?;
+            self.expect_word("else")?;
+            let else_case = self.parse_braced_region(func)?;
+            ControlNodeKind::Select {
+                kind: SelectionKind::BoolCond,
+                scrutinee,
+                cases: [then_case, else_case].into_iter().collect(),
+            }
+        } else if self.eat_word("loop") {
+            if !outputs.is_empty() {
+                return Err(invalid(pos, "loops cannot have outputs"));
+            }
+
+            let mut input_names = vec![];
+            let mut inputs = SmallVec::new();
+            let mut initial_inputs = SmallVec::new();
+            if self.eat_punct("(") {
+                self.comma_sep(")", |p| {
+                    let attrs = p.parse_attrs()?;
+                    input_names.push(p.parse_value_def_name()?);
+                    p.expect_punct(":")?;
+                    let ty = p.parse_type()?;
+                    p.expect_punct("<-")?;
+                    initial_inputs.push(p.parse_value(func)?);
+                    inputs.push(ControlRegionInputDecl { attrs, ty });
+                    Ok(())
+                })?;
+            }
+
+            let body = func.func_def_body.control_regions.define(
+                &self.cx,
+                ControlRegionDef {
+                    inputs,
+                    children: EntityList::empty(),
+                    outputs: SmallVec::new(),
+                },
+            );
+            for (i, &name) in input_names.iter().enumerate() {
+                let ty = func.func_def_body.control_regions[body].inputs[i].ty;
+                let input_idx = i.try_into().unwrap();
+                func.define_value(
+                    name,
+                    Value::ControlRegionInput {
+                        region: body,
+                        input_idx,
+                    },
+                    ty,
+                )?;
+            }
+
+            self.expect_punct("{")?;
+            self.parse_region_children(func, body)?;
+
+            // NOTE(eddyb) `-> ...` only repeats the body inputs, for clarity.
+            if self.eat_punct("->") {
+                let pos = self.pos();
+                let mut dests = SmallVec::<[_; 2]>::new();
+                if self.eat_punct("(") {
+                    self.comma_sep(")", |p| {
+                        dests.push(p.parse_value(func)?);
+                        Ok(())
+                    })?;
+                } else {
+                    dests.push(self.parse_value(func)?);
+                }
+                let expected_dests = (0..input_names.len()).map(|i| Value::ControlRegionInput {
+                    region: body,
+                    input_idx: i.try_into().unwrap(),
+                });
+                if !dests.iter().copied().eq(expected_dests) {
+                    return Err(invalid(pos, "expected loop body inputs after `->`"));
+                }
+            }
+            self.expect_punct("}")?;
+
+            self.expect_word("while")?;
+            let repeat_condition = self.parse_value(func)?;
+
+            ControlNodeKind::Loop {
+                initial_inputs,
+                body,
+                repeat_condition,
+            }
+        } else if self.eat_word("exit") {
+            if !outputs.is_empty() {
+                return Err(invalid(pos, "invocation exits cannot have outputs"));
+            }
+
+            let (kind, inputs) = self.parse_exit_invocation(func)?;
+            ControlNodeKind::ExitInvocation { kind, inputs }
+        } else if let Some(opcode) = self.try_parse_spv_opcode()? {
+            let imms = self.parse_spv_imms(opcode)?;
+            self.expect_punct("(")?;
+            let (scrutinee, scrutinee_type) = self.parse_value_and_type(func)?;
+            self.eat_punct(",");
+            self.expect_punct(")")?;
+            let imms = imms.finish(&self.cx, Some(scrutinee_type))?;
+
+            self.expect_punct("{")?;
+            let mut cases = SmallVec::new();
+            while self.eat_word("case") {
+                self.expect_punct("=>")?;
+                cases.push(self.parse_braced_region(func)?);
+            }
+            self.expect_punct("}")?;
+
+            ControlNodeKind::Select {
+                kind: SelectionKind::SpvInst(spv::Inst { opcode, imms }),
+                scrutinee,
+                cases,
+            }
+        } else {
+            return Err(self.expected("`if`, `loop`, `exit` or SPIR-V instruction"));
+        };
+
+        let (output_names, outputs): (Vec<_>, _) = outputs.into_iter().unzip();
+        let control_node = func.func_def_body.control_nodes.define(
+            &self.cx,
+            ControlNodeDef {
+                attrs,
+                kind,
+                outputs,
+            }
+            .into(),
+        );
+        func.func_def_body.control_regions[region]
+            .children
+            .insert_last(control_node, &mut func.func_def_body.control_nodes);
+
+        for (i, name) in output_names.into_iter().enumerate() {
+            let ty = func.func_def_body.control_nodes[control_node].outputs[i].ty;
+            let output_idx = i.try_into().unwrap();
+            func.define_value(
+                name,
+                Value::ControlNodeOutput {
+                    control_node,
+                    output_idx,
+                },
+                ty,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn parse_data_inst(
+        &mut self,
+        func: &mut FuncBodyState<'a, '_>,
+        region: ControlRegion,
+        attrs: AttrSet,
+        output_name: Option<ValueDefName<'a>>,
+    ) -> io::Result<()> {
+        let pos = self.pos();
+        let (kind, spv_inst_imms) = if self.eat_word("call") {
+            let pos = self.pos();
+            let name = self.expect_any_word("function name")?;
+            (DataInstKind::FuncCall(self.func_named(name, pos)?), None)
+        } else if self.is_punct("(") {
+            let (ext_set, inst) = self.parse_spv_ext_inst_header()?;
+            (DataInstKind::SpvExtInst { ext_set, inst }, None)
+        } else if self.is_word("glsl")
+            && self.is_punct_at(self.cursor + 1, ".")
+            && self.is_word_at(self.cursor + 2, "ext")
+            && self.is_punct_at(self.cursor + 3, ".")
+        {
+            self.cursor += 4;
+            let pos = self.pos();
+            let name = self.expect_any_word("`GLSL.std.450` instruction name")?;
+            let op = spv::glsl_std_450::Op::from_name(name).ok_or_else(|| {
+                invalid(pos, &format!("unknown `GLSL.std.450` instruction `{name}`"))
+            })?;
+            (DataInstKind::SpvGlslStd450(op), None)
+        } else if self.is_word("atomic") && self.is_punct_at(self.cursor + 1, ".") {
+            let wk = &spec::Spec::get().well_known;
+
+            self.cursor += 2;
+            let pos = self.pos();
+            let name = self.expect_any_word("atomic operation name")?;
+            self.expect_punct("<")?;
+            let scope = self.parse_spv_single_imm_operand(wk.Scope)?;
+            self.expect_punct(",")?;
+            let semantics = self.parse_spv_single_imm_operand(wk.MemorySemantics)?;
+            let unequal_semantics = if self.eat_punct(",") {
+                Some(self.parse_spv_single_imm_operand(wk.MemorySemantics)?)
+            } else {
+                None
+            };
+            self.expect_punct(">")?;
+            let op = AtomicOp::from_name(name, unequal_semantics).ok_or_else(|| {
+                invalid(
+                    pos,
+                    &format!("unknown atomic operation `{name}` (or wrong number of semantics)"),
+                )
+            })?;
+            (
+                DataInstKind::Atomic {
+                    op,
+                    scope,
+                    semantics,
+                },
+                None,
+            )
+        } else if self.is_word("barrier") && self.is_punct_at(self.cursor + 1, ".") {
+            let wk = &spec::Spec::get().well_known;
+
+            self.cursor += 2;
+            let has_execution_scope = if self.eat_word("control") {
+                true
+            } else if self.eat_word("memory") {
+                false
+            } else {
+                return Err(self.expected("`control` or `memory`"));
+            };
+            self.expect_punct("<")?;
+            let execution_scope = if has_execution_scope {
+                let scope = self.parse_spv_single_imm_operand(wk.Scope)?;
+                self.expect_punct(",")?;
+                Some(scope)
+            } else {
+                None
+            };
+            let memory_scope = self.parse_spv_single_imm_operand(wk.Scope)?;
+            self.expect_punct(",")?;
+            let semantics = self.parse_spv_single_imm_operand(wk.MemorySemantics)?;
+            self.expect_punct(">")?;
+            (
+                DataInstKind::Barrier {
+                    execution_scope,
+                    memory_scope,
+                    semantics,
+                },
+                None,
+            )
+        } else if self.is_word("group") && self.is_punct_at(self.cursor + 1, ".") {
+            let wk = &spec::Spec::get().well_known;
+
+            self.cursor += 2;
+            let pos = self.pos();
+            let name = self.expect_any_word("group operation name")?;
+            let op = GroupOp::from_name(name)
+                .ok_or_else(|| invalid(pos, &format!("unknown group operation `{name}`")))?;
+            self.expect_punct("<")?;
+            let scope = self.parse_spv_single_imm_operand(wk.Scope)?;
+            let group_operation = if op.takes_group_operation() {
+                self.expect_punct(",")?;
+                Some(self.parse_spv_single_imm_operand(wk.GroupOperation)?)
+            } else {
+                None
+            };
+            self.expect_punct(">")?;
+            (
+                DataInstKind::Group {
+                    op,
+                    scope,
+                    group_operation,
+                },
+                None,
+            )
+        } else if self.is_word("qptr") && self.is_punct_at(self.cursor + 1, ".") {
+            self.cursor += 2;
+            let op = if self.eat_word("offset") {
+                self.expect_punct("<")?;
+                let pointee = self.parse_type()?;
+                self.expect_punct(">")?;
+                QPtrOp::Offset { pointee }
+            } else if self.eat_word("load") {
+                QPtrOp::Load
+            } else if self.eat_word("store") {
+                QPtrOp::Store
+            } else {
+                return Err(self.expected("`offset`, `load` or `store`"));
+            };
+            (DataInstKind::QPtr(op), None)
+        } else if let Some(opcode) = self.try_parse_spv_opcode()? {
+            let imms = self.parse_spv_imms(opcode)?;
+            // HACK(eddyb) placeholder, replaced below (once `output_type` is known).
+            (DataInstKind::SpvInst(opcode.into()), Some(imms))
+        } else {
+            return Err(self.expected("instruction"));
+        };
+
+        let mut inputs = SmallVec::new();
+        if self.eat_punct("(") {
+            self.comma_sep(")", |p| {
+                inputs.push(p.parse_value(func)?);
+                Ok(())
+            })?;
+        }
+
+        let output_type = if self.eat_punct(":") {
+            Some(self.parse_type()?)
+        } else {
+            None
+        };
+        if output_name.is_some() != output_type.is_some() {
+            return Err(invalid(
+                pos,
+                "instructions must have both an output name and type, or neither",
+            ));
+        }
+
+        let kind = match (kind, spv_inst_imms) {
+            (DataInstKind::SpvInst(spv::Inst { opcode, .. }), Some(imms)) => {
+                DataInstKind::SpvInst(spv::Inst {
+                    opcode,
+                    imms: imms.finish(&self.cx, output_type)?,
+                })
+            }
+            (kind, _) => kind,
+        };
+
+        let func_def_body = &mut *func.func_def_body;
+        let inst = func_def_body.data_insts.define(
+            &self.cx,
+            DataInstDef {
+                attrs,
+                kind,
+                output_type,
+                inputs,
+            }
+            .into(),
+        );
+
+        // Append to the last child of `region`, if it's a block, or a new block.
+        let region_def = &mut func_def_body.control_regions[region];
+        let block_node = region_def
+            .children
+            .iter()
+            .last
+            .filter(|&last_node| {
+                matches!(
+                    func_def_body.control_nodes[last_node].kind,
+                    ControlNodeKind::Block { .. }
+                )
+            })
+            .unwrap_or_else(|| {
+                let block_node = func_def_body.control_nodes.define(
+                    &self.cx,
+                    ControlNodeDef {
+                        attrs: AttrSet::default(),
+                        kind: ControlNodeKind::Block {
+                            insts: EntityList::empty(),
+                        },
+                        outputs: SmallVec::new(),
+                    }
+                    .into(),
+                );
+                region_def
+                    .children
+                    .insert_last(block_node, &mut func_def_body.control_nodes);
+                block_node
+            });
+        match &mut func_def_body.control_nodes[block_node].kind {
+            ControlNodeKind::Block { insts } => {
+                insts.insert_last(inst, &mut func_def_body.data_insts);
+            }
+            _ => unreachable!(),
+        }
+
+        if let (Some(name), Some(ty)) = (output_name, output_type) {
+            func.define_value(name, Value::DataInstOutput(inst), ty)?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,150 @@
+//! Resource binding remapping (i.e. of `DescriptorSet`/`Binding` decorations).
+
+use crate::passes::legalize::reachable_global_vars_and_funcs;
+use crate::{spv, Attr, AttrSet, AttrSetDef, Context, GlobalVar, Module};
+use std::collections::BTreeMap;
+
+/// Resource binding of a global variable, i.e. the values of its
+/// `DescriptorSet` and `Binding` decorations.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct DescriptorBinding {
+    pub set: u32,
+    pub binding: u32,
+}
+
+impl DescriptorBinding {
+    /// Get the `DescriptorBinding` described by the decorations in `attrs`,
+    /// if a `Binding` decoration is present (with a missing `DescriptorSet`
+    /// decoration being treated as set `0`).
+    pub fn from_attrs(cx: &Context, attrs: AttrSet) -> Option<Self> {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        let (mut set, mut binding) = (None, None);
+        for attr in &cx[attrs].attrs {
+            if let Attr::SpvAnnotation(spv::Inst { opcode, imms }) = attr {
+                if *opcode != wk.OpDecorate {
+                    continue;
+                }
+                if let [spv::Imm::Short(_, decoration), spv::Imm::Short(_, value)] = imms[..] {
+                    if decoration == wk.DescriptorSet {
+                        set = Some(value);
+                    } else if decoration == wk.Binding {
+                        binding = Some(value);
+                    }
+                }
+            }
+        }
+        Some(Self {
+            set: set.unwrap_or(0),
+            binding: binding?,
+        })
+    }
+
+    /// Get the equivalent binding in set `0`, for all sets being flattened into
+    /// it, with each set taking up (at most) `bindings_per_set` bindings, i.e.
+    /// `binding` in set `s` becomes `s * bindings_per_set + binding` in set `0`
+    /// (or `None` if that doesn't fit in `u32`).
+    pub fn flattened(self, bindings_per_set: u32) -> Option<Self> {
+        Some(Self {
+            set: 0,
+            binding: self
+                .set
+                .checked_mul(bindings_per_set)?
+                .checked_add(self.binding)?,
+        })
+    }
+
+    /// Replace any `DescriptorSet`/`Binding` decorations in `attrs` with ones
+    /// describing `self`.
+    pub(crate) fn apply_to_attrs(self, cx: &Context, attrs: AttrSet) -> AttrSet {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        let decorate = |decoration, value| {
+            Attr::SpvAnnotation(spv::Inst {
+                opcode: wk.OpDecorate,
+                imms: [
+                    spv::Imm::Short(wk.Decoration, decoration),
+                    spv::Imm::Short(wk.LiteralInteger, value),
+                ]
+                .into_iter()
+                .collect(),
+            })
+        };
+
+        let mut attrs = cx[attrs].attrs.clone();
+        attrs.retain(|attr| match attr {
+            Attr::SpvAnnotation(spv::Inst { opcode, imms }) if *opcode == wk.OpDecorate => {
+                !matches!(
+                    imms.first(),
+                    Some(&spv::Imm::Short(_, decoration))
+                        if decoration == wk.DescriptorSet || decoration == wk.Binding
+                )
+            }
+            _ => true,
+        });
+        attrs.insert(decorate(wk.DescriptorSet, self.set));
+        attrs.insert(decorate(wk.Binding, self.binding));
+        cx.intern(AttrSetDef { attrs })
+    }
+}
+
+/// Multiple global variables ending up with the same [`DescriptorBinding`],
+/// after [`remap_descriptor_bindings`] (see also its documentation).
+#[derive(Clone)]
+pub struct BindingCollision {
+    pub binding: DescriptorBinding,
+
+    /// All global variables using `binding` (at least one of which was
+    /// remapped to it, while the rest may have been already using it).
+    pub global_vars: Vec<GlobalVar>,
+}
+
+/// Remap the [`DescriptorBinding`]s of all (reachable) global variables in
+/// `module`, according to `remap` (with `None` meaning "leave unchanged"),
+/// by replacing their `DescriptorSet`/`Binding` decorations.
+///
+/// E.g. [`DescriptorBinding::flattened`] can be used as `remap`, to merge all
+/// sets into set `0`, for targets with only one (or a virtualized) binding model.
+///
+/// Returns every [`DescriptorBinding`] which was the target of some remapping,
+/// and which ended up being used by more than one global variable, but the
+/// remapping is still performed, as some aliasing may be intentional (e.g. the
+/// same resource being accessed through different types), leaving it up to the
+/// caller to decide whether any of the [`BindingCollision`]s are errors.
+pub fn remap_descriptor_bindings(
+    module: &mut Module,
+    remap: impl Fn(DescriptorBinding) -> Option<DescriptorBinding>,
+) -> Vec<BindingCollision> {
+    let cx = &module.cx();
+
+    // NOTE(eddyb) `BTreeMap` is used for a deterministic order of collisions.
+    let mut users_of_binding = BTreeMap::<DescriptorBinding, (Vec<GlobalVar>, bool)>::new();
+
+    let (global_vars, _) = reachable_global_vars_and_funcs(module);
+    for gv in global_vars {
+        let attrs = &mut module.global_vars[gv].attrs;
+        let old_binding = match DescriptorBinding::from_attrs(cx, *attrs) {
+            Some(binding) => binding,
+            None => continue,
+        };
+        let new_binding = remap(old_binding).filter(|&new_binding| new_binding != old_binding);
+        if let Some(new_binding) = new_binding {
+            *attrs = new_binding.apply_to_attrs(cx, *attrs);
+        }
+
+        let (users, any_remapped) = users_of_binding
+            .entry(new_binding.unwrap_or(old_binding))
+            .or_default();
+        users.push(gv);
+        *any_remapped |= new_binding.is_some();
+    }
+
+    users_of_binding
+        .into_iter()
+        .filter(|(_, (users, any_remapped))| *any_remapped && users.len() > 1)
+        .map(|(binding, (global_vars, _))| BindingCollision {
+            binding,
+            global_vars,
+        })
+        .collect()
+}
@@ -0,0 +1,628 @@
+//! Bounds checking (i.e. robustness) for array indices used by access chains.
+
+use crate::passes::legalize::reachable_funcs;
+use crate::spv::fold::{const_splat_value, splat_const, ScalarValue};
+use crate::spv::{self, glsl_std_450};
+use crate::value_range::ValueRanges;
+use crate::{
+    AttrSet, ConstCtor, ConstDef, Context, ControlNode, ControlNodeDef, ControlNodeKind,
+    ControlNodeOutputDecl, ControlRegion, ControlRegionDef, DataInst, DataInstDef, DataInstKind,
+    DeclDef, EntityList, FuncDefBody, Module, SelectionKind, Type, TypeCtor, TypeCtorArg, TypeDef,
+    Value,
+};
+use rustc_hash::FxHashMap;
+use smallvec::SmallVec;
+use std::mem;
+
+/// How out-of-bounds indices are handled (see [`insert_bounds_checks`]).
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum BoundsCheckMode {
+    /// Out-of-bounds indices are clamped to the last element (i.e. `min(i, len - 1)`).
+    Clamp,
+
+    /// Out-of-bounds indices are replaced with the first element (i.e. `i < len ? i : 0`).
+    TrapToZero,
+
+    /// Like [`BoundsCheckMode::TrapToZero`], but also skipping any `OpLoad`s (which
+    /// produce zero instead) or `OpStore`s, through out-of-bounds access chains.
+    ///
+    /// Only `OpLoad`s/`OpStore`s in the same block as the access chain are skipped,
+    /// while other uses of the resulting pointer only get the index replacement.
+    Predicate,
+}
+
+/// Options for bounds checking (see [`insert_bounds_checks`]).
+#[derive(Clone)]
+pub struct BoundsCheckOptions {
+    pub mode: BoundsCheckMode,
+
+    /// Whether to check indices into fixed-size arrays (i.e. `OpTypeArray`).
+    pub fixed_size_arrays: bool,
+
+    /// Whether to check indices into runtime arrays (i.e. `OpTypeRuntimeArray`),
+    /// which requires computing their length (with `OpArrayLength`).
+    pub runtime_arrays: bool,
+}
+
+impl Default for BoundsCheckOptions {
+    fn default() -> Self {
+        Self {
+            mode: BoundsCheckMode::Clamp,
+            fixed_size_arrays: true,
+            runtime_arrays: true,
+        }
+    }
+}
+
+/// Insert bounds checks (according to `options`) for the array indices used by
+/// `OpAccessChain`s/`OpInBoundsAccessChain`s, in all function definitions in
+/// `module`, i.e. guarantee that the resulting pointers are always in-bounds
+/// (for applications wanting robustness without relying on driver support).
+///
+/// Indices are only checked when indexing arrays (not vectors/matrices, or
+/// struct members, which are always constant), and when they're not already
+/// known to be in-bounds (i.e. indices into fixed-size arrays, which are either
+/// constant, or always less than the array length, according to [`ValueRanges`]).
+///
+/// The length of a runtime array is only known (through `OpArrayLength`) if it's
+/// the last member of the struct that the access chain starts from (i.e. the
+/// only way runtime arrays are allowed to be used, in `StorageBuffer`s).
+//
+// FIXME(eddyb) support non-32-bit indices, and arrays with lengths which depend
+// on specialization constants.
+pub fn insert_bounds_checks(module: &mut Module, options: &BoundsCheckOptions) {
+    let wk = &spv::spec::Spec::get().well_known;
+    let cx = &module.cx();
+
+    // FIXME(eddyb) SPIR-T should have native booleans itself.
+    let type_bool = cx.intern(TypeDef {
+        attrs: AttrSet::default(),
+        ctor: TypeCtor::SpvInst(wk.OpTypeBool.into()),
+        ctor_args: [].into_iter().collect(),
+    });
+    let type_u32 = cx.intern(TypeDef {
+        attrs: AttrSet::default(),
+        ctor: TypeCtor::SpvInst(spv::Inst {
+            opcode: wk.OpTypeInt,
+            imms: [
+                spv::Imm::Short(wk.LiteralInteger, 32),
+                spv::Imm::Short(wk.LiteralInteger, 0),
+            ]
+            .into_iter()
+            .collect(),
+        }),
+        ctor_args: [].into_iter().collect(),
+    });
+
+    for func in reachable_funcs(module) {
+        if let DeclDef::Present(func_def_body) = &mut module.funcs[func].def {
+            let regions = match &func_def_body.unstructured_cfg {
+                None => vec![func_def_body.body],
+                Some(cfg) => cfg.rev_post_order(func_def_body).collect(),
+            };
+
+            let value_ranges = ValueRanges::compute(cx.clone(), func_def_body);
+            let mut checker = BoundsChecker {
+                cx,
+                options,
+                value_ranges,
+                func_def_body,
+                type_bool,
+                type_u32,
+            };
+            for region in regions {
+                checker.check_in_region(region);
+            }
+        }
+    }
+}
+
+/// Length of an array being indexed (see [`BoundsChecker::check_access_chain`]).
+enum ArrayLen {
+    Const(u64),
+
+    /// Runtime array found as the `member_idx`-th member of the access chain base.
+    RuntimeArray {
+        member_idx: u32,
+    },
+}
+
+struct BoundsChecker<'a> {
+    cx: &'a Context,
+    options: &'a BoundsCheckOptions,
+
+    /// Value ranges, computed before any bounds checks were inserted (which
+    /// doesn't affect any of the original values).
+    value_ranges: ValueRanges,
+
+    func_def_body: &'a mut FuncDefBody,
+
+    type_bool: Type,
+    type_u32: Type,
+}
+
+impl BoundsChecker<'_> {
+    fn check_in_region(&mut self, region: ControlRegion) {
+        let children: SmallVec<[_; 8]> = self
+            .func_def_body
+            .at(region)
+            .at_children()
+            .into_iter()
+            .map(|func_at_control_node| func_at_control_node.position)
+            .collect();
+        for control_node in children {
+            match &self.func_def_body.control_nodes[control_node].kind {
+                ControlNodeKind::Block { .. } => self.check_in_block(region, control_node),
+                ControlNodeKind::Select { cases, .. } => {
+                    for case in cases.clone() {
+                        self.check_in_region(case);
+                    }
+                }
+                &ControlNodeKind::Loop { body, .. } => self.check_in_region(body),
+                ControlNodeKind::ExitInvocation { .. } => {}
+            }
+        }
+    }
+
+    /// Check all the access chains in `block`, which may also be split into
+    /// several blocks (with `Select`s in between, for [`BoundsCheckMode::Predicate`]).
+    fn check_in_block(&mut self, parent_region: ControlRegion, block: ControlNode) {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        let mut old_insts = match &mut self.func_def_body.control_nodes[block].kind {
+            ControlNodeKind::Block { insts } => mem::take(insts),
+            _ => unreachable!(),
+        };
+
+        // NOTE(eddyb) as `EntityList` doesn't support inserting before a node,
+        // the whole list of instructions is rebuilt (see also `passes::strength_reduce`).
+        let original_insts: SmallVec<[_; 8]> = self
+            .func_def_body
+            .at(old_insts)
+            .into_iter()
+            .map(|func_at_inst| func_at_inst.position)
+            .collect();
+        let mut new_insts = EntityList::empty();
+        let mut current_block = block;
+        let mut following_nodes = SmallVec::<[_; 4]>::new();
+        let mut access_chain_in_bounds = FxHashMap::default();
+        for inst in original_insts {
+            old_insts.remove(inst, &mut self.func_def_body.data_insts);
+
+            let inst_def = &self.func_def_body.data_insts[inst];
+            let opcode = match &inst_def.kind {
+                DataInstKind::SpvInst(spv_inst) => spv_inst.opcode,
+                _ => {
+                    new_insts.insert_last(inst, &mut self.func_def_body.data_insts);
+                    continue;
+                }
+            };
+
+            if [wk.OpAccessChain, wk.OpInBoundsAccessChain].contains(&opcode) {
+                if let Some(in_bounds) = self.check_access_chain(inst, &mut new_insts) {
+                    access_chain_in_bounds.insert(inst, in_bounds);
+                }
+                new_insts.insert_last(inst, &mut self.func_def_body.data_insts);
+                continue;
+            }
+
+            let guarded_access_in_bounds = match inst_def.inputs.first() {
+                Some(&Value::DataInstOutput(ptr_inst))
+                    if [wk.OpLoad, wk.OpStore].contains(&opcode) =>
+                {
+                    access_chain_in_bounds.get(&ptr_inst).copied()
+                }
+                _ => None,
+            };
+            let in_bounds = match guarded_access_in_bounds {
+                Some(in_bounds) => in_bounds,
+                None => {
+                    new_insts.insert_last(inst, &mut self.func_def_body.data_insts);
+                    continue;
+                }
+            };
+
+            // Split the block, with the `OpLoad`/`OpStore` moved into a `Select`
+            // (on `in_bounds`), placed between the two halves of the block.
+            let output_type = inst_def.output_type;
+            let debug_locations =
+                AttrSet::default().with_debug_locations_from(self.cx, inst_def.attrs);
+            let guarded_inst = match output_type {
+                // HACK(eddyb) the original `OpLoad` is replaced with a copy of
+                // the `Select` output (placed in the second half of the block),
+                // so that none of its uses need to change.
+                Some(_) => {
+                    let inst_def = DataInstDef {
+                        attrs: debug_locations,
+                        ..self.func_def_body.at(inst).def().clone()
+                    };
+                    self.func_def_body
+                        .data_insts
+                        .define(self.cx, inst_def.into())
+                }
+                None => inst,
+            };
+            let mut guarded_insts = EntityList::empty();
+            guarded_insts.insert_last(guarded_inst, &mut self.func_def_body.data_insts);
+            let guarded_block = self.func_def_body.control_nodes.define(
+                self.cx,
+                ControlNodeDef {
+                    attrs: AttrSet::default(),
+                    kind: ControlNodeKind::Block {
+                        insts: guarded_insts,
+                    },
+                    outputs: [].into_iter().collect(),
+                }
+                .into(),
+            );
+            let mut then_children = EntityList::empty();
+            then_children.insert_last(guarded_block, &mut self.func_def_body.control_nodes);
+            let then_case = self.func_def_body.control_regions.define(
+                self.cx,
+                ControlRegionDef {
+                    inputs: [].into_iter().collect(),
+                    children: then_children,
+                    outputs: output_type
+                        .map(|_| Value::DataInstOutput(guarded_inst))
+                        .into_iter()
+                        .collect(),
+                },
+            );
+            let else_case = self.func_def_body.control_regions.define(
+                self.cx,
+                ControlRegionDef {
+                    inputs: [].into_iter().collect(),
+                    children: EntityList::empty(),
+                    outputs: output_type
+                        .map(|ty| {
+                            Value::Const(self.cx.intern(ConstDef {
+                                attrs: AttrSet::default(),
+                                ty,
+                                ctor: ConstCtor::SpvInst(wk.OpConstantNull.into()),
+                                ctor_args: [].into_iter().collect(),
+                            }))
+                        })
+                        .into_iter()
+                        .collect(),
+                },
+            );
+            let select_node = self.func_def_body.control_nodes.define(
+                self.cx,
+                ControlNodeDef {
+                    attrs: debug_locations,
+                    kind: ControlNodeKind::Select {
+                        kind: SelectionKind::BoolCond,
+                        scrutinee: in_bounds,
+                        cases: [then_case, else_case].into_iter().collect(),
+                    },
+                    outputs: output_type
+                        .map(|ty| ControlNodeOutputDecl {
+                            attrs: AttrSet::default(),
+                            ty,
+                        })
+                        .into_iter()
+                        .collect(),
+                }
+                .into(),
+            );
+
+            match &mut self.func_def_body.control_nodes[current_block].kind {
+                ControlNodeKind::Block { insts } => *insts = mem::take(&mut new_insts),
+                _ => unreachable!(),
+            }
+            current_block = self.func_def_body.control_nodes.define(
+                self.cx,
+                ControlNodeDef {
+                    attrs: AttrSet::default(),
+                    kind: ControlNodeKind::Block {
+                        insts: EntityList::empty(),
+                    },
+                    outputs: [].into_iter().collect(),
+                }
+                .into(),
+            );
+            following_nodes.push(select_node);
+            following_nodes.push(current_block);
+
+            if output_type.is_some() {
+                let inst_def = &mut self.func_def_body.data_insts[inst];
+                inst_def.kind = DataInstKind::SpvInst(wk.OpCopyObject.into());
+                inst_def.inputs = [Value::ControlNodeOutput {
+                    control_node: select_node,
+                    output_idx: 0,
+                }]
+                .into_iter()
+                .collect();
+                new_insts.insert_last(inst, &mut self.func_def_body.data_insts);
+            }
+        }
+        match &mut self.func_def_body.control_nodes[current_block].kind {
+            ControlNodeKind::Block { insts } => *insts = new_insts,
+            _ => unreachable!(),
+        }
+
+        if !following_nodes.is_empty() {
+            insert_after(self.func_def_body, parent_region, block, following_nodes);
+        }
+    }
+
+    /// Check (and replace) the array indices of the access chain `inst`, with
+    /// any new instructions appended to `new_insts`, returning whether all the
+    /// indices were in-bounds (only for [`BoundsCheckMode::Predicate`]).
+    fn check_access_chain(
+        &mut self,
+        inst: DataInst,
+        new_insts: &mut EntityList<DataInst>,
+    ) -> Option<Value> {
+        let wk = &spv::spec::Spec::get().well_known;
+        let cx = self.cx;
+
+        let access_chain_attrs = self.func_def_body.data_insts[inst].attrs;
+        let base_ptr = self.func_def_body.data_insts[inst].inputs[0];
+        let base_type = self
+            .func_def_body
+            .at(base_ptr)
+            .type_of(cx)
+            .pointee_type(cx)?;
+
+        // Find all the array indices, alongside the length of the array.
+        let mut checks = SmallVec::<[_; 2]>::new();
+        let mut ty = base_type;
+        for (i, &index) in self.func_def_body.data_insts[inst].inputs[1..]
+            .iter()
+            .enumerate()
+        {
+            let const_index = match index {
+                Value::Const(ct) => match const_splat_value(cx, ct) {
+                    Some(ScalarValue::Int { bits, .. }) => Some(bits),
+                    _ => None,
+                },
+                _ => None,
+            };
+
+            let ty_def = &cx[ty];
+            let (len, elem_type) = match (&ty_def.ctor, &ty_def.ctor_args[..]) {
+                (TypeCtor::Struct { .. }, _) => {
+                    match const_index
+                        .and_then(|idx| ty_def.ctor_args.get(usize::try_from(idx).ok()?))
+                    {
+                        Some(&TypeCtorArg::Type(member_type)) => {
+                            ty = member_type;
+                            continue;
+                        }
+                        _ => break,
+                    }
+                }
+                (TypeCtor::Array, &[TypeCtorArg::Type(elem_type), TypeCtorArg::Const(len)]) => {
+                    let len = match const_splat_value(cx, len) {
+                        Some(ScalarValue::Int { bits, .. }) => Some(ArrayLen::Const(bits)),
+                        _ => None,
+                    };
+                    (len.filter(|_| self.options.fixed_size_arrays), elem_type)
+                }
+                (TypeCtor::RuntimeArray, &[TypeCtorArg::Type(elem_type)]) => {
+                    // NOTE(eddyb) runtime arrays can only be the last member
+                    // of the struct the access chain starts from (see above).
+                    let member_idx = match (&cx[base_type].ctor, i) {
+                        (TypeCtor::Struct { members }, 1) => self.func_def_body.data_insts[inst]
+                            .inputs
+                            .get(1)
+                            .and_then(|&idx| match idx {
+                                Value::Const(ct) => match const_splat_value(cx, ct) {
+                                    Some(ScalarValue::Int { bits, .. }) => u32::try_from(bits).ok(),
+                                    _ => None,
+                                },
+                                _ => None,
+                            })
+                            .filter(|&member_idx| member_idx as usize + 1 == members.len()),
+                        _ => None,
+                    };
+                    let len = member_idx.map(|member_idx| ArrayLen::RuntimeArray { member_idx });
+                    (len.filter(|_| self.options.runtime_arrays), elem_type)
+                }
+                (TypeCtor::Matrix { .. }, &[TypeCtorArg::Type(elem_type)]) => {
+                    ty = elem_type;
+                    continue;
+                }
+                (TypeCtor::SpvInst(spv_inst), &[TypeCtorArg::Type(elem_type)])
+                    if spv_inst.opcode == wk.OpTypeVector =>
+                {
+                    ty = elem_type;
+                    continue;
+                }
+                _ => break,
+            };
+            ty = elem_type;
+
+            let len = match len {
+                Some(len) => len,
+                None => continue,
+            };
+            if let ArrayLen::Const(len) = len {
+                if const_index.is_some_and(|const_index| const_index < len) {
+                    continue;
+                }
+                if self
+                    .value_ranges
+                    .range_of(index)
+                    .is_some_and(|range| range.max < len)
+                {
+                    continue;
+                }
+            }
+            checks.push((1 + i, index, len));
+        }
+
+        let mut all_in_bounds = None;
+        for (input_idx, index, len) in checks {
+            let index_type = self.func_def_body.at(index).type_of(cx);
+            let signed = match int_type(cx, index_type) {
+                Some((32, signed)) => signed,
+                _ => continue,
+            };
+            let int_const =
+                |bits| splat_const(cx, index_type, ScalarValue::Int { width: 32, bits });
+
+            let len = match len {
+                ArrayLen::Const(len) => match int_const(len) {
+                    Some(len) => Value::Const(len),
+                    None => continue,
+                },
+                ArrayLen::RuntimeArray { member_idx } => {
+                    let len = self.push_inst(
+                        access_chain_attrs,
+                        new_insts,
+                        DataInstKind::SpvInst(spv::Inst {
+                            opcode: wk.OpArrayLength,
+                            imms: [spv::Imm::Short(wk.LiteralInteger, member_idx)]
+                                .into_iter()
+                                .collect(),
+                        }),
+                        [base_ptr],
+                        self.type_u32,
+                    );
+                    if signed {
+                        self.push_inst(
+                            access_chain_attrs,
+                            new_insts,
+                            DataInstKind::SpvInst(wk.OpBitcast.into()),
+                            [len],
+                            index_type,
+                        )
+                    } else {
+                        len
+                    }
+                }
+            };
+
+            let new_index = match self.options.mode {
+                BoundsCheckMode::Clamp => {
+                    let last = match len {
+                        Value::Const(len) => match const_splat_value(cx, len) {
+                            Some(ScalarValue::Int { bits, .. }) => {
+                                int_const(bits.saturating_sub(1)).map(Value::Const)
+                            }
+                            _ => None,
+                        },
+                        _ => int_const(1).map(|one| {
+                            self.push_inst(
+                                access_chain_attrs,
+                                new_insts,
+                                DataInstKind::SpvInst(wk.OpISub.into()),
+                                [len, Value::Const(one)],
+                                index_type,
+                            )
+                        }),
+                    };
+                    let last = match last {
+                        Some(last) => last,
+                        None => continue,
+                    };
+                    self.push_inst(
+                        access_chain_attrs,
+                        new_insts,
+                        DataInstKind::SpvGlslStd450(glsl_std_450::Op::UMin),
+                        [index, last],
+                        index_type,
+                    )
+                }
+                BoundsCheckMode::TrapToZero | BoundsCheckMode::Predicate => {
+                    let zero = match int_const(0) {
+                        Some(zero) => zero,
+                        None => continue,
+                    };
+                    let in_bounds = self.push_inst(
+                        access_chain_attrs,
+                        new_insts,
+                        DataInstKind::SpvInst(wk.OpULessThan.into()),
+                        [index, len],
+                        self.type_bool,
+                    );
+                    if self.options.mode == BoundsCheckMode::Predicate {
+                        all_in_bounds = Some(match all_in_bounds {
+                            Some(all) => self.push_inst(
+                                access_chain_attrs,
+                                new_insts,
+                                DataInstKind::SpvInst(wk.OpLogicalAnd.into()),
+                                [all, in_bounds],
+                                self.type_bool,
+                            ),
+                            None => in_bounds,
+                        });
+                    }
+                    self.push_inst(
+                        access_chain_attrs,
+                        new_insts,
+                        DataInstKind::SpvInst(wk.OpSelect.into()),
+                        [in_bounds, index, Value::Const(zero)],
+                        index_type,
+                    )
+                }
+            };
+            self.func_def_body.data_insts[inst].inputs[input_idx] = new_index;
+        }
+        all_in_bounds
+    }
+
+    /// Append a new instruction to `insts`, returning its output, with the
+    /// source locations of the access chain it was created for (i.e. the one
+    /// with `source_attrs` as its attributes).
+    fn push_inst<const N: usize>(
+        &mut self,
+        source_attrs: AttrSet,
+        insts: &mut EntityList<DataInst>,
+        kind: DataInstKind,
+        inputs: [Value; N],
+        output_type: Type,
+    ) -> Value {
+        let inst = self.func_def_body.data_insts.define(
+            self.cx,
+            DataInstDef {
+                attrs: AttrSet::default().with_debug_locations_from(self.cx, source_attrs),
+                kind,
+                output_type: Some(output_type),
+                inputs: inputs.into_iter().collect(),
+            }
+            .into(),
+        );
+        insts.insert_last(inst, &mut self.func_def_body.data_insts);
+        Value::DataInstOutput(inst)
+    }
+}
+
+/// Get the width and signedness of `ty`, if it's a SPIR-V integer type.
+fn int_type(cx: &Context, ty: Type) -> Option<(u32, bool)> {
+    let wk = &spv::spec::Spec::get().well_known;
+
+    match &cx[ty].ctor {
+        TypeCtor::SpvInst(inst) if inst.opcode == wk.OpTypeInt => match inst.imms[..] {
+            [spv::Imm::Short(_, width), spv::Imm::Short(_, signedness)] => {
+                Some((width, signedness != 0))
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Insert `new_nodes` into `region`'s children, right after `prev_node`.
+fn insert_after(
+    func_def_body: &mut FuncDefBody,
+    region: ControlRegion,
+    prev_node: ControlNode,
+    new_nodes: impl IntoIterator<Item = ControlNode>,
+) {
+    let control_nodes = &mut func_def_body.control_nodes;
+    let children = &mut func_def_body.control_regions[region].children;
+
+    // FIXME(eddyb) `EntityList` should have a way to insert in the middle.
+    let mut following_nodes = EntityList::empty();
+    while let Some(next) = control_nodes[prev_node].next_in_list() {
+        children.remove(next, control_nodes);
+        following_nodes.insert_last(next, control_nodes);
+    }
+    for new_node in new_nodes {
+        children.insert_last(new_node, control_nodes);
+    }
+    children.append(following_nodes, control_nodes);
+}
@@ -0,0 +1,380 @@
+//! Control-flow graph (CFG) simplification, for functions which haven't been
+//! (fully) structurized yet (i.e. still have an `unstructured_cfg`).
+
+use crate::cfg::ControlInstKind;
+use crate::passes::dce::value_use_counts;
+use crate::passes::legalize::reachable_funcs;
+use crate::spv::fold::{const_splat_value, ScalarValue};
+use crate::transform::{InnerInPlaceTransform, ReplaceValueWith};
+use crate::{
+    spv, AttrSet, AttrSetDef, Context, ControlRegion, DeclDef, EntityList, FuncDefBody, FxIndexMap,
+    Module, SelectionKind, Value,
+};
+use rustc_hash::{FxHashMap, FxHashSet};
+use smallvec::SmallVec;
+
+/// Simplify the unstructured CFGs of all function definitions in `module`, by
+/// repeatedly (until no more changes can be made):
+/// * threading `SelectBranch`es which always pick the same target (due to
+///   either a constant input, or all targets being the same), i.e. replacing
+///   them with an unconditional `Branch` to that target
+/// * removing empty "forwarding" [`ControlRegion`]s (i.e. without children, and
+///   which only `Branch` to another region), by retargeting their predecessors
+/// * merging a [`ControlRegion`] ending in a `Branch` with its target, if it's
+///   the only predecessor of that target
+///
+/// This doesn't change the semantics of any function, but can make subsequent
+/// structurization (see [`structurize_func_cfgs`]) cheaper and more successful.
+///
+/// [`structurize_func_cfgs`]: crate::passes::legalize::structurize_func_cfgs
+pub fn simplify_func_cfgs(module: &mut Module) {
+    let cx = &module.cx();
+
+    for func in reachable_funcs(module) {
+        if let DeclDef::Present(func_def_body) = &mut module.funcs[func].def {
+            if func_def_body.unstructured_cfg.is_some() {
+                loop {
+                    let mut changed = thread_const_branches(cx, func_def_body);
+                    changed |= bypass_forwarding_regions(func_def_body);
+                    changed |= merge_linear_regions(cx, func_def_body);
+                    if !changed {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Replace every `SelectBranch` in `func_def_body` which can only ever pick one
+/// of its targets with a `Branch` to that target, returning `true` if any
+/// changes were made.
+fn thread_const_branches(cx: &Context, func_def_body: &mut FuncDefBody) -> bool {
+    let cfg = func_def_body.unstructured_cfg.as_ref().unwrap();
+    let regions: SmallVec<[_; 8]> = cfg.rev_post_order(func_def_body).collect();
+
+    let cfg = func_def_body.unstructured_cfg.as_mut().unwrap();
+    let mut changed = false;
+    for region in regions {
+        let control_inst = match cfg.control_inst_on_exit_from.get_mut(region) {
+            Some(control_inst) => control_inst,
+            None => continue,
+        };
+        let kind = match &control_inst.kind {
+            ControlInstKind::SelectBranch(kind) => kind,
+            _ => continue,
+        };
+
+        let first_target = control_inst.targets[0];
+        let target = if control_inst.targets.iter().all(|&t| t == first_target) {
+            first_target
+        } else {
+            let scrutinee = match control_inst.inputs[..] {
+                [Value::Const(ct)] => const_splat_value(cx, ct),
+                _ => None,
+            };
+            let target_idx = match (kind, scrutinee) {
+                (SelectionKind::BoolCond, Some(ScalarValue::Bool(cond))) => {
+                    if cond {
+                        0
+                    } else {
+                        1
+                    }
+                }
+                (SelectionKind::SpvInst(inst), Some(ScalarValue::Int { width, bits })) => {
+                    match switch_case_idx(inst, width, bits) {
+                        Some(target_idx) => target_idx,
+                        None => continue,
+                    }
+                }
+                _ => continue,
+            };
+            control_inst.targets[target_idx]
+        };
+
+        control_inst.kind = ControlInstKind::Branch;
+        control_inst.inputs.clear();
+        control_inst.targets = [target].into_iter().collect();
+        control_inst.target_inputs.retain(|&t, _| t == target);
+        changed = true;
+    }
+    changed
+}
+
+/// Find the index (into the `targets` of the `SelectBranch`) of the target that
+/// an `OpSwitch` (i.e. `switch_inst`) would pick for a selector of `width` bits
+/// (zero-extended to 64 bits as `selector_bits`), with `0` being the default.
+///
+/// Returns `None` if `switch_inst` isn't an `OpSwitch`, or has malformed cases.
+pub(crate) fn switch_case_idx(
+    switch_inst: &spv::Inst,
+    width: u32,
+    selector_bits: u64,
+) -> Option<usize> {
+    let wk = &spv::spec::Spec::get().well_known;
+
+    if switch_inst.opcode != wk.OpSwitch {
+        return None;
+    }
+
+    let mask = u64::MAX >> (64 - width.clamp(1, 64));
+    let mut imms = switch_inst.imms.iter().copied();
+    let mut case_idx = 0;
+    while let Some(imm) = imms.next() {
+        // NOTE(eddyb) literals narrower than 32 bits may be sign-extended, so
+        // they're always masked to the selector's width before comparing.
+        let case_bits = match imm {
+            spv::Imm::Short(_, bits) => u64::from(bits),
+            spv::Imm::LongStart(_, lo) => match imms.next()? {
+                spv::Imm::LongCont(_, hi) => u64::from(lo) | (u64::from(hi) << 32),
+                _ => return None,
+            },
+            spv::Imm::LongCont(..) => return None,
+        };
+        case_idx += 1;
+        if case_bits & mask == selector_bits & mask {
+            return Some(case_idx);
+        }
+    }
+    Some(0)
+}
+
+/// Remove every empty [`ControlRegion`] which only `Branch`es to another region
+/// (i.e. "forwarding" regions), by retargeting all of its predecessors to its
+/// target, returning `true` if any changes were made.
+fn bypass_forwarding_regions(func_def_body: &mut FuncDefBody) -> bool {
+    let cfg = func_def_body.unstructured_cfg.as_ref().unwrap();
+    let regions: SmallVec<[_; 8]> = cfg.rev_post_order(func_def_body).collect();
+
+    // NOTE(eddyb) the inputs of a forwarding region can't be used outside of its
+    // own `ControlInst` (i.e. by regions it dominates), as they'd be left without
+    // a definition, once the forwarding region is removed. While bypassing other
+    // regions can move uses around, it can't introduce such uses where none
+    // existed, so this set (computed only once) remains conservatively correct.
+    let use_counts = value_use_counts(func_def_body);
+    let regions_with_escaping_inputs: FxHashSet<_> = regions
+        .iter()
+        .copied()
+        .filter(|&region| {
+            let own_target_inputs = &cfg.control_inst_on_exit_from[region].target_inputs;
+            let region_def = func_def_body.at(region).def();
+            (0..region_def.inputs.len()).any(|input_idx| {
+                let input = Value::ControlRegionInput {
+                    region,
+                    input_idx: input_idx.try_into().unwrap(),
+                };
+                let own_uses = own_target_inputs
+                    .values()
+                    .flatten()
+                    .filter(|&&v| v == input)
+                    .count();
+                use_counts.get(&input).copied().unwrap_or(0) != own_uses
+            })
+        })
+        .collect();
+
+    let mut preds = FxIndexMap::<ControlRegion, SmallVec<[ControlRegion; 4]>>::default();
+    for &region in &regions {
+        for &target in &cfg.control_inst_on_exit_from[region].targets {
+            let target_preds = preds.entry(target).or_default();
+            if !target_preds.contains(&region) {
+                target_preds.push(region);
+            }
+        }
+    }
+
+    let cfg = func_def_body.unstructured_cfg.as_mut().unwrap();
+    let mut changed = false;
+    for region in regions {
+        if region == func_def_body.body || regions_with_escaping_inputs.contains(&region) {
+            continue;
+        }
+
+        let region_def = &func_def_body.control_regions[region];
+        let control_inst = &cfg.control_inst_on_exit_from[region];
+
+        // NOTE(eddyb) any `attrs` (e.g. `LoopControl` hints) on the `Branch` are
+        // kept, by not removing the region (as they'd be lost otherwise).
+        let is_forwarding = region_def.children.is_empty()
+            && matches!(control_inst.kind, ControlInstKind::Branch)
+            && control_inst.attrs == AttrSet::default()
+            && control_inst.targets[0] != region;
+        if !is_forwarding {
+            continue;
+        }
+        let target = control_inst.targets[0];
+        let forwarded_inputs = control_inst
+            .target_inputs
+            .get(&target)
+            .cloned()
+            .unwrap_or_default();
+
+        // The values each predecessor would pass to `target` directly, with
+        // `region`'s inputs replaced by the values that predecessor provides.
+        let region_preds = preds.get(&region).cloned().unwrap_or_default();
+        let new_target_inputs: Option<SmallVec<[SmallVec<[Value; 2]>; 4]>> = region_preds
+            .iter()
+            .map(|&pred| {
+                let pred_control_inst = &cfg.control_inst_on_exit_from[pred];
+                let pred_inputs = pred_control_inst.target_inputs.get(&region);
+                let new_inputs: SmallVec<[Value; 2]> = forwarded_inputs
+                    .iter()
+                    .map(|&v| match v {
+                        Value::ControlRegionInput {
+                            region: input_region,
+                            input_idx,
+                        } if input_region == region => pred_inputs.unwrap()[input_idx as usize],
+                        _ => v,
+                    })
+                    .collect();
+
+                // HACK(eddyb) φ ("phi") nodes can't tell apart multiple edges
+                // with the same source and destination, so a predecessor which
+                // already targets `target` must agree on the values passed.
+                if pred_control_inst.targets.contains(&target) {
+                    let existing_inputs = pred_control_inst
+                        .target_inputs
+                        .get(&target)
+                        .map_or(&[][..], |inputs| &inputs[..]);
+                    if existing_inputs != &new_inputs[..] {
+                        return None;
+                    }
+                }
+
+                Some(new_inputs)
+            })
+            .collect();
+        let new_target_inputs = match new_target_inputs {
+            Some(new_target_inputs) => new_target_inputs,
+            None => continue,
+        };
+
+        for (&pred, new_inputs) in region_preds.iter().zip(new_target_inputs) {
+            let pred_control_inst = cfg.control_inst_on_exit_from.get_mut(pred).unwrap();
+            for pred_target in &mut pred_control_inst.targets {
+                if *pred_target == region {
+                    *pred_target = target;
+                }
+            }
+            pred_control_inst.target_inputs.shift_remove(&region);
+            if !new_inputs.is_empty() {
+                pred_control_inst.target_inputs.insert(target, new_inputs);
+            }
+
+            let target_preds = preds.entry(target).or_default();
+            if !target_preds.contains(&pred) {
+                target_preds.push(pred);
+            }
+        }
+        preds
+            .get_mut(&target)
+            .unwrap()
+            .retain(|&mut pred| pred != region);
+        preds.shift_remove(&region);
+
+        cfg.control_inst_on_exit_from.remove(region);
+        changed = true;
+    }
+    changed
+}
+
+/// Merge every [`ControlRegion`] which is the only target of a `Branch`, and
+/// which has no other predecessors, into that predecessor (appending its
+/// children, and taking over its `ControlInst`), returning `true` if any
+/// changes were made.
+fn merge_linear_regions(cx: &Context, func_def_body: &mut FuncDefBody) -> bool {
+    let cfg = func_def_body.unstructured_cfg.as_ref().unwrap();
+    let regions: SmallVec<[_; 8]> = cfg.rev_post_order(func_def_body).collect();
+
+    let mut incoming_edge_counts = FxHashMap::<ControlRegion, usize>::default();
+    for &region in &regions {
+        for &target in &cfg.control_inst_on_exit_from[region].targets {
+            *incoming_edge_counts.entry(target).or_default() += 1;
+        }
+    }
+
+    let mut merged_regions = FxHashSet::default();
+    let mut replacements = FxHashMap::default();
+    for region in regions {
+        if merged_regions.contains(&region) {
+            continue;
+        }
+        loop {
+            let cfg = func_def_body.unstructured_cfg.as_mut().unwrap();
+            let control_inst = &cfg.control_inst_on_exit_from[region];
+            if !matches!(control_inst.kind, ControlInstKind::Branch) {
+                break;
+            }
+            let target = control_inst.targets[0];
+            if target == region
+                || target == func_def_body.body
+                || incoming_edge_counts[&target] != 1
+                || !func_def_body.control_regions[target].outputs.is_empty()
+            {
+                break;
+            }
+
+            let mut control_inst = cfg.control_inst_on_exit_from.remove(region).unwrap();
+            let target_inputs = control_inst
+                .target_inputs
+                .shift_remove(&target)
+                .unwrap_or_default();
+            let mut target_control_inst = cfg.control_inst_on_exit_from.remove(target).unwrap();
+
+            // NOTE(eddyb) `attrs` on `region`'s `Branch` (e.g. `LoopControl`
+            // hints, for a loop header) must be kept, as `region` remains.
+            if control_inst.attrs != AttrSet::default() {
+                target_control_inst.attrs = cx.intern(AttrSetDef {
+                    attrs: cx[control_inst.attrs]
+                        .attrs
+                        .iter()
+                        .chain(&cx[target_control_inst.attrs].attrs)
+                        .cloned()
+                        .collect(),
+                });
+            }
+            cfg.control_inst_on_exit_from
+                .insert(region, target_control_inst);
+
+            let target_def = &mut func_def_body.control_regions[target];
+            assert_eq!(target_def.inputs.len(), target_inputs.len());
+            target_def.inputs.clear();
+            let target_children = std::mem::replace(&mut target_def.children, EntityList::empty());
+            func_def_body.control_regions[region]
+                .children
+                .append(target_children, &mut func_def_body.control_nodes);
+
+            for (input_idx, v) in target_inputs.into_iter().enumerate() {
+                let input_idx = input_idx.try_into().unwrap();
+                replacements.insert(
+                    Value::ControlRegionInput {
+                        region: target,
+                        input_idx,
+                    },
+                    v,
+                );
+            }
+            merged_regions.insert(target);
+        }
+    }
+
+    if merged_regions.is_empty() {
+        return false;
+    }
+
+    if !replacements.is_empty() {
+        // NOTE(eddyb) the replacement of an input can itself be an input of
+        // another merged region, so the replacements need to be chased.
+        let resolve = |mut v| {
+            let mut changed = false;
+            while let Some(&replacement) = replacements.get(&v) {
+                v = replacement;
+                changed = true;
+            }
+            changed.then_some(v)
+        };
+        func_def_body.inner_in_place_transform_with(&mut ReplaceValueWith(resolve));
+    }
+
+    true
+}
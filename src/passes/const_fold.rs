@@ -0,0 +1,230 @@
+//! Constant folding (of instructions with constant inputs, and control-flow).
+
+use crate::passes::legalize::reachable_funcs;
+use crate::spv::{self, fold::eval_spv_inst};
+use crate::transform::{InnerInPlaceTransform, ReplaceValueWith};
+use crate::{
+    cfg, ConstCtor, Context, ControlNode, ControlNodeKind, ControlRegion, DataInst, DataInstKind,
+    DeclDef, EntityList, FuncDefBody, Module, SelectionKind, Value,
+};
+use rustc_hash::FxHashMap;
+use smallvec::SmallVec;
+use std::mem;
+
+/// Fold constants in all function definitions in `module`, i.e.:
+/// * SPIR-V instructions with only constant inputs are replaced with their
+///   results, wherever they can be evaluated (see [`eval_spv_inst`])
+/// * `Select`s on a constant `BoolCond` scrutinee are replaced with the contents
+///   of the case that would always be chosen (and, likewise, conditional branches
+///   on a constant condition, in the unstructured CFG, become unconditional)
+///
+/// Folding an instruction can make others foldable (i.e. if they use its result),
+/// and this is taken into account, so a single call to `fold_consts` suffices.
+pub fn fold_consts(module: &mut Module) {
+    let cx = &module.cx();
+
+    for func in reachable_funcs(module) {
+        if let DeclDef::Present(func_def_body) = &mut module.funcs[func].def {
+            fold_consts_in_func(cx, func_def_body);
+        }
+    }
+}
+
+fn fold_consts_in_func(cx: &Context, func_def_body: &mut FuncDefBody) {
+    let mut folder = ConstFolder {
+        cx,
+        func_def_body,
+
+        replacements: FxHashMap::default(),
+        folded_insts: vec![],
+        folded_selects: vec![],
+        folded_branches: vec![],
+    };
+    match &func_def_body.unstructured_cfg {
+        None => folder.fold_in_region(func_def_body.body),
+        Some(cfg) => {
+            for region in cfg.rev_post_order(func_def_body) {
+                folder.fold_in_region(region);
+
+                let control_inst = &cfg.control_inst_on_exit_from[region];
+                if let cfg::ControlInstKind::SelectBranch(SelectionKind::BoolCond) =
+                    control_inst.kind
+                {
+                    if let Some(cond) = folder.const_bool(control_inst.inputs[0]) {
+                        folder.folded_branches.push((region, cond));
+                    }
+                }
+            }
+        }
+    }
+    let ConstFolder {
+        replacements,
+        folded_insts,
+        folded_selects,
+        folded_branches,
+        ..
+    } = folder;
+
+    for (block_node, inst) in folded_insts {
+        match &mut func_def_body.control_nodes[block_node].kind {
+            ControlNodeKind::Block { insts } => insts.remove(inst, &mut func_def_body.data_insts),
+            _ => unreachable!(),
+        }
+    }
+
+    // NOTE(eddyb) `Select`s nested in the chosen case of another folded `Select`
+    // are always found later, so they're replaced first (i.e. in reverse order),
+    // while `parent_region` is still the chosen case they're found in.
+    for (parent_region, select_node, chosen_case) in folded_selects.into_iter().rev() {
+        let case_children = mem::take(&mut func_def_body.control_regions[chosen_case].children);
+        let control_nodes = &mut func_def_body.control_nodes;
+        let children = &mut func_def_body.control_regions[parent_region].children;
+
+        let mut following_nodes = EntityList::empty();
+        while let Some(next) = control_nodes[select_node].next_in_list() {
+            children.remove(next, control_nodes);
+            following_nodes.insert_last(next, control_nodes);
+        }
+        children.remove(select_node, control_nodes);
+        children.append(case_children, control_nodes);
+        children.append(following_nodes, control_nodes);
+    }
+
+    if let Some(cfg) = &mut func_def_body.unstructured_cfg {
+        for (region, cond) in folded_branches {
+            let control_inst = cfg.control_inst_on_exit_from.get_mut(region).unwrap();
+            let target = control_inst.targets[if cond { 0 } else { 1 }];
+            control_inst.kind = cfg::ControlInstKind::Branch;
+            control_inst.inputs = SmallVec::new();
+            control_inst.targets = [target].into_iter().collect();
+            control_inst.target_inputs.retain(|&r, _| r == target);
+        }
+    }
+
+    if replacements.is_empty() {
+        return;
+    }
+
+    // NOTE(eddyb) replacements can refer to other replaced values (e.g. a
+    // `Select` output from a folded instruction in the chosen case), but
+    // only ones defined before them (i.e. dominating them), so this terminates.
+    fn resolve(replacements: &FxHashMap<Value, Value>, v: Value) -> Value {
+        match replacements.get(&v) {
+            Some(&new) => resolve(replacements, new),
+            None => v,
+        }
+    }
+
+    func_def_body.inner_in_place_transform_with(&mut ReplaceValueWith(|v| {
+        replacements
+            .contains_key(&v)
+            .then(|| resolve(&replacements, v))
+    }));
+}
+
+struct ConstFolder<'a> {
+    cx: &'a Context,
+    func_def_body: &'a FuncDefBody,
+
+    /// Values to replace all uses of (e.g. the outputs of folded instructions).
+    replacements: FxHashMap<Value, Value>,
+
+    /// Instructions (alongside the `Block` [`ControlNode`] they're found in)
+    /// which have been folded, and are to be removed.
+    folded_insts: Vec<(ControlNode, DataInst)>,
+
+    /// `Select` [`ControlNode`]s (alongside the [`ControlRegion`] they're found
+    /// in) to replace with the contents of the chosen case [`ControlRegion`].
+    folded_selects: Vec<(ControlRegion, ControlNode, ControlRegion)>,
+
+    /// [`ControlRegion`]s (in the unstructured CFG) which end in a conditional
+    /// branch, on a constant condition (of the value in this list).
+    folded_branches: Vec<(ControlRegion, bool)>,
+}
+
+impl ConstFolder<'_> {
+    fn resolve(&self, mut v: Value) -> Value {
+        while let Some(&new) = self.replacements.get(&v) {
+            v = new;
+        }
+        v
+    }
+
+    /// Get the value of `v` (after replacements), if it's a boolean constant.
+    fn const_bool(&self, v: Value) -> Option<bool> {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        match self.resolve(v) {
+            Value::Const(ct) => match &self.cx[ct].ctor {
+                ConstCtor::SpvInst(inst) if inst.opcode == wk.OpConstantTrue => Some(true),
+                ConstCtor::SpvInst(inst) if inst.opcode == wk.OpConstantFalse => Some(false),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    fn fold_in_region(&mut self, region: ControlRegion) {
+        let func = self.func_def_body.at(region);
+        for func_at_control_node in func.at_children() {
+            let control_node = func_at_control_node.position;
+            let control_node_def = func_at_control_node.def();
+            match &control_node_def.kind {
+                ControlNodeKind::Block { insts } => {
+                    for func_at_inst in func.at(*insts) {
+                        let inst_def = func_at_inst.def();
+                        let (spv_inst, output_type) = match (&inst_def.kind, inst_def.output_type) {
+                            (DataInstKind::SpvInst(spv_inst), Some(ty)) => (spv_inst, ty),
+                            _ => continue,
+                        };
+                        let const_inputs = inst_def
+                            .inputs
+                            .iter()
+                            .map(|&v| match self.resolve(v) {
+                                Value::Const(ct) => Some(ct),
+                                _ => None,
+                            })
+                            .collect::<Option<SmallVec<[_; 3]>>>();
+                        let folded = const_inputs.and_then(|const_inputs| {
+                            eval_spv_inst(self.cx, spv_inst, output_type, &const_inputs)
+                        });
+                        if let Some(ct) = folded {
+                            let inst = func_at_inst.position;
+                            self.replacements
+                                .insert(Value::DataInstOutput(inst), Value::Const(ct));
+                            self.folded_insts.push((control_node, inst));
+                        }
+                    }
+                }
+                ControlNodeKind::Select {
+                    kind: SelectionKind::BoolCond,
+                    scrutinee,
+                    cases,
+                } if self.const_bool(*scrutinee).is_some() => {
+                    let cond = self.const_bool(*scrutinee).unwrap();
+                    let chosen_case = cases[if cond { 0 } else { 1 }];
+                    let case_outputs = &func.at(chosen_case).def().outputs;
+                    for (output_idx, &case_output) in case_outputs.iter().enumerate() {
+                        self.replacements.insert(
+                            Value::ControlNodeOutput {
+                                control_node,
+                                output_idx: output_idx.try_into().unwrap(),
+                            },
+                            case_output,
+                        );
+                    }
+                    self.folded_selects
+                        .push((region, control_node, chosen_case));
+                    self.fold_in_region(chosen_case);
+                }
+                ControlNodeKind::Select { cases, .. } => {
+                    for &case in cases {
+                        self.fold_in_region(case);
+                    }
+                }
+                &ControlNodeKind::Loop { body, .. } => self.fold_in_region(body),
+                ControlNodeKind::ExitInvocation { .. } => {}
+            }
+        }
+    }
+}
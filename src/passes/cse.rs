@@ -0,0 +1,169 @@
+//! Common subexpression elimination (i.e. deduplication of pure instructions).
+
+use crate::passes::legalize::reachable_funcs;
+use crate::transform::{InnerInPlaceTransform, ReplaceValueWith};
+use crate::{
+    AttrSet, Context, ControlNode, ControlNodeKind, ControlRegion, DataInst, DataInstKind, DeclDef,
+    FuncDefBody, Module, Type, Value,
+};
+use rustc_hash::FxHashMap;
+use smallvec::SmallVec;
+
+/// Eliminate common subexpressions in all (structured) function definitions in
+/// `module`, i.e. replace all uses of a pure instruction (see
+/// [`DataInstDef::is_pure`](crate::DataInstDef::is_pure)) with the output of an
+/// identical instruction (same kind, inputs, output type, and attributes) which
+/// dominates it, and remove the now-unused duplicate.
+///
+/// Attributes which can't affect semantics (e.g. debuginfo, see
+/// [`Attr::is_non_semantic`](crate::Attr::is_non_semantic)) are ignored,
+/// and only those of the dominating instruction are kept.
+///
+/// As constants are interned (by [`Context`]), identical constant inputs are
+/// always the same [`Const`](crate::Const), so they need no special handling,
+/// while other inputs are compared after replacing any removed duplicates,
+/// which allows whole chains of duplicated instructions to be removed at once.
+///
+/// Only functions with fully structured control-flow are supported (i.e. after
+/// [`structurize_func_cfgs`](crate::passes::legalize::structurize_func_cfgs)).
+pub fn eliminate_common_subexprs(module: &mut Module) {
+    let cx = &module.cx();
+
+    for func in reachable_funcs(module) {
+        if let DeclDef::Present(func_def_body) = &mut module.funcs[func].def {
+            if func_def_body.unstructured_cfg.is_none() {
+                eliminate_common_subexprs_in_func(cx, func_def_body);
+            }
+        }
+    }
+}
+
+fn eliminate_common_subexprs_in_func(cx: &Context, func_def_body: &mut FuncDefBody) {
+    let mut eliminator = CommonSubexprEliminator {
+        cx,
+        func_def_body,
+
+        available: FxHashMap::default(),
+        available_keys: vec![],
+        replacements: FxHashMap::default(),
+        duplicate_insts: vec![],
+    };
+    eliminator.eliminate_in_region(func_def_body.body);
+    let CommonSubexprEliminator {
+        replacements,
+        duplicate_insts,
+        ..
+    } = eliminator;
+
+    for (block_node, inst) in duplicate_insts {
+        match &mut func_def_body.control_nodes[block_node].kind {
+            ControlNodeKind::Block { insts } => insts.remove(inst, &mut func_def_body.data_insts),
+            _ => unreachable!(),
+        }
+    }
+
+    if replacements.is_empty() {
+        return;
+    }
+
+    func_def_body
+        .inner_in_place_transform_with(&mut ReplaceValueWith(|v| replacements.get(&v).copied()));
+}
+
+/// The parts of a pure instruction which determine its output value (see also
+/// [`eliminate_common_subexprs`]).
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct InstKey {
+    // NOTE(eddyb) semantic attributes are included (instead of e.g. merging
+    // them), as they can affect semantics (e.g. SPIR-V `RelaxedPrecision`
+    // decorations), unlike the rest (e.g. debuginfo, or `SpvOriginalId`).
+    semantic_attrs: AttrSet,
+    kind: DataInstKind,
+    output_type: Option<Type>,
+    inputs: SmallVec<[Value; 2]>,
+}
+
+struct CommonSubexprEliminator<'a> {
+    cx: &'a Context,
+    func_def_body: &'a FuncDefBody,
+
+    /// Pure instructions available for reuse (i.e. dominating the current
+    /// position), keyed by everything which determines their output value.
+    available: FxHashMap<InstKey, DataInst>,
+
+    /// The keys in `available`, in the order they were added, to allow removing
+    /// the ones added in a nested [`ControlRegion`], when leaving it.
+    available_keys: Vec<InstKey>,
+
+    /// Values to replace all uses of (i.e. the outputs of duplicates).
+    replacements: FxHashMap<Value, Value>,
+
+    /// Instructions (alongside the `Block` [`ControlNode`] they're found in)
+    /// which duplicate an available instruction, and are to be removed.
+    duplicate_insts: Vec<(ControlNode, DataInst)>,
+}
+
+impl CommonSubexprEliminator<'_> {
+    fn eliminate_in_region(&mut self, region: ControlRegion) {
+        let func = self.func_def_body.at(region);
+        for func_at_control_node in func.at_children() {
+            let control_node = func_at_control_node.position;
+            match &func_at_control_node.def().kind {
+                ControlNodeKind::Block { insts } => {
+                    for func_at_inst in func.at(*insts) {
+                        let inst = func_at_inst.position;
+                        let inst_def = func_at_inst.def();
+                        if !inst_def.is_pure(self.cx) {
+                            continue;
+                        }
+
+                        // NOTE(eddyb) replacements always map to the original
+                        // (available) instruction, so this needs no recursion.
+                        let key = InstKey {
+                            semantic_attrs: inst_def.attrs.semantic_subset(self.cx),
+                            kind: inst_def.kind.clone(),
+                            output_type: inst_def.output_type,
+                            inputs: inst_def
+                                .inputs
+                                .iter()
+                                .map(|v| self.replacements.get(v).copied().unwrap_or(*v))
+                                .collect(),
+                        };
+                        match self.available.get(&key) {
+                            Some(&original_inst) => {
+                                self.replacements.insert(
+                                    Value::DataInstOutput(inst),
+                                    Value::DataInstOutput(original_inst),
+                                );
+                                self.duplicate_insts.push((control_node, inst));
+                            }
+                            None => {
+                                self.available.insert(key.clone(), inst);
+                                self.available_keys.push(key);
+                            }
+                        }
+                    }
+                }
+
+                // NOTE(eddyb) instructions in nested regions don't dominate
+                // anything outside of those regions (other than in the case of
+                // `Loop` bodies, which are conservatively treated the same).
+                ControlNodeKind::Select { cases, .. } => {
+                    for &case in cases {
+                        self.eliminate_in_nested_region(case);
+                    }
+                }
+                &ControlNodeKind::Loop { body, .. } => self.eliminate_in_nested_region(body),
+                ControlNodeKind::ExitInvocation { .. } => {}
+            }
+        }
+    }
+
+    fn eliminate_in_nested_region(&mut self, region: ControlRegion) {
+        let outer_available_count = self.available_keys.len();
+        self.eliminate_in_region(region);
+        for key in self.available_keys.drain(outer_available_count..) {
+            self.available.remove(&key);
+        }
+    }
+}
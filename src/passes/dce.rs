@@ -0,0 +1,320 @@
+//! Dead code elimination (DCE).
+
+use crate::passes::legalize::reachable_funcs;
+use crate::transform::{InnerInPlaceTransform, ReplaceValueWith};
+use crate::visit::{InnerVisit, Visitor};
+use crate::{
+    AttrSet, Const, Context, ControlNode, ControlNodeKind, ControlRegion, DeclDef,
+    Func, FuncDefBody, FxIndexMap, GlobalVar, Module, Type, Value,
+};
+use rustc_hash::FxHashMap;
+use smallvec::SmallVec;
+
+/// Remove dead code from all function definitions in `module`, i.e.:
+/// * [`DataInst`]s without side-effects (see [`DataInstDef::has_side_effects`]),
+///   whose outputs are unused (and `Block`s left empty by their removal)
+/// * unused `Select` outputs (and the case outputs providing their values)
+/// * unused `Loop` body inputs (and the `initial_inputs`/body outputs for them)
+/// * unused inputs of [`ControlRegion`]s in the unstructured CFG (and the
+///   values provided for them by every branch targeting those regions)
+///
+/// Function parameters (i.e. the inputs of function bodies) are never removed.
+///
+/// [`DataInstDef::has_side_effects`]: crate::DataInstDef::has_side_effects
+pub fn remove_dead_code(module: &mut Module) {
+    let cx = &module.cx();
+
+    for func in reachable_funcs(module) {
+        if let DeclDef::Present(func_def_body) = &mut module.funcs[func].def {
+            while remove_dead_code_in_func(cx, func_def_body) {}
+        }
+    }
+}
+
+/// Perform one round of [`remove_dead_code`] on `func_def_body`,
+/// returning `true` if any changes were made.
+//
+// FIXME(eddyb) this only counts uses, so it can't remove cycles of values which
+// are only used by each other (e.g. an otherwise unused loop counter), other
+// than the trivial case of a loop body input being passed to the next iteration.
+fn remove_dead_code_in_func(cx: &Context, func_def_body: &mut FuncDefBody) -> bool {
+    let use_counts = value_use_counts(func_def_body);
+    let use_count = |v| use_counts.get(&v).copied().unwrap_or(0);
+
+    let mut removed_insts = vec![];
+    let mut removed_select_outputs = FxIndexMap::<_, SmallVec<[u32; 2]>>::default();
+    let mut removed_loop_body_inputs = FxIndexMap::<_, SmallVec<[u32; 2]>>::default();
+    let mut removed_region_inputs = FxIndexMap::<_, SmallVec<[u32; 2]>>::default();
+
+    let mut regions = vec![func_def_body.body];
+    if let Some(cfg) = &func_def_body.unstructured_cfg {
+        regions.extend(
+            cfg.rev_post_order(func_def_body)
+                .filter(|&region| region != func_def_body.body),
+        );
+
+        // NOTE(eddyb) a region passing its own input back to itself (i.e. on
+        // a backedge) doesn't count as a use which keeps that input alive.
+        for region in cfg.rev_post_order(func_def_body) {
+            if region == func_def_body.body {
+                continue;
+            }
+            let self_inputs = cfg
+                .control_inst_on_exit_from
+                .get(region)
+                .and_then(|control_inst| control_inst.target_inputs.get(&region));
+            let region_def = func_def_body.at(region).def();
+            for input_idx in 0..region_def.inputs.len() {
+                let input_idx = input_idx.try_into().unwrap();
+                let input = Value::ControlRegionInput { region, input_idx };
+                let self_uses = self_inputs
+                    .map_or(0, |inputs| usize::from(inputs[input_idx as usize] == input));
+                if use_count(input) == self_uses {
+                    removed_region_inputs
+                        .entry(region)
+                        .or_default()
+                        .push(input_idx);
+                }
+            }
+        }
+    }
+
+    while let Some(region) = regions.pop() {
+        let func = func_def_body.at(region);
+        for func_at_control_node in func.at_children() {
+            let control_node = func_at_control_node.position;
+            let control_node_def = func_at_control_node.def();
+            match &control_node_def.kind {
+                ControlNodeKind::Block { insts } => {
+                    for func_at_inst in func.at(*insts) {
+                        let inst = func_at_inst.position;
+                        if !func_at_inst.def().has_side_effects(cx)
+                            && use_count(Value::DataInstOutput(inst)) == 0
+                        {
+                            removed_insts.push((control_node, inst));
+                        }
+                    }
+                }
+                ControlNodeKind::Select { cases, .. } => {
+                    for output_idx in 0..control_node_def.outputs.len() {
+                        let output_idx = output_idx.try_into().unwrap();
+                        let output = Value::ControlNodeOutput {
+                            control_node,
+                            output_idx,
+                        };
+                        if use_count(output) == 0 {
+                            removed_select_outputs
+                                .entry(control_node)
+                                .or_default()
+                                .push(output_idx);
+                        }
+                    }
+                    regions.extend(cases.iter().copied());
+                }
+                &ControlNodeKind::Loop { body, .. } => {
+                    let body_def = func.at(body).def();
+                    for input_idx in 0..body_def.inputs.len() {
+                        let input_idx = input_idx.try_into().unwrap();
+                        let input = Value::ControlRegionInput {
+                            region: body,
+                            input_idx,
+                        };
+                        let self_uses = usize::from(body_def.outputs[input_idx as usize] == input);
+                        if use_count(input) == self_uses {
+                            removed_loop_body_inputs
+                                .entry(control_node)
+                                .or_default()
+                                .push(input_idx);
+                        }
+                    }
+                    regions.push(body);
+                }
+                ControlNodeKind::ExitInvocation { .. } => {}
+            }
+        }
+    }
+    if removed_insts.is_empty()
+        && removed_select_outputs.is_empty()
+        && removed_loop_body_inputs.is_empty()
+        && removed_region_inputs.is_empty()
+    {
+        return false;
+    }
+
+    let mut renumbered_values = FxHashMap::default();
+
+    // Keeps only the elements of `values` not at any of the `removed` indices,
+    // returning the `(old, new)` indices for the kept elements which moved.
+    fn retain_unremoved<T>(
+        values: &mut SmallVec<[T; 2]>,
+        removed: &[u32],
+    ) -> impl Iterator<Item = (u32, u32)> {
+        let old_values = std::mem::take(values);
+        let mut moves = SmallVec::<[_; 2]>::new();
+        for (old_idx, v) in (0..).zip(old_values) {
+            if !removed.contains(&old_idx) {
+                let new_idx = values.len().try_into().unwrap();
+                if new_idx != old_idx {
+                    moves.push((old_idx, new_idx));
+                }
+                values.push(v);
+            }
+        }
+        moves.into_iter()
+    }
+
+    for (select_node, removed) in removed_select_outputs {
+        let select_node_def = &mut func_def_body.control_nodes[select_node];
+        let cases = match &select_node_def.kind {
+            ControlNodeKind::Select { cases, .. } => cases,
+            _ => unreachable!(),
+        };
+        for &case in cases {
+            let _ = retain_unremoved(&mut func_def_body.control_regions[case].outputs, &removed);
+        }
+        for (old_idx, new_idx) in retain_unremoved(&mut select_node_def.outputs, &removed) {
+            renumbered_values.insert(
+                Value::ControlNodeOutput {
+                    control_node: select_node,
+                    output_idx: old_idx,
+                },
+                Value::ControlNodeOutput {
+                    control_node: select_node,
+                    output_idx: new_idx,
+                },
+            );
+        }
+    }
+    for (loop_node, removed) in removed_loop_body_inputs {
+        let (initial_inputs, body) = match &mut func_def_body.control_nodes[loop_node].kind {
+            ControlNodeKind::Loop {
+                initial_inputs,
+                body,
+                ..
+            } => (initial_inputs, *body),
+            _ => unreachable!(),
+        };
+        let _ = retain_unremoved(initial_inputs, &removed);
+        let body_def = &mut func_def_body.control_regions[body];
+        let _ = retain_unremoved(&mut body_def.outputs, &removed);
+        for (old_idx, new_idx) in retain_unremoved(&mut body_def.inputs, &removed) {
+            renumbered_values.insert(
+                Value::ControlRegionInput {
+                    region: body,
+                    input_idx: old_idx,
+                },
+                Value::ControlRegionInput {
+                    region: body,
+                    input_idx: new_idx,
+                },
+            );
+        }
+    }
+    if !removed_region_inputs.is_empty() {
+        let sources: SmallVec<[_; 8]> = func_def_body
+            .unstructured_cfg
+            .as_ref()
+            .unwrap()
+            .rev_post_order(func_def_body)
+            .collect();
+        let cfg = func_def_body.unstructured_cfg.as_mut().unwrap();
+        for source in sources {
+            if let Some(control_inst) = cfg.control_inst_on_exit_from.get_mut(source) {
+                for (target, target_inputs) in &mut control_inst.target_inputs {
+                    if let Some(removed) = removed_region_inputs.get(target) {
+                        let _ = retain_unremoved(target_inputs, removed);
+                    }
+                }
+            }
+        }
+        for (region, removed) in removed_region_inputs {
+            let region_def = &mut func_def_body.control_regions[region];
+            for (old_idx, new_idx) in retain_unremoved(&mut region_def.inputs, &removed) {
+                renumbered_values.insert(
+                    Value::ControlRegionInput {
+                        region,
+                        input_idx: old_idx,
+                    },
+                    Value::ControlRegionInput {
+                        region,
+                        input_idx: new_idx,
+                    },
+                );
+            }
+        }
+    }
+    for (block_node, inst) in removed_insts {
+        match &mut func_def_body.control_nodes[block_node].kind {
+            ControlNodeKind::Block { insts } => insts.remove(inst, &mut func_def_body.data_insts),
+            _ => unreachable!(),
+        }
+    }
+    remove_empty_blocks(func_def_body);
+
+    if !renumbered_values.is_empty() {
+        func_def_body.inner_in_place_transform_with(&mut ReplaceValueWith(|v| {
+            renumbered_values.get(&v).copied()
+        }));
+    }
+
+    true
+}
+
+/// Remove all the `Block` [`ControlNode`]s without any [`DataInst`]s.
+fn remove_empty_blocks(func_def_body: &mut FuncDefBody) {
+    let mut regions = vec![func_def_body.body];
+    if let Some(cfg) = &func_def_body.unstructured_cfg {
+        regions.extend(
+            cfg.rev_post_order(func_def_body)
+                .filter(|&region| region != func_def_body.body),
+        );
+    }
+
+    let mut empty_blocks: Vec<(ControlRegion, ControlNode)> = vec![];
+    while let Some(region) = regions.pop() {
+        for func_at_control_node in func_def_body.at(region).at_children() {
+            match &func_at_control_node.def().kind {
+                ControlNodeKind::Block { insts } => {
+                    if insts.is_empty() {
+                        empty_blocks.push((region, func_at_control_node.position));
+                    }
+                }
+                ControlNodeKind::Select { cases, .. } => regions.extend(cases.iter().copied()),
+                &ControlNodeKind::Loop { body, .. } => regions.push(body),
+                ControlNodeKind::ExitInvocation { .. } => {}
+            }
+        }
+    }
+    for (region, block_node) in empty_blocks {
+        func_def_body.control_regions[region]
+            .children
+            .remove(block_node, &mut func_def_body.control_nodes);
+    }
+}
+
+/// Count the uses of every [`Value`] (including constants) in `func_def_body`.
+pub(crate) fn value_use_counts(func_def_body: &FuncDefBody) -> FxHashMap<Value, usize> {
+    let mut use_counter = UseCounter {
+        use_counts: FxHashMap::default(),
+    };
+    func_def_body.inner_visit_with(&mut use_counter);
+    use_counter.use_counts
+}
+
+/// [`Visitor`] counting the uses of every [`Value`].
+struct UseCounter {
+    use_counts: FxHashMap<Value, usize>,
+}
+
+impl Visitor<'_> for UseCounter {
+    // NOTE(eddyb) uses of anything other than values are irrelevant here.
+    fn visit_attr_set_use(&mut self, _attrs: AttrSet) {}
+    fn visit_type_use(&mut self, _ty: Type) {}
+    fn visit_const_use(&mut self, _ct: Const) {}
+    fn visit_global_var_use(&mut self, _gv: GlobalVar) {}
+    fn visit_func_use(&mut self, _func: Func) {}
+
+    fn visit_value_use(&mut self, v: &Value) {
+        *self.use_counts.entry(*v).or_default() += 1;
+    }
+}
@@ -0,0 +1,295 @@
+//! Injection of `NonSemantic.DebugPrintf` calls (for debugging shaders).
+
+use crate::passes::legalize::reachable_funcs;
+use crate::{
+    cfg, spv, Attr, AttrSet, Context, ControlNodeDef, ControlNodeKind, ControlRegion, DataInstDef,
+    DataInstKind, DeclDef, EntityList, ExportKey, Exportee, Func, FuncDefBody, FxIndexMap,
+    InternedStr, Module, ModuleDialect, Type, TypeCtor, TypeCtorArg, TypeDef, Value,
+};
+use smallvec::SmallVec;
+
+/// Name of the extended instruction set (i.e. the `OpExtInstImport` operand).
+const EXT_INST_SET_NAME: &str = "NonSemantic.DebugPrintf";
+
+/// The only instruction in the `NonSemantic.DebugPrintf` extended instruction
+/// set, taking an `OpString` format string, followed by the values to format.
+const DEBUG_PRINTF: u32 = 1;
+
+/// Point in a function where [`inject_debug_printf`] can insert a printf call.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum InjectionPoint {
+    /// Start of the function body, printing the function's parameters.
+    FuncEntry,
+
+    /// Right before any `return`, printing the returned value (if any).
+    BeforeReturn,
+}
+
+/// Insert `NonSemantic.DebugPrintf` calls into all the functions (reachable from
+/// `module`'s exports) for which `should_inject(func, point)` returns `true`.
+///
+/// Values are formatted based on their types, with only 32-bit integer and
+/// floating-point scalars (and vectors of them) being printable, while values
+/// of any other types are printed as `_` (i.e. only their presence is noted).
+///
+/// The `SPV_KHR_non_semantic_info` extension is also added to the module (if
+/// it's not part of the SPIR-V version already), as it's required by SPIR-V
+/// for importing any `NonSemantic.*` extended instruction sets.
+pub fn inject_debug_printf(
+    module: &mut Module,
+    mut should_inject: impl FnMut(Func, InjectionPoint) -> bool,
+) {
+    let cx = &module.cx();
+    let wk = &spv::spec::Spec::get().well_known;
+
+    let void_type = cx.intern(TypeDef {
+        attrs: AttrSet::default(),
+        ctor: TypeCtor::SpvInst(wk.OpTypeVoid.into()),
+        ctor_args: [].into_iter().collect(),
+    });
+    let injector = DebugPrintfInjector {
+        cx,
+        ext_set: cx.intern(EXT_INST_SET_NAME),
+        void_type,
+    };
+
+    // NOTE(eddyb) functions without a name of their own (i.e. `OpName`) are
+    // named after their exports, which is most useful for entry-points.
+    let mut export_names = FxIndexMap::default();
+    for (export_key, &exportee) in &module.exports {
+        let name = match export_key {
+            &ExportKey::LinkName(name) => cx[name].to_string(),
+            ExportKey::SpvEntryPoint { imms, .. } => match imms[..] {
+                [_, ref name_imms @ ..] => {
+                    spv::extract_literal_string(name_imms).unwrap_or_default()
+                }
+                [] => continue,
+            },
+        };
+        if let Exportee::Func(func) = exportee {
+            export_names.entry(func).or_insert(name);
+        }
+    }
+
+    let mut injected_any = false;
+    for func in reachable_funcs(module) {
+        let inject_at_entry = should_inject(func, InjectionPoint::FuncEntry);
+        let inject_before_return = should_inject(func, InjectionPoint::BeforeReturn);
+        if !(inject_at_entry || inject_before_return) {
+            continue;
+        }
+
+        let func_decl = &mut module.funcs[func];
+        let name = func_name(cx, func_decl.attrs)
+            .or_else(|| export_names.get(&func).cloned())
+            .unwrap_or_else(|| "<unnamed function>".to_string())
+            .replace('%', "%%");
+        let param_types: SmallVec<[_; 4]> = func_decl.params.iter().map(|p| p.ty).collect();
+        let func_def_body = match &mut func_decl.def {
+            DeclDef::Present(func_def_body) => func_def_body,
+            DeclDef::Imported(_) => continue,
+        };
+
+        if inject_at_entry {
+            let body = func_def_body.body;
+            let params = param_types.iter().enumerate().map(|(i, &ty)| {
+                (
+                    Value::ControlRegionInput {
+                        region: body,
+                        input_idx: i.try_into().unwrap(),
+                    },
+                    ty,
+                )
+            });
+            let (format_args, inputs) = injector.format_values(params);
+            injector.insert_printf(
+                func_def_body,
+                body,
+                true,
+                format!("entering `{name}`({format_args})"),
+                inputs,
+            );
+            injected_any = true;
+        }
+
+        if inject_before_return {
+            // NOTE(eddyb) returns are either region terminators (in unstructured
+            // CFGs), or implied by the end of the body (once structurized).
+            let returns: SmallVec<[_; 4]> = match &func_def_body.unstructured_cfg {
+                Some(cfg) => cfg
+                    .rev_post_order(func_def_body)
+                    .filter_map(|region| {
+                        let control_inst = &cfg.control_inst_on_exit_from[region];
+                        matches!(control_inst.kind, cfg::ControlInstKind::Return)
+                            .then(|| (region, control_inst.inputs.clone()))
+                    })
+                    .collect(),
+                None => [(
+                    func_def_body.body,
+                    func_def_body.at_body().def().outputs.clone(),
+                )]
+                .into_iter()
+                .collect(),
+            };
+            for (region, return_values) in returns {
+                let return_values: SmallVec<[_; 1]> = return_values
+                    .iter()
+                    .map(|&v| (v, func_def_body.at(v).type_of(cx)))
+                    .collect();
+                let (format_args, inputs) = injector.format_values(return_values);
+                let format_string = if format_args.is_empty() {
+                    format!("returning from `{name}`")
+                } else {
+                    format!("returning from `{name}`: {format_args}")
+                };
+                injector.insert_printf(func_def_body, region, false, format_string, inputs);
+                injected_any = true;
+            }
+        }
+    }
+
+    if injected_any {
+        match &mut module.dialect {
+            ModuleDialect::Spv(dialect) => {
+                if (dialect.version_major, dialect.version_minor) < (1, 6) {
+                    dialect
+                        .extensions
+                        .insert("SPV_KHR_non_semantic_info".to_string());
+                }
+            }
+        }
+    }
+}
+
+/// Get the name of a function from its `OpName` (kept in `attrs`), if any.
+fn func_name(cx: &Context, attrs: AttrSet) -> Option<String> {
+    let wk = &spv::spec::Spec::get().well_known;
+
+    cx[attrs].attrs.iter().find_map(|attr| match attr {
+        Attr::SpvAnnotation(inst) if inst.opcode == wk.OpName => {
+            spv::extract_literal_string(&inst.imms).ok()
+        }
+        _ => None,
+    })
+}
+
+struct DebugPrintfInjector<'a> {
+    cx: &'a Context,
+    ext_set: InternedStr,
+    void_type: Type,
+}
+
+impl DebugPrintfInjector<'_> {
+    /// Get the `printf`-style format specifier for values of type `ty`, if
+    /// `NonSemantic.DebugPrintf` supports printing them.
+    fn format_specifier(&self, ty: Type) -> Option<String> {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        let scalar_conversion = |ty: Type| {
+            let ty_def = &self.cx[ty];
+            let inst = match &ty_def.ctor {
+                TypeCtor::SpvInst(inst) => inst,
+                _ => return None,
+            };
+            match inst.imms[..] {
+                [spv::Imm::Short(_, 32), spv::Imm::Short(_, signedness)]
+                    if inst.opcode == wk.OpTypeInt =>
+                {
+                    Some(if signedness != 0 { 'd' } else { 'u' })
+                }
+                [spv::Imm::Short(_, 32)] if inst.opcode == wk.OpTypeFloat => Some('f'),
+                _ => None,
+            }
+        };
+
+        if let Some(conversion) = scalar_conversion(ty) {
+            return Some(format!("%{conversion}"));
+        }
+
+        let ty_def = &self.cx[ty];
+        match (&ty_def.ctor, &ty_def.ctor_args[..]) {
+            (TypeCtor::SpvInst(inst), &[TypeCtorArg::Type(elem_type)])
+                if inst.opcode == wk.OpTypeVector =>
+            {
+                match inst.imms[..] {
+                    [spv::Imm::Short(_, count @ 2..=4)] => {
+                        Some(format!("%v{count}{}", scalar_conversion(elem_type)?))
+                    }
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// Build the comma-separated format string fragment for `values`, and the
+    /// subset of `values` which need to be passed to `DebugPrintf` for it.
+    fn format_values(
+        &self,
+        values: impl IntoIterator<Item = (Value, Type)>,
+    ) -> (String, SmallVec<[Value; 4]>) {
+        let mut format_args = vec![];
+        let mut inputs = SmallVec::new();
+        for (v, ty) in values {
+            match self.format_specifier(ty) {
+                Some(spec) => {
+                    format_args.push(spec);
+                    inputs.push(v);
+                }
+                None => format_args.push("_".to_string()),
+            }
+        }
+        (format_args.join(", "), inputs)
+    }
+
+    /// Insert a `DebugPrintf` call (in its own block) at the start or the end
+    /// of `region` (based on `at_start`).
+    fn insert_printf(
+        &self,
+        func_def_body: &mut FuncDefBody,
+        region: ControlRegion,
+        at_start: bool,
+        format_string: String,
+        values: SmallVec<[Value; 4]>,
+    ) {
+        let cx = self.cx;
+
+        let format_string =
+            spv::lower::const_spv_string_literal_for_ext_inst(cx, cx.intern(format_string));
+        let printf_inst = func_def_body.data_insts.define(
+            cx,
+            DataInstDef {
+                attrs: AttrSet::default(),
+                kind: DataInstKind::SpvExtInst {
+                    ext_set: self.ext_set,
+                    inst: DEBUG_PRINTF,
+                },
+                output_type: Some(self.void_type),
+                inputs: [Value::Const(format_string)]
+                    .into_iter()
+                    .chain(values)
+                    .collect(),
+            }
+            .into(),
+        );
+
+        let mut insts = EntityList::empty();
+        insts.insert_last(printf_inst, &mut func_def_body.data_insts);
+        let block = func_def_body.control_nodes.define(
+            cx,
+            ControlNodeDef {
+                attrs: AttrSet::default(),
+                kind: ControlNodeKind::Block { insts },
+                outputs: [].into_iter().collect(),
+            }
+            .into(),
+        );
+
+        let children = &mut func_def_body.control_regions[region].children;
+        if at_start {
+            children.insert_first(block, &mut func_def_body.control_nodes);
+        } else {
+            children.insert_last(block, &mut func_def_body.control_nodes);
+        }
+    }
+}
@@ -0,0 +1,255 @@
+//! If-conversion (i.e. replacing tiny `if`-`else`s with `OpSelect`s).
+
+use crate::passes::legalize::reachable_funcs;
+use crate::spv;
+use crate::transform::{InnerInPlaceTransform, ReplaceValueWith};
+use crate::{
+    AttrSet, Context, ControlNode, ControlNodeKind, ControlRegion, DataInstDef, DataInstKind,
+    DeclDef, EntityList, FuncDefBody, Module, SelectionKind, Type, TypeCtor, Value,
+};
+use rustc_hash::FxHashMap;
+use smallvec::SmallVec;
+
+/// Options for if-conversion (see [`convert_ifs_to_selects`]).
+#[derive(Clone)]
+pub struct IfConversionOptions {
+    /// Only `Select`s with at most this many instructions in each of their
+    /// cases are converted, as all of them will always be executed afterwards.
+    pub max_case_inst_count: usize,
+}
+
+impl Default for IfConversionOptions {
+    fn default() -> Self {
+        Self {
+            max_case_inst_count: 4,
+        }
+    }
+}
+
+/// Convert tiny `BoolCond` `Select`s (within the limits of `options`) in all
+/// function definitions in `module`, into straight-line code, i.e.:
+/// ```text
+/// y = if c {
+///     a = f(x)
+///     a
+/// } else {
+///     x
+/// }
+/// ```
+/// becomes (with the `Select` being replaced by a `Block`, in-place):
+/// ```text
+/// a = f(x)
+/// y = spv.OpSelect(c, a, x)
+/// ```
+///
+/// Only `Select`s whose cases contain nothing other than instructions that can
+/// be safely executed unconditionally (see [`DataInstDef::is_pure`], though e.g.
+/// integer division is also excluded), and whose outputs are all scalars (as
+/// `OpSelect` only supports a scalar condition for those, before SPIR-V 1.4),
+/// are converted - nested `Select`s are always converted first, so that whole
+/// trees of `if`-`else`s can be flattened (if they're small enough).
+///
+/// Like other branches, `Select`s can cause divergence on GPUs, which this avoids
+/// (at the cost of executing both cases), making it more profitable the smaller
+/// the cases are.
+///
+/// [`DataInstDef::is_pure`]: crate::DataInstDef::is_pure
+pub fn convert_ifs_to_selects(module: &mut Module, options: &IfConversionOptions) {
+    let cx = &module.cx();
+
+    for func in reachable_funcs(module) {
+        if let DeclDef::Present(func_def_body) = &mut module.funcs[func].def {
+            let mut regions = vec![func_def_body.body];
+            if let Some(cfg) = &func_def_body.unstructured_cfg {
+                regions.extend(
+                    cfg.rev_post_order(func_def_body)
+                        .filter(|&region| region != func_def_body.body),
+                );
+            }
+
+            let mut converter = IfConverter {
+                cx,
+                options,
+                replacements: FxHashMap::default(),
+            };
+            for region in regions {
+                converter.convert_in_region(func_def_body, region);
+            }
+
+            let replacements = converter.replacements;
+            if replacements.is_empty() {
+                continue;
+            }
+
+            func_def_body.inner_in_place_transform_with(&mut ReplaceValueWith(|v| {
+                replacements.get(&v).copied()
+            }));
+        }
+    }
+}
+
+struct IfConverter<'a> {
+    cx: &'a Context,
+    options: &'a IfConversionOptions,
+
+    /// Values to replace all uses of (i.e. the outputs of converted `Select`s).
+    replacements: FxHashMap<Value, Value>,
+}
+
+impl IfConverter<'_> {
+    fn resolve(&self, mut v: Value) -> Value {
+        while let Some(&new) = self.replacements.get(&v) {
+            v = new;
+        }
+        v
+    }
+
+    fn convert_in_region(&mut self, func_def_body: &mut FuncDefBody, region: ControlRegion) {
+        let control_nodes: SmallVec<[_; 8]> = func_def_body
+            .at(region)
+            .at_children()
+            .into_iter()
+            .map(|func_at_control_node| func_at_control_node.position)
+            .collect();
+        for control_node in control_nodes {
+            match &func_def_body.at(control_node).def().kind {
+                ControlNodeKind::Block { .. } | ControlNodeKind::ExitInvocation { .. } => {}
+                ControlNodeKind::Select { kind, cases, .. } => {
+                    let is_bool_cond = matches!(kind, SelectionKind::BoolCond);
+                    let cases = cases.clone();
+                    for &case in &cases {
+                        self.convert_in_region(func_def_body, case);
+                    }
+                    if is_bool_cond {
+                        self.try_convert_select(func_def_body, control_node);
+                    }
+                }
+                &ControlNodeKind::Loop { body, .. } => self.convert_in_region(func_def_body, body),
+            }
+        }
+    }
+
+    fn try_convert_select(&mut self, func_def_body: &mut FuncDefBody, select_node: ControlNode) {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        let select_def = func_def_body.at(select_node).def();
+        let (condition, cases) = match &select_def.kind {
+            ControlNodeKind::Select {
+                kind: SelectionKind::BoolCond,
+                scrutinee,
+                cases,
+            } => (*scrutinee, cases.clone()),
+            _ => unreachable!(),
+        };
+        if !select_def
+            .outputs
+            .iter()
+            .all(|output| self.is_selectable_type(output.ty))
+        {
+            return;
+        }
+
+        // Collect the `Block`s making up each case (if they're all that's there).
+        let mut case_blocks = SmallVec::<[ControlNode; 4]>::new();
+        for &case in &cases {
+            let mut case_inst_count = 0;
+            for func_at_control_node in func_def_body.at(case).at_children() {
+                let insts = match func_at_control_node.def().kind {
+                    ControlNodeKind::Block { insts } => insts,
+                    _ => return,
+                };
+                for func_at_inst in func_at_control_node.at(insts) {
+                    if !self.can_speculate(func_at_inst.def()) {
+                        return;
+                    }
+                    case_inst_count += 1;
+                }
+                case_blocks.push(func_at_control_node.position);
+            }
+            if case_inst_count > self.options.max_case_inst_count {
+                return;
+            }
+        }
+
+        // Move all the instructions out of the cases (in order), followed by
+        // an `OpSelect` for every output which differs between the cases.
+        let mut insts = EntityList::empty();
+        for case_block in case_blocks {
+            let case_insts = match &mut func_def_body.control_nodes[case_block].kind {
+                ControlNodeKind::Block { insts } => std::mem::take(insts),
+                _ => unreachable!(),
+            };
+            insts.append(case_insts, &mut func_def_body.data_insts);
+        }
+        for case in &cases {
+            func_def_body.control_regions[*case].children = EntityList::empty();
+        }
+
+        let output_types: SmallVec<[Type; 2]> = func_def_body.control_nodes[select_node]
+            .outputs
+            .iter()
+            .map(|output| output.ty)
+            .collect();
+        for (output_idx, output_type) in output_types.into_iter().enumerate() {
+            let [then_value, else_value] = [cases[0], cases[1]]
+                .map(|case| self.resolve(func_def_body.at(case).def().outputs[output_idx]));
+            let value = if then_value == else_value {
+                then_value
+            } else {
+                let inst = func_def_body.data_insts.define(
+                    self.cx,
+                    DataInstDef {
+                        attrs: AttrSet::default(),
+                        kind: DataInstKind::SpvInst(wk.OpSelect.into()),
+                        output_type: Some(output_type),
+                        inputs: [condition, then_value, else_value].into_iter().collect(),
+                    }
+                    .into(),
+                );
+                insts.insert_last(inst, &mut func_def_body.data_insts);
+                Value::DataInstOutput(inst)
+            };
+            self.replacements.insert(
+                Value::ControlNodeOutput {
+                    control_node: select_node,
+                    output_idx: output_idx.try_into().unwrap(),
+                },
+                value,
+            );
+        }
+
+        // NOTE(eddyb) the `Select` is replaced in-place, by turning it into a
+        // `Block`, which avoids having to insert anything into its parent region.
+        let select_def = &mut func_def_body.control_nodes[select_node];
+        select_def.kind = ControlNodeKind::Block { insts };
+        select_def.outputs.clear();
+    }
+
+    /// Whether `OpSelect` can be used with values of type `ty`, and a scalar
+    /// condition, in all SPIR-V versions (i.e. `ty` is a scalar type).
+    fn is_selectable_type(&self, ty: Type) -> bool {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        match &self.cx[ty].ctor {
+            TypeCtor::SpvInst(inst) => {
+                [wk.OpTypeBool, wk.OpTypeInt, wk.OpTypeFloat].contains(&inst.opcode)
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether `inst_def` can be executed even when it wouldn't have been
+    /// (i.e. "speculatively"), without changing the behavior of the program.
+    fn can_speculate(&self, inst_def: &DataInstDef) -> bool {
+        // NOTE(eddyb) integer division (and remainder) is undefined behavior
+        // for a divisor of `0`, which a condition may have been guarding against.
+        let is_int_division = match &inst_def.kind {
+            DataInstKind::SpvInst(inst) => matches!(
+                inst.opcode.name(),
+                "OpUDiv" | "OpSDiv" | "OpUMod" | "OpSMod" | "OpSRem"
+            ),
+            _ => false,
+        };
+        inst_def.is_pure(self.cx) && !is_int_division
+    }
+}
@@ -0,0 +1,379 @@
+//! Combined image/sampler splitting (i.e. of `OpTypeSampledImage` global variables).
+
+use crate::passes::binding_remap::DescriptorBinding;
+use crate::passes::legalize::reachable_global_vars_and_funcs;
+use crate::visit::{InnerVisit, Visitor};
+use crate::{
+    spv, AddrSpace, Attr, AttrSet, AttrSetDef, Const, ConstCtor, ConstDef, Context,
+    ControlNodeKind, ControlRegion, DataInstDef, DataInstKind, DeclDef, EntityList, ExportKey,
+    Func, FuncDefBody, FxIndexMap, GlobalVar, GlobalVarDecl, Module, Type, TypeCtor, TypeCtorArg,
+    TypeDef, Value,
+};
+use rustc_hash::{FxHashMap, FxHashSet};
+use smallvec::SmallVec;
+use std::mem;
+
+/// Separate image and sampler [`GlobalVar`]s, replacing a combined image/sampler
+/// [`GlobalVar`] (see [`split_combined_image_samplers`]).
+#[derive(Clone)]
+pub struct SplitImageSampler {
+    /// The original combined image/sampler global variable (now unused).
+    pub combined: GlobalVar,
+
+    pub image: GlobalVar,
+    pub sampler: GlobalVar,
+
+    /// The binding of `image` (same as that of `combined`), if any.
+    pub image_binding: Option<DescriptorBinding>,
+
+    /// The binding of `sampler` (newly allocated in the same set), if any.
+    pub sampler_binding: Option<DescriptorBinding>,
+}
+
+/// Split every (reachable) combined image/sampler global variable (i.e. of
+/// `OpTypeSampledImage` type) in `module` into separate image and sampler global
+/// variables, for targets without combined image/samplers (e.g. HLSL or MSL).
+///
+/// Every `OpLoad` of a combined image/sampler is replaced with an `OpLoad` of
+/// each of the new global variables, combined by `OpSampledImage`, so all the
+/// sampling instructions (and any other uses) keep working unchanged.
+///
+/// The image keeps the original `DescriptorSet`/`Binding`, while the sampler
+/// gets a new `Binding`, in the same set, after all the ones already in use,
+/// and the returned [`SplitImageSampler`]s can be used as a reflection report
+/// (or to further remap bindings, e.g. with [`binding_remap`]).
+///
+/// Combined image/samplers used in any way other than `OpLoad` (e.g. passed by
+/// pointer to a function call), and arrays of them, are left unchanged.
+///
+/// [`binding_remap`]: crate::passes::binding_remap
+//
+// FIXME(eddyb) support arrays of combined image/samplers (i.e. splitting them
+// into arrays of images and samplers, and their `OpAccessChain`s as well).
+pub fn split_combined_image_samplers(module: &mut Module) -> Vec<SplitImageSampler> {
+    let wk = &spv::spec::Spec::get().well_known;
+    let cx = &module.cx();
+
+    let (global_vars, funcs) = reachable_global_vars_and_funcs(module);
+
+    let mut ptr_to_combined = FxIndexMap::default();
+    let mut next_binding_per_set = FxHashMap::<u32, u32>::default();
+    for &gv in &global_vars {
+        let gv_decl = &module.global_vars[gv];
+        if let Some(binding) = DescriptorBinding::from_attrs(cx, gv_decl.attrs) {
+            let next_binding = next_binding_per_set.entry(binding.set).or_default();
+            *next_binding = (*next_binding).max(binding.binding.saturating_add(1));
+        }
+
+        if gv_decl.addr_space != AddrSpace::SpvStorageClass(wk.UniformConstant) {
+            continue;
+        }
+        let is_combined = gv_decl
+            .type_of_ptr_to
+            .pointee_type(cx)
+            .is_some_and(|pointee| matches!(cx[pointee].ctor, TypeCtor::SampledImage));
+        if is_combined {
+            ptr_to_combined.insert(ptr_to_global_var(cx, gv_decl.type_of_ptr_to, gv), gv);
+        }
+    }
+
+    // Only combined image/samplers which are only ever used by `OpLoad` can be split.
+    let mut usage_checker = UsageChecker {
+        ptr_to_combined: &ptr_to_combined,
+        unsplittable: FxHashSet::default(),
+    };
+    for &func in &funcs {
+        module.funcs[func].inner_visit_with(&mut usage_checker);
+    }
+    let unsplittable = usage_checker.unsplittable;
+    ptr_to_combined.retain(|_, gv| !unsplittable.contains(gv));
+    if ptr_to_combined.is_empty() {
+        return vec![];
+    }
+
+    let mut splits = vec![];
+    let mut load_replacements = FxHashMap::default();
+    for &combined in ptr_to_combined.values() {
+        let combined_decl = module.global_vars[combined].clone();
+        let image_type = match combined_decl
+            .type_of_ptr_to
+            .pointee_type(cx)
+            .map(|pointee| &cx[pointee].ctor_args[..])
+        {
+            Some(&[TypeCtorArg::Type(image_type)]) => image_type,
+            _ => unreachable!(),
+        };
+        let sampler_type = cx.intern(TypeDef {
+            attrs: AttrSet::default(),
+            ctor: TypeCtor::Sampler,
+            ctor_args: [].into_iter().collect(),
+        });
+
+        let image_binding = DescriptorBinding::from_attrs(cx, combined_decl.attrs);
+        let sampler_binding = image_binding.map(|binding| {
+            let next_binding = next_binding_per_set.entry(binding.set).or_default();
+            let sampler_binding = DescriptorBinding {
+                set: binding.set,
+                binding: *next_binding,
+            };
+            *next_binding = next_binding.saturating_add(1);
+            sampler_binding
+        });
+
+        let image_type_of_ptr_to = with_pointee_type(cx, combined_decl.type_of_ptr_to, image_type);
+        let image = module.global_vars.define(
+            cx,
+            GlobalVarDecl {
+                type_of_ptr_to: image_type_of_ptr_to,
+                ..combined_decl.clone()
+            },
+        );
+
+        let sampler_type_of_ptr_to =
+            with_pointee_type(cx, combined_decl.type_of_ptr_to, sampler_type);
+        let mut sampler_attrs = with_name_suffix(cx, combined_decl.attrs, "_sampler");
+        if let Some(sampler_binding) = sampler_binding {
+            sampler_attrs = sampler_binding.apply_to_attrs(cx, sampler_attrs);
+        }
+        let sampler = module.global_vars.define(
+            cx,
+            GlobalVarDecl {
+                attrs: sampler_attrs,
+                type_of_ptr_to: sampler_type_of_ptr_to,
+                ..combined_decl
+            },
+        );
+
+        load_replacements.insert(
+            combined,
+            [
+                (
+                    ptr_to_global_var(cx, image_type_of_ptr_to, image),
+                    image_type,
+                ),
+                (
+                    ptr_to_global_var(cx, sampler_type_of_ptr_to, sampler),
+                    sampler_type,
+                ),
+            ],
+        );
+        splits.push(SplitImageSampler {
+            combined,
+            image,
+            sampler,
+            image_binding,
+            sampler_binding,
+        });
+    }
+
+    let splitter = Splitter {
+        cx,
+        ptr_to_combined: &ptr_to_combined,
+        load_replacements: &load_replacements,
+    };
+    for func in funcs {
+        if let DeclDef::Present(func_def_body) = &mut module.funcs[func].def {
+            let regions = match &func_def_body.unstructured_cfg {
+                None => vec![func_def_body.body],
+                Some(cfg) => cfg.rev_post_order(func_def_body).collect(),
+            };
+            for region in regions {
+                splitter.split_loads_in_region(func_def_body, region);
+            }
+        }
+    }
+
+    // Entry-point interfaces also need to list the new global variables.
+    module.exports = mem::take(&mut module.exports)
+        .into_iter()
+        .map(|(export_key, exportee)| {
+            let export_key = match export_key {
+                ExportKey::SpvEntryPoint {
+                    imms,
+                    interface_global_vars,
+                } => ExportKey::SpvEntryPoint {
+                    imms,
+                    interface_global_vars: interface_global_vars
+                        .into_iter()
+                        .flat_map(
+                            |gv| match splits.iter().find(|split| split.combined == gv) {
+                                Some(split) => [split.image, split.sampler].into_iter().collect(),
+                                None => [gv].into_iter().collect::<SmallVec<[_; 2]>>(),
+                            },
+                        )
+                        .collect(),
+                },
+                ExportKey::LinkName(_) => export_key,
+            };
+            (export_key, exportee)
+        })
+        .collect();
+
+    splits
+}
+
+/// [`Visitor`] finding combined image/samplers used other than by `OpLoad`.
+struct UsageChecker<'a> {
+    ptr_to_combined: &'a FxIndexMap<Const, GlobalVar>,
+    unsplittable: FxHashSet<GlobalVar>,
+}
+
+impl Visitor<'_> for UsageChecker<'_> {
+    fn visit_attr_set_use(&mut self, _attrs: AttrSet) {}
+    fn visit_type_use(&mut self, _ty: Type) {}
+    fn visit_const_use(&mut self, ct: Const) {
+        if let Some(&gv) = self.ptr_to_combined.get(&ct) {
+            self.unsplittable.insert(gv);
+        }
+    }
+    fn visit_global_var_use(&mut self, _gv: GlobalVar) {}
+    fn visit_func_use(&mut self, _func: Func) {}
+
+    fn visit_data_inst_def(&mut self, data_inst_def: &DataInstDef) {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        let is_load_of_combined = match (&data_inst_def.kind, &data_inst_def.inputs[..]) {
+            (DataInstKind::SpvInst(inst), &[Value::Const(ptr)]) => {
+                inst.opcode == wk.OpLoad && self.ptr_to_combined.contains_key(&ptr)
+            }
+            _ => false,
+        };
+        if !is_load_of_combined {
+            data_inst_def.inner_visit_with(self);
+        }
+    }
+}
+
+struct Splitter<'a> {
+    cx: &'a Context,
+
+    ptr_to_combined: &'a FxIndexMap<Const, GlobalVar>,
+
+    /// Pointers to (and types of) the new image and sampler global variables,
+    /// for each split combined image/sampler.
+    load_replacements: &'a FxHashMap<GlobalVar, [(Const, Type); 2]>,
+}
+
+impl Splitter<'_> {
+    fn split_loads_in_region(&self, func_def_body: &mut FuncDefBody, region: ControlRegion) {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        let children: SmallVec<[_; 8]> = func_def_body
+            .at(region)
+            .at_children()
+            .into_iter()
+            .map(|func_at_control_node| func_at_control_node.position)
+            .collect();
+        for control_node in children {
+            let mut old_insts = match &mut func_def_body.control_nodes[control_node].kind {
+                ControlNodeKind::Block { insts } => mem::take(insts),
+                ControlNodeKind::Select { cases, .. } => {
+                    for case in cases.clone() {
+                        self.split_loads_in_region(func_def_body, case);
+                    }
+                    continue;
+                }
+                &mut ControlNodeKind::Loop { body, .. } => {
+                    self.split_loads_in_region(func_def_body, body);
+                    continue;
+                }
+                ControlNodeKind::ExitInvocation { .. } => continue,
+            };
+
+            // NOTE(eddyb) as `EntityList` doesn't support inserting before a node,
+            // the whole list of instructions is rebuilt (see also `passes::strength_reduce`).
+            let original_insts: SmallVec<[_; 8]> = func_def_body
+                .at(old_insts)
+                .into_iter()
+                .map(|func_at_inst| func_at_inst.position)
+                .collect();
+            let mut new_insts = EntityList::empty();
+            for inst in original_insts {
+                old_insts.remove(inst, &mut func_def_body.data_insts);
+
+                let inst_def = &func_def_body.data_insts[inst];
+                let combined = match (&inst_def.kind, &inst_def.inputs[..]) {
+                    (DataInstKind::SpvInst(spv_inst), &[Value::Const(ptr)])
+                        if spv_inst.opcode == wk.OpLoad =>
+                    {
+                        self.ptr_to_combined.get(&ptr).copied()
+                    }
+                    _ => None,
+                };
+                if let Some(combined) = combined {
+                    let load_attrs =
+                        AttrSet::default().with_debug_locations_from(self.cx, inst_def.attrs);
+                    let load_kind = inst_def.kind.clone();
+                    let [image, sampler] = self.load_replacements[&combined].map(|(ptr, ty)| {
+                        let load = func_def_body.data_insts.define(
+                            self.cx,
+                            DataInstDef {
+                                attrs: load_attrs,
+                                kind: load_kind.clone(),
+                                output_type: Some(ty),
+                                inputs: [Value::Const(ptr)].into_iter().collect(),
+                            }
+                            .into(),
+                        );
+                        new_insts.insert_last(load, &mut func_def_body.data_insts);
+                        Value::DataInstOutput(load)
+                    });
+
+                    // NOTE(eddyb) the original `OpLoad` is reused, so its uses
+                    // (e.g. sampling instructions) can remain unchanged.
+                    let inst_def = &mut func_def_body.data_insts[inst];
+                    inst_def.kind = DataInstKind::SpvInst(wk.OpSampledImage.into());
+                    inst_def.inputs = [image, sampler].into_iter().collect();
+                }
+                new_insts.insert_last(inst, &mut func_def_body.data_insts);
+            }
+            match &mut func_def_body.control_nodes[control_node].kind {
+                ControlNodeKind::Block { insts } => *insts = new_insts,
+                _ => unreachable!(),
+            }
+        }
+    }
+}
+
+fn ptr_to_global_var(cx: &Context, type_of_ptr_to: Type, gv: GlobalVar) -> Const {
+    cx.intern(ConstDef {
+        attrs: AttrSet::default(),
+        ty: type_of_ptr_to,
+        ctor: ConstCtor::PtrToGlobalVar(gv),
+        ctor_args: [].into_iter().collect(),
+    })
+}
+
+/// Get the pointer type like `ptr_type`, but pointing to `pointee` instead.
+fn with_pointee_type(cx: &Context, ptr_type: Type, pointee: Type) -> Type {
+    let ptr_type_def = &cx[ptr_type];
+    cx.intern(TypeDef {
+        attrs: ptr_type_def.attrs,
+        ctor: ptr_type_def.ctor.clone(),
+        ctor_args: [TypeCtorArg::Type(pointee)].into_iter().collect(),
+    })
+}
+
+/// Append `suffix` to the name of a definition (its `OpName`, kept in `attrs`),
+/// if it has one.
+fn with_name_suffix(cx: &Context, attrs: AttrSet, suffix: &str) -> AttrSet {
+    let wk = &spv::spec::Spec::get().well_known;
+
+    let attrs = cx[attrs]
+        .attrs
+        .iter()
+        .map(|attr| match attr {
+            Attr::SpvAnnotation(inst) if inst.opcode == wk.OpName => {
+                match spv::extract_literal_string(&inst.imms) {
+                    Ok(name) => Attr::SpvAnnotation(spv::Inst {
+                        opcode: wk.OpName,
+                        imms: spv::encode_literal_string(&(name + suffix)).collect(),
+                    }),
+                    Err(_) => attr.clone(),
+                }
+            }
+            _ => attr.clone(),
+        })
+        .collect();
+    cx.intern(AttrSetDef { attrs })
+}
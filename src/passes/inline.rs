@@ -0,0 +1,513 @@
+//! Function inlining (i.e. replacing calls with copies of the callee's body).
+
+use crate::call_graph::{calls_in_func, resolve_callee};
+use crate::passes::legalize::reachable_funcs;
+use crate::spv::{self, spec};
+use crate::transform::{InnerInPlaceTransform, ReplaceValueWith};
+use crate::{
+    Attr, AttrSet, Context, ControlNode, ControlNodeDef, ControlNodeKind, ControlRegion,
+    ControlRegionDef, DataInst, DataInstDef, DataInstKind, DeclDef, EntityDefs, EntityList,
+    ExportKey, Exportee, Func, FuncDecl, FuncDefBody, FxIndexSet, Module, Value,
+};
+use rustc_hash::{FxHashMap, FxHashSet};
+use smallvec::SmallVec;
+use std::mem;
+
+/// Options for inlining (see [`inline_calls`]).
+#[derive(Clone, Default)]
+pub struct InlineOptions {
+    /// Whether to inline calls to all functions (other than those with the
+    /// `DontInline` function control), not just those with `Inline`.
+    pub inline_all: bool,
+
+    /// Whether to treat [`ExportKey::LinkName`] exports of inlined functions as
+    /// only "available for inlining", i.e. to remove such exports if all calls
+    /// to the function were inlined, allowing it to be dropped from the module
+    /// (e.g. for definitions pulled in from libraries, when linking).
+    pub remove_inlined_exports: bool,
+}
+
+/// Inline calls (in all function definitions in `module`) to functions which
+/// have the `Inline` function control (or all functions, with [`InlineOptions`]),
+/// replacing each call with a copy of the body of the called function.
+///
+/// Calls to imported functions are also inlined, if `module` itself exports a
+/// definition for the import (i.e. the import can be resolved within `module`,
+/// as is the case after [`link_modules`](crate::passes::link::link_modules)),
+/// allowing functions to be inlined across (originally separate) modules.
+///
+/// Functions are processed callees-first, so that any inlining into a callee is
+/// done only once, before inlining the callee itself, and recursive calls (which
+/// can't be inlined without bound) are never inlined.
+//
+// FIXME(eddyb) support functions with unstructured control-flow, which are
+// currently never inlined, nor have any calls inlined into them.
+pub fn inline_calls(module: &mut Module, options: &InlineOptions) {
+    let mut inliner = Inliner {
+        cx: &module.cx(),
+        options,
+
+        done_funcs: FxHashSet::default(),
+        in_progress_funcs: FxHashSet::default(),
+        inlined_callees: FxHashSet::default(),
+    };
+    for func in reachable_funcs(module) {
+        inliner.inline_into_func(module, func);
+    }
+
+    if options.remove_inlined_exports && !inliner.inlined_callees.is_empty() {
+        let mut remaining_callees = FxHashSet::default();
+        for func in reachable_funcs(module) {
+            if let DeclDef::Present(func_def_body) = &module.funcs[func].def {
+                remaining_callees.extend(
+                    calls_in_func(func_def_body).map(|callee| resolve_callee(module, callee)),
+                );
+            }
+        }
+        module
+            .exports
+            .retain(|export_key, exportee| match (export_key, *exportee) {
+                (ExportKey::LinkName(_), Exportee::Func(func)) => {
+                    !inliner.inlined_callees.contains(&func) || remaining_callees.contains(&func)
+                }
+                _ => true,
+            });
+    }
+}
+
+/// Get the value of the `FunctionControl` bit named `name`.
+fn function_control_bit(name: &str) -> u32 {
+    let wk = &spec::Spec::get().well_known;
+
+    match wk.FunctionControl.def() {
+        spec::OperandKindDef::BitEnum { bits, .. } => 1 << bits.lookup(name).unwrap().0,
+        _ => unreachable!(),
+    }
+}
+
+struct Inliner<'a> {
+    cx: &'a Context,
+    options: &'a InlineOptions,
+
+    /// Functions which have already had all the calls in them inlined (and
+    /// which can therefore be themselves inlined into their callers).
+    done_funcs: FxHashSet<Func>,
+
+    /// Functions which are still being processed (i.e. their callees are),
+    /// calls to which are therefore recursive, and never inlined.
+    in_progress_funcs: FxHashSet<Func>,
+
+    /// Functions which have been inlined into at least one caller.
+    inlined_callees: FxHashSet<Func>,
+}
+
+impl Inliner<'_> {
+    fn should_inline(&self, func_decl: &FuncDecl) -> bool {
+        let wk = &spec::Spec::get().well_known;
+
+        let function_control = self.cx[func_decl.attrs]
+            .attrs
+            .iter()
+            .find_map(|attr| match attr {
+                Attr::SpvBitflagsOperand(imms) => match imms[..] {
+                    [spv::Imm::Short(kind, bits)] if kind == wk.FunctionControl => Some(bits),
+                    _ => None,
+                },
+                _ => None,
+            })
+            .unwrap_or(0);
+        if function_control & function_control_bit("DontInline") != 0 {
+            return false;
+        }
+        self.options.inline_all || function_control & function_control_bit("Inline") != 0
+    }
+
+    fn inline_into_func(&mut self, module: &mut Module, func: Func) {
+        if self.done_funcs.contains(&func) || !self.in_progress_funcs.insert(func) {
+            return;
+        }
+
+        // NOTE(eddyb) calls are keyed by the function being called, which can
+        // be an import, alongside the definition it resolves to (if any).
+        let calls: FxIndexSet<_> = match &module.funcs[func].def {
+            DeclDef::Present(func_def_body) if func_def_body.unstructured_cfg.is_none() => {
+                calls_in_func(func_def_body)
+                    .map(|callee| (callee, resolve_callee(module, callee)))
+                    .collect()
+            }
+            _ => FxIndexSet::default(),
+        };
+        for &(_, def_func) in &calls {
+            self.inline_into_func(module, def_func);
+        }
+
+        // NOTE(eddyb) the bodies of callees are copied out of `module`, to allow
+        // mutating the caller's body while inlining them.
+        let mut inlinable_callees = FxHashMap::default();
+        for (callee, def_func) in calls {
+            if !self.done_funcs.contains(&def_func) {
+                continue;
+            }
+            let callee_decl = &module.funcs[def_func];
+            match &callee_decl.def {
+                DeclDef::Present(callee_def_body)
+                    if callee_def_body.unstructured_cfg.is_none()
+                        && self.should_inline(callee_decl) =>
+                {
+                    inlinable_callees.insert(callee, (def_func, callee_def_body.clone()));
+                }
+                _ => {}
+            }
+        }
+
+        if !inlinable_callees.is_empty() {
+            if let DeclDef::Present(func_def_body) = &mut module.funcs[func].def {
+                let mut call_inliner = CallInliner {
+                    cx: self.cx,
+                    func_def_body,
+                    inlinable_callees: &inlinable_callees,
+
+                    inlined_callees: &mut self.inlined_callees,
+                    replacements: FxHashMap::default(),
+                };
+                call_inliner.inline_calls_in_region(call_inliner.func_def_body.body);
+                let replacements = call_inliner.replacements;
+                replace_values(func_def_body, replacements);
+            }
+        }
+
+        self.in_progress_funcs.remove(&func);
+        self.done_funcs.insert(func);
+    }
+}
+
+/// Replace all uses of the keys of `replacements`, in `func_def_body`, with
+/// their respective values (which can themselves be replaced, transitively).
+fn replace_values(func_def_body: &mut FuncDefBody, replacements: FxHashMap<Value, Value>) {
+    if replacements.is_empty() {
+        return;
+    }
+
+    // NOTE(eddyb) replacements can refer to other replaced values (e.g. the
+    // result of one inlined call can be returned from another), but only ones
+    // defined before them (i.e. dominating them), so this terminates.
+    fn resolve(replacements: &FxHashMap<Value, Value>, v: Value) -> Value {
+        match replacements.get(&v) {
+            Some(&new) => resolve(replacements, new),
+            None => v,
+        }
+    }
+
+    func_def_body.inner_in_place_transform_with(&mut ReplaceValueWith(|v| {
+        replacements
+            .contains_key(&v)
+            .then(|| resolve(&replacements, v))
+    }));
+}
+
+/// Inliner for all the calls in one (structured) function body, to the callees
+/// in `inlinable_callees` (alongside the definitions they resolve to).
+struct CallInliner<'a> {
+    cx: &'a Context,
+    func_def_body: &'a mut FuncDefBody,
+    inlinable_callees: &'a FxHashMap<Func, (Func, FuncDefBody)>,
+
+    /// Definitions which have been inlined (see [`Inliner::inlined_callees`]).
+    inlined_callees: &'a mut FxHashSet<Func>,
+
+    /// Values to replace all uses of (i.e. the outputs of inlined calls).
+    replacements: FxHashMap<Value, Value>,
+}
+
+impl CallInliner<'_> {
+    fn inline_calls_in_region(&mut self, region: ControlRegion) {
+        let children: SmallVec<[_; 8]> = self
+            .func_def_body
+            .at(region)
+            .at_children()
+            .into_iter()
+            .map(|func_at_control_node| func_at_control_node.position)
+            .collect();
+
+        // NOTE(eddyb) as `EntityList` doesn't support inserting before a node,
+        // the whole list of children is rebuilt (see also `passes::strength_reduce`).
+        let mut new_children = EntityList::empty();
+        for control_node in children {
+            self.func_def_body.control_regions[region]
+                .children
+                .remove(control_node, &mut self.func_def_body.control_nodes);
+
+            match &mut self.func_def_body.control_nodes[control_node].kind {
+                ControlNodeKind::Block { insts } => {
+                    let insts = mem::take(insts);
+                    self.inline_calls_in_block(control_node, insts, &mut new_children);
+                    continue;
+                }
+                ControlNodeKind::Select { cases, .. } => {
+                    for case in cases.clone() {
+                        self.inline_calls_in_region(case);
+                    }
+                }
+                &mut ControlNodeKind::Loop { body, .. } => {
+                    self.inline_calls_in_region(body);
+                }
+                ControlNodeKind::ExitInvocation { .. } => {}
+            }
+            new_children.insert_last(control_node, &mut self.func_def_body.control_nodes);
+        }
+        self.func_def_body.control_regions[region].children = new_children;
+    }
+
+    /// Inline all the calls in `insts` (the instructions of the `Block` `block`),
+    /// splitting it at each inlined call, and appending the resulting `Block`s
+    /// (and inlined control nodes, in between them) to `new_children`.
+    fn inline_calls_in_block(
+        &mut self,
+        block: ControlNode,
+        mut insts: EntityList<DataInst>,
+        new_children: &mut EntityList<ControlNode>,
+    ) {
+        let cx = self.cx;
+
+        let original_insts: SmallVec<[_; 8]> = self
+            .func_def_body
+            .at(insts)
+            .into_iter()
+            .map(|func_at_inst| func_at_inst.position)
+            .collect();
+
+        let mut current_block = block;
+        let mut current_insts = EntityList::empty();
+        for inst in original_insts {
+            insts.remove(inst, &mut self.func_def_body.data_insts);
+
+            let inst_def = &self.func_def_body.data_insts[inst];
+            let callee = match inst_def.kind {
+                DataInstKind::FuncCall(callee) => callee,
+                _ => {
+                    current_insts.insert_last(inst, &mut self.func_def_body.data_insts);
+                    continue;
+                }
+            };
+            let (def_func, callee_def_body) = match self.inlinable_callees.get(&callee) {
+                Some((def_func, callee_def_body)) => (*def_func, callee_def_body),
+                None => {
+                    current_insts.insert_last(inst, &mut self.func_def_body.data_insts);
+                    continue;
+                }
+            };
+            let args = inst_def.inputs.clone();
+
+            // Finish the `Block` holding all the instructions before the call.
+            self.push_block(current_block, current_insts, new_children);
+
+            let mut cloner = BodyCloner {
+                cx,
+                from: callee_def_body,
+                control_regions: &mut self.func_def_body.control_regions,
+                control_nodes: &mut self.func_def_body.control_nodes,
+                data_insts: &mut self.func_def_body.data_insts,
+                values: args
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, arg)| {
+                        let param = Value::ControlRegionInput {
+                            region: callee_def_body.body,
+                            input_idx: i as u32,
+                        };
+                        (param, arg)
+                    })
+                    .collect(),
+            };
+            let callee_children = cloner.clone_children(callee_def_body.body);
+            let callee_outputs: SmallVec<[_; 2]> = callee_def_body.at_body().def().outputs[..]
+                .iter()
+                .map(|&v| cloner.remap(v))
+                .collect();
+            new_children.append(callee_children, &mut self.func_def_body.control_nodes);
+
+            if let [ret_value] = callee_outputs[..] {
+                self.replacements
+                    .insert(Value::DataInstOutput(inst), ret_value);
+            }
+            self.inlined_callees.insert(def_func);
+
+            // Start a new `Block` for all the instructions after the call.
+            current_block = self.func_def_body.control_nodes.define(
+                cx,
+                ControlNodeDef {
+                    attrs: AttrSet::default(),
+                    kind: ControlNodeKind::Block {
+                        insts: EntityList::empty(),
+                    },
+                    outputs: SmallVec::new(),
+                }
+                .into(),
+            );
+            current_insts = EntityList::empty();
+        }
+        self.push_block(current_block, current_insts, new_children);
+    }
+
+    /// Set the instructions of the `Block` `block` to `insts`, and append it to
+    /// `new_children`, unless it would be empty.
+    fn push_block(
+        &mut self,
+        block: ControlNode,
+        insts: EntityList<DataInst>,
+        new_children: &mut EntityList<ControlNode>,
+    ) {
+        if insts.is_empty() {
+            return;
+        }
+        match &mut self.func_def_body.control_nodes[block].kind {
+            ControlNodeKind::Block { insts: block_insts } => *block_insts = insts,
+            _ => unreachable!(),
+        }
+        new_children.insert_last(block, &mut self.func_def_body.control_nodes);
+    }
+}
+
+/// Deep copier of (parts of) the function body `from` into the entity definitions
+/// of another function body, with all new entities allocated from `cx`.
+pub(crate) struct BodyCloner<'a> {
+    pub cx: &'a Context,
+    pub from: &'a FuncDefBody,
+    pub control_regions: &'a mut EntityDefs<ControlRegion>,
+    pub control_nodes: &'a mut EntityDefs<ControlNode>,
+    pub data_insts: &'a mut EntityDefs<DataInst>,
+
+    /// Values (defined in `from`) already copied (or otherwise mapped) to
+    /// the new function body.
+    pub values: FxHashMap<Value, Value>,
+}
+
+impl BodyCloner<'_> {
+    pub fn remap(&self, v: Value) -> Value {
+        match v {
+            Value::Const(_) => v,
+            _ => self.values[&v],
+        }
+    }
+
+    pub fn clone_region(&mut self, region: ControlRegion) -> ControlRegion {
+        let region_def = &self.from.control_regions[region];
+        let new_region = self.control_regions.define(
+            self.cx,
+            ControlRegionDef {
+                inputs: region_def.inputs.clone(),
+                children: EntityList::empty(),
+                outputs: SmallVec::new(),
+            },
+        );
+        for input_idx in 0..region_def.inputs.len() as u32 {
+            self.values.insert(
+                Value::ControlRegionInput { region, input_idx },
+                Value::ControlRegionInput {
+                    region: new_region,
+                    input_idx,
+                },
+            );
+        }
+
+        let children = self.clone_children(region);
+        let outputs = self.from.control_regions[region]
+            .outputs
+            .iter()
+            .map(|&v| self.remap(v))
+            .collect();
+
+        let new_region_def = &mut self.control_regions[new_region];
+        new_region_def.children = children;
+        new_region_def.outputs = outputs;
+        new_region
+    }
+
+    pub fn clone_children(&mut self, region: ControlRegion) -> EntityList<ControlNode> {
+        let mut children = EntityList::empty();
+        for func_at_control_node in self.from.at(region).at_children() {
+            let new_control_node = self.clone_control_node(func_at_control_node.position);
+            children.insert_last(new_control_node, self.control_nodes);
+        }
+        children
+    }
+
+    fn clone_control_node(&mut self, control_node: ControlNode) -> ControlNode {
+        let cx = self.cx;
+        let control_node_def = &self.from.control_nodes[control_node];
+
+        let kind = match &control_node_def.kind {
+            &ControlNodeKind::Block { insts } => {
+                let mut new_insts = EntityList::empty();
+                for func_at_inst in self.from.at(insts) {
+                    let inst_def = func_at_inst.def();
+                    let new_inst = self.data_insts.define(
+                        cx,
+                        DataInstDef {
+                            attrs: inst_def.attrs,
+                            kind: inst_def.kind.clone(),
+                            output_type: inst_def.output_type,
+                            inputs: inst_def.inputs.iter().map(|&v| self.remap(v)).collect(),
+                        }
+                        .into(),
+                    );
+                    self.values.insert(
+                        Value::DataInstOutput(func_at_inst.position),
+                        Value::DataInstOutput(new_inst),
+                    );
+                    new_insts.insert_last(new_inst, self.data_insts);
+                }
+                ControlNodeKind::Block { insts: new_insts }
+            }
+            ControlNodeKind::Select {
+                kind,
+                scrutinee,
+                cases,
+            } => ControlNodeKind::Select {
+                kind: kind.clone(),
+                scrutinee: self.remap(*scrutinee),
+                cases: cases.iter().map(|&case| self.clone_region(case)).collect(),
+            },
+            ControlNodeKind::Loop {
+                initial_inputs,
+                body,
+                repeat_condition,
+            } => {
+                let initial_inputs = initial_inputs.iter().map(|&v| self.remap(v)).collect();
+                let body = self.clone_region(*body);
+                ControlNodeKind::Loop {
+                    initial_inputs,
+                    body,
+                    repeat_condition: self.remap(*repeat_condition),
+                }
+            }
+            ControlNodeKind::ExitInvocation { kind, inputs } => ControlNodeKind::ExitInvocation {
+                kind: kind.clone(),
+                inputs: inputs.iter().map(|&v| self.remap(v)).collect(),
+            },
+        };
+
+        let new_control_node = self.control_nodes.define(
+            cx,
+            ControlNodeDef {
+                attrs: control_node_def.attrs,
+                kind,
+                outputs: control_node_def.outputs.clone(),
+            }
+            .into(),
+        );
+        for output_idx in 0..control_node_def.outputs.len() as u32 {
+            self.values.insert(
+                Value::ControlNodeOutput {
+                    control_node,
+                    output_idx,
+                },
+                Value::ControlNodeOutput {
+                    control_node: new_control_node,
+                    output_idx,
+                },
+            );
+        }
+        new_control_node
+    }
+}
@@ -0,0 +1,2655 @@
+//! 64-bit integer (and floating-point) emulation (i.e. replacing SPIR-V `Int64`
+//! and/or `Float64` with pairs of 32-bit integers, for devices lacking native
+//! support for 64-bit integers and/or floats).
+
+use crate::func_at::FuncAt;
+use crate::passes::legalize::reachable_global_vars_and_funcs;
+use crate::spv::fold::{composite_const, const_splat_value, splat_const, ScalarValue};
+use crate::spv::{self, spec::Opcode};
+use crate::transform::{Transformed, Transformer};
+use crate::visit::Visitor;
+use crate::{
+    Attr, AttrSet, AttrSetDef, Const, ConstCtor, Context, ControlNodeDef, ControlNodeKind,
+    ControlRegion, ControlRegionDef, ControlRegionInputDecl, DataInst, DataInstDef, DataInstKind,
+    DeclDef, EntityDefs, EntityList, Func, FuncDecl, FuncDefBody, FuncParam, FxIndexSet, GlobalVar,
+    Module, ModuleDialect, SelectionKind, StructMember, Type, TypeCtor, TypeCtorArg, TypeDef,
+    Value,
+};
+use rustc_hash::{FxHashMap, FxHashSet};
+use smallvec::SmallVec;
+use std::{fmt, mem};
+
+/// Error returned by [`emulate_int64`] (or [`emulate_float64`]), when some use
+/// of 64-bit integers (or floats) can't be emulated (in which case the module
+/// is left unchanged).
+#[derive(Debug)]
+pub struct Int64EmulationError {
+    pub message: String,
+}
+
+impl fmt::Display for Int64EmulationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "failed to emulate 64-bit types: {}", self.message)
+    }
+}
+
+impl std::error::Error for Int64EmulationError {}
+
+/// Emulate all 64-bit integers in `module` (which has the `Int64` capability)
+/// with pairs of 32-bit integers, i.e.:
+/// * 64-bit integer types are replaced with 2-component vectors of 32-bit
+///   unsigned integers (in little-endian order, i.e. the low half first),
+///   which keeps the same size (and memory layout) wherever they're used
+/// * 64-bit integer constants are replaced with the equivalent vector constants
+/// * arithmetic, bitwise, shift, comparison and conversion operations are
+///   replaced with sequences of 32-bit operations on the halves, except for
+///   division and remainder, which call an injected helper function
+///   (implementing long division with a loop)
+/// * operations which only move values around (e.g. `OpLoad`/`OpStore`, or
+///   function calls) are left unchanged (other than the types involved)
+///
+/// If successful, the `Int64` capability is removed from the module.
+///
+/// Vectors of 64-bit integers (which can be first split, see
+/// [`scalarize`](crate::passes::scalarize)), 64-bit specialization constants,
+/// `OpSwitch` on a 64-bit integer, or any other operations on 64-bit integers
+/// (e.g. atomics, or extended instructions), result in [`Int64EmulationError`].
+pub fn emulate_int64(module: &mut Module) -> Result<(), Int64EmulationError> {
+    emulate(module, Emulated::Int64)
+}
+
+/// Emulate all 64-bit floats in `module` (which has the `Float64` capability)
+/// with their IEEE 754 bits (as pairs of 32-bit integers), i.e.:
+/// * 64-bit float types are replaced with 2-component vectors of 32-bit
+///   unsigned integers (just like 64-bit integers, see [`emulate_int64`])
+/// * 64-bit float constants are replaced with the vector constants of their bits
+/// * negation, comparison (including `OpIsNan`/`OpIsInf`) and conversion
+///   operations are replaced with sequences of 32-bit integer operations on
+///   the halves, while addition, subtraction, multiplication and division call
+///   injected "soft-float" helper functions
+/// * operations which only move values around (e.g. `OpLoad`/`OpStore`, or
+///   function calls) are left unchanged (other than the types involved)
+///
+/// All emulated operations round to nearest (ties to even), and support
+/// subnormals, infinities and NaNs (though NaN results are always the same
+/// quiet NaN, i.e. NaN payloads aren't preserved).
+///
+/// Any (native) 64-bit integers are left unchanged, and they're only converted
+/// to/from (emulated) 64-bit floats through bitcasts (to/from their halves).
+///
+/// If successful, the `Float64` capability is removed from the module.
+///
+/// Vectors of 64-bit floats (which can be first split, see
+/// [`scalarize`](crate::passes::scalarize)), 64-bit specialization constants,
+/// conversions between 64-bit and 16-bit floats, or any other operations on
+/// 64-bit floats (e.g. `OpFRem`/`OpFMod`, or extended instructions, like `Sqrt`),
+/// result in [`Int64EmulationError`].
+pub fn emulate_float64(module: &mut Module) -> Result<(), Int64EmulationError> {
+    emulate(module, Emulated::Float64)
+}
+
+/// Which 64-bit types are emulated (see [`emulate_int64`] and [`emulate_float64`]).
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Emulated {
+    Int64,
+    Float64,
+}
+
+impl Emulated {
+    /// Get whether `ty` is the emulated (scalar) 64-bit type.
+    fn contains(self, cx: &Context, ty: Type) -> bool {
+        match self {
+            Emulated::Int64 => is_int64(cx, ty),
+            Emulated::Float64 => float_width(cx, ty) == Some(64),
+        }
+    }
+
+    /// Get the plural name of the emulated 64-bit type (for error messages).
+    fn name(self) -> &'static str {
+        match self {
+            Emulated::Int64 => "64-bit integers",
+            Emulated::Float64 => "64-bit floats",
+        }
+    }
+
+    /// Get whether operations on the emulated 64-bit type, with `opcode`,
+    /// can be emulated (see [`Int64Lowerer`]).
+    fn is_emulated_op(self, opcode: Opcode) -> bool {
+        match self {
+            Emulated::Int64 => is_emulated_int_op(opcode),
+            Emulated::Float64 => is_emulated_float_op(opcode),
+        }
+    }
+}
+
+fn emulate(module: &mut Module, emulated: Emulated) -> Result<(), Int64EmulationError> {
+    let wk = &spv::spec::Spec::get().well_known;
+    let cx = &module.cx();
+
+    let capability = match emulated {
+        Emulated::Int64 => wk.Int64,
+        Emulated::Float64 => wk.Float64,
+    };
+    match &module.dialect {
+        ModuleDialect::Spv(dialect) => {
+            if !dialect.capabilities.contains(&capability) {
+                return Ok(());
+            }
+        }
+    }
+
+    let (global_vars, funcs) = reachable_global_vars_and_funcs(module);
+
+    let mut checker = Int64UseChecker {
+        cx,
+        emulated,
+        types: FxHashMap::default(),
+        seen_consts: FxHashSet::default(),
+        needed_helpers: FxIndexSet::default(),
+        error: None,
+    };
+    for &gv in &global_vars {
+        checker.visit_global_var_decl(&module.global_vars[gv]);
+    }
+    for &func in &funcs {
+        let func_decl = &module.funcs[func];
+        checker.visit_func_decl(func_decl);
+        if let DeclDef::Present(func_def_body) = &func_decl.def {
+            checker.check_func_def_body(func_def_body);
+        }
+    }
+    if let Some(message) = checker.error {
+        return Err(Int64EmulationError { message });
+    }
+
+    let types = Int64EmulationTypes::new(cx);
+    let helper_funcs: FxHashMap<_, _> = checker
+        .needed_helpers
+        .into_iter()
+        .map(|helper| {
+            (
+                helper,
+                module.funcs.define(cx, helper.func_decl(cx, &types)),
+            )
+        })
+        .collect();
+
+    for &func in &funcs {
+        if let DeclDef::Present(func_def_body) = &mut module.funcs[func].def {
+            let regions = match &func_def_body.unstructured_cfg {
+                None => vec![func_def_body.body],
+                Some(cfg) => cfg.rev_post_order(func_def_body).collect(),
+            };
+
+            let mut lowerer = Int64Lowerer {
+                builder: Builder {
+                    cx,
+                    types: &types,
+                    func_def_body,
+                    insts: EntityList::empty(),
+                },
+                emulated,
+                helper_funcs: &helper_funcs,
+                halves: FxHashMap::default(),
+            };
+            for region in regions {
+                lowerer.lower_in_region(region);
+            }
+        }
+    }
+
+    let mut replacer = Int64TypeReplacer {
+        cx,
+        emulated,
+        types: &types,
+        transformed_types: FxHashMap::default(),
+        transformed_consts: FxHashMap::default(),
+    };
+    for &gv in &global_vars {
+        replacer.in_place_transform_global_var_decl(&mut module.global_vars[gv]);
+    }
+    for &func in &funcs {
+        replacer.in_place_transform_func_decl(&mut module.funcs[func]);
+    }
+
+    match &mut module.dialect {
+        ModuleDialect::Spv(dialect) => {
+            dialect.capabilities.remove(&capability);
+        }
+    }
+
+    Ok(())
+}
+
+/// Get whether `ty` is a 64-bit (signed or unsigned) integer type.
+fn is_int64(cx: &Context, ty: Type) -> bool {
+    let wk = &spv::spec::Spec::get().well_known;
+
+    match &cx[ty].ctor {
+        TypeCtor::SpvInst(inst) => {
+            inst.opcode == wk.OpTypeInt && matches!(inst.imms[..], [spv::Imm::Short(_, 64), _])
+        }
+        _ => false,
+    }
+}
+
+/// Get the width and signedness of `ty`, if it's a (scalar) integer type.
+fn int_width_and_signedness(cx: &Context, ty: Type) -> Option<(u32, bool)> {
+    let wk = &spv::spec::Spec::get().well_known;
+
+    match &cx[ty].ctor {
+        TypeCtor::SpvInst(inst) if inst.opcode == wk.OpTypeInt => match inst.imms[..] {
+            [spv::Imm::Short(_, width), spv::Imm::Short(_, signedness)] => {
+                Some((width, signedness != 0))
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Get the width of `ty`, if it's a (scalar) floating-point type.
+fn float_width(cx: &Context, ty: Type) -> Option<u32> {
+    let wk = &spv::spec::Spec::get().well_known;
+
+    match &cx[ty].ctor {
+        TypeCtor::SpvInst(inst) if inst.opcode == wk.OpTypeFloat => match inst.imms[..] {
+            [spv::Imm::Short(_, width), ..] => Some(width),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Operations on 64-bit integers (or floats) which only move values around,
+/// and therefore don't need to be changed (other than the types involved).
+fn is_opaque_op(opcode: Opcode) -> bool {
+    let wk = &spv::spec::Spec::get().well_known;
+
+    [
+        wk.OpVariable,
+        wk.OpLoad,
+        wk.OpStore,
+        wk.OpCopyObject,
+        wk.OpCompositeConstruct,
+        wk.OpCompositeExtract,
+        wk.OpCompositeInsert,
+    ]
+    .contains(&opcode)
+}
+
+/// Operations on 64-bit integers which can be emulated (see [`Int64Lowerer`]).
+fn is_emulated_int_op(opcode: Opcode) -> bool {
+    let wk = &spv::spec::Spec::get().well_known;
+
+    [
+        wk.OpAccessChain,
+        wk.OpInBoundsAccessChain,
+        wk.OpConvertPtrToU,
+        wk.OpConvertUToPtr,
+        wk.OpUConvert,
+        wk.OpSConvert,
+        wk.OpConvertFToU,
+        wk.OpConvertFToS,
+        wk.OpConvertSToF,
+        wk.OpConvertUToF,
+        wk.OpBitcast,
+        wk.OpSNegate,
+        wk.OpNot,
+        wk.OpIAdd,
+        wk.OpISub,
+        wk.OpIMul,
+        wk.OpUDiv,
+        wk.OpSDiv,
+        wk.OpUMod,
+        wk.OpSRem,
+        wk.OpSMod,
+        wk.OpShiftRightLogical,
+        wk.OpShiftRightArithmetic,
+        wk.OpShiftLeftLogical,
+        wk.OpBitwiseOr,
+        wk.OpBitwiseXor,
+        wk.OpBitwiseAnd,
+        wk.OpSelect,
+        wk.OpIEqual,
+        wk.OpINotEqual,
+        wk.OpUGreaterThan,
+        wk.OpSGreaterThan,
+        wk.OpUGreaterThanEqual,
+        wk.OpSGreaterThanEqual,
+        wk.OpULessThan,
+        wk.OpSLessThan,
+        wk.OpULessThanEqual,
+        wk.OpSLessThanEqual,
+    ]
+    .contains(&opcode)
+}
+
+/// Operations on 64-bit floats which can be emulated (see [`Int64Lowerer`]).
+fn is_emulated_float_op(opcode: Opcode) -> bool {
+    let wk = &spv::spec::Spec::get().well_known;
+
+    [
+        wk.OpConvertFToU,
+        wk.OpConvertFToS,
+        wk.OpConvertSToF,
+        wk.OpConvertUToF,
+        wk.OpFConvert,
+        wk.OpBitcast,
+        wk.OpSelect,
+        wk.OpFNegate,
+        wk.OpFAdd,
+        wk.OpFSub,
+        wk.OpFMul,
+        wk.OpFDiv,
+        wk.OpIsNan,
+        wk.OpIsInf,
+        wk.OpFOrdEqual,
+        wk.OpFUnordEqual,
+        wk.OpFOrdNotEqual,
+        wk.OpFUnordNotEqual,
+        wk.OpFOrdLessThan,
+        wk.OpFUnordLessThan,
+        wk.OpFOrdGreaterThan,
+        wk.OpFUnordGreaterThan,
+        wk.OpFOrdLessThanEqual,
+        wk.OpFUnordLessThanEqual,
+        wk.OpFOrdGreaterThanEqual,
+        wk.OpFUnordGreaterThanEqual,
+    ]
+    .contains(&opcode)
+}
+
+/// Helper functions injected into the module, for operations too large to
+/// emulate inline (each taking two `u32x2` inputs).
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+enum Helper {
+    /// 64-bit unsigned integer division and remainder (see [`divmod_func_decl`]).
+    DivMod,
+
+    /// 64-bit float addition (also used for subtraction, see [`Builder::f64_add`]).
+    FAdd,
+
+    /// 64-bit float multiplication (see [`Builder::f64_mul`]).
+    FMul,
+
+    /// 64-bit float division (see [`f64_div_func_decl`]).
+    FDiv,
+}
+
+impl Helper {
+    fn func_decl(self, cx: &Context, types: &Int64EmulationTypes) -> FuncDecl {
+        match self {
+            Helper::DivMod => divmod_func_decl(cx, types),
+            Helper::FAdd => f64_binop_func_decl(cx, types, |b, x, y| b.f64_add(x, y)),
+            Helper::FMul => f64_binop_func_decl(cx, types, |b, x, y| b.f64_mul(x, y)),
+            Helper::FDiv => f64_div_func_decl(cx, types),
+        }
+    }
+}
+
+/// Visitor checking that all the uses of the emulated 64-bit type can be emulated.
+struct Int64UseChecker<'a> {
+    cx: &'a Context,
+    emulated: Emulated,
+
+    /// Whether each type visited so far contains the emulated 64-bit type.
+    types: FxHashMap<Type, bool>,
+    seen_consts: FxHashSet<Const>,
+
+    /// Helper functions required by the operations found so far.
+    needed_helpers: FxIndexSet<Helper>,
+
+    error: Option<String>,
+}
+
+impl Int64UseChecker<'_> {
+    fn unsupported(&mut self, message: impl Into<String>) {
+        if self.error.is_none() {
+            self.error = Some(message.into());
+        }
+    }
+
+    /// Check `ty`, returning whether it contains the emulated 64-bit type.
+    fn check_type(&mut self, ty: Type) -> bool {
+        if let Some(&contains_emulated) = self.types.get(&ty) {
+            return contains_emulated;
+        }
+        let cx = self.cx;
+        let ty_def = &cx[ty];
+        let mut contains_emulated = self.emulated.contains(cx, ty);
+        for &arg in &ty_def.ctor_args {
+            match arg {
+                TypeCtorArg::Type(arg) => contains_emulated |= self.check_type(arg),
+                TypeCtorArg::Const(arg) => self.visit_const_use(arg),
+            }
+        }
+        if let TypeCtor::SpvInst(inst) = &ty_def.ctor {
+            let wk = &spv::spec::Spec::get().well_known;
+            if inst.opcode == wk.OpTypeVector && contains_emulated {
+                self.unsupported(format!(
+                    "vectors of {} (see `passes::scalarize` for splitting them)",
+                    self.emulated.name()
+                ));
+            }
+        }
+        self.types.insert(ty, contains_emulated);
+        contains_emulated
+    }
+
+    /// Check all the operations (on the emulated 64-bit type) in `func_def_body`.
+    fn check_func_def_body(&mut self, func_def_body: &FuncDefBody) {
+        let cx = self.cx;
+
+        let mut regions = vec![func_def_body.body];
+        if let Some(cfg) = &func_def_body.unstructured_cfg {
+            regions.extend(
+                cfg.rev_post_order(func_def_body)
+                    .filter(|&region| region != func_def_body.body),
+            );
+        }
+        while let Some(region) = regions.pop() {
+            let func = func_def_body.at(region);
+            for func_at_control_node in func.at_children() {
+                match &func_at_control_node.def().kind {
+                    ControlNodeKind::Block { insts } => {
+                        for func_at_inst in func.at(*insts) {
+                            self.check_inst(func_at_inst);
+                        }
+                    }
+                    ControlNodeKind::Select {
+                        kind,
+                        scrutinee,
+                        cases,
+                    } => {
+                        if let SelectionKind::SpvInst(_) = kind {
+                            if self.emulated.contains(cx, func.at(*scrutinee).type_of(cx)) {
+                                self.unsupported("`OpSwitch` on 64-bit integers");
+                            }
+                        }
+                        regions.extend(cases.iter().copied());
+                    }
+                    &ControlNodeKind::Loop { body, .. } => regions.push(body),
+                    ControlNodeKind::ExitInvocation { .. } => {}
+                }
+            }
+        }
+    }
+
+    fn check_inst(&mut self, func_at_inst: FuncAt<'_, DataInst>) {
+        let wk = &spv::spec::Spec::get().well_known;
+        let cx = self.cx;
+
+        let inst_def = func_at_inst.def();
+        let output_is_emulated = inst_def
+            .output_type
+            .is_some_and(|ty| self.emulated.contains(cx, ty));
+        let input_types: SmallVec<[_; 4]> = inst_def
+            .inputs
+            .iter()
+            .map(|&v| func_at_inst.at(v).type_of(cx))
+            .collect();
+        if !output_is_emulated && !input_types.iter().any(|&ty| self.emulated.contains(cx, ty)) {
+            return;
+        }
+
+        let opcode = match &inst_def.kind {
+            DataInstKind::FuncCall(_) => return,
+            DataInstKind::SpvInst(spv_inst) => spv_inst.opcode,
+            _ => {
+                self.unsupported(format!("non-SPIR-V operation on {}", self.emulated.name()));
+                return;
+            }
+        };
+        if is_opaque_op(opcode) {
+            return;
+        }
+        if !self.emulated.is_emulated_op(opcode) {
+            self.unsupported(format!("`{}` on {}", opcode.name(), self.emulated.name()));
+            return;
+        }
+
+        let helper = if [wk.OpUDiv, wk.OpSDiv, wk.OpUMod, wk.OpSRem, wk.OpSMod].contains(&opcode) {
+            Some(Helper::DivMod)
+        } else if opcode == wk.OpFAdd || opcode == wk.OpFSub {
+            Some(Helper::FAdd)
+        } else if opcode == wk.OpFMul {
+            Some(Helper::FMul)
+        } else if opcode == wk.OpFDiv {
+            Some(Helper::FDiv)
+        } else {
+            None
+        };
+        if let Some(helper) = helper {
+            self.needed_helpers.insert(helper);
+        }
+
+        if self.emulated == Emulated::Float64 {
+            // NOTE(eddyb) 16-bit floats would have to be converted through
+            // 32-bit floats, which isn't implemented (and would round twice).
+            if opcode == wk.OpFConvert {
+                let other_type = if output_is_emulated {
+                    input_types[0]
+                } else {
+                    inst_def.output_type.unwrap()
+                };
+                if float_width(cx, other_type) == Some(16) {
+                    self.unsupported("`OpFConvert` between 64-bit and 16-bit floats");
+                }
+            }
+            return;
+        }
+
+        // NOTE(eddyb) conversions between 64-bit integers and floats are
+        // emulated with floating-point arithmetic on the 32-bit halves, which
+        // requires being able to represent `2^32` (i.e. excludes 16-bit floats).
+        let float_type = if [wk.OpConvertSToF, wk.OpConvertUToF].contains(&opcode) {
+            inst_def.output_type
+        } else if [wk.OpConvertFToU, wk.OpConvertFToS].contains(&opcode) {
+            input_types.first().copied()
+        } else {
+            None
+        };
+        if let Some(float_type) = float_type {
+            if !matches!(float_width(cx, float_type), Some(32 | 64)) {
+                self.unsupported(format!(
+                    "`{}` between 64-bit integers and non-32/64-bit floats",
+                    opcode.name()
+                ));
+            }
+        }
+    }
+}
+
+impl Visitor<'_> for Int64UseChecker<'_> {
+    fn visit_attr_set_use(&mut self, _attrs: AttrSet) {}
+    fn visit_type_use(&mut self, ty: Type) {
+        self.check_type(ty);
+    }
+    fn visit_const_use(&mut self, ct: Const) {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        if !self.seen_consts.insert(ct) {
+            return;
+        }
+        let cx = self.cx;
+        let ct_def = &cx[ct];
+        let contains_emulated = self.check_type(ct_def.ty)
+            | ct_def
+                .ctor_args
+                .iter()
+                .any(|&arg| self.check_type(cx[arg].ty));
+        let is_supported = match &ct_def.ctor {
+            ConstCtor::PtrToGlobalVar(_) | ConstCtor::Undef => true,
+            ConstCtor::SpvInst(inst) => {
+                [wk.OpConstant, wk.OpConstantNull, wk.OpConstantComposite].contains(&inst.opcode)
+            }
+            _ => false,
+        };
+        if contains_emulated && !is_supported {
+            self.unsupported(format!(
+                "{} in specialization constants",
+                self.emulated.name()
+            ));
+        }
+        for &arg in &ct_def.ctor_args {
+            self.visit_const_use(arg);
+        }
+    }
+    fn visit_global_var_use(&mut self, _gv: GlobalVar) {}
+    fn visit_func_use(&mut self, _func: Func) {}
+}
+
+/// Types used by the emulation of 64-bit integers (or floats).
+struct Int64EmulationTypes {
+    bool: Type,
+    u32: Type,
+
+    /// 2-component vector of `u32`s, replacing the emulated 64-bit type.
+    u32x2: Type,
+
+    /// `struct { u32, u32 }` (i.e. the result type of `OpUMulExtended`).
+    u32_pair: Type,
+
+    /// `struct { u32x2, u32x2 }` (i.e. the quotient and remainder returned by
+    /// the division helper function, see [`divmod_func_decl`]).
+    u32x2_pair: Type,
+}
+
+impl Int64EmulationTypes {
+    fn new(cx: &Context) -> Self {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        let spv_type = |inst: spv::Inst, ctor_args: &[TypeCtorArg]| {
+            cx.intern(TypeDef {
+                attrs: AttrSet::default(),
+                ctor: TypeCtor::SpvInst(inst),
+                ctor_args: ctor_args.iter().copied().collect(),
+            })
+        };
+        let struct_type = |fields: [Type; 2]| {
+            cx.intern(TypeDef {
+                attrs: AttrSet::default(),
+                ctor: TypeCtor::Struct {
+                    members: fields
+                        .iter()
+                        .map(|_| StructMember {
+                            attrs: AttrSet::default(),
+                            offset: None,
+                        })
+                        .collect(),
+                },
+                ctor_args: fields.into_iter().map(TypeCtorArg::Type).collect(),
+            })
+        };
+
+        let bool = spv_type(wk.OpTypeBool.into(), &[]);
+        let u32 = spv_type(
+            spv::Inst {
+                opcode: wk.OpTypeInt,
+                imms: [
+                    spv::Imm::Short(wk.LiteralInteger, 32),
+                    spv::Imm::Short(wk.LiteralInteger, 0),
+                ]
+                .into_iter()
+                .collect(),
+            },
+            &[],
+        );
+        let u32x2 = spv_type(
+            spv::Inst {
+                opcode: wk.OpTypeVector,
+                imms: [spv::Imm::Short(wk.LiteralInteger, 2)]
+                    .into_iter()
+                    .collect(),
+            },
+            &[TypeCtorArg::Type(u32)],
+        );
+        Self {
+            bool,
+            u32,
+            u32x2,
+            u32_pair: struct_type([u32, u32]),
+            u32x2_pair: struct_type([u32x2, u32x2]),
+        }
+    }
+}
+
+/// Helper for building new instructions, appended to `insts`.
+struct Builder<'a> {
+    cx: &'a Context,
+    types: &'a Int64EmulationTypes,
+    func_def_body: &'a mut FuncDefBody,
+    insts: EntityList<DataInst>,
+}
+
+impl Builder<'_> {
+    fn inst(
+        &mut self,
+        kind: DataInstKind,
+        inputs: impl IntoIterator<Item = Value>,
+        output_type: Type,
+    ) -> Value {
+        let inst = self.func_def_body.data_insts.define(
+            self.cx,
+            DataInstDef {
+                attrs: AttrSet::default(),
+                kind,
+                output_type: Some(output_type),
+                inputs: inputs.into_iter().collect(),
+            }
+            .into(),
+        );
+        self.insts
+            .insert_last(inst, &mut self.func_def_body.data_insts);
+        Value::DataInstOutput(inst)
+    }
+
+    fn op(
+        &mut self,
+        opcode: Opcode,
+        inputs: impl IntoIterator<Item = Value>,
+        output_type: Type,
+    ) -> Value {
+        self.inst(DataInstKind::SpvInst(opcode.into()), inputs, output_type)
+    }
+
+    /// Build a 32-bit operation (i.e. with a `u32` output).
+    fn op32(&mut self, opcode: Opcode, inputs: impl IntoIterator<Item = Value>) -> Value {
+        self.op(opcode, inputs, self.types.u32)
+    }
+
+    /// Build a comparison (or logical) operation (i.e. with a `bool` output).
+    fn cmp(&mut self, opcode: Opcode, inputs: impl IntoIterator<Item = Value>) -> Value {
+        self.op(opcode, inputs, self.types.bool)
+    }
+
+    fn extract(&mut self, composite: Value, indices: &[u32], output_type: Type) -> Value {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        self.inst(
+            DataInstKind::SpvInst(spv::Inst {
+                opcode: wk.OpCompositeExtract,
+                imms: indices
+                    .iter()
+                    .map(|&i| spv::Imm::Short(wk.LiteralInteger, i))
+                    .collect(),
+            }),
+            [composite],
+            output_type,
+        )
+    }
+
+    fn const_u32(&self, x: u32) -> Value {
+        Value::Const(
+            splat_const(
+                self.cx,
+                self.types.u32,
+                ScalarValue::Int {
+                    width: 32,
+                    bits: x.into(),
+                },
+            )
+            .unwrap(),
+        )
+    }
+
+    fn select(
+        &mut self,
+        cond: Value,
+        [a_lo, a_hi]: [Value; 2],
+        [b_lo, b_hi]: [Value; 2],
+    ) -> [Value; 2] {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        [
+            self.op32(wk.OpSelect, [cond, a_lo, b_lo]),
+            self.op32(wk.OpSelect, [cond, a_hi, b_hi]),
+        ]
+    }
+
+    fn add64(&mut self, [a_lo, a_hi]: [Value; 2], [b_lo, b_hi]: [Value; 2]) -> [Value; 2] {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        let lo = self.op32(wk.OpIAdd, [a_lo, b_lo]);
+        let carry_cond = self.cmp(wk.OpULessThan, [lo, a_lo]);
+        let (one, zero) = (self.const_u32(1), self.const_u32(0));
+        let carry = self.op32(wk.OpSelect, [carry_cond, one, zero]);
+        let hi = self.op32(wk.OpIAdd, [a_hi, b_hi]);
+        let hi = self.op32(wk.OpIAdd, [hi, carry]);
+        [lo, hi]
+    }
+
+    fn sub64(&mut self, [a_lo, a_hi]: [Value; 2], [b_lo, b_hi]: [Value; 2]) -> [Value; 2] {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        let lo = self.op32(wk.OpISub, [a_lo, b_lo]);
+        let borrow_cond = self.cmp(wk.OpULessThan, [a_lo, b_lo]);
+        let (one, zero) = (self.const_u32(1), self.const_u32(0));
+        let borrow = self.op32(wk.OpSelect, [borrow_cond, one, zero]);
+        let hi = self.op32(wk.OpISub, [a_hi, b_hi]);
+        let hi = self.op32(wk.OpISub, [hi, borrow]);
+        [lo, hi]
+    }
+
+    /// Compare `a` and `b` with `op` (one of `OpULessThan`, `OpSLessThan`,
+    /// `OpULessThanEqual`, etc.), using the strict version of `op` on the high
+    /// halves, and (only if those are equal) the unsigned version on the low ones.
+    fn cmp64(&mut self, op: Opcode, [a_lo, a_hi]: [Value; 2], [b_lo, b_hi]: [Value; 2]) -> Value {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        let (hi_op, lo_op) = if op == wk.OpULessThan || op == wk.OpULessThanEqual {
+            (wk.OpULessThan, op)
+        } else if op == wk.OpUGreaterThan || op == wk.OpUGreaterThanEqual {
+            (wk.OpUGreaterThan, op)
+        } else if op == wk.OpSLessThan {
+            (wk.OpSLessThan, wk.OpULessThan)
+        } else if op == wk.OpSLessThanEqual {
+            (wk.OpSLessThan, wk.OpULessThanEqual)
+        } else if op == wk.OpSGreaterThan {
+            (wk.OpSGreaterThan, wk.OpUGreaterThan)
+        } else if op == wk.OpSGreaterThanEqual {
+            (wk.OpSGreaterThan, wk.OpUGreaterThanEqual)
+        } else {
+            unreachable!()
+        };
+        let hi_cmp = self.cmp(hi_op, [a_hi, b_hi]);
+        let hi_eq = self.cmp(wk.OpIEqual, [a_hi, b_hi]);
+        let lo_cmp = self.cmp(lo_op, [a_lo, b_lo]);
+        let hi_eq_and_lo_cmp = self.cmp(wk.OpLogicalAnd, [hi_eq, lo_cmp]);
+        self.cmp(wk.OpLogicalOr, [hi_cmp, hi_eq_and_lo_cmp])
+    }
+
+    /// Build a `u32` which is `1` if `cond` is `true`, or `0` otherwise.
+    fn bool_to_u32(&mut self, cond: Value) -> Value {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        let (one, zero) = (self.const_u32(1), self.const_u32(0));
+        self.op32(wk.OpSelect, [cond, one, zero])
+    }
+
+    /// Whether the 64-bit integer `x` is `0`.
+    fn is_zero64(&mut self, [lo, hi]: [Value; 2]) -> Value {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        let zero = self.const_u32(0);
+        let lo_or_hi = self.op32(wk.OpBitwiseOr, [lo, hi]);
+        self.cmp(wk.OpIEqual, [lo_or_hi, zero])
+    }
+
+    /// Full 32x32->64-bit unsigned multiplication (returning the low and high halves).
+    fn mul32_wide(&mut self, a: Value, b: Value) -> [Value; 2] {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        let mul = self.op(wk.OpUMulExtended, [a, b], self.types.u32_pair);
+        [0, 1].map(|i| self.extract(mul, &[i], self.types.u32))
+    }
+
+    /// Full 64x64->128-bit unsigned multiplication (returning 32-bit words,
+    /// from least to most significant).
+    fn mul64_wide(&mut self, [a_lo, a_hi]: [Value; 2], [b_lo, b_hi]: [Value; 2]) -> [Value; 4] {
+        let zero = self.const_u32(0);
+        let [w0, lo_lo_hi] = self.mul32_wide(a_lo, b_lo);
+        let [lo_hi_lo, lo_hi_hi] = self.mul32_wide(a_lo, b_hi);
+        let [hi_lo_lo, hi_lo_hi] = self.mul32_wide(a_hi, b_lo);
+        let hi_hi = self.mul32_wide(a_hi, b_hi);
+
+        // The second word gathers three partial products, and their carries
+        // (at most `2`) are added to the upper 64 bits.
+        let [w1, carry1] = self.add64([lo_lo_hi, zero], [lo_hi_lo, zero]);
+        let [w1, carry2] = self.add64([w1, carry1], [hi_lo_lo, zero]);
+        let upper = self.add64(hi_hi, [lo_hi_hi, zero]);
+        let upper = self.add64(upper, [hi_lo_hi, zero]);
+        let [w2, w3] = self.add64(upper, [carry2, zero]);
+        [w0, w1, w2, w3]
+    }
+
+    /// Shift the 64-bit integer `x` by `n` (in `0..64`), with `opcode` being one
+    /// of `OpShiftLeftLogical`, `OpShiftRightLogical` or `OpShiftRightArithmetic`.
+    //
+    // NOTE(eddyb) shifting by `n` in `32..64` is equivalent to moving one half
+    // into the other, then shifting by `n & 31`, while shifts by `n` in `0..32`
+    // combine bits from both halves, with the bits crossing between halves
+    // shifted in two steps, to avoid shifting by `32` (which is undefined)
+    // when `n` is `0`.
+    fn shift64(&mut self, opcode: Opcode, [lo, hi]: [Value; 2], n: Value) -> [Value; 2] {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        let (zero, one) = (self.const_u32(0), self.const_u32(1));
+        let (c31, c32) = (self.const_u32(31), self.const_u32(32));
+
+        let shift = self.op32(wk.OpBitwiseAnd, [n, c31]);
+        let is_small = self.cmp(wk.OpULessThan, [n, c32]);
+        let cross_shift = self.op32(wk.OpISub, [c31, shift]);
+        if opcode == wk.OpShiftLeftLogical {
+            let lo_shifted = self.op32(opcode, [lo, shift]);
+            let hi_shifted = self.op32(opcode, [hi, shift]);
+            let cross = self.op32(wk.OpShiftRightLogical, [lo, one]);
+            let cross = self.op32(wk.OpShiftRightLogical, [cross, cross_shift]);
+            let hi_small = self.op32(wk.OpBitwiseOr, [hi_shifted, cross]);
+            self.select(is_small, [lo_shifted, hi_small], [zero, lo_shifted])
+        } else {
+            let lo_shifted = self.op32(wk.OpShiftRightLogical, [lo, shift]);
+            let hi_shifted = self.op32(opcode, [hi, shift]);
+            let cross = self.op32(wk.OpShiftLeftLogical, [hi, one]);
+            let cross = self.op32(wk.OpShiftLeftLogical, [cross, cross_shift]);
+            let lo_small = self.op32(wk.OpBitwiseOr, [lo_shifted, cross]);
+            let hi_large = if opcode == wk.OpShiftRightArithmetic {
+                self.op32(opcode, [hi, c31])
+            } else {
+                zero
+            };
+            self.select(is_small, [lo_small, hi_shifted], [hi_shifted, hi_large])
+        }
+    }
+
+    /// Shift the 64-bit integer `x` left by the constant `n` (in `1..32`).
+    fn shl64_by(&mut self, [lo, hi]: [Value; 2], n: u32) -> [Value; 2] {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        let (n, cross_n) = (self.const_u32(n), self.const_u32(32 - n));
+        let hi = self.op32(wk.OpShiftLeftLogical, [hi, n]);
+        let cross = self.op32(wk.OpShiftRightLogical, [lo, cross_n]);
+        [
+            self.op32(wk.OpShiftLeftLogical, [lo, n]),
+            self.op32(wk.OpBitwiseOr, [hi, cross]),
+        ]
+    }
+
+    /// Shift the 64-bit unsigned integer `x` right by the constant `n` (in `1..32`).
+    fn shr64_by(&mut self, [lo, hi]: [Value; 2], n: u32) -> [Value; 2] {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        let (n, cross_n) = (self.const_u32(n), self.const_u32(32 - n));
+        let lo = self.op32(wk.OpShiftRightLogical, [lo, n]);
+        let cross = self.op32(wk.OpShiftLeftLogical, [hi, cross_n]);
+        [
+            self.op32(wk.OpBitwiseOr, [lo, cross]),
+            self.op32(wk.OpShiftRightLogical, [hi, n]),
+        ]
+    }
+
+    /// Shift the 64-bit unsigned integer `x` right by `n` (which can be `64`
+    /// or larger), setting the lowest bit of the result if any of the bits
+    /// shifted out were set (i.e. "jamming" them, to keep track of inexactness).
+    fn shr64_jam(&mut self, x: [Value; 2], n: Value) -> [Value; 2] {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        let (zero, c63) = (self.const_u32(0), self.const_u32(63));
+        let is_large = self.cmp(wk.OpUGreaterThan, [n, c63]);
+        let n = self.op32(wk.OpSelect, [is_large, zero, n]);
+        let shifted = self.shift64(wk.OpShiftRightLogical, x, n);
+        let [lo, hi] = self.select(is_large, [zero, zero], shifted);
+
+        // Bits were lost if shifting back doesn't result in the original value
+        // (or, for large shifts, which always lose all bits, if it was nonzero).
+        let unshifted = self.shift64(wk.OpShiftLeftLogical, shifted, n);
+        let unshifted_lo_ne = self.cmp(wk.OpINotEqual, [unshifted[0], x[0]]);
+        let unshifted_hi_ne = self.cmp(wk.OpINotEqual, [unshifted[1], x[1]]);
+        let lost_small = self.cmp(wk.OpLogicalOr, [unshifted_lo_ne, unshifted_hi_ne]);
+        let x_is_zero = self.is_zero64(x);
+        let x_nonzero = self.cmp(wk.OpLogicalNot, [x_is_zero]);
+        let lost_large = self.cmp(wk.OpLogicalAnd, [is_large, x_nonzero]);
+        let lost = self.cmp(wk.OpLogicalOr, [lost_small, lost_large]);
+        let lost = self.bool_to_u32(lost);
+        [self.op32(wk.OpBitwiseOr, [lo, lost]), hi]
+    }
+
+    /// Count the leading zeros of the 32-bit integer `x` (`32` if `x` is `0`).
+    fn clz32(&mut self, x: Value) -> Value {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        // NOTE(eddyb) `FindUMsb` returns `-1` for `0`, resulting in `31 - (-1)`.
+        let msb = self.inst(
+            DataInstKind::SpvGlslStd450(spv::glsl_std_450::Op::FindUMsb),
+            [x],
+            self.types.u32,
+        );
+        let c31 = self.const_u32(31);
+        self.op32(wk.OpISub, [c31, msb])
+    }
+
+    /// Count the leading zeros of the 64-bit integer `x` (`64` if `x` is `0`).
+    fn clz64(&mut self, [lo, hi]: [Value; 2]) -> Value {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        let (zero, c32) = (self.const_u32(0), self.const_u32(32));
+        let hi_is_zero = self.cmp(wk.OpIEqual, [hi, zero]);
+        let lo_clz = self.clz32(lo);
+        let lo_clz = self.op32(wk.OpIAdd, [lo_clz, c32]);
+        let hi_clz = self.clz32(hi);
+        self.op32(wk.OpSelect, [hi_is_zero, lo_clz, hi_clz])
+    }
+
+    /// Get the sign (as `0` or `-1`) and absolute value of the signed 64-bit integer `x`.
+    fn sign_and_abs64(&mut self, x: [Value; 2]) -> (Value, [Value; 2]) {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        // `abs(x)` is `(x ^ sign) - sign`.
+        let c31 = self.const_u32(31);
+        let sign = self.op32(wk.OpShiftRightArithmetic, [x[1], c31]);
+        let flipped = x.map(|x| self.op32(wk.OpBitwiseXor, [x, sign]));
+        (sign, self.sub64(flipped, [sign, sign]))
+    }
+
+    /// Convert the 64-bit integer `x` to a 32-bit float (of type `f32_type`),
+    /// rounding only once (which converting each half separately wouldn't).
+    //
+    // NOTE(eddyb) the absolute value is normalized (i.e. shifted left until its
+    // leading bit is at bit 63), then its high half is converted, after setting
+    // its lowest bit if any bits in the low half are set ("round to odd"), which
+    // prevents the single rounding from going the wrong way, as the lowest bit
+    // is far enough (i.e. 8 bits) below the precision of 32-bit floats.
+    fn f32_from_int64(&mut self, signed: bool, x: [Value; 2], f32_type: Type) -> Value {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        let zero = self.const_u32(0);
+        let (sign, [lo, hi]) = if signed {
+            self.sign_and_abs64(x)
+        } else {
+            (zero, x)
+        };
+
+        let shift = self.clz32(hi);
+        let [norm_lo, norm_hi] = self.shift64(wk.OpShiftLeftLogical, [lo, hi], shift);
+        let norm_lo_nonzero = self.cmp(wk.OpINotEqual, [norm_lo, zero]);
+        let sticky = self.bool_to_u32(norm_lo_nonzero);
+        let norm_hi = self.op32(wk.OpBitwiseOr, [norm_hi, sticky]);
+        let large = self.op(wk.OpConvertUToF, [norm_hi], f32_type);
+
+        // Scale by `2^(32 - shift)`, built directly from its exponent bits.
+        let (c23, scale_exp) = (self.const_u32(23), self.const_u32(127 + 32));
+        let scale_exp = self.op32(wk.OpISub, [scale_exp, shift]);
+        let scale = self.op32(wk.OpShiftLeftLogical, [scale_exp, c23]);
+        let scale = self.op(wk.OpBitcast, [scale], f32_type);
+        let large = self.op(wk.OpFMul, [large, scale], f32_type);
+
+        let small = self.op(wk.OpConvertUToF, [lo], f32_type);
+        let hi_is_zero = self.cmp(wk.OpIEqual, [hi, zero]);
+        let abs = self.op(wk.OpSelect, [hi_is_zero, small, large], f32_type);
+        if !signed {
+            return abs;
+        }
+        let neg = self.op(wk.OpFNegate, [abs], f32_type);
+        let is_neg = self.cmp(wk.OpINotEqual, [sign, zero]);
+        self.op(wk.OpSelect, [is_neg, neg, abs], f32_type)
+    }
+
+    /// Get the high half of the (emulated) 64-bit float `x`, without its sign bit.
+    fn f64_abs_hi(&mut self, [_, hi]: [Value; 2]) -> Value {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        let abs_mask = self.const_u32(0x7fff_ffff);
+        self.op32(wk.OpBitwiseAnd, [hi, abs_mask])
+    }
+
+    /// Whether the (emulated) 64-bit float `x` is a NaN.
+    fn f64_is_nan(&mut self, x: [Value; 2]) -> Value {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        let abs_hi = self.f64_abs_hi(x);
+        let inf = [self.const_u32(0), self.const_u32(0x7ff0_0000)];
+        self.cmp64(wk.OpUGreaterThan, [x[0], abs_hi], inf)
+    }
+
+    /// Whether the (emulated) 64-bit float `x` is (positive or negative) infinity.
+    fn f64_is_inf(&mut self, x: [Value; 2]) -> Value {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        let abs_hi = self.f64_abs_hi(x);
+        let (zero, inf_hi) = (self.const_u32(0), self.const_u32(0x7ff0_0000));
+        let lo_is_zero = self.cmp(wk.OpIEqual, [x[0], zero]);
+        let hi_is_inf = self.cmp(wk.OpIEqual, [abs_hi, inf_hi]);
+        self.cmp(wk.OpLogicalAnd, [lo_is_zero, hi_is_inf])
+    }
+
+    /// Whether the (emulated) 64-bit float `x` is `+0.0` or `-0.0`.
+    fn f64_is_zero(&mut self, x: [Value; 2]) -> Value {
+        let abs_hi = self.f64_abs_hi(x);
+        self.is_zero64([x[0], abs_hi])
+    }
+
+    /// Build a (signed) infinity, or zero, with the sign of `sign` (`0` or `1`).
+    fn f64_inf_or_zero(&mut self, sign: Value, inf: bool) -> [Value; 2] {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        let zero = self.const_u32(0);
+        let c31 = self.const_u32(31);
+        let sign_bit = self.op32(wk.OpShiftLeftLogical, [sign, c31]);
+        if inf {
+            let inf_hi = self.const_u32(0x7ff0_0000);
+            [zero, self.op32(wk.OpBitwiseOr, [sign_bit, inf_hi])]
+        } else {
+            [zero, sign_bit]
+        }
+    }
+
+    /// Get the quiet NaN returned by all emulated 64-bit float operations.
+    fn f64_canonical_nan(&self) -> [Value; 2] {
+        [self.const_u32(0), self.const_u32(0x7ff8_0000)]
+    }
+
+    /// Split the (emulated) finite 64-bit float `x` into its sign (`0` or `1`),
+    /// exponent and significand (including the implicit leading bit, for normal
+    /// numbers, with subnormals instead having their exponent adjusted to `1`),
+    /// such that its absolute value is `sig * 2^(exp - 1075)`.
+    fn f64_unpack(&mut self, [lo, hi]: [Value; 2]) -> (Value, Value, [Value; 2]) {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        let (zero, one) = (self.const_u32(0), self.const_u32(1));
+        let (c20, c31) = (self.const_u32(20), self.const_u32(31));
+        let exp_mask = self.const_u32(0x7ff);
+        let (frac_hi_mask, implicit_bit) = (self.const_u32(0xf_ffff), self.const_u32(0x10_0000));
+
+        let sign = self.op32(wk.OpShiftRightLogical, [hi, c31]);
+        let exp = self.op32(wk.OpShiftRightLogical, [hi, c20]);
+        let exp = self.op32(wk.OpBitwiseAnd, [exp, exp_mask]);
+        let is_normal = self.cmp(wk.OpINotEqual, [exp, zero]);
+        let sig_hi = self.op32(wk.OpBitwiseAnd, [hi, frac_hi_mask]);
+        let implicit_bit = self.op32(wk.OpSelect, [is_normal, implicit_bit, zero]);
+        let sig_hi = self.op32(wk.OpBitwiseOr, [sig_hi, implicit_bit]);
+        let exp = self.op32(wk.OpSelect, [is_normal, exp, one]);
+        (sign, exp, [lo, sig_hi])
+    }
+
+    /// Like [`f64_unpack`](Self::f64_unpack), but also normalizing subnormals
+    /// (i.e. shifting their significand to have its leading bit at bit 52),
+    /// which can result in negative exponents.
+    fn f64_unpack_normalized(&mut self, x: [Value; 2]) -> (Value, Value, [Value; 2]) {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        let (sign, exp, sig) = self.f64_unpack(x);
+        let c11 = self.const_u32(11);
+        let shift = self.clz64(sig);
+        let shift = self.op32(wk.OpISub, [shift, c11]);
+        let sig = self.shift64(wk.OpShiftLeftLogical, sig, shift);
+        let exp = self.op32(wk.OpISub, [exp, shift]);
+        (sign, exp, sig)
+    }
+
+    /// Round (to nearest, ties to even) and pack the sign (`0` or `1`), (signed)
+    /// exponent and significand (with its leading bit at bit 62, or `0`) of
+    /// a 64-bit float (whose absolute value is `sig * 2^(exp - 1084)`),
+    /// handling overflow (to infinity) and underflow (to subnormals or zero).
+    //
+    // NOTE(eddyb) this is `softfloat_roundPackToF64` from Berkeley SoftFloat.
+    fn f64_round_pack(&mut self, sign: Value, exp: Value, sig: [Value; 2]) -> [Value; 2] {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        let (zero, one) = (self.const_u32(0), self.const_u32(1));
+        let (c20, c31) = (self.const_u32(20), self.const_u32(31));
+
+        // Tiny results become subnormals (with an exponent of `0`).
+        let is_tiny = self.cmp(wk.OpSLessThan, [exp, zero]);
+        let neg_exp = self.op32(wk.OpISub, [zero, exp]);
+        let tiny_shift = self.op32(wk.OpSelect, [is_tiny, neg_exp, zero]);
+        let sig = self.shr64_jam(sig, tiny_shift);
+        let exp = self.op32(wk.OpSelect, [is_tiny, zero, exp]);
+
+        // Round to nearest, using the 10 bits below the final significand,
+        // and clearing the lowest bit on ties (to round them to even).
+        let round_bits_mask = self.const_u32(0x3ff);
+        let half = self.const_u32(0x200);
+        let round_bits = self.op32(wk.OpBitwiseAnd, [sig[0], round_bits_mask]);
+        let sig = self.add64(sig, [half, zero]);
+        let [sig_lo, sig_hi] = self.shr64_by(sig, 10);
+        let is_tie = self.cmp(wk.OpIEqual, [round_bits, half]);
+        let tie_mask = self.op32(wk.OpSelect, [is_tie, one, zero]);
+        let tie_mask = self.op32(wk.OpNot, [tie_mask]);
+        let sig_lo = self.op32(wk.OpBitwiseAnd, [sig_lo, tie_mask]);
+        let sig_is_zero = self.is_zero64([sig_lo, sig_hi]);
+        let exp = self.op32(wk.OpSelect, [sig_is_zero, zero, exp]);
+
+        // NOTE(eddyb) the exponent is added (instead of OR-ed) into the result,
+        // so that the significand rounding up to `2^53` correctly increments
+        // the exponent (including up to infinity, for the largest exponent).
+        let exp_bits = self.op32(wk.OpShiftLeftLogical, [exp, c20]);
+        let hi = self.op32(wk.OpIAdd, [sig_hi, exp_bits]);
+        let sign_bit = self.op32(wk.OpShiftLeftLogical, [sign, c31]);
+        let hi = self.op32(wk.OpBitwiseOr, [hi, sign_bit]);
+
+        let max_exp = self.const_u32(0x7fd);
+        let overflow = self.cmp(wk.OpSGreaterThan, [exp, max_exp]);
+        let inf = self.f64_inf_or_zero(sign, true);
+        self.select(overflow, inf, [sig_lo, hi])
+    }
+
+    /// Add the (emulated) 64-bit floats `a` and `b`.
+    //
+    // NOTE(eddyb) this is a simplified (branchless) combination of
+    // `softfloat_addMagsF64` and `softfloat_subMagsF64` from Berkeley SoftFloat.
+    fn f64_add(&mut self, a: [Value; 2], b: [Value; 2]) -> [Value; 2] {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        let zero = self.const_u32(0);
+
+        // Order the inputs by magnitude, so that `x` has the larger exponent.
+        let a_abs = [a[0], self.f64_abs_hi(a)];
+        let b_abs = [b[0], self.f64_abs_hi(b)];
+        let swap = self.cmp64(wk.OpULessThan, a_abs, b_abs);
+        let x = self.select(swap, b, a);
+        let y = self.select(swap, a, b);
+
+        let (x_sign, x_exp, x_sig) = self.f64_unpack(x);
+        let (y_sign, y_exp, y_sig) = self.f64_unpack(y);
+        let same_sign = self.cmp(wk.OpIEqual, [x_sign, y_sign]);
+
+        // Add or subtract the significands (with 9 bits of extra precision,
+        // plus the sticky bit from aligning `y` to `x`), then normalize.
+        let x_sig = self.shl64_by(x_sig, 9);
+        let y_sig = self.shl64_by(y_sig, 9);
+        let exp_diff = self.op32(wk.OpISub, [x_exp, y_exp]);
+        let y_sig = self.shr64_jam(y_sig, exp_diff);
+        let sum = self.add64(x_sig, y_sig);
+        let diff = self.sub64(x_sig, y_sig);
+        let sig = self.select(same_sign, sum, diff);
+        let one = self.const_u32(1);
+        let shift = self.clz64(sig);
+        let shift = self.op32(wk.OpISub, [shift, one]);
+        let sig = self.shift64(wk.OpShiftLeftLogical, sig, shift);
+        let exp = self.op32(wk.OpISub, [x_exp, shift]);
+        let sum = self.f64_round_pack(x_sign, exp, sig);
+
+        // Exact zero results are `+0.0`, unless both inputs were `-0.0`.
+        let sig_is_zero = self.is_zero64(sig);
+        let zero_sign = self.op32(wk.OpSelect, [same_sign, x_sign, zero]);
+        let signed_zero = self.f64_inf_or_zero(zero_sign, false);
+        let sum = self.select(sig_is_zero, signed_zero, sum);
+
+        // NOTE(eddyb) as `x` has the larger magnitude, it's infinite (or NaN)
+        // if either input is, and the result is NaN if `x` is NaN, or for the
+        // sum of opposite infinities.
+        let exp_mask = self.const_u32(0x7ff);
+        let c20 = self.const_u32(20);
+        let x_raw_exp = self.op32(wk.OpShiftRightLogical, [x[1], c20]);
+        let x_raw_exp = self.op32(wk.OpBitwiseAnd, [x_raw_exp, exp_mask]);
+        let x_is_special = self.cmp(wk.OpIEqual, [x_raw_exp, exp_mask]);
+        let sum = self.select(x_is_special, x, sum);
+        let x_is_nan = self.f64_is_nan(x);
+        let y_is_inf = self.f64_is_inf(y);
+        let diff_sign = self.cmp(wk.OpLogicalNot, [same_sign]);
+        let inf_minus_inf = self.cmp(wk.OpLogicalAnd, [y_is_inf, diff_sign]);
+        let is_nan = self.cmp(wk.OpLogicalOr, [x_is_nan, inf_minus_inf]);
+        let nan = self.f64_canonical_nan();
+        self.select(is_nan, nan, sum)
+    }
+
+    /// Select the result of an (emulated) 64-bit float multiplication or
+    /// division, for special cases (i.e. if it's infinite, zero, or NaN).
+    fn f64_mul_or_div_special_cases(
+        &mut self,
+        result: [Value; 2],
+        sign: Value,
+        [is_inf, is_zero, is_nan]: [Value; 3],
+    ) -> [Value; 2] {
+        let zero = self.f64_inf_or_zero(sign, false);
+        let result = self.select(is_zero, zero, result);
+        let inf = self.f64_inf_or_zero(sign, true);
+        let result = self.select(is_inf, inf, result);
+        let nan = self.f64_canonical_nan();
+        self.select(is_nan, nan, result)
+    }
+
+    /// Multiply the (emulated) 64-bit floats `a` and `b`.
+    //
+    // NOTE(eddyb) this is a simplified (branchless) `f64_mul` from Berkeley SoftFloat.
+    fn f64_mul(&mut self, a: [Value; 2], b: [Value; 2]) -> [Value; 2] {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        let (zero, one) = (self.const_u32(0), self.const_u32(1));
+
+        let (a_sign, a_exp, a_sig) = self.f64_unpack_normalized(a);
+        let (b_sign, b_exp, b_sig) = self.f64_unpack_normalized(b);
+        let sign = self.op32(wk.OpBitwiseXor, [a_sign, b_sign]);
+
+        // The 128-bit product has its leading bit at bit 125 or 126, and its
+        // lower 64 bits only matter for rounding (i.e. as a sticky bit).
+        let exp = self.op32(wk.OpIAdd, [a_exp, b_exp]);
+        let bias = self.const_u32(0x3ff);
+        let exp = self.op32(wk.OpISub, [exp, bias]);
+        let a_sig = self.shl64_by(a_sig, 10);
+        let b_sig = self.shl64_by(b_sig, 11);
+        let [w0, w1, w2, w3] = self.mul64_wide(a_sig, b_sig);
+        let low_is_zero = self.is_zero64([w0, w1]);
+        let sticky = self.op32(wk.OpSelect, [low_is_zero, zero, one]);
+        let sig = [self.op32(wk.OpBitwiseOr, [w2, sticky]), w3];
+        let bit62 = self.const_u32(0x4000_0000);
+        let is_small = self.cmp(wk.OpULessThan, [w3, bit62]);
+        let exp_minus_one = self.op32(wk.OpISub, [exp, one]);
+        let exp = self.op32(wk.OpSelect, [is_small, exp_minus_one, exp]);
+        let sig_shifted = self.shl64_by(sig, 1);
+        let sig = self.select(is_small, sig_shifted, sig);
+        let product = self.f64_round_pack(sign, exp, sig);
+
+        let [a_is_nan, a_is_inf, a_is_zero] =
+            [Self::f64_is_nan, Self::f64_is_inf, Self::f64_is_zero].map(|f| f(self, a));
+        let [b_is_nan, b_is_inf, b_is_zero] =
+            [Self::f64_is_nan, Self::f64_is_inf, Self::f64_is_zero].map(|f| f(self, b));
+        let is_inf = self.cmp(wk.OpLogicalOr, [a_is_inf, b_is_inf]);
+        let is_zero = self.cmp(wk.OpLogicalOr, [a_is_zero, b_is_zero]);
+        let any_nan = self.cmp(wk.OpLogicalOr, [a_is_nan, b_is_nan]);
+        let inf_times_zero = self.cmp(wk.OpLogicalAnd, [is_inf, is_zero]);
+        let is_nan = self.cmp(wk.OpLogicalOr, [any_nan, inf_times_zero]);
+        self.f64_mul_or_div_special_cases(product, sign, [is_inf, is_zero, is_nan])
+    }
+
+    /// Start dividing the (emulated) 64-bit floats `a` and `b`, returning the
+    /// sign and exponent of the quotient, and the initial remainder and divisor
+    /// for long division (see [`f64_div_step`](Self::f64_div_step)).
+    //
+    // NOTE(eddyb) this (and the rest of the division) is based on `f64_div`
+    // from Berkeley SoftFloat, but using bit-by-bit long division.
+    fn f64_div_start(
+        &mut self,
+        a: [Value; 2],
+        b: [Value; 2],
+    ) -> (Value, Value, [Value; 2], [Value; 2]) {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        let (a_sign, a_exp, a_sig) = self.f64_unpack_normalized(a);
+        let (b_sign, b_exp, b_sig) = self.f64_unpack_normalized(b);
+        let sign = self.op32(wk.OpBitwiseXor, [a_sign, b_sign]);
+
+        // The remainder starts out in `b_sig..2*b_sig` (i.e. the first quotient
+        // bit is always `1`, for nonzero finite inputs).
+        let exp = self.op32(wk.OpISub, [a_exp, b_exp]);
+        let bias = self.const_u32(0x3fe);
+        let exp = self.op32(wk.OpIAdd, [exp, bias]);
+        let is_small = self.cmp64(wk.OpULessThan, a_sig, b_sig);
+        let one = self.const_u32(1);
+        let exp_minus_one = self.op32(wk.OpISub, [exp, one]);
+        let exp = self.op32(wk.OpSelect, [is_small, exp_minus_one, exp]);
+        let a_sig_shifted = self.shl64_by(a_sig, 1);
+        let rem = self.select(is_small, a_sig_shifted, a_sig);
+        (sign, exp, rem, b_sig)
+    }
+
+    /// Compute the next bit of the quotient `q` (of 64-bit float division),
+    /// returning the updated quotient and remainder.
+    fn f64_div_step(
+        &mut self,
+        q: [Value; 2],
+        rem: [Value; 2],
+        b_sig: [Value; 2],
+    ) -> ([Value; 2], [Value; 2]) {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        let ge = self.cmp64(wk.OpUGreaterThanEqual, rem, b_sig);
+        let rem_minus_b = self.sub64(rem, b_sig);
+        let rem = self.select(ge, rem_minus_b, rem);
+        let q_bit = self.bool_to_u32(ge);
+        let [q_lo, q_hi] = self.shl64_by(q, 1);
+        let q = [self.op32(wk.OpBitwiseOr, [q_lo, q_bit]), q_hi];
+        (q, self.shl64_by(rem, 1))
+    }
+
+    /// Finish dividing the (emulated) 64-bit floats `a` and `b`, from the
+    /// sign and exponent computed by [`f64_div_start`](Self::f64_div_start),
+    /// and the 55-bit quotient and final remainder.
+    fn f64_div_finish(
+        &mut self,
+        [a, b]: [[Value; 2]; 2],
+        sign: Value,
+        exp: Value,
+        q: [Value; 2],
+        rem: [Value; 2],
+    ) -> [Value; 2] {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        let (zero, one) = (self.const_u32(0), self.const_u32(1));
+        let [q_lo, q_hi] = self.shl64_by(q, 8);
+        let rem_is_zero = self.is_zero64(rem);
+        let sticky = self.op32(wk.OpSelect, [rem_is_zero, zero, one]);
+        let sig = [self.op32(wk.OpBitwiseOr, [q_lo, sticky]), q_hi];
+        let quotient = self.f64_round_pack(sign, exp, sig);
+
+        let [a_is_nan, a_is_inf, a_is_zero] =
+            [Self::f64_is_nan, Self::f64_is_inf, Self::f64_is_zero].map(|f| f(self, a));
+        let [b_is_nan, b_is_inf, b_is_zero] =
+            [Self::f64_is_nan, Self::f64_is_inf, Self::f64_is_zero].map(|f| f(self, b));
+        let is_inf = self.cmp(wk.OpLogicalOr, [a_is_inf, b_is_zero]);
+        let is_zero = self.cmp(wk.OpLogicalOr, [a_is_zero, b_is_inf]);
+        let any_nan = self.cmp(wk.OpLogicalOr, [a_is_nan, b_is_nan]);
+        let inf_by_inf = self.cmp(wk.OpLogicalAnd, [a_is_inf, b_is_inf]);
+        let zero_by_zero = self.cmp(wk.OpLogicalAnd, [a_is_zero, b_is_zero]);
+        let is_nan = self.cmp(wk.OpLogicalOr, [any_nan, inf_by_inf]);
+        let is_nan = self.cmp(wk.OpLogicalOr, [is_nan, zero_by_zero]);
+        self.f64_mul_or_div_special_cases(quotient, sign, [is_inf, is_zero, is_nan])
+    }
+
+    /// Compare the (emulated) 64-bit floats `a` and `b` with `opcode` (one of
+    /// the `OpFOrd*` or `OpFUnord*` comparisons).
+    fn f64_cmp(&mut self, opcode: Opcode, a: [Value; 2], b: [Value; 2]) -> Value {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        let (ord_ops, unord_ops) = (
+            [
+                wk.OpFOrdEqual,
+                wk.OpFOrdNotEqual,
+                wk.OpFOrdLessThan,
+                wk.OpFOrdGreaterThan,
+                wk.OpFOrdLessThanEqual,
+                wk.OpFOrdGreaterThanEqual,
+            ],
+            [
+                wk.OpFUnordEqual,
+                wk.OpFUnordNotEqual,
+                wk.OpFUnordLessThan,
+                wk.OpFUnordGreaterThan,
+                wk.OpFUnordLessThanEqual,
+                wk.OpFUnordGreaterThanEqual,
+            ],
+        );
+        let (is_ordered, op_idx) = match ord_ops.iter().position(|&op| op == opcode) {
+            Some(i) => (true, i),
+            None => (
+                false,
+                unord_ops.iter().position(|&op| op == opcode).unwrap(),
+            ),
+        };
+
+        // Map the floats to signed 64-bit integers with the same ordering,
+        // i.e. negating negative floats (making `-0.0` equal to `+0.0`).
+        let zero = self.const_u32(0);
+        let c31 = self.const_u32(31);
+        let [a_key, b_key] = [a, b].map(|x| {
+            let abs = [x[0], self.f64_abs_hi(x)];
+            let neg_abs = self.sub64([zero, zero], abs);
+            let is_neg = self.op32(wk.OpShiftRightLogical, [x[1], c31]);
+            let is_neg = self.cmp(wk.OpINotEqual, [is_neg, zero]);
+            self.select(is_neg, neg_abs, abs)
+        });
+        let cmp = match op_idx {
+            0 | 1 => {
+                let lo_eq = self.cmp(wk.OpIEqual, [a_key[0], b_key[0]]);
+                let hi_eq = self.cmp(wk.OpIEqual, [a_key[1], b_key[1]]);
+                let eq = self.cmp(wk.OpLogicalAnd, [lo_eq, hi_eq]);
+                if op_idx == 0 {
+                    eq
+                } else {
+                    self.cmp(wk.OpLogicalNot, [eq])
+                }
+            }
+            _ => {
+                let int_op = [
+                    wk.OpSLessThan,
+                    wk.OpSGreaterThan,
+                    wk.OpSLessThanEqual,
+                    wk.OpSGreaterThanEqual,
+                ][op_idx - 2];
+                self.cmp64(int_op, a_key, b_key)
+            }
+        };
+
+        let a_is_nan = self.f64_is_nan(a);
+        let b_is_nan = self.f64_is_nan(b);
+        let any_nan = self.cmp(wk.OpLogicalOr, [a_is_nan, b_is_nan]);
+        if is_ordered {
+            let not_nan = self.cmp(wk.OpLogicalNot, [any_nan]);
+            self.cmp(wk.OpLogicalAnd, [not_nan, cmp])
+        } else {
+            self.cmp(wk.OpLogicalOr, [any_nan, cmp])
+        }
+    }
+
+    /// Convert the 64-bit integer `x` to an (emulated) 64-bit float.
+    fn f64_from_int(&mut self, signed: bool, x: [Value; 2]) -> [Value; 2] {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        let (zero, one) = (self.const_u32(0), self.const_u32(1));
+        let c31 = self.const_u32(31);
+
+        let (sign, abs) = if signed {
+            let (sign, abs) = self.sign_and_abs64(x);
+            (self.op32(wk.OpBitwiseAnd, [sign, one]), abs)
+        } else {
+            (zero, x)
+        };
+
+        // Values with the top bit set (which can't be normalized to have their
+        // leading bit at bit 62) are first halved (keeping the lost bit as sticky).
+        let top_bit = self.op32(wk.OpShiftRightLogical, [abs[1], c31]);
+        let has_top_bit = self.cmp(wk.OpINotEqual, [top_bit, zero]);
+        let halved = self.shr64_jam(abs, one);
+        let abs = self.select(has_top_bit, halved, abs);
+        let exp = self.const_u32(0x43c);
+        let exp = self.op32(wk.OpIAdd, [exp, top_bit]);
+        let shift = self.clz64(abs);
+        let shift = self.op32(wk.OpISub, [shift, one]);
+        let sig = self.shift64(wk.OpShiftLeftLogical, abs, shift);
+        let exp = self.op32(wk.OpISub, [exp, shift]);
+        self.f64_round_pack(sign, exp, sig)
+    }
+
+    /// Convert the (emulated) 64-bit float `x` to a 64-bit integer, rounding
+    /// towards zero (with unspecified results for NaNs and out-of-range values).
+    fn f64_to_int(&mut self, signed: bool, x: [Value; 2]) -> [Value; 2] {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        let (zero, c31, c63) = (self.const_u32(0), self.const_u32(31), self.const_u32(63));
+
+        // NOTE(eddyb) `abs(x)` is `sig * 2^(exp - 1075)`, and `shift64` can't
+        // cause undefined behavior (even for out-of-range shift amounts).
+        let (_, exp, sig) = self.f64_unpack(x);
+        let c1075 = self.const_u32(1075);
+        let is_int = self.cmp(wk.OpUGreaterThanEqual, [exp, c1075]);
+        let left_shift = self.op32(wk.OpISub, [exp, c1075]);
+        let right_shift = self.op32(wk.OpISub, [c1075, exp]);
+        let left_shift = self.op32(wk.OpSelect, [is_int, left_shift, zero]);
+        let right_shift = self.op32(wk.OpSelect, [is_int, zero, right_shift]);
+        let is_tiny = self.cmp(wk.OpUGreaterThan, [right_shift, c63]);
+        let abs = self.shift64(wk.OpShiftLeftLogical, sig, left_shift);
+        let abs = self.shift64(wk.OpShiftRightLogical, abs, right_shift);
+        let abs = self.select(is_tiny, [zero, zero], abs);
+        if !signed {
+            return abs;
+        }
+        let neg_abs = self.sub64([zero, zero], abs);
+        let sign = self.op32(wk.OpShiftRightLogical, [x[1], c31]);
+        let is_neg = self.cmp(wk.OpINotEqual, [sign, zero]);
+        self.select(is_neg, neg_abs, abs)
+    }
+
+    /// Convert the 32-bit float `x` (given as its bits) to an (emulated) 64-bit float.
+    fn f64_from_f32_bits(&mut self, x: Value) -> [Value; 2] {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        let (zero, one) = (self.const_u32(0), self.const_u32(1));
+        let (c3, c8, c20, c23, c29, c31) = (
+            self.const_u32(3),
+            self.const_u32(8),
+            self.const_u32(20),
+            self.const_u32(23),
+            self.const_u32(29),
+            self.const_u32(31),
+        );
+        let (exp_mask, frac_mask) = (self.const_u32(0xff), self.const_u32(0x7f_ffff));
+
+        let sign = self.op32(wk.OpShiftRightLogical, [x, c31]);
+        let raw_exp = self.op32(wk.OpShiftRightLogical, [x, c23]);
+        let raw_exp = self.op32(wk.OpBitwiseAnd, [raw_exp, exp_mask]);
+        let frac = self.op32(wk.OpBitwiseAnd, [x, frac_mask]);
+
+        // Normalize subnormals (all of which are normal as 64-bit floats), so
+        // that the leading bit is at bit 23 (where it's then removed from).
+        let is_normal = self.cmp(wk.OpINotEqual, [raw_exp, zero]);
+        let implicit_bit = self.const_u32(0x80_0000);
+        let implicit_bit = self.op32(wk.OpSelect, [is_normal, implicit_bit, zero]);
+        let sig = self.op32(wk.OpBitwiseOr, [frac, implicit_bit]);
+        let exp = self.op32(wk.OpSelect, [is_normal, raw_exp, one]);
+        let shift = self.clz32(sig);
+        let shift = self.op32(wk.OpISub, [shift, c8]);
+        let sig = self.op32(wk.OpShiftLeftLogical, [sig, shift]);
+        let exp = self.op32(wk.OpISub, [exp, shift]);
+        let exp_bias_diff = self.const_u32(1023 - 127);
+        let exp = self.op32(wk.OpIAdd, [exp, exp_bias_diff]);
+        let sig = self.op32(wk.OpBitwiseAnd, [sig, frac_mask]);
+
+        let sign_bit = self.op32(wk.OpShiftLeftLogical, [sign, c31]);
+        let exp_bits = self.op32(wk.OpShiftLeftLogical, [exp, c20]);
+        let sig_hi = self.op32(wk.OpShiftRightLogical, [sig, c3]);
+        let hi = self.op32(wk.OpBitwiseOr, [sign_bit, exp_bits]);
+        let hi = self.op32(wk.OpBitwiseOr, [hi, sig_hi]);
+        let lo = self.op32(wk.OpShiftLeftLogical, [sig, c29]);
+        let is_zero = self.cmp(wk.OpIEqual, [x, sign_bit]);
+        let signed_zero = self.f64_inf_or_zero(sign, false);
+        let result = self.select(is_zero, signed_zero, [lo, hi]);
+
+        // Infinities stay infinite, while NaNs become the canonical NaN.
+        let is_special = self.cmp(wk.OpIEqual, [raw_exp, exp_mask]);
+        let frac_is_zero = self.cmp(wk.OpIEqual, [frac, zero]);
+        let inf = self.f64_inf_or_zero(sign, true);
+        let nan = self.f64_canonical_nan();
+        let special = self.select(frac_is_zero, inf, nan);
+        self.select(is_special, special, result)
+    }
+
+    /// Convert the (emulated) 64-bit float `x` to a 32-bit float (returning
+    /// its bits), rounding to nearest (ties to even).
+    //
+    // NOTE(eddyb) this is a simplified (branchless) `f64_to_f32` (and its use
+    // of `softfloat_roundPackToF32`) from Berkeley SoftFloat.
+    fn f64_to_f32_bits(&mut self, [lo, hi]: [Value; 2]) -> Value {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        let (zero, one) = (self.const_u32(0), self.const_u32(1));
+        let (c7, c10, c20, c22, c23, c31) = (
+            self.const_u32(7),
+            self.const_u32(10),
+            self.const_u32(20),
+            self.const_u32(22),
+            self.const_u32(23),
+            self.const_u32(31),
+        );
+
+        let sign = self.op32(wk.OpShiftRightLogical, [hi, c31]);
+        let sign_bit = self.op32(wk.OpShiftLeftLogical, [sign, c31]);
+        let exp = self.op32(wk.OpShiftRightLogical, [hi, c20]);
+        let exp_mask = self.const_u32(0x7ff);
+        let raw_exp = self.op32(wk.OpBitwiseAnd, [exp, exp_mask]);
+
+        // Keep the top 30 bits of the significand (with its leading bit at bit
+        // 30), with the rest of the bits only used as a sticky bit. Subnormals
+        // (and zeros) are far enough below the 32-bit float range to not need
+        // special handling (as they always underflow, with the right sign).
+        let frac_hi_mask = self.const_u32(0xf_ffff);
+        let frac_hi = self.op32(wk.OpBitwiseAnd, [hi, frac_hi_mask]);
+        let sig = self.op32(wk.OpShiftLeftLogical, [frac_hi, c10]);
+        let lo_top = self.op32(wk.OpShiftRightLogical, [lo, c22]);
+        let sig = self.op32(wk.OpBitwiseOr, [sig, lo_top]);
+        let lo_rest_mask = self.const_u32(0x3f_ffff);
+        let lo_rest = self.op32(wk.OpBitwiseAnd, [lo, lo_rest_mask]);
+        let has_lo_rest = self.cmp(wk.OpINotEqual, [lo_rest, zero]);
+        let sticky = self.bool_to_u32(has_lo_rest);
+        let sig = self.op32(wk.OpBitwiseOr, [sig, sticky]);
+        let frac_is_zero = self.cmp(wk.OpIEqual, [sig, zero]);
+        let implicit_bit = self.const_u32(0x4000_0000);
+        let sig = self.op32(wk.OpBitwiseOr, [sig, implicit_bit]);
+        let exp_bias_diff = self.const_u32(1023 - 127 + 1);
+        let exp = self.op32(wk.OpISub, [raw_exp, exp_bias_diff]);
+
+        // Tiny results become subnormals (with an exponent of `0`).
+        let is_tiny = self.cmp(wk.OpSLessThan, [exp, zero]);
+        let neg_exp = self.op32(wk.OpISub, [zero, exp]);
+        let tiny_shift = self.op32(wk.OpSelect, [is_tiny, neg_exp, zero]);
+        let [sig, _] = self.shr64_jam([sig, zero], tiny_shift);
+        let exp = self.op32(wk.OpSelect, [is_tiny, zero, exp]);
+
+        // Round to nearest, using the 7 bits below the final significand,
+        // and clearing the lowest bit on ties (to round them to even).
+        let round_bits_mask = self.const_u32(0x7f);
+        let half = self.const_u32(0x40);
+        let round_bits = self.op32(wk.OpBitwiseAnd, [sig, round_bits_mask]);
+        let sig = self.op32(wk.OpIAdd, [sig, half]);
+        let sig = self.op32(wk.OpShiftRightLogical, [sig, c7]);
+        let is_tie = self.cmp(wk.OpIEqual, [round_bits, half]);
+        let tie_mask = self.op32(wk.OpSelect, [is_tie, one, zero]);
+        let tie_mask = self.op32(wk.OpNot, [tie_mask]);
+        let sig = self.op32(wk.OpBitwiseAnd, [sig, tie_mask]);
+        let sig_is_zero = self.cmp(wk.OpIEqual, [sig, zero]);
+        let exp = self.op32(wk.OpSelect, [sig_is_zero, zero, exp]);
+        let exp_bits = self.op32(wk.OpShiftLeftLogical, [exp, c23]);
+        let result = self.op32(wk.OpIAdd, [sig, exp_bits]);
+        let result = self.op32(wk.OpBitwiseOr, [result, sign_bit]);
+
+        let max_exp = self.const_u32(0xfd);
+        let overflow = self.cmp(wk.OpSGreaterThan, [exp, max_exp]);
+        let inf = self.const_u32(0x7f80_0000);
+        let inf = self.op32(wk.OpBitwiseOr, [inf, sign_bit]);
+        let nan = self.const_u32(0x7fc0_0000);
+        let result = self.op32(wk.OpSelect, [overflow, inf, result]);
+
+        let is_special = self.cmp(wk.OpIEqual, [raw_exp, exp_mask]);
+        let special = self.op32(wk.OpSelect, [frac_is_zero, inf, nan]);
+        self.op32(wk.OpSelect, [is_special, special, result])
+    }
+}
+
+/// Start building a helper function taking `params` (as the inputs of the
+/// body region of the returned `FuncDefBody`, which starts out empty).
+fn helper_func_def_body(cx: &Context, params: &[Type]) -> FuncDefBody {
+    let mut control_regions = EntityDefs::default();
+    let body = control_regions.define(
+        cx,
+        ControlRegionDef {
+            inputs: params
+                .iter()
+                .map(|&ty| ControlRegionInputDecl {
+                    attrs: AttrSet::default(),
+                    ty,
+                })
+                .collect(),
+            children: EntityList::empty(),
+            outputs: SmallVec::new(),
+        },
+    );
+    FuncDefBody {
+        control_regions,
+        control_nodes: Default::default(),
+        data_insts: Default::default(),
+        body,
+        unstructured_cfg: None,
+    }
+}
+
+/// Define a loop body region (for a helper function), with `input_count`
+/// `u32` inputs (and no children or outputs, to be added later).
+fn helper_loop_body(
+    cx: &Context,
+    types: &Int64EmulationTypes,
+    func_def_body: &mut FuncDefBody,
+    input_count: usize,
+) -> ControlRegion {
+    func_def_body.control_regions.define(
+        cx,
+        ControlRegionDef {
+            inputs: (0..input_count)
+                .map(|_| ControlRegionInputDecl {
+                    attrs: AttrSet::default(),
+                    ty: types.u32,
+                })
+                .collect(),
+            children: EntityList::empty(),
+            outputs: SmallVec::new(),
+        },
+    )
+}
+
+/// Append a new control node (of kind `kind`) to `region` (in a helper function).
+fn append_control_node(
+    cx: &Context,
+    func_def_body: &mut FuncDefBody,
+    region: ControlRegion,
+    kind: ControlNodeKind,
+) {
+    let control_node = func_def_body.control_nodes.define(
+        cx,
+        ControlNodeDef {
+            attrs: AttrSet::default(),
+            kind,
+            outputs: SmallVec::new(),
+        }
+        .into(),
+    );
+    func_def_body.control_regions[region]
+        .children
+        .insert_last(control_node, &mut func_def_body.control_nodes);
+}
+
+/// Finish building a helper function (see [`helper_func_def_body`]), which
+/// returns `output` (of type `ret_type`).
+fn helper_func_decl(
+    mut func_def_body: FuncDefBody,
+    params: &[Type],
+    output: Value,
+    ret_type: Type,
+) -> FuncDecl {
+    func_def_body.control_regions[func_def_body.body].outputs = [output].into_iter().collect();
+    FuncDecl {
+        attrs: AttrSet::default(),
+        ret_type,
+        params: params
+            .iter()
+            .map(|&ty| FuncParam {
+                attrs: AttrSet::default(),
+                ty,
+            })
+            .collect(),
+        def: DeclDef::Present(func_def_body),
+    }
+}
+
+/// Build the declaration of the helper function for division (and remainder),
+/// which takes two (emulated) 64-bit unsigned integers, and returns both their
+/// quotient and remainder, computed with long (i.e. bit-by-bit) division.
+fn divmod_func_decl(cx: &Context, types: &Int64EmulationTypes) -> FuncDecl {
+    let wk = &spv::spec::Spec::get().well_known;
+
+    let params = [types.u32x2; 2];
+    let mut func_def_body = helper_func_def_body(cx, &params);
+    let body = func_def_body.body;
+    let loop_body = helper_loop_body(cx, types, &mut func_def_body, 5);
+
+    let mut builder = Builder {
+        cx,
+        types,
+        func_def_body: &mut func_def_body,
+        insts: EntityList::empty(),
+    };
+    let u32 = types.u32;
+    let [a, b] = [0, 1].map(|input_idx| Value::ControlRegionInput {
+        region: body,
+        input_idx,
+    });
+    let [a_lo, a_hi, b_lo, b_hi] =
+        [(a, 0), (a, 1), (b, 0), (b, 1)].map(|(v, i)| builder.extract(v, &[i], u32));
+    let entry_insts = mem::take(&mut builder.insts);
+
+    // The loop state is the quotient and remainder (each split into halves),
+    // and the number of bits left to process (counting down from `64`).
+    let [q_lo, q_hi, r_lo, r_hi, bits_left] =
+        [0, 1, 2, 3, 4].map(|input_idx| Value::ControlRegionInput {
+            region: loop_body,
+            input_idx,
+        });
+    let (zero, one) = (builder.const_u32(0), builder.const_u32(1));
+    let (c31, c32, c64) = (
+        builder.const_u32(31),
+        builder.const_u32(32),
+        builder.const_u32(64),
+    );
+
+    let i = builder.op32(wk.OpISub, [bits_left, one]);
+    let shift = builder.op32(wk.OpBitwiseAnd, [i, c31]);
+    let in_lo = builder.cmp(wk.OpULessThan, [i, c32]);
+
+    // Shift the next bit of `a` into the remainder.
+    let a_word = builder.op32(wk.OpSelect, [in_lo, a_lo, a_hi]);
+    let a_bit = builder.op32(wk.OpShiftRightLogical, [a_word, shift]);
+    let a_bit = builder.op32(wk.OpBitwiseAnd, [a_bit, one]);
+    let r_carry = builder.op32(wk.OpShiftRightLogical, [r_hi, c31]);
+    let r_lo_top = builder.op32(wk.OpShiftRightLogical, [r_lo, c31]);
+    let r_hi = builder.op32(wk.OpShiftLeftLogical, [r_hi, one]);
+    let r_hi = builder.op32(wk.OpBitwiseOr, [r_hi, r_lo_top]);
+    let r_lo = builder.op32(wk.OpShiftLeftLogical, [r_lo, one]);
+    let r_lo = builder.op32(wk.OpBitwiseOr, [r_lo, a_bit]);
+
+    // Subtract `b` from the remainder if possible (i.e. if the remainder
+    // overflowed 64 bits above, or is otherwise at least `b`).
+    let r_overflow = builder.cmp(wk.OpINotEqual, [r_carry, zero]);
+    let r_ge_b = builder.cmp64(wk.OpUGreaterThanEqual, [r_lo, r_hi], [b_lo, b_hi]);
+    let sub = builder.cmp(wk.OpLogicalOr, [r_overflow, r_ge_b]);
+    let r_minus_b = builder.sub64([r_lo, r_hi], [b_lo, b_hi]);
+    let [r_lo, r_hi] = builder.select(sub, r_minus_b, [r_lo, r_hi]);
+
+    // Set the respective quotient bit, if `b` was subtracted.
+    let q_bit = builder.op32(wk.OpSelect, [sub, one, zero]);
+    let q_bit = builder.op32(wk.OpShiftLeftLogical, [q_bit, shift]);
+    let [q_lo_bit, q_hi_bit] = builder.select(in_lo, [q_bit, zero], [zero, q_bit]);
+    let q_lo = builder.op32(wk.OpBitwiseOr, [q_lo, q_lo_bit]);
+    let q_hi = builder.op32(wk.OpBitwiseOr, [q_hi, q_hi_bit]);
+
+    let repeat_condition = builder.cmp(wk.OpINotEqual, [i, zero]);
+    let loop_body_insts = mem::take(&mut builder.insts);
+
+    // NOTE(eddyb) values defined in the loop body are used after the loop,
+    // to get the quotient and remainder from the last iteration.
+    let q = builder.op(wk.OpCompositeConstruct, [q_lo, q_hi], types.u32x2);
+    let r = builder.op(wk.OpCompositeConstruct, [r_lo, r_hi], types.u32x2);
+    let q_and_r = builder.op(wk.OpCompositeConstruct, [q, r], types.u32x2_pair);
+    let exit_insts = mem::take(&mut builder.insts);
+
+    append_control_node(
+        cx,
+        &mut func_def_body,
+        loop_body,
+        ControlNodeKind::Block {
+            insts: loop_body_insts,
+        },
+    );
+    func_def_body.control_regions[loop_body].outputs =
+        [q_lo, q_hi, r_lo, r_hi, i].into_iter().collect();
+
+    for kind in [
+        ControlNodeKind::Block { insts: entry_insts },
+        ControlNodeKind::Loop {
+            initial_inputs: [zero, zero, zero, zero, c64].into_iter().collect(),
+            body: loop_body,
+            repeat_condition,
+        },
+        ControlNodeKind::Block { insts: exit_insts },
+    ] {
+        append_control_node(cx, &mut func_def_body, body, kind);
+    }
+
+    helper_func_decl(func_def_body, &params, q_and_r, types.u32x2_pair)
+}
+
+/// Build the declaration of a helper function for a 64-bit float operation
+/// (i.e. `OpFAdd` or `OpFMul`), with its body built by `build`, from the
+/// halves of the two (emulated) 64-bit floats it takes.
+fn f64_binop_func_decl(
+    cx: &Context,
+    types: &Int64EmulationTypes,
+    build: impl FnOnce(&mut Builder<'_>, [Value; 2], [Value; 2]) -> [Value; 2],
+) -> FuncDecl {
+    let wk = &spv::spec::Spec::get().well_known;
+
+    let params = [types.u32x2; 2];
+    let mut func_def_body = helper_func_def_body(cx, &params);
+    let body = func_def_body.body;
+
+    let mut builder = Builder {
+        cx,
+        types,
+        func_def_body: &mut func_def_body,
+        insts: EntityList::empty(),
+    };
+    let [a, b] = [0, 1].map(|input_idx| {
+        let v = Value::ControlRegionInput {
+            region: body,
+            input_idx,
+        };
+        [0, 1].map(|i| builder.extract(v, &[i], types.u32))
+    });
+    let [lo, hi] = build(&mut builder, a, b);
+    let output = builder.op(wk.OpCompositeConstruct, [lo, hi], types.u32x2);
+    let insts = mem::take(&mut builder.insts);
+
+    append_control_node(
+        cx,
+        &mut func_def_body,
+        body,
+        ControlNodeKind::Block { insts },
+    );
+    helper_func_decl(func_def_body, &params, output, types.u32x2)
+}
+
+/// Build the declaration of the helper function for 64-bit float division,
+/// which takes two (emulated) 64-bit floats, and computes the significand of
+/// their quotient with long (i.e. bit-by-bit) division, in a loop (see also
+/// [`Builder::f64_div_start`]).
+fn f64_div_func_decl(cx: &Context, types: &Int64EmulationTypes) -> FuncDecl {
+    let wk = &spv::spec::Spec::get().well_known;
+
+    let params = [types.u32x2; 2];
+    let mut func_def_body = helper_func_def_body(cx, &params);
+    let body = func_def_body.body;
+    let loop_body = helper_loop_body(cx, types, &mut func_def_body, 5);
+
+    let mut builder = Builder {
+        cx,
+        types,
+        func_def_body: &mut func_def_body,
+        insts: EntityList::empty(),
+    };
+    let [a, b] = [0, 1].map(|input_idx| {
+        let v = Value::ControlRegionInput {
+            region: body,
+            input_idx,
+        };
+        [0, 1].map(|i| builder.extract(v, &[i], types.u32))
+    });
+    let (sign, exp, initial_rem, b_sig) = builder.f64_div_start(a, b);
+    let entry_insts = mem::take(&mut builder.insts);
+
+    // The loop state is the quotient and remainder (each split into halves),
+    // and the number of quotient bits left to compute (counting down from `55`,
+    // i.e. the 53 bits of precision, and two more bits for rounding).
+    let [q_lo, q_hi, rem_lo, rem_hi, bits_left] =
+        [0, 1, 2, 3, 4].map(|input_idx| Value::ControlRegionInput {
+            region: loop_body,
+            input_idx,
+        });
+    let (zero, one, c55) = (
+        builder.const_u32(0),
+        builder.const_u32(1),
+        builder.const_u32(55),
+    );
+    let (q, rem) = builder.f64_div_step([q_lo, q_hi], [rem_lo, rem_hi], b_sig);
+    let bits_left = builder.op32(wk.OpISub, [bits_left, one]);
+    let repeat_condition = builder.cmp(wk.OpINotEqual, [bits_left, zero]);
+    let loop_body_insts = mem::take(&mut builder.insts);
+
+    // NOTE(eddyb) values defined in the loop body are used after the loop,
+    // to get the quotient and remainder from the last iteration.
+    let [lo, hi] = builder.f64_div_finish([a, b], sign, exp, q, rem);
+    let output = builder.op(wk.OpCompositeConstruct, [lo, hi], types.u32x2);
+    let exit_insts = mem::take(&mut builder.insts);
+
+    append_control_node(
+        cx,
+        &mut func_def_body,
+        loop_body,
+        ControlNodeKind::Block {
+            insts: loop_body_insts,
+        },
+    );
+    func_def_body.control_regions[loop_body].outputs = [q[0], q[1], rem[0], rem[1], bits_left]
+        .into_iter()
+        .collect();
+
+    for kind in [
+        ControlNodeKind::Block { insts: entry_insts },
+        ControlNodeKind::Loop {
+            initial_inputs: [zero, zero, initial_rem[0], initial_rem[1], c55]
+                .into_iter()
+                .collect(),
+            body: loop_body,
+            repeat_condition,
+        },
+        ControlNodeKind::Block { insts: exit_insts },
+    ] {
+        append_control_node(cx, &mut func_def_body, body, kind);
+    }
+
+    helper_func_decl(func_def_body, &params, output, types.u32x2)
+}
+
+struct Int64Lowerer<'a> {
+    builder: Builder<'a>,
+    emulated: Emulated,
+
+    /// Helper functions injected into the module (see [`Helper`]).
+    helper_funcs: &'a FxHashMap<Helper, Func>,
+
+    /// Low and high halves of (the emulated 64-bit outputs of) emulated instructions.
+    halves: FxHashMap<DataInst, [Value; 2]>,
+}
+
+impl Int64Lowerer<'_> {
+    fn lower_in_region(&mut self, region: ControlRegion) {
+        let children: SmallVec<[_; 8]> = self
+            .builder
+            .func_def_body
+            .at(region)
+            .at_children()
+            .into_iter()
+            .map(|func_at_control_node| func_at_control_node.position)
+            .collect();
+        for control_node in children {
+            let func_def_body = &mut *self.builder.func_def_body;
+            let mut old_insts = match &mut func_def_body.control_nodes[control_node].kind {
+                ControlNodeKind::Block { insts } => mem::take(insts),
+                ControlNodeKind::Select { cases, .. } => {
+                    for case in cases.clone() {
+                        self.lower_in_region(case);
+                    }
+                    continue;
+                }
+                &mut ControlNodeKind::Loop { body, .. } => {
+                    self.lower_in_region(body);
+                    continue;
+                }
+                ControlNodeKind::ExitInvocation { .. } => continue,
+            };
+
+            // NOTE(eddyb) as `EntityList` doesn't support inserting before a node,
+            // the whole list of instructions is rebuilt (see also `passes::strength_reduce`).
+            let original_insts: SmallVec<[_; 8]> = func_def_body
+                .at(old_insts)
+                .into_iter()
+                .map(|func_at_inst| func_at_inst.position)
+                .collect();
+            for inst in original_insts {
+                old_insts.remove(inst, &mut self.builder.func_def_body.data_insts);
+                self.lower_inst(inst);
+                self.builder
+                    .insts
+                    .insert_last(inst, &mut self.builder.func_def_body.data_insts);
+            }
+            let new_insts = mem::take(&mut self.builder.insts);
+            match &mut self.builder.func_def_body.control_nodes[control_node].kind {
+                ControlNodeKind::Block { insts } => *insts = new_insts,
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    fn type_of(&self, v: Value) -> Type {
+        self.builder.func_def_body.at(v).type_of(self.builder.cx)
+    }
+
+    /// Get the low and high halves of the 64-bit integer (or float) `v`.
+    fn halves_of(&mut self, v: Value) -> [Value; 2] {
+        let wk = &spv::spec::Spec::get().well_known;
+        let cx = self.builder.cx;
+
+        match v {
+            Value::DataInstOutput(inst) => {
+                if let Some(&halves) = self.halves.get(&inst) {
+                    return halves;
+                }
+            }
+            Value::Const(ct) => {
+                let bits = match const_splat_value(cx, ct) {
+                    Some(ScalarValue::Int { bits, .. }) => Some(bits),
+                    Some(ScalarValue::Float(x)) => Some(x.to_bits()),
+                    _ => None,
+                };
+                if let Some(bits) = bits {
+                    return [
+                        self.builder.const_u32(bits as u32),
+                        self.builder.const_u32((bits >> 32) as u32),
+                    ];
+                }
+            }
+            Value::ControlRegionInput { .. } | Value::ControlNodeOutput { .. } => {}
+        }
+
+        // NOTE(eddyb) `v` will have the `u32x2` type, once all emulated 64-bit
+        // types are replaced (see `Int64TypeReplacer`), while any other (i.e.
+        // native) 64-bit types can be bitcast to `u32x2` instead.
+        let types = self.builder.types;
+        let v = if self.emulated.contains(cx, self.type_of(v)) {
+            v
+        } else {
+            self.builder.op(wk.OpBitcast, [v], types.u32x2)
+        };
+        [0, 1].map(|i| self.builder.extract(v, &[i], types.u32))
+    }
+
+    /// Get the (at most 32-bit) integer `v` as an `u32`, zero-extending it if
+    /// narrower (or taking its low half, if it's a 64-bit integer).
+    fn u32_of(&mut self, v: Value) -> Value {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        match int_width_and_signedness(self.builder.cx, self.type_of(v)) {
+            Some((64, _)) => self.halves_of(v)[0],
+            Some((32, false)) => v,
+            Some((32, true)) => self.builder.op32(wk.OpBitcast, [v]),
+            _ => self.builder.op32(wk.OpUConvert, [v]),
+        }
+    }
+
+    /// Replace `inst` with the emulation of its 64-bit integer (or float)
+    /// operation (if any), with `inst` itself being kept as the last instruction
+    /// (producing the same value), so that none of its uses need to change.
+    fn lower_inst(&mut self, inst: DataInst) {
+        let wk = &spv::spec::Spec::get().well_known;
+        let cx = self.builder.cx;
+
+        let inst_def = &self.builder.func_def_body.data_insts[inst];
+        let opcode = match &inst_def.kind {
+            DataInstKind::SpvInst(spv_inst) if self.emulated.is_emulated_op(spv_inst.opcode) => {
+                spv_inst.opcode
+            }
+            _ => return,
+        };
+        let output_type = inst_def.output_type;
+        let inputs = inst_def.inputs.clone();
+        let output_is_emulated = output_type.is_some_and(|ty| self.emulated.contains(cx, ty));
+        let input_types: SmallVec<[_; 4]> = inputs.iter().map(|&v| self.type_of(v)).collect();
+        if !output_is_emulated && !input_types.iter().any(|&ty| self.emulated.contains(cx, ty)) {
+            return;
+        }
+
+        if self.emulated == Emulated::Float64 {
+            if let Some((kind, new_inputs)) =
+                self.lower_float64_op(inst, opcode, output_type.unwrap(), &inputs)
+            {
+                self.replace_op(inst, kind, new_inputs);
+            }
+            return;
+        }
+        let output_is_int64 = output_is_emulated;
+
+        // The final operation (replacing the one in `inst`), and its inputs.
+        let (kind, new_inputs): (_, SmallVec<[_; 2]>) =
+            if opcode == wk.OpAccessChain || opcode == wk.OpInBoundsAccessChain {
+                // NOTE(eddyb) 64-bit indices are replaced with their low halves.
+                let mut new_inputs = inputs.clone();
+                for (idx, &ty) in new_inputs.iter_mut().zip(&input_types).skip(1) {
+                    if is_int64(cx, ty) {
+                        *idx = self.halves_of(*idx)[0];
+                    }
+                }
+                (DataInstKind::SpvInst(opcode.into()), new_inputs)
+            } else if opcode == wk.OpConvertPtrToU || opcode == wk.OpConvertUToPtr {
+                // NOTE(eddyb) 64-bit pointers can be bitcast to/from `u32x2`.
+                (DataInstKind::SpvInst(wk.OpBitcast.into()), inputs.clone())
+            } else if opcode == wk.OpBitcast && !(output_is_int64 && is_int64(cx, input_types[0])) {
+                // NOTE(eddyb) only bitcasts between 64-bit integers need emulation,
+                // others become bitcasts to/from `u32x2` (which are always valid).
+                return;
+            } else if !output_is_int64 {
+                let output_type = output_type.unwrap();
+                if [
+                    wk.OpShiftLeftLogical,
+                    wk.OpShiftRightLogical,
+                    wk.OpShiftRightArithmetic,
+                ]
+                .contains(&opcode)
+                {
+                    // Shifting a narrower integer by a 64-bit amount.
+                    let amount = self.halves_of(inputs[1])[0];
+                    (
+                        DataInstKind::SpvInst(opcode.into()),
+                        [inputs[0], amount].into_iter().collect(),
+                    )
+                } else if opcode == wk.OpUConvert || opcode == wk.OpSConvert {
+                    let lo = self.halves_of(inputs[0])[0];
+                    match int_width_and_signedness(cx, output_type) {
+                        Some((32, false)) => (
+                            DataInstKind::SpvInst(wk.OpCopyObject.into()),
+                            [lo].into_iter().collect(),
+                        ),
+                        Some((32, true)) => (
+                            DataInstKind::SpvInst(wk.OpBitcast.into()),
+                            [lo].into_iter().collect(),
+                        ),
+                        _ => (
+                            DataInstKind::SpvInst(opcode.into()),
+                            [lo].into_iter().collect(),
+                        ),
+                    }
+                } else if (opcode == wk.OpConvertUToF || opcode == wk.OpConvertSToF)
+                    && float_width(cx, output_type) == Some(32)
+                {
+                    let x = self.halves_of(inputs[0]);
+                    let signed = opcode == wk.OpConvertSToF;
+                    let f = self.builder.f32_from_int64(signed, x, output_type);
+                    (
+                        DataInstKind::SpvInst(wk.OpCopyObject.into()),
+                        [f].into_iter().collect(),
+                    )
+                } else if opcode == wk.OpConvertUToF || opcode == wk.OpConvertSToF {
+                    // `hi * 2^32 + lo` (with only `hi` being signed, for `OpConvertSToF`),
+                    // which only rounds once (in the final addition), as both
+                    // terms are exact 64-bit floats (with 32 significant bits).
+                    let [lo, hi] = self.halves_of(inputs[0]);
+                    let two_pow_32 = Value::Const(
+                        splat_const(cx, output_type, ScalarValue::Float(4294967296.0)).unwrap(),
+                    );
+                    let hi = self.builder.op(opcode, [hi], output_type);
+                    let hi = self.builder.op(wk.OpFMul, [hi, two_pow_32], output_type);
+                    let lo = self.builder.op(wk.OpConvertUToF, [lo], output_type);
+                    (
+                        DataInstKind::SpvInst(wk.OpFAdd.into()),
+                        [hi, lo].into_iter().collect(),
+                    )
+                } else if opcode == wk.OpIEqual || opcode == wk.OpINotEqual {
+                    let [a_lo, a_hi] = self.halves_of(inputs[0]);
+                    let [b_lo, b_hi] = self.halves_of(inputs[1]);
+                    let lo = self.builder.cmp(opcode, [a_lo, b_lo]);
+                    let hi = self.builder.cmp(opcode, [a_hi, b_hi]);
+                    let combine = if opcode == wk.OpIEqual {
+                        wk.OpLogicalAnd
+                    } else {
+                        wk.OpLogicalOr
+                    };
+                    (
+                        DataInstKind::SpvInst(combine.into()),
+                        [lo, hi].into_iter().collect(),
+                    )
+                } else {
+                    // Ordered comparisons (e.g. `OpULessThan`).
+                    let a = self.halves_of(inputs[0]);
+                    let b = self.halves_of(inputs[1]);
+                    let cmp = self.builder.cmp64(opcode, a, b);
+                    (
+                        DataInstKind::SpvInst(wk.OpCopyObject.into()),
+                        [cmp].into_iter().collect(),
+                    )
+                }
+            } else {
+                let [lo, hi] = self.lower_int64_op(opcode, &inputs, &input_types);
+                self.halves.insert(inst, [lo, hi]);
+                (
+                    DataInstKind::SpvInst(wk.OpCompositeConstruct.into()),
+                    [lo, hi].into_iter().collect(),
+                )
+            };
+
+        self.replace_op(inst, kind, new_inputs);
+    }
+
+    /// Replace the operation in `inst` with `kind` (and its inputs with `new_inputs`).
+    fn replace_op(&mut self, inst: DataInst, kind: DataInstKind, new_inputs: SmallVec<[Value; 2]>) {
+        let cx = self.builder.cx;
+
+        // NOTE(eddyb) any decorations (e.g. `NoSignedWrap`) only applied to the
+        // original operation, and may not be valid on the replacement.
+        let inst_def = &mut self.builder.func_def_body.data_insts[inst];
+        let mut attrs = cx[inst_def.attrs].attrs.clone();
+        attrs.retain(|attr| !matches!(attr, Attr::SpvAnnotation(_)));
+        inst_def.attrs = cx.intern(AttrSetDef { attrs });
+        inst_def.kind = kind;
+        inst_def.inputs = new_inputs;
+    }
+
+    /// Emulate the 64-bit float operation `opcode` (of `inst`), returning the
+    /// final operation (replacing the one in `inst`), and its inputs, or `None`
+    /// if `inst` doesn't need to change.
+    fn lower_float64_op(
+        &mut self,
+        inst: DataInst,
+        opcode: Opcode,
+        output_type: Type,
+        inputs: &[Value],
+    ) -> Option<(DataInstKind, SmallVec<[Value; 2]>)> {
+        let wk = &spv::spec::Spec::get().well_known;
+        let cx = self.builder.cx;
+        let types = self.builder.types;
+
+        let is_f64 = |ty| float_width(cx, ty) == Some(64);
+        let input_types: SmallVec<[_; 2]> = inputs.iter().map(|&v| self.type_of(v)).collect();
+
+        if !is_f64(output_type) {
+            let output = if opcode == wk.OpBitcast {
+                // NOTE(eddyb) bitcasts from 64-bit floats become bitcasts
+                // from `u32x2` (which are always valid).
+                return None;
+            } else if opcode == wk.OpIsNan || opcode == wk.OpIsInf {
+                let x = self.halves_of(inputs[0]);
+                if opcode == wk.OpIsNan {
+                    self.builder.f64_is_nan(x)
+                } else {
+                    self.builder.f64_is_inf(x)
+                }
+            } else if opcode == wk.OpFConvert {
+                let x = self.halves_of(inputs[0]);
+                let bits = self.builder.f64_to_f32_bits(x);
+                return Some((
+                    DataInstKind::SpvInst(wk.OpBitcast.into()),
+                    [bits].into_iter().collect(),
+                ));
+            } else if opcode == wk.OpConvertFToU || opcode == wk.OpConvertFToS {
+                let x = self.halves_of(inputs[0]);
+                let [lo, hi] = self.builder.f64_to_int(opcode == wk.OpConvertFToS, x);
+                return Some(match int_width_and_signedness(cx, output_type) {
+                    Some((64, _)) => {
+                        // NOTE(eddyb) 64-bit integers aren't emulated, so
+                        // they're bitcast from their (`u32x2`) halves.
+                        let x = self
+                            .builder
+                            .op(wk.OpCompositeConstruct, [lo, hi], types.u32x2);
+                        (
+                            DataInstKind::SpvInst(wk.OpBitcast.into()),
+                            [x].into_iter().collect(),
+                        )
+                    }
+                    Some((32, false)) => (
+                        DataInstKind::SpvInst(wk.OpCopyObject.into()),
+                        [lo].into_iter().collect(),
+                    ),
+                    Some((32, true)) => (
+                        DataInstKind::SpvInst(wk.OpBitcast.into()),
+                        [lo].into_iter().collect(),
+                    ),
+                    Some((_, signed)) => (
+                        DataInstKind::SpvInst(
+                            if signed { wk.OpSConvert } else { wk.OpUConvert }.into(),
+                        ),
+                        [lo].into_iter().collect(),
+                    ),
+                    None => unreachable!(),
+                });
+            } else {
+                // Comparisons (e.g. `OpFOrdLessThan`).
+                let a = self.halves_of(inputs[0]);
+                let b = self.halves_of(inputs[1]);
+                self.builder.f64_cmp(opcode, a, b)
+            };
+            return Some((
+                DataInstKind::SpvInst(wk.OpCopyObject.into()),
+                [output].into_iter().collect(),
+            ));
+        }
+
+        let call_helper = |this: &mut Self, helper, a: Value, b: Value| {
+            let output = this.builder.inst(
+                DataInstKind::FuncCall(this.helper_funcs[&helper]),
+                [a, b],
+                types.u32x2,
+            );
+            [0, 1].map(|i| this.builder.extract(output, &[i], types.u32))
+        };
+
+        let [lo, hi] = if opcode == wk.OpSelect {
+            let a = self.halves_of(inputs[1]);
+            let b = self.halves_of(inputs[2]);
+            self.builder.select(inputs[0], a, b)
+        } else if opcode == wk.OpBitcast {
+            if !is_f64(input_types[0]) {
+                // NOTE(eddyb) bitcasts to 64-bit floats become bitcasts to
+                // `u32x2` (which are always valid).
+                return None;
+            }
+            self.halves_of(inputs[0])
+        } else if opcode == wk.OpFNegate {
+            let [lo, hi] = self.halves_of(inputs[0]);
+            let sign_bit = self.builder.const_u32(0x8000_0000);
+            [lo, self.builder.op32(wk.OpBitwiseXor, [hi, sign_bit])]
+        } else if opcode == wk.OpFAdd || opcode == wk.OpFMul || opcode == wk.OpFDiv {
+            // NOTE(eddyb) the original inputs will have the `u32x2` type, once
+            // all 64-bit float types are replaced (see `Int64TypeReplacer`).
+            let helper = if opcode == wk.OpFAdd {
+                Helper::FAdd
+            } else if opcode == wk.OpFMul {
+                Helper::FMul
+            } else {
+                Helper::FDiv
+            };
+            call_helper(self, helper, inputs[0], inputs[1])
+        } else if opcode == wk.OpFSub {
+            // `a - b` is `a + (-b)`.
+            let [b_lo, b_hi] = self.halves_of(inputs[1]);
+            let sign_bit = self.builder.const_u32(0x8000_0000);
+            let b_hi = self.builder.op32(wk.OpBitwiseXor, [b_hi, sign_bit]);
+            let neg_b = self
+                .builder
+                .op(wk.OpCompositeConstruct, [b_lo, b_hi], types.u32x2);
+            call_helper(self, Helper::FAdd, inputs[0], neg_b)
+        } else if opcode == wk.OpFConvert {
+            let bits = self.builder.op32(wk.OpBitcast, [inputs[0]]);
+            self.builder.f64_from_f32_bits(bits)
+        } else {
+            assert!(opcode == wk.OpConvertUToF || opcode == wk.OpConvertSToF);
+
+            // NOTE(eddyb) integers are first extended to 64 bits (with the
+            // same code used for emulated 64-bit integers, which also works
+            // for native 64-bit integers, as their halves are bitcast).
+            let signed = opcode == wk.OpConvertSToF;
+            let extend = if signed { wk.OpSConvert } else { wk.OpUConvert };
+            let x = self.lower_int64_op(extend, &inputs[..1], &input_types[..1]);
+            self.builder.f64_from_int(signed, x)
+        };
+        self.halves.insert(inst, [lo, hi]);
+        Some((
+            DataInstKind::SpvInst(wk.OpCompositeConstruct.into()),
+            [lo, hi].into_iter().collect(),
+        ))
+    }
+
+    /// Emulate the 64-bit integer operation `opcode` (with a 64-bit integer
+    /// output), returning the low and high halves of its output.
+    fn lower_int64_op(
+        &mut self,
+        opcode: Opcode,
+        inputs: &[Value],
+        input_types: &[Type],
+    ) -> [Value; 2] {
+        let wk = &spv::spec::Spec::get().well_known;
+        let cx = self.builder.cx;
+
+        let (zero, c31) = (self.builder.const_u32(0), self.builder.const_u32(31));
+
+        if opcode == wk.OpUConvert || opcode == wk.OpSConvert {
+            let (width, _) = int_width_and_signedness(cx, input_types[0]).unwrap();
+            if width == 64 {
+                return self.halves_of(inputs[0]);
+            }
+            let mut lo = self.u32_of(inputs[0]);
+            if opcode == wk.OpUConvert {
+                return [lo, zero];
+            }
+            if width < 32 {
+                // Sign-extend from `width` bits to 32 bits.
+                let shift = self.builder.const_u32(32 - width);
+                lo = self.builder.op32(wk.OpShiftLeftLogical, [lo, shift]);
+                lo = self.builder.op32(wk.OpShiftRightArithmetic, [lo, shift]);
+            }
+            let hi = self.builder.op32(wk.OpShiftRightArithmetic, [lo, c31]);
+            return [lo, hi];
+        }
+
+        if opcode == wk.OpConvertFToU || opcode == wk.OpConvertFToS {
+            // `hi = trunc(x / 2^32)` and `lo = x - hi * 2^32` (which are exact,
+            // as `hi` can't have more significant bits than `x` itself).
+            let x = inputs[0];
+            let float_type = input_types[0];
+            let float_const =
+                |x| Value::Const(splat_const(cx, float_type, ScalarValue::Float(x)).unwrap());
+            let abs_x = if opcode == wk.OpConvertFToS {
+                self.builder.inst(
+                    DataInstKind::SpvGlslStd450(spv::glsl_std_450::Op::FAbs),
+                    [x],
+                    float_type,
+                )
+            } else {
+                x
+            };
+            let hi_f = self.builder.op(
+                wk.OpFMul,
+                [abs_x, float_const(1.0 / 4294967296.0)],
+                float_type,
+            );
+            let hi = self.builder.op32(wk.OpConvertFToU, [hi_f]);
+            let hi_f = self.builder.op(wk.OpConvertUToF, [hi], float_type);
+            let hi_f = self
+                .builder
+                .op(wk.OpFMul, [hi_f, float_const(4294967296.0)], float_type);
+            let lo_f = self.builder.op(wk.OpFSub, [abs_x, hi_f], float_type);
+            let lo = self.builder.op32(wk.OpConvertFToU, [lo_f]);
+            if opcode == wk.OpConvertFToU {
+                return [lo, hi];
+            }
+            let is_neg = self.builder.cmp(wk.OpFOrdLessThan, [x, float_const(0.0)]);
+            let neg = self.builder.sub64([zero, zero], [lo, hi]);
+            return self.builder.select(is_neg, neg, [lo, hi]);
+        }
+
+        if opcode == wk.OpSelect {
+            let a = self.halves_of(inputs[1]);
+            let b = self.halves_of(inputs[2]);
+            return self.builder.select(inputs[0], a, b);
+        }
+
+        if [
+            wk.OpShiftLeftLogical,
+            wk.OpShiftRightLogical,
+            wk.OpShiftRightArithmetic,
+        ]
+        .contains(&opcode)
+        {
+            let a = self.halves_of(inputs[0]);
+            let n = self.u32_of(inputs[1]);
+            return self.builder.shift64(opcode, a, n);
+        }
+
+        let a = self.halves_of(inputs[0]);
+        let b = inputs.get(1).map(|&b| self.halves_of(b));
+
+        if opcode == wk.OpBitcast {
+            a
+        } else if opcode == wk.OpSNegate {
+            self.builder.sub64([zero, zero], a)
+        } else if opcode == wk.OpNot {
+            a.map(|x| self.builder.op32(opcode, [x]))
+        } else if [wk.OpBitwiseAnd, wk.OpBitwiseOr, wk.OpBitwiseXor].contains(&opcode) {
+            let b = b.unwrap();
+            [0, 1].map(|i| self.builder.op32(opcode, [a[i], b[i]]))
+        } else if opcode == wk.OpIAdd {
+            self.builder.add64(a, b.unwrap())
+        } else if opcode == wk.OpISub {
+            self.builder.sub64(a, b.unwrap())
+        } else if opcode == wk.OpIMul {
+            // `(a_hi * 2^32 + a_lo) * (b_hi * 2^32 + b_lo) mod 2^64` is
+            // `a_lo * b_lo + ((a_hi * b_lo + a_lo * b_hi) mod 2^32) * 2^32`.
+            let ([a_lo, a_hi], [b_lo, b_hi]) = (a, b.unwrap());
+            let [lo, hi] = self.builder.mul32_wide(a_lo, b_lo);
+            let cross1 = self.builder.op32(wk.OpIMul, [a_hi, b_lo]);
+            let cross2 = self.builder.op32(wk.OpIMul, [a_lo, b_hi]);
+            let hi = self.builder.op32(wk.OpIAdd, [hi, cross1]);
+            let hi = self.builder.op32(wk.OpIAdd, [hi, cross2]);
+            [lo, hi]
+        } else {
+            self.lower_div_or_rem(opcode, inputs, a, b.unwrap())
+        }
+    }
+
+    /// Emulate 64-bit integer division/remainder (`opcode`) by calling the
+    /// helper function (see [`divmod_func_decl`]), which is unsigned-only, so
+    /// signed operations are performed on absolute values, and then adjusted.
+    fn lower_div_or_rem(
+        &mut self,
+        opcode: Opcode,
+        inputs: &[Value],
+        a: [Value; 2],
+        b: [Value; 2],
+    ) -> [Value; 2] {
+        let wk = &spv::spec::Spec::get().well_known;
+        let types = self.builder.types;
+
+        let zero = self.builder.const_u32(0);
+        let c31 = self.builder.const_u32(31);
+
+        let divmod_func = self.helper_funcs[&Helper::DivMod];
+        let call = |this: &mut Self, a: Value, b: Value| {
+            this.builder.inst(
+                DataInstKind::FuncCall(divmod_func),
+                [a, b],
+                types.u32x2_pair,
+            )
+        };
+        let q_and_r_halves = |this: &mut Self, q_and_r: Value| {
+            [[0, 0], [0, 1], [1, 0], [1, 1]]
+                .map(|indices| this.builder.extract(q_and_r, &indices, types.u32))
+        };
+
+        if opcode == wk.OpUDiv || opcode == wk.OpUMod {
+            // NOTE(eddyb) the original inputs will have the `u32x2` type, once
+            // all 64-bit integer types are replaced (see `Int64TypeReplacer`).
+            let q_and_r = call(self, inputs[0], inputs[1]);
+            let [q_lo, q_hi, r_lo, r_hi] = q_and_r_halves(self, q_and_r);
+            return if opcode == wk.OpUDiv {
+                [q_lo, q_hi]
+            } else {
+                [r_lo, r_hi]
+            };
+        }
+
+        // `abs(x)` is `(x ^ sign) - sign`, with `sign` being `0` or `-1`,
+        // which also negates `abs(x)` back to `x`.
+        let sign_of = |this: &mut Self, [_, hi]: [Value; 2]| {
+            this.builder.op32(wk.OpShiftRightArithmetic, [hi, c31])
+        };
+        let apply_sign = |this: &mut Self, x: [Value; 2], sign: Value| {
+            let flipped = x.map(|x| this.builder.op32(wk.OpBitwiseXor, [x, sign]));
+            this.builder.sub64(flipped, [sign, sign])
+        };
+        let a_sign = sign_of(self, a);
+        let b_sign = sign_of(self, b);
+        let [abs_a, abs_b] = [(a, a_sign), (b, b_sign)].map(|(x, sign)| {
+            let [lo, hi] = apply_sign(self, x, sign);
+            self.builder
+                .op(wk.OpCompositeConstruct, [lo, hi], types.u32x2)
+        });
+        let q_and_r = call(self, abs_a, abs_b);
+        let [q_lo, q_hi, r_lo, r_hi] = q_and_r_halves(self, q_and_r);
+
+        if opcode == wk.OpSDiv {
+            let q_sign = self.builder.op32(wk.OpBitwiseXor, [a_sign, b_sign]);
+            return apply_sign(self, [q_lo, q_hi], q_sign);
+        }
+
+        // The result of `OpSRem` has the sign of `a`.
+        let rem = apply_sign(self, [r_lo, r_hi], a_sign);
+        if opcode == wk.OpSRem {
+            return rem;
+        }
+
+        // The result of `OpSMod` has the sign of `b` instead, which requires
+        // adding `b` to (non-zero) remainders with a different sign.
+        assert!(opcode == wk.OpSMod);
+        let signs_differ = self.builder.cmp(wk.OpINotEqual, [a_sign, b_sign]);
+        let r_lo_nonzero = self.builder.cmp(wk.OpINotEqual, [r_lo, zero]);
+        let r_hi_nonzero = self.builder.cmp(wk.OpINotEqual, [r_hi, zero]);
+        let r_nonzero = self
+            .builder
+            .cmp(wk.OpLogicalOr, [r_lo_nonzero, r_hi_nonzero]);
+        let adjust = self.builder.cmp(wk.OpLogicalAnd, [signs_differ, r_nonzero]);
+        let adjusted = self.builder.add64(rem, b);
+        self.builder.select(adjust, adjusted, rem)
+    }
+}
+
+/// Transformer replacing the emulated 64-bit type with `u32x2`, and all of its
+/// constants with the equivalent `u32x2` constants.
+struct Int64TypeReplacer<'a> {
+    cx: &'a Context,
+    emulated: Emulated,
+    types: &'a Int64EmulationTypes,
+
+    // FIXME(eddyb) build some automation to avoid ever repeating these.
+    transformed_types: FxHashMap<Type, Transformed<Type>>,
+    transformed_consts: FxHashMap<Const, Transformed<Const>>,
+}
+
+impl Transformer for Int64TypeReplacer<'_> {
+    fn transform_type_use(&mut self, ty: Type) -> Transformed<Type> {
+        if let Some(&cached) = self.transformed_types.get(&ty) {
+            return cached;
+        }
+        let transformed = if self.emulated.contains(self.cx, ty) {
+            Transformed::Changed(self.types.u32x2)
+        } else {
+            self.transform_type_def(&self.cx[ty])
+                .map(|ty_def| self.cx.intern(ty_def))
+        };
+        self.transformed_types.insert(ty, transformed);
+        transformed
+    }
+    fn transform_const_use(&mut self, ct: Const) -> Transformed<Const> {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        if let Some(&cached) = self.transformed_consts.get(&ct) {
+            return cached;
+        }
+        let cx = self.cx;
+        let ct_def = &cx[ct];
+        let is_emulated_const = self.emulated.contains(cx, ct_def.ty)
+            && matches!(&ct_def.ctor, ConstCtor::SpvInst(inst) if inst.opcode == wk.OpConstant);
+        let bits = match const_splat_value(cx, ct) {
+            Some(ScalarValue::Int { bits, .. }) => Some(bits),
+            Some(ScalarValue::Float(x)) => Some(x.to_bits()),
+            _ => None,
+        };
+        let transformed = match bits {
+            Some(bits) if is_emulated_const => {
+                let halves = [bits as u32, (bits >> 32) as u32].map(|half| {
+                    splat_const(
+                        cx,
+                        self.types.u32,
+                        ScalarValue::Int {
+                            width: 32,
+                            bits: half.into(),
+                        },
+                    )
+                    .unwrap()
+                });
+                Transformed::Changed(composite_const(
+                    cx,
+                    self.types.u32x2,
+                    halves.into_iter().collect(),
+                ))
+            }
+            _ => self
+                .transform_const_def(ct_def)
+                .map(|ct_def| cx.intern(ct_def)),
+        };
+        self.transformed_consts.insert(ct, transformed);
+        transformed
+    }
+}
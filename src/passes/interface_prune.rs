@@ -0,0 +1,103 @@
+//! Entry-point interface pruning (i.e. of unused `Input`/`Output` variables,
+//! or of any variables no longer used by the entry-point).
+
+use crate::passes::legalize::reachable_global_vars_and_funcs_from_exportee;
+use crate::passes::prune::prune_unreachable;
+use crate::{spv, AddrSpace, ExportKey, GlobalVarDecl, Module};
+
+/// Remove all the `Input`/`Output` variables unused by the entry-point(s) named
+/// `entry_point_name`, from their interface (i.e. the `interface_global_vars`
+/// of the [`ExportKey::SpvEntryPoint`]), returning the number removed.
+///
+/// Interface variables which are used (or aren't `Input`/`Output` variables,
+/// e.g. the ones SPIR-V 1.4 and later also requires to be listed) are kept.
+///
+/// If any variables are removed, the module is also pruned (of them, and any
+/// other definitions they were the only users of, e.g. their initializers),
+/// by [`prune_unreachable`] (see its documentation for the consequences).
+///
+/// This is the equivalent of `spirv-opt --eliminate-dead-input-output`, and is
+/// typically used when the neighboring stage(s) don't read or write them.
+pub fn prune_entry_point_interface(module: &mut Module, entry_point_name: &str) -> usize {
+    let wk = &spv::spec::Spec::get().well_known;
+
+    let is_io_storage_class = |addr_space| {
+        [
+            AddrSpace::SpvStorageClass(wk.Input),
+            AddrSpace::SpvStorageClass(wk.Output),
+        ]
+        .contains(&addr_space)
+    };
+
+    prune_interfaces(
+        module,
+        |imms| spv::extract_literal_string(&imms[1..]).is_ok_and(|name| name == entry_point_name),
+        |gv_decl| is_io_storage_class(gv_decl.addr_space),
+    )
+}
+
+/// Remove all the variables unused by each entry-point, from its interface
+/// (i.e. the `interface_global_vars` of every [`ExportKey::SpvEntryPoint`]),
+/// returning the number removed (across all entry-points).
+///
+/// Unlike [`prune_entry_point_interface`], this applies to all variables (not
+/// just `Input`/`Output` ones), keeping the interfaces consistent with the
+/// entry-point bodies, after e.g. linking and/or dead code elimination (which
+/// can leave interface variables no longer used by the entry-point, as the
+/// interfaces aren't recomputed, and are lifted as-is, by `spv::lift`).
+///
+/// If any variables are removed, the module is also pruned, like it is by
+/// [`prune_entry_point_interface`] (see its documentation for details).
+pub fn prune_dead_interface_global_vars(module: &mut Module) -> usize {
+    prune_interfaces(module, |_| true, |_| true)
+}
+
+/// Remove the variables for which `is_removable` returns `true`, and which are
+/// unused by the entry-point, from the interface of each entry-point for which
+/// `is_selected(imms)` returns `true`, returning the number removed.
+fn prune_interfaces(
+    module: &mut Module,
+    is_selected: impl Fn(&[spv::Imm]) -> bool,
+    is_removable: impl Fn(&GlobalVarDecl) -> bool,
+) -> usize {
+    let mut removed_count = 0;
+    let exports = std::mem::take(&mut module.exports);
+    module.exports = exports
+        .into_iter()
+        .map(|(export_key, exportee)| {
+            let export_key = match export_key {
+                ExportKey::SpvEntryPoint {
+                    imms,
+                    interface_global_vars,
+                } if is_selected(&imms) => {
+                    let (used_global_vars, _) =
+                        reachable_global_vars_and_funcs_from_exportee(module, exportee);
+                    let interface_global_vars = interface_global_vars
+                        .iter()
+                        .copied()
+                        .filter(|gv| {
+                            let keep = used_global_vars.contains(gv)
+                                || !is_removable(&module.global_vars[*gv]);
+                            if !keep {
+                                removed_count += 1;
+                            }
+                            keep
+                        })
+                        .collect();
+                    ExportKey::SpvEntryPoint {
+                        imms,
+                        interface_global_vars,
+                    }
+                }
+                _ => export_key,
+            };
+            (export_key, exportee)
+        })
+        .collect();
+
+    if removed_count > 0 {
+        prune_unreachable(module);
+    }
+
+    removed_count
+}
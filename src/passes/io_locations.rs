@@ -0,0 +1,463 @@
+//! Shader stage interface `Location` assignment (for `Input`/`Output` variables).
+
+use crate::spv::fold::{const_splat_value, vector_type, ScalarValue};
+use crate::{
+    spv, AddrSpace, Attr, AttrSet, AttrSetDef, Context, ExportKey, GlobalVar, Module, Type,
+    TypeCtor, TypeCtorArg,
+};
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+/// Direction of shader stage interface variables (i.e. their storage class).
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum IoDirection {
+    Input,
+    Output,
+}
+
+impl IoDirection {
+    pub(crate) fn storage_class(self) -> u32 {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        match self {
+            Self::Input => wk.Input,
+            Self::Output => wk.Output,
+        }
+    }
+
+    /// Some stages (`execution_model`) access their interface through an extra
+    /// outer array (one element per vertex), which doesn't count towards locations.
+    pub(crate) fn is_arrayed_for(self, execution_model: u32) -> bool {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        match self {
+            Self::Input => [
+                wk.TessellationControl,
+                wk.TessellationEvaluation,
+                wk.Geometry,
+            ]
+            .contains(&execution_model),
+            Self::Output => {
+                [wk.TessellationControl, wk.MeshNV, wk.MeshEXT].contains(&execution_model)
+            }
+        }
+    }
+}
+
+/// Interface signature of one side (inputs or outputs) of a shader stage, i.e.
+/// the `Location` of each interface variable, keyed by its name (`OpName`).
+#[derive(Clone, Default)]
+pub struct IoSignature {
+    pub locations_by_name: BTreeMap<String, u32>,
+}
+
+/// Options for `Location` assignment (see [`assign_io_locations`]).
+#[derive(Clone)]
+pub struct IoLocationOptions {
+    /// Whether to assign a `Location` to every interface variable lacking one
+    /// (or which had to be moved to make room for matching a signature).
+    pub assign_missing: bool,
+
+    /// Signature to renumber input variables to match (typically the outputs
+    /// of the previous stage, see [`collect_io_signature`]).
+    pub match_inputs: Option<IoSignature>,
+
+    /// Signature to renumber output variables to match (typically the inputs
+    /// of the next stage, see [`collect_io_signature`]).
+    pub match_outputs: Option<IoSignature>,
+}
+
+impl Default for IoLocationOptions {
+    fn default() -> Self {
+        Self {
+            assign_missing: true,
+            match_inputs: None,
+            match_outputs: None,
+        }
+    }
+}
+
+/// Collect the [`IoSignature`] of all named interface variables in `direction`,
+/// which have a `Location` decoration, across all entry-points in `module`.
+pub fn collect_io_signature(module: &Module, direction: IoDirection) -> IoSignature {
+    let cx = &module.cx();
+
+    let mut signature = IoSignature::default();
+    for export_key in module.exports.keys() {
+        if let ExportKey::SpvEntryPoint {
+            interface_global_vars,
+            ..
+        } = export_key
+        {
+            for &gv in interface_global_vars {
+                let gv_decl = &module.global_vars[gv];
+                if gv_decl.addr_space != AddrSpace::SpvStorageClass(direction.storage_class()) {
+                    continue;
+                }
+                let attrs = &cx[gv_decl.attrs];
+                if let (Some(name), Some(location)) = (
+                    debug_name(attrs),
+                    decoration_value(attrs, Decoration::Location),
+                ) {
+                    signature.locations_by_name.insert(name, location);
+                }
+            }
+        }
+    }
+    signature
+}
+
+/// Assign `Location` decorations to the `Input`/`Output` interface variables of
+/// every entry-point in `module` (which lack them, or need renumbering to match
+/// an [`IoSignature`], see [`IoLocationOptions`]).
+///
+/// Assignment is stable (i.e. it follows the order of the entry-point interface,
+/// and picks the lowest free `Location`s), and takes into account the number of
+/// `Location`s taken up by each variable's type (e.g. arrays, matrices, structs,
+/// and 64-bit 3/4-component vectors, all take up more than one).
+///
+/// Built-in variables (and `Block`s of built-ins), and structs with their own
+/// per-member `Location` decorations, are left untouched.
+pub fn assign_io_locations(module: &mut Module, options: &IoLocationOptions) {
+    let cx = &module.cx();
+
+    // NOTE(eddyb) collected ahead of time to avoid borrowing `module.exports`.
+    let entry_points: Vec<_> = module
+        .exports
+        .keys()
+        .filter_map(|export_key| match export_key {
+            ExportKey::SpvEntryPoint {
+                imms,
+                interface_global_vars,
+            } => {
+                let execution_model = match imms.first() {
+                    Some(&spv::Imm::Short(_, execution_model)) => execution_model,
+                    _ => return None,
+                };
+                Some((execution_model, interface_global_vars.clone()))
+            }
+            ExportKey::LinkName(_) => None,
+        })
+        .collect();
+
+    for (execution_model, interface_global_vars) in entry_points {
+        for (direction, signature) in [
+            (IoDirection::Input, &options.match_inputs),
+            (IoDirection::Output, &options.match_outputs),
+        ] {
+            let arrayed = direction.is_arrayed_for(execution_model);
+
+            let vars = interface_global_vars
+                .iter()
+                .filter_map(|&gv| {
+                    let gv_decl = &module.global_vars[gv];
+                    if gv_decl.addr_space != AddrSpace::SpvStorageClass(direction.storage_class()) {
+                        return None;
+                    }
+                    IoVar::new(cx, gv, gv_decl.attrs, gv_decl.type_of_ptr_to, arrayed)
+                })
+                .collect();
+
+            let assigned = LocationAssigner {
+                vars,
+                occupied: vec![],
+            }
+            .assign(signature.as_ref(), options.assign_missing);
+
+            for (gv, location) in assigned {
+                let attrs = &mut module.global_vars[gv].attrs;
+                *attrs = with_location(cx, *attrs, location);
+            }
+        }
+    }
+}
+
+/// Interface variable which may have its `Location` (re)assigned.
+pub(crate) struct IoVar {
+    pub(crate) global_var: GlobalVar,
+    name: Option<String>,
+    pub(crate) location: Option<u32>,
+
+    /// Number of `Location`s taken up by the variable (or `None` if unknown).
+    pub(crate) location_count: Option<u32>,
+}
+
+impl IoVar {
+    /// Returns `None` for variables which shouldn't (or can't) have `Location`s,
+    /// or which have them on their struct members instead.
+    pub(crate) fn new(
+        cx: &Context,
+        global_var: GlobalVar,
+        attrs: AttrSet,
+        type_of_ptr_to: Type,
+        arrayed: bool,
+    ) -> Option<Self> {
+        let attrs_def = &cx[attrs];
+        if decoration_value(attrs_def, Decoration::BuiltIn).is_some() {
+            return None;
+        }
+
+        let mut ty = type_of_ptr_to.pointee_type(cx)?;
+        if arrayed && decoration_value(attrs_def, Decoration::Patch).is_none() {
+            if let TypeCtor::Array | TypeCtor::RuntimeArray = cx[ty].ctor {
+                ty = match cx[ty].ctor_args[0] {
+                    TypeCtorArg::Type(elem_type) => elem_type,
+                    TypeCtorArg::Const(_) => return None,
+                };
+            }
+        }
+
+        if let TypeCtor::Struct { members } = &cx[ty].ctor {
+            let has_member_decoration = |decoration| {
+                members
+                    .iter()
+                    .any(|member| decoration_value(&cx[member.attrs], decoration).is_some())
+            };
+            if has_member_decoration(Decoration::BuiltIn)
+                || has_member_decoration(Decoration::Location)
+            {
+                return None;
+            }
+        }
+
+        Some(Self {
+            global_var,
+            name: debug_name(attrs_def),
+            location: decoration_value(attrs_def, Decoration::Location),
+            location_count: location_count(cx, ty),
+        })
+    }
+}
+
+struct LocationAssigner {
+    vars: Vec<IoVar>,
+
+    /// Ranges of `Location`s already taken up by some variable.
+    occupied: Vec<Range<u32>>,
+}
+
+impl LocationAssigner {
+    /// Compute the new `Location`s of all variables which need to change.
+    fn assign(
+        mut self,
+        signature: Option<&IoSignature>,
+        assign_missing: bool,
+    ) -> Vec<(GlobalVar, u32)> {
+        let mut assigned = vec![];
+
+        // Variables in the signature have their `Location`s dictated by it,
+        // and take priority over any other (pre-existing) ones.
+        let mut matched = vec![false; self.vars.len()];
+        if let Some(signature) = signature {
+            for (var, matched) in self.vars.iter_mut().zip(&mut matched) {
+                let location = var
+                    .name
+                    .as_ref()
+                    .and_then(|name| signature.locations_by_name.get(name));
+                if let Some(&location) = location {
+                    if var.location != Some(location) {
+                        var.location = Some(location);
+                        assigned.push((var.global_var, location));
+                    }
+                    *matched = true;
+                    self.occupied
+                        .push(location..location.saturating_add(var.location_count.unwrap_or(1)));
+                }
+            }
+        }
+
+        // Pre-existing `Location`s are kept unless they overlap a matched one.
+        for (i, &matched) in matched.iter().enumerate() {
+            let var = &self.vars[i];
+            if matched {
+                continue;
+            }
+            if let Some(location) = var.location {
+                let range = location..location.saturating_add(var.location_count.unwrap_or(1));
+                if signature.is_some() && self.overlaps_occupied(&range) {
+                    self.vars[i].location = None;
+                } else {
+                    self.occupied.push(range);
+                }
+            }
+        }
+
+        if assign_missing {
+            for var in &self.vars {
+                if var.location.is_some() {
+                    continue;
+                }
+                let count = match var.location_count {
+                    Some(count) => count,
+                    None => continue,
+                };
+                let location = self.first_free(count);
+                self.occupied.push(location..location.saturating_add(count));
+                assigned.push((var.global_var, location));
+            }
+        }
+
+        assigned
+    }
+
+    fn overlaps_occupied(&self, range: &Range<u32>) -> bool {
+        // FIXME(eddyb) take `Component` decorations into account, which allow
+        // multiple variables to share the same `Location`.
+        self.occupied
+            .iter()
+            .any(|occupied| occupied.start < range.end && range.start < occupied.end)
+    }
+
+    /// Find the lowest `Location` starting `count` free consecutive ones.
+    fn first_free(&self, count: u32) -> u32 {
+        let mut location = 0u32;
+        // NOTE(eddyb) every iteration moves `location` past some occupied range,
+        // so this can't loop more times than there are occupied ranges.
+        while let Some(occupied) = self.occupied.iter().find(|occupied| {
+            occupied.start < location.saturating_add(count) && location < occupied.end
+        }) {
+            location = occupied.end;
+        }
+        location
+    }
+}
+
+/// Number of `Location`s taken up by an interface variable of type `ty`, per the
+/// "Location Assignment" rules of the Vulkan specification.
+fn location_count(cx: &Context, ty: Type) -> Option<u32> {
+    let wk = &spv::spec::Spec::get().well_known;
+
+    if let Some((elem_type, count)) = vector_type(cx, ty) {
+        return Some(if scalar_width(cx, elem_type)? == 64 && count > 2 {
+            2
+        } else {
+            1
+        });
+    }
+
+    let ty_def = &cx[ty];
+    match &ty_def.ctor {
+        TypeCtor::SpvInst(inst) if inst.opcode == wk.OpTypeBool => Some(1),
+        TypeCtor::SpvInst(_) => scalar_width(cx, ty).map(|_| 1),
+        &TypeCtor::Matrix { column_count } => match ty_def.ctor_args[..] {
+            [TypeCtorArg::Type(column_type)] => {
+                column_count.checked_mul(location_count(cx, column_type)?)
+            }
+            _ => None,
+        },
+        TypeCtor::Array => match ty_def.ctor_args[..] {
+            [TypeCtorArg::Type(elem_type), TypeCtorArg::Const(len)] => {
+                match const_splat_value(cx, len)? {
+                    ScalarValue::Int { bits, .. } => u32::try_from(bits)
+                        .ok()?
+                        .checked_mul(location_count(cx, elem_type)?),
+                    _ => None,
+                }
+            }
+            _ => None,
+        },
+        TypeCtor::Struct { .. } => {
+            ty_def
+                .ctor_args
+                .iter()
+                .try_fold(0u32, |total, &arg| match arg {
+                    TypeCtorArg::Type(member_type) => {
+                        total.checked_add(location_count(cx, member_type)?)
+                    }
+                    TypeCtorArg::Const(_) => None,
+                })
+        }
+        _ => None,
+    }
+}
+
+/// Get the bit width of `ty`, if it's a SPIR-V integer or floating-point type.
+fn scalar_width(cx: &Context, ty: Type) -> Option<u32> {
+    let wk = &spv::spec::Spec::get().well_known;
+
+    match &cx[ty].ctor {
+        TypeCtor::SpvInst(inst) if [wk.OpTypeInt, wk.OpTypeFloat].contains(&inst.opcode) => {
+            match inst.imms.first() {
+                Some(&spv::Imm::Short(_, width)) => Some(width),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+#[derive(Copy, Clone)]
+enum Decoration {
+    BuiltIn,
+    Location,
+    Patch,
+}
+
+impl Decoration {
+    fn to_spv(self) -> u32 {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        match self {
+            Self::BuiltIn => wk.BuiltIn,
+            Self::Location => wk.Location,
+            Self::Patch => wk.Patch,
+        }
+    }
+}
+
+/// Get the (first) operand of the `decoration` in `attrs` (or `0` if it has no
+/// operands, e.g. `Patch`), if present.
+fn decoration_value(attrs: &AttrSetDef, decoration: Decoration) -> Option<u32> {
+    let wk = &spv::spec::Spec::get().well_known;
+
+    attrs.attrs.iter().find_map(|attr| match attr {
+        Attr::SpvAnnotation(spv::Inst { opcode, imms }) if *opcode == wk.OpDecorate => {
+            match imms[..] {
+                [spv::Imm::Short(_, d), ref operands @ ..] if d == decoration.to_spv() => {
+                    match operands.first() {
+                        Some(&spv::Imm::Short(_, value)) => Some(value),
+                        _ => Some(0),
+                    }
+                }
+                _ => None,
+            }
+        }
+        _ => None,
+    })
+}
+
+/// Get the name of a definition from its `OpName` (kept in `attrs`), if any.
+fn debug_name(attrs: &AttrSetDef) -> Option<String> {
+    let wk = &spv::spec::Spec::get().well_known;
+
+    attrs.attrs.iter().find_map(|attr| match attr {
+        Attr::SpvAnnotation(inst) if inst.opcode == wk.OpName => {
+            spv::extract_literal_string(&inst.imms).ok()
+        }
+        _ => None,
+    })
+}
+
+/// Replace any `Location` decoration in `attrs` with one for `location`.
+fn with_location(cx: &Context, attrs: AttrSet, location: u32) -> AttrSet {
+    let wk = &spv::spec::Spec::get().well_known;
+
+    let mut attrs = cx[attrs].attrs.clone();
+    attrs.retain(|attr| match attr {
+        Attr::SpvAnnotation(spv::Inst { opcode, imms }) if *opcode == wk.OpDecorate => !matches!(
+            imms.first(),
+            Some(&spv::Imm::Short(_, decoration)) if decoration == wk.Location
+        ),
+        _ => true,
+    });
+    attrs.insert(Attr::SpvAnnotation(spv::Inst {
+        opcode: wk.OpDecorate,
+        imms: [
+            spv::Imm::Short(wk.Decoration, wk.Location),
+            spv::Imm::Short(wk.LiteralInteger, location),
+        ]
+        .into_iter()
+        .collect(),
+    }));
+    cx.intern(AttrSetDef { attrs })
+}
@@ -1,31 +1,477 @@
+use crate::transform::{InnerInPlaceTransform, ReplaceValueWith};
 use crate::visit::{InnerVisit, Visitor};
-use crate::{cfg, AttrSet, Const, Context, DeclDef, Func, FxIndexSet, GlobalVar, Module, Type};
+use crate::{
+    cfg, spv, AddrSpace, AttrSet, Const, ConstCtor, Context, ControlNode, ControlNodeKind,
+    ControlRegion, DataInst, DataInstKind, DeclDef, ExportKey, Exportee, Func, FuncDefBody,
+    FxIndexMap, FxIndexSet, GlobalVar, Module, Type, Value,
+};
+use rustc_hash::FxHashMap;
+use smallvec::SmallVec;
 
 /// Apply the [`cfg::Structurizer`] algorithm to all function definitions in `module`.
 pub fn structurize_func_cfgs(module: &mut Module) {
     let cx = &module.cx();
 
-    // FIXME(eddyb) reuse this collection work in some kind of "pass manager".
-    let mut collector = ReachableUseCollector {
-        cx,
-        module,
-
-        seen_types: FxIndexSet::default(),
-        seen_consts: FxIndexSet::default(),
-        seen_global_vars: FxIndexSet::default(),
-        seen_funcs: FxIndexSet::default(),
-    };
-    for &exportee in module.exports.values() {
-        exportee.inner_visit_with(&mut collector);
+    for func in reachable_funcs(module) {
+        let func_decl = &mut module.funcs[func];
+        if let DeclDef::Present(func_def_body) = &mut func_decl.def {
+            cfg::Structurizer::new(cx, func_def_body)
+                .with_func_ret_type(func_decl.ret_type)
+                .structurize_func();
+        }
     }
+}
+
+/// Pointer-typed value which can't (trivially) be resolved to a single pointer,
+/// i.e. one that SPIR-V has to express with `OpPhi` or `OpSelect` (requiring
+/// the `VariablePointers` or `VariablePointersStorageBuffer` capabilities).
+///
+/// Physical pointers (see [`AddrSpace::has_physical_ptrs`]) are never included,
+/// as they're plain addresses, which can always be chosen between dynamically.
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+pub enum VariablePtr {
+    /// Pointer-typed output of a `Select` [`ControlNode`].
+    SelectOutput {
+        select_node: ControlNode,
+        output_idx: u32,
+    },
+
+    /// Pointer-typed input of a `Loop` [`ControlNode`]'s `body` (loop state).
+    LoopBodyInput {
+        loop_node: ControlNode,
+        input_idx: u32,
+    },
+
+    /// Pointer-typed input of a [`ControlRegion`] in the unstructured CFG
+    /// (i.e. a SPIR-V `OpPhi` which structurization couldn't get rid of).
+    UnstructuredRegionInput {
+        region: ControlRegion,
+        input_idx: u32,
+    },
+
+    /// SPIR-V `OpSelect` producing a pointer (found in the `block_node` block).
+    SpvSelect {
+        block_node: ControlNode,
+        inst: DataInst,
+    },
+}
+
+/// Find all the [`VariablePtr`]s in `func_def_body`.
+pub fn find_variable_ptrs(cx: &Context, func_def_body: &FuncDefBody) -> Vec<VariablePtr> {
+    VariablePtrFinder::find_in_func(cx, func_def_body).variable_ptrs
+}
 
-    for &func in &collector.seen_funcs {
+/// Remove [`VariablePtr`]s from all function definitions in `module`, wherever
+/// they can be replaced with a single pointer, i.e.:
+/// * `Select` outputs which are the same pointer in all cases
+/// * `Loop` body inputs which never change from their initial pointer
+/// * SPIR-V `OpSelect`s between the same pointer, or with a constant condition
+///
+/// Additionally, conversions of physical pointers to integers and back (i.e.
+/// SPIR-V `OpConvertPtrToU`/`OpBitcast` followed by `OpConvertUToPtr`/`OpBitcast`),
+/// are replaced with the original pointer (if its type matches), which may
+/// allow further simplifications (e.g. if the same pointer ends up in all cases).
+///
+/// Any remaining [`VariablePtr`]s can be found with [`find_variable_ptrs`].
+pub fn simplify_variable_ptrs(module: &mut Module) {
+    let cx = &module.cx();
+
+    for func in reachable_funcs(module) {
         if let DeclDef::Present(func_def_body) = &mut module.funcs[func].def {
-            cfg::Structurizer::new(cx, func_def_body).structurize_func();
+            while simplify_variable_ptrs_in_func(cx, func_def_body) {}
         }
     }
 }
 
+/// Replacement for a [`Value`] invalidated by [`simplify_variable_ptrs_in_func`].
+#[derive(Copy, Clone)]
+enum ValueReplacement {
+    /// The value was removed, and all of its uses should use this other value
+    /// (which may itself need to be replaced).
+    Removed(Value),
+
+    /// The value was only moved to a new index (after a removal).
+    Renumbered(Value),
+}
+
+/// Perform one round of [`simplify_variable_ptrs`] on `func_def_body`,
+/// returning `true` if any changes were made.
+fn simplify_variable_ptrs_in_func(cx: &Context, func_def_body: &mut FuncDefBody) -> bool {
+    let wk = &spv::spec::Spec::get().well_known;
+
+    let mut removed_values = FxHashMap::default();
+    let mut removed_select_outputs = FxIndexMap::<_, SmallVec<[u32; 2]>>::default();
+    let mut removed_loop_body_inputs = FxIndexMap::<_, SmallVec<[u32; 2]>>::default();
+    let mut removed_insts = vec![];
+
+    let VariablePtrFinder {
+        variable_ptrs,
+        int_to_ptr_casts,
+        ..
+    } = VariablePtrFinder::find_in_func(cx, func_def_body);
+
+    for (block_node, inst) in int_to_ptr_casts {
+        let inst_def = func_def_body.at(inst).def();
+        let is_ptr_to_int_cast = |v| match v {
+            Value::DataInstOutput(src_inst) => match &func_def_body.at(src_inst).def().kind {
+                DataInstKind::SpvInst(src_inst) => {
+                    [wk.OpConvertPtrToU, wk.OpBitcast].contains(&src_inst.opcode)
+                }
+                _ => false,
+            },
+            _ => false,
+        };
+        let original_ptr = match inst_def.inputs[..] {
+            [int] if is_ptr_to_int_cast(int) => match int {
+                Value::DataInstOutput(src_inst) => func_def_body.at(src_inst).def().inputs[0],
+                _ => unreachable!(),
+            },
+            // NOTE(eddyb) `OpBitcast` can also be between two pointer types.
+            [ptr] => ptr,
+            _ => continue,
+        };
+        if Some(func_def_body.at(original_ptr).type_of(cx)) == inst_def.output_type {
+            removed_values.insert(Value::DataInstOutput(inst), original_ptr);
+            removed_insts.push((block_node, inst));
+        }
+    }
+
+    for variable_ptr in variable_ptrs {
+        match variable_ptr {
+            VariablePtr::SelectOutput {
+                select_node,
+                output_idx,
+            } => {
+                let cases = match &func_def_body.at(select_node).def().kind {
+                    ControlNodeKind::Select { cases, .. } => cases,
+                    _ => unreachable!(),
+                };
+                let mut case_outputs = cases
+                    .iter()
+                    .map(|&case| func_def_body.at(case).def().outputs[output_idx as usize]);
+                let first = case_outputs.next();
+                if let Some(v) = first.filter(|&v| case_outputs.all(|v2| v2 == v)) {
+                    removed_values.insert(
+                        Value::ControlNodeOutput {
+                            control_node: select_node,
+                            output_idx,
+                        },
+                        v,
+                    );
+                    removed_select_outputs
+                        .entry(select_node)
+                        .or_default()
+                        .push(output_idx);
+                }
+            }
+            VariablePtr::LoopBodyInput {
+                loop_node,
+                input_idx,
+            } => {
+                let (initial_inputs, body) = match &func_def_body.at(loop_node).def().kind {
+                    ControlNodeKind::Loop {
+                        initial_inputs,
+                        body,
+                        ..
+                    } => (initial_inputs, *body),
+                    _ => unreachable!(),
+                };
+                let input = Value::ControlRegionInput {
+                    region: body,
+                    input_idx,
+                };
+                let initial = initial_inputs[input_idx as usize];
+                let next = func_def_body.at(body).def().outputs[input_idx as usize];
+                if next == input || next == initial {
+                    removed_values.insert(input, initial);
+                    removed_loop_body_inputs
+                        .entry(loop_node)
+                        .or_default()
+                        .push(input_idx);
+                }
+            }
+            VariablePtr::UnstructuredRegionInput { .. } => {}
+            VariablePtr::SpvSelect { block_node, inst } => {
+                let (cond, a, b) = match func_def_body.at(inst).def().inputs[..] {
+                    [cond, a, b] => (cond, a, b),
+                    _ => continue,
+                };
+                let const_cond = match cond {
+                    Value::Const(ct) => match &cx[ct].ctor {
+                        ConstCtor::SpvInst(inst) if inst.opcode == wk.OpConstantTrue => Some(true),
+                        ConstCtor::SpvInst(inst) if inst.opcode == wk.OpConstantFalse => {
+                            Some(false)
+                        }
+                        _ => None,
+                    },
+                    _ => None,
+                };
+                let replacement = match const_cond {
+                    _ if a == b => Some(a),
+                    Some(true) => Some(a),
+                    Some(false) => Some(b),
+                    None => None,
+                };
+                if let Some(v) = replacement {
+                    removed_values.insert(Value::DataInstOutput(inst), v);
+                    removed_insts.push((block_node, inst));
+                }
+            }
+        }
+    }
+    if removed_values.is_empty() {
+        return false;
+    }
+
+    let mut replacements: FxHashMap<_, _> = removed_values
+        .into_iter()
+        .map(|(old, new)| (old, ValueReplacement::Removed(new)))
+        .collect();
+
+    // Keeps only the elements of `values` not at any of the `removed` indices,
+    // returning the `(old, new)` indices for the kept elements which moved.
+    fn retain_unremoved<T>(
+        values: &mut SmallVec<[T; 2]>,
+        removed: &[u32],
+    ) -> impl Iterator<Item = (u32, u32)> {
+        let old_values = std::mem::take(values);
+        let mut moves = SmallVec::<[_; 2]>::new();
+        for (old_idx, v) in (0..).zip(old_values) {
+            if !removed.contains(&old_idx) {
+                let new_idx = values.len().try_into().unwrap();
+                if new_idx != old_idx {
+                    moves.push((old_idx, new_idx));
+                }
+                values.push(v);
+            }
+        }
+        moves.into_iter()
+    }
+
+    for (select_node, removed) in removed_select_outputs {
+        let select_node_def = &mut func_def_body.control_nodes[select_node];
+        let cases = match &select_node_def.kind {
+            ControlNodeKind::Select { cases, .. } => cases,
+            _ => unreachable!(),
+        };
+        for &case in cases {
+            let _ = retain_unremoved(&mut func_def_body.control_regions[case].outputs, &removed);
+        }
+        for (old_idx, new_idx) in retain_unremoved(&mut select_node_def.outputs, &removed) {
+            replacements.insert(
+                Value::ControlNodeOutput {
+                    control_node: select_node,
+                    output_idx: old_idx,
+                },
+                ValueReplacement::Renumbered(Value::ControlNodeOutput {
+                    control_node: select_node,
+                    output_idx: new_idx,
+                }),
+            );
+        }
+    }
+    for (loop_node, removed) in removed_loop_body_inputs {
+        let (initial_inputs, body) = match &mut func_def_body.control_nodes[loop_node].kind {
+            ControlNodeKind::Loop {
+                initial_inputs,
+                body,
+                ..
+            } => (initial_inputs, *body),
+            _ => unreachable!(),
+        };
+        let _ = retain_unremoved(initial_inputs, &removed);
+        let body_def = &mut func_def_body.control_regions[body];
+        let _ = retain_unremoved(&mut body_def.outputs, &removed);
+        for (old_idx, new_idx) in retain_unremoved(&mut body_def.inputs, &removed) {
+            replacements.insert(
+                Value::ControlRegionInput {
+                    region: body,
+                    input_idx: old_idx,
+                },
+                ValueReplacement::Renumbered(Value::ControlRegionInput {
+                    region: body,
+                    input_idx: new_idx,
+                }),
+            );
+        }
+    }
+    for (block_node, inst) in removed_insts {
+        match &mut func_def_body.control_nodes[block_node].kind {
+            ControlNodeKind::Block { insts } => insts.remove(inst, &mut func_def_body.data_insts),
+            _ => unreachable!(),
+        }
+    }
+
+    // NOTE(eddyb) removed values can only be replaced with values defined
+    // before them (i.e. dominating them), so this always terminates.
+    fn resolve(replacements: &FxHashMap<Value, ValueReplacement>, v: Value) -> Value {
+        match replacements.get(&v) {
+            None => v,
+            Some(&ValueReplacement::Renumbered(new)) => new,
+            Some(&ValueReplacement::Removed(new)) => resolve(replacements, new),
+        }
+    }
+
+    func_def_body.inner_in_place_transform_with(&mut ReplaceValueWith(|v| {
+        replacements
+            .contains_key(&v)
+            .then(|| resolve(&replacements, v))
+    }));
+
+    true
+}
+
+struct VariablePtrFinder<'a> {
+    cx: &'a Context,
+    func_def_body: &'a FuncDefBody,
+
+    variable_ptrs: Vec<VariablePtr>,
+
+    /// SPIR-V `OpConvertUToPtr`/`OpBitcast`s producing physical pointers (alongside
+    /// the `Block` [`ControlNode`] they're found in), see [`simplify_variable_ptrs`].
+    int_to_ptr_casts: Vec<(ControlNode, DataInst)>,
+}
+
+impl<'a> VariablePtrFinder<'a> {
+    fn find_in_func(cx: &'a Context, func_def_body: &'a FuncDefBody) -> Self {
+        let mut finder = VariablePtrFinder {
+            cx,
+            func_def_body,
+            variable_ptrs: vec![],
+            int_to_ptr_casts: vec![],
+        };
+        match &func_def_body.unstructured_cfg {
+            None => finder.find_in_region(func_def_body.body),
+            Some(cfg) => {
+                for region in cfg.rev_post_order(func_def_body) {
+                    // NOTE(eddyb) the function body's inputs are its parameters.
+                    if region != func_def_body.body {
+                        let inputs = &func_def_body.at(region).def().inputs;
+                        for (input_idx, input) in inputs.iter().enumerate() {
+                            if finder.is_logical_ptr_type(input.ty) {
+                                finder
+                                    .variable_ptrs
+                                    .push(VariablePtr::UnstructuredRegionInput {
+                                        region,
+                                        input_idx: input_idx.try_into().unwrap(),
+                                    });
+                            }
+                        }
+                    }
+                    finder.find_in_region(region);
+                }
+            }
+        }
+        finder
+    }
+
+    /// Whether `ty` is a pointer type for "logical" (i.e. non-physical) pointers,
+    /// the only kind of pointers which can be [`VariablePtr`]s.
+    fn is_logical_ptr_type(&self, ty: Type) -> bool {
+        AddrSpace::of_ptr_type(self.cx, ty)
+            .is_some_and(|addr_space| !addr_space.has_physical_ptrs())
+    }
+
+    fn find_in_region(&mut self, region: ControlRegion) {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        let func = self.func_def_body.at(region);
+        for func_at_control_node in func.at_children() {
+            let control_node = func_at_control_node.position;
+            let control_node_def = func_at_control_node.def();
+            match &control_node_def.kind {
+                ControlNodeKind::Block { insts } => {
+                    for func_at_inst in func.at(*insts) {
+                        let inst_def = func_at_inst.def();
+                        let (opcode, output_type) = match (&inst_def.kind, inst_def.output_type) {
+                            (DataInstKind::SpvInst(inst), Some(ty)) => (inst.opcode, ty),
+                            _ => continue,
+                        };
+                        if opcode == wk.OpSelect && self.is_logical_ptr_type(output_type) {
+                            self.variable_ptrs.push(VariablePtr::SpvSelect {
+                                block_node: control_node,
+                                inst: func_at_inst.position,
+                            });
+                        }
+                        if [wk.OpConvertUToPtr, wk.OpBitcast].contains(&opcode)
+                            && AddrSpace::of_ptr_type(self.cx, output_type)
+                                .is_some_and(|addr_space| addr_space.has_physical_ptrs())
+                        {
+                            self.int_to_ptr_casts
+                                .push((control_node, func_at_inst.position));
+                        }
+                    }
+                }
+                ControlNodeKind::Select { cases, .. } => {
+                    for (output_idx, output) in control_node_def.outputs.iter().enumerate() {
+                        if self.is_logical_ptr_type(output.ty) {
+                            self.variable_ptrs.push(VariablePtr::SelectOutput {
+                                select_node: control_node,
+                                output_idx: output_idx.try_into().unwrap(),
+                            });
+                        }
+                    }
+                    for &case in cases {
+                        self.find_in_region(case);
+                    }
+                }
+                &ControlNodeKind::Loop { body, .. } => {
+                    for (input_idx, input) in func.at(body).def().inputs.iter().enumerate() {
+                        if self.is_logical_ptr_type(input.ty) {
+                            self.variable_ptrs.push(VariablePtr::LoopBodyInput {
+                                loop_node: control_node,
+                                input_idx: input_idx.try_into().unwrap(),
+                            });
+                        }
+                    }
+                    self.find_in_region(body);
+                }
+                ControlNodeKind::ExitInvocation { .. } => {}
+            }
+        }
+    }
+}
+
+/// Collect all the [`Func`]s reachable from `module`'s exports.
+//
+// FIXME(eddyb) reuse this collection work in some kind of "pass manager".
+pub(crate) fn reachable_funcs(module: &Module) -> FxIndexSet<Func> {
+    reachable_global_vars_and_funcs(module).1
+}
+
+/// Collect all the [`GlobalVar`]s and [`Func`]s reachable from `module`'s exports
+/// (including the interface [`GlobalVar`]s of SPIR-V entry-points).
+pub(crate) fn reachable_global_vars_and_funcs(
+    module: &Module,
+) -> (FxIndexSet<GlobalVar>, FxIndexSet<Func>) {
+    reachable_global_vars_and_funcs_from_exports(module, &module.exports)
+}
+
+/// Collect all the [`GlobalVar`]s and [`Func`]s reachable from `exports` (which
+/// may be only a subset of `module`'s exports), like [`reachable_global_vars_and_funcs`].
+pub(crate) fn reachable_global_vars_and_funcs_from_exports<'a>(
+    module: &Module,
+    exports: impl IntoIterator<Item = (&'a ExportKey, &'a Exportee)>,
+) -> (FxIndexSet<GlobalVar>, FxIndexSet<Func>) {
+    let mut collector = ReachableUseCollector::new(module);
+    for (export_key, &exportee) in exports {
+        export_key.inner_visit_with(&mut collector);
+        exportee.inner_visit_with(&mut collector);
+    }
+    (collector.seen_global_vars, collector.seen_funcs)
+}
+
+/// Collect all the [`GlobalVar`]s and [`Func`]s reachable from `exportee` alone
+/// (i.e. ignoring its [`ExportKey`], and so any entry-point interface).
+pub(crate) fn reachable_global_vars_and_funcs_from_exportee(
+    module: &Module,
+    exportee: Exportee,
+) -> (FxIndexSet<GlobalVar>, FxIndexSet<Func>) {
+    let mut collector = ReachableUseCollector::new(module);
+    exportee.inner_visit_with(&mut collector);
+    (collector.seen_global_vars, collector.seen_funcs)
+}
+
 struct ReachableUseCollector<'a> {
     cx: &'a Context,
     module: &'a Module,
@@ -37,6 +483,20 @@ struct ReachableUseCollector<'a> {
     seen_funcs: FxIndexSet<Func>,
 }
 
+impl<'a> ReachableUseCollector<'a> {
+    fn new(module: &'a Module) -> Self {
+        Self {
+            cx: module.cx_ref(),
+            module,
+
+            seen_types: FxIndexSet::default(),
+            seen_consts: FxIndexSet::default(),
+            seen_global_vars: FxIndexSet::default(),
+            seen_funcs: FxIndexSet::default(),
+        }
+    }
+}
+
 impl Visitor<'_> for ReachableUseCollector<'_> {
     // FIXME(eddyb) build some automation to avoid ever repeating these.
     fn visit_attr_set_use(&mut self, _attrs: AttrSet) {}
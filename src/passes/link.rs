@@ -1,11 +1,20 @@
+use crate::passes::legalize::{
+    reachable_global_vars_and_funcs, reachable_global_vars_and_funcs_from_exportee,
+};
 use crate::transform::{InnerTransform, Transformed, Transformer};
 use crate::visit::{InnerVisit, Visitor};
 use crate::{
-    AttrSet, Const, Context, DeclDef, ExportKey, Exportee, Func, FxIndexSet, GlobalVar, Import,
-    Module, Type,
+    cfg, print, spv, Attr, AttrSet, AttrSetDef, Const, ConstCtor, Context, ControlNode,
+    ControlNodeKind, ControlRegion, DataInst, DataInstKind, DeclDef, ExportKey, Exportee, Func,
+    FuncDefBody, FxIndexMap, FxIndexSet, GlobalVar, Import, InternedStr, Module, ModuleDebugInfo,
+    ModuleDialect, SelectionKind, Type, Value,
 };
 use rustc_hash::{FxHashMap, FxHashSet};
+use smallvec::SmallVec;
 use std::collections::VecDeque;
+use std::fmt;
+use std::mem;
+use std::rc::Rc;
 
 // FIXME(eddyb) maybe make an export pruning pass that keeps some exports as
 // roots and then only other exports if they're used by imports.
@@ -21,7 +30,8 @@ use std::collections::VecDeque;
 ///
 /// Note that the "dead" definitions are not removed from the module, and any
 /// external references to them could still be used (e.g. from a clone of the
-/// `module.exports` map, before calling `minimize_exports`).
+/// `module.exports` map, before calling `minimize_exports`), unless they're
+/// removed afterwards, using [`prune_unreachable`](crate::passes::prune::prune_unreachable).
 //
 // FIXME(eddyb) make this operate on multiple modules.
 pub fn minimize_exports(module: &mut Module, is_root: impl Fn(&ExportKey) -> bool) {
@@ -297,3 +307,1083 @@ impl Transformer for ImportResolver<'_> {
         transformed
     }
 }
+
+/// Options for linking (see [`link_modules`]).
+#[derive(Clone, Default)]
+pub struct LinkOptions {
+    /// Whether to allow [`Import::LinkName`]s without any matching export, which
+    /// are then kept as imports (e.g. when producing a library, not an executable).
+    pub allow_unresolved_imports: bool,
+
+    /// How to handle definitions exported with the same [`ExportKey::LinkName`]
+    /// by multiple modules (see [`DuplicateExports`]).
+    pub duplicate_exports: DuplicateExports,
+}
+
+/// How [`link_modules`] handles definitions exported with the same
+/// [`ExportKey::LinkName`] by multiple modules.
+#[derive(Copy, Clone, Default, PartialEq, Eq)]
+pub enum DuplicateExports {
+    /// Report all duplicates as [`LinkDiagnostic::ConflictingExports`].
+    #[default]
+    Conflict,
+
+    /// Keep only the first definition, and discard all the duplicates (i.e.
+    /// all their uses are replaced with uses of the first definition), like
+    /// `LinkOnceODR` linkage, which relies on the "One Definition Rule" (e.g.
+    /// generic instantiations emitted by each crate that uses them, in Rust).
+    KeepFirst,
+
+    /// Like [`DuplicateExports::KeepFirst`], but also check that all the
+    /// duplicates are structurally identical to the first definition, other
+    /// than in debuginfo (and report any that aren't, as
+    /// [`LinkDiagnostic::NonIdenticalDuplicateExports`]).
+    KeepFirstIfIdentical,
+}
+
+/// Problem found while linking (see [`LinkError`]).
+#[derive(Clone, Debug)]
+pub enum LinkDiagnostic {
+    /// [`Import::LinkName`] without a matching [`ExportKey::LinkName`] (of the
+    /// same kind, i.e. a global variable or a function).
+    UnresolvedImport { name: String },
+
+    /// The same [`ExportKey`] was exported by multiple modules (only the first
+    /// export is kept).
+    ConflictingExports { name: String },
+
+    /// Module-wide settings which differ between modules (e.g. memory models).
+    IncompatibleDialects { message: String },
+
+    /// [`Import::LinkName`] with a type (or function signature) that differs
+    /// from that of the definition exported with the matching [`ExportKey::LinkName`]
+    /// (`message` includes both, printed using [`print::ExpectedVsFound`]).
+    IncompatibleImport { name: String, message: String },
+
+    /// The same [`ExportKey::LinkName`] was exported by multiple modules, with
+    /// definitions that aren't structurally identical (only reported with
+    /// [`DuplicateExports::KeepFirstIfIdentical`]).
+    NonIdenticalDuplicateExports { name: String },
+}
+
+impl fmt::Display for LinkDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnresolvedImport { name } => write!(f, "unresolved import `{name}`"),
+            Self::ConflictingExports { name } => {
+                write!(f, "`{name}` is exported by multiple modules")
+            }
+            Self::IncompatibleDialects { message } => write!(f, "incompatible modules: {message}"),
+            Self::IncompatibleImport { name, message } => {
+                write!(f, "import `{name}` incompatible with its export: {message}")
+            }
+            Self::NonIdenticalDuplicateExports { name } => {
+                write!(
+                    f,
+                    "`{name}` is exported by multiple modules, with different definitions"
+                )
+            }
+        }
+    }
+}
+
+/// Error produced by [`link_modules`], with all the problems found while linking.
+#[derive(Debug)]
+pub struct LinkError {
+    pub diagnostics: Vec<LinkDiagnostic>,
+}
+
+impl fmt::Display for LinkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "failed to link modules:")?;
+        for diag in &self.diagnostics {
+            write!(f, "\n  {diag}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for LinkError {}
+
+/// Link `modules` together into one module, i.e. merge the definitions reachable
+/// from all of their exports (and the exports themselves) into the first module,
+/// then use [`resolve_imports`] to remap every [`Import::LinkName`] to the
+/// definition exported as the matching [`ExportKey::LinkName`], by any module.
+///
+/// All `modules` must share the same [`Context`], so that identical types and
+/// constants (which are interned in the [`Context`]) are also deduplicated.
+///
+/// Any unresolved imports (unless allowed by [`LinkOptions`]), any imports with
+/// types incompatible with their matching exports, any exports conflicting
+/// between modules, and any modules that cannot be merged (e.g. due to different
+/// memory models), are reported together, in a [`LinkError`].
+pub fn link_modules(modules: Vec<Module>, options: &LinkOptions) -> Result<Module, LinkError> {
+    link_modules_and_libraries(modules, &[], options)
+}
+
+/// Link `modules` together (like [`link_modules`]), and also with `libraries`,
+/// from which only the definitions needed to resolve imports are copied (i.e.
+/// an [`ExportKey::LinkName`] export of a library, and everything it uses, is
+/// only copied if its name is imported, even if only by another such export).
+///
+/// If multiple `libraries` export the same name, the first one is used, and
+/// any names exported by `modules` themselves always take precedence.
+pub fn link_modules_and_libraries(
+    modules: Vec<Module>,
+    libraries: &[Module],
+    options: &LinkOptions,
+) -> Result<Module, LinkError> {
+    let mut modules = modules.into_iter();
+    let mut linked = modules.next().expect("link_modules: no modules to link");
+    let mut diagnostics = vec![];
+
+    for module in modules.as_slice().iter().chain(libraries) {
+        assert!(
+            Rc::ptr_eq(linked.cx_ref(), module.cx_ref()),
+            "link_modules: modules from different `Context`s"
+        );
+    }
+
+    let cx = linked.cx();
+
+    for module in modules {
+        let mut remapper = EntityRemapper::new(&cx);
+        merge_dialect_and_debug_info_into(&mut linked, &module, &mut diagnostics);
+        if options.duplicate_exports != DuplicateExports::Conflict {
+            merge_duplicate_exports_into(
+                &linked,
+                &module,
+                &mut remapper,
+                options.duplicate_exports,
+                &mut diagnostics,
+            );
+        }
+        let (global_vars, funcs) = reachable_global_vars_and_funcs(&module);
+        copy_defs_into(&mut linked, &module, &mut remapper, global_vars, funcs);
+        for (export_key, exportee) in module.exports {
+            copy_export_into(
+                &mut linked,
+                &mut remapper,
+                export_key,
+                exportee,
+                &mut diagnostics,
+            );
+        }
+    }
+
+    // NOTE(eddyb) copying library exports can introduce new imports, so this
+    // keeps going until no more unresolved imports can be resolved this way.
+    let mut library_remappers: Vec<Option<EntityRemapper<'_>>> =
+        libraries.iter().map(|_| None).collect();
+    loop {
+        let mut copied_any = false;
+        for name in reachable_import_names(&linked) {
+            let export_key = ExportKey::LinkName(name);
+            if linked.exports.contains_key(&export_key) {
+                continue;
+            }
+            let mut found = None;
+            for (library, remapper) in libraries.iter().zip(&mut library_remappers) {
+                if let Some(&exportee) = library.exports.get(&export_key) {
+                    found = Some((library, remapper, exportee));
+                    break;
+                }
+            }
+            let (library, remapper, exportee) = match found {
+                Some(found) => found,
+                None => continue,
+            };
+
+            let remapper = remapper.get_or_insert_with(|| {
+                merge_dialect_and_debug_info_into(&mut linked, library, &mut diagnostics);
+                EntityRemapper::new(&cx)
+            });
+            let (global_vars, funcs) =
+                reachable_global_vars_and_funcs_from_exportee(library, exportee);
+            copy_defs_into(&mut linked, library, remapper, global_vars, funcs);
+            copy_export_into(
+                &mut linked,
+                remapper,
+                export_key,
+                exportee,
+                &mut diagnostics,
+            );
+            copied_any = true;
+        }
+        if !copied_any {
+            break;
+        }
+    }
+
+    check_import_types(&linked, &mut diagnostics);
+    resolve_imports(&mut linked);
+
+    if !options.allow_unresolved_imports {
+        for name in reachable_import_names(&linked) {
+            diagnostics.push(LinkDiagnostic::UnresolvedImport {
+                name: cx[name].to_string(),
+            });
+        }
+    }
+
+    if diagnostics.is_empty() {
+        Ok(linked)
+    } else {
+        Err(LinkError { diagnostics })
+    }
+}
+
+/// Collect the names of all the [`Import::LinkName`]s reachable from `module`'s
+/// exports (after [`resolve_imports`], these are all unresolved imports).
+fn reachable_import_names(module: &Module) -> FxIndexSet<InternedStr> {
+    let (global_vars, funcs) = reachable_global_vars_and_funcs(module);
+    let global_var_imports =
+        global_vars
+            .into_iter()
+            .filter_map(|gv| match module.global_vars[gv].def {
+                DeclDef::Imported(import) => Some(import),
+                DeclDef::Present(_) => None,
+            });
+    let func_imports = funcs
+        .into_iter()
+        .filter_map(|func| match module.funcs[func].def {
+            DeclDef::Imported(import) => Some(import),
+            DeclDef::Present(_) => None,
+        });
+    global_var_imports
+        .chain(func_imports)
+        .map(|import| match import {
+            Import::LinkName(name) => name,
+        })
+        .collect()
+}
+
+/// Check that all the [`Import::LinkName`]s reachable from `module`'s exports,
+/// which [`resolve_imports`] would resolve to a [`ExportKey::LinkName`] export,
+/// have the same type (or function signature) as the exported definition, as
+/// otherwise their uses would become invalid, once they're resolved.
+//
+// FIXME(eddyb) this should also be done by `resolve_imports` itself, once it
+// has a way to report errors.
+fn check_import_types(module: &Module, diagnostics: &mut Vec<LinkDiagnostic>) {
+    let cx = module.cx_ref();
+
+    let mismatch = |name: InternedStr, what: &str, expected: Type, found: Type| {
+        LinkDiagnostic::IncompatibleImport {
+            name: cx[name].to_string(),
+            message: format!(
+                "{what} differs between import (expected) and export (found):\n\n{}",
+                print::Plan::for_root(cx, &print::ExpectedVsFound { expected, found })
+                    .pretty_print()
+            ),
+        }
+    };
+
+    let (global_vars, funcs) = reachable_global_vars_and_funcs(module);
+    for gv in global_vars {
+        let gv_decl = &module.global_vars[gv];
+        let name = match gv_decl.def {
+            DeclDef::Imported(Import::LinkName(name)) => name,
+            DeclDef::Present(_) => continue,
+        };
+        if let Some(&Exportee::GlobalVar(def_gv)) = module.exports.get(&ExportKey::LinkName(name)) {
+            let def_gv_decl = &module.global_vars[def_gv];
+            if gv_decl.type_of_ptr_to != def_gv_decl.type_of_ptr_to {
+                diagnostics.push(mismatch(
+                    name,
+                    "pointer type",
+                    gv_decl.type_of_ptr_to,
+                    def_gv_decl.type_of_ptr_to,
+                ));
+            }
+        }
+    }
+    for func in funcs {
+        let func_decl = &module.funcs[func];
+        let name = match func_decl.def {
+            DeclDef::Imported(Import::LinkName(name)) => name,
+            DeclDef::Present(_) => continue,
+        };
+        if let Some(&Exportee::Func(def_func)) = module.exports.get(&ExportKey::LinkName(name)) {
+            let def_func_decl = &module.funcs[def_func];
+            if func_decl.ret_type != def_func_decl.ret_type {
+                diagnostics.push(mismatch(
+                    name,
+                    "return type",
+                    func_decl.ret_type,
+                    def_func_decl.ret_type,
+                ));
+            }
+            if func_decl.params.len() != def_func_decl.params.len() {
+                diagnostics.push(LinkDiagnostic::IncompatibleImport {
+                    name: cx[name].to_string(),
+                    message: format!(
+                        "import has {} params, but export has {} params",
+                        func_decl.params.len(),
+                        def_func_decl.params.len()
+                    ),
+                });
+                continue;
+            }
+            for (i, (param, def_param)) in func_decl
+                .params
+                .iter()
+                .zip(&def_func_decl.params)
+                .enumerate()
+            {
+                if param.ty != def_param.ty {
+                    diagnostics.push(mismatch(
+                        name,
+                        &format!("param {i}'s type"),
+                        param.ty,
+                        def_param.ty,
+                    ));
+                }
+            }
+        }
+    }
+}
+
+/// Remap the definitions exported by `module` with the same [`ExportKey::LinkName`]
+/// (and of the same kind) as an existing export of `linked`, to that existing
+/// definition, so that `copy_defs_into` never copies them (see [`DuplicateExports`]).
+fn merge_duplicate_exports_into(
+    linked: &Module,
+    module: &Module,
+    remapper: &mut EntityRemapper<'_>,
+    duplicate_exports: DuplicateExports,
+    diagnostics: &mut Vec<LinkDiagnostic>,
+) {
+    for (export_key, &exportee) in &module.exports {
+        let name = match *export_key {
+            ExportKey::LinkName(name) => name,
+            ExportKey::SpvEntryPoint { .. } => continue,
+        };
+        let existing = match linked.exports.get(export_key) {
+            Some(&existing) => existing,
+            None => continue,
+        };
+        let is_identical = |a, b| match duplicate_exports {
+            DuplicateExports::Conflict | DuplicateExports::KeepFirst => true,
+            DuplicateExports::KeepFirstIfIdentical => {
+                StructuralEq::new(linked, module).exportees(a, b)
+            }
+        };
+        let identical = match (existing, exportee) {
+            (Exportee::GlobalVar(existing_gv), Exportee::GlobalVar(gv)) => {
+                remapper.global_vars.insert(gv, existing_gv);
+                is_identical(existing, exportee)
+            }
+            (Exportee::Func(existing_func), Exportee::Func(func)) => {
+                remapper.funcs.insert(func, existing_func);
+                is_identical(existing, exportee)
+            }
+            // NOTE(eddyb) left to be reported by `copy_export_into`.
+            _ => continue,
+        };
+        if !identical {
+            diagnostics.push(LinkDiagnostic::NonIdenticalDuplicateExports {
+                name: remapper.cx[name].to_string(),
+            });
+        }
+    }
+}
+
+/// Structural equality between definitions from two modules (`a` and `b`),
+/// which share the same [`Context`] (so interned types and constants which
+/// don't refer to any entities can be compared by their handles).
+///
+/// Attributes are compared ignoring those which can't affect semantics (see
+/// [`Attr::is_non_semantic`]), as e.g. debuginfo and original SPIR-V IDs are
+/// expected to differ between otherwise identical definitions from two modules.
+//
+// FIXME(eddyb) types are always compared by their handles, which is only
+// incorrect for types (indirectly) referring to global variables.
+struct StructuralEq<'a> {
+    a: &'a Module,
+    b: &'a Module,
+
+    // NOTE(eddyb) pairs of entities are assumed equal while comparing them
+    // (which is only sound because a new `StructuralEq` is used for each
+    // top-level comparison), to support cycles (e.g. recursive functions).
+    global_vars: FxHashSet<(GlobalVar, GlobalVar)>,
+    funcs: FxHashSet<(Func, Func)>,
+}
+
+impl<'a> StructuralEq<'a> {
+    fn new(a: &'a Module, b: &'a Module) -> Self {
+        Self {
+            a,
+            b,
+
+            global_vars: FxHashSet::default(),
+            funcs: FxHashSet::default(),
+        }
+    }
+
+    fn attrs(&self, a: AttrSet, b: AttrSet) -> bool {
+        let cx = self.a.cx_ref();
+        a == b || a.semantic_subset(cx) == b.semantic_subset(cx)
+    }
+
+    fn exportees(&mut self, a: Exportee, b: Exportee) -> bool {
+        match (a, b) {
+            (Exportee::GlobalVar(a), Exportee::GlobalVar(b)) => self.global_vars(a, b),
+            (Exportee::Func(a), Exportee::Func(b)) => self.funcs(a, b),
+            _ => false,
+        }
+    }
+
+    fn consts(&mut self, a: Const, b: Const) -> bool {
+        if a == b {
+            return true;
+        }
+        let cx = self.a.cx_ref();
+        let (a_def, b_def) = (&cx[a], &cx[b]);
+        let ctors_eq = match (&a_def.ctor, &b_def.ctor) {
+            (&ConstCtor::PtrToGlobalVar(a), &ConstCtor::PtrToGlobalVar(b)) => {
+                self.global_vars(a, b)
+            }
+            (a_ctor, b_ctor) => a_ctor == b_ctor,
+        };
+        ctors_eq
+            && self.attrs(a_def.attrs, b_def.attrs)
+            && a_def.ty == b_def.ty
+            && a_def.ctor_args.len() == b_def.ctor_args.len()
+            && (a_def.ctor_args.iter().zip(&b_def.ctor_args)).all(|(&a, &b)| self.consts(a, b))
+    }
+
+    fn global_vars(&mut self, a: GlobalVar, b: GlobalVar) -> bool {
+        if !self.global_vars.insert((a, b)) {
+            return true;
+        }
+        let (a_decl, b_decl) = (&self.a.global_vars[a], &self.b.global_vars[b]);
+        self.attrs(a_decl.attrs, b_decl.attrs)
+            && a_decl.type_of_ptr_to == b_decl.type_of_ptr_to
+            && a_decl.addr_space == b_decl.addr_space
+            && match (&a_decl.def, &b_decl.def) {
+                (DeclDef::Imported(a), DeclDef::Imported(b)) => a == b,
+                (DeclDef::Present(a), DeclDef::Present(b)) => {
+                    match (a.initializer, b.initializer) {
+                        (Some(a), Some(b)) => self.consts(a, b),
+                        (None, None) => true,
+                        _ => false,
+                    }
+                }
+                _ => false,
+            }
+    }
+
+    fn funcs(&mut self, a: Func, b: Func) -> bool {
+        if !self.funcs.insert((a, b)) {
+            return true;
+        }
+        let (a_decl, b_decl) = (&self.a.funcs[a], &self.b.funcs[b]);
+        self.attrs(a_decl.attrs, b_decl.attrs)
+            && a_decl.ret_type == b_decl.ret_type
+            && a_decl.params.len() == b_decl.params.len()
+            && (a_decl.params.iter().zip(&b_decl.params))
+                .all(|(a, b)| self.attrs(a.attrs, b.attrs) && a.ty == b.ty)
+            && match (&a_decl.def, &b_decl.def) {
+                (DeclDef::Imported(a), DeclDef::Imported(b)) => a == b,
+                (DeclDef::Present(a), DeclDef::Present(b)) => StructuralEqBody {
+                    entities: self,
+                    a,
+                    b,
+
+                    control_regions: FxHashMap::default(),
+                    control_nodes: FxHashMap::default(),
+                    data_insts: FxHashMap::default(),
+                }
+                .func_def_bodies(),
+                _ => false,
+            }
+    }
+}
+
+/// Structural equality between two function bodies (see [`StructuralEq`]).
+struct StructuralEqBody<'a, 'b> {
+    entities: &'b mut StructuralEq<'a>,
+    a: &'a FuncDefBody,
+    b: &'a FuncDefBody,
+
+    // NOTE(eddyb) these map from `a`'s entities to their `b` equivalents.
+    control_regions: FxHashMap<ControlRegion, ControlRegion>,
+    control_nodes: FxHashMap<ControlNode, ControlNode>,
+    data_insts: FxHashMap<DataInst, DataInst>,
+}
+
+impl StructuralEqBody<'_, '_> {
+    fn func_def_bodies(&mut self) -> bool {
+        match (&self.a.unstructured_cfg, &self.b.unstructured_cfg) {
+            (None, None) => self.control_regions(self.a.body, self.b.body),
+            (Some(a_cfg), Some(b_cfg)) => {
+                // NOTE(eddyb) regions are compared in reverse post-order, so
+                // that all values are compared after their definitions (other
+                // than those used in control instructions, compared last).
+                let a_rpo: SmallVec<[_; 8]> = a_cfg.rev_post_order(self.a).collect();
+                let b_rpo: SmallVec<[_; 8]> = b_cfg.rev_post_order(self.b).collect();
+                if a_rpo.len() != b_rpo.len()
+                    || !(a_rpo.iter().zip(&b_rpo)).all(|(&a, &b)| self.control_regions(a, b))
+                {
+                    return false;
+                }
+                a_rpo.iter().zip(&b_rpo).all(|(&a, &b)| {
+                    match (
+                        a_cfg.control_inst_on_exit_from.get(a),
+                        b_cfg.control_inst_on_exit_from.get(b),
+                    ) {
+                        (Some(a), Some(b)) => self.control_insts(a, b),
+                        (None, None) => true,
+                        _ => false,
+                    }
+                })
+            }
+            _ => false,
+        }
+    }
+
+    fn control_insts(&mut self, a: &cfg::ControlInst, b: &cfg::ControlInst) -> bool {
+        let kinds_eq = match (&a.kind, &b.kind) {
+            (cfg::ControlInstKind::Unreachable, cfg::ControlInstKind::Unreachable)
+            | (cfg::ControlInstKind::Return, cfg::ControlInstKind::Return)
+            | (cfg::ControlInstKind::Branch, cfg::ControlInstKind::Branch) => true,
+            (cfg::ControlInstKind::ExitInvocation(a), cfg::ControlInstKind::ExitInvocation(b)) => {
+                exit_invocation_kinds_eq(a, b)
+            }
+            (cfg::ControlInstKind::SelectBranch(a), cfg::ControlInstKind::SelectBranch(b)) => {
+                selection_kinds_eq(a, b)
+            }
+            _ => false,
+        };
+        kinds_eq
+            && self.entities.attrs(a.attrs, b.attrs)
+            && self.value_lists(&a.inputs, &b.inputs)
+            && a.targets.len() == b.targets.len()
+            && (a.targets.iter().zip(&b.targets))
+                .all(|(a, &b)| self.control_regions.get(a) == Some(&b))
+            && a.target_inputs.len() == b.target_inputs.len()
+            && (a.target_inputs.iter().zip(&b.target_inputs)).all(
+                |((a_target, a_inputs), (&b_target, b_inputs))| {
+                    self.control_regions.get(a_target) == Some(&b_target)
+                        && self.value_lists(a_inputs, b_inputs)
+                },
+            )
+    }
+
+    fn control_regions(&mut self, a: ControlRegion, b: ControlRegion) -> bool {
+        self.control_regions.insert(a, b);
+
+        let (a_def, b_def) = (&self.a.control_regions[a], &self.b.control_regions[b]);
+        if a_def.inputs.len() != b_def.inputs.len()
+            || !(a_def.inputs.iter().zip(&b_def.inputs))
+                .all(|(a, b)| self.entities.attrs(a.attrs, b.attrs) && a.ty == b.ty)
+        {
+            return false;
+        }
+
+        let a_children: SmallVec<[_; 8]> = self
+            .a
+            .at(a)
+            .at_children()
+            .into_iter()
+            .map(|func_at| func_at.position)
+            .collect();
+        let b_children: SmallVec<[_; 8]> = self
+            .b
+            .at(b)
+            .at_children()
+            .into_iter()
+            .map(|func_at| func_at.position)
+            .collect();
+        a_children.len() == b_children.len()
+            && (a_children.iter().zip(&b_children)).all(|(&a, &b)| self.control_nodes(a, b))
+            && self.value_lists(&a_def.outputs, &b_def.outputs)
+    }
+
+    fn control_nodes(&mut self, a: ControlNode, b: ControlNode) -> bool {
+        let (a_def, b_def) = (&self.a.control_nodes[a], &self.b.control_nodes[b]);
+        let kinds_eq = match (&a_def.kind, &b_def.kind) {
+            (
+                &ControlNodeKind::Block { insts: a_insts },
+                &ControlNodeKind::Block { insts: b_insts },
+            ) => {
+                let a_insts: SmallVec<[_; 8]> = self
+                    .a
+                    .at(a_insts)
+                    .into_iter()
+                    .map(|func_at| func_at.position)
+                    .collect();
+                let b_insts: SmallVec<[_; 8]> = self
+                    .b
+                    .at(b_insts)
+                    .into_iter()
+                    .map(|func_at| func_at.position)
+                    .collect();
+                a_insts.len() == b_insts.len()
+                    && (a_insts.iter().zip(&b_insts)).all(|(&a, &b)| self.data_insts(a, b))
+            }
+            (
+                ControlNodeKind::Select {
+                    kind: a_kind,
+                    scrutinee: a_scrutinee,
+                    cases: a_cases,
+                },
+                ControlNodeKind::Select {
+                    kind: b_kind,
+                    scrutinee: b_scrutinee,
+                    cases: b_cases,
+                },
+            ) => {
+                selection_kinds_eq(a_kind, b_kind)
+                    && self.values(*a_scrutinee, *b_scrutinee)
+                    && a_cases.len() == b_cases.len()
+                    && (a_cases.iter().zip(b_cases)).all(|(&a, &b)| self.control_regions(a, b))
+            }
+            (
+                ControlNodeKind::Loop {
+                    initial_inputs: a_initial_inputs,
+                    body: a_body,
+                    repeat_condition: a_repeat_condition,
+                },
+                ControlNodeKind::Loop {
+                    initial_inputs: b_initial_inputs,
+                    body: b_body,
+                    repeat_condition: b_repeat_condition,
+                },
+            ) => {
+                self.value_lists(a_initial_inputs, b_initial_inputs)
+                    && self.control_regions(*a_body, *b_body)
+                    && self.values(*a_repeat_condition, *b_repeat_condition)
+            }
+            (
+                ControlNodeKind::ExitInvocation {
+                    kind: a_kind,
+                    inputs: a_inputs,
+                },
+                ControlNodeKind::ExitInvocation {
+                    kind: b_kind,
+                    inputs: b_inputs,
+                },
+            ) => exit_invocation_kinds_eq(a_kind, b_kind) && self.value_lists(a_inputs, b_inputs),
+            _ => false,
+        };
+        if !kinds_eq
+            || !self.entities.attrs(a_def.attrs, b_def.attrs)
+            || a_def.outputs.len() != b_def.outputs.len()
+            || !(a_def.outputs.iter().zip(&b_def.outputs))
+                .all(|(a, b)| self.entities.attrs(a.attrs, b.attrs) && a.ty == b.ty)
+        {
+            return false;
+        }
+        self.control_nodes.insert(a, b);
+        true
+    }
+
+    fn data_insts(&mut self, a: DataInst, b: DataInst) -> bool {
+        let (a_def, b_def) = (&*self.a.data_insts[a], &*self.b.data_insts[b]);
+        let kinds_eq = match (&a_def.kind, &b_def.kind) {
+            (&DataInstKind::FuncCall(a), &DataInstKind::FuncCall(b)) => self.entities.funcs(a, b),
+            (a_kind, b_kind) => a_kind == b_kind,
+        };
+        if !kinds_eq
+            || !self.entities.attrs(a_def.attrs, b_def.attrs)
+            || a_def.output_type != b_def.output_type
+            || !self.value_lists(&a_def.inputs, &b_def.inputs)
+        {
+            return false;
+        }
+        self.data_insts.insert(a, b);
+        true
+    }
+
+    fn value_lists(&mut self, a: &[Value], b: &[Value]) -> bool {
+        a.len() == b.len() && a.iter().zip(b).all(|(&a, &b)| self.values(a, b))
+    }
+
+    fn values(&mut self, a: Value, b: Value) -> bool {
+        match (a, b) {
+            (Value::Const(a), Value::Const(b)) => self.entities.consts(a, b),
+            (
+                Value::ControlRegionInput {
+                    region: a_region,
+                    input_idx: a_idx,
+                },
+                Value::ControlRegionInput {
+                    region: b_region,
+                    input_idx: b_idx,
+                },
+            ) => self.control_regions.get(&a_region) == Some(&b_region) && a_idx == b_idx,
+            (
+                Value::ControlNodeOutput {
+                    control_node: a_node,
+                    output_idx: a_idx,
+                },
+                Value::ControlNodeOutput {
+                    control_node: b_node,
+                    output_idx: b_idx,
+                },
+            ) => self.control_nodes.get(&a_node) == Some(&b_node) && a_idx == b_idx,
+            (Value::DataInstOutput(a), Value::DataInstOutput(b)) => {
+                self.data_insts.get(&a) == Some(&b)
+            }
+            _ => false,
+        }
+    }
+}
+
+fn selection_kinds_eq(a: &SelectionKind, b: &SelectionKind) -> bool {
+    match (a, b) {
+        (SelectionKind::BoolCond, SelectionKind::BoolCond) => true,
+        (SelectionKind::SpvInst(a), SelectionKind::SpvInst(b)) => a == b,
+        _ => false,
+    }
+}
+
+fn exit_invocation_kinds_eq(a: &cfg::ExitInvocationKind, b: &cfg::ExitInvocationKind) -> bool {
+    match (a, b) {
+        (cfg::ExitInvocationKind::SpvInst(a), cfg::ExitInvocationKind::SpvInst(b)) => a == b,
+        (cfg::ExitInvocationKind::EmitMeshTasks, cfg::ExitInvocationKind::EmitMeshTasks) => true,
+        _ => false,
+    }
+}
+
+/// Merge the module-wide settings (i.e. dialect and debuginfo) of `module`
+/// into `linked` (see [`link_modules`]).
+fn merge_dialect_and_debug_info_into(
+    linked: &mut Module,
+    module: &Module,
+    diagnostics: &mut Vec<LinkDiagnostic>,
+) {
+    match (&mut linked.dialect, &module.dialect) {
+        (ModuleDialect::Spv(dialect), ModuleDialect::Spv(other_dialect)) => {
+            let version = (dialect.version_major, dialect.version_minor)
+                .max((other_dialect.version_major, other_dialect.version_minor));
+            dialect.version_major = version.0;
+            dialect.version_minor = version.1;
+
+            dialect
+                .capabilities
+                .extend(other_dialect.capabilities.iter().copied());
+            dialect
+                .extensions
+                .extend(other_dialect.extensions.iter().cloned());
+
+            if dialect.addressing_model != other_dialect.addressing_model {
+                diagnostics.push(LinkDiagnostic::IncompatibleDialects {
+                    message: format!(
+                        "addressing model {} vs {}",
+                        dialect.addressing_model, other_dialect.addressing_model
+                    ),
+                });
+            }
+            if dialect.memory_model != other_dialect.memory_model {
+                diagnostics.push(LinkDiagnostic::IncompatibleDialects {
+                    message: format!(
+                        "memory model {} vs {}",
+                        dialect.memory_model, other_dialect.memory_model
+                    ),
+                });
+            }
+        }
+    }
+
+    match (&mut linked.debug_info, &module.debug_info) {
+        (ModuleDebugInfo::Spv(debug_info), ModuleDebugInfo::Spv(other_debug_info)) => {
+            if debug_info.original_generator_magic != other_debug_info.original_generator_magic {
+                debug_info.original_generator_magic = None;
+            }
+            for (lang, sources) in &other_debug_info.source_languages {
+                debug_info
+                    .source_languages
+                    .entry(lang.clone())
+                    .or_default()
+                    .file_contents
+                    .extend(
+                        sources
+                            .file_contents
+                            .iter()
+                            .map(|(&file, contents)| (file, contents.clone())),
+                    );
+            }
+            for ext in &other_debug_info.source_extensions {
+                if !debug_info.source_extensions.contains(ext) {
+                    debug_info.source_extensions.push(ext.clone());
+                }
+            }
+            for proc in &other_debug_info.module_processes {
+                if !debug_info.module_processes.contains(proc) {
+                    debug_info.module_processes.push(proc.clone());
+                }
+            }
+        }
+    }
+}
+
+/// Copy the definitions of `global_vars` and `funcs` from `module` into `linked`
+/// (skipping any already copied, i.e. already known to `remapper`), remapping
+/// all of their uses of other [`GlobalVar`]s and [`Func`]s through `remapper`.
+fn copy_defs_into(
+    linked: &mut Module,
+    module: &Module,
+    remapper: &mut EntityRemapper<'_>,
+    global_vars: FxIndexSet<GlobalVar>,
+    funcs: FxIndexSet<Func>,
+) {
+    let cx = remapper.cx;
+
+    let global_vars: SmallVec<[_; 8]> = global_vars
+        .into_iter()
+        .filter(|gv| !remapper.global_vars.contains_key(gv))
+        .collect();
+    let funcs: SmallVec<[_; 8]> = funcs
+        .into_iter()
+        .filter(|func| !remapper.funcs.contains_key(func))
+        .collect();
+
+    // NOTE(eddyb) all definitions are first copied as-is, to allocate their
+    // new entities, and only then are their uses of other entities remapped.
+    for &gv in &global_vars {
+        let new_gv = linked
+            .global_vars
+            .define(cx, module.global_vars[gv].clone());
+        remapper.global_vars.insert(gv, new_gv);
+    }
+    for &func in &funcs {
+        let new_func = linked.funcs.define(cx, module.funcs[func].clone());
+        remapper.funcs.insert(func, new_func);
+    }
+    for gv in global_vars {
+        let new_gv = remapper.global_vars[&gv];
+        remapper.in_place_transform_global_var_decl(&mut linked.global_vars[new_gv]);
+    }
+    for func in funcs {
+        let new_func = remapper.funcs[&func];
+        remapper.in_place_transform_func_decl(&mut linked.funcs[new_func]);
+    }
+}
+
+/// Add the export of `exportee` as `export_key` (both remapped through `remapper`,
+/// i.e. their definitions must have already been copied, see [`copy_defs_into`])
+/// to `linked`, unless `export_key` is already exported (which is reported).
+fn copy_export_into(
+    linked: &mut Module,
+    remapper: &mut EntityRemapper<'_>,
+    mut export_key: ExportKey,
+    mut exportee: Exportee,
+    diagnostics: &mut Vec<LinkDiagnostic>,
+) {
+    export_key
+        .inner_transform_with(remapper)
+        .apply_to(&mut export_key);
+    exportee
+        .inner_transform_with(remapper)
+        .apply_to(&mut exportee);
+
+    match linked.exports.entry(export_key) {
+        indexmap::map::Entry::Occupied(entry) => {
+            // NOTE(eddyb) duplicates already merged (see `DuplicateExports`)
+            // get remapped to the existing definition, and can be ignored.
+            match (*entry.get(), exportee) {
+                (Exportee::GlobalVar(a), Exportee::GlobalVar(b)) if a == b => return,
+                (Exportee::Func(a), Exportee::Func(b)) if a == b => return,
+                _ => {}
+            }
+            diagnostics.push(LinkDiagnostic::ConflictingExports {
+                name: export_key_name(remapper.cx, entry.key()),
+            });
+        }
+        indexmap::map::Entry::Vacant(entry) => {
+            entry.insert(exportee);
+        }
+    }
+}
+
+/// Get a human-readable name for `export_key`, for diagnostics.
+fn export_key_name(cx: &Context, export_key: &ExportKey) -> String {
+    match export_key {
+        &ExportKey::LinkName(name) => cx[name].to_string(),
+        ExportKey::SpvEntryPoint { imms, .. } => {
+            let name = imms
+                .get(1..)
+                .and_then(|name| spv::extract_literal_string(name).ok())
+                .unwrap_or_default();
+            format!("entry-point {name:?}")
+        }
+    }
+}
+
+/// [`Transformer`] remapping [`GlobalVar`]s and [`Func`]s (e.g. from one module's
+/// [`EntityDefs`](crate::EntityDefs) to another's, see [`copy_defs_into`]).
+struct EntityRemapper<'a> {
+    cx: &'a Context,
+
+    global_vars: FxHashMap<GlobalVar, GlobalVar>,
+    funcs: FxHashMap<Func, Func>,
+
+    // FIXME(eddyb) build some automation to avoid ever repeating these.
+    transformed_types: FxHashMap<Type, Transformed<Type>>,
+    transformed_consts: FxHashMap<Const, Transformed<Const>>,
+}
+
+impl<'a> EntityRemapper<'a> {
+    fn new(cx: &'a Context) -> Self {
+        Self {
+            cx,
+
+            global_vars: FxHashMap::default(),
+            funcs: FxHashMap::default(),
+
+            transformed_types: FxHashMap::default(),
+            transformed_consts: FxHashMap::default(),
+        }
+    }
+}
+
+impl Transformer for EntityRemapper<'_> {
+    // FIXME(eddyb) build some automation to avoid ever repeating these.
+    fn transform_type_use(&mut self, ty: Type) -> Transformed<Type> {
+        if let Some(&cached) = self.transformed_types.get(&ty) {
+            return cached;
+        }
+        let transformed = self
+            .transform_type_def(&self.cx[ty])
+            .map(|ty_def| self.cx.intern(ty_def));
+        self.transformed_types.insert(ty, transformed);
+        transformed
+    }
+    fn transform_const_use(&mut self, ct: Const) -> Transformed<Const> {
+        if let Some(&cached) = self.transformed_consts.get(&ct) {
+            return cached;
+        }
+        let transformed = self
+            .transform_const_def(&self.cx[ct])
+            .map(|ct_def| self.cx.intern(ct_def));
+        self.transformed_consts.insert(ct, transformed);
+        transformed
+    }
+
+    fn transform_global_var_use(&mut self, gv: GlobalVar) -> Transformed<GlobalVar> {
+        match self.global_vars.get(&gv) {
+            Some(&new_gv) => Transformed::Changed(new_gv),
+            None => Transformed::Unchanged,
+        }
+    }
+    fn transform_func_use(&mut self, func: Func) -> Transformed<Func> {
+        match self.funcs.get(&func) {
+            Some(&new_func) => Transformed::Changed(new_func),
+            None => Transformed::Unchanged,
+        }
+    }
+}
+
+/// Rename every [`ExportKey::LinkName`] and [`Import::LinkName`] in `module` for
+/// which `rename(name)` returns `Some(new_name)`, along with the names (i.e.
+/// `OpName`s) of the exported/imported definitions, wherever they match.
+///
+/// `rename` is called at most once per name, and should never map different
+/// names to the same new name (otherwise, all but one of the exports ending up
+/// with the same name would be lost).
+pub fn rename_link_names(module: &mut Module, mut rename: impl FnMut(&str) -> Option<String>) {
+    let cx = module.cx();
+
+    let mut renamed = FxHashMap::<InternedStr, Option<InternedStr>>::default();
+    let mut rename = |name: InternedStr| {
+        *renamed
+            .entry(name)
+            .or_insert_with(|| rename(&cx[name]).map(|new_name| cx.intern(new_name)))
+    };
+
+    let (global_vars, funcs) = reachable_global_vars_and_funcs(module);
+    for gv in global_vars {
+        let gv_decl = &mut module.global_vars[gv];
+        if let DeclDef::Imported(Import::LinkName(name)) = &mut gv_decl.def {
+            if let Some(new_name) = rename(*name) {
+                gv_decl.attrs = with_renamed_debug_name(&cx, gv_decl.attrs, *name, new_name);
+                *name = new_name;
+            }
+        }
+    }
+    for func in funcs {
+        let func_decl = &mut module.funcs[func];
+        if let DeclDef::Imported(Import::LinkName(name)) = &mut func_decl.def {
+            if let Some(new_name) = rename(*name) {
+                func_decl.attrs = with_renamed_debug_name(&cx, func_decl.attrs, *name, new_name);
+                *name = new_name;
+            }
+        }
+    }
+
+    let mut exports = FxIndexMap::default();
+    for (export_key, exportee) in mem::take(&mut module.exports) {
+        let export_key = match export_key {
+            ExportKey::LinkName(name) => match rename(name) {
+                Some(new_name) => {
+                    let attrs = match exportee {
+                        Exportee::GlobalVar(gv) => &mut module.global_vars[gv].attrs,
+                        Exportee::Func(func) => &mut module.funcs[func].attrs,
+                    };
+                    *attrs = with_renamed_debug_name(&cx, *attrs, name, new_name);
+                    ExportKey::LinkName(new_name)
+                }
+                None => export_key,
+            },
+            ExportKey::SpvEntryPoint { .. } => export_key,
+        };
+        exports.insert(export_key, exportee);
+    }
+    module.exports = exports;
+}
+
+/// Prefix with `prefix` the names of all [`ExportKey::LinkName`]s in `module`,
+/// and of any [`Import::LinkName`]s referring to them (see [`rename_link_names`]),
+/// e.g. to avoid conflicts when linking several libraries together.
+///
+/// Imports of names not exported by `module` itself are left unchanged.
+pub fn prefix_link_names(module: &mut Module, prefix: &str) {
+    let cx = module.cx();
+
+    let exported_names: FxHashSet<_> = module
+        .exports
+        .keys()
+        .filter_map(|export_key| match *export_key {
+            ExportKey::LinkName(name) => Some(name),
+            ExportKey::SpvEntryPoint { .. } => None,
+        })
+        .collect();
+    rename_link_names(module, |name| {
+        exported_names
+            .contains(&cx.intern(name))
+            .then(|| format!("{prefix}{name}"))
+    });
+}
+
+/// Replace the name of a definition (its `OpName`, kept in `attrs`) with
+/// `new_name`, if it's `old_name`.
+fn with_renamed_debug_name(
+    cx: &Context,
+    attrs: AttrSet,
+    old_name: InternedStr,
+    new_name: InternedStr,
+) -> AttrSet {
+    let wk = &spv::spec::Spec::get().well_known;
+
+    let attrs = cx[attrs]
+        .attrs
+        .iter()
+        .map(|attr| match attr {
+            Attr::SpvAnnotation(inst)
+                if inst.opcode == wk.OpName
+                    && spv::extract_literal_string(&inst.imms).ok().as_deref()
+                        == Some(&cx[old_name]) =>
+            {
+                Attr::SpvAnnotation(spv::Inst {
+                    opcode: wk.OpName,
+                    imms: spv::encode_literal_string(&cx[new_name]).collect(),
+                })
+            }
+            _ => attr.clone(),
+        })
+        .collect();
+    cx.intern(AttrSetDef { attrs })
+}
@@ -0,0 +1,173 @@
+//! Pass manager, for running pipelines of passes (see [`PassManager`]).
+
+use crate::Module;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Ordered pipeline of (named) passes, to run over a [`Module`], e.g.:
+/// ```text
+/// let report = PassManager::new()
+///     .add("structurize_func_cfgs", legalize::structurize_func_cfgs)
+///     .add("remove_dead_code", dce::remove_dead_code)
+///     .capture_snapshots(true)
+///     .run(&mut module)?;
+///
+/// let plan = Plan::for_versions(&cx, report.versions());
+/// ```
+///
+/// Besides running the passes, [`PassManager::run`] also records how long each
+/// pass took, and can optionally run a verifier after each pass (see
+/// [`PassManager::verify_with`]), and/or capture a snapshot (i.e. a clone) of
+/// the [`Module`] after each pass (see [`PassManager::capture_snapshots`]).
+#[derive(Default)]
+pub struct PassManager<'a> {
+    passes: Vec<(String, BoxedPass<'a>)>,
+
+    verifier: Option<BoxedVerifier<'a>>,
+
+    capture_snapshots: bool,
+}
+
+type BoxedPass<'a> = Box<dyn FnMut(&mut Module) + 'a>;
+type BoxedVerifier<'a> = Box<dyn Fn(&Module) -> Result<(), String> + 'a>;
+
+/// Timing and (optional) snapshots from running a [`PassManager`].
+pub struct PassManagerReport {
+    /// Wall time taken by each pass (in the order they were run), excluding
+    /// any time spent verifying or capturing snapshots.
+    pub timings: Vec<(String, Duration)>,
+
+    /// Snapshots of the [`Module`] before any passes (named `"input"`), and
+    /// after each pass (named after the pass), if enabled (see
+    /// [`PassManager::capture_snapshots`]).
+    pub snapshots: Vec<(String, Module)>,
+}
+
+/// Error returned by [`PassManager::run`], when the verifier (see
+/// [`PassManager::verify_with`]) rejects the output of some pass.
+#[derive(Debug)]
+pub struct PassVerificationError {
+    /// Name of the pass whose output failed verification.
+    pub pass_name: String,
+
+    pub message: String,
+}
+
+impl fmt::Display for PassVerificationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "verification failed after `{}`: {}",
+            self.pass_name, self.message
+        )
+    }
+}
+
+impl std::error::Error for PassVerificationError {}
+
+impl<'a> PassManager<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a [`PassManager`] with a pipeline of general-purpose optimizations,
+    /// which also legalizes (i.e. structurizes) control-flow along the way.
+    pub fn with_default_optimizations() -> Self {
+        use crate::passes::{
+            cfg_simplify, const_fold, cse, dce, legalize, mem2reg, peephole, strength_reduce,
+            unreachable,
+        };
+
+        let mut pass_manager = Self::new();
+        pass_manager
+            .add("simplify_func_cfgs", cfg_simplify::simplify_func_cfgs)
+            .add(
+                "remove_unreachable_code",
+                unreachable::remove_unreachable_code,
+            )
+            .add("structurize_func_cfgs", legalize::structurize_func_cfgs)
+            .add("promote_vars_to_values", mem2reg::promote_vars_to_values)
+            .add("fold_consts", const_fold::fold_consts)
+            .add("apply_peephole_rules", |module: &mut Module| {
+                peephole::apply_peephole_rules(module, &peephole::PeepholeRules::default());
+            })
+            .add("reduce_strength", strength_reduce::reduce_strength)
+            .add("eliminate_common_subexprs", cse::eliminate_common_subexprs)
+            .add("remove_dead_code", dce::remove_dead_code);
+        pass_manager
+    }
+
+    /// Append `pass` (with the descriptive `name`) to the end of the pipeline.
+    pub fn add(
+        &mut self,
+        name: impl Into<String>,
+        pass: impl FnMut(&mut Module) + 'a,
+    ) -> &mut Self {
+        self.passes.push((name.into(), Box::new(pass)));
+        self
+    }
+
+    /// Run `verifier` after each pass, stopping at the first pass whose output
+    /// it rejects (see [`PassVerificationError`]).
+    pub fn verify_with<E: fmt::Display>(
+        &mut self,
+        verifier: impl Fn(&Module) -> Result<(), E> + 'a,
+    ) -> &mut Self {
+        self.verifier = Some(Box::new(move |module| {
+            verifier(module).map_err(|e| e.to_string())
+        }));
+        self
+    }
+
+    /// Whether to capture a snapshot of the [`Module`] before and after each
+    /// pass (see [`PassManagerReport::snapshots`]).
+    pub fn capture_snapshots(&mut self, capture_snapshots: bool) -> &mut Self {
+        self.capture_snapshots = capture_snapshots;
+        self
+    }
+
+    /// Run all the passes in the pipeline (in order) over `module`.
+    pub fn run(&mut self, module: &mut Module) -> Result<PassManagerReport, PassVerificationError> {
+        let mut report = PassManagerReport {
+            timings: Vec::with_capacity(self.passes.len()),
+            snapshots: vec![],
+        };
+        if self.capture_snapshots {
+            report.snapshots.push(("input".to_string(), module.clone()));
+        }
+
+        for (name, pass) in &mut self.passes {
+            let start = Instant::now();
+            pass(module);
+            report.timings.push((name.clone(), start.elapsed()));
+
+            if self.capture_snapshots {
+                report.snapshots.push((name.clone(), module.clone()));
+            }
+
+            if let Some(verifier) = &self.verifier {
+                verifier(module).map_err(|message| PassVerificationError {
+                    pass_name: name.clone(),
+                    message,
+                })?;
+            }
+        }
+
+        Ok(report)
+    }
+}
+
+impl PassManagerReport {
+    /// Total wall time taken by all the passes.
+    pub fn total_duration(&self) -> Duration {
+        self.timings.iter().map(|&(_, duration)| duration).sum()
+    }
+
+    /// All the captured snapshots, in a form that can be passed directly to
+    /// [`Plan::for_versions`](crate::print::Plan::for_versions).
+    pub fn versions(&self) -> impl Iterator<Item = (&str, &Module)> {
+        self.snapshots
+            .iter()
+            .map(|(name, module)| (&name[..], module))
+    }
+}
@@ -0,0 +1,543 @@
+//! Promotion of variables (i.e. memory) to SSA values (also known as "mem2reg").
+
+use crate::passes::dce::value_use_counts;
+use crate::passes::legalize::reachable_global_vars_and_funcs;
+use crate::transform::{InnerInPlaceTransform, ReplaceValueWith};
+use crate::visit::Visitor;
+use crate::{
+    spv, AddrSpace, AttrSet, Const, ConstCtor, ConstDef, Context, ControlNode, ControlNodeKind,
+    ControlNodeOutputDecl, ControlRegion, ControlRegionInputDecl, DataInst, DataInstKind, DeclDef,
+    ExportKey, Exportee, Func, FuncDefBody, FxIndexMap, FxIndexSet, GlobalVar, Module, Type, Value,
+};
+use rustc_hash::{FxHashMap, FxHashSet};
+use smallvec::SmallVec;
+
+/// Promote variables to SSA values, wherever they're only accessed by loading
+/// from, and storing to, the whole variable (i.e. they're not "address-taken"),
+/// replacing the loads with the last stored value, which is passed around using
+/// new `Select` outputs and `Loop` body inputs, when stored in either.
+///
+/// The variables which can be promoted are:
+/// * `Function`-storage SPIR-V `OpVariable`s, i.e. local to a function
+/// * `Private`-storage [`GlobalVar`]s only used in a single function, which is
+///   an entry-point (and not otherwise exported, or called from any function),
+///   i.e. the variable is local to every invocation of that entry-point
+///   (and is removed from the entry-point's interface [`GlobalVar`]s)
+///
+/// Only functions with fully structured control-flow are supported (i.e. after
+/// [`structurize_func_cfgs`](crate::passes::legalize::structurize_func_cfgs)).
+pub fn promote_vars_to_values(module: &mut Module) {
+    let cx = &module.cx();
+    let wk = &spv::spec::Spec::get().well_known;
+
+    let (reachable_global_vars, reachable_funcs) = reachable_global_vars_and_funcs(module);
+
+    let mut collector = GlobalVarUseCollector {
+        cx,
+        current_func: None,
+        global_var_users: FxIndexMap::default(),
+        called_funcs: FxHashSet::default(),
+    };
+    for &gv in &reachable_global_vars {
+        collector.visit_global_var_decl(&module.global_vars[gv]);
+    }
+    for &func in &reachable_funcs {
+        collector.current_func = Some(func);
+        collector.visit_func_decl(&module.funcs[func]);
+    }
+    let mut entry_points = FxHashSet::default();
+    let mut otherwise_exported_funcs = FxHashSet::default();
+    for (export_key, &exportee) in &module.exports {
+        match (export_key, exportee) {
+            (ExportKey::SpvEntryPoint { .. }, Exportee::Func(func)) => {
+                entry_points.insert(func);
+            }
+            (_, Exportee::Func(func)) => {
+                otherwise_exported_funcs.insert(func);
+            }
+            (_, Exportee::GlobalVar(gv)) => {
+                collector.global_var_users.insert(gv, None);
+            }
+        }
+    }
+
+    let mut private_vars_per_func = FxIndexMap::<_, SmallVec<[_; 4]>>::default();
+    for (&gv, &user) in &collector.global_var_users {
+        let func = match user {
+            Some(func)
+                if entry_points.contains(&func)
+                    && !otherwise_exported_funcs.contains(&func)
+                    && !collector.called_funcs.contains(&func) =>
+            {
+                func
+            }
+            _ => continue,
+        };
+        let gv_decl = &module.global_vars[gv];
+        let initializer = match &gv_decl.def {
+            DeclDef::Present(def) => def.initializer,
+            DeclDef::Imported(_) => continue,
+        };
+        if gv_decl.addr_space != AddrSpace::SpvStorageClass(wk.Private) {
+            continue;
+        }
+        let pointee_type = match gv_decl.type_of_ptr_to.pointee_type(cx) {
+            Some(ty) => ty,
+            None => continue,
+        };
+        let ptr = cx.intern(ConstDef {
+            attrs: AttrSet::default(),
+            ty: gv_decl.type_of_ptr_to,
+            ctor: ConstCtor::PtrToGlobalVar(gv),
+            ctor_args: [].into_iter().collect(),
+        });
+        let initial_value = initializer.unwrap_or_else(|| undef(cx, pointee_type));
+        private_vars_per_func
+            .entry(func)
+            .or_default()
+            .push(PrivateVar {
+                global_var: gv,
+                ptr,
+                pointee_type,
+                initial_value,
+            });
+    }
+
+    let mut promoted_global_vars = FxHashSet::default();
+    for func in reachable_funcs {
+        let func_def_body = match &mut module.funcs[func].def {
+            DeclDef::Present(func_def_body) => func_def_body,
+            DeclDef::Imported(_) => continue,
+        };
+        if func_def_body.unstructured_cfg.is_some() {
+            continue;
+        }
+        let private_vars = private_vars_per_func
+            .get(&func)
+            .map_or(&[][..], |vars| &vars[..]);
+        promoted_global_vars.extend(promote_vars_in_func(cx, func_def_body, private_vars));
+    }
+
+    if !promoted_global_vars.is_empty() {
+        module.exports = std::mem::take(&mut module.exports)
+            .into_iter()
+            .map(|(mut export_key, exportee)| {
+                if let ExportKey::SpvEntryPoint {
+                    interface_global_vars,
+                    ..
+                } = &mut export_key
+                {
+                    interface_global_vars.retain(|gv| !promoted_global_vars.contains(gv));
+                }
+                (export_key, exportee)
+            })
+            .collect();
+    }
+}
+
+/// `Private`-storage [`GlobalVar`] which may be promoted (to SSA values) in
+/// the only function using it (see [`promote_vars_to_values`]).
+#[derive(Copy, Clone)]
+struct PrivateVar {
+    global_var: GlobalVar,
+
+    /// The pointer to `global_var` (i.e. a [`ConstCtor::PtrToGlobalVar`]).
+    ptr: Const,
+
+    pointee_type: Type,
+
+    /// The value of `global_var` on entry (its initializer, or `undef`).
+    initial_value: Const,
+}
+
+fn undef(cx: &Context, ty: Type) -> Const {
+    cx.intern(ConstDef {
+        attrs: AttrSet::default(),
+        ty,
+        ctor: ConstCtor::Undef,
+        ctor_args: [].into_iter().collect(),
+    })
+}
+
+/// Promote all the `Function`-storage `OpVariable`s in `func_def_body`, and any
+/// of `private_vars`, which aren't address-taken (see [`promote_vars_to_values`]),
+/// returning the [`GlobalVar`]s (of `private_vars`) which were promoted.
+fn promote_vars_in_func(
+    cx: &Context,
+    func_def_body: &mut FuncDefBody,
+    private_vars: &[PrivateVar],
+) -> SmallVec<[GlobalVar; 4]> {
+    let wk = &spv::spec::Spec::get().well_known;
+
+    // Find all the variables, and count their uses as pointers for whole-variable
+    // loads and stores, which is compared with the total count of their uses,
+    // to determine whether they're address-taken (i.e. used in any other way).
+    let mut vars = FxIndexMap::default();
+    for var in private_vars {
+        vars.insert(Value::Const(var.ptr), var.pointee_type);
+    }
+    let mut load_store_counts = FxHashMap::<(Value, Type), usize>::default();
+    let mut regions = vec![func_def_body.body];
+    while let Some(region) = regions.pop() {
+        let func = func_def_body.at(region);
+        for func_at_control_node in func.at_children() {
+            match &func_at_control_node.def().kind {
+                ControlNodeKind::Block { insts } => {
+                    for func_at_inst in func.at(*insts) {
+                        let inst_def = func_at_inst.def();
+                        let spv_inst = match &inst_def.kind {
+                            DataInstKind::SpvInst(spv_inst) => spv_inst,
+                            _ => continue,
+                        };
+                        let is_function_storage =
+                            spv_inst.imms[..] == [spv::Imm::Short(wk.StorageClass, wk.Function)];
+                        let output_type = inst_def.output_type;
+                        if spv_inst.opcode == wk.OpVariable && is_function_storage {
+                            let ptr = Value::DataInstOutput(func_at_inst.position);
+                            if let Some(ty) = output_type.and_then(|ty| ty.pointee_type(cx)) {
+                                vars.insert(ptr, ty);
+                            }
+                            continue;
+                        }
+
+                        // NOTE(eddyb) `Volatile` is the lowest bit of `MemoryAccess`,
+                        // and other memory operands are irrelevant for variables.
+                        let is_volatile = matches!(
+                            spv_inst.imms[..],
+                            [spv::Imm::Short(_, mask), ..] if mask & 1 != 0
+                        );
+                        let (ptr, accessed_type) = match inst_def.inputs[..] {
+                            [ptr] if spv_inst.opcode == wk.OpLoad => (ptr, output_type),
+                            [ptr, value] if spv_inst.opcode == wk.OpStore => {
+                                (ptr, Some(func_def_body.at(value).type_of(cx)))
+                            }
+                            _ => continue,
+                        };
+                        if let (false, Some(ty)) = (is_volatile, accessed_type) {
+                            // NOTE(eddyb) the type is checked below, after all
+                            // the variables (and their types) are known.
+                            *load_store_counts.entry((ptr, ty)).or_default() += 1;
+                        }
+                    }
+                }
+                ControlNodeKind::Select { cases, .. } => regions.extend(cases.iter().copied()),
+                &ControlNodeKind::Loop { body, .. } => regions.push(body),
+                ControlNodeKind::ExitInvocation { .. } => {}
+            }
+        }
+    }
+    // NOTE(eddyb) loads and stores of the wrong type (i.e. not of the pointee
+    // type) aren't counted, so they also prevent promotion, just like any other
+    // use of the variable's pointer (other than as the pointer of a load/store).
+    let use_counts = value_use_counts(func_def_body);
+    vars.retain(|&ptr, &mut ty| {
+        let uses = use_counts.get(&ptr).copied().unwrap_or(0);
+        load_store_counts.get(&(ptr, ty)).copied().unwrap_or(0) == uses
+    });
+    if vars.is_empty() {
+        return SmallVec::new();
+    }
+
+    let mut promoter = Promoter {
+        cx,
+        vars,
+        replacements: FxHashMap::default(),
+        removed_insts: vec![],
+    };
+    let mut var_values = FxIndexMap::default();
+    for var in private_vars {
+        let ptr = Value::Const(var.ptr);
+        if promoter.vars.contains_key(&ptr) {
+            var_values.insert(ptr, Value::Const(var.initial_value));
+        }
+    }
+    promoter.promote_in_region(func_def_body, func_def_body.body, &mut var_values);
+
+    let Promoter {
+        vars,
+        replacements,
+        removed_insts,
+        ..
+    } = promoter;
+
+    for (block_node, inst) in removed_insts {
+        match &mut func_def_body.control_nodes[block_node].kind {
+            ControlNodeKind::Block { insts } => insts.remove(inst, &mut func_def_body.data_insts),
+            _ => unreachable!(),
+        }
+    }
+
+    if !replacements.is_empty() {
+        func_def_body.inner_in_place_transform_with(&mut ReplaceValueWith(|v| {
+            replacements.get(&v).copied()
+        }));
+    }
+
+    private_vars
+        .iter()
+        .filter(|var| vars.contains_key(&Value::Const(var.ptr)))
+        .map(|var| var.global_var)
+        .collect()
+}
+
+/// Current values of promoted variables (keyed by the pointer to the variable).
+type VarValues = FxIndexMap<Value, Value>;
+
+struct Promoter<'a> {
+    cx: &'a Context,
+
+    /// Variables being promoted, keyed by the pointer to the variable, with
+    /// the type of the variable (i.e. the pointee type) as the value.
+    vars: FxIndexMap<Value, Type>,
+
+    /// Values to replace all uses of (i.e. the outputs of promoted loads).
+    replacements: FxHashMap<Value, Value>,
+
+    /// Instructions (alongside the `Block` [`ControlNode`] they're found in)
+    /// which access promoted variables, and are to be removed.
+    removed_insts: Vec<(ControlNode, DataInst)>,
+}
+
+impl Promoter<'_> {
+    fn resolve(&self, mut v: Value) -> Value {
+        while let Some(&new) = self.replacements.get(&v) {
+            v = new;
+        }
+        v
+    }
+}
+
+impl Promoter<'_> {
+    /// Promote variables in `region`, with `var_values` being the values of the
+    /// variables on entry into `region`, and updated to their values on exit.
+    fn promote_in_region(
+        &mut self,
+        func_def_body: &mut FuncDefBody,
+        region: ControlRegion,
+        var_values: &mut VarValues,
+    ) {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        let children: SmallVec<[_; 8]> = func_def_body
+            .at(region)
+            .at_children()
+            .into_iter()
+            .map(|func_at_control_node| func_at_control_node.position)
+            .collect();
+        for control_node in children {
+            match &func_def_body.control_nodes[control_node].kind {
+                ControlNodeKind::Block { insts } => {
+                    for func_at_inst in func_def_body.at(*insts) {
+                        let inst = func_at_inst.position;
+                        let inst_def = func_at_inst.def();
+                        let spv_inst = match &inst_def.kind {
+                            DataInstKind::SpvInst(spv_inst) => spv_inst,
+                            _ => continue,
+                        };
+                        if spv_inst.opcode == wk.OpVariable {
+                            let ptr = Value::DataInstOutput(inst);
+                            if let Some(&ty) = self.vars.get(&ptr) {
+                                let initial_value = match inst_def.inputs.first() {
+                                    Some(&initializer) => self.resolve(initializer),
+                                    None => Value::Const(undef(self.cx, ty)),
+                                };
+                                var_values.insert(ptr, initial_value);
+                                self.removed_insts.push((control_node, inst));
+                            }
+                        } else if spv_inst.opcode == wk.OpLoad {
+                            let ptr = inst_def.inputs[0];
+                            if let Some(&ty) = self.vars.get(&ptr) {
+                                let value = var_values
+                                    .get(&ptr)
+                                    .copied()
+                                    .unwrap_or_else(|| Value::Const(undef(self.cx, ty)));
+                                self.replacements.insert(Value::DataInstOutput(inst), value);
+                                self.removed_insts.push((control_node, inst));
+                            }
+                        } else if spv_inst.opcode == wk.OpStore {
+                            let ptr = inst_def.inputs[0];
+                            if self.vars.contains_key(&ptr) {
+                                let value = self.resolve(inst_def.inputs[1]);
+                                var_values.insert(ptr, value);
+                                self.removed_insts.push((control_node, inst));
+                            }
+                        }
+                    }
+                }
+
+                ControlNodeKind::Select { cases, .. } => {
+                    let cases = cases.clone();
+                    if cases.is_empty() {
+                        continue;
+                    }
+
+                    let per_case_var_values: SmallVec<[_; 2]> = cases
+                        .iter()
+                        .map(|&case| {
+                            let mut case_var_values = var_values.clone();
+                            self.promote_in_region(func_def_body, case, &mut case_var_values);
+                            case_var_values
+                        })
+                        .collect();
+
+                    // NOTE(eddyb) only variables defined before the `Select`
+                    // can be used after it, so others are ignored here.
+                    for (&ptr, value) in var_values.iter_mut() {
+                        let case_values = per_case_var_values.iter().map(|vv| vv[&ptr]);
+                        let first_case_value = per_case_var_values[0][&ptr];
+                        if case_values.clone().all(|v| v == first_case_value) {
+                            *value = first_case_value;
+                            continue;
+                        }
+
+                        let outputs = &mut func_def_body.control_nodes[control_node].outputs;
+                        let output_idx = outputs.len().try_into().unwrap();
+                        outputs.push(ControlNodeOutputDecl {
+                            attrs: AttrSet::default(),
+                            ty: self.vars[&ptr],
+                        });
+                        for (&case, case_value) in cases.iter().zip(case_values) {
+                            func_def_body.control_regions[case].outputs.push(case_value);
+                        }
+                        *value = Value::ControlNodeOutput {
+                            control_node,
+                            output_idx,
+                        };
+                    }
+                }
+
+                &ControlNodeKind::Loop { body, .. } => {
+                    // Variables stored to in the loop body need to become
+                    // loop state (i.e. body inputs), to be able to observe
+                    // the values stored in the previous loop iteration.
+                    let mut stored_vars = FxIndexSet::default();
+                    self.collect_stored_vars(func_def_body, body, &mut stored_vars);
+                    stored_vars.retain(|ptr| var_values.contains_key(ptr));
+
+                    for &ptr in &stored_vars {
+                        let body_inputs = &mut func_def_body.control_regions[body].inputs;
+                        let input_idx = body_inputs.len().try_into().unwrap();
+                        body_inputs.push(ControlRegionInputDecl {
+                            attrs: AttrSet::default(),
+                            ty: self.vars[&ptr],
+                        });
+                        match &mut func_def_body.control_nodes[control_node].kind {
+                            ControlNodeKind::Loop { initial_inputs, .. } => {
+                                initial_inputs.push(var_values[&ptr]);
+                            }
+                            _ => unreachable!(),
+                        }
+                        var_values.insert(
+                            ptr,
+                            Value::ControlRegionInput {
+                                region: body,
+                                input_idx,
+                            },
+                        );
+                    }
+
+                    // NOTE(eddyb) values defined in the loop body can be used
+                    // after the loop (see also `ControlRegionDef`'s `outputs`),
+                    // so the values at the end of the body are kept afterwards.
+                    let mut body_var_values = var_values.clone();
+                    self.promote_in_region(func_def_body, body, &mut body_var_values);
+                    for &ptr in &stored_vars {
+                        func_def_body.control_regions[body]
+                            .outputs
+                            .push(body_var_values[&ptr]);
+                    }
+                    for (ptr, value) in var_values.iter_mut() {
+                        *value = body_var_values[ptr];
+                    }
+                }
+
+                ControlNodeKind::ExitInvocation { .. } => {}
+            }
+        }
+    }
+
+    /// Collect all the promoted variables stored to anywhere in `region`.
+    fn collect_stored_vars(
+        &self,
+        func_def_body: &FuncDefBody,
+        region: ControlRegion,
+        stored_vars: &mut FxIndexSet<Value>,
+    ) {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        let func = func_def_body.at(region);
+        for func_at_control_node in func.at_children() {
+            match &func_at_control_node.def().kind {
+                ControlNodeKind::Block { insts } => {
+                    for func_at_inst in func.at(*insts) {
+                        let inst_def = func_at_inst.def();
+                        if let DataInstKind::SpvInst(spv_inst) = &inst_def.kind {
+                            if spv_inst.opcode == wk.OpStore
+                                && self.vars.contains_key(&inst_def.inputs[0])
+                            {
+                                stored_vars.insert(inst_def.inputs[0]);
+                            }
+                        }
+                    }
+                }
+                ControlNodeKind::Select { cases, .. } => {
+                    for &case in cases {
+                        self.collect_stored_vars(func_def_body, case, stored_vars);
+                    }
+                }
+                &ControlNodeKind::Loop { body, .. } => {
+                    self.collect_stored_vars(func_def_body, body, stored_vars);
+                }
+                ControlNodeKind::ExitInvocation { .. } => {}
+            }
+        }
+    }
+}
+
+/// [`Visitor`] collecting, for every [`GlobalVar`] used (as a pointer constant),
+/// the only [`Func`] using it, or `None` if it's used from several places (or
+/// from outside functions, e.g. nested in other constants), and also collecting
+/// every [`Func`] which is called from anywhere.
+struct GlobalVarUseCollector<'a> {
+    cx: &'a Context,
+
+    current_func: Option<Func>,
+
+    global_var_users: FxIndexMap<GlobalVar, Option<Func>>,
+    called_funcs: FxHashSet<Func>,
+}
+
+impl GlobalVarUseCollector<'_> {
+    fn record_global_var_user(&mut self, gv: GlobalVar, user: Option<Func>) {
+        let recorded_user = self.global_var_users.entry(gv).or_insert(user);
+        if *recorded_user != user {
+            *recorded_user = None;
+        }
+    }
+}
+
+impl<'a> Visitor<'a> for GlobalVarUseCollector<'a> {
+    // NOTE(eddyb) uses of types are irrelevant here, as `GlobalVar`s can only
+    // be used (and therefore, escape) through constants.
+    fn visit_attr_set_use(&mut self, _attrs: AttrSet) {}
+    fn visit_type_use(&mut self, _ty: Type) {}
+    fn visit_const_use(&mut self, ct: Const) {
+        let ct_def = &self.cx[ct];
+        match ct_def.ctor {
+            ConstCtor::PtrToGlobalVar(gv) => self.record_global_var_user(gv, self.current_func),
+            _ => {
+                // NOTE(eddyb) any `GlobalVar` used by nested constants may
+                // escape through the outer constant, in unknown ways.
+                let current_func = self.current_func.take();
+                self.visit_const_def(ct_def);
+                self.current_func = current_func;
+            }
+        }
+    }
+    fn visit_global_var_use(&mut self, gv: GlobalVar) {
+        self.record_global_var_user(gv, None);
+    }
+    fn visit_func_use(&mut self, func: Func) {
+        self.called_funcs.insert(func);
+    }
+}
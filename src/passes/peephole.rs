@@ -0,0 +1,445 @@
+//! Peephole simplification (of individual instructions, using extensible rules).
+
+use crate::func_at::FuncAt;
+use crate::passes::legalize::reachable_funcs;
+use crate::spv::{
+    self,
+    fold::{const_splat_value, ScalarValue},
+};
+use crate::transform::{InnerInPlaceTransform, ReplaceValueWith};
+use crate::{
+    Context, ControlNodeKind, ControlRegion, DataInst, DataInstKind, DeclDef, FuncDefBody, Module,
+    Value,
+};
+use rustc_hash::FxHashMap;
+use smallvec::SmallVec;
+
+/// Simplification of an instruction, produced by a [`PeepholeRule`].
+pub enum Simplification {
+    /// Replace all uses of the instruction's output with this value (which has
+    /// to be of the same type, and defined before the instruction), and remove
+    /// the instruction (which is then expected to become unused).
+    ReplaceWith(Value),
+
+    /// Replace the instruction with a different one (with the same output type,
+    /// and the same attributes), e.g. one which is cheaper, or easier to analyze.
+    Rewrite {
+        kind: DataInstKind,
+        inputs: SmallVec<[Value; 2]>,
+    },
+}
+
+/// Peephole simplification rule, i.e. a local rewrite of a single instruction,
+/// which may inspect its inputs (including the definitions of those inputs).
+///
+/// Rules are only ever applied to instructions without side-effects (see
+/// [`DataInstDef::has_side_effects`](crate::DataInstDef::has_side_effects)),
+/// and any [`Simplification`] they produce has to preserve the instruction's
+/// semantics (e.g. `x + 0.0` can't be simplified to `x`, because of `-0.0`).
+///
+/// As rules are applied until none of them apply anymore, they must also always
+/// make progress, i.e. no [`Simplification::Rewrite`] should ever be undone by
+/// another (or the same) rule, as that would result in an infinite loop.
+///
+/// Any `Fn(&Context, FuncAt<'_, DataInst>) -> Option<Simplification>` closure
+/// can also be used as a rule (e.g. for one-off, or downstream-specific, rules).
+pub trait PeepholeRule {
+    /// Try to simplify the instruction at `func_at_inst`, returning `None` if
+    /// this rule doesn't apply to it.
+    fn simplify(&self, cx: &Context, func_at_inst: FuncAt<'_, DataInst>) -> Option<Simplification>;
+}
+
+impl<F: Fn(&Context, FuncAt<'_, DataInst>) -> Option<Simplification>> PeepholeRule for F {
+    fn simplify(&self, cx: &Context, func_at_inst: FuncAt<'_, DataInst>) -> Option<Simplification> {
+        self(cx, func_at_inst)
+    }
+}
+
+/// Set of [`PeepholeRule`]s, to apply with [`apply_peephole_rules`].
+///
+/// The [`Default`] set contains all the rules provided by this module (i.e.
+/// [`IdentityOperand`], [`DoubleNegation`], [`SelectOfConsts`] and
+/// [`RedundantBitcast`]), while [`PeepholeRules::empty`] can be used to build
+/// a set from scratch (e.g. with only custom rules).
+pub struct PeepholeRules {
+    rules: Vec<Box<dyn PeepholeRule>>,
+}
+
+impl Default for PeepholeRules {
+    fn default() -> Self {
+        let mut rules = Self::empty();
+        rules
+            .add(IdentityOperand)
+            .add(DoubleNegation)
+            .add(SelectOfConsts)
+            .add(RedundantBitcast);
+        rules
+    }
+}
+
+impl PeepholeRules {
+    pub fn empty() -> Self {
+        Self { rules: vec![] }
+    }
+
+    /// Add `rule` to this set (to be tried after all previously added rules).
+    pub fn add(&mut self, rule: impl PeepholeRule + 'static) -> &mut Self {
+        self.rules.push(Box::new(rule));
+        self
+    }
+
+    fn simplify(&self, cx: &Context, func_at_inst: FuncAt<'_, DataInst>) -> Option<Simplification> {
+        self.rules
+            .iter()
+            .find_map(|rule| rule.simplify(cx, func_at_inst))
+    }
+}
+
+/// Apply `rules` to all instructions in all function definitions in `module`,
+/// repeatedly (i.e. until no rule applies anymore), removing the instructions
+/// which are replaced with other values (see [`Simplification::ReplaceWith`]).
+///
+/// Instructions are visited in order (i.e. with definitions before uses), and
+/// always see their inputs as already simplified (i.e. with all replacements
+/// applied), so that one simplification can enable others.
+pub fn apply_peephole_rules(module: &mut Module, rules: &PeepholeRules) {
+    let cx = &module.cx();
+
+    for func in reachable_funcs(module) {
+        if let DeclDef::Present(func_def_body) = &mut module.funcs[func].def {
+            apply_peephole_rules_in_func(cx, func_def_body, rules);
+        }
+    }
+}
+
+fn apply_peephole_rules_in_func(
+    cx: &Context,
+    func_def_body: &mut FuncDefBody,
+    rules: &PeepholeRules,
+) {
+    let mut replacements = FxHashMap::default();
+    loop {
+        let regions = match &func_def_body.unstructured_cfg {
+            None => vec![func_def_body.body],
+            Some(cfg) => cfg.rev_post_order(func_def_body).collect(),
+        };
+        let mut changed = false;
+        for region in regions {
+            changed |=
+                apply_peephole_rules_in_region(cx, func_def_body, region, rules, &mut replacements);
+        }
+        if !changed {
+            break;
+        }
+    }
+
+    if replacements.is_empty() {
+        return;
+    }
+
+    func_def_body.inner_in_place_transform_with(&mut ReplaceValueWith(|v| {
+        replacements
+            .contains_key(&v)
+            .then(|| resolve(&replacements, v))
+    }));
+}
+
+// NOTE(eddyb) replacements can refer to other replaced values, but only ones
+// defined before them (i.e. dominating them), so this terminates.
+fn resolve(replacements: &FxHashMap<Value, Value>, v: Value) -> Value {
+    match replacements.get(&v) {
+        Some(&new) => resolve(replacements, new),
+        None => v,
+    }
+}
+
+/// Apply `rules` to all instructions in `region` (and any nested regions),
+/// returning `true` if any instructions were simplified.
+fn apply_peephole_rules_in_region(
+    cx: &Context,
+    func_def_body: &mut FuncDefBody,
+    region: ControlRegion,
+    rules: &PeepholeRules,
+    replacements: &mut FxHashMap<Value, Value>,
+) -> bool {
+    let mut changed = false;
+
+    let children: SmallVec<[_; 8]> = func_def_body
+        .at(region)
+        .at_children()
+        .into_iter()
+        .map(|func_at_control_node| func_at_control_node.position)
+        .collect();
+    for control_node in children {
+        let insts = match &func_def_body.at(control_node).def().kind {
+            ControlNodeKind::Block { insts } => *insts,
+            ControlNodeKind::Select { cases, .. } => {
+                for case in cases.clone() {
+                    changed |= apply_peephole_rules_in_region(
+                        cx,
+                        func_def_body,
+                        case,
+                        rules,
+                        replacements,
+                    );
+                }
+                continue;
+            }
+            &ControlNodeKind::Loop { body, .. } => {
+                changed |=
+                    apply_peephole_rules_in_region(cx, func_def_body, body, rules, replacements);
+                continue;
+            }
+            ControlNodeKind::ExitInvocation { .. } => continue,
+        };
+
+        let insts: SmallVec<[_; 8]> = func_def_body
+            .at(insts)
+            .into_iter()
+            .map(|func_at_inst| func_at_inst.position)
+            .collect();
+        for inst in insts {
+            let inst_def = &mut func_def_body.data_insts[inst];
+            for v in &mut inst_def.inputs {
+                *v = resolve(replacements, *v);
+            }
+            if inst_def.has_side_effects(cx) {
+                continue;
+            }
+
+            // NOTE(eddyb) the same instruction can be simplified repeatedly,
+            // as long as it keeps being rewritten (into other instructions).
+            while let Some(simplification) = rules.simplify(cx, func_def_body.at(inst)) {
+                changed = true;
+                match simplification {
+                    Simplification::ReplaceWith(v) => {
+                        replacements.insert(Value::DataInstOutput(inst), v);
+                        match &mut func_def_body.control_nodes[control_node].kind {
+                            ControlNodeKind::Block { insts } => {
+                                insts.remove(inst, &mut func_def_body.data_insts);
+                            }
+                            _ => unreachable!(),
+                        }
+                        break;
+                    }
+                    Simplification::Rewrite { kind, inputs } => {
+                        let inst_def = &mut func_def_body.data_insts[inst];
+                        inst_def.kind = kind;
+                        inst_def.inputs = inputs;
+                    }
+                }
+            }
+        }
+    }
+
+    changed
+}
+
+/// Get the value of `v`, if it's an ordinary scalar (or splat vector) constant.
+fn splat_value(cx: &Context, v: Value) -> Option<ScalarValue> {
+    match v {
+        Value::Const(ct) => const_splat_value(cx, ct),
+        _ => None,
+    }
+}
+
+/// Get the SPIR-V instruction (and its inputs) which defines `v`, if any.
+fn spv_inst_def(func_at: FuncAt<'_, ()>, v: Value) -> Option<(&spv::Inst, &SmallVec<[Value; 2]>)> {
+    match v {
+        Value::DataInstOutput(inst) => {
+            let inst_def = func_at.at(inst).def();
+            match &inst_def.kind {
+                DataInstKind::SpvInst(spv_inst) => Some((spv_inst, &inst_def.inputs)),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// [`PeepholeRule`] simplifying binary operations with an operand which is the
+/// identity element of the operation, e.g. `x + 0`, `x * 1`, `x & ~0`, `x - 0.0`,
+/// `x * 1.0`, or `x && true` (including component-wise vector operations).
+pub struct IdentityOperand;
+
+impl PeepholeRule for IdentityOperand {
+    fn simplify(&self, cx: &Context, func_at_inst: FuncAt<'_, DataInst>) -> Option<Simplification> {
+        use ScalarValue::{Bool, Float, Int};
+
+        let wk = &spv::spec::Spec::get().well_known;
+
+        let inst_def = func_at_inst.def();
+        let opcode = match &inst_def.kind {
+            DataInstKind::SpvInst(spv_inst) => spv_inst.opcode,
+            _ => return None,
+        };
+        let (a, b) = match inst_def.inputs[..] {
+            [a, b] => (a, b),
+            _ => return None,
+        };
+
+        let is_int = |x: Option<ScalarValue>, expected: fn(u32) -> u64| match x {
+            Some(Int { width, bits }) => bits == expected(width),
+            _ => false,
+        };
+        let zero = |_| 0;
+        let one = |_| 1;
+        let all_ones = |width| u64::MAX >> (64 - width);
+        let (a_value, b_value) = (splat_value(cx, a), splat_value(cx, b));
+
+        // NOTE(eddyb) `x + -0.0` and `x - 0.0` are the only exact floating-point
+        // additions/subtractions (as `-0.0 + 0.0` is `0.0`, not `-0.0`).
+        let is_neg_zero = |x| matches!(x, Some(Float(x)) if x == 0.0 && x.is_sign_negative());
+        let is_pos_zero = |x| matches!(x, Some(Float(x)) if x == 0.0 && x.is_sign_positive());
+        let is_float_one = |x| x == Some(Float(1.0));
+
+        let (commutative, identity): (bool, &dyn Fn(Option<ScalarValue>) -> bool) =
+            if opcode == wk.OpIAdd || opcode == wk.OpBitwiseOr || opcode == wk.OpBitwiseXor {
+                (true, &|x| is_int(x, zero))
+            } else if opcode == wk.OpISub
+                || opcode == wk.OpShiftLeftLogical
+                || opcode == wk.OpShiftRightLogical
+                || opcode == wk.OpShiftRightArithmetic
+            {
+                (false, &|x| is_int(x, zero))
+            } else if opcode == wk.OpIMul {
+                (true, &|x| is_int(x, one))
+            } else if opcode == wk.OpUDiv || opcode == wk.OpSDiv {
+                (false, &|x| is_int(x, one))
+            } else if opcode == wk.OpBitwiseAnd {
+                (true, &|x| is_int(x, all_ones))
+            } else if opcode == wk.OpFAdd {
+                (true, &is_neg_zero)
+            } else if opcode == wk.OpFSub {
+                (false, &is_pos_zero)
+            } else if opcode == wk.OpFMul {
+                (true, &is_float_one)
+            } else if opcode == wk.OpFDiv {
+                (false, &is_float_one)
+            } else if opcode == wk.OpLogicalAnd {
+                (true, &|x| x == Some(Bool(true)))
+            } else if opcode == wk.OpLogicalOr {
+                (true, &|x| x == Some(Bool(false)))
+            } else {
+                return None;
+            };
+
+        let kept = if identity(b_value) {
+            a
+        } else if commutative && identity(a_value) {
+            b
+        } else {
+            return None;
+        };
+
+        // NOTE(eddyb) the other operand may be a vector splat of a scalar operand,
+        // or of a different type (e.g. shift amounts), which can't be kept.
+        (Some(func_at_inst.at(kept).type_of(cx)) == inst_def.output_type)
+            .then_some(Simplification::ReplaceWith(kept))
+    }
+}
+
+/// [`PeepholeRule`] simplifying the negation of a negation (of the same kind),
+/// e.g. `-(-x)`, `!!x` and `~~x`, to the original value.
+pub struct DoubleNegation;
+
+impl PeepholeRule for DoubleNegation {
+    fn simplify(
+        &self,
+        _cx: &Context,
+        func_at_inst: FuncAt<'_, DataInst>,
+    ) -> Option<Simplification> {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        let inst_def = func_at_inst.def();
+        let opcode = match &inst_def.kind {
+            DataInstKind::SpvInst(spv_inst) => spv_inst.opcode,
+            _ => return None,
+        };
+        if ![wk.OpSNegate, wk.OpFNegate, wk.OpNot, wk.OpLogicalNot].contains(&opcode) {
+            return None;
+        }
+        let (input_inst, input_inputs) = spv_inst_def(func_at_inst.at(()), inst_def.inputs[0])?;
+        match input_inputs[..] {
+            [x] if input_inst.opcode == opcode => Some(Simplification::ReplaceWith(x)),
+            _ => None,
+        }
+    }
+}
+
+/// [`PeepholeRule`] simplifying `OpSelect`s where the condition is constant,
+/// both cases are the same value, or the cases are the constants `true` and
+/// `false` (i.e. the result is the condition itself, or its negation).
+pub struct SelectOfConsts;
+
+impl PeepholeRule for SelectOfConsts {
+    fn simplify(&self, cx: &Context, func_at_inst: FuncAt<'_, DataInst>) -> Option<Simplification> {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        let inst_def = func_at_inst.def();
+        match &inst_def.kind {
+            DataInstKind::SpvInst(spv_inst) if spv_inst.opcode == wk.OpSelect => {}
+            _ => return None,
+        }
+        let (cond, a, b) = match inst_def.inputs[..] {
+            [cond, a, b] => (cond, a, b),
+            _ => return None,
+        };
+
+        if a == b {
+            return Some(Simplification::ReplaceWith(a));
+        }
+        match splat_value(cx, cond) {
+            Some(ScalarValue::Bool(true)) => return Some(Simplification::ReplaceWith(a)),
+            Some(ScalarValue::Bool(false)) => return Some(Simplification::ReplaceWith(b)),
+            _ => {}
+        }
+
+        // NOTE(eddyb) a scalar condition can be used with vector cases, in
+        // which case the result can't be replaced with the condition.
+        if Some(func_at_inst.at(cond).type_of(cx)) != inst_def.output_type {
+            return None;
+        }
+        match (splat_value(cx, a), splat_value(cx, b)) {
+            (Some(ScalarValue::Bool(true)), Some(ScalarValue::Bool(false))) => {
+                Some(Simplification::ReplaceWith(cond))
+            }
+            (Some(ScalarValue::Bool(false)), Some(ScalarValue::Bool(true))) => {
+                Some(Simplification::Rewrite {
+                    kind: DataInstKind::SpvInst(wk.OpLogicalNot.into()),
+                    inputs: [cond].into_iter().collect(),
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// [`PeepholeRule`] simplifying `OpBitcast`s to the same type as their input
+/// (to the input itself), and chains of `OpBitcast`s (to a single one).
+pub struct RedundantBitcast;
+
+impl PeepholeRule for RedundantBitcast {
+    fn simplify(&self, cx: &Context, func_at_inst: FuncAt<'_, DataInst>) -> Option<Simplification> {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        let inst_def = func_at_inst.def();
+        match &inst_def.kind {
+            DataInstKind::SpvInst(spv_inst) if spv_inst.opcode == wk.OpBitcast => {}
+            _ => return None,
+        }
+        let input = inst_def.inputs[0];
+        if Some(func_at_inst.at(input).type_of(cx)) == inst_def.output_type {
+            return Some(Simplification::ReplaceWith(input));
+        }
+
+        let (input_inst, input_inputs) = spv_inst_def(func_at_inst.at(()), input)?;
+        match input_inputs[..] {
+            [original] if input_inst.opcode == wk.OpBitcast => Some(Simplification::Rewrite {
+                kind: inst_def.kind.clone(),
+                inputs: [original].into_iter().collect(),
+            }),
+            _ => None,
+        }
+    }
+}
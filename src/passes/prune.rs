@@ -0,0 +1,113 @@
+//! Pruning of module-level definitions (i.e. [`GlobalVar`]s and [`Func`]s).
+
+use crate::passes::legalize::reachable_global_vars_and_funcs;
+use crate::transform::{InnerTransform, Transformed, Transformer};
+use crate::{Const, Context, EntityDefs, Func, FxIndexMap, GlobalVar, Module, Type};
+use rustc_hash::FxHashMap;
+
+/// Remove all the [`GlobalVar`]s and [`Func`]s which aren't reachable from any
+/// of `module`'s exports (e.g. leftovers from monomorphization, or from earlier
+/// calls to [`link::minimize_exports`](crate::passes::link::minimize_exports)).
+///
+/// As [`EntityDefs`] doesn't support removing definitions, the reachable ones
+/// are moved into new [`EntityDefs`] (preserving their relative order), which
+/// replace `module.global_vars` and `module.funcs`, so all the [`GlobalVar`]s
+/// and [`Func`]s in `module` change, and any external references to them (e.g.
+/// from a clone of the `module.exports` map) are invalidated by this pass.
+pub fn prune_unreachable(module: &mut Module) {
+    let cx = &module.cx();
+
+    let (reachable_global_vars, reachable_funcs) = reachable_global_vars_and_funcs(module);
+
+    let mut new_global_vars = EntityDefs::new();
+    let remapped_global_vars: FxIndexMap<_, _> = reachable_global_vars
+        .into_iter()
+        .map(|gv| {
+            let new_gv = new_global_vars.define(cx, module.global_vars[gv].clone());
+            (gv, new_gv)
+        })
+        .collect();
+    let mut new_funcs = EntityDefs::new();
+    let remapped_funcs: FxIndexMap<_, _> = reachable_funcs
+        .into_iter()
+        .map(|func| {
+            let new_func = new_funcs.define(cx, module.funcs[func].clone());
+            (func, new_func)
+        })
+        .collect();
+
+    let mut remapper = EntityRemapper {
+        cx,
+
+        remapped_global_vars: &remapped_global_vars,
+        remapped_funcs: &remapped_funcs,
+
+        transformed_types: FxHashMap::default(),
+        transformed_consts: FxHashMap::default(),
+    };
+    for &gv in remapped_global_vars.values() {
+        remapper.in_place_transform_global_var_decl(&mut new_global_vars[gv]);
+    }
+    for &func in remapped_funcs.values() {
+        remapper.in_place_transform_func_decl(&mut new_funcs[func]);
+    }
+    module.exports = std::mem::take(&mut module.exports)
+        .into_iter()
+        .map(|(mut export_key, mut exportee)| {
+            export_key
+                .inner_transform_with(&mut remapper)
+                .apply_to(&mut export_key);
+            exportee
+                .inner_transform_with(&mut remapper)
+                .apply_to(&mut exportee);
+            (export_key, exportee)
+        })
+        .collect();
+
+    module.global_vars = new_global_vars;
+    module.funcs = new_funcs;
+}
+
+/// [`Transformer`] replacing all uses of [`GlobalVar`]s and [`Func`]s with their
+/// new counterparts (which must exist for every use, see [`prune_unreachable`]).
+struct EntityRemapper<'a> {
+    cx: &'a Context,
+
+    remapped_global_vars: &'a FxIndexMap<GlobalVar, GlobalVar>,
+    remapped_funcs: &'a FxIndexMap<Func, Func>,
+
+    // FIXME(eddyb) build some automation to avoid ever repeating these.
+    transformed_types: FxHashMap<Type, Transformed<Type>>,
+    transformed_consts: FxHashMap<Const, Transformed<Const>>,
+}
+
+impl Transformer for EntityRemapper<'_> {
+    // FIXME(eddyb) build some automation to avoid ever repeating these.
+    fn transform_type_use(&mut self, ty: Type) -> Transformed<Type> {
+        if let Some(&cached) = self.transformed_types.get(&ty) {
+            return cached;
+        }
+        let transformed = self
+            .transform_type_def(&self.cx[ty])
+            .map(|ty_def| self.cx.intern(ty_def));
+        self.transformed_types.insert(ty, transformed);
+        transformed
+    }
+    fn transform_const_use(&mut self, ct: Const) -> Transformed<Const> {
+        if let Some(&cached) = self.transformed_consts.get(&ct) {
+            return cached;
+        }
+        let transformed = self
+            .transform_const_def(&self.cx[ct])
+            .map(|ct_def| self.cx.intern(ct_def));
+        self.transformed_consts.insert(ct, transformed);
+        transformed
+    }
+
+    fn transform_global_var_use(&mut self, gv: GlobalVar) -> Transformed<GlobalVar> {
+        Transformed::Changed(self.remapped_global_vars[&gv])
+    }
+    fn transform_func_use(&mut self, func: Func) -> Transformed<Func> {
+        Transformed::Changed(self.remapped_funcs[&func])
+    }
+}
@@ -0,0 +1,293 @@
+//! Relaxed precision lowering (i.e. of `RelaxedPrecision` floating-point
+//! operations to genuine 16-bit ones).
+
+use crate::passes::legalize::reachable_funcs;
+use crate::spv::fold::{eval_spv_inst, vector_type};
+use crate::spv::{self, spec::Opcode};
+use crate::{
+    Attr, AttrSet, Context, ControlNodeKind, ControlRegion, DataInst, DataInstDef, DataInstKind,
+    DeclDef, EntityList, FuncDefBody, Module, ModuleDialect, Type, TypeCtor, TypeCtorArg,
+    TypeDef, Value,
+};
+use rustc_hash::FxHashMap;
+use smallvec::SmallVec;
+use std::mem;
+
+/// Options for relaxed precision lowering (see [`lower_relaxed_precision`]).
+#[derive(Clone, Default)]
+pub struct RelaxedPrecisionOptions {
+    /// Whether to add the `Float16` capability to the module, if missing
+    /// (otherwise, modules without it are left unchanged).
+    pub add_float16_capability: bool,
+}
+
+/// Lower all (componentwise) 32-bit floating-point arithmetic operations which
+/// are decorated with `RelaxedPrecision`, in all function definitions in `module`,
+/// to 16-bit floating-point ones, with `OpFConvert`s inserted at the boundaries
+/// (i.e. for any 32-bit inputs, and for the results, for any 32-bit users).
+///
+/// Chains of relaxed operations are converted as a whole (i.e. without any
+/// round-trips through 32-bit in between), as every result is available in
+/// both 16-bit (used by other relaxed operations) and 32-bit (used by anything
+/// else, and left for [`dce`](crate::passes::dce) to remove, if unused) forms.
+///
+/// This is only done if the module (already, or by [`RelaxedPrecisionOptions`])
+/// has the `Float16` capability, and can be a significant performance win on
+/// (mostly mobile) GPUs which otherwise ignore `RelaxedPrecision`.
+pub fn lower_relaxed_precision(module: &mut Module, options: &RelaxedPrecisionOptions) {
+    let wk = &spv::spec::Spec::get().well_known;
+    let cx = &module.cx();
+
+    match &mut module.dialect {
+        ModuleDialect::Spv(dialect) => {
+            if !dialect.capabilities.contains(&wk.Float16) {
+                if !options.add_float16_capability {
+                    return;
+                }
+                dialect.capabilities.insert(wk.Float16);
+            }
+        }
+    }
+
+    for func in reachable_funcs(module) {
+        if let DeclDef::Present(func_def_body) = &mut module.funcs[func].def {
+            let regions = match &func_def_body.unstructured_cfg {
+                None => vec![func_def_body.body],
+                Some(cfg) => cfg.rev_post_order(func_def_body).collect(),
+            };
+
+            let mut lowerer = RelaxedPrecisionLowerer {
+                cx,
+                func_def_body,
+                f16_types: FxHashMap::default(),
+                f16_values: FxHashMap::default(),
+            };
+            for region in regions {
+                lowerer.lower_in_region(region);
+            }
+        }
+    }
+}
+
+/// Get whether `opcode` is a componentwise floating-point arithmetic operation
+/// which can be performed with 16-bit floats instead of 32-bit ones.
+fn is_lowerable_op(opcode: Opcode) -> bool {
+    let wk = &spv::spec::Spec::get().well_known;
+
+    [
+        wk.OpFNegate,
+        wk.OpFAdd,
+        wk.OpFSub,
+        wk.OpFMul,
+        wk.OpFDiv,
+        wk.OpFRem,
+        wk.OpFMod,
+    ]
+    .contains(&opcode)
+}
+
+struct RelaxedPrecisionLowerer<'a> {
+    cx: &'a Context,
+    func_def_body: &'a mut FuncDefBody,
+
+    /// Cached 16-bit equivalents of 32-bit floating-point (scalar or vector) types.
+    f16_types: FxHashMap<Type, Option<Type>>,
+
+    /// 16-bit versions of the results of lowered instructions (whose original
+    /// [`DataInst`]s now convert those 16-bit results back to 32-bit).
+    f16_values: FxHashMap<DataInst, Value>,
+}
+
+impl RelaxedPrecisionLowerer<'_> {
+    fn lower_in_region(&mut self, region: ControlRegion) {
+        let children: SmallVec<[_; 8]> = self
+            .func_def_body
+            .at(region)
+            .at_children()
+            .into_iter()
+            .map(|func_at_control_node| func_at_control_node.position)
+            .collect();
+        for control_node in children {
+            let mut old_insts = match &mut self.func_def_body.control_nodes[control_node].kind {
+                ControlNodeKind::Block { insts } => mem::take(insts),
+                ControlNodeKind::Select { cases, .. } => {
+                    for case in cases.clone() {
+                        self.lower_in_region(case);
+                    }
+                    continue;
+                }
+                &mut ControlNodeKind::Loop { body, .. } => {
+                    self.lower_in_region(body);
+                    continue;
+                }
+                ControlNodeKind::ExitInvocation { .. } => continue,
+            };
+
+            // NOTE(eddyb) as `EntityList` doesn't support inserting before a node,
+            // the whole list of instructions is rebuilt (see also `passes::strength_reduce`).
+            let original_insts: SmallVec<[_; 8]> = self
+                .func_def_body
+                .at(old_insts)
+                .into_iter()
+                .map(|func_at_inst| func_at_inst.position)
+                .collect();
+            let mut new_insts = EntityList::empty();
+            for inst in original_insts {
+                old_insts.remove(inst, &mut self.func_def_body.data_insts);
+                self.try_lower_inst(inst, &mut new_insts);
+                new_insts.insert_last(inst, &mut self.func_def_body.data_insts);
+            }
+            match &mut self.func_def_body.control_nodes[control_node].kind {
+                ControlNodeKind::Block { insts } => *insts = new_insts,
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    /// Lower `inst` to 16-bit floats, if it's a relaxed precision operation,
+    /// with all the new instructions (including the 16-bit operation itself)
+    /// appended to `new_insts`, and `inst` replaced with an `OpFConvert` of the
+    /// 16-bit result back to 32-bit.
+    fn try_lower_inst(&mut self, inst: DataInst, new_insts: &mut EntityList<DataInst>) {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        let inst_def = &self.func_def_body.data_insts[inst];
+        let spv_inst = match &inst_def.kind {
+            DataInstKind::SpvInst(spv_inst) if is_lowerable_op(spv_inst.opcode) => spv_inst,
+            _ => return,
+        };
+        let is_relaxed = self.cx[inst_def.attrs].attrs.iter().any(|attr| match attr {
+            Attr::SpvAnnotation(spv::Inst { opcode, imms }) => {
+                *opcode == wk.OpDecorate
+                    && imms[..] == [spv::Imm::Short(wk.Decoration, wk.RelaxedPrecision)]
+            }
+            _ => false,
+        });
+        if !is_relaxed {
+            return;
+        }
+        let output_type = match inst_def.output_type {
+            Some(ty) => ty,
+            None => return,
+        };
+        let spv_inst = spv_inst.clone();
+        let inputs = inst_def.inputs.clone();
+        let debug_locations = AttrSet::default().with_debug_locations_from(self.cx, inst_def.attrs);
+
+        let f16_output_type = match self.f16_type(output_type) {
+            Some(ty) => ty,
+            None => return,
+        };
+
+        // All inputs must have the same (32-bit float) type as the output.
+        if !inputs
+            .iter()
+            .all(|&input| self.func_def_body.at(input).type_of(self.cx) == output_type)
+        {
+            return;
+        }
+
+        let f16_inputs = inputs
+            .into_iter()
+            .map(|input| match input {
+                Value::DataInstOutput(input_inst) if self.f16_values.contains_key(&input_inst) => {
+                    self.f16_values[&input_inst]
+                }
+                Value::Const(ct) => {
+                    match eval_spv_inst(self.cx, &wk.OpFConvert.into(), f16_output_type, &[ct]) {
+                        Some(f16_ct) => Value::Const(f16_ct),
+                        None => {
+                            self.push_fconvert(debug_locations, new_insts, input, f16_output_type)
+                        }
+                    }
+                }
+                _ => self.push_fconvert(debug_locations, new_insts, input, f16_output_type),
+            })
+            .collect();
+
+        let f16_inst = self.func_def_body.data_insts.define(
+            self.cx,
+            DataInstDef {
+                attrs: debug_locations,
+                kind: DataInstKind::SpvInst(spv_inst),
+                output_type: Some(f16_output_type),
+                inputs: f16_inputs,
+            }
+            .into(),
+        );
+        new_insts.insert_last(f16_inst, &mut self.func_def_body.data_insts);
+        self.f16_values.insert(inst, Value::DataInstOutput(f16_inst));
+
+        // NOTE(eddyb) the original instruction is reused to convert the 16-bit
+        // result back to 32-bit, so that none of its uses need to change.
+        let inst_def = &mut self.func_def_body.data_insts[inst];
+        inst_def.kind = DataInstKind::SpvInst(wk.OpFConvert.into());
+        inst_def.inputs = [Value::DataInstOutput(f16_inst)].into_iter().collect();
+    }
+
+    fn push_fconvert(
+        &mut self,
+        attrs: AttrSet,
+        insts: &mut EntityList<DataInst>,
+        input: Value,
+        output_type: Type,
+    ) -> Value {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        let inst = self.func_def_body.data_insts.define(
+            self.cx,
+            DataInstDef {
+                attrs,
+                kind: DataInstKind::SpvInst(wk.OpFConvert.into()),
+                output_type: Some(output_type),
+                inputs: [input].into_iter().collect(),
+            }
+            .into(),
+        );
+        insts.insert_last(inst, &mut self.func_def_body.data_insts);
+        Value::DataInstOutput(inst)
+    }
+
+    /// Get the 16-bit equivalent of `ty`, if it's a 32-bit floating-point type
+    /// (or a vector of them).
+    fn f16_type(&mut self, ty: Type) -> Option<Type> {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        if let Some(&cached) = self.f16_types.get(&ty) {
+            return cached;
+        }
+        let cx = self.cx;
+        let ty_def = &cx[ty];
+        let f16_type = match vector_type(cx, ty) {
+            Some((elem_type, _)) => self.f16_type(elem_type).map(|f16_elem_type| {
+                cx.intern(TypeDef {
+                    attrs: ty_def.attrs,
+                    ctor: ty_def.ctor.clone(),
+                    ctor_args: [TypeCtorArg::Type(f16_elem_type)].into_iter().collect(),
+                })
+            }),
+            None => match &ty_def.ctor {
+                TypeCtor::SpvInst(inst)
+                    if inst.opcode == wk.OpTypeFloat
+                        && inst.imms[..] == [spv::Imm::Short(wk.LiteralInteger, 32)] =>
+                {
+                    Some(
+                        cx.intern(TypeDef {
+                            attrs: ty_def.attrs,
+                            ctor: TypeCtor::SpvInst(spv::Inst {
+                                opcode: wk.OpTypeFloat,
+                                imms: [spv::Imm::Short(wk.LiteralInteger, 16)]
+                                    .into_iter()
+                                    .collect(),
+                            }),
+                            ctor_args: [].into_iter().collect(),
+                        }),
+                    )
+                }
+                _ => None,
+            },
+        };
+        self.f16_types.insert(ty, f16_type);
+        f16_type
+    }
+}
@@ -0,0 +1,704 @@
+//! Scalarization (i.e. splitting vector operations into per-component ones).
+
+use crate::passes::legalize::reachable_funcs;
+use crate::spv::{
+    self,
+    fold::{composite_elements, vector_type},
+};
+use crate::transform::{InnerInPlaceTransform, ReplaceValueWith};
+use crate::{
+    AttrSet, Context, ControlNode, ControlNodeDef, ControlNodeKind, ControlNodeOutputDecl,
+    ControlRegion, ControlRegionInputDecl, DataInst, DataInstDef, DataInstKind, DeclDef,
+    EntityList, FuncDefBody, Module, Type, Value,
+};
+use rustc_hash::FxHashMap;
+use smallvec::SmallVec;
+use std::mem;
+
+/// Options for scalarization (see [`scalarize_vectors`]).
+#[derive(Clone)]
+pub struct ScalarizeOptions {
+    /// Whether to scalarize all (supported) vector operations, even those whose
+    /// inputs don't already have their components available (e.g. from an
+    /// `OpCompositeConstruct`), which requires extracting those components
+    /// (with `OpCompositeExtract`), and is mostly useful for targets which
+    /// require (or heavily prefer) scalar operations.
+    pub scalarize_all_ops: bool,
+
+    /// Whether to also split vector-typed `Select` outputs and `Loop` state
+    /// (i.e. region inputs/outputs) into one (scalar) value per component.
+    pub scalarize_region_values: bool,
+}
+
+impl Default for ScalarizeOptions {
+    fn default() -> Self {
+        Self {
+            scalarize_all_ops: false,
+            scalarize_region_values: true,
+        }
+    }
+}
+
+/// Scalarize vector operations (within the limits of `options`) in all function
+/// definitions in `module`, i.e. replace:
+/// * componentwise operations on vectors (e.g. `OpFAdd`, `OpIEqual`, `OpSelect`)
+///   with one operation per component, combined by `OpCompositeConstruct`
+/// * `OpCompositeInsert`, `OpVectorShuffle` and `OpCompositeConstruct` (from
+///   smaller vectors) with `OpCompositeConstruct` directly from the components
+/// * `OpCompositeExtract` (of one component) with the extracted component
+/// * vector `Select` outputs and `Loop` state with one value per component
+///   (if [`ScalarizeOptions::scalarize_region_values`] is enabled)
+///
+/// Vector operations are only scalarized when the components of all of their
+/// inputs are already available (e.g. from `OpCompositeConstruct`, or vector
+/// constants), unless [`ScalarizeOptions::scalarize_all_ops`] is enabled, so
+/// that (by default) chains of vector operations only get scalarized when that
+/// removes the need to build (and then take apart) intermediary vectors.
+///
+/// Any `OpCompositeConstruct`s (and `OpCompositeExtract`s) which become unused
+/// are left in place, for [`dce`](crate::passes::dce) to remove.
+pub fn scalarize_vectors(module: &mut Module, options: &ScalarizeOptions) {
+    let cx = &module.cx();
+
+    for func in reachable_funcs(module) {
+        if let DeclDef::Present(func_def_body) = &mut module.funcs[func].def {
+            let mut regions = vec![func_def_body.body];
+            if let Some(cfg) = &func_def_body.unstructured_cfg {
+                regions.extend(
+                    cfg.rev_post_order(func_def_body)
+                        .filter(|&region| region != func_def_body.body),
+                );
+            }
+
+            let mut scalarizer = Scalarizer {
+                cx,
+                options,
+                func_def_body,
+                replacements: FxHashMap::default(),
+            };
+            if options.scalarize_region_values {
+                scalarizer.scalarize_region_values(&regions);
+            }
+            for &region in &regions {
+                scalarizer.scalarize_insts_in_region(region);
+            }
+
+            let Scalarizer {
+                func_def_body,
+                replacements,
+                ..
+            } = scalarizer;
+            if !replacements.is_empty() {
+                func_def_body.inner_in_place_transform_with(&mut ReplaceValueWith(|mut v| {
+                    let original = v;
+                    while let Some(&new) = replacements.get(&v) {
+                        v = new;
+                    }
+                    (v != original).then_some(v)
+                }));
+            }
+        }
+    }
+}
+
+/// Whether `opcode` applies independently to each component of its (vector)
+/// inputs, producing the respective component of its (vector) output.
+pub(crate) fn is_componentwise(opcode: spv::spec::Opcode) -> bool {
+    let wk = &spv::spec::Spec::get().well_known;
+
+    [
+        wk.OpSConvert,
+        wk.OpUConvert,
+        wk.OpFConvert,
+        wk.OpConvertFToU,
+        wk.OpConvertFToS,
+        wk.OpConvertSToF,
+        wk.OpConvertUToF,
+        wk.OpBitcast,
+        wk.OpSNegate,
+        wk.OpNot,
+        wk.OpIAdd,
+        wk.OpISub,
+        wk.OpIMul,
+        wk.OpUDiv,
+        wk.OpSDiv,
+        wk.OpUMod,
+        wk.OpSRem,
+        wk.OpSMod,
+        wk.OpShiftRightLogical,
+        wk.OpShiftRightArithmetic,
+        wk.OpShiftLeftLogical,
+        wk.OpBitwiseOr,
+        wk.OpBitwiseXor,
+        wk.OpBitwiseAnd,
+        wk.OpLogicalEqual,
+        wk.OpLogicalNotEqual,
+        wk.OpLogicalOr,
+        wk.OpLogicalAnd,
+        wk.OpLogicalNot,
+        wk.OpSelect,
+        wk.OpIEqual,
+        wk.OpINotEqual,
+        wk.OpUGreaterThan,
+        wk.OpSGreaterThan,
+        wk.OpUGreaterThanEqual,
+        wk.OpSGreaterThanEqual,
+        wk.OpULessThan,
+        wk.OpSLessThan,
+        wk.OpULessThanEqual,
+        wk.OpSLessThanEqual,
+        wk.OpFNegate,
+        wk.OpFAdd,
+        wk.OpFSub,
+        wk.OpFMul,
+        wk.OpFDiv,
+        wk.OpFRem,
+        wk.OpFMod,
+        wk.OpFOrdEqual,
+        wk.OpFUnordEqual,
+        wk.OpFOrdNotEqual,
+        wk.OpFUnordNotEqual,
+        wk.OpFOrdLessThan,
+        wk.OpFUnordLessThan,
+        wk.OpFOrdGreaterThan,
+        wk.OpFUnordGreaterThan,
+        wk.OpFOrdLessThanEqual,
+        wk.OpFUnordLessThanEqual,
+        wk.OpFOrdGreaterThanEqual,
+        wk.OpFUnordGreaterThanEqual,
+    ]
+    .contains(&opcode)
+}
+
+struct Scalarizer<'a> {
+    cx: &'a Context,
+    options: &'a ScalarizeOptions,
+    func_def_body: &'a mut FuncDefBody,
+
+    /// Outputs of `OpCompositeExtract`s, mapped to the components they extract.
+    replacements: FxHashMap<Value, Value>,
+}
+
+impl Scalarizer<'_> {
+    fn resolve(&self, mut v: Value) -> Value {
+        while let Some(&new) = self.replacements.get(&v) {
+            v = new;
+        }
+        v
+    }
+
+    /// Get the component type and count of `v`, if it's a vector.
+    fn vector_type_of(&self, v: Value) -> Option<(Type, u32)> {
+        vector_type(self.cx, self.func_def_body.at(v).type_of(self.cx))
+    }
+
+    /// Get the components of the vector `v`, if they're already available.
+    fn components(&self, v: Value) -> Option<SmallVec<[Value; 4]>> {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        let v = self.resolve(v);
+        let (_, count) = self.vector_type_of(v)?;
+        let components: SmallVec<[_; 4]> = match v {
+            Value::Const(ct) => composite_elements(self.cx, ct)?
+                .into_iter()
+                .map(Value::Const)
+                .collect(),
+            Value::DataInstOutput(inst) => {
+                let inst_def = &self.func_def_body.data_insts[inst];
+                match &inst_def.kind {
+                    DataInstKind::SpvInst(spv_inst)
+                        if spv_inst.opcode == wk.OpCompositeConstruct =>
+                    {
+                        inst_def.inputs.iter().map(|&v| self.resolve(v)).collect()
+                    }
+                    _ => return None,
+                }
+            }
+            _ => return None,
+        };
+        (components.len() == count as usize).then_some(components)
+    }
+
+    /// Get the components of the vector `v`, extracting them (using new
+    /// instructions, added to `new_insts`) if they're not already available.
+    fn components_or_extract(
+        &mut self,
+        v: Value,
+        source_attrs: AttrSet,
+        new_insts: &mut Vec<DataInst>,
+    ) -> SmallVec<[Value; 4]> {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        if let Some(components) = self.components(v) {
+            return components;
+        }
+        let (component_type, count) = self.vector_type_of(v).unwrap();
+        (0..count)
+            .map(|i| {
+                self.new_inst(
+                    source_attrs,
+                    wk.OpCompositeExtract,
+                    [spv::Imm::Short(wk.LiteralInteger, i)],
+                    [v],
+                    component_type,
+                    new_insts,
+                )
+            })
+            .collect()
+    }
+
+    /// Create a new instruction (added to `new_insts`), returning its output,
+    /// with the source locations of the instruction it was created for (if any),
+    /// i.e. the one with `source_attrs` as its attributes.
+    fn new_inst(
+        &mut self,
+        source_attrs: AttrSet,
+        opcode: spv::spec::Opcode,
+        imms: impl IntoIterator<Item = spv::Imm>,
+        inputs: impl IntoIterator<Item = Value>,
+        output_type: Type,
+        new_insts: &mut Vec<DataInst>,
+    ) -> Value {
+        let inst = self.func_def_body.data_insts.define(
+            self.cx,
+            DataInstDef {
+                attrs: AttrSet::default().with_debug_locations_from(self.cx, source_attrs),
+                kind: DataInstKind::SpvInst(spv::Inst {
+                    opcode,
+                    imms: imms.into_iter().collect(),
+                }),
+                output_type: Some(output_type),
+                inputs: inputs.into_iter().collect(),
+            }
+            .into(),
+        );
+        new_insts.push(inst);
+        Value::DataInstOutput(inst)
+    }
+
+    /// Create a new `Block` containing `new_insts`, if there are any.
+    fn new_block(&mut self, new_insts: Vec<DataInst>) -> Option<ControlNode> {
+        if new_insts.is_empty() {
+            return None;
+        }
+        let mut insts = EntityList::empty();
+        for inst in new_insts {
+            insts.insert_last(inst, &mut self.func_def_body.data_insts);
+        }
+        Some(
+            self.func_def_body.control_nodes.define(
+                self.cx,
+                ControlNodeDef {
+                    attrs: AttrSet::default(),
+                    kind: ControlNodeKind::Block { insts },
+                    outputs: [].into_iter().collect(),
+                }
+                .into(),
+            ),
+        )
+    }
+
+    fn collect_selects_and_loops(
+        &self,
+        region: ControlRegion,
+        nodes: &mut Vec<(ControlRegion, ControlNode)>,
+    ) {
+        for func_at_control_node in self.func_def_body.at(region).at_children() {
+            let control_node = func_at_control_node.position;
+            match &func_at_control_node.def().kind {
+                ControlNodeKind::Select { cases, .. } => {
+                    nodes.push((region, control_node));
+                    for &case in cases {
+                        self.collect_selects_and_loops(case, nodes);
+                    }
+                }
+                &ControlNodeKind::Loop { body, .. } => {
+                    nodes.push((region, control_node));
+                    self.collect_selects_and_loops(body, nodes);
+                }
+                ControlNodeKind::Block { .. } | ControlNodeKind::ExitInvocation { .. } => {}
+            }
+        }
+    }
+
+    /// Split all vector `Select` outputs and `Loop` state into one value per
+    /// component, with an `OpCompositeConstruct` replacing each original value.
+    fn scalarize_region_values(&mut self, regions: &[ControlRegion]) {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        let mut nodes = vec![];
+        for &region in regions {
+            self.collect_selects_and_loops(region, &mut nodes);
+        }
+
+        // NOTE(eddyb) all the `OpCompositeConstruct`s are created (but not yet
+        // attached) first, so that all uses of the original values can then be
+        // replaced at once, without affecting the new values (which would be
+        // ambiguous, as the indices of region inputs/outputs are shifted).
+        let mut old_to_new = FxHashMap::<Value, Value>::default();
+        let mut split_nodes = vec![];
+        for (parent_region, control_node) in nodes {
+            let control_node_def = &self.func_def_body.control_nodes[control_node];
+            let (old_decls, value_at): (SmallVec<[_; 4]>, Box<dyn Fn(u32) -> Value>) =
+                match control_node_def.kind {
+                    ControlNodeKind::Select { .. } => (
+                        control_node_def
+                            .outputs
+                            .iter()
+                            .map(|decl| (decl.attrs, decl.ty))
+                            .collect(),
+                        Box::new(move |output_idx| Value::ControlNodeOutput {
+                            control_node,
+                            output_idx,
+                        }),
+                    ),
+                    ControlNodeKind::Loop { body, .. } => (
+                        self.func_def_body.control_regions[body]
+                            .inputs
+                            .iter()
+                            .map(|decl| (decl.attrs, decl.ty))
+                            .collect(),
+                        Box::new(move |input_idx| Value::ControlRegionInput {
+                            region: body,
+                            input_idx,
+                        }),
+                    ),
+                    _ => unreachable!(),
+                };
+            if !old_decls
+                .iter()
+                .any(|&(_, ty)| vector_type(self.cx, ty).is_some())
+            {
+                continue;
+            }
+
+            let mut new_decls = SmallVec::<[_; 4]>::new();
+            let mut is_split = SmallVec::<[_; 4]>::new();
+            let mut constructs = vec![];
+            for (old_idx, (attrs, ty)) in (0..).zip(old_decls) {
+                let new_idx = u32::try_from(new_decls.len()).unwrap();
+                let new_value = match vector_type(self.cx, ty) {
+                    Some((component_type, count)) => {
+                        new_decls.extend((0..count).map(|_| (AttrSet::default(), component_type)));
+                        let construct = self.new_inst(
+                            AttrSet::default(),
+                            wk.OpCompositeConstruct,
+                            [],
+                            (new_idx..new_idx + count).map(&value_at),
+                            ty,
+                            &mut constructs,
+                        );
+                        // NOTE(eddyb) any attributes (e.g. debuginfo) of the
+                        // original value are kept on its replacement.
+                        self.func_def_body.data_insts[*constructs.last().unwrap()].attrs = attrs;
+                        Some(construct)
+                    }
+                    None => {
+                        new_decls.push((attrs, ty));
+                        (new_idx != old_idx).then(|| value_at(new_idx))
+                    }
+                };
+                is_split.push(vector_type(self.cx, ty).is_some());
+                if let Some(new_value) = new_value {
+                    old_to_new.insert(value_at(old_idx), new_value);
+                }
+            }
+            split_nodes.push((parent_region, control_node, new_decls, is_split, constructs));
+        }
+        if split_nodes.is_empty() {
+            return;
+        }
+
+        self.func_def_body
+            .inner_in_place_transform_with(&mut ReplaceValueWith(|v| old_to_new.get(&v).copied()));
+
+        for (parent_region, control_node, new_decls, is_split, constructs) in split_nodes {
+            let construct_block = self.new_block(constructs).unwrap();
+            match self.func_def_body.control_nodes[control_node].kind.clone() {
+                ControlNodeKind::Select { cases, .. } => {
+                    self.func_def_body.control_nodes[control_node].outputs = new_decls
+                        .into_iter()
+                        .map(|(attrs, ty)| ControlNodeOutputDecl { attrs, ty })
+                        .collect();
+                    for case in cases {
+                        let old_outputs =
+                            mem::take(&mut self.func_def_body.control_regions[case].outputs);
+                        let mut new_insts = vec![];
+                        let new_outputs =
+                            self.split_values(&old_outputs, &is_split, &mut new_insts);
+                        self.func_def_body.control_regions[case].outputs = new_outputs;
+                        if let Some(block) = self.new_block(new_insts) {
+                            self.append_to_region(case, block);
+                        }
+                    }
+                    self.insert_after(parent_region, control_node, construct_block);
+                }
+                ControlNodeKind::Loop {
+                    initial_inputs,
+                    body,
+                    ..
+                } => {
+                    self.func_def_body.control_regions[body].inputs = new_decls
+                        .into_iter()
+                        .map(|(attrs, ty)| ControlRegionInputDecl { attrs, ty })
+                        .collect();
+
+                    let mut new_insts = vec![];
+                    let new_initial_inputs =
+                        self.split_values(&initial_inputs, &is_split, &mut new_insts);
+                    match &mut self.func_def_body.control_nodes[control_node].kind {
+                        ControlNodeKind::Loop { initial_inputs, .. } => {
+                            *initial_inputs = new_initial_inputs;
+                        }
+                        _ => unreachable!(),
+                    }
+                    if let Some(block) = self.new_block(new_insts) {
+                        self.insert_before(parent_region, control_node, block);
+                    }
+
+                    let old_outputs =
+                        mem::take(&mut self.func_def_body.control_regions[body].outputs);
+                    let mut new_insts = vec![];
+                    let new_outputs = self.split_values(&old_outputs, &is_split, &mut new_insts);
+                    self.func_def_body.control_regions[body].outputs = new_outputs;
+                    if let Some(block) = self.new_block(new_insts) {
+                        self.append_to_region(body, block);
+                    }
+
+                    self.func_def_body.control_regions[body]
+                        .children
+                        .insert_first(construct_block, &mut self.func_def_body.control_nodes);
+                }
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    /// Replace every value in `values` which `is_split` with its components.
+    fn split_values(
+        &mut self,
+        values: &[Value],
+        is_split: &[bool],
+        new_insts: &mut Vec<DataInst>,
+    ) -> SmallVec<[Value; 2]> {
+        let mut new_values = SmallVec::new();
+        for (&v, &split) in values.iter().zip(is_split) {
+            if split {
+                new_values.extend(self.components_or_extract(v, AttrSet::default(), new_insts));
+            } else {
+                new_values.push(v);
+            }
+        }
+        new_values
+    }
+
+    /// Add `new_node` at the end of `region` (but before any `ExitInvocation`,
+    /// which has to always be last).
+    fn append_to_region(&mut self, region: ControlRegion, new_node: ControlNode) {
+        let last_node = self.func_def_body.control_regions[region]
+            .children
+            .iter()
+            .last;
+        match last_node {
+            Some(last_node)
+                if matches!(
+                    self.func_def_body.control_nodes[last_node].kind,
+                    ControlNodeKind::ExitInvocation { .. }
+                ) =>
+            {
+                self.insert_before(region, last_node, new_node);
+            }
+            _ => self.func_def_body.control_regions[region]
+                .children
+                .insert_last(new_node, &mut self.func_def_body.control_nodes),
+        }
+    }
+
+    fn insert_after(
+        &mut self,
+        region: ControlRegion,
+        prev_node: ControlNode,
+        new_node: ControlNode,
+    ) {
+        match self.func_def_body.control_nodes[prev_node].next_in_list() {
+            Some(next_node) => self.insert_before(region, next_node, new_node),
+            None => self.func_def_body.control_regions[region]
+                .children
+                .insert_last(new_node, &mut self.func_def_body.control_nodes),
+        }
+    }
+
+    fn insert_before(
+        &mut self,
+        region: ControlRegion,
+        next_node: ControlNode,
+        new_node: ControlNode,
+    ) {
+        let control_nodes = &mut self.func_def_body.control_nodes;
+        let children = &mut self.func_def_body.control_regions[region].children;
+
+        // FIXME(eddyb) `EntityList` should have a way to insert in the middle.
+        let mut following_nodes = EntityList::empty();
+        while let Some(next) = control_nodes[next_node].next_in_list() {
+            children.remove(next, control_nodes);
+            following_nodes.insert_last(next, control_nodes);
+        }
+        children.remove(next_node, control_nodes);
+        children.insert_last(new_node, control_nodes);
+        children.insert_last(next_node, control_nodes);
+        children.append(following_nodes, control_nodes);
+    }
+
+    fn scalarize_insts_in_region(&mut self, region: ControlRegion) {
+        let children: SmallVec<[_; 8]> = self
+            .func_def_body
+            .at(region)
+            .at_children()
+            .into_iter()
+            .map(|func_at_control_node| func_at_control_node.position)
+            .collect();
+        for control_node in children {
+            let mut old_insts = match &mut self.func_def_body.control_nodes[control_node].kind {
+                ControlNodeKind::Block { insts } => mem::take(insts),
+                ControlNodeKind::Select { cases, .. } => {
+                    for case in cases.clone() {
+                        self.scalarize_insts_in_region(case);
+                    }
+                    continue;
+                }
+                &mut ControlNodeKind::Loop { body, .. } => {
+                    self.scalarize_insts_in_region(body);
+                    continue;
+                }
+                ControlNodeKind::ExitInvocation { .. } => continue,
+            };
+
+            // NOTE(eddyb) as `EntityList` doesn't support inserting before a
+            // node, the whole list of instructions is rebuilt (see also
+            // `passes::strength_reduce`).
+            let original_insts: SmallVec<[_; 8]> = self
+                .func_def_body
+                .at(old_insts)
+                .into_iter()
+                .map(|func_at_inst| func_at_inst.position)
+                .collect();
+            let mut insts = EntityList::empty();
+            for inst in original_insts {
+                old_insts.remove(inst, &mut self.func_def_body.data_insts);
+
+                let mut new_insts = vec![];
+                self.scalarize_inst(inst, &mut new_insts);
+                for new_inst in new_insts.into_iter().chain([inst]) {
+                    insts.insert_last(new_inst, &mut self.func_def_body.data_insts);
+                }
+            }
+            match &mut self.func_def_body.control_nodes[control_node].kind {
+                ControlNodeKind::Block { insts: block_insts } => *block_insts = insts,
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    /// Scalarize `inst`, if possible, by replacing it in-place with an
+    /// `OpCompositeConstruct` (with any new instructions added to `new_insts`),
+    /// or (for `OpCompositeExtract`) by replacing all uses of its output.
+    fn scalarize_inst(&mut self, inst: DataInst, new_insts: &mut Vec<DataInst>) -> Option<()> {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        let inst_def = &self.func_def_body.data_insts[inst];
+        let spv_inst = match &inst_def.kind {
+            DataInstKind::SpvInst(spv_inst) => spv_inst.clone(),
+            _ => return None,
+        };
+        let attrs = inst_def.attrs;
+        let output_type = inst_def.output_type?;
+        let inputs: SmallVec<[_; 2]> = inst_def.inputs.iter().map(|&v| self.resolve(v)).collect();
+
+        if spv_inst.opcode == wk.OpCompositeExtract {
+            let (&[spv::Imm::Short(_, idx)], &[vector]) = (&spv_inst.imms[..], &inputs[..]) else {
+                return None;
+            };
+            let component = *self.components(vector)?.get(usize::try_from(idx).ok()?)?;
+            self.replacements
+                .insert(Value::DataInstOutput(inst), component);
+            return Some(());
+        }
+
+        let (component_type, count) = vector_type(self.cx, output_type)?;
+        let components: SmallVec<[_; 4]> = if spv_inst.opcode == wk.OpCompositeInsert {
+            let (&[spv::Imm::Short(_, idx)], &[object, composite]) =
+                (&spv_inst.imms[..], &inputs[..])
+            else {
+                return None;
+            };
+            let mut components = self.components(composite)?;
+            *components.get_mut(usize::try_from(idx).ok()?)? = object;
+            components
+        } else if spv_inst.opcode == wk.OpVectorShuffle {
+            let &[a, b] = &inputs[..] else {
+                return None;
+            };
+            let (a, b) = (self.components(a)?, self.components(b)?);
+            let mut components = SmallVec::new();
+            for &imm in &spv_inst.imms {
+                // NOTE(eddyb) this also rejects `0xFFFFFFFF` (undefined component).
+                let spv::Imm::Short(_, idx) = imm else {
+                    return None;
+                };
+                components.push(*a.iter().chain(&b).nth(usize::try_from(idx).ok()?)?);
+            }
+            components
+        } else if spv_inst.opcode == wk.OpCompositeConstruct {
+            if !inputs.iter().any(|&v| self.vector_type_of(v).is_some()) {
+                return None;
+            }
+            let mut components = SmallVec::new();
+            for &v in &inputs {
+                if self.vector_type_of(v).is_some() {
+                    components.extend(self.components(v)?);
+                } else {
+                    components.push(v);
+                }
+            }
+            components
+        } else if is_componentwise(spv_inst.opcode) && spv_inst.imms.is_empty() {
+            if !inputs
+                .iter()
+                .all(|&v| self.vector_type_of(v).is_some_and(|(_, c)| c == count))
+            {
+                return None;
+            }
+            let input_components: SmallVec<[_; 2]> =
+                match inputs.iter().map(|&v| self.components(v)).collect() {
+                    Some(input_components) => input_components,
+                    None if self.options.scalarize_all_ops => inputs
+                        .iter()
+                        .map(|&v| self.components_or_extract(v, attrs, new_insts))
+                        .collect(),
+                    None => return None,
+                };
+            (0..count as usize)
+                .map(|i| {
+                    self.new_inst(
+                        attrs,
+                        spv_inst.opcode,
+                        [],
+                        input_components.iter().map(|components| components[i]),
+                        component_type,
+                        new_insts,
+                    )
+                })
+                .collect()
+        } else {
+            return None;
+        };
+        if components.len() != count as usize {
+            return None;
+        }
+
+        let inst_def = &mut self.func_def_body.data_insts[inst];
+        inst_def.kind = DataInstKind::SpvInst(wk.OpCompositeConstruct.into());
+        inst_def.inputs = components.into_iter().collect();
+        Some(())
+    }
+}
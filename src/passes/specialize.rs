@@ -0,0 +1,108 @@
+//! Specialization (i.e. applying values to SPIR-V specialization constants).
+
+use crate::passes::const_fold::fold_consts;
+use crate::passes::legalize::reachable_global_vars_and_funcs;
+use crate::passes::unreachable::remove_unreachable_code;
+use crate::spv::fold::{SpecConstFolder, SpecConstValues};
+use crate::transform::{Transformed, Transformer};
+use crate::{Const, Context, Module, Type};
+use rustc_hash::FxHashMap;
+
+/// Options for specialization (see [`specialize_consts`]).
+#[derive(Clone)]
+pub struct SpecializeOptions {
+    /// Whether to also remove code which can no longer be reached (e.g. the
+    /// unchosen cases of `Select`s on conditions which depended on specialization
+    /// constants), by running [`fold_consts`] and [`remove_unreachable_code`].
+    pub remove_dead_code: bool,
+}
+
+impl Default for SpecializeOptions {
+    fn default() -> Self {
+        Self {
+            remove_dead_code: true,
+        }
+    }
+}
+
+/// Specialize `module` with `values`, i.e. replace all specialization constants
+/// (and all constants depending on them, e.g. `OpSpecConstantOp`s) used anywhere
+/// in `module` (including in types, e.g. array lengths, in global variable
+/// initializers, and in function bodies) with ordinary constants, using default
+/// values for any `SpecId`s missing from `values` (see [`SpecConstFolder`]).
+///
+/// This is usually done just before creating a pipeline (which would otherwise
+/// do the same, using the same values), e.g. to allow further optimizations to
+/// take advantage of the values, or for targets without specialization constants.
+pub fn specialize_consts(
+    module: &mut Module,
+    values: &SpecConstValues,
+    options: &SpecializeOptions,
+) {
+    let cx = &module.cx();
+
+    let mut specializer = Specializer {
+        cx,
+        folder: SpecConstFolder::new(cx, values),
+
+        transformed_types: FxHashMap::default(),
+        transformed_consts: FxHashMap::default(),
+    };
+    let (global_vars, funcs) = reachable_global_vars_and_funcs(module);
+    for gv in global_vars {
+        specializer.in_place_transform_global_var_decl(&mut module.global_vars[gv]);
+    }
+    for func in funcs {
+        specializer.in_place_transform_func_decl(&mut module.funcs[func]);
+    }
+
+    if options.remove_dead_code {
+        fold_consts(module);
+        remove_unreachable_code(module);
+    }
+}
+
+/// [`Transformer`] folding all constants (see [`SpecConstFolder`]), including
+/// those nested in types (e.g. array lengths).
+struct Specializer<'a> {
+    cx: &'a Context,
+    folder: SpecConstFolder<'a>,
+
+    // FIXME(eddyb) build some automation to avoid ever repeating these.
+    transformed_types: FxHashMap<Type, Transformed<Type>>,
+    transformed_consts: FxHashMap<Const, Transformed<Const>>,
+}
+
+impl Transformer for Specializer<'_> {
+    // FIXME(eddyb) build some automation to avoid ever repeating these.
+    fn transform_type_use(&mut self, ty: Type) -> Transformed<Type> {
+        if let Some(&cached) = self.transformed_types.get(&ty) {
+            return cached;
+        }
+        let transformed = self
+            .transform_type_def(&self.cx[ty])
+            .map(|ty_def| self.cx.intern(ty_def));
+        self.transformed_types.insert(ty, transformed);
+        transformed
+    }
+    fn transform_const_use(&mut self, ct: Const) -> Transformed<Const> {
+        if let Some(&cached) = self.transformed_consts.get(&ct) {
+            return cached;
+        }
+
+        // NOTE(eddyb) the folded constant can still contain types which depend
+        // on specialization constants (e.g. `OpConstantNull` of an array type).
+        let mut folded = self.folder.fold_const(ct);
+        self.transform_const_def(&self.cx[folded])
+            .map(|ct_def| self.cx.intern(ct_def))
+            .apply_to(&mut folded);
+
+        let transformed = if folded == ct {
+            Transformed::Unchanged
+        } else {
+            Transformed::Changed(folded)
+        };
+        self.transformed_consts.insert(ct, transformed);
+        transformed
+    }
+}
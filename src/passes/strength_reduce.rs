@@ -0,0 +1,447 @@
+//! Strength reduction (of integer multiplication/division by constants).
+
+use crate::passes::legalize::reachable_funcs;
+use crate::spv::{
+    self,
+    fold::{const_splat_value, splat_const, ScalarValue},
+};
+use crate::value_range::ValueRanges;
+use crate::{
+    AttrSet, Context, ControlNodeKind, ControlRegion, DataInst, DataInstDef, DataInstKind, DeclDef,
+    EntityDefs, EntityList, FuncDefBody, Module, StructMember, Type, TypeCtor, TypeCtorArg,
+    TypeDef, Value,
+};
+use smallvec::SmallVec;
+use std::mem;
+
+/// Replace integer multiplication, division and remainder operations with a
+/// constant (right-hand side) operand, with cheaper sequences of instructions,
+/// in all function definitions in `module`, i.e.:
+/// * multiplication by a power of two: a left shift
+/// * unsigned division/remainder by a power of two: a right shift/bitwise mask
+/// * signed division by a power of two: right shifts, with rounding towards zero
+///   (unless the dividend is known to be non-negative, see [`ValueRanges`],
+///   in which case signed division/remainder are reduced like unsigned ones)
+/// * division by any other (positive) constant: multiplication by a "magic"
+///   constant (keeping only the high half of the result), followed by shifts
+///   (see "Division by Invariant Integers using Multiplication", by Granlund
+///   and Montgomery, which describes the algorithms used here)
+/// * remainder by any other (positive) constant: `x - (x / c) * c`, with the
+///   division reduced as above
+///
+/// This is useful for targets whose compilers don't do this themselves (which
+/// is the case for many mobile GPU drivers), while `OpSMod` (and division
+/// by negative constants) are left alone, being both uncommon and costlier.
+///
+/// The resulting instructions are left in place (unlike e.g. `x * 1`, which is
+/// left to [`peephole`](crate::passes::peephole)), and the original instruction
+/// is reused as the last instruction in the sequence (so its uses are unchanged).
+pub fn reduce_strength(module: &mut Module) {
+    let cx = &module.cx();
+
+    for func in reachable_funcs(module) {
+        if let DeclDef::Present(func_def_body) = &mut module.funcs[func].def {
+            let regions = match &func_def_body.unstructured_cfg {
+                None => vec![func_def_body.body],
+                Some(cfg) => cfg.rev_post_order(func_def_body).collect(),
+            };
+            let value_ranges = ValueRanges::compute(cx.clone(), func_def_body);
+            for region in regions {
+                reduce_strength_in_region(cx, &value_ranges, func_def_body, region);
+            }
+        }
+    }
+}
+
+fn reduce_strength_in_region(
+    cx: &Context,
+    value_ranges: &ValueRanges,
+    func_def_body: &mut FuncDefBody,
+    region: ControlRegion,
+) {
+    let children: SmallVec<[_; 8]> = func_def_body
+        .at(region)
+        .at_children()
+        .into_iter()
+        .map(|func_at_control_node| func_at_control_node.position)
+        .collect();
+    for control_node in children {
+        let mut old_insts = match &mut func_def_body.control_nodes[control_node].kind {
+            ControlNodeKind::Block { insts } => mem::take(insts),
+            ControlNodeKind::Select { cases, .. } => {
+                for case in cases.clone() {
+                    reduce_strength_in_region(cx, value_ranges, func_def_body, case);
+                }
+                continue;
+            }
+            &mut ControlNodeKind::Loop { body, .. } => {
+                reduce_strength_in_region(cx, value_ranges, func_def_body, body);
+                continue;
+            }
+            ControlNodeKind::ExitInvocation { .. } => continue,
+        };
+
+        // NOTE(eddyb) as `EntityList` doesn't support inserting before a node,
+        // the whole list of instructions is rebuilt (with any new instructions
+        // inserted just before the original instruction they were created for).
+        let original_insts: SmallVec<[_; 8]> = func_def_body
+            .at(old_insts)
+            .into_iter()
+            .map(|func_at_inst| func_at_inst.position)
+            .collect();
+        let mut new_insts = EntityList::empty();
+        for inst in original_insts {
+            old_insts.remove(inst, &mut func_def_body.data_insts);
+
+            let mut reducer = StrengthReducer {
+                cx,
+                value_ranges,
+                data_insts: &mut func_def_body.data_insts,
+                inst,
+                new_insts: SmallVec::new(),
+            };
+            reducer.try_reduce();
+            for new_inst in reducer.new_insts {
+                new_insts.insert_last(new_inst, &mut func_def_body.data_insts);
+            }
+            new_insts.insert_last(inst, &mut func_def_body.data_insts);
+        }
+        match &mut func_def_body.control_nodes[control_node].kind {
+            ControlNodeKind::Block { insts } => *insts = new_insts,
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// Integer type information needed by [`StrengthReducer`].
+#[derive(Copy, Clone)]
+struct IntType {
+    /// The (scalar or vector) type of the values being operated on.
+    ty: Type,
+
+    width: u32,
+    signed: bool,
+}
+
+impl IntType {
+    fn from_type(cx: &Context, ty: Type) -> Option<Self> {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        let scalar_type = match &cx[ty].ctor {
+            TypeCtor::SpvInst(inst) if inst.opcode == wk.OpTypeVector => match cx[ty].ctor_args[..]
+            {
+                [TypeCtorArg::Type(elem_type)] => elem_type,
+                _ => return None,
+            },
+            _ => ty,
+        };
+        match &cx[scalar_type].ctor {
+            TypeCtor::SpvInst(inst) if inst.opcode == wk.OpTypeInt => match inst.imms[..] {
+                [spv::Imm::Short(_, width), spv::Imm::Short(_, signedness)]
+                    if (1..=64).contains(&width) =>
+                {
+                    Some(Self {
+                        ty,
+                        width,
+                        signed: signedness != 0,
+                    })
+                }
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Truncate `bits` to the width of this type (i.e. zero all other bits).
+    fn truncate(self, bits: u64) -> u64 {
+        bits & (u64::MAX >> (64 - self.width))
+    }
+
+    /// Sign-extend `bits` (assumed to already be truncated to this type).
+    fn sign_extend(self, bits: u64) -> i64 {
+        let shift = 64 - self.width;
+        ((bits << shift) as i64) >> shift
+    }
+}
+
+/// Strength reduction of a single instruction (see [`reduce_strength`]).
+struct StrengthReducer<'a> {
+    cx: &'a Context,
+    value_ranges: &'a ValueRanges,
+    data_insts: &'a mut EntityDefs<DataInst>,
+
+    /// The instruction being reduced, which is reused for the final result.
+    inst: DataInst,
+
+    /// New instructions, to be inserted before `inst`, in this order.
+    new_insts: SmallVec<[DataInst; 4]>,
+}
+
+impl StrengthReducer<'_> {
+    fn try_reduce(&mut self) {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        let inst_def = &self.data_insts[self.inst];
+        let opcode = match &inst_def.kind {
+            DataInstKind::SpvInst(spv_inst) => spv_inst.opcode,
+            _ => return,
+        };
+        let int_type = match inst_def
+            .output_type
+            .and_then(|ty| IntType::from_type(self.cx, ty))
+        {
+            Some(int_type) => int_type,
+            None => return,
+        };
+        let const_bits = |v| match v {
+            Value::Const(ct) => match const_splat_value(self.cx, ct) {
+                Some(ScalarValue::Int { bits, .. }) => Some(bits),
+                _ => None,
+            },
+            _ => None,
+        };
+        let (x, c) = match inst_def.inputs[..] {
+            [a, b] if opcode == wk.OpIMul => match (const_bits(a), const_bits(b)) {
+                (_, Some(c)) => (a, c),
+                (Some(c), _) => (b, c),
+                _ => return,
+            },
+            [x, c] => match const_bits(c) {
+                Some(c) => (x, c),
+                None => return,
+            },
+            _ => return,
+        };
+
+        // NOTE(eddyb) `x * 0` and `x * 1` (and so on) are already trivial.
+        if c < 2 {
+            return;
+        }
+        let pow2_log2 = c.is_power_of_two().then(|| c.trailing_zeros());
+
+        // NOTE(eddyb) signed division (or remainder) of a non-negative value,
+        // by a (positive) power of two, is the same as the unsigned one, which
+        // doesn't need any adjustments for rounding towards zero.
+        let opcode = if (opcode == wk.OpSDiv || opcode == wk.OpSRem)
+            && pow2_log2.is_some()
+            && int_type.sign_extend(c) > 0
+            && self
+                .value_ranges
+                .range_of(x)
+                .is_some_and(|range| range.is_known_non_negative())
+        {
+            if opcode == wk.OpSDiv {
+                wk.OpUDiv
+            } else {
+                wk.OpUMod
+            }
+        } else {
+            opcode
+        };
+
+        if opcode == wk.OpIMul {
+            if let Some(k) = pow2_log2 {
+                let k = self.int_const(int_type, k.into());
+                self.finish(wk.OpShiftLeftLogical, [x, k]);
+            }
+        } else if opcode == wk.OpUDiv {
+            if let Some(k) = pow2_log2 {
+                let k = self.int_const(int_type, k.into());
+                self.finish(wk.OpShiftRightLogical, [x, k]);
+            } else if let Some(q) = self.udiv_by_const(int_type, x, c) {
+                self.finish_with_value(q);
+            }
+        } else if opcode == wk.OpUMod {
+            if pow2_log2.is_some() {
+                let mask = self.int_const(int_type, c - 1);
+                self.finish(wk.OpBitwiseAnd, [x, mask]);
+            } else if let Some(q) = self.udiv_by_const(int_type, x, c) {
+                self.finish_remainder(int_type, x, q, c);
+            }
+        } else if opcode == wk.OpSDiv || opcode == wk.OpSRem {
+            if int_type.sign_extend(c) < 0 {
+                return;
+            }
+            let q = match pow2_log2 {
+                Some(k) => self.sdiv_by_pow2(int_type, x, k),
+                None => self.sdiv_by_const(int_type, x, c),
+            };
+            if opcode == wk.OpSDiv {
+                self.finish_with_value(q);
+            } else {
+                self.finish_remainder(int_type, x, q, c);
+            }
+        }
+    }
+
+    fn int_const(&self, int_type: IntType, bits: u64) -> Value {
+        let value = ScalarValue::Int {
+            width: int_type.width,
+            bits: int_type.truncate(bits),
+        };
+        Value::Const(splat_const(self.cx, int_type.ty, value).unwrap())
+    }
+
+    /// Add a new instruction (before the original one), returning its output.
+    fn push(
+        &mut self,
+        opcode: spv::spec::Opcode,
+        imms: impl IntoIterator<Item = spv::Imm>,
+        inputs: impl IntoIterator<Item = Value>,
+        output_type: Type,
+    ) -> Value {
+        let attrs =
+            AttrSet::default().with_debug_locations_from(self.cx, self.data_insts[self.inst].attrs);
+        let inst = self.data_insts.define(
+            self.cx,
+            DataInstDef {
+                attrs,
+                kind: DataInstKind::SpvInst(spv::Inst {
+                    opcode,
+                    imms: imms.into_iter().collect(),
+                }),
+                output_type: Some(output_type),
+                inputs: inputs.into_iter().collect(),
+            }
+            .into(),
+        );
+        self.new_insts.push(inst);
+        Value::DataInstOutput(inst)
+    }
+
+    fn push_op<const N: usize>(
+        &mut self,
+        int_type: IntType,
+        opcode: spv::spec::Opcode,
+        inputs: [Value; N],
+    ) -> Value {
+        self.push(opcode, [], inputs, int_type.ty)
+    }
+
+    /// Replace the original instruction with `opcode` (applied to `inputs`).
+    fn finish<const N: usize>(&mut self, opcode: spv::spec::Opcode, inputs: [Value; N]) {
+        let inst_def = &mut self.data_insts[self.inst];
+        inst_def.kind = DataInstKind::SpvInst(opcode.into());
+        inst_def.inputs = inputs.into_iter().collect();
+    }
+
+    /// Replace the original instruction with a copy of `v` (the output of the
+    /// last new instruction), by moving that instruction's definition into it.
+    fn finish_with_value(&mut self, v: Value) {
+        let last_inst = self.new_insts.pop().unwrap();
+        assert!(v == Value::DataInstOutput(last_inst));
+
+        let last_inst_def = &self.data_insts[last_inst];
+        let (kind, inputs) = (last_inst_def.kind.clone(), last_inst_def.inputs.clone());
+        let inst_def = &mut self.data_insts[self.inst];
+        inst_def.kind = kind;
+        inst_def.inputs = inputs;
+    }
+
+    /// Replace the original instruction with `x - q * c` (i.e. the remainder
+    /// of dividing `x` by `c`, given `q = x / c`).
+    fn finish_remainder(&mut self, int_type: IntType, x: Value, q: Value, c: u64) {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        let c = self.int_const(int_type, c);
+        let qc = self.push_op(int_type, wk.OpIMul, [q, c]);
+        self.finish(wk.OpISub, [x, qc]);
+    }
+
+    /// Compute the high half of the (double-width) product of `x` and `m`,
+    /// using `OpUMulExtended` (or `OpSMulExtended`, if `signed`).
+    fn mul_high(&mut self, int_type: IntType, signed: bool, x: Value, m: u64) -> Value {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        let member = StructMember {
+            attrs: AttrSet::default(),
+            offset: None,
+        };
+        let pair_type = self.cx.intern(TypeDef {
+            attrs: AttrSet::default(),
+            ctor: TypeCtor::Struct {
+                members: [member, member].into_iter().collect(),
+            },
+            ctor_args: [
+                TypeCtorArg::Type(int_type.ty),
+                TypeCtorArg::Type(int_type.ty),
+            ]
+            .into_iter()
+            .collect(),
+        });
+        let m = self.int_const(int_type, m);
+        let opcode = if signed {
+            wk.OpSMulExtended
+        } else {
+            wk.OpUMulExtended
+        };
+        let pair = self.push(opcode, [], [m, x], pair_type);
+        self.push(
+            wk.OpCompositeExtract,
+            [spv::Imm::Short(wk.LiteralInteger, 1)],
+            [pair],
+            int_type.ty,
+        )
+    }
+
+    /// Compute `x / c` (unsigned), for `c` which isn't a power of two, returning
+    /// `None` if `OpUMulExtended` can't be used (i.e. for signed integer types).
+    fn udiv_by_const(&mut self, int_type: IntType, x: Value, c: u64) -> Option<Value> {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        // FIXME(eddyb) support this by bitcasting to (and from) unsigned types.
+        if int_type.signed {
+            return None;
+        }
+
+        // NOTE(eddyb) this is Figure 4.1 from the Granlund-Montgomery paper,
+        // with `l = ceil(log2(c))`, which is always at least `2` here.
+        let n = int_type.width;
+        let l = 64 - (c - 1).leading_zeros();
+        let m = (((1u128 << n) * ((1u128 << l) - u128::from(c))) / u128::from(c)) + 1;
+
+        let t1 = self.mul_high(int_type, false, x, m as u64);
+        let x_minus_t1 = self.push_op(int_type, wk.OpISub, [x, t1]);
+        let one = self.int_const(int_type, 1);
+        let t2 = self.push_op(int_type, wk.OpShiftRightLogical, [x_minus_t1, one]);
+        let t3 = self.push_op(int_type, wk.OpIAdd, [t1, t2]);
+        let l_minus_1 = self.int_const(int_type, (l - 1).into());
+        Some(self.push_op(int_type, wk.OpShiftRightLogical, [t3, l_minus_1]))
+    }
+
+    /// Compute `x / 2**k` (signed, i.e. rounding towards zero), for `k > 0`.
+    fn sdiv_by_pow2(&mut self, int_type: IntType, x: Value, k: u32) -> Value {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        // NOTE(eddyb) negative `x` needs `2**k - 1` added to it before shifting,
+        // for the result to be rounded towards zero (instead of negative infinity).
+        let n = int_type.width;
+        let n_minus_1 = self.int_const(int_type, (n - 1).into());
+        let sign = self.push_op(int_type, wk.OpShiftRightArithmetic, [x, n_minus_1]);
+        let n_minus_k = self.int_const(int_type, (n - k).into());
+        let bias = self.push_op(int_type, wk.OpShiftRightLogical, [sign, n_minus_k]);
+        let biased_x = self.push_op(int_type, wk.OpIAdd, [x, bias]);
+        let k = self.int_const(int_type, k.into());
+        self.push_op(int_type, wk.OpShiftRightArithmetic, [biased_x, k])
+    }
+
+    /// Compute `x / c` (signed), for positive `c` which isn't a power of two.
+    fn sdiv_by_const(&mut self, int_type: IntType, x: Value, c: u64) -> Value {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        // NOTE(eddyb) this is Figure 5.2 from the Granlund-Montgomery paper,
+        // with `l = ceil(log2(c))`, which is always at least `2` here.
+        let n = int_type.width;
+        let l = 64 - (c - 1).leading_zeros();
+        // NOTE(eddyb) `m` may be negative (and is used as a signed integer).
+        let m = 1 + (1i128 << (n + l - 1)) / i128::from(c) - (1i128 << n);
+
+        let t1 = self.mul_high(int_type, true, x, m as u64);
+        let t2 = self.push_op(int_type, wk.OpIAdd, [x, t1]);
+        let l_minus_1 = self.int_const(int_type, (l - 1).into());
+        let q0 = self.push_op(int_type, wk.OpShiftRightArithmetic, [t2, l_minus_1]);
+        let n_minus_1 = self.int_const(int_type, (n - 1).into());
+        let sign = self.push_op(int_type, wk.OpShiftRightArithmetic, [x, n_minus_1]);
+        self.push_op(int_type, wk.OpISub, [q0, sign])
+    }
+}
@@ -0,0 +1,268 @@
+//! Lowering of `OpSwitch` selections to chains of `if`-`else` (`BoolCond`) ones.
+
+use crate::passes::legalize::reachable_funcs;
+use crate::spv;
+use crate::{
+    AttrSet, ConstCtor, ConstDef, Context, ControlNode, ControlNodeDef, ControlNodeKind,
+    ControlRegion, ControlRegionDef, DataInstDef, DataInstKind, DeclDef, EntityList, FuncDefBody,
+    Module, SelectionKind, TypeCtor, TypeDef, Value,
+};
+use smallvec::SmallVec;
+
+/// Options for lowering `OpSwitch`es (see [`lower_switches_to_ifs`]).
+#[derive(Clone)]
+pub struct SwitchToIfOptions {
+    /// Only `OpSwitch`es with at least this many cases (not counting the default
+    /// case) are lowered (with `OpSwitch`es without any cases always ignored).
+    pub min_case_count: usize,
+
+    /// Only `OpSwitch`es with at most this many cases (not counting the default
+    /// case) are lowered, as every case adds one more level of nesting.
+    pub max_case_count: usize,
+}
+
+impl Default for SwitchToIfOptions {
+    fn default() -> Self {
+        Self {
+            min_case_count: 1,
+            max_case_count: usize::MAX,
+        }
+    }
+}
+
+/// Lower `OpSwitch` `Select`s (within the limits of `options`) in all function
+/// definitions in `module`, to nested `BoolCond` `Select`s, i.e.:
+/// ```text
+/// switch x {
+///     default => d
+///     case 1 => a
+///     case 2 => b
+/// }
+/// ```
+/// becomes (with all the `OpIEqual`s being placed before the outermost `if`):
+/// ```text
+/// if x == 1 {
+///     a
+/// } else {
+///     if x == 2 {
+///         b
+///     } else {
+///         d
+///     }
+/// }
+/// ```
+///
+/// The original case [`ControlRegion`]s are reused as the "then" cases (and the
+/// last "else" case), while the outermost `Select` replaces the `OpSwitch` one
+/// in-place (keeping its outputs, and any `SelectionControl` attributes).
+pub fn lower_switches_to_ifs(module: &mut Module, options: &SwitchToIfOptions) {
+    let cx = &module.cx();
+
+    for func in reachable_funcs(module) {
+        if let DeclDef::Present(func_def_body) = &mut module.funcs[func].def {
+            lower_switches_in_func(cx, func_def_body, options);
+        }
+    }
+}
+
+fn lower_switches_in_func(
+    cx: &Context,
+    func_def_body: &mut FuncDefBody,
+    options: &SwitchToIfOptions,
+) {
+    let wk = &spv::spec::Spec::get().well_known;
+
+    let mut regions = vec![func_def_body.body];
+    if let Some(cfg) = &func_def_body.unstructured_cfg {
+        regions.extend(
+            cfg.rev_post_order(func_def_body)
+                .filter(|&region| region != func_def_body.body),
+        );
+    }
+
+    // `OpSwitch` `Select`s (alongside the region they're found in), and the
+    // literal value (as `OpConstant` immediates) for each of their cases.
+    let mut switches = vec![];
+    while let Some(region) = regions.pop() {
+        for func_at_control_node in func_def_body.at(region).at_children() {
+            match &func_at_control_node.def().kind {
+                ControlNodeKind::Block { .. } | ControlNodeKind::ExitInvocation { .. } => {}
+                ControlNodeKind::Select { kind, cases, .. } => {
+                    regions.extend(cases.iter().copied());
+
+                    let case_literals = match kind {
+                        SelectionKind::SpvInst(inst) if inst.opcode == wk.OpSwitch => {
+                            switch_case_literals(inst)
+                        }
+                        _ => continue,
+                    };
+                    let case_literals = match case_literals {
+                        Some(case_literals) if case_literals.len() + 1 == cases.len() => {
+                            case_literals
+                        }
+                        _ => continue,
+                    };
+                    let case_count = case_literals.len();
+                    if case_count > 0
+                        && case_count >= options.min_case_count
+                        && case_count <= options.max_case_count
+                    {
+                        switches.push((region, func_at_control_node.position, case_literals));
+                    }
+                }
+                &ControlNodeKind::Loop { body, .. } => regions.push(body),
+            }
+        }
+    }
+
+    if switches.is_empty() {
+        return;
+    }
+
+    // FIXME(eddyb) SPIR-T should have native booleans itself.
+    let type_bool = cx.intern(TypeDef {
+        attrs: AttrSet::default(),
+        ctor: TypeCtor::SpvInst(wk.OpTypeBool.into()),
+        ctor_args: [].into_iter().collect(),
+    });
+
+    for (parent_region, switch_node, case_literals) in switches {
+        let (scrutinee, cases) = match &func_def_body.at(switch_node).def().kind {
+            ControlNodeKind::Select {
+                scrutinee, cases, ..
+            } => (*scrutinee, cases.clone()),
+            _ => unreachable!(),
+        };
+        let scrutinee_type = func_def_body.at(scrutinee).type_of(cx);
+        let output_decls = func_def_body.at(switch_node).def().outputs.clone();
+
+        // Compare the scrutinee against every case literal, ahead of the `Select`.
+        let mut compare_insts = EntityList::empty();
+        let conditions: SmallVec<[_; 4]> = case_literals
+            .into_iter()
+            .map(|literal| {
+                let literal = cx.intern(ConstDef {
+                    attrs: AttrSet::default(),
+                    ty: scrutinee_type,
+                    ctor: ConstCtor::SpvInst(spv::Inst {
+                        opcode: wk.OpConstant,
+                        imms: literal,
+                    }),
+                    ctor_args: [].into_iter().collect(),
+                });
+                let inst = func_def_body.data_insts.define(
+                    cx,
+                    DataInstDef {
+                        attrs: AttrSet::default(),
+                        kind: DataInstKind::SpvInst(wk.OpIEqual.into()),
+                        output_type: Some(type_bool),
+                        inputs: [scrutinee, Value::Const(literal)].into_iter().collect(),
+                    }
+                    .into(),
+                );
+                compare_insts.insert_last(inst, &mut func_def_body.data_insts);
+                Value::DataInstOutput(inst)
+            })
+            .collect();
+        let compare_block = func_def_body.control_nodes.define(
+            cx,
+            ControlNodeDef {
+                attrs: AttrSet::default(),
+                kind: ControlNodeKind::Block {
+                    insts: compare_insts,
+                },
+                outputs: [].into_iter().collect(),
+            }
+            .into(),
+        );
+
+        // Build the chain of `Select`s from the innermost one outwards, with the
+        // default case being the "else" case of the innermost `Select`.
+        let default_case = cases[0];
+        let mut else_case = default_case;
+        for case_idx in (1..conditions.len()).rev() {
+            let select_node = func_def_body.control_nodes.define(
+                cx,
+                ControlNodeDef {
+                    attrs: AttrSet::default(),
+                    kind: ControlNodeKind::Select {
+                        kind: SelectionKind::BoolCond,
+                        scrutinee: conditions[case_idx],
+                        cases: [cases[case_idx + 1], else_case].into_iter().collect(),
+                    },
+                    outputs: output_decls.clone(),
+                }
+                .into(),
+            );
+            let mut children = EntityList::empty();
+            children.insert_last(select_node, &mut func_def_body.control_nodes);
+            else_case = func_def_body.control_regions.define(
+                cx,
+                ControlRegionDef {
+                    inputs: [].into_iter().collect(),
+                    children,
+                    outputs: (0..output_decls.len())
+                        .map(|output_idx| Value::ControlNodeOutput {
+                            control_node: select_node,
+                            output_idx: output_idx.try_into().unwrap(),
+                        })
+                        .collect(),
+                },
+            );
+        }
+
+        // The outermost `Select` replaces the `OpSwitch` one, in-place.
+        func_def_body.control_nodes[switch_node].kind = ControlNodeKind::Select {
+            kind: SelectionKind::BoolCond,
+            scrutinee: conditions[0],
+            cases: [cases[1], else_case].into_iter().collect(),
+        };
+
+        insert_before(func_def_body, parent_region, switch_node, compare_block);
+    }
+}
+
+/// Split the immediates of an `OpSwitch` into the literal value of each case
+/// (each one being either a single `Short` immediate, or a `LongStart` one
+/// followed by `LongCont` ones, i.e. the same as the immediates of an
+/// `OpConstant` with the same type as the `OpSwitch` selector).
+fn switch_case_literals(switch_inst: &spv::Inst) -> Option<SmallVec<[SmallVec<[spv::Imm; 2]>; 4]>> {
+    let mut case_literals = SmallVec::<[SmallVec<[_; 2]>; 4]>::new();
+    for &imm in &switch_inst.imms {
+        match imm {
+            spv::Imm::Short(..) | spv::Imm::LongStart(..) => {
+                case_literals.push([imm].into_iter().collect());
+            }
+            spv::Imm::LongCont(..) => {
+                let literal = case_literals.last_mut()?;
+                if !matches!(literal[0], spv::Imm::LongStart(..)) {
+                    return None;
+                }
+                literal.push(imm);
+            }
+        }
+    }
+    Some(case_literals)
+}
+
+/// Insert `new_node` into `region`'s children, right before `next_node`.
+fn insert_before(
+    func_def_body: &mut FuncDefBody,
+    region: ControlRegion,
+    next_node: ControlNode,
+    new_node: ControlNode,
+) {
+    let control_nodes = &mut func_def_body.control_nodes;
+    let children = &mut func_def_body.control_regions[region].children;
+
+    // FIXME(eddyb) `EntityList` should have a way to insert in the middle.
+    let mut following_nodes = EntityList::empty();
+    while let Some(next) = control_nodes[next_node].next_in_list() {
+        children.remove(next, control_nodes);
+        following_nodes.insert_last(next, control_nodes);
+    }
+    children.remove(next_node, control_nodes);
+    children.insert_last(new_node, control_nodes);
+    children.insert_last(next_node, control_nodes);
+    children.append(following_nodes, control_nodes);
+}
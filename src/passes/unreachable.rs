@@ -0,0 +1,235 @@
+//! Unreachable code elimination.
+
+use crate::cfg::ControlInstKind;
+use crate::passes::cfg_simplify::switch_case_idx;
+use crate::passes::legalize::reachable_funcs;
+use crate::spv::fold::{const_splat_value, ScalarValue};
+use crate::transform::{InnerInPlaceTransform, ReplaceValueWith};
+use crate::{
+    Context, ControlNode, ControlNodeKind, ControlRegion, DeclDef, EntityList, FuncDefBody,
+    Module, SelectionKind, Value,
+};
+use rustc_hash::{FxHashMap, FxHashSet};
+use smallvec::SmallVec;
+use std::mem;
+
+/// Remove unreachable code from all function definitions in `module`, i.e.:
+/// * `Select`s on a constant scrutinee (for both `BoolCond` and `OpSwitch`)
+///   are replaced with the contents of the case that would always be chosen
+/// * [`ControlRegion`]s (in the unstructured CFG) which can only be left through
+///   an `Unreachable` (without any side-effects before that), are themselves
+///   considered unreachable, i.e. branches to them are never taken, so:
+///   * conditional branches with only one target that can still be taken
+///     become unconditional branches to that target
+///   * branches with no targets that can still be taken become `Unreachable`
+/// * [`ControlRegion`]s (in the unstructured CFG) which are no longer reachable
+///   from the function body (i.e. the entry) are removed
+///
+/// Side-effects before an `Unreachable` are always kept, as it may only be
+/// reached if they never return, e.g. a call to a function which always exits
+/// the invocation (as [`ControlInstKind::Unreachable`] describes).
+pub fn remove_unreachable_code(module: &mut Module) {
+    let cx = &module.cx();
+
+    for func in reachable_funcs(module) {
+        if let DeclDef::Present(func_def_body) = &mut module.funcs[func].def {
+            collapse_const_selects(cx, func_def_body);
+            if func_def_body.unstructured_cfg.is_some() {
+                remove_unreachable_regions(cx, func_def_body);
+            }
+        }
+    }
+}
+
+/// Get the index of the case a `Select` (or the target a `SelectBranch`) of
+/// `kind` would always choose, given its `scrutinee`, if that's a constant.
+fn const_case_idx(cx: &Context, kind: &SelectionKind, scrutinee: Value) -> Option<usize> {
+    let scrutinee = match scrutinee {
+        Value::Const(ct) => const_splat_value(cx, ct)?,
+        _ => return None,
+    };
+    match (kind, scrutinee) {
+        (SelectionKind::BoolCond, ScalarValue::Bool(cond)) => Some(if cond { 0 } else { 1 }),
+        (SelectionKind::SpvInst(inst), ScalarValue::Int { width, bits }) => {
+            switch_case_idx(inst, width, bits)
+        }
+        _ => None,
+    }
+}
+
+fn collapse_const_selects(cx: &Context, func_def_body: &mut FuncDefBody) {
+    let mut regions = vec![func_def_body.body];
+    if let Some(cfg) = &func_def_body.unstructured_cfg {
+        regions.extend(
+            cfg.rev_post_order(func_def_body)
+                .filter(|&region| region != func_def_body.body),
+        );
+    }
+
+    // `Select` nodes (alongside the region they're found in, and the case that
+    // would always be chosen), in the order they're found in (see below).
+    let mut collapsed_selects: Vec<(ControlRegion, ControlNode, ControlRegion)> = vec![];
+    let mut replacements = FxHashMap::default();
+    while let Some(region) = regions.pop() {
+        for func_at_control_node in func_def_body.at(region).at_children() {
+            let control_node = func_at_control_node.position;
+            match &func_at_control_node.def().kind {
+                ControlNodeKind::Block { .. } | ControlNodeKind::ExitInvocation { .. } => {}
+                ControlNodeKind::Select {
+                    kind,
+                    scrutinee,
+                    cases,
+                } => match const_case_idx(cx, kind, *scrutinee) {
+                    Some(case_idx) => {
+                        let chosen_case = cases[case_idx];
+                        let case_outputs = &func_def_body.at(chosen_case).def().outputs;
+                        for (output_idx, &case_output) in case_outputs.iter().enumerate() {
+                            replacements.insert(
+                                Value::ControlNodeOutput {
+                                    control_node,
+                                    output_idx: output_idx.try_into().unwrap(),
+                                },
+                                case_output,
+                            );
+                        }
+                        collapsed_selects.push((region, control_node, chosen_case));
+                        regions.push(chosen_case);
+                    }
+                    None => regions.extend(cases.iter().copied()),
+                },
+                &ControlNodeKind::Loop { body, .. } => regions.push(body),
+            }
+        }
+    }
+
+    // NOTE(eddyb) `Select`s nested in the chosen case of another collapsed
+    // `Select` are always found later, so they're replaced first (i.e. in
+    // reverse order), while their parent region is still that chosen case.
+    for (parent_region, select_node, chosen_case) in collapsed_selects.into_iter().rev() {
+        let case_children = mem::take(&mut func_def_body.control_regions[chosen_case].children);
+        let control_nodes = &mut func_def_body.control_nodes;
+        let children = &mut func_def_body.control_regions[parent_region].children;
+
+        let mut following_nodes = EntityList::empty();
+        while let Some(next) = control_nodes[select_node].next_in_list() {
+            children.remove(next, control_nodes);
+            following_nodes.insert_last(next, control_nodes);
+        }
+        children.remove(select_node, control_nodes);
+        children.append(case_children, control_nodes);
+        children.append(following_nodes, control_nodes);
+    }
+
+    if replacements.is_empty() {
+        return;
+    }
+
+    // NOTE(eddyb) the output of a chosen case can itself be the output of
+    // another collapsed `Select` (nested in it), so replacements are chased.
+    func_def_body.inner_in_place_transform_with(&mut ReplaceValueWith(|mut v| {
+        let mut changed = false;
+        while let Some(&replacement) = replacements.get(&v) {
+            v = replacement;
+            changed = true;
+        }
+        changed.then_some(v)
+    }));
+}
+
+fn remove_unreachable_regions(cx: &Context, func_def_body: &mut FuncDefBody) {
+    let cfg = func_def_body.unstructured_cfg.as_ref().unwrap();
+    let old_rpo: SmallVec<[_; 8]> = cfg.rev_post_order(func_def_body).collect();
+
+    // Whether reaching `region` is undefined behavior, i.e. it can only be left
+    // through an `Unreachable`, with no side-effects before that.
+    let is_unreachable_end = |func_def_body: &FuncDefBody, region: ControlRegion| {
+        let cfg = func_def_body.unstructured_cfg.as_ref().unwrap();
+        matches!(
+            cfg.control_inst_on_exit_from[region].kind,
+            ControlInstKind::Unreachable
+        ) && func_def_body
+            .at(region)
+            .at_children()
+            .into_iter()
+            .all(
+                |func_at_control_node| match &func_at_control_node.def().kind {
+                    ControlNodeKind::Block { insts } => func_at_control_node
+                        .at(*insts)
+                        .into_iter()
+                        .all(|func_at_inst| !func_at_inst.def().has_side_effects(cx)),
+                    // FIXME(eddyb) nested `Select`s without side-effects could also
+                    // be allowed, but (potentially infinite) `Loop`s can't be.
+                    _ => false,
+                },
+            )
+    };
+
+    // NOTE(eddyb) visiting regions in post-order (i.e. targets before the regions
+    // branching to them) handles all acyclic cases in one go, but backedges
+    // require repeating this until no more changes can be made.
+    let mut unreachable_ends = FxHashSet::default();
+    loop {
+        let mut changed = false;
+        for &region in old_rpo.iter().rev() {
+            if region != func_def_body.body
+                && !unreachable_ends.contains(&region)
+                && is_unreachable_end(func_def_body, region)
+            {
+                unreachable_ends.insert(region);
+                changed = true;
+                continue;
+            }
+
+            let cfg = func_def_body.unstructured_cfg.as_mut().unwrap();
+            let control_inst = &mut cfg.control_inst_on_exit_from[region];
+            if !matches!(
+                control_inst.kind,
+                ControlInstKind::Branch | ControlInstKind::SelectBranch(_)
+            ) {
+                continue;
+            }
+
+            let mut live_targets = control_inst
+                .targets
+                .iter()
+                .copied()
+                .filter(|target| !unreachable_ends.contains(target));
+            let first_live_target = live_targets.next();
+            let single_live_target = match first_live_target {
+                Some(target) if live_targets.all(|t| t == target) => Some(target),
+                _ => None,
+            };
+
+            if first_live_target.is_none() {
+                control_inst.kind = ControlInstKind::Unreachable;
+                control_inst.inputs.clear();
+                control_inst.targets.clear();
+                control_inst.target_inputs.clear();
+                changed = true;
+            } else if let (ControlInstKind::SelectBranch(_), Some(target)) =
+                (&control_inst.kind, single_live_target)
+            {
+                control_inst.kind = ControlInstKind::Branch;
+                control_inst.inputs.clear();
+                control_inst.targets = [target].into_iter().collect();
+                control_inst.target_inputs.retain(|&t, _| t == target);
+                changed = true;
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+
+    // NOTE(eddyb) regions can only be reached through `ControlInst`s (starting
+    // at the function body), so removing the `ControlInst` of an unreachable
+    // region is enough to remove it (and anything it dominates) from the CFG.
+    let cfg = func_def_body.unstructured_cfg.as_ref().unwrap();
+    let new_rpo: FxHashSet<_> = cfg.rev_post_order(func_def_body).collect();
+    let cfg = func_def_body.unstructured_cfg.as_mut().unwrap();
+    for region in old_rpo {
+        if !new_rpo.contains(&region) {
+            cfg.control_inst_on_exit_from.remove(region);
+        }
+    }
+}
@@ -0,0 +1,591 @@
+//! Loop unrolling (full and partial, of loops with known trip counts).
+
+use crate::passes::legalize::reachable_funcs;
+use crate::spv::{self, fold::eval_spv_inst};
+use crate::transform::{InnerInPlaceTransform, ReplaceValueWith};
+use crate::{
+    Attr, AttrSet, AttrSetDef, Const, ConstCtor, Context, ControlNode, ControlNodeDef,
+    ControlNodeKind, ControlRegion, ControlRegionDef, DataInst, DataInstKind, DeclDef, EntityList,
+    FuncDefBody, Module, Value,
+};
+use rustc_hash::{FxHashMap, FxHashSet};
+use smallvec::SmallVec;
+use std::mem;
+
+/// Options for loop unrolling (see [`unroll_loops`]).
+#[derive(Clone)]
+pub struct UnrollOptions {
+    /// Loops with a known trip count of at most this many iterations are fully
+    /// unrolled (i.e. replaced with that many copies of the loop body).
+    pub max_full_unroll_trip_count: u32,
+
+    /// Like [`max_full_unroll_trip_count`](UnrollOptions::max_full_unroll_trip_count),
+    /// but for loops with an `Unroll` hint (from SPIR-V `LoopControl`).
+    pub max_hinted_full_unroll_trip_count: u32,
+
+    /// Number of copies of the loop body to use for partially unrolling loops
+    /// which have an `Unroll` hint, but weren't fully unrolled (overridden by
+    /// any `PartialCount` hint, and ignored if less than `2`).
+    ///
+    /// Partial unrolling is only done if the trip count is known to be a multiple
+    /// of this factor (either because the trip count is known, or because of an
+    /// `IterationMultiple` hint), as every copy of the loop body other than the
+    /// last one has to be always followed by another iteration.
+    pub partial_unroll_factor: u32,
+}
+
+impl Default for UnrollOptions {
+    fn default() -> Self {
+        Self {
+            max_full_unroll_trip_count: 4,
+            max_hinted_full_unroll_trip_count: 64,
+            partial_unroll_factor: 4,
+        }
+    }
+}
+
+/// Maximum number of iterations [`LoopUnroller::const_trip_count`] will evaluate.
+//
+// FIXME(eddyb) this could instead be computed from the loop's induction
+// variable(s), but that would require more general analysis of the loop body.
+const MAX_EVALUATED_TRIP_COUNT: u32 = 4096;
+
+/// Unroll loops in all (structured) function definitions in `module`, when
+/// their trip count is a known constant (within the limits of `options`), or
+/// when their `LoopControl` hints allow partial unrolling (see [`UnrollOptions`]).
+///
+/// The trip count is found by evaluating (see [`eval_spv_inst`]) the loop's
+/// `repeat_condition` (and the loop state it depends on), starting with
+/// constant `initial_inputs`, i.e. it's only "known" for loops which would be
+/// entirely constant-folded, if it weren't for the loop itself.
+///
+/// Unrolling never changes the behavior of a loop, and any `DontUnroll` hints
+/// are always respected, while all other `LoopControl` hints are removed from
+/// partially unrolled loops (as they're no longer accurate).
+///
+/// Only functions with fully structured control-flow are supported (i.e. after
+/// [`structurize_func_cfgs`](crate::passes::legalize::structurize_func_cfgs)).
+pub fn unroll_loops(module: &mut Module, options: &UnrollOptions) {
+    let cx = &module.cx();
+
+    for func in reachable_funcs(module) {
+        if let DeclDef::Present(func_def_body) = &mut module.funcs[func].def {
+            if func_def_body.unstructured_cfg.is_none() {
+                let unroller = LoopUnroller { cx, options };
+                unroller.unroll_loops_in_region(func_def_body, func_def_body.body);
+            }
+        }
+    }
+}
+
+/// The `LoopControl` hints (from SPIR-V `OpLoopMerge`) relevant to unrolling.
+#[derive(Default)]
+struct UnrollHints {
+    unroll: bool,
+    dont_unroll: bool,
+    iteration_multiple: Option<u32>,
+    partial_count: Option<u32>,
+}
+
+impl UnrollHints {
+    // NOTE(eddyb) these are the SPIR-V `LoopControl` bits (and the parameters
+    // of those which take one are in the same order as the bits).
+    const UNROLL: u32 = 0x1;
+    const DONT_UNROLL: u32 = 0x2;
+    const DEPENDENCY_LENGTH: u32 = 0x8;
+    const MIN_ITERATIONS: u32 = 0x10;
+    const MAX_ITERATIONS: u32 = 0x20;
+    const ITERATION_MULTIPLE: u32 = 0x40;
+    const PEEL_COUNT: u32 = 0x80;
+    const PARTIAL_COUNT: u32 = 0x100;
+
+    fn is_loop_control_attr(attr: &Attr) -> bool {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        match attr {
+            Attr::SpvBitflagsOperand(imms) => {
+                matches!(imms[..], [spv::Imm::Short(kind, _), ..] if kind == wk.LoopControl)
+            }
+            _ => false,
+        }
+    }
+
+    fn from_attrs(cx: &Context, attrs: AttrSet) -> Self {
+        let mut hints = Self::default();
+        for attr in &cx[attrs].attrs {
+            let imms = match attr {
+                Attr::SpvBitflagsOperand(imms) if Self::is_loop_control_attr(attr) => imms,
+                _ => continue,
+            };
+            let bits = match imms[0] {
+                spv::Imm::Short(_, bits) => bits,
+                _ => unreachable!(),
+            };
+            hints.unroll |= bits & Self::UNROLL != 0;
+            hints.dont_unroll |= bits & Self::DONT_UNROLL != 0;
+
+            let mut params = imms[1..].iter().map(|&imm| match imm {
+                spv::Imm::Short(_, x) => Some(x),
+                _ => None,
+            });
+            for bit in [
+                Self::DEPENDENCY_LENGTH,
+                Self::MIN_ITERATIONS,
+                Self::MAX_ITERATIONS,
+                Self::ITERATION_MULTIPLE,
+                Self::PEEL_COUNT,
+                Self::PARTIAL_COUNT,
+            ] {
+                if bits & bit == 0 {
+                    continue;
+                }
+                let param = params.next().flatten();
+                if bit == Self::ITERATION_MULTIPLE {
+                    hints.iteration_multiple = param;
+                } else if bit == Self::PARTIAL_COUNT {
+                    hints.partial_count = param;
+                }
+            }
+        }
+        hints
+    }
+}
+
+struct LoopUnroller<'a> {
+    cx: &'a Context,
+    options: &'a UnrollOptions,
+}
+
+impl LoopUnroller<'_> {
+    fn unroll_loops_in_region(&self, func_def_body: &mut FuncDefBody, region: ControlRegion) {
+        let children: SmallVec<[_; 8]> = func_def_body
+            .at(region)
+            .at_children()
+            .into_iter()
+            .map(|func_at_control_node| func_at_control_node.position)
+            .collect();
+
+        // NOTE(eddyb) nested loops are unrolled first, so that unrolling the
+        // outer loop can duplicate the already-unrolled inner loops.
+        for control_node in children {
+            match &func_def_body.at(control_node).def().kind {
+                ControlNodeKind::Select { cases, .. } => {
+                    for case in cases.clone() {
+                        self.unroll_loops_in_region(func_def_body, case);
+                    }
+                }
+                &ControlNodeKind::Loop { body, .. } => {
+                    self.unroll_loops_in_region(func_def_body, body);
+                    self.try_unroll_loop(func_def_body, region, control_node);
+                }
+                ControlNodeKind::Block { .. } | ControlNodeKind::ExitInvocation { .. } => {}
+            }
+        }
+    }
+
+    /// Unroll `loop_node` (found in `parent_region`), if possible and allowed.
+    fn try_unroll_loop(
+        &self,
+        func_def_body: &mut FuncDefBody,
+        parent_region: ControlRegion,
+        loop_node: ControlNode,
+    ) {
+        let loop_node_def = func_def_body.at(loop_node).def();
+        let hints = UnrollHints::from_attrs(self.cx, loop_node_def.attrs);
+        if hints.dont_unroll {
+            return;
+        }
+        let (initial_inputs, body) = match &loop_node_def.kind {
+            ControlNodeKind::Loop {
+                initial_inputs,
+                body,
+                ..
+            } => (initial_inputs.clone(), *body),
+            _ => unreachable!(),
+        };
+
+        // NOTE(eddyb) nothing can follow an `ExitInvocation` in the same region,
+        // so a body ending with one can't be followed by another copy of itself.
+        let body_exits_invocation =
+            func_def_body
+                .at(body)
+                .at_children()
+                .into_iter()
+                .any(|func_at_control_node| {
+                    matches!(
+                        func_at_control_node.def().kind,
+                        ControlNodeKind::ExitInvocation { .. }
+                    )
+                });
+        if body_exits_invocation {
+            return;
+        }
+
+        let trip_count = self.const_trip_count(func_def_body, loop_node);
+
+        let max_full_unroll_trip_count = if hints.unroll {
+            self.options.max_hinted_full_unroll_trip_count
+        } else {
+            self.options.max_full_unroll_trip_count
+        };
+        if let Some(trip_count) = trip_count.filter(|&n| n <= max_full_unroll_trip_count) {
+            self.unroll_loop_body(func_def_body, body, initial_inputs, trip_count - 1);
+
+            // Replace the loop with the (now fully unrolled) body's contents.
+            let body_children = mem::take(&mut func_def_body.control_regions[body].children);
+            let control_nodes = &mut func_def_body.control_nodes;
+            let children = &mut func_def_body.control_regions[parent_region].children;
+
+            let mut following_nodes = EntityList::empty();
+            while let Some(next) = control_nodes[loop_node].next_in_list() {
+                children.remove(next, control_nodes);
+                following_nodes.insert_last(next, control_nodes);
+            }
+            children.remove(loop_node, control_nodes);
+            children.append(body_children, control_nodes);
+            children.append(following_nodes, control_nodes);
+            return;
+        }
+
+        if !(hints.unroll || hints.partial_count.is_some()) {
+            return;
+        }
+        let factor = hints
+            .partial_count
+            .unwrap_or(self.options.partial_unroll_factor);
+        let trip_count_multiple = trip_count.or(hints.iteration_multiple);
+        if factor < 2 || !trip_count_multiple.is_some_and(|m| m > 0 && m % factor == 0) {
+            return;
+        }
+
+        let body_inputs = (0..func_def_body.at(body).def().inputs.len())
+            .map(|input_idx| Value::ControlRegionInput {
+                region: body,
+                input_idx: input_idx.try_into().unwrap(),
+            })
+            .collect();
+        self.unroll_loop_body(func_def_body, body, body_inputs, factor - 1);
+
+        // NOTE(eddyb) the remaining `LoopControl` hints (e.g. `MaxIterations`)
+        // describe the loop before unrolling, so they have to be removed.
+        let attrs = &mut func_def_body.control_nodes[loop_node].attrs;
+        *attrs = self.cx.intern(AttrSetDef {
+            attrs: self.cx[*attrs]
+                .attrs
+                .iter()
+                .filter(|attr| !UnrollHints::is_loop_control_attr(attr))
+                .cloned()
+                .collect(),
+        });
+    }
+
+    /// Prepend `extra_copies` copies of the contents of the loop `body` to itself,
+    /// with the first copy using `first_inputs` (instead of the `body` inputs),
+    /// and every other copy (including the original contents of `body`, which
+    /// become the last copy) using the outputs of the previous copy as its inputs.
+    fn unroll_loop_body(
+        &self,
+        func_def_body: &mut FuncDefBody,
+        body: ControlRegion,
+        first_inputs: SmallVec<[Value; 2]>,
+        extra_copies: u32,
+    ) {
+        let mut inputs = first_inputs;
+        let mut copies = EntityList::empty();
+        for _ in 0..extra_copies {
+            let (children, outputs) = self.clone_region_contents(func_def_body, body, &inputs);
+            copies.append(children, &mut func_def_body.control_nodes);
+            inputs = outputs;
+        }
+
+        // NOTE(eddyb) this also replaces any uses of the `body` inputs from
+        // after the loop, which observe the inputs of the last iteration,
+        // and has to be done before attaching the copies to `body`, so that
+        // the first one's uses of the `body` inputs (if any) remain unchanged.
+        let replacements: FxHashMap<_, _> = (0..inputs.len())
+            .map(|input_idx| Value::ControlRegionInput {
+                region: body,
+                input_idx: input_idx.try_into().unwrap(),
+            })
+            .zip(inputs)
+            .filter(|(old, new)| old != new)
+            .collect();
+        if !replacements.is_empty() {
+            func_def_body.inner_in_place_transform_with(&mut ReplaceValueWith(|v| {
+                replacements.get(&v).copied()
+            }));
+        }
+
+        let original_children = mem::take(&mut func_def_body.control_regions[body].children);
+        copies.append(original_children, &mut func_def_body.control_nodes);
+        func_def_body.control_regions[body].children = copies;
+    }
+
+    /// Deep-clone the contents (i.e. children) of `region`, using `inputs` in
+    /// place of `region`'s inputs, and returning the cloned children alongside
+    /// the (correspondingly cloned) values of `region`'s outputs.
+    fn clone_region_contents(
+        &self,
+        func_def_body: &mut FuncDefBody,
+        region: ControlRegion,
+        inputs: &[Value],
+    ) -> (EntityList<ControlNode>, SmallVec<[Value; 2]>) {
+        let mut replacements: FxHashMap<_, _> = inputs
+            .iter()
+            .enumerate()
+            .map(|(input_idx, &new)| {
+                let old = Value::ControlRegionInput {
+                    region,
+                    input_idx: input_idx.try_into().unwrap(),
+                };
+                (old, new)
+            })
+            .collect();
+
+        let original_children = func_def_body.at(region).def().children;
+        let children = self.clone_children(func_def_body, original_children, &mut replacements);
+
+        // NOTE(eddyb) all the definitions had to be cloned before any uses
+        // could be replaced (as the replacements were still being collected).
+        let mut replacer = ReplaceValueWith(|v| replacements.get(&v).copied());
+        let mut cloned_nodes = func_def_body.at_mut(children).into_iter();
+        while let Some(mut func_at_control_node) = cloned_nodes.next() {
+            func_at_control_node.inner_in_place_transform_with(&mut replacer);
+        }
+        let outputs = func_def_body
+            .at(region)
+            .def()
+            .outputs
+            .iter()
+            .map(|v| replacements.get(v).copied().unwrap_or(*v))
+            .collect();
+
+        (children, outputs)
+    }
+
+    /// Deep-clone `children` (without replacing any uses in the cloned nodes),
+    /// collecting the replacements of the values they define, into `replacements`.
+    fn clone_children(
+        &self,
+        func_def_body: &mut FuncDefBody,
+        children: EntityList<ControlNode>,
+        replacements: &mut FxHashMap<Value, Value>,
+    ) -> EntityList<ControlNode> {
+        let original_nodes: SmallVec<[_; 8]> = func_def_body
+            .at(children)
+            .into_iter()
+            .map(|func_at_control_node| func_at_control_node.position)
+            .collect();
+
+        let mut cloned_children = EntityList::empty();
+        for original_node in original_nodes {
+            let original_node_def = func_def_body.at(original_node).def();
+            let mut kind = original_node_def.kind.clone();
+            let (attrs, outputs) = (original_node_def.attrs, original_node_def.outputs.clone());
+            match &mut kind {
+                ControlNodeKind::Block { insts } => {
+                    let original_insts: SmallVec<[_; 8]> = func_def_body
+                        .at(*insts)
+                        .into_iter()
+                        .map(|func_at_inst| func_at_inst.position)
+                        .collect();
+                    *insts = EntityList::empty();
+                    for original_inst in original_insts {
+                        let inst_def = func_def_body.at(original_inst).def().clone();
+                        let inst = func_def_body.data_insts.define(self.cx, inst_def.into());
+                        insts.insert_last(inst, &mut func_def_body.data_insts);
+                        replacements.insert(
+                            Value::DataInstOutput(original_inst),
+                            Value::DataInstOutput(inst),
+                        );
+                    }
+                }
+                ControlNodeKind::Select { cases, .. } => {
+                    for case in cases {
+                        *case = self.clone_region(func_def_body, *case, replacements);
+                    }
+                }
+                ControlNodeKind::Loop { body, .. } => {
+                    *body = self.clone_region(func_def_body, *body, replacements);
+                }
+                ControlNodeKind::ExitInvocation { .. } => {}
+            }
+
+            let output_count = outputs.len();
+            let node = func_def_body.control_nodes.define(
+                self.cx,
+                ControlNodeDef {
+                    attrs,
+                    kind,
+                    outputs,
+                }
+                .into(),
+            );
+            cloned_children.insert_last(node, &mut func_def_body.control_nodes);
+            for output_idx in 0..output_count {
+                let output_idx = output_idx.try_into().unwrap();
+                replacements.insert(
+                    Value::ControlNodeOutput {
+                        control_node: original_node,
+                        output_idx,
+                    },
+                    Value::ControlNodeOutput {
+                        control_node: node,
+                        output_idx,
+                    },
+                );
+            }
+        }
+        cloned_children
+    }
+
+    /// Deep-clone `region` (see also [`LoopUnroller::clone_children`]).
+    fn clone_region(
+        &self,
+        func_def_body: &mut FuncDefBody,
+        region: ControlRegion,
+        replacements: &mut FxHashMap<Value, Value>,
+    ) -> ControlRegion {
+        let original_children = func_def_body.at(region).def().children;
+        let children = self.clone_children(func_def_body, original_children, replacements);
+
+        let original_region_def = func_def_body.at(region).def();
+        let (inputs, outputs) = (
+            original_region_def.inputs.clone(),
+            original_region_def.outputs.clone(),
+        );
+        let input_count = inputs.len();
+        let cloned_region = func_def_body.control_regions.define(
+            self.cx,
+            ControlRegionDef {
+                inputs,
+                children,
+                outputs,
+            },
+        );
+        for input_idx in 0..input_count {
+            let input_idx = input_idx.try_into().unwrap();
+            replacements.insert(
+                Value::ControlRegionInput { region, input_idx },
+                Value::ControlRegionInput {
+                    region: cloned_region,
+                    input_idx,
+                },
+            );
+        }
+        cloned_region
+    }
+
+    /// Compute the trip count (i.e. the number of times the body is executed)
+    /// of `loop_node`, by evaluating its `repeat_condition` (and the loop state,
+    /// i.e. the loop body outputs) on constants, for every iteration.
+    fn const_trip_count(&self, func_def_body: &FuncDefBody, loop_node: ControlNode) -> Option<u32> {
+        let (initial_inputs, body, repeat_condition) = match &func_def_body.at(loop_node).def().kind
+        {
+            ControlNodeKind::Loop {
+                initial_inputs,
+                body,
+                repeat_condition,
+            } => (initial_inputs, *body, *repeat_condition),
+            _ => unreachable!(),
+        };
+
+        // NOTE(eddyb) only instructions directly in the loop body (i.e. not in
+        // any nested control-flow) are always executed, on every iteration.
+        let mut body_insts = FxHashSet::default();
+        for func_at_control_node in func_def_body.at(body).at_children() {
+            if let ControlNodeKind::Block { insts } = func_at_control_node.def().kind {
+                body_insts.extend(
+                    func_def_body
+                        .at(insts)
+                        .into_iter()
+                        .map(|func_at_inst| func_at_inst.position),
+                );
+            }
+        }
+
+        let mut evaluator = IterationEvaluator {
+            cx: self.cx,
+            func_def_body,
+            body,
+            body_insts: &body_insts,
+            inputs: initial_inputs
+                .iter()
+                .map(|&v| match v {
+                    Value::Const(ct) => Some(ct),
+                    _ => None,
+                })
+                .collect(),
+            evaluated: FxHashMap::default(),
+        };
+        let wk = &spv::spec::Spec::get().well_known;
+        for trip_count in 1..=MAX_EVALUATED_TRIP_COUNT {
+            let cond = evaluator.eval(repeat_condition)?;
+            match &self.cx[cond].ctor {
+                ConstCtor::SpvInst(inst) if inst.opcode == wk.OpConstantTrue => {}
+                ConstCtor::SpvInst(inst) if inst.opcode == wk.OpConstantFalse => {
+                    return Some(trip_count);
+                }
+                _ => return None,
+            }
+
+            let next_inputs = func_def_body
+                .at(body)
+                .def()
+                .outputs
+                .iter()
+                .map(|&v| evaluator.eval(v))
+                .collect();
+            evaluator.inputs = next_inputs;
+            evaluator.evaluated.clear();
+        }
+        None
+    }
+}
+
+/// Evaluator of values computed by a loop body, in a single iteration of the
+/// loop (see [`LoopUnroller::const_trip_count`]).
+struct IterationEvaluator<'a> {
+    cx: &'a Context,
+    func_def_body: &'a FuncDefBody,
+
+    body: ControlRegion,
+    body_insts: &'a FxHashSet<DataInst>,
+
+    /// Values of the loop body inputs, for the current iteration.
+    inputs: SmallVec<[Option<Const>; 2]>,
+
+    evaluated: FxHashMap<DataInst, Option<Const>>,
+}
+
+impl IterationEvaluator<'_> {
+    fn eval(&mut self, v: Value) -> Option<Const> {
+        match v {
+            Value::Const(ct) => Some(ct),
+            Value::ControlRegionInput { region, input_idx } if region == self.body => {
+                self.inputs[input_idx as usize]
+            }
+            Value::DataInstOutput(inst) if self.body_insts.contains(&inst) => {
+                if let Some(&cached) = self.evaluated.get(&inst) {
+                    return cached;
+                }
+                let inst_def = self.func_def_body.at(inst).def();
+                let result = match (&inst_def.kind, inst_def.output_type) {
+                    (DataInstKind::SpvInst(spv_inst), Some(ty)) if inst_def.is_pure(self.cx) => {
+                        inst_def
+                            .inputs
+                            .iter()
+                            .map(|&input| self.eval(input))
+                            .collect::<Option<SmallVec<[_; 3]>>>()
+                            .and_then(|args| eval_spv_inst(self.cx, spv_inst, ty, &args))
+                    }
+                    _ => None,
+                };
+                self.evaluated.insert(inst, result);
+                result
+            }
+            _ => None,
+        }
+    }
+}
+
@@ -0,0 +1,374 @@
+//! Vectorization (i.e. fusing identical scalar operations back into vector ones).
+
+use crate::passes::legalize::reachable_funcs;
+use crate::passes::scalarize::is_componentwise;
+use crate::spv::{
+    self,
+    fold::{composite_const, vector_type},
+};
+use crate::transform::{InnerInPlaceTransform, ReplaceValueWith};
+use crate::{
+    AttrSet, Const, Context, ControlNodeKind, ControlRegion, DataInst, DataInstDef, DataInstKind,
+    DeclDef, EntityList, FuncDefBody, Module, Type, TypeCtor, TypeCtorArg, TypeDef, Value,
+};
+use rustc_hash::FxHashMap;
+use smallvec::SmallVec;
+use std::mem;
+
+/// Vectorize groups of identical scalar operations in all function definitions
+/// in `module`, i.e. find `OpCompositeConstruct`s of vectors (from one scalar
+/// per component) whose components are all computed "in lockstep" (also known
+/// as "superword-level parallelism", or SLP), e.g.:
+/// ```text
+/// x = spv.OpFAdd(spv.OpCompositeExtract(a, 0), spv.OpCompositeExtract(b, 0))
+/// y = spv.OpFAdd(spv.OpCompositeExtract(a, 1), spv.OpCompositeExtract(b, 1))
+/// v = spv.OpCompositeConstruct(x, y)
+/// ```
+/// becomes (with the `OpCompositeConstruct` replaced in-place):
+/// ```text
+/// v = spv.OpFAdd(a, b)
+/// ```
+///
+/// The (componentwise) operations are matched recursively, with their inputs
+/// being vectorized from any mix of: components of the same vector (i.e. each
+/// extracted from the matching component of that vector), constants (becoming
+/// one vector constant), the same value in every component (becoming a "splat"
+/// `OpCompositeConstruct`), or more operations (matched in the same way).
+///
+/// This is effectively the opposite of [`scalarize`](crate::passes::scalarize),
+/// and can repair the (overly) scalarized output of some compilers, with any
+/// scalar instructions which become unused left for [`dce`](crate::passes::dce)
+/// to remove (while `OpCompositeConstruct`s of all the components of a vector,
+/// in order, are replaced with that vector, wherever they're used).
+pub fn vectorize_scalar_ops(module: &mut Module) {
+    let cx = &module.cx();
+
+    for func in reachable_funcs(module) {
+        if let DeclDef::Present(func_def_body) = &mut module.funcs[func].def {
+            let regions = match &func_def_body.unstructured_cfg {
+                None => vec![func_def_body.body],
+                Some(cfg) => cfg.rev_post_order(func_def_body).collect(),
+            };
+
+            let mut vectorizer = Vectorizer {
+                cx,
+                func_def_body,
+                replacements: FxHashMap::default(),
+            };
+            for region in regions {
+                vectorizer.vectorize_in_region(region);
+            }
+
+            let Vectorizer {
+                func_def_body,
+                replacements,
+                ..
+            } = vectorizer;
+            if replacements.is_empty() {
+                continue;
+            }
+
+            func_def_body.inner_in_place_transform_with(&mut ReplaceValueWith(|mut v| {
+                let original = v;
+                while let Some(&new) = replacements.get(&v) {
+                    v = new;
+                }
+                (v != original).then_some(v)
+            }));
+        }
+    }
+}
+
+/// Vector value, computed from the scalar values of all of its components.
+enum Lanes {
+    /// An existing vector, with each component extracted from it.
+    Vector(Value),
+
+    /// The same value in every component (of a vector of type `ty`).
+    Splat {
+        value: Value,
+        ty: Type,
+    },
+
+    Const(Const),
+
+    /// The same (componentwise) operation, applied to every component (of a
+    /// vector of type `ty`).
+    Op {
+        /// Attributes of the first component's instruction (only used for its
+        /// source locations, see [`AttrSet::with_debug_locations_from`]).
+        attrs: AttrSet,
+
+        opcode: spv::spec::Opcode,
+        ty: Type,
+        inputs: Vec<Lanes>,
+    },
+}
+
+struct Vectorizer<'a> {
+    cx: &'a Context,
+    func_def_body: &'a mut FuncDefBody,
+
+    /// Outputs of `OpCompositeConstruct`s, mapped to the vectors they rebuild.
+    replacements: FxHashMap<Value, Value>,
+}
+
+impl Vectorizer<'_> {
+    fn resolve(&self, mut v: Value) -> Value {
+        while let Some(&new) = self.replacements.get(&v) {
+            v = new;
+        }
+        v
+    }
+
+    fn type_of(&self, v: Value) -> Type {
+        self.func_def_body.at(v).type_of(self.cx)
+    }
+
+    fn vector_type(&self, component_type: Type, count: u32) -> Type {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        self.cx.intern(TypeDef {
+            attrs: AttrSet::default(),
+            ctor: TypeCtor::SpvInst(spv::Inst {
+                opcode: wk.OpTypeVector,
+                imms: [spv::Imm::Short(wk.LiteralInteger, count)]
+                    .into_iter()
+                    .collect(),
+            }),
+            ctor_args: [TypeCtorArg::Type(component_type)].into_iter().collect(),
+        })
+    }
+
+    /// Try to find a way to compute all of `lanes` as one vector (of type `ty`).
+    fn match_lanes(&self, lanes: &[Value], ty: Type) -> Option<Lanes> {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        let lanes: SmallVec<[_; 4]> = lanes.iter().map(|&v| self.resolve(v)).collect();
+
+        let consts: Option<SmallVec<[_; 4]>> = lanes
+            .iter()
+            .map(|&v| match v {
+                Value::Const(ct) => Some(ct),
+                _ => None,
+            })
+            .collect();
+        if let Some(consts) = consts {
+            return Some(Lanes::Const(composite_const(self.cx, ty, consts)));
+        }
+        if lanes.iter().all(|&v| v == lanes[0]) {
+            return Some(Lanes::Splat {
+                value: lanes[0],
+                ty,
+            });
+        }
+
+        let lane_insts: SmallVec<[_; 4]> = lanes
+            .iter()
+            .map(|&v| match v {
+                Value::DataInstOutput(inst) => {
+                    let inst_def = &self.func_def_body.data_insts[inst];
+                    match &inst_def.kind {
+                        DataInstKind::SpvInst(spv_inst) => {
+                            Some((inst_def.attrs, spv_inst, &inst_def.inputs))
+                        }
+                        _ => None,
+                    }
+                }
+                _ => None,
+            })
+            .collect::<Option<_>>()?;
+        let (first_attrs, first_spv_inst, first_inputs) = lane_insts[0];
+
+        // Each component extracted from the same component of the same vector.
+        if first_spv_inst.opcode == wk.OpCompositeExtract {
+            let vector = self.resolve(*first_inputs.first()?);
+            let all_extracted_from_vector =
+                (0..).zip(&lane_insts).all(|(i, &(_, spv_inst, inputs))| {
+                    spv_inst.opcode == wk.OpCompositeExtract
+                        && matches!(spv_inst.imms[..], [spv::Imm::Short(_, idx)] if idx == i)
+                        && inputs.len() == 1
+                        && self.resolve(inputs[0]) == vector
+                });
+            if all_extracted_from_vector && self.type_of(vector) == ty {
+                return Some(Lanes::Vector(vector));
+            }
+            return None;
+        }
+
+        // The same componentwise operation in every component.
+        let opcode = first_spv_inst.opcode;
+        let all_same_op = lane_insts.iter().all(|&(_, spv_inst, inputs)| {
+            spv_inst.opcode == opcode
+                && spv_inst.imms.is_empty()
+                && inputs.len() == first_inputs.len()
+        });
+        if !(is_componentwise(opcode) && first_spv_inst.imms.is_empty() && all_same_op) {
+            return None;
+        }
+        let count = u32::try_from(lanes.len()).unwrap();
+        let inputs = (0..first_inputs.len())
+            .map(|input_idx| {
+                let input_lanes: SmallVec<[_; 4]> = lane_insts
+                    .iter()
+                    .map(|&(_, _, inputs)| inputs[input_idx])
+                    .collect();
+                let input_type = self.type_of(input_lanes[0]);
+                if input_lanes.iter().any(|&v| self.type_of(v) != input_type) {
+                    return None;
+                }
+                self.match_lanes(&input_lanes, self.vector_type(input_type, count))
+            })
+            .collect::<Option<_>>()?;
+        Some(Lanes::Op {
+            attrs: first_attrs,
+            opcode,
+            ty,
+            inputs,
+        })
+    }
+
+    /// Compute `lanes` as a vector, using new instructions (added to `new_insts`)
+    /// where needed.
+    fn materialize(&mut self, lanes: Lanes, new_insts: &mut Vec<DataInst>) -> Value {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        let (source_attrs, opcode, ty, inputs) = match lanes {
+            Lanes::Vector(v) => return v,
+            Lanes::Const(ct) => return Value::Const(ct),
+            Lanes::Splat { value, ty } => {
+                let (_, count) = vector_type(self.cx, ty).unwrap();
+                (
+                    AttrSet::default(),
+                    wk.OpCompositeConstruct,
+                    ty,
+                    (0..count).map(|_| value).collect(),
+                )
+            }
+            Lanes::Op {
+                attrs,
+                opcode,
+                ty,
+                inputs,
+            } => (
+                attrs,
+                opcode,
+                ty,
+                inputs
+                    .into_iter()
+                    .map(|input| self.materialize(input, new_insts))
+                    .collect(),
+            ),
+        };
+        let inst = self.func_def_body.data_insts.define(
+            self.cx,
+            DataInstDef {
+                attrs: AttrSet::default().with_debug_locations_from(self.cx, source_attrs),
+                kind: DataInstKind::SpvInst(opcode.into()),
+                output_type: Some(ty),
+                inputs,
+            }
+            .into(),
+        );
+        new_insts.push(inst);
+        Value::DataInstOutput(inst)
+    }
+
+    fn vectorize_in_region(&mut self, region: ControlRegion) {
+        let children: SmallVec<[_; 8]> = self
+            .func_def_body
+            .at(region)
+            .at_children()
+            .into_iter()
+            .map(|func_at_control_node| func_at_control_node.position)
+            .collect();
+        for control_node in children {
+            let mut old_insts = match &mut self.func_def_body.control_nodes[control_node].kind {
+                ControlNodeKind::Block { insts } => mem::take(insts),
+                ControlNodeKind::Select { cases, .. } => {
+                    for case in cases.clone() {
+                        self.vectorize_in_region(case);
+                    }
+                    continue;
+                }
+                &mut ControlNodeKind::Loop { body, .. } => {
+                    self.vectorize_in_region(body);
+                    continue;
+                }
+                ControlNodeKind::ExitInvocation { .. } => continue,
+            };
+
+            // NOTE(eddyb) as `EntityList` doesn't support inserting before a
+            // node, the whole list of instructions is rebuilt (see also
+            // `passes::strength_reduce`).
+            let original_insts: SmallVec<[_; 8]> = self
+                .func_def_body
+                .at(old_insts)
+                .into_iter()
+                .map(|func_at_inst| func_at_inst.position)
+                .collect();
+            let mut insts = EntityList::empty();
+            for inst in original_insts {
+                old_insts.remove(inst, &mut self.func_def_body.data_insts);
+
+                let mut new_insts = vec![];
+                self.vectorize_construct(inst, &mut new_insts);
+                for new_inst in new_insts.into_iter().chain([inst]) {
+                    insts.insert_last(new_inst, &mut self.func_def_body.data_insts);
+                }
+            }
+            match &mut self.func_def_body.control_nodes[control_node].kind {
+                ControlNodeKind::Block { insts: block_insts } => *block_insts = insts,
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    /// Vectorize `inst`, if it's an `OpCompositeConstruct` of a vector (from one
+    /// scalar per component), by replacing it in-place with a vector operation
+    /// (with any new instructions added to `new_insts`), or by replacing all
+    /// uses of its output with the existing vector it's rebuilding.
+    fn vectorize_construct(&mut self, inst: DataInst, new_insts: &mut Vec<DataInst>) -> Option<()> {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        let inst_def = &self.func_def_body.data_insts[inst];
+        match &inst_def.kind {
+            DataInstKind::SpvInst(spv_inst) if spv_inst.opcode == wk.OpCompositeConstruct => {}
+            _ => return None,
+        }
+        let ty = inst_def.output_type?;
+        let (_, count) = vector_type(self.cx, ty)?;
+        if inst_def.inputs.len() != count as usize
+            || inst_def
+                .inputs
+                .iter()
+                .any(|&v| vector_type(self.cx, self.type_of(v)).is_some())
+        {
+            return None;
+        }
+
+        match self.match_lanes(&inst_def.inputs.clone(), ty)? {
+            Lanes::Vector(v) => {
+                self.replacements.insert(Value::DataInstOutput(inst), v);
+            }
+            Lanes::Op {
+                attrs: _,
+                opcode,
+                ty: _,
+                inputs,
+            } => {
+                let inputs = inputs
+                    .into_iter()
+                    .map(|input| self.materialize(input, new_insts))
+                    .collect();
+                let inst_def = &mut self.func_def_body.data_insts[inst];
+                inst_def.kind = DataInstKind::SpvInst(opcode.into());
+                inst_def.inputs = inputs;
+            }
+            // NOTE(eddyb) these are already as cheap as they can be (with
+            // constant folding being responsible for `Const`s).
+            Lanes::Splat { .. } | Lanes::Const(_) => return None,
+        }
+        Some(())
+    }
+}
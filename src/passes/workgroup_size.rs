@@ -0,0 +1,243 @@
+//! Workgroup size specialization (i.e. replacing `LocalSize`/`LocalSizeId`
+//! execution modes, and the `WorkgroupSize` built-in, with a concrete size).
+
+use crate::passes::const_fold::fold_consts;
+use crate::passes::legalize::reachable_global_vars_and_funcs;
+use crate::passes::specialize::SpecializeOptions;
+use crate::passes::unreachable::remove_unreachable_code;
+use crate::spv::fold::{eval_spv_inst, is_ordinary_const, splat_const, vector_type, ScalarValue};
+use crate::transform::{Transformed, Transformer};
+use crate::{spv, Attr, AttrSet, AttrSetDef, Const, ConstCtor, ConstDef, Context, Module, Type};
+use rustc_hash::FxHashMap;
+
+/// Specialize the workgroup size of all entry-points in `module` to `size`, i.e.:
+/// * `LocalSize`/`LocalSizeId` execution modes are replaced with `LocalSize`
+///   execution modes using `size` (entry-points without either are left alone)
+/// * constants decorated with `BuiltIn WorkgroupSize` (i.e. `gl_WorkGroupSize`,
+///   often an `OpSpecConstantComposite`, for `local_size_{x,y,z}_id`) become
+///   `OpConstantComposite`s of `size`, and all constants depending on them
+///   (e.g. `OpSpecConstantOp`s extracting components, or computing array
+///   lengths from them) are folded, wherever possible
+///
+/// With [`SpecializeOptions::remove_dead_code`], [`fold_consts`] and
+/// [`remove_unreachable_code`] are also used to fold any code reading the
+/// workgroup size, in function bodies.
+///
+/// This is usually done at pipeline creation time, when the workgroup size is
+/// known, to allow further optimizations to take advantage of it (and the
+/// specialization constants backing it, if any, should be given matching values
+/// by [`specialize_consts`](crate::passes::specialize::specialize_consts), or
+/// at pipeline creation time, as they're not themselves replaced).
+//
+// FIXME(eddyb) support `Input` variables decorated with `BuiltIn WorkgroupSize`
+// (only allowed in `Kernel` modules), by replacing loads from them.
+pub fn specialize_workgroup_size(module: &mut Module, size: [u32; 3], options: &SpecializeOptions) {
+    let wk = &spv::spec::Spec::get().well_known;
+    let cx = &module.cx();
+
+    let local_size = Attr::SpvAnnotation(spv::Inst {
+        opcode: wk.OpExecutionMode,
+        imms: [spv::Imm::Short(wk.ExecutionMode, wk.LocalSize)]
+            .into_iter()
+            .chain(size.map(|x| spv::Imm::Short(wk.LiteralInteger, x)))
+            .collect(),
+    });
+
+    let mut specializer = WorkgroupSizeSpecializer {
+        cx,
+        size,
+
+        transformed_types: FxHashMap::default(),
+        transformed_consts: FxHashMap::default(),
+    };
+    let (global_vars, funcs) = reachable_global_vars_and_funcs(module);
+    for gv in global_vars {
+        specializer.in_place_transform_global_var_decl(&mut module.global_vars[gv]);
+    }
+    for func in funcs {
+        let func_decl = &mut module.funcs[func];
+        func_decl.attrs = with_local_size(cx, func_decl.attrs, &local_size);
+        specializer.in_place_transform_func_decl(func_decl);
+    }
+
+    if options.remove_dead_code {
+        fold_consts(module);
+        remove_unreachable_code(module);
+    }
+}
+
+/// Replace any `LocalSize`/`LocalSizeId` execution modes in `attrs` with `local_size`.
+fn with_local_size(cx: &Context, attrs: AttrSet, local_size: &Attr) -> AttrSet {
+    let wk = &spv::spec::Spec::get().well_known;
+
+    let is_local_size = |attr: &Attr| match attr {
+        Attr::SpvAnnotation(spv::Inst { opcode, imms }) => {
+            *opcode == wk.OpExecutionMode
+                && matches!(
+                    imms.first(),
+                    Some(&spv::Imm::Short(_, mode)) if mode == wk.LocalSize
+                )
+        }
+        Attr::SpvExecutionModeId {
+            inst: spv::Inst { imms, .. },
+            ..
+        } => matches!(
+            imms.first(),
+            Some(&spv::Imm::Short(_, mode)) if mode == wk.LocalSizeId
+        ),
+        _ => false,
+    };
+    if !cx[attrs].attrs.iter().any(is_local_size) {
+        return attrs;
+    }
+
+    let mut attrs = cx[attrs].attrs.clone();
+    attrs.retain(|attr| !is_local_size(attr));
+    attrs.insert(local_size.clone());
+    cx.intern(AttrSetDef { attrs })
+}
+
+/// Returns `true` if `attrs` contain a `BuiltIn WorkgroupSize` decoration.
+fn is_workgroup_size_builtin(attrs: &AttrSetDef) -> bool {
+    let wk = &spv::spec::Spec::get().well_known;
+
+    attrs.attrs.iter().any(|attr| match attr {
+        Attr::SpvAnnotation(spv::Inst { opcode, imms }) if *opcode == wk.OpDecorate => matches!(
+            imms[..],
+            [spv::Imm::Short(_, decoration), spv::Imm::Short(_, builtin)]
+                if decoration == wk.BuiltIn && builtin == wk.WorkgroupSize
+        ),
+        _ => false,
+    })
+}
+
+/// [`Transformer`] replacing `BuiltIn WorkgroupSize` constants, and folding
+/// all constants depending on them, including those nested in types (e.g.
+/// array lengths).
+struct WorkgroupSizeSpecializer<'a> {
+    cx: &'a Context,
+    size: [u32; 3],
+
+    // FIXME(eddyb) build some automation to avoid ever repeating these.
+    transformed_types: FxHashMap<Type, Transformed<Type>>,
+    transformed_consts: FxHashMap<Const, Transformed<Const>>,
+}
+
+impl WorkgroupSizeSpecializer<'_> {
+    /// Get the `OpConstantComposite` equivalent of the `BuiltIn WorkgroupSize`
+    /// constant definition `ct_def`, if it has the expected type (`uvec3`).
+    fn workgroup_size_const(&self, ct_def: &ConstDef) -> Option<Const> {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        let cx = self.cx;
+        let elem_type = match vector_type(cx, ct_def.ty) {
+            Some((elem_type, 3)) => elem_type,
+            _ => return None,
+        };
+        let elements = self
+            .size
+            .iter()
+            .map(|&x| {
+                splat_const(
+                    cx,
+                    elem_type,
+                    ScalarValue::Int {
+                        width: 32,
+                        bits: x.into(),
+                    },
+                )
+            })
+            .collect::<Option<_>>()?;
+        Some(cx.intern(ConstDef {
+            attrs: ct_def.attrs,
+            ty: ct_def.ty,
+            ctor: ConstCtor::SpvInst(wk.OpConstantComposite.into()),
+            ctor_args: elements,
+        }))
+    }
+
+    /// Fold `ct` (assumed to have its operands already transformed), if it's
+    /// a specialization constant operation with only ordinary constant operands.
+    fn fold_const(&self, ct: Const) -> Const {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        let cx = self.cx;
+        let ct_def = &cx[ct];
+        let evaluated = match &ct_def.ctor {
+            ConstCtor::SpecConstOp(inst) => eval_spv_inst(cx, inst, ct_def.ty, &ct_def.ctor_args),
+            ConstCtor::SpvInst(inst)
+                if inst.opcode == wk.OpSpecConstantComposite
+                    && ct_def
+                        .ctor_args
+                        .iter()
+                        .all(|&arg| is_ordinary_const(cx, arg)) =>
+            {
+                Some(cx.intern(ConstDef {
+                    attrs: AttrSet::default(),
+                    ty: ct_def.ty,
+                    ctor: ConstCtor::SpvInst(spv::Inst {
+                        opcode: wk.OpConstantComposite,
+                        imms: inst.imms.clone(),
+                    }),
+                    ctor_args: ct_def.ctor_args.clone(),
+                }))
+            }
+            _ => None,
+        };
+        match evaluated {
+            Some(evaluated) => {
+                let evaluated_def = &cx[evaluated];
+                cx.intern(ConstDef {
+                    attrs: ct_def.attrs,
+                    ty: evaluated_def.ty,
+                    ctor: evaluated_def.ctor.clone(),
+                    ctor_args: evaluated_def.ctor_args.clone(),
+                })
+            }
+            None => ct,
+        }
+    }
+}
+
+impl Transformer for WorkgroupSizeSpecializer<'_> {
+    // FIXME(eddyb) build some automation to avoid ever repeating these.
+    fn transform_type_use(&mut self, ty: Type) -> Transformed<Type> {
+        if let Some(&cached) = self.transformed_types.get(&ty) {
+            return cached;
+        }
+        let transformed = self
+            .transform_type_def(&self.cx[ty])
+            .map(|ty_def| self.cx.intern(ty_def));
+        self.transformed_types.insert(ty, transformed);
+        transformed
+    }
+    fn transform_const_use(&mut self, ct: Const) -> Transformed<Const> {
+        if let Some(&cached) = self.transformed_consts.get(&ct) {
+            return cached;
+        }
+
+        let ct_def = &self.cx[ct];
+        let replacement = if is_workgroup_size_builtin(&self.cx[ct_def.attrs]) {
+            self.workgroup_size_const(ct_def)
+        } else {
+            None
+        };
+        let transformed = match replacement {
+            Some(replacement) => Transformed::Changed(replacement),
+            None => {
+                let mut folded = ct;
+                self.transform_const_def(ct_def)
+                    .map(|ct_def| self.cx.intern(ct_def))
+                    .apply_to(&mut folded);
+                let folded = self.fold_const(folded);
+                if folded == ct {
+                    Transformed::Unchanged
+                } else {
+                    Transformed::Changed(folded)
+                }
+            }
+        };
+        self.transformed_consts.insert(ct, transformed);
+        transformed
+    }
+}
@@ -0,0 +1,233 @@
+//! Zero-initialization of (otherwise uninitialized) `Function`/`Workgroup` variables.
+
+use crate::passes::legalize::{
+    reachable_funcs, reachable_global_vars_and_funcs, reachable_global_vars_and_funcs_from_exportee,
+};
+use crate::spv;
+use crate::{
+    AddrSpace, AttrSet, Const, ConstCtor, ConstDef, Context, ControlNodeDef, ControlNodeKind,
+    DataInstDef, DataInstKind, DeclDef, EntityList, ExportKey, Exportee, FuncDefBody, GlobalVar,
+    Module, Type, Value,
+};
+use smallvec::SmallVec;
+
+/// Options for zero-initialization (see [`zero_initialize_vars`]).
+#[derive(Clone)]
+pub struct ZeroInitOptions {
+    /// Whether to zero-initialize `Function` variables (through their initializer).
+    pub function_vars: bool,
+
+    /// Whether to zero-initialize `Workgroup` variables.
+    pub workgroup_vars: bool,
+
+    /// Whether `Workgroup` variables can be zero-initialized through their
+    /// initializer (which requires `VK_KHR_zero_initialize_workgroup_memory`),
+    /// instead of stores at the start of every entry-point using them.
+    pub workgroup_initializers: bool,
+}
+
+impl Default for ZeroInitOptions {
+    fn default() -> Self {
+        Self {
+            function_vars: true,
+            workgroup_vars: true,
+            workgroup_initializers: false,
+        }
+    }
+}
+
+/// Zero-initialize all the `Function`/`Workgroup` variables in `module` which
+/// lack an initializer (according to `options`), i.e. guarantee that they're
+/// never read from before being written to, both to work around drivers with
+/// uninitialized memory bugs, and to implement WGSL-like semantics.
+///
+/// Unless [`ZeroInitOptions::workgroup_initializers`] is enabled, `Workgroup`
+/// variables are zeroed by `OpStore`s at the start of every entry-point using
+/// them, followed by a workgroup-wide `OpControlBarrier` (so that no invocation
+/// can observe another invocation's store happening after its own writes).
+//
+// FIXME(eddyb) every invocation stores zero to the whole of every `Workgroup`
+// variable, which could be split between the invocations in the workgroup
+// instead (but that requires the `LocalInvocationIndex` built-in).
+pub fn zero_initialize_vars(module: &mut Module, options: &ZeroInitOptions) {
+    let wk = &spv::spec::Spec::get().well_known;
+    let cx = &module.cx();
+
+    if options.function_vars {
+        for func in reachable_funcs(module) {
+            if let DeclDef::Present(func_def_body) = &mut module.funcs[func].def {
+                zero_initialize_function_vars(cx, func_def_body);
+            }
+        }
+    }
+
+    if !options.workgroup_vars {
+        return;
+    }
+
+    let is_uninit_workgroup_var = |module: &Module, gv: GlobalVar| {
+        let gv_decl = &module.global_vars[gv];
+        gv_decl.addr_space == AddrSpace::SpvStorageClass(wk.Workgroup)
+            && match &gv_decl.def {
+                DeclDef::Imported(_) => false,
+                DeclDef::Present(gv_def_body) => gv_def_body.initializer.is_none(),
+            }
+    };
+
+    if options.workgroup_initializers {
+        let (global_vars, _) = reachable_global_vars_and_funcs(module);
+        for gv in global_vars {
+            if !is_uninit_workgroup_var(module, gv) {
+                continue;
+            }
+            let gv_decl = &mut module.global_vars[gv];
+            let initializer = gv_decl
+                .type_of_ptr_to
+                .pointee_type(cx)
+                .map(|pointee| const_null(cx, pointee));
+            if let DeclDef::Present(gv_def_body) = &mut gv_decl.def {
+                gv_def_body.initializer = initializer;
+            }
+        }
+        return;
+    }
+
+    let entry_points: SmallVec<[_; 4]> = module
+        .exports
+        .iter()
+        .filter_map(|(export_key, &exportee)| match (export_key, exportee) {
+            (ExportKey::SpvEntryPoint { .. }, Exportee::Func(func)) => Some(func),
+            _ => None,
+        })
+        .collect();
+    for func in entry_points {
+        let (global_vars, _) =
+            reachable_global_vars_and_funcs_from_exportee(module, Exportee::Func(func));
+
+        let stores: SmallVec<[_; 4]> = global_vars
+            .into_iter()
+            .filter(|&gv| is_uninit_workgroup_var(module, gv))
+            .filter_map(|gv| {
+                let type_of_ptr_to = module.global_vars[gv].type_of_ptr_to;
+                let pointee = type_of_ptr_to.pointee_type(cx)?;
+                let ptr = cx.intern(ConstDef {
+                    attrs: AttrSet::default(),
+                    ty: type_of_ptr_to,
+                    ctor: ConstCtor::PtrToGlobalVar(gv),
+                    ctor_args: [].into_iter().collect(),
+                });
+                Some(DataInstDef {
+                    attrs: AttrSet::default(),
+                    kind: DataInstKind::SpvInst(wk.OpStore.into()),
+                    output_type: None,
+                    inputs: [Value::Const(ptr), Value::Const(const_null(cx, pointee))]
+                        .into_iter()
+                        .collect(),
+                })
+            })
+            .collect();
+        if stores.is_empty() {
+            continue;
+        }
+
+        let func_def_body = match &mut module.funcs[func].def {
+            DeclDef::Present(func_def_body) => func_def_body,
+            DeclDef::Imported(_) => continue,
+        };
+
+        // NOTE(eddyb) `2` is the `Workgroup` SPIR-V `Scope`, and `0x108` the
+        // `AcquireRelease | WorkgroupMemory` SPIR-V `MemorySemantics`.
+        let barrier = DataInstDef {
+            attrs: AttrSet::default(),
+            kind: DataInstKind::Barrier {
+                execution_scope: Some(2),
+                memory_scope: 2,
+                semantics: 0x108,
+            },
+            output_type: None,
+            inputs: [].into_iter().collect(),
+        };
+
+        let mut insts = EntityList::empty();
+        for inst_def in stores.into_iter().chain([barrier]) {
+            let inst = func_def_body.data_insts.define(cx, inst_def.into());
+            insts.insert_last(inst, &mut func_def_body.data_insts);
+        }
+        let block = func_def_body.control_nodes.define(
+            cx,
+            ControlNodeDef {
+                attrs: AttrSet::default(),
+                kind: ControlNodeKind::Block { insts },
+                outputs: [].into_iter().collect(),
+            }
+            .into(),
+        );
+        func_def_body.control_regions[func_def_body.body]
+            .children
+            .insert_first(block, &mut func_def_body.control_nodes);
+    }
+}
+
+/// Zero-initialize all the `Function` variables in `func_def_body` which lack
+/// an initializer (by giving them an `OpConstantNull` one).
+fn zero_initialize_function_vars(cx: &Context, func_def_body: &mut FuncDefBody) {
+    let wk = &spv::spec::Spec::get().well_known;
+
+    // Find all the `Function` variables lacking an initializer.
+    let mut uninit_vars = SmallVec::<[_; 8]>::new();
+    let mut regions = vec![func_def_body.body];
+    if let Some(cfg) = &func_def_body.unstructured_cfg {
+        regions.extend(
+            cfg.rev_post_order(func_def_body)
+                .filter(|&region| region != func_def_body.body),
+        );
+    }
+    while let Some(region) = regions.pop() {
+        let func = func_def_body.at(region);
+        for func_at_control_node in func.at_children() {
+            match &func_at_control_node.def().kind {
+                ControlNodeKind::Block { insts } => {
+                    for func_at_inst in func.at(*insts) {
+                        let inst_def = func_at_inst.def();
+                        let is_uninit_function_var = match &inst_def.kind {
+                            DataInstKind::SpvInst(spv_inst) => {
+                                spv_inst.opcode == wk.OpVariable
+                                    && spv_inst.imms[..]
+                                        == [spv::Imm::Short(wk.StorageClass, wk.Function)]
+                                    && inst_def.inputs.is_empty()
+                            }
+                            _ => false,
+                        };
+                        let pointee = inst_def.output_type.and_then(|ty| ty.pointee_type(cx));
+                        if let (true, Some(pointee)) = (is_uninit_function_var, pointee) {
+                            uninit_vars.push((func_at_inst.position, pointee));
+                        }
+                    }
+                }
+                ControlNodeKind::Select { cases, .. } => {
+                    regions.extend(cases.iter().copied());
+                }
+                &ControlNodeKind::Loop { body, .. } => regions.push(body),
+                ControlNodeKind::ExitInvocation { .. } => {}
+            }
+        }
+    }
+
+    for (var, pointee) in uninit_vars {
+        func_def_body.data_insts[var]
+            .inputs
+            .push(Value::Const(const_null(cx, pointee)));
+    }
+}
+
+/// Get the (`OpConstantNull`) zero value of type `ty`.
+fn const_null(cx: &Context, ty: Type) -> Const {
+    let wk = &spv::spec::Spec::get().well_known;
+
+    cx.intern(ConstDef {
+        attrs: AttrSet::default(),
+        ty,
+        ctor: ConstCtor::SpvInst(wk.OpConstantNull.into()),
+        ctor_args: [].into_iter().collect(),
+    })
+}
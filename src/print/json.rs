@@ -0,0 +1,908 @@
+//! JSON export of SPIR-T [`Module`]s (e.g. for analysis tools not written in Rust).
+//!
+//! # Schema
+//!
+//! The top-level JSON object has these fields:
+//! * `"dialect"`: `{"kind": "SPIR-V", "version": [major, minor], "extensions": [string],
+//!   "capabilities": [operand], "addressing_model": operand, "memory_model": operand}`
+//! * `"debug_info"`: `{"kind": "SPIR-V", "generator": {"tool_id": int, "version": int} | null,
+//!   "source_languages": [{"lang": operand, "version": int, "files": {path: contents}}],
+//!   "source_extensions": [string], "module_processes": [string]}`
+//! * `"attr_sets"`: `[[attr]]` (see below for `attr`)
+//! * `"types"`: `[{"attrs": attrs, "ctor": ctor, "args": [{"type": type} | {"const": const}]}]`
+//!   * `ctor`: `{"spv_inst": inst}` | `"spv_string_literal_for_ext_inst"` | `"qptr"`
+//!     | `{"matrix": {"column_count": int}}` | `"array"` | `"runtime_array"`
+//!     | `{"image": {"dim": operand, "depth": bool | null, "arrayed": bool,
+//!     "multisampled": bool, "sampled": bool | null, "format": operand,
+//!     "access_qualifier": operand | null}}` | `"sampler"` | `"sampled_image"`
+//!     | `{"recursive_ptr": {"storage_class": operand}}` | `{"recursive_ptr_self": {"depth": int}}`
+//!     | `{"struct": {"members": [{"attrs": attrs, "offset": int | null}]}}`
+//! * `"consts"`: `[{"attrs": attrs, "type": type, "ctor": ctor, "args": [const]}]`
+//!   * `ctor`: `{"ptr_to_global_var": global_var}` | `"undef"` | `{"spv_inst": inst}`
+//!     | `{"spec_const": {"spec_id": int, "default": inst}}` | `{"spec_const_op": inst}`
+//!     | `{"spv_ext_inst": {"ext_set": string, "inst": int}}`
+//!     | `{"spv_string_literal_for_ext_inst": string}` | `"spv_type_operand"`
+//! * `"global_vars"`: `[{"attrs": attrs, "type_of_ptr_to": type, "addr_space": addr_space,
+//!   "import": import} | {..., "initializer": const | null}]`
+//!   * `addr_space`: `{"spv_storage_class": operand}` | `"physical_storage_buffer"`
+//! * `"funcs"`: `[{"attrs": attrs, "ret_type": type, "params": [{"attrs": attrs, "type": type}],
+//!   "import": import} | {..., "body": func_body}]` (see below for `func_body`)
+//! * `"exports"`: `[{"key": {"link_name": string} | {"spv_entry_point": {"operands": [operand],
+//!   "interface_global_vars": [global_var]}}, "exportee": {"global_var": global_var} | {"func": func}}]`
+//!
+//! References to interned/entity definitions (i.e. `attrs`, `type`, `const`,
+//! `global_var` and `func` above) are indices into the respective top-level
+//! arrays, while other common parts are represented as:
+//! * `attr`: `{"spv_annotation": inst}`
+//!   | `{"spv_execution_mode_id": {"inst": inst, "consts": [const]}}`
+//!   | `{"spv_debug_line": {"file_path": string, "line": int, "col": int}}`
+//!   | `{"spv_bitflags_operand": operand}`
+//!   | `{"spv_original_id": int}` | `{"spv_unsupported": string}`
+//!   | `{"diagnostic": {"severity": "error" | "warning", "message": string, "related": [string]}}`
+//!   | `{"spv_shader_debug_scope": {"scope": const, "inlined_at": const | null}}`
+//!   | `{"spv_shader_debug_line": {"source": const, "line_start": int, "line_end": int,
+//!   "col_start": int, "col_end": int}}`
+//!   | `{"qptr_from_spv_ptr": {"storage_class": operand, "pointee": type}}`
+//!   | `{"qptr_to_spv_ptr_input": {"input_idx": int, "pointee": type}}`
+//! * `import`: `{"link_name": string}`
+//! * `inst`: `{"opcode": string, "operands": [operand], "imms": [[kind, word]]}`, where
+//!   `operands` are printed as in plain text output (e.g. `"spv.Decoration.Flat"`),
+//!   and `imms` are the raw immediate words, paired with their operand kind names
+//!   (with any multi-word literals using several consecutive words, lowest first)
+//! * `operand`: string (in the same form as in plain text output)
+//!
+//! Function bodies (`func_body`) have their own arrays of definitions, referred
+//! to by index (as `region`, `node` and `data_inst`):
+//! * `"regions"`: `[{"inputs": [{"attrs": attrs, "type": type}], "children": [node],
+//!   "outputs": [value]}]`
+//! * `"nodes"`: `[{"kind": "block", "insts": [data_inst]} | {"kind": "select", "selection":
+//!   "bool_cond" | {"spv_inst": inst}, "scrutinee": value, "cases": [region]} | {"kind": "loop",
+//!   "initial_inputs": [value], "body": region, "repeat_condition": value} | {"kind":
+//!   "exit_invocation", "exit_invocation": exit_invocation_kind, "inputs": [value]}]`, all with
+//!   additional `"attrs": attrs` (e.g. SPIR-V merge hints, as `spv_bitflags_operand`s)
+//!   and `"outputs": [{"attrs": attrs, "type": type}]` fields
+//! * `"data_insts"`: `[{"attrs": attrs, "kind": {"func_call": func} | {"spv_inst": inst}
+//!   | {"spv_ext_inst": {"ext_set": string, "inst": int}} | {"spv_glsl_std_450": string}
+//!   | {"atomic": {"op": string, "scope": int, "semantics": int, "unequal_semantics": int}}
+//!   | {"barrier": {"execution_scope": int | null, "memory_scope": int, "semantics": int}}
+//!   | {"group": {"op": string, "scope": int, "group_operation": int | null}}
+//!   | {"qptr": {"op": string, "pointee": type}}, "output_type": type | null,
+//!   "inputs": [value]}]` (with `"unequal_semantics"` only present for
+//!   `"CompareExchange"`/`"CompareExchangeWeak"` atomics, and `"pointee"` for `"offset"`)
+//! * `"body"`: region
+//! * `"unstructured_cfg"`: `null` | `[{"region": region, "control_inst": {"attrs": attrs,
+//!   "kind": kind, "inputs": [value], "targets": [region], "target_inputs": [{"target": region,
+//!   "inputs": [value]}]}}]`
+//!   * `kind`: `"unreachable"` | `"return"` | `{"exit_invocation": exit_invocation_kind}`
+//!     | `"branch"` | `{"select_branch": "bool_cond" | {"spv_inst": inst}}`
+//!
+//! where `exit_invocation_kind` is `{"spv_inst": inst}` | `"emit_mesh_tasks"`, and
+//! where `value` is `{"const": const}` | `{"region_input": {"region": region, "input_idx": int}}`
+//! | `{"node_output": {"node": node, "output_idx": int}}` | `{"data_inst_output": data_inst}`.
+
+use crate::func_at::FuncAt;
+use crate::qptr::{QPtrAttr, QPtrOp};
+use crate::spv::{self, spec};
+use crate::visit::{InnerVisit, Visitor};
+use crate::{
+    cfg, AddrSpace, AtomicOp, Attr, AttrSet, Const, ConstCtor, ConstDef, Context, ControlNode,
+    ControlNodeDef, ControlNodeKind, ControlRegion, ControlRegionDef, DataInst, DataInstDef,
+    DataInstKind, DeclDef, ExportKey, Exportee, Func, FuncDecl, FuncDefBody, FxIndexSet, GlobalVar,
+    GlobalVarDecl, ImageType, Import, Module, ModuleDebugInfo, ModuleDialect, SelectionKind, Type,
+    TypeCtor, TypeCtorArg, TypeDef, Value,
+};
+use serde_json::{json, Value as Json};
+
+/// Produce a JSON representation of `module` (see the [module-level docs](self)
+/// for the schema), including everything reachable from its exports.
+pub fn to_json(module: &Module) -> Json {
+    let cx = &module.cx();
+
+    let mut collector = ReachableDefCollector {
+        cx,
+        module,
+
+        seen_attr_sets: FxIndexSet::default(),
+        seen_types: FxIndexSet::default(),
+        seen_consts: FxIndexSet::default(),
+        seen_global_vars: FxIndexSet::default(),
+        seen_funcs: FxIndexSet::default(),
+    };
+    module.inner_visit_with(&mut collector);
+
+    let w = JsonWriter {
+        cx,
+        attr_sets: &collector.seen_attr_sets,
+        types: &collector.seen_types,
+        consts: &collector.seen_consts,
+        global_vars: &collector.seen_global_vars,
+        funcs: &collector.seen_funcs,
+    };
+
+    let dialect = match &module.dialect {
+        ModuleDialect::Spv(dialect) => spv_dialect(dialect),
+    };
+    let debug_info = match &module.debug_info {
+        ModuleDebugInfo::Spv(debug_info) => w.spv_module_debug_info(debug_info),
+    };
+
+    let attr_sets: Vec<_> = w
+        .attr_sets
+        .iter()
+        .map(|&attrs| {
+            cx[attrs]
+                .attrs
+                .iter()
+                .map(|attr| w.attr(attr))
+                .collect::<Json>()
+        })
+        .collect();
+    let types: Vec<_> = w.types.iter().map(|&ty| w.type_def(&cx[ty])).collect();
+    let consts: Vec<_> = w.consts.iter().map(|&ct| w.const_def(&cx[ct])).collect();
+    let global_vars: Vec<_> = w
+        .global_vars
+        .iter()
+        .map(|&gv| w.global_var_decl(&module.global_vars[gv]))
+        .collect();
+    let funcs: Vec<_> = w
+        .funcs
+        .iter()
+        .map(|&func| w.func_decl(&module.funcs[func]))
+        .collect();
+    let exports: Vec<_> = module
+        .exports
+        .iter()
+        .map(|(export_key, exportee)| {
+            json!({
+                "key": w.export_key(export_key),
+                "exportee": w.exportee(*exportee),
+            })
+        })
+        .collect();
+
+    json!({
+        "dialect": dialect,
+        "debug_info": debug_info,
+        "attr_sets": attr_sets,
+        "types": types,
+        "consts": consts,
+        "global_vars": global_vars,
+        "funcs": funcs,
+        "exports": exports,
+    })
+}
+
+struct ReachableDefCollector<'a> {
+    cx: &'a Context,
+    module: &'a Module,
+
+    // FIXME(eddyb) build some automation to avoid ever repeating these.
+    seen_attr_sets: FxIndexSet<AttrSet>,
+    seen_types: FxIndexSet<Type>,
+    seen_consts: FxIndexSet<Const>,
+    seen_global_vars: FxIndexSet<GlobalVar>,
+    seen_funcs: FxIndexSet<Func>,
+}
+
+impl Visitor<'_> for ReachableDefCollector<'_> {
+    // FIXME(eddyb) build some automation to avoid ever repeating these.
+    fn visit_attr_set_use(&mut self, attrs: AttrSet) {
+        if self.seen_attr_sets.insert(attrs) {
+            self.visit_attr_set_def(&self.cx[attrs]);
+        }
+    }
+    fn visit_type_use(&mut self, ty: Type) {
+        if self.seen_types.insert(ty) {
+            self.visit_type_def(&self.cx[ty]);
+        }
+    }
+    fn visit_const_use(&mut self, ct: Const) {
+        if self.seen_consts.insert(ct) {
+            self.visit_const_def(&self.cx[ct]);
+        }
+    }
+
+    fn visit_global_var_use(&mut self, gv: GlobalVar) {
+        if self.seen_global_vars.insert(gv) {
+            self.visit_global_var_decl(&self.module.global_vars[gv]);
+        }
+    }
+    fn visit_func_use(&mut self, func: Func) {
+        if self.seen_funcs.insert(func) {
+            self.visit_func_decl(&self.module.funcs[func]);
+        }
+    }
+}
+
+fn spv_operands(opcode: spec::Opcode, imms: impl IntoIterator<Item = spv::Imm>) -> Vec<String> {
+    spv::print::inst_operands(opcode, imms, std::iter::empty::<String>())
+        .map(|operand| operand.concat_to_plain_text())
+        .collect()
+}
+
+fn spv_single_operand(kind: spec::OperandKind, word: u32) -> Json {
+    spv::print::operand_from_imms([spv::Imm::Short(kind, word)])
+        .concat_to_plain_text()
+        .into()
+}
+
+fn spv_inst(inst: &spv::Inst) -> Json {
+    let imms: Vec<_> = inst
+        .imms
+        .iter()
+        .map(|&imm| {
+            let (kind, word) = match imm {
+                spv::Imm::Short(kind, word)
+                | spv::Imm::LongStart(kind, word)
+                | spv::Imm::LongCont(kind, word) => (kind, word),
+            };
+            json!([kind.name(), word])
+        })
+        .collect();
+    json!({
+        "opcode": inst.opcode.name(),
+        "operands": spv_operands(inst.opcode, inst.imms.iter().copied()),
+        "imms": imms,
+    })
+}
+
+fn spv_dialect(dialect: &spv::Dialect) -> Json {
+    let wk = &spec::Spec::get().well_known;
+
+    let spv::Dialect {
+        version_major,
+        version_minor,
+        capabilities,
+        extensions,
+        addressing_model,
+        memory_model,
+    } = dialect;
+
+    let capabilities: Vec<_> = capabilities
+        .iter()
+        .map(|&cap| spv_single_operand(wk.Capability, cap))
+        .collect();
+    json!({
+        "kind": "SPIR-V",
+        "version": [version_major, version_minor],
+        "extensions": extensions,
+        "capabilities": capabilities,
+        "addressing_model": spv_single_operand(wk.AddressingModel, *addressing_model),
+        "memory_model": spv_single_operand(wk.MemoryModel, *memory_model),
+    })
+}
+
+struct JsonWriter<'a> {
+    cx: &'a Context,
+
+    attr_sets: &'a FxIndexSet<AttrSet>,
+    types: &'a FxIndexSet<Type>,
+    consts: &'a FxIndexSet<Const>,
+    global_vars: &'a FxIndexSet<GlobalVar>,
+    funcs: &'a FxIndexSet<Func>,
+}
+
+/// Indices (into the `"regions"`/`"nodes"`/`"data_insts"` arrays) for all the
+/// definitions in a function body (see [`JsonWriter::func_def_body`]).
+#[derive(Default)]
+struct FuncBodyIndices {
+    regions: FxIndexSet<ControlRegion>,
+    nodes: FxIndexSet<ControlNode>,
+    data_insts: FxIndexSet<DataInst>,
+}
+
+impl FuncBodyIndices {
+    fn visit_region(&mut self, func_at_region: FuncAt<'_, ControlRegion>) {
+        self.regions.insert(func_at_region.position);
+        for func_at_node in func_at_region.at_children() {
+            self.nodes.insert(func_at_node.position);
+            match &func_at_node.def().kind {
+                ControlNodeKind::Block { insts } => {
+                    for func_at_inst in func_at_node.at(*insts) {
+                        self.data_insts.insert(func_at_inst.position);
+                    }
+                }
+                ControlNodeKind::ExitInvocation { .. } => {}
+                ControlNodeKind::Select { cases, .. } => {
+                    for &case in cases {
+                        self.visit_region(func_at_node.at(case));
+                    }
+                }
+                ControlNodeKind::Loop { body, .. } => {
+                    self.visit_region(func_at_node.at(*body));
+                }
+            }
+        }
+    }
+}
+
+impl JsonWriter<'_> {
+    fn attrs(&self, attrs: AttrSet) -> Json {
+        self.attr_sets.get_index_of(&attrs).unwrap().into()
+    }
+    fn ty(&self, ty: Type) -> Json {
+        self.types.get_index_of(&ty).unwrap().into()
+    }
+    fn ct(&self, ct: Const) -> Json {
+        self.consts.get_index_of(&ct).unwrap().into()
+    }
+    fn global_var(&self, gv: GlobalVar) -> Json {
+        self.global_vars.get_index_of(&gv).unwrap().into()
+    }
+    fn func(&self, func: Func) -> Json {
+        self.funcs.get_index_of(&func).unwrap().into()
+    }
+
+    fn spv_module_debug_info(&self, debug_info: &spv::ModuleDebugInfo) -> Json {
+        let wk = &spec::Spec::get().well_known;
+
+        let spv::ModuleDebugInfo {
+            original_generator_magic,
+            source_languages,
+            source_extensions,
+            module_processes,
+        } = debug_info;
+
+        let generator = original_generator_magic.map(|generator_magic| {
+            let (tool_id, tool_version) =
+                (generator_magic.get() >> 16, generator_magic.get() as u16);
+            json!({ "tool_id": tool_id, "version": tool_version })
+        });
+        let source_languages: Vec<_> = source_languages
+            .iter()
+            .map(|(lang, sources)| {
+                let files: serde_json::Map<_, _> = sources
+                    .file_contents
+                    .iter()
+                    .map(|(&file, contents)| (self.cx[file].to_string(), contents.clone().into()))
+                    .collect();
+                json!({
+                    "lang": spv_single_operand(wk.SourceLanguage, lang.lang),
+                    "version": lang.version,
+                    "files": files,
+                })
+            })
+            .collect();
+        json!({
+            "kind": "SPIR-V",
+            "generator": generator,
+            "source_languages": source_languages,
+            "source_extensions": source_extensions,
+            "module_processes": module_processes,
+        })
+    }
+
+    fn attr(&self, attr: &Attr) -> Json {
+        match attr {
+            Attr::SpvAnnotation(inst) => json!({ "spv_annotation": spv_inst(inst) }),
+            Attr::SpvExecutionModeId { inst, consts } => {
+                let consts: Vec<_> = consts.iter().map(|ct| self.ct(ct.0)).collect();
+                json!({
+                    "spv_execution_mode_id": { "inst": spv_inst(inst), "consts": consts },
+                })
+            }
+            &Attr::SpvDebugLine {
+                file_path,
+                line,
+                col,
+            } => json!({
+                "spv_debug_line": {
+                    "file_path": &self.cx[file_path.0],
+                    "line": line,
+                    "col": col,
+                },
+            }),
+            Attr::SpvBitflagsOperand(imms) => json!({
+                "spv_bitflags_operand": spv::print::operand_from_imms(imms.iter().copied())
+                    .concat_to_plain_text(),
+            }),
+            Attr::SpvOriginalId(id) => json!({ "spv_original_id": id.get() }),
+            Attr::SpvUnsupported(message) => json!({ "spv_unsupported": message }),
+            Attr::Diagnostic {
+                severity,
+                message,
+                related,
+            } => json!({
+                "diagnostic": {
+                    "severity": severity.name(),
+                    "message": message,
+                    "related": related,
+                },
+            }),
+            &Attr::SpvShaderDebugScope { scope, inlined_at } => json!({
+                "spv_shader_debug_scope": {
+                    "scope": self.ct(scope.0),
+                    "inlined_at": inlined_at.map(|ct| self.ct(ct.0)),
+                },
+            }),
+            &Attr::SpvShaderDebugLine {
+                source,
+                line_start,
+                line_end,
+                col_start,
+                col_end,
+            } => json!({
+                "spv_shader_debug_line": {
+                    "source": self.ct(source.0),
+                    "line_start": line_start,
+                    "line_end": line_end,
+                    "col_start": col_start,
+                    "col_end": col_end,
+                },
+            }),
+            &Attr::QPtr(QPtrAttr::FromSpvPtr {
+                storage_class,
+                pointee,
+            }) => {
+                let wk = &spec::Spec::get().well_known;
+                json!({
+                    "qptr_from_spv_ptr": {
+                        "storage_class": spv_single_operand(wk.StorageClass, storage_class),
+                        "pointee": self.ty(pointee.0),
+                    },
+                })
+            }
+            &Attr::QPtr(QPtrAttr::ToSpvPtrInput { input_idx, pointee }) => json!({
+                "qptr_to_spv_ptr_input": {
+                    "input_idx": input_idx,
+                    "pointee": self.ty(pointee.0),
+                },
+            }),
+        }
+    }
+
+    fn import(&self, import: &Import) -> Json {
+        match *import {
+            Import::LinkName(name) => json!({ "link_name": &self.cx[name] }),
+        }
+    }
+
+    fn type_def(&self, ty_def: &TypeDef) -> Json {
+        let TypeDef {
+            attrs,
+            ctor,
+            ctor_args,
+        } = ty_def;
+
+        let ctor = match ctor {
+            TypeCtor::SpvInst(inst) => json!({ "spv_inst": spv_inst(inst) }),
+            TypeCtor::SpvStringLiteralForExtInst => json!("spv_string_literal_for_ext_inst"),
+            TypeCtor::Matrix { column_count } => {
+                json!({ "matrix": { "column_count": column_count } })
+            }
+            TypeCtor::Array => json!("array"),
+            TypeCtor::RuntimeArray => json!("runtime_array"),
+            &TypeCtor::Image(ImageType {
+                dim,
+                depth,
+                arrayed,
+                multisampled,
+                sampled,
+                format,
+                access_qualifier,
+            }) => {
+                let wk = &spec::Spec::get().well_known;
+                json!({ "image": {
+                    "dim": spv_single_operand(wk.Dim, dim),
+                    "depth": depth,
+                    "arrayed": arrayed,
+                    "multisampled": multisampled,
+                    "sampled": sampled,
+                    "format": spv_single_operand(wk.ImageFormat, format),
+                    "access_qualifier": access_qualifier.map(|access_qualifier| {
+                        spv_single_operand(wk.AccessQualifier, access_qualifier)
+                    }),
+                } })
+            }
+            &TypeCtor::RecursivePtr { storage_class } => {
+                let wk = &spec::Spec::get().well_known;
+                json!({ "recursive_ptr": {
+                    "storage_class": spv_single_operand(wk.StorageClass, storage_class),
+                } })
+            }
+            TypeCtor::RecursivePtrSelf { depth } => {
+                json!({ "recursive_ptr_self": { "depth": depth } })
+            }
+            TypeCtor::QPtr => json!("qptr"),
+            TypeCtor::Sampler => json!("sampler"),
+            TypeCtor::SampledImage => json!("sampled_image"),
+            TypeCtor::Struct { members } => {
+                let members: Vec<_> = members
+                    .iter()
+                    .map(|member| {
+                        json!({ "attrs": self.attrs(member.attrs), "offset": member.offset })
+                    })
+                    .collect();
+                json!({ "struct": { "members": members } })
+            }
+        };
+        let args: Vec<_> = ctor_args
+            .iter()
+            .map(|&arg| match arg {
+                TypeCtorArg::Type(ty) => json!({ "type": self.ty(ty) }),
+                TypeCtorArg::Const(ct) => json!({ "const": self.ct(ct) }),
+            })
+            .collect();
+        json!({
+            "attrs": self.attrs(*attrs),
+            "ctor": ctor,
+            "args": args,
+        })
+    }
+
+    fn const_def(&self, ct_def: &ConstDef) -> Json {
+        let ConstDef {
+            attrs,
+            ty,
+            ctor,
+            ctor_args,
+        } = ct_def;
+
+        let ctor = match ctor {
+            &ConstCtor::PtrToGlobalVar(gv) => json!({ "ptr_to_global_var": self.global_var(gv) }),
+            ConstCtor::Undef => json!("undef"),
+            ConstCtor::SpvInst(inst) => json!({ "spv_inst": spv_inst(inst) }),
+            ConstCtor::SpecConst { spec_id, default } => json!({
+                "spec_const": {
+                    "spec_id": spec_id,
+                    "default": spv_inst(default),
+                },
+            }),
+            ConstCtor::SpecConstOp(inst) => json!({ "spec_const_op": spv_inst(inst) }),
+            &ConstCtor::SpvExtInst { ext_set, inst } => json!({
+                "spv_ext_inst": { "ext_set": &self.cx[ext_set], "inst": inst },
+            }),
+            &ConstCtor::SpvStringLiteralForExtInst(s) => {
+                json!({ "spv_string_literal_for_ext_inst": &self.cx[s] })
+            }
+            ConstCtor::SpvTypeOperand => json!("spv_type_operand"),
+        };
+        let args: Vec<_> = ctor_args.iter().map(|&ct| self.ct(ct)).collect();
+        json!({
+            "attrs": self.attrs(*attrs),
+            "type": self.ty(*ty),
+            "ctor": ctor,
+            "args": args,
+        })
+    }
+
+    fn global_var_decl(&self, gv_decl: &GlobalVarDecl) -> Json {
+        let wk = &spec::Spec::get().well_known;
+
+        let GlobalVarDecl {
+            attrs,
+            type_of_ptr_to,
+            addr_space,
+            def,
+        } = gv_decl;
+
+        let addr_space = match *addr_space {
+            AddrSpace::SpvStorageClass(sc) => {
+                json!({ "spv_storage_class": spv_single_operand(wk.StorageClass, sc) })
+            }
+            AddrSpace::PhysicalStorageBuffer => json!("physical_storage_buffer"),
+        };
+        let mut json = json!({
+            "attrs": self.attrs(*attrs),
+            "type_of_ptr_to": self.ty(*type_of_ptr_to),
+            "addr_space": addr_space,
+        });
+        match def {
+            DeclDef::Imported(import) => json["import"] = self.import(import),
+            DeclDef::Present(def) => {
+                json["initializer"] = def.initializer.map(|ct| self.ct(ct)).into();
+            }
+        }
+        json
+    }
+
+    fn func_decl(&self, func_decl: &FuncDecl) -> Json {
+        let FuncDecl {
+            attrs,
+            ret_type,
+            params,
+            def,
+        } = func_decl;
+
+        let params: Vec<_> = params
+            .iter()
+            .map(|param| json!({ "attrs": self.attrs(param.attrs), "type": self.ty(param.ty) }))
+            .collect();
+        let mut json = json!({
+            "attrs": self.attrs(*attrs),
+            "ret_type": self.ty(*ret_type),
+            "params": params,
+        });
+        match def {
+            DeclDef::Imported(import) => json["import"] = self.import(import),
+            DeclDef::Present(func_def_body) => json["body"] = self.func_def_body(func_def_body),
+        }
+        json
+    }
+
+    fn export_key(&self, export_key: &ExportKey) -> Json {
+        let wk = &spec::Spec::get().well_known;
+
+        match export_key {
+            &ExportKey::LinkName(name) => json!({ "link_name": &self.cx[name] }),
+            ExportKey::SpvEntryPoint {
+                imms,
+                interface_global_vars,
+            } => {
+                let interface_global_vars: Vec<_> = interface_global_vars
+                    .iter()
+                    .map(|&gv| self.global_var(gv))
+                    .collect();
+                json!({
+                    "spv_entry_point": {
+                        "operands": spv_operands(wk.OpEntryPoint, imms.iter().copied()),
+                        "interface_global_vars": interface_global_vars,
+                    },
+                })
+            }
+        }
+    }
+
+    fn exportee(&self, exportee: Exportee) -> Json {
+        match exportee {
+            Exportee::GlobalVar(gv) => json!({ "global_var": self.global_var(gv) }),
+            Exportee::Func(func) => json!({ "func": self.func(func) }),
+        }
+    }
+
+    fn func_def_body(&self, func_def_body: &FuncDefBody) -> Json {
+        let mut indices = FuncBodyIndices::default();
+        match &func_def_body.unstructured_cfg {
+            None => indices.visit_region(func_def_body.at_body()),
+            Some(cfg) => {
+                for region in cfg.rev_post_order(func_def_body) {
+                    indices.visit_region(func_def_body.at(region));
+                }
+            }
+        }
+
+        let region = |region: ControlRegion| -> Json {
+            indices.regions.get_index_of(&region).unwrap().into()
+        };
+        let value = |v: Value| match v {
+            Value::Const(ct) => json!({ "const": self.ct(ct) }),
+            Value::ControlRegionInput {
+                region: input_region,
+                input_idx,
+            } => json!({
+                "region_input": { "region": region(input_region), "input_idx": input_idx },
+            }),
+            Value::ControlNodeOutput {
+                control_node,
+                output_idx,
+            } => json!({
+                "node_output": {
+                    "node": indices.nodes.get_index_of(&control_node).unwrap(),
+                    "output_idx": output_idx,
+                },
+            }),
+            Value::DataInstOutput(inst) => {
+                json!({ "data_inst_output": indices.data_insts.get_index_of(&inst).unwrap() })
+            }
+        };
+        let values = |vs: &[Value]| vs.iter().map(|&v| value(v)).collect::<Json>();
+        let selection_kind = |kind: &SelectionKind| match kind {
+            SelectionKind::BoolCond => json!("bool_cond"),
+            SelectionKind::SpvInst(inst) => json!({ "spv_inst": spv_inst(inst) }),
+        };
+        let exit_invocation_kind = |kind: &cfg::ExitInvocationKind| match kind {
+            cfg::ExitInvocationKind::SpvInst(inst) => json!({ "spv_inst": spv_inst(inst) }),
+            cfg::ExitInvocationKind::EmitMeshTasks => json!("emit_mesh_tasks"),
+        };
+
+        let regions: Vec<_> = indices
+            .regions
+            .iter()
+            .map(|&r| {
+                let ControlRegionDef {
+                    inputs,
+                    children,
+                    outputs,
+                } = func_def_body.at(r).def();
+                let inputs: Vec<_> = inputs
+                    .iter()
+                    .map(|input| json!({ "attrs": self.attrs(input.attrs), "type": self.ty(input.ty) }))
+                    .collect();
+                let children: Vec<_> = func_def_body
+                    .at(*children)
+                    .into_iter()
+                    .map(|func_at_node| indices.nodes.get_index_of(&func_at_node.position).unwrap())
+                    .collect();
+                json!({
+                    "inputs": inputs,
+                    "children": children,
+                    "outputs": values(outputs),
+                })
+            })
+            .collect();
+
+        let nodes: Vec<_> = indices
+            .nodes
+            .iter()
+            .map(|&node| {
+                let ControlNodeDef {
+                    attrs,
+                    kind,
+                    outputs,
+                } = func_def_body.at(node).def();
+                let mut json = match kind {
+                    ControlNodeKind::Block { insts } => {
+                        let insts: Vec<_> = func_def_body
+                            .at(*insts)
+                            .into_iter()
+                            .map(|func_at_inst| {
+                                indices.data_insts.get_index_of(&func_at_inst.position).unwrap()
+                            })
+                            .collect();
+                        json!({ "kind": "block", "insts": insts })
+                    }
+                    ControlNodeKind::Select {
+                        kind,
+                        scrutinee,
+                        cases,
+                    } => {
+                        let cases: Vec<_> = cases.iter().map(|&case| region(case)).collect();
+                        json!({
+                            "kind": "select",
+                            "selection": selection_kind(kind),
+                            "scrutinee": value(*scrutinee),
+                            "cases": cases,
+                        })
+                    }
+                    ControlNodeKind::Loop {
+                        initial_inputs,
+                        body,
+                        repeat_condition,
+                    } => json!({
+                        "kind": "loop",
+                        "initial_inputs": values(initial_inputs),
+                        "body": region(*body),
+                        "repeat_condition": value(*repeat_condition),
+                    }),
+                    ControlNodeKind::ExitInvocation { kind, inputs } => json!({
+                        "kind": "exit_invocation",
+                        "exit_invocation": exit_invocation_kind(kind),
+                        "inputs": values(inputs),
+                    }),
+                };
+                json["attrs"] = self.attrs(*attrs);
+                json["outputs"] = outputs
+                    .iter()
+                    .map(|output| json!({ "attrs": self.attrs(output.attrs), "type": self.ty(output.ty) }))
+                    .collect();
+                json
+            })
+            .collect();
+
+        let data_insts: Vec<_> = indices
+            .data_insts
+            .iter()
+            .map(|&inst| {
+                let DataInstDef {
+                    attrs,
+                    kind,
+                    output_type,
+                    inputs,
+                } = func_def_body.at(inst).def();
+                let kind = match kind {
+                    &DataInstKind::FuncCall(func) => json!({ "func_call": self.func(func) }),
+                    DataInstKind::SpvInst(inst) => json!({ "spv_inst": spv_inst(inst) }),
+                    &DataInstKind::SpvExtInst { ext_set, inst } => json!({
+                        "spv_ext_inst": { "ext_set": &self.cx[ext_set], "inst": inst },
+                    }),
+                    &DataInstKind::SpvGlslStd450(op) => json!({ "spv_glsl_std_450": op.name() }),
+                    &DataInstKind::Atomic {
+                        op,
+                        scope,
+                        semantics,
+                    } => {
+                        let mut atomic = json!({
+                            "op": op.name(),
+                            "scope": scope,
+                            "semantics": semantics,
+                        });
+                        if let AtomicOp::CompareExchange {
+                            unequal_semantics, ..
+                        } = op
+                        {
+                            atomic["unequal_semantics"] = json!(unequal_semantics);
+                        }
+                        json!({ "atomic": atomic })
+                    }
+                    &DataInstKind::Barrier {
+                        execution_scope,
+                        memory_scope,
+                        semantics,
+                    } => json!({
+                        "barrier": {
+                            "execution_scope": execution_scope,
+                            "memory_scope": memory_scope,
+                            "semantics": semantics,
+                        },
+                    }),
+                    &DataInstKind::Group {
+                        op,
+                        scope,
+                        group_operation,
+                    } => json!({
+                        "group": {
+                            "op": op.name(),
+                            "scope": scope,
+                            "group_operation": group_operation,
+                        },
+                    }),
+                    &DataInstKind::QPtr(op) => {
+                        let mut qptr = json!({ "op": op.name() });
+                        if let QPtrOp::Offset { pointee } = op {
+                            qptr["pointee"] = self.ty(pointee);
+                        }
+                        json!({ "qptr": qptr })
+                    }
+                };
+                json!({
+                    "attrs": self.attrs(*attrs),
+                    "kind": kind,
+                    "output_type": output_type.map(|ty| self.ty(ty)),
+                    "inputs": values(inputs),
+                })
+            })
+            .collect();
+
+        let unstructured_cfg = func_def_body.unstructured_cfg.as_ref().map(|cfg| {
+            indices
+                .regions
+                .iter()
+                .filter_map(|&source| {
+                    let cfg::ControlInst {
+                        attrs,
+                        kind,
+                        inputs,
+                        targets,
+                        target_inputs,
+                    } = cfg.control_inst_on_exit_from.get(source)?;
+                    let kind = match kind {
+                        cfg::ControlInstKind::Unreachable => json!("unreachable"),
+                        cfg::ControlInstKind::Return => json!("return"),
+                        cfg::ControlInstKind::ExitInvocation(kind) => {
+                            json!({ "exit_invocation": exit_invocation_kind(kind) })
+                        }
+                        cfg::ControlInstKind::Branch => json!("branch"),
+                        cfg::ControlInstKind::SelectBranch(kind) => {
+                            json!({ "select_branch": selection_kind(kind) })
+                        }
+                    };
+                    let targets: Vec<_> = targets.iter().map(|&target| region(target)).collect();
+                    let target_inputs: Vec<_> = target_inputs
+                    .iter()
+                    .map(|(&target, inputs)| {
+                        json!({ "target": region(target), "inputs": values(inputs) })
+                    })
+                    .collect();
+                    Some(json!({
+                        "region": region(source),
+                        "control_inst": {
+                            "attrs": self.attrs(*attrs),
+                            "kind": kind,
+                            "inputs": values(inputs),
+                            "targets": targets,
+                            "target_inputs": target_inputs,
+                        },
+                    }))
+                })
+                .collect::<Json>()
+        });
+
+        json!({
+            "regions": regions,
+            "nodes": nodes,
+            "data_insts": data_insts,
+            "body": region(func_def_body.body),
+            "unstructured_cfg": unstructured_cfg,
+        })
+    }
+}
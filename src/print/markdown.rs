@@ -0,0 +1,113 @@
+//! Markdown rendering of pretty-printed SPIR-T (e.g. for issue trackers, or docs).
+
+use super::{Node, Plan, PrintOptions, Printer, Use};
+use itertools::Itertools as _;
+use smallvec::SmallVec;
+use std::fmt::Write;
+
+// HACK(eddyb) SPIR-T syntax is close enough to Rust's, for Rust highlighting to
+// be more useful than none at all (which is what unknown languages would get).
+const CODE_FENCE_LANG: &str = "rust";
+
+/// Append `code` to `md` as a fenced code block (using a fence long enough to
+/// not be confused with any sequences of backticks in `code` itself).
+fn push_code_fence(md: &mut String, code: &str) {
+    let longest_backtick_run = code
+        .split(|c| c != '`')
+        .map(|run| run.len())
+        .max()
+        .unwrap_or(0);
+    let fence = "`".repeat(longest_backtick_run.max(2) + 1);
+    writeln!(md, "{fence}{CODE_FENCE_LANG}\n{code}\n{fence}\n").unwrap();
+}
+
+impl Plan<'_> {
+    /// Print the whole [`Plan`] as a Markdown document, with all definitions in
+    /// (syntax-hinted) code fences, and every function under its own heading
+    /// (e.g. `## func0`, which most Markdown renderers turn into an anchor,
+    /// allowing links like `#func0`).
+    ///
+    /// When printing multiple versions (see [`Plan::for_versions`]), definitions
+    /// which differ between versions get separate code fences, each preceded by
+    /// the names of the versions it applies to.
+    pub fn pretty_print_to_markdown(&self, options: &PrintOptions) -> String {
+        // FIXME(eddyb) make max line width configurable.
+        let max_line_width = 120;
+
+        let printer = Printer::new(self, options.clone());
+        let version_names: SmallVec<[_; 1]> = self
+            .per_version_name_and_node_defs
+            .iter()
+            .map(|(name, _)| &name[..])
+            .collect();
+
+        let mut md = String::new();
+
+        // Definitions that don't need their own heading (i.e. all but functions),
+        // and which are identical across versions, are grouped together.
+        let mut grouped_code = String::new();
+        let flush_grouped_code = |md: &mut String, grouped_code: &mut String| {
+            if !grouped_code.is_empty() {
+                push_code_fence(md, grouped_code);
+                grouped_code.clear();
+            }
+        };
+
+        for (node, versions_with_repeat_count) in
+            self.print_per_node_versions_with_repeat_count(&printer)
+        {
+            let versions_with_repeat_count: SmallVec<[_; 1]> = versions_with_repeat_count
+                .into_iter()
+                .map(|(fragment, repeat_count)| {
+                    let code = fragment
+                        .layout_with_max_line_width(max_line_width)
+                        .to_string();
+                    (code.trim_end().to_string(), repeat_count)
+                })
+                .collect();
+            let uniform_across_versions = versions_with_repeat_count.len() == 1;
+
+            if uniform_across_versions && !matches!(node, Node::Func(_)) {
+                let code = &versions_with_repeat_count[0].0;
+                if !code.trim().is_empty() {
+                    if !grouped_code.is_empty() {
+                        grouped_code += "\n\n";
+                    }
+                    grouped_code += code;
+                }
+                continue;
+            }
+
+            flush_grouped_code(&mut md, &mut grouped_code);
+
+            if let Node::Func(_) = node {
+                let name = Use::Node(node)
+                    .print_as_def(&printer)
+                    .layout_with_max_line_width(max_line_width);
+                writeln!(md, "## {name}\n").unwrap();
+            }
+
+            let mut next_version_idx = 0;
+            for (code, repeat_count) in versions_with_repeat_count {
+                let version_idxs = next_version_idx..next_version_idx + repeat_count;
+                next_version_idx = version_idxs.end;
+
+                if !uniform_across_versions {
+                    let names = version_names[version_idxs]
+                        .iter()
+                        .map(|name| format!("`{name}`"))
+                        .join(" | ");
+                    writeln!(md, "**{names}:**\n").unwrap();
+                }
+                if code.trim().is_empty() {
+                    md += "*(not present)*\n\n";
+                } else {
+                    push_code_fence(&mut md, &code);
+                }
+            }
+        }
+        flush_grouped_code(&mut md, &mut grouped_code);
+
+        md
+    }
+}
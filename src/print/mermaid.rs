@@ -0,0 +1,218 @@
+//! [Mermaid](https://mermaid.js.org) flowchart export of SPIR-T control-flow.
+
+use crate::func_at::FuncAt;
+use crate::{
+    cfg, ControlNode, ControlNodeDef, ControlNodeKind, ControlRegion, ControlRegionDef,
+    FuncDefBody, FxIndexMap, SelectionKind,
+};
+use std::fmt::Write;
+
+/// Produce a Mermaid `flowchart` describing the control-flow of `func_def_body`.
+///
+/// Every [`ControlRegion`] becomes a `subgraph` (containing its children, as a
+/// linear chain of [`ControlNode`]s), with `Select` cases and `Loop` bodies
+/// being connected to their parent [`ControlNode`] by labeled edges, which also
+/// indicate the flow of values through region `inputs`/`outputs`.
+///
+/// Any unstructured control-flow (i.e. `func_def_body.unstructured_cfg`) is
+/// included as edges between (otherwise disconnected) [`ControlRegion`]s.
+///
+/// Only the shape of the control-flow is described, i.e. the output is not
+/// (meant to be) a replacement for [`Plan`](super::Plan)-based printing.
+pub fn to_mermaid(func_def_body: &FuncDefBody) -> String {
+    let mut mermaid = MermaidWriter {
+        func_def_body,
+        region_ids: FxIndexMap::default(),
+        node_ids: FxIndexMap::default(),
+        out: String::new(),
+        edges: String::new(),
+    };
+    mermaid.out += "flowchart TD\n";
+
+    let all_regions = func_def_body.unstructured_cfg.as_ref().map_or_else(
+        || vec![func_def_body.body],
+        |cfg| cfg.rev_post_order(func_def_body).collect(),
+    );
+    for &region in &all_regions {
+        mermaid.region(func_def_body.at(region), 1);
+    }
+
+    if let Some(cfg) = &func_def_body.unstructured_cfg {
+        for &source in &all_regions {
+            let control_inst = match cfg.control_inst_on_exit_from.get(source) {
+                Some(control_inst) => control_inst,
+                None => continue,
+            };
+            for (i, &target) in control_inst.targets.iter().enumerate() {
+                let label = match control_inst.target_inputs.get(&target) {
+                    Some(inputs) => format!("#{i}, {} inputs", inputs.len()),
+                    None => format!("#{i}"),
+                };
+                let (source, target) = (mermaid.region_id(source), mermaid.region_id(target));
+                writeln!(mermaid.edges, "    {source} ==>|\"{label}\"| {target}").unwrap();
+            }
+        }
+    }
+
+    let MermaidWriter { mut out, edges, .. } = mermaid;
+    out += &edges;
+    out
+}
+
+struct MermaidWriter<'a> {
+    func_def_body: &'a FuncDefBody,
+
+    // FIXME(eddyb) use `EntityOrientedDenseMap` here.
+    region_ids: FxIndexMap<ControlRegion, usize>,
+    // FIXME(eddyb) use `EntityOrientedDenseMap` here.
+    node_ids: FxIndexMap<ControlNode, usize>,
+
+    /// Node/subgraph definitions, which are nested inside `subgraph`s.
+    out: String,
+
+    /// Edges, which are all emitted at the end, after all the definitions.
+    edges: String,
+}
+
+impl MermaidWriter<'_> {
+    fn region_id(&mut self, region: ControlRegion) -> String {
+        let next_idx = self.region_ids.len();
+        format!(
+            "region{}",
+            self.region_ids.entry(region).or_insert(next_idx)
+        )
+    }
+
+    fn node_id(&mut self, control_node: ControlNode) -> String {
+        let next_idx = self.node_ids.len();
+        format!(
+            "node{}",
+            self.node_ids.entry(control_node).or_insert(next_idx)
+        )
+    }
+
+    fn region(&mut self, func_at_region: FuncAt<'_, ControlRegion>, depth: usize) {
+        let indent = "    ".repeat(depth);
+        let region = func_at_region.position;
+        let ControlRegionDef {
+            inputs,
+            children,
+            outputs,
+        } = func_at_region.def();
+
+        let region_id = self.region_id(region);
+        let mut title = region_id.clone();
+        if region == self.func_def_body.body {
+            title += " (body)";
+        }
+        if !inputs.is_empty() {
+            write!(title, " inputs: {}", inputs.len()).unwrap();
+        }
+        if !outputs.is_empty() {
+            write!(title, " outputs: {}", outputs.len()).unwrap();
+        }
+        writeln!(self.out, "{indent}subgraph {region_id} [\"{title}\"]").unwrap();
+
+        let mut prev_node_id = None;
+        for func_at_control_node in func_at_region.at(*children) {
+            let node_id = self.control_node(func_at_control_node, depth + 1);
+            if let Some(prev_node_id) = prev_node_id {
+                writeln!(self.edges, "    {prev_node_id} --> {node_id}").unwrap();
+            }
+            prev_node_id = Some(node_id);
+        }
+
+        // HACK(eddyb) Mermaid doesn't allow empty `subgraph`s.
+        if prev_node_id.is_none() {
+            writeln!(self.out, "{indent}    {region_id}_empty[\" \"]").unwrap();
+        }
+
+        writeln!(self.out, "{indent}end").unwrap();
+    }
+
+    fn control_node(
+        &mut self,
+        func_at_control_node: FuncAt<'_, ControlNode>,
+        depth: usize,
+    ) -> String {
+        let indent = "    ".repeat(depth);
+        let node_id = self.node_id(func_at_control_node.position);
+        let ControlNodeDef {
+            attrs: _,
+            kind,
+            outputs,
+        } = func_at_control_node.def();
+
+        let outputs_suffix = if outputs.is_empty() {
+            String::new()
+        } else {
+            format!(" -> {} outputs", outputs.len())
+        };
+
+        match kind {
+            ControlNodeKind::Block { insts } => {
+                let inst_count = func_at_control_node.at(*insts).into_iter().count();
+                writeln!(
+                    self.out,
+                    "{indent}{node_id}[\"block: {inst_count} insts{outputs_suffix}\"]"
+                )
+                .unwrap();
+            }
+            ControlNodeKind::Select {
+                kind,
+                scrutinee: _,
+                cases,
+            } => {
+                let kind = match kind {
+                    SelectionKind::BoolCond => "if",
+                    SelectionKind::SpvInst(inst) => inst.opcode.name(),
+                };
+                writeln!(self.out, "{indent}{node_id}{{\"{kind}{outputs_suffix}\"}}").unwrap();
+                for (i, &case) in cases.iter().enumerate() {
+                    self.region(func_at_control_node.at(case), depth);
+
+                    let case_id = self.region_id(case);
+                    writeln!(self.edges, "    {node_id} -->|\"case {i}\"| {case_id}").unwrap();
+                    if !outputs.is_empty() {
+                        writeln!(
+                            self.edges,
+                            "    {case_id} -.->|\"{} outputs\"| {node_id}",
+                            outputs.len()
+                        )
+                        .unwrap();
+                    }
+                }
+            }
+            ControlNodeKind::Loop {
+                initial_inputs,
+                body,
+                repeat_condition: _,
+            } => {
+                writeln!(self.out, "{indent}{node_id}((\"loop{outputs_suffix}\"))").unwrap();
+                self.region(func_at_control_node.at(*body), depth);
+
+                let body_id = self.region_id(*body);
+                let initial_label = if initial_inputs.is_empty() {
+                    "body".to_string()
+                } else {
+                    format!("body, {} initial inputs", initial_inputs.len())
+                };
+                writeln!(
+                    self.edges,
+                    "    {node_id} -->|\"{initial_label}\"| {body_id}"
+                )
+                .unwrap();
+                writeln!(self.edges, "    {body_id} -.->|\"repeat\"| {node_id}").unwrap();
+            }
+            ControlNodeKind::ExitInvocation { kind, inputs: _ } => {
+                let kind = match kind {
+                    cfg::ExitInvocationKind::SpvInst(inst) => inst.opcode.name(),
+                    cfg::ExitInvocationKind::EmitMeshTasks => "mesh.emit_tasks",
+                };
+                writeln!(self.out, "{indent}{node_id}[/\"{kind}\"/]").unwrap();
+            }
+        }
+
+        node_id
+    }
+}
@@ -13,6 +13,23 @@
 //! * HTML (styled and hyperlinked): [`.render_to_html()`](Versions::render_to_html)
 #![allow(rustdoc::private_intra_doc_links)]
 //!   (returning a [`pretty::HtmlSnippet`])
+//!
+//! Alternatively, [`.pretty_print_to_markdown()`](Plan::pretty_print_to_markdown)
+//! produces a Markdown document (e.g. for issue trackers, or design docs).
+//!
+//! Separately, [`to_mermaid`] can describe the control-flow of a function as
+//! a [Mermaid](https://mermaid.js.org) flowchart (e.g. for docs or bug reports),
+//! and [`to_json`] can export a whole [`Module`] as JSON (e.g. for analysis tools).
+//!
+//! # Determinism
+//!
+//! The output of printing only depends on its inputs (and not on e.g. hash map
+//! iteration order, or memory addresses), so it's the same across runs, for the
+//! same module (or other roots) built the same way (e.g. lowered from the same
+//! SPIR-V into a new [`Context`]), making it suitable for golden-file comparisons.
+//!
+//! Any new maps that can end up being iterated during printing should therefore
+//! remain indexed ones (i.e. `FxIndexMap`, which preserves insertion order).
 
 // FIXME(eddyb) stop using `itertools` for methods like `intersperse` when they
 // get stabilized on `Iterator` instead.
@@ -20,23 +37,30 @@
 use itertools::Itertools as _;
 
 use crate::func_at::FuncAt;
+use crate::qptr::{QPtrAttr, QPtrOp};
 use crate::visit::{DynVisit, InnerVisit, Visit, Visitor};
 use crate::{
-    cfg, spv, AddrSpace, Attr, AttrSet, AttrSetDef, Const, ConstCtor, ConstDef, Context,
+    cfg, spv, AtomicOp, Attr, AttrSet, AttrSetDef, Const, ConstCtor, ConstDef, Context,
     ControlNode, ControlNodeDef, ControlNodeKind, ControlNodeOutputDecl, ControlRegion,
     ControlRegionDef, ControlRegionInputDecl, DataInst, DataInstDef, DataInstKind, DeclDef,
-    EntityListIter, ExportKey, Exportee, Func, FuncDecl, FuncParam, FxIndexMap, GlobalVar,
-    GlobalVarDecl, GlobalVarDefBody, Import, Module, ModuleDebugInfo, ModuleDialect, SelectionKind,
-    Type, TypeCtor, TypeCtorArg, TypeDef, Value,
+    DiagnosticSeverity, EntityListIter, ExportKey, Exportee, Func, FuncDecl, FuncParam, FxIndexMap,
+    GlobalVar, GlobalVarDecl, GlobalVarDefBody, ImageType, Import, InternedStr, Module,
+    ModuleDebugInfo, ModuleDialect, SelectionKind, Type, TypeCtor, TypeCtorArg, TypeDef, Value,
 };
-use rustc_hash::FxHashMap;
+use indexmap::map::Entry;
 use smallvec::SmallVec;
-use std::collections::hash_map::Entry;
 use std::fmt::Write;
+use std::rc::Rc;
 use std::{fmt, mem};
 
+mod json;
+mod markdown;
+mod mermaid;
 mod pretty;
 
+pub use json::to_json;
+pub use mermaid::to_mermaid;
+
 /// "Definitions-before-uses" / "topo-sorted" printing plan.
 ///
 /// In order to represent parts of a DAG textually, it first needs to have its
@@ -55,7 +79,7 @@ pub struct Plan<'a> {
 
     /// When visiting module-stored nodes, the [`Module`] is needed to map the
     /// [`Node`] to the (per-version) definition, which is then stored in the
-    /// (per-version) [`FxHashMap`] within `per_version_name_and_node_defs`.
+    /// (per-version) [`FxIndexMap`] within `per_version_name_and_node_defs`.
     current_module: Option<&'a Module>,
 
     /// Versions allow comparing multiple copies of the same e.g. [`Module`],
@@ -70,7 +94,7 @@ pub struct Plan<'a> {
     /// a distinction will be reflected in the output.
     ///
     /// For [`Node`] collection, the last entry consistutes the "active" version.
-    per_version_name_and_node_defs: Vec<(String, FxHashMap<Node, &'a dyn DynNodeDef<'a>>)>,
+    per_version_name_and_node_defs: Vec<(String, FxIndexMap<Node, &'a dyn DynNodeDef<'a>>)>,
 
     /// Merged per-[`Use`] counts across all versions.
     ///
@@ -78,6 +102,13 @@ pub struct Plan<'a> {
     /// as opposed to their sum. This approach avoids pessimizing e.g. inline
     /// printing of interned definitions, which may need the use count to be `1`.
     use_counts: FxIndexMap<Use, usize>,
+
+    /// Export names (i.e. [`ExportKey::LinkName`], or the name of an entry-point)
+    /// of exported [`Node`]s, in all versions, used by [`NodeOrder::SortedByExportName`].
+    ///
+    /// For nodes exported under multiple names, only the (lexicographically)
+    /// smallest name is kept.
+    export_names: FxIndexMap<Node, String>,
 }
 
 /// Helper for printing a mismatch error between two nodes (e.g. types), while
@@ -227,8 +258,9 @@ impl<'a> Plan<'a> {
         let mut plan = Self {
             cx,
             current_module: None,
-            per_version_name_and_node_defs: vec![(String::new(), FxHashMap::default())],
+            per_version_name_and_node_defs: vec![(String::new(), FxIndexMap::default())],
             use_counts: FxIndexMap::default(),
+            export_names: FxIndexMap::default(),
         };
         plan.use_node(Node::Root, root);
         plan
@@ -264,11 +296,12 @@ impl<'a> Plan<'a> {
             current_module: None,
             per_version_name_and_node_defs: vec![],
             use_counts: FxIndexMap::default(),
+            export_names: FxIndexMap::default(),
         };
         for (version_name, version_root) in versions {
             let mut combined_use_counts = mem::take(&mut plan.use_counts);
             plan.per_version_name_and_node_defs
-                .push((version_name.into(), FxHashMap::default()));
+                .push((version_name.into(), FxIndexMap::default()));
 
             plan.use_node(Node::Root, version_root);
 
@@ -391,6 +424,33 @@ impl<'a> Visitor<'a> for Plan<'a> {
              different `Context` than the one it was initially created with",
         );
 
+        for (export_key, exportee) in &module.exports {
+            let name = match export_key {
+                &ExportKey::LinkName(name) => Some(self.cx[name].to_string()),
+                ExportKey::SpvEntryPoint { imms, .. } => {
+                    // NOTE(eddyb) the execution model precedes the name.
+                    imms.get(1..)
+                        .and_then(|name_imms| spv::extract_literal_string(name_imms).ok())
+                }
+            };
+            let node = match *exportee {
+                Exportee::GlobalVar(gv) => Node::GlobalVar(gv),
+                Exportee::Func(func) => Node::Func(func),
+            };
+            if let Some(name) = name {
+                match self.export_names.entry(node) {
+                    Entry::Occupied(mut entry) => {
+                        if name < *entry.get() {
+                            entry.insert(name);
+                        }
+                    }
+                    Entry::Vacant(entry) => {
+                        entry.insert(name);
+                    }
+                }
+            }
+        }
+
         let old_module = self.current_module.replace(module);
         module.inner_visit_with(self);
         self.current_module = old_module;
@@ -640,6 +700,58 @@ impl<PF> Versions<PF> {
     }
 }
 
+/// Options for [`Plan::pretty_print_with_options`], controlling which details
+/// get printed (with [`Plan::pretty_print`] using the [`Default`] options).
+#[derive(Clone, Default)]
+pub struct PrintOptions {
+    /// Show the SPIR-V result IDs (e.g. `%123`) that definitions were lowered
+    /// from (see [`Attr::SpvOriginalId`]), as comments before the definitions.
+    pub show_spv_original_ids: bool,
+
+    /// Limit on how many elements of constant aggregates (e.g. arrays defined
+    /// with `OpConstantComposite`) get printed, with the rest being elided
+    /// (replaced with `… (N more)`, which in HTML output lists the elided
+    /// elements when hovered), or `None` (the default) to always print all
+    /// elements, which is required for the output to be parseable again
+    /// (see [`parse`](crate::parse)).
+    pub max_const_aggregate_elements: Option<usize>,
+
+    /// Order in which top-level definitions (and the definitions of types,
+    /// constants, etc. grouped together at the start) are printed.
+    pub node_order: NodeOrder,
+
+    /// Show the SPIR-V instructions (by index and word offset) that definitions
+    /// were lifted to, according to a [`spv::lift::SourceMap`] (obtained from
+    /// [`Module::lift_to_spv_module_emitter_with_source_map`]), as comments
+    /// before the definitions.
+    pub spv_source_map: Option<Rc<spv::lift::SourceMap>>,
+}
+
+/// Choice of order for [`PrintOptions::node_order`].
+///
+/// Regardless of the choice, the relative order between definitions that aren't
+/// otherwise distinguished by the ordering criteria is that of [`NodeOrder::DefsBeforeUses`].
+#[derive(Copy, Clone, Default, PartialEq, Eq)]
+pub enum NodeOrder {
+    /// "Definitions-before-uses" / "topo-sorted" order (as computed by [`Plan`]).
+    #[default]
+    DefsBeforeUses,
+
+    /// Grouped by kind: all attribute sets, types, then constants, followed by
+    /// all global variables, and then all functions.
+    ///
+    /// Unlike [`NodeOrder::DefsBeforeUses`], some uses may precede definitions
+    /// (e.g. a global variable initializer referring to a later function).
+    GroupedByKind,
+
+    /// Like [`NodeOrder::GroupedByKind`], but with exported global variables
+    /// and functions sorted by their export names (and preceding any others).
+    ///
+    /// Useful when the output needs to be stable (e.g. golden files for tests)
+    /// across changes that could affect the [`NodeOrder::DefsBeforeUses`] order.
+    SortedByExportName,
+}
+
 impl Plan<'_> {
     #[allow(rustdoc::private_intra_doc_links)]
     /// Print the whole [`Plan`] to a [`Versions<pretty::Fragment>`] and perform
@@ -649,17 +761,33 @@ impl Plan<'_> {
     /// [`fmt::Display`] for convenience, but also more specific methods
     /// (e.g. HTML output).
     pub fn pretty_print(&self) -> Versions<pretty::FragmentPostLayout> {
+        self.pretty_print_with_options(&PrintOptions::default())
+    }
+
+    /// Like [`pretty_print`](Plan::pretty_print), but using custom [`PrintOptions`].
+    pub fn pretty_print_with_options(
+        &self,
+        options: &PrintOptions,
+    ) -> Versions<pretty::FragmentPostLayout> {
         // FIXME(eddyb) make max line width configurable.
         let max_line_width = 120;
 
-        self.print(&Printer::new(self))
+        self.print(&Printer::new(self, options.clone()))
             .map_pretty_fragments(|fragment| fragment.layout_with_max_line_width(max_line_width))
     }
 }
 
 pub struct Printer<'a> {
     cx: &'a Context,
+    options: PrintOptions,
     use_styles: FxIndexMap<Use, UseStyle>,
+
+    /// Plain text summaries (type, definition, attributes) of values, to show
+    /// (e.g. on hover, in HTML output) alongside every use of those values.
+    value_tooltips: FxIndexMap<Use, String>,
+
+    /// Reverse mapping of [`PrintOptions::spv_source_map`] (if provided).
+    spv_inst_idxs_by_lifted_from: FxIndexMap<spv::lift::LiftedFrom, SmallVec<[usize; 1]>>,
 }
 
 /// How an [`Use`] of a definition should be printed.
@@ -678,8 +806,19 @@ enum UseStyle {
     Inline,
 }
 
+/// The definition of a value, as recorded (by [`Printer::new`]) for the purpose
+/// of generating its entry in `value_tooltips`.
+enum ValueDefForTooltip {
+    /// The defining [`DataInstDef`] (with its `attrs` removed, as they're
+    /// instead shown separately).
+    DataInst(DataInstDef),
+
+    /// Short description of a non-instruction definition (e.g. `"loop output #0"`).
+    Other(String),
+}
+
 impl<'a> Printer<'a> {
-    fn new(plan: &Plan<'a>) -> Self {
+    fn new(plan: &Plan<'a>, options: PrintOptions) -> Self {
         let cx = plan.cx;
         let wk = &spv::spec::Spec::get().well_known;
 
@@ -694,9 +833,42 @@ impl<'a> Printer<'a> {
         }
         let mut anon_counters = AnonCounters::default();
 
-        let mut use_styles: FxIndexMap<_, _> = plan
-            .use_counts
-            .iter()
+        // NOTE(eddyb) reordering happens before anything else, so that indices
+        // (e.g. the `123` in `type123`) follow the same order as definitions.
+        let mut use_counts: Vec<_> = plan.use_counts.iter().collect();
+        if options.node_order != NodeOrder::DefsBeforeUses {
+            let kind_rank = |use_kind: Use| match use_kind {
+                Use::Node(Node::ModuleDialect) => 0,
+                Use::Node(Node::ModuleDebugInfo) => 1,
+                Use::Node(Node::AllCxInterned) => 2,
+                Use::CxInterned(CxInterned::AttrSet(_)) => 3,
+                Use::CxInterned(CxInterned::Type(_)) => 4,
+                Use::CxInterned(CxInterned::Const(_)) => 5,
+                Use::Node(Node::GlobalVar(_)) => 6,
+                Use::Node(Node::Func(_)) => 7,
+                Use::Node(Node::Root) => 8,
+                Use::ControlRegionLabel(_)
+                | Use::ControlRegionInput { .. }
+                | Use::ControlNodeOutput { .. }
+                | Use::DataInstOutput(_) => 9,
+            };
+            let export_name = |use_kind: Use| match (options.node_order, use_kind) {
+                (NodeOrder::SortedByExportName, Use::Node(node)) => {
+                    plan.export_names.get(&node).map(|name| &name[..])
+                }
+                _ => None,
+            };
+
+            // NOTE(eddyb) `sort_by_key` is stable, and `None` sorts before
+            // `Some`, hence the `is_none()` to make exported nodes go first.
+            use_counts.sort_by_key(|&(&use_kind, _)| {
+                let export_name = export_name(use_kind);
+                (kind_rank(use_kind), export_name.is_none(), export_name)
+            });
+        }
+
+        let mut use_styles: FxIndexMap<_, _> = use_counts
+            .into_iter()
             .map(|(&use_kind, &use_count)| {
                 // HACK(eddyb) these are assigned later.
                 if let Use::ControlRegionLabel(_)
@@ -733,7 +905,13 @@ impl<'a> Printer<'a> {
                                             // are printed as comments outside
                                             // the `#{...}` syntax, they can't
                                             // work unless they're printed inline.
-                                            matches!(attr, Attr::SpvDebugLine { .. })
+                                            matches!(
+                                                attr,
+                                                Attr::SpvDebugLine { .. }
+                                                    | Attr::SpvOriginalId(_)
+                                                    | Attr::SpvUnsupported(_)
+                                                    | Attr::Diagnostic { .. }
+                                            )
                                         })
                                 }
                                 CxInterned::Type(ty) => {
@@ -750,7 +928,18 @@ impl<'a> Printer<'a> {
                                         ]
                                         .contains(&inst.opcode),
 
-                                        TypeCtor::SpvStringLiteralForExtInst => true,
+                                        TypeCtor::SpvStringLiteralForExtInst
+                                        | TypeCtor::Matrix { .. }
+                                        | TypeCtor::Array
+                                        | TypeCtor::RuntimeArray
+                                        | TypeCtor::RecursivePtrSelf { .. }
+                                        | TypeCtor::QPtr
+                                        | TypeCtor::Sampler
+                                        | TypeCtor::SampledImage => true,
+
+                                        TypeCtor::Struct { .. }
+                                        | TypeCtor::RecursivePtr { .. }
+                                        | TypeCtor::Image(_) => false,
                                     };
 
                                     ty_def.attrs == AttrSet::default()
@@ -769,7 +958,14 @@ impl<'a> Printer<'a> {
                                         _ => false,
                                     };
 
+                                    // NOTE(eddyb) spec constants are only
+                                    // inlined when used once, as their identity
+                                    // matters more than their default value.
+                                    let is_spec_const =
+                                        matches!(ct_def.ctor, ConstCtor::SpecConst { .. });
+
                                     ct_def.attrs == AttrSet::default()
+                                        && !is_spec_const
                                         && (has_compact_print || ct_def.ctor_args.is_empty())
                                 }
                             }
@@ -816,6 +1012,10 @@ impl<'a> Printer<'a> {
             })
             .collect();
 
+        // NOTE(eddyb) values are only summarized for tooltips after all the
+        // `use_styles` have been assigned (as that's needed to print anything).
+        let mut value_defs_for_tooltips = vec![];
+
         let all_funcs = plan
             .use_counts
             .keys()
@@ -835,6 +1035,8 @@ impl<'a> Printer<'a> {
             // Assign a new label/value index, but only if:
             // * the definition is actually used
             // * it doesn't already have an index (e.g. from a previous version)
+            //
+            // Returns `true` iff a new index was assigned.
             let mut define_label_or_value = |use_kind: Use| {
                 if let Some(use_style @ UseStyle::Inline) = use_styles.get_mut(&use_kind) {
                     let counter = match use_kind {
@@ -847,6 +1049,9 @@ impl<'a> Printer<'a> {
                         parent_func: Some(func),
                         idx,
                     };
+                    true
+                } else {
+                    false
                 }
             };
 
@@ -876,32 +1081,77 @@ impl<'a> Printer<'a> {
                         outputs: _,
                     } = func_def_body.at(region).def();
 
-                    for (i, _) in inputs.iter().enumerate() {
-                        define_label_or_value(Use::ControlRegionInput {
+                    for (i, input_decl) in inputs.iter().enumerate() {
+                        let input = Use::ControlRegionInput {
                             region,
                             input_idx: i.try_into().unwrap(),
-                        });
+                        };
+                        if define_label_or_value(input) {
+                            value_defs_for_tooltips.push((
+                                input,
+                                input_decl.attrs,
+                                input_decl.ty,
+                                ValueDefForTooltip::Other(format!("region input #{i}")),
+                            ));
+                        }
                     }
 
                     for func_at_control_node in func_def_body.at(*children) {
                         let control_node = func_at_control_node.position;
-                        let ControlNodeDef { kind, outputs } = func_at_control_node.def();
+                        let ControlNodeDef {
+                            attrs: _,
+                            kind,
+                            outputs,
+                        } = func_at_control_node.def();
 
                         if let ControlNodeKind::Block { insts } = *kind {
                             for func_at_inst in func_def_body.at(insts) {
-                                if func_at_inst.def().output_type.is_some() {
-                                    define_label_or_value(Use::DataInstOutput(
-                                        func_at_inst.position,
-                                    ));
+                                let data_inst_def = func_at_inst.def();
+                                if let Some(output_type) = data_inst_def.output_type {
+                                    let output = Use::DataInstOutput(func_at_inst.position);
+                                    if define_label_or_value(output) {
+                                        value_defs_for_tooltips.push((
+                                            output,
+                                            data_inst_def.attrs,
+                                            output_type,
+                                            ValueDefForTooltip::DataInst(DataInstDef {
+                                                attrs: AttrSet::default(),
+                                                ..data_inst_def.clone()
+                                            }),
+                                        ));
+                                    }
                                 }
                             }
                         }
 
-                        for (i, _) in outputs.iter().enumerate() {
-                            define_label_or_value(Use::ControlNodeOutput {
+                        let control_node_kind_name = match kind {
+                            ControlNodeKind::Block { .. } => "block",
+                            ControlNodeKind::Select {
+                                kind: SelectionKind::BoolCond,
+                                ..
+                            } => "if",
+                            ControlNodeKind::Select {
+                                kind: SelectionKind::SpvInst(inst),
+                                ..
+                            } => inst.opcode.name(),
+                            ControlNodeKind::Loop { .. } => "loop",
+                            ControlNodeKind::ExitInvocation { .. } => "exit",
+                        };
+                        for (i, output_decl) in outputs.iter().enumerate() {
+                            let output = Use::ControlNodeOutput {
                                 control_node,
                                 output_idx: i.try_into().unwrap(),
-                            });
+                            };
+                            if define_label_or_value(output) {
+                                value_defs_for_tooltips.push((
+                                    output,
+                                    output_decl.attrs,
+                                    output_decl.ty,
+                                    ValueDefForTooltip::Other(format!(
+                                        "{control_node_kind_name} output #{i}"
+                                    )),
+                                ));
+                            }
                         }
                     }
                 };
@@ -929,12 +1179,85 @@ impl<'a> Printer<'a> {
             }
         }
 
-        Self { cx, use_styles }
+        let spv_inst_idxs_by_lifted_from = options
+            .spv_source_map
+            .as_ref()
+            .map(|source_map| source_map.inst_idxs_by_lifted_from())
+            .unwrap_or_default();
+
+        let mut printer = Self {
+            cx,
+            options,
+            use_styles,
+            value_tooltips: FxIndexMap::default(),
+            spv_inst_idxs_by_lifted_from,
+        };
+        printer.value_tooltips = value_defs_for_tooltips
+            .into_iter()
+            .map(|(value, attrs, ty, def)| {
+                let def = match def {
+                    ValueDefForTooltip::DataInst(data_inst_def) => {
+                        data_inst_def.print(&printer).def_without_name
+                    }
+                    ValueDefForTooltip::Other(descr) => descr.into(),
+                };
+
+                // FIXME(eddyb) make max line width configurable.
+                let max_line_width = 80;
+
+                let mut tooltip = format!(
+                    "type: {}\ndef: {}",
+                    ty.print(&printer)
+                        .layout_with_max_line_width(max_line_width),
+                    def.layout_with_max_line_width(max_line_width)
+                );
+                if attrs != AttrSet::default() {
+                    write!(
+                        tooltip,
+                        "\nattrs: {}",
+                        attrs
+                            .print(&printer)
+                            .layout_with_max_line_width(max_line_width)
+                    )
+                    .unwrap();
+                }
+                (value, tooltip.trim().to_string())
+            })
+            .collect();
+        printer
     }
 
     pub fn cx(&self) -> &'a Context {
         self.cx
     }
+
+    /// Comment (followed by a line break) listing the SPIR-V instructions that
+    /// `lifted_from` was lifted to (see [`PrintOptions::spv_source_map`]), or
+    /// an empty fragment if there aren't any.
+    fn pretty_spv_source_map_comment(
+        &self,
+        lifted_from: spv::lift::LiftedFrom,
+    ) -> pretty::Fragment {
+        let (source_map, inst_idxs) = match (
+            &self.options.spv_source_map,
+            self.spv_inst_idxs_by_lifted_from.get(&lifted_from),
+        ) {
+            (Some(source_map), Some(inst_idxs)) => (source_map, inst_idxs),
+            _ => return pretty::Fragment::default(),
+        };
+        let insts = inst_idxs
+            .iter()
+            .map(|&inst_idx| {
+                let word_offset = source_map.insts[inst_idx].word_offset;
+                format!("#{inst_idx} (word {word_offset})")
+            })
+            .join(", ");
+        pretty::Fragment::new([
+            self.comment_style()
+                .apply(format!("// lifted to SPIR-V {insts}")),
+            pretty::Node::ForceLineSeparation,
+        ])
+    }
 }
 
 // Styles for a variety of syntactic categories.
@@ -946,6 +1269,9 @@ impl Printer<'_> {
     fn error_style(&self) -> pretty::Styles {
         pretty::Styles::color(pretty::palettes::simple::MAGENTA)
     }
+    fn warning_style(&self) -> pretty::Styles {
+        pretty::Styles::color(pretty::palettes::simple::YELLOW)
+    }
     fn comment_style(&self) -> pretty::Styles {
         pretty::Styles {
             color_opacity: Some(0.3),
@@ -1024,6 +1350,27 @@ impl<'a> Printer<'a> {
         ])
     }
 
+    /// Pretty-print the "header" of a SPIR-V `OpExtInst` (i.e. its extended
+    /// instruction set and instruction number, but not its operands).
+    fn pretty_spv_ext_inst_header(&self, ext_set: InternedStr, inst: u32) -> pretty::Fragment {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        // FIXME(eddyb) should this be rendered more compactly?
+        pretty::Fragment::new([
+            "(".into(),
+            self.pretty_spv_opcode(self.spv_op_style(), wk.OpExtInstImport),
+            "<".into(),
+            self.string_literal_style()
+                .apply(format!("{:?}", &self.cx[ext_set]))
+                .into(),
+            ">).".into(),
+            self.pretty_spv_opcode(self.spv_op_style(), wk.OpExtInst),
+            "<".into(),
+            self.numeric_literal_style().apply(format!("{inst}")).into(),
+            ">".into(),
+        ])
+    }
+
     /// Pretty-print a single SPIR-V operand from only immediates, potentially
     /// composed of an enumerand with parameters (which consumes more immediates).
     fn pretty_spv_operand_from_imms(
@@ -1188,6 +1535,10 @@ impl AttrsAndDef {
     /// If `name` starts with an anchor definition, the definition of that anchor
     /// gets hoised to before (some non-empty) `attrs`, so that navigating to that
     /// anchor doesn't "hide" those attributes (requiring scrolling to see them).
+    ///
+    /// The `name` itself remains an anchor definition as well (e.g. so that it
+    /// can still link to all uses), but HTML output only gives an `id` to the
+    /// first anchor definition (i.e. the hoisted one, in this case).
     fn insert_name_before_def(self, name: impl Into<pretty::Fragment>) -> pretty::Fragment {
         let Self {
             attrs,
@@ -1195,10 +1546,10 @@ impl AttrsAndDef {
         } = self;
 
         let mut maybe_hoisted_anchor = pretty::Fragment::default();
-        let mut name = name.into();
-        if let [pretty::Node::StyledText(ref mut styles_and_text), ..] = name.nodes[..] {
-            let styles = &mut styles_and_text.0;
-            if !attrs.nodes.is_empty() && mem::take(&mut styles.anchor_is_def) {
+        let name = name.into();
+        if let [pretty::Node::StyledText(ref styles_and_text), ..] = name.nodes[..] {
+            let styles = &styles_and_text.0;
+            if !attrs.nodes.is_empty() && styles.anchor_is_def {
                 maybe_hoisted_anchor = pretty::Styles {
                     anchor: styles.anchor.clone(),
                     anchor_is_def: true,
@@ -1285,6 +1636,7 @@ impl Use {
                 let name = pretty::Styles {
                     anchor: Some(anchor),
                     anchor_is_def: is_def,
+                    tooltip: printer.value_tooltips.get(self).cloned(),
                     ..name_style
                 }
                 .apply(name);
@@ -1359,66 +1711,111 @@ impl Print for Func {
     }
 }
 
-// NOTE(eddyb) the `Print` impl for `Node` is for the top-level definition,
-// *not* any uses (which go through the `Print` impls above).
-
-impl Print for Plan<'_> {
-    type Output = Versions<pretty::Fragment>;
-    fn print(&self, printer: &Printer<'_>) -> Versions<pretty::Fragment> {
+impl Plan<'_> {
+    /// Print every top-level [`Node`] (in order), with its definitions across
+    /// all versions (see [`Versions::Multiple`] for the meaning of the `usize`).
+    #[allow(clippy::type_complexity)]
+    fn print_per_node_versions_with_repeat_count(
+        &self,
+        printer: &Printer<'_>,
+    ) -> Vec<(Node, SmallVec<[(pretty::Fragment, usize); 1]>)> {
         let num_versions = self.per_version_name_and_node_defs.len();
-        let per_node_versions_with_repeat_count = printer
+        printer
             .use_styles
             .keys()
             .filter_map(|&use_kind| match use_kind {
                 Use::Node(node) => Some(node),
                 _ => None,
             })
-            .map(|node| -> SmallVec<[_; 1]> {
-                if num_versions == 0 {
-                    return [].into_iter().collect();
-                }
+            .map(|node| {
+                (
+                    node,
+                    self.print_node_versions_with_repeat_count(printer, node, num_versions),
+                )
+            })
+            .collect()
+    }
 
-                let name = if node.category().is_err() {
-                    pretty::Fragment::default()
-                } else {
-                    Use::Node(node).print_as_def(printer)
-                };
+    fn print_node_versions_with_repeat_count(
+        &self,
+        printer: &Printer<'_>,
+        node: Node,
+        num_versions: usize,
+    ) -> SmallVec<[(pretty::Fragment, usize); 1]> {
+        if num_versions == 0 {
+            return [].into_iter().collect();
+        }
 
-                // Avoid printing `AllCxInterned` more than once, it doesn't
-                // really have per-version node definitions in the first place.
-                if let Node::AllCxInterned = node {
-                    // FIXME(eddyb) maybe make `DynNodeDef` `Any`-like, to be
-                    // able to assert that all per-version defs are identical.
-                    return [(
-                        AllCxInterned.print(printer).insert_name_before_def(name),
-                        num_versions,
-                    )]
-                    .into_iter()
-                    .collect();
-                }
+        let name = if node.category().is_err() {
+            pretty::Fragment::default()
+        } else {
+            Use::Node(node).print_as_def(printer)
+        };
 
-                self.per_version_name_and_node_defs
-                    .iter()
-                    .map(move |(_, node_defs)| {
-                        node_defs
-                            .get(&node)
-                            .map(|def| def.print(printer).insert_name_before_def(name.clone()))
-                            .unwrap_or_default()
-                    })
-                    .dedup_with_count()
-                    .map(|(repeat_count, fragment)| {
-                        // FIXME(eddyb) consider rewriting intra-func anchors
-                        // here, post-deduplication, to be unique per-version.
-                        // Additionally, a diff algorithm could be employed, to
-                        // annotate the changes between versions.
-
-                        (fragment, repeat_count)
+        // Avoid printing `AllCxInterned` more than once, it doesn't
+        // really have per-version node definitions in the first place.
+        if let Node::AllCxInterned = node {
+            // FIXME(eddyb) maybe make `DynNodeDef` `Any`-like, to be
+            // able to assert that all per-version defs are identical.
+            return [(
+                AllCxInterned.print(printer).insert_name_before_def(name),
+                num_versions,
+            )]
+            .into_iter()
+            .collect();
+        }
+
+        let spv_source_map_comment = match node {
+            Node::GlobalVar(gv) => {
+                printer.pretty_spv_source_map_comment(spv::lift::LiftedFrom::GlobalVar(gv))
+            }
+            Node::Func(func) => {
+                printer.pretty_spv_source_map_comment(spv::lift::LiftedFrom::Func(func))
+            }
+            _ => pretty::Fragment::default(),
+        };
+
+        self.per_version_name_and_node_defs
+            .iter()
+            .map(move |(_, node_defs)| {
+                node_defs
+                    .get(&node)
+                    .map(|def| {
+                        pretty::Fragment::new([
+                            spv_source_map_comment.clone(),
+                            def.print(printer).insert_name_before_def(name.clone()),
+                        ])
                     })
-                    .collect()
-            });
+                    .unwrap_or_default()
+            })
+            .dedup_with_count()
+            .map(|(repeat_count, fragment)| {
+                // FIXME(eddyb) consider rewriting intra-func anchors
+                // here, post-deduplication, to be unique per-version.
+                // Additionally, a diff algorithm could be employed, to
+                // annotate the changes between versions.
+
+                (fragment, repeat_count)
+            })
+            .collect()
+    }
+}
+
+// NOTE(eddyb) the `Print` impl for `Node` is for the top-level definition,
+// *not* any uses (which go through the `Print` impls above).
+
+impl Print for Plan<'_> {
+    type Output = Versions<pretty::Fragment>;
+    fn print(&self, printer: &Printer<'_>) -> Versions<pretty::Fragment> {
+        let per_node_versions_with_repeat_count = self
+            .print_per_node_versions_with_repeat_count(printer)
+            .into_iter()
+            .map(|(_, versions_with_repeat_count)| versions_with_repeat_count);
 
         // Unversioned, flatten the nodes.
-        if num_versions == 1 && self.per_version_name_and_node_defs[0].0.is_empty() {
+        if self.per_version_name_and_node_defs.len() == 1
+            && self.per_version_name_and_node_defs[0].0.is_empty()
+        {
             Versions::Single(pretty::Fragment::new(
                 per_node_versions_with_repeat_count
                     .map(|mut versions_with_repeat_count| {
@@ -1721,9 +2118,22 @@ impl Print for AllCxInterned {
                 }
                 .apply(name);
 
-                interned
-                    .print(printer)
-                    .insert_name_before_def(pretty::Fragment::new([name, " = ".into()]))
+                let spv_source_map_comment = match interned {
+                    CxInterned::AttrSet(_) => pretty::Fragment::default(),
+                    CxInterned::Type(ty) => {
+                        printer.pretty_spv_source_map_comment(spv::lift::LiftedFrom::Type(ty))
+                    }
+                    CxInterned::Const(ct) => {
+                        printer.pretty_spv_source_map_comment(spv::lift::LiftedFrom::Const(ct))
+                    }
+                };
+
+                pretty::Fragment::new([
+                    spv_source_map_comment,
+                    interned
+                        .print(printer)
+                        .insert_name_before_def(pretty::Fragment::new([name, " = ".into()])),
+                ])
             })
             .intersperse({
                 // Separate top-level definitions with empty lines.
@@ -1761,6 +2171,12 @@ impl Print for AttrSetDef {
         let mut comments = SmallVec::<[_; 1]>::new();
         let mut non_comment_attrs = SmallVec::<[_; 4]>::new();
         for attr in attrs {
+            if let Attr::SpvOriginalId(_) = attr {
+                if !printer.options.show_spv_original_ids {
+                    continue;
+                }
+            }
+
             let (attr_style, attr) = attr.print(printer);
             match attr_style {
                 AttrStyle::Comment => comments.push(attr),
@@ -1823,6 +2239,21 @@ impl Print for Attr {
                     ),
                 )
             }
+            Attr::SpvExecutionModeId {
+                inst: spv::Inst { opcode, imms },
+                consts,
+            } => (
+                AttrStyle::NonComment,
+                // NOTE(eddyb) `None` stands for the implicit target ID.
+                printer.pretty_spv_inst(
+                    printer.attr_style(),
+                    *opcode,
+                    imms,
+                    [None].into_iter().chain(consts.iter().map(|ct| Some(ct.0))),
+                    |ct: Option<Const>, printer| ct.map(|ct| ct.print(printer)),
+                    None,
+                ),
+            ),
             &Attr::SpvDebugLine {
                 file_path,
                 line,
@@ -1848,9 +2279,115 @@ impl Print for Attr {
                     printer.comment_style().apply(comment).into(),
                 )
             }
-            &Attr::SpvBitflagsOperand(imm) => (
+            Attr::SpvBitflagsOperand(imms) => (
+                AttrStyle::NonComment,
+                printer.pretty_spv_operand_from_imms(imms.iter().copied()),
+            ),
+            &Attr::SpvOriginalId(id) => (
+                AttrStyle::Comment,
+                printer
+                    .comment_style()
+                    .apply(format!("// originally %{id}"))
+                    .into(),
+            ),
+            Attr::SpvUnsupported(message) => (
+                AttrStyle::Comment,
+                printer
+                    .error_style()
+                    .apply(format!("// unsupported SPIR-V: {message:?}"))
+                    .into(),
+            ),
+            Attr::Diagnostic {
+                severity,
+                message,
+                related,
+            } => {
+                let style = match severity {
+                    DiagnosticSeverity::Error => printer.error_style(),
+                    DiagnosticSeverity::Warning => printer.warning_style(),
+                };
+                let mut comment = format!("// {severity}: {message:?}");
+                if !related.is_empty() {
+                    comment += &format!(", related: {related:?}");
+                }
+                (AttrStyle::Comment, style.apply(comment).into())
+            }
+            &Attr::SpvShaderDebugScope { scope, inlined_at } => (
+                AttrStyle::NonComment,
+                pretty::Fragment::new([
+                    printer.attr_style().apply("debug_scope").into(),
+                    pretty::join_comma_sep(
+                        "(",
+                        [scope.0.print(printer)]
+                            .into_iter()
+                            .chain(inlined_at.map(|inlined_at| {
+                                pretty::Fragment::new([
+                                    "inlined_at: ".into(),
+                                    inlined_at.0.print(printer),
+                                ])
+                            })),
+                        ")",
+                    ),
+                ]),
+            ),
+            &Attr::SpvShaderDebugLine {
+                source,
+                line_start,
+                line_end,
+                col_start,
+                col_end,
+            } => (
+                AttrStyle::NonComment,
+                pretty::Fragment::new([
+                    printer.attr_style().apply("debug_line").into(),
+                    pretty::join_comma_sep(
+                        "(",
+                        [source.0.print(printer)].into_iter().chain(
+                            [line_start, line_end, col_start, col_end].map(|x| {
+                                printer.numeric_literal_style().apply(format!("{x}")).into()
+                            }),
+                        ),
+                        ")",
+                    ),
+                ]),
+            ),
+            &Attr::QPtr(QPtrAttr::FromSpvPtr {
+                storage_class,
+                pointee,
+            }) => {
+                let wk = &spv::spec::Spec::get().well_known;
+
+                (
+                    AttrStyle::NonComment,
+                    pretty::Fragment::new([
+                        printer.attr_style().apply("qptr.from_spv_ptr").into(),
+                        pretty::join_comma_sep(
+                            "(",
+                            [
+                                printer.pretty_spv_imm(wk.StorageClass, storage_class),
+                                pointee.0.print(printer),
+                            ],
+                            ")",
+                        ),
+                    ]),
+                )
+            }
+            &Attr::QPtr(QPtrAttr::ToSpvPtrInput { input_idx, pointee }) => (
                 AttrStyle::NonComment,
-                printer.pretty_spv_operand_from_imms([imm]),
+                pretty::Fragment::new([
+                    printer.attr_style().apply("qptr.to_spv_ptr_input").into(),
+                    pretty::join_comma_sep(
+                        "(",
+                        [
+                            printer
+                                .numeric_literal_style()
+                                .apply(format!("{input_idx}"))
+                                .into(),
+                            pointee.0.print(printer),
+                        ],
+                        ")",
+                    ),
+                ]),
             ),
         }
     }
@@ -1916,6 +2453,11 @@ impl Print for TypeDef {
             None
         };
 
+        let pretty_ctor_arg = |&arg: &TypeCtorArg| match arg {
+            TypeCtorArg::Type(ty) => ty.print(printer),
+            TypeCtorArg::Const(ct) => ct.print(printer),
+        };
+
         AttrsAndDef {
             attrs: attrs.print(printer),
             def_without_name: if let Some(def) = compact_def {
@@ -1939,6 +2481,139 @@ impl Print for TypeDef {
                         printer.pretty_spv_opcode(printer.spv_op_style(), wk.OpString),
                         ")".into(),
                     ]),
+                    // NOTE(eddyb) matrices are printed like vectors, but with
+                    // the column (vector) type in place of the element type.
+                    TypeCtor::Matrix { column_count } => pretty::Fragment::new([
+                        pretty_ctor_arg(&ctor_args[0]),
+                        "×".into(),
+                        printer
+                            .numeric_literal_style()
+                            .apply(format!("{column_count}"))
+                            .into(),
+                    ]),
+                    TypeCtor::Array => pretty::Fragment::new([
+                        "[".into(),
+                        pretty_ctor_arg(&ctor_args[0]),
+                        "; ".into(),
+                        pretty_ctor_arg(&ctor_args[1]),
+                        "]".into(),
+                    ]),
+                    TypeCtor::RuntimeArray => pretty::Fragment::new([
+                        "[".into(),
+                        pretty_ctor_arg(&ctor_args[0]),
+                        "]".into(),
+                    ]),
+                    // NOTE(eddyb) all the immediate operands of `OpTypeImage`
+                    // are printed as named fields, after the sampled type.
+                    TypeCtor::Image(ImageType {
+                        dim,
+                        depth,
+                        arrayed,
+                        multisampled,
+                        sampled,
+                        format,
+                        access_qualifier,
+                    }) => {
+                        let pretty_bool = |b: Option<bool>| {
+                            kw(match b {
+                                Some(false) => "false",
+                                Some(true) => "true",
+                                None => "unknown",
+                            }
+                            .into())
+                        };
+                        let field = |name: &'static str, value| {
+                            pretty::Fragment::new([name.into(), ": ".into(), value])
+                        };
+                        pretty::Fragment::new([
+                            kw("image".into()),
+                            pretty::join_comma_sep(
+                                "(",
+                                [
+                                    pretty_ctor_arg(&ctor_args[0]),
+                                    field("dim", printer.pretty_spv_imm(wk.Dim, dim)),
+                                    field("depth", pretty_bool(depth)),
+                                    field("arrayed", pretty_bool(Some(arrayed))),
+                                    field("multisampled", pretty_bool(Some(multisampled))),
+                                    field("sampled", pretty_bool(sampled)),
+                                    field("format", printer.pretty_spv_imm(wk.ImageFormat, format)),
+                                ]
+                                .into_iter()
+                                .chain(access_qualifier.map(|access_qualifier| {
+                                    field(
+                                        "access",
+                                        printer
+                                            .pretty_spv_imm(wk.AccessQualifier, access_qualifier),
+                                    )
+                                })),
+                                ")",
+                            ),
+                        ])
+                    }
+                    // NOTE(eddyb) recursive pointer types are printed like
+                    // any other pointer type, just with a `recursive` prefix,
+                    // while their (folded) pointee refers back to them through
+                    // `recursive_self` (with a de Bruijn index if not `0`).
+                    TypeCtor::RecursivePtr { storage_class } => pretty::Fragment::new([
+                        kw("recursive".into()),
+                        " ".into(),
+                        printer.pretty_spv_inst(
+                            printer.spv_op_style(),
+                            wk.OpTypePointer,
+                            &[spv::Imm::Short(wk.StorageClass, storage_class)],
+                            ctor_args,
+                            |arg, _| pretty_ctor_arg(arg),
+                            None,
+                        ),
+                    ]),
+                    TypeCtor::RecursivePtrSelf { depth } => pretty::Fragment::new([
+                        kw("recursive_self".into()),
+                        if depth == 0 {
+                            pretty::Fragment::default()
+                        } else {
+                            pretty::Fragment::new([
+                                "(".into(),
+                                printer.numeric_literal_style().apply(format!("{depth}")),
+                                ")".into(),
+                            ])
+                        },
+                    ]),
+                    TypeCtor::QPtr => kw("qptr".into()),
+                    TypeCtor::Sampler => kw("sampler".into()),
+                    TypeCtor::SampledImage => pretty::Fragment::new([
+                        kw("sampled_image".into()),
+                        "(".into(),
+                        pretty_ctor_arg(&ctor_args[0]),
+                        ")".into(),
+                    ]),
+                    // NOTE(eddyb) members are printed as `offset: type`, or
+                    // just `type` if lacking an explicit layout, preceded by
+                    // their member attributes (i.e. member decorations).
+                    TypeCtor::Struct { ref members } => pretty::Fragment::new([
+                        kw("struct".into()),
+                        " ".into(),
+                        pretty::join_comma_sep(
+                            "{",
+                            members.iter().zip(ctor_args).map(|(member, arg)| {
+                                pretty::Fragment::new([
+                                    member.attrs.print(printer),
+                                    member
+                                        .offset
+                                        .map(|offset| {
+                                            pretty::Fragment::new([
+                                                printer
+                                                    .numeric_literal_style()
+                                                    .apply(format!("{offset}")),
+                                                ": ".into(),
+                                            ])
+                                        })
+                                        .unwrap_or_default(),
+                                    pretty_ctor_arg(arg),
+                                ])
+                            }),
+                            "}",
+                        ),
+                    ]),
                 }
             },
         }
@@ -1967,7 +2642,7 @@ impl Print for ConstDef {
             }
             .apply(ty)
         };
-        let compact_def = if let &ConstCtor::SpvInst(spv::Inst { opcode, ref imms }) = ctor {
+        let compact_spv_const = |opcode, imms: &[spv::Imm]| {
             if opcode == wk.OpConstantFalse {
                 Some(kw("false"))
             } else if opcode == wk.OpConstantTrue {
@@ -2067,8 +2742,10 @@ impl Print for ConstDef {
             } else {
                 None
             }
-        } else {
-            None
+        };
+        let compact_def = match *ctor {
+            ConstCtor::SpvInst(spv::Inst { opcode, ref imms }) => compact_spv_const(opcode, imms),
+            _ => None,
         };
 
         AttrsAndDef {
@@ -2077,14 +2754,98 @@ impl Print for ConstDef {
                 ConstCtor::PtrToGlobalVar(gv) => {
                     pretty::Fragment::new(["&".into(), gv.print(printer)])
                 }
-                ConstCtor::SpvInst(spv::Inst { opcode, ref imms }) => printer.pretty_spv_inst(
-                    printer.spv_op_style(),
-                    opcode,
-                    imms,
-                    ctor_args,
-                    Print::print,
-                    Some(*ty),
-                ),
+                ConstCtor::Undef => {
+                    pretty::Fragment::new([kw("undef"), printer.pretty_type_ascription_suffix(*ty)])
+                }
+                ConstCtor::SpvInst(spv::Inst { opcode, ref imms }) => {
+                    let elided_ctor_args = printer
+                        .options
+                        .max_const_aggregate_elements
+                        .filter(|_| {
+                            [wk.OpConstantComposite, wk.OpSpecConstantComposite].contains(&opcode)
+                        })
+                        .and_then(|max_elements| ctor_args.get(max_elements..))
+                        .filter(|elided| !elided.is_empty())
+                        .unwrap_or_default();
+
+                    // HACK(eddyb) `None` is used below to indicate the position
+                    // of all the elided elements, i.e. the `… (N more)` marker.
+                    let printed_ctor_args = ctor_args[..ctor_args.len() - elided_ctor_args.len()]
+                        .iter()
+                        .copied()
+                        .map(Some)
+                        .chain((!elided_ctor_args.is_empty()).then_some(None));
+
+                    printer.pretty_spv_inst(
+                        printer.spv_op_style(),
+                        opcode,
+                        imms,
+                        printed_ctor_args,
+                        |ct, printer| match ct {
+                            Some(ct) => ct.print(printer),
+                            None => {
+                                // FIXME(eddyb) make max line width configurable.
+                                let max_line_width = 80;
+
+                                let elided = elided_ctor_args
+                                    .iter()
+                                    .map(|ct| {
+                                        ct.print(printer)
+                                            .layout_with_max_line_width(max_line_width)
+                                            .to_string()
+                                    })
+                                    .collect::<Vec<_>>()
+                                    .join(", ");
+                                pretty::Styles {
+                                    tooltip: Some(elided),
+                                    ..printer.comment_style()
+                                }
+                                .apply(format!("… ({} more)", elided_ctor_args.len()))
+                                .into()
+                            }
+                        },
+                        Some(*ty),
+                    )
+                }
+                ConstCtor::SpecConst {
+                    spec_id,
+                    ref default,
+                } => pretty::Fragment::new([
+                    kw("spec"),
+                    "(".into(),
+                    printer
+                        .numeric_literal_style()
+                        .apply(format!("{spec_id}"))
+                        .into(),
+                    ") = ".into(),
+                    compact_spv_const(default.opcode, &default.imms).unwrap_or_else(|| {
+                        printer.pretty_spv_inst(
+                            printer.spv_op_style(),
+                            default.opcode,
+                            &default.imms,
+                            [] as [Const; 0],
+                            |ct, printer| ct.print(printer),
+                            Some(*ty),
+                        )
+                    }),
+                ]),
+                ConstCtor::SpecConstOp(spv::Inst { opcode, ref imms }) => pretty::Fragment::new([
+                    kw("spec_op"),
+                    " ".into(),
+                    printer.pretty_spv_inst(
+                        printer.spv_op_style(),
+                        opcode,
+                        imms,
+                        ctor_args.iter().copied(),
+                        |ct, printer| ct.print(printer),
+                        Some(*ty),
+                    ),
+                ]),
+                ConstCtor::SpvExtInst { ext_set, inst } => pretty::Fragment::new([
+                    printer.pretty_spv_ext_inst_header(ext_set, inst),
+                    pretty::join_comma_sep("(", ctor_args.iter().map(|ct| ct.print(printer)), ")"),
+                    printer.pretty_type_ascription_suffix(*ty),
+                ]),
                 ConstCtor::SpvStringLiteralForExtInst(s) => pretty::Fragment::new([
                     printer.pretty_spv_opcode(printer.spv_op_style(), wk.OpString),
                     "<".into(),
@@ -2094,6 +2855,10 @@ impl Print for ConstDef {
                         .into(),
                     ">".into(),
                 ]),
+                ConstCtor::SpvTypeOperand => pretty::Fragment::new([
+                    kw("type_operand"),
+                    printer.pretty_type_ascription_suffix(*ty),
+                ]),
             }),
         }
     }
@@ -2147,9 +2912,9 @@ impl Print for GlobalVarDecl {
                 ]),
             }
         };
-        let addr_space = match *addr_space {
-            AddrSpace::SpvStorageClass(sc) => printer.pretty_spv_imm(wk.StorageClass, sc),
-        };
+        // NOTE(eddyb) `AddrSpace::PhysicalStorageBuffer` is printed as its SPIR-V
+        // `StorageClass`, relying on `AddrSpace::from_spv_storage_class` when parsing.
+        let addr_space = printer.pretty_spv_imm(wk.StorageClass, addr_space.to_spv_storage_class());
         let header = pretty::Fragment::new([" in ".into(), addr_space, type_ascription_suffix]);
 
         let body = match def {
@@ -2318,6 +3083,8 @@ impl Print for FuncAt<'_, ControlRegion> {
         };
 
         pretty::Fragment::new([
+            printer
+                .pretty_spv_source_map_comment(spv::lift::LiftedFrom::ControlRegion(self.position)),
             self.at(*children).into_iter().print(printer),
             outputs_footer,
         ])
@@ -2338,7 +3105,11 @@ impl Print for FuncAt<'_, ControlNode> {
     type Output = pretty::Fragment;
     fn print(&self, printer: &Printer<'_>) -> pretty::Fragment {
         let control_node = self.position;
-        let ControlNodeDef { kind, outputs } = self.def();
+        let ControlNodeDef {
+            attrs,
+            kind,
+            outputs,
+        } = self.def();
 
         let outputs_header = if !outputs.is_empty() {
             let mut outputs = outputs.iter().enumerate().map(|(output_idx, output)| {
@@ -2373,7 +3144,20 @@ impl Print for FuncAt<'_, ControlNode> {
                         .into_iter()
                         .map(|func_at_inst| {
                             let data_inst_def = func_at_inst.def();
-                            data_inst_def.print(printer).insert_name_before_def(
+                            let AttrsAndDef {
+                                attrs,
+                                def_without_name,
+                            } = data_inst_def.print(printer);
+                            AttrsAndDef {
+                                attrs: pretty::Fragment::new([
+                                    attrs,
+                                    printer.pretty_spv_source_map_comment(
+                                        spv::lift::LiftedFrom::DataInst(func_at_inst.position),
+                                    ),
+                                ]),
+                                def_without_name,
+                            }
+                            .insert_name_before_def(
                                 if data_inst_def.output_type.is_none() {
                                     pretty::Fragment::default()
                                 } else {
@@ -2483,8 +3267,27 @@ impl Print for FuncAt<'_, ControlNode> {
                     repeat_condition.print(printer),
                 ])
             }
+            ControlNodeKind::ExitInvocation { kind, inputs } => {
+                assert!(outputs.is_empty());
+
+                // NOTE(eddyb) the `exit` prefix distinguishes this from the
+                // equivalent `cfg::ControlInst` (i.e. a region terminator).
+                pretty::Fragment::new([
+                    kw("exit"),
+                    " ".into(),
+                    kind.print_with_inputs(printer, kw_style.clone(), inputs),
+                ])
+            }
         };
-        pretty::Fragment::new([outputs_header, control_node_body])
+        // NOTE(eddyb) the attributes of the `ControlNode` itself are printed
+        // after its outputs (if any), to avoid confusing them with the
+        // attributes of a single output (which get printed before its name).
+        pretty::Fragment::new([
+            printer.pretty_spv_source_map_comment(spv::lift::LiftedFrom::ControlNode(control_node)),
+            outputs_header,
+            attrs.print(printer),
+            control_node_body,
+        ])
     }
 }
 
@@ -2544,27 +3347,114 @@ impl Print for DataInstDef {
                 };
             }
             DataInstKind::SpvExtInst { ext_set, inst } => {
+                printer.pretty_spv_ext_inst_header(ext_set, inst)
+            }
+            DataInstKind::SpvGlslStd450(op) => pretty::Fragment::new([
+                printer
+                    .demote_style_for_namespace_prefix(printer.spv_base_style())
+                    .apply("glsl.ext."),
+                printer.spv_op_style().apply(op.name()),
+            ]),
+            DataInstKind::Atomic {
+                op,
+                scope,
+                semantics,
+            } => {
                 let wk = &spv::spec::Spec::get().well_known;
 
-                // FIXME(eddyb) should this be rendered more compactly?
+                let unequal_semantics = match op {
+                    AtomicOp::CompareExchange {
+                        unequal_semantics, ..
+                    } => Some(unequal_semantics),
+                    _ => None,
+                };
                 pretty::Fragment::new([
-                    "(".into(),
-                    printer.pretty_spv_opcode(printer.spv_op_style(), wk.OpExtInstImport),
-                    "<".into(),
                     printer
-                        .string_literal_style()
-                        .apply(format!("{:?}", &printer.cx[ext_set]))
+                        .demote_style_for_namespace_prefix(printer.declarative_keyword_style())
+                        .apply("atomic.")
                         .into(),
-                    ">).".into(),
-                    printer.pretty_spv_opcode(printer.spv_op_style(), wk.OpExtInst),
-                    "<".into(),
+                    printer.declarative_keyword_style().apply(op.name()).into(),
+                    pretty::join_comma_sep(
+                        "<",
+                        [
+                            printer.pretty_spv_imm(wk.Scope, scope),
+                            printer.pretty_spv_imm(wk.MemorySemantics, semantics),
+                        ]
+                        .into_iter()
+                        .chain(
+                            unequal_semantics
+                                .map(|x| printer.pretty_spv_imm(wk.MemorySemantics, x)),
+                        ),
+                        ">",
+                    ),
+                ])
+            }
+            DataInstKind::Barrier {
+                execution_scope,
+                memory_scope,
+                semantics,
+            } => {
+                let wk = &spv::spec::Spec::get().well_known;
+
+                pretty::Fragment::new([
                     printer
-                        .numeric_literal_style()
-                        .apply(format!("{inst}"))
+                        .demote_style_for_namespace_prefix(printer.declarative_keyword_style())
+                        .apply("barrier.")
                         .into(),
-                    ">".into(),
+                    printer
+                        .declarative_keyword_style()
+                        .apply(if execution_scope.is_some() {
+                            "control"
+                        } else {
+                            "memory"
+                        })
+                        .into(),
+                    pretty::join_comma_sep(
+                        "<",
+                        execution_scope
+                            .into_iter()
+                            .chain([memory_scope])
+                            .map(|scope| printer.pretty_spv_imm(wk.Scope, scope))
+                            .chain([printer.pretty_spv_imm(wk.MemorySemantics, semantics)]),
+                        ">",
+                    ),
+                ])
+            }
+            DataInstKind::Group {
+                op,
+                scope,
+                group_operation,
+            } => {
+                let wk = &spv::spec::Spec::get().well_known;
+
+                pretty::Fragment::new([
+                    printer
+                        .demote_style_for_namespace_prefix(printer.declarative_keyword_style())
+                        .apply("group.")
+                        .into(),
+                    printer.declarative_keyword_style().apply(op.name()).into(),
+                    pretty::join_comma_sep(
+                        "<",
+                        [printer.pretty_spv_imm(wk.Scope, scope)].into_iter().chain(
+                            group_operation.map(|x| printer.pretty_spv_imm(wk.GroupOperation, x)),
+                        ),
+                        ">",
+                    ),
                 ])
             }
+            DataInstKind::QPtr(op) => pretty::Fragment::new([
+                printer
+                    .demote_style_for_namespace_prefix(printer.declarative_keyword_style())
+                    .apply("qptr.")
+                    .into(),
+                printer.declarative_keyword_style().apply(op.name()).into(),
+                match op {
+                    QPtrOp::Offset { pointee } => {
+                        pretty::Fragment::new(["<".into(), pointee.print(printer), ">".into()])
+                    }
+                    QPtrOp::Load | QPtrOp::Store => pretty::Fragment::default(),
+                },
+            ]),
         };
 
         // FIXME(eddyb) deduplicate the "parens + optional type ascription"
@@ -2630,13 +3520,10 @@ impl Print for cfg::ControlInst {
                     _ => unreachable!(),
                 }
             }
-            cfg::ControlInstKind::ExitInvocation(cfg::ExitInvocationKind::SpvInst(spv::Inst {
-                opcode,
-                imms,
-            })) => {
+            cfg::ControlInstKind::ExitInvocation(kind) => {
                 // FIXME(eddyb) use `targets.is_empty()` when that is stabilized.
                 assert!(targets.len() == 0);
-                printer.pretty_spv_inst(kw_style, *opcode, imms, inputs, Print::print, None)
+                kind.print_with_inputs(printer, kw_style, inputs)
             }
 
             cfg::ControlInstKind::Branch => {
@@ -2654,6 +3541,29 @@ impl Print for cfg::ControlInst {
     }
 }
 
+impl cfg::ExitInvocationKind {
+    fn print_with_inputs(
+        &self,
+        printer: &Printer<'_>,
+        kw_style: pretty::Styles,
+        inputs: &[Value],
+    ) -> pretty::Fragment {
+        match self {
+            cfg::ExitInvocationKind::SpvInst(spv::Inst { opcode, imms }) => {
+                printer.pretty_spv_inst(kw_style, *opcode, imms, inputs, Print::print, None)
+            }
+            cfg::ExitInvocationKind::EmitMeshTasks => pretty::Fragment::new([
+                printer
+                    .demote_style_for_namespace_prefix(kw_style.clone())
+                    .apply("mesh.")
+                    .into(),
+                kw_style.apply("emit_tasks").into(),
+                pretty::join_comma_sep("(", inputs.iter().map(|v| v.print(printer)), ")"),
+            ]),
+        }
+    }
+}
+
 impl SelectionKind {
     fn print_with_scrutinee_and_cases(
         &self,
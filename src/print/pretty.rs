@@ -1,6 +1,7 @@
 //! Pretty-printing functionality (such as automatic indentation).
 
 use indexmap::IndexSet;
+use rustc_hash::{FxHashMap, FxHashSet};
 use smallvec::SmallVec;
 use std::borrow::Cow;
 use std::fmt::Write as _;
@@ -54,6 +55,12 @@ pub struct Styles {
     pub anchor: Option<String>,
     pub anchor_is_def: bool,
 
+    /// Extra information about the styled text, only shown on demand.
+    ///
+    /// For HTML output, this becomes the `title` attribute (i.e. a tooltip
+    /// shown on hover), while plain text output ignores it entirely.
+    pub tooltip: Option<String>,
+
     /// RGB color.
     pub color: Option<[u8; 3]>,
 
@@ -279,10 +286,51 @@ impl FragmentPostLayout {
     SCOPE a:not(:hover) {
         text-decoration: unset;
     }
+    SCOPE a:target {
+        outline: solid 1px;
+    }
 </style>
 "
         .replace("SCOPE", &format!("pre.{ROOT_CLASS_NAME}"));
 
+        // NOTE(eddyb) every use of an anchor gets its own `id`, of the form
+        // `{anchor}.use{n}`, which the definition anchor links to (starting
+        // with the first use, and then cycling through the rest, on each
+        // click, if JavaScript is available, otherwise only the first use),
+        // as long as there are any uses at all (see `used_anchors` below).
+        let find_all_uses_script = r##"
+<script>
+    document.addEventListener("click", function(e) {
+        var def = e.target.closest && e.target.closest("pre.SCOPE a[data-uses-of]");
+        if(!def) return;
+        var prefix = def.getAttribute("data-uses-of") + ".use";
+        var uses = document.querySelectorAll("pre.SCOPE a[id^='" + CSS.escape(prefix) + "']");
+        if(uses.length === 0) return;
+        var next = (parseInt(def.getAttribute("data-next-use")) || 0) % uses.length;
+        def.setAttribute("data-next-use", next + 1);
+        e.preventDefault();
+        document.location.hash = "#" + uses[next].id;
+    });
+</script>
+"##
+        .replace("SCOPE", ROOT_CLASS_NAME);
+        // HACK(eddyb) uses can come after their definition, so whether there
+        // are any uses to link to has to be determined ahead of time.
+        let mut used_anchors = FxHashSet::<&str>::default();
+        self.0.render_to_line_ops(
+            &mut |op| {
+                if let LineOp::PushStyles(styles) = op {
+                    if let (Some(id), false) = (&styles.anchor, styles.anchor_is_def) {
+                        used_anchors.insert(id);
+                    }
+                }
+            },
+            false,
+        );
+
+        let mut defined_anchors = FxHashSet::<&str>::default();
+        let mut anchor_use_counts = FxHashMap::<&str, usize>::default();
+
         let mut body = format!("<pre class=\"{ROOT_CLASS_NAME}\">");
         self.0.render_to_line_ops(
             &mut LineOp::interpret_with(|op| match op {
@@ -309,15 +357,23 @@ impl FragmentPostLayout {
 
                     if let TextOp::PushStyles(_) = op {
                         let mut push_attr = |attr, value: &str| {
-                            // Quick sanity check.
-                            assert!(value.chars().all(|c| !(c == '"' || c == '&')));
-
-                            body.extend([" ", attr, "=\"", value, "\""]);
+                            // Minimal escaping, just enough to produce valid HTML.
+                            body.extend([" ", attr, "=\""]);
+                            for c in value.chars() {
+                                match c {
+                                    '&' => body += "&amp;",
+                                    '"' => body += "&quot;",
+                                    '<' => body += "&lt;",
+                                    _ => body.push(c),
+                                }
+                            }
+                            body += "\"";
                         };
 
                         let Styles {
                             ref anchor,
                             anchor_is_def,
+                            ref tooltip,
                             color,
                             color_opacity,
                             thickness,
@@ -328,9 +384,25 @@ impl FragmentPostLayout {
 
                         if let Some(id) = anchor {
                             if anchor_is_def {
-                                push_attr("id", id);
+                                // HACK(eddyb) see `AttrsAndDef::insert_name_before_def`.
+                                if defined_anchors.insert(id) {
+                                    push_attr("id", id);
+                                }
+                                if used_anchors.contains(&id[..]) {
+                                    push_attr("href", &format!("#{id}.use0"));
+                                    push_attr("data-uses-of", id);
+                                }
+                            } else {
+                                let use_count = anchor_use_counts.entry(id).or_default();
+                                push_attr("id", &format!("{id}.use{use_count}"));
+                                *use_count += 1;
+
+                                push_attr("href", &format!("#{id}"));
                             }
-                            push_attr("href", &format!("#{id}"));
+                        }
+
+                        if let Some(tooltip) = tooltip {
+                            push_attr("title", tooltip);
                         }
 
                         let mut css_style = String::new();
@@ -379,7 +451,7 @@ impl FragmentPostLayout {
         body += "</pre>";
 
         HtmlSnippet {
-            head_deduplicatable_elements: [style_elem].into_iter().collect(),
+            head_deduplicatable_elements: [style_elem, find_all_uses_script].into_iter().collect(),
             body,
         }
     }
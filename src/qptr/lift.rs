@@ -0,0 +1,426 @@
+//! Lifting `QPtr`s back to SPIR-V pointers (see [`lift_to_spv_ptrs`]).
+
+use super::{FuncBodyDefs, QPtrAttr, QPtrOp};
+use crate::passes::legalize::reachable_funcs;
+use crate::spv::fold::{const_splat_value, splat_const, ScalarValue};
+use crate::spv;
+use crate::transform::{InnerInPlaceTransform, ReplaceValueWith};
+use crate::{
+    Attr, AttrSet, AttrSetDef, Const, ConstDef, Context, ControlNode, ControlNodeKind, DataInst,
+    DataInstDef, DataInstKind, DeclDef, EntityList, Func, FuncDefBody, Module, Type, TypeCtor,
+    TypeCtorArg, TypeDef, Value,
+};
+use rustc_hash::FxHashMap;
+use smallvec::SmallVec;
+use std::fmt;
+
+/// Error returned by [`lift_to_spv_ptrs`], when some `QPtr` can't be turned
+/// back into a SPIR-V pointer (which may leave the module partially lifted).
+#[derive(Debug)]
+pub struct LiftError {
+    pub message: String,
+}
+
+impl fmt::Display for LiftError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "failed to lift `qptr`s to SPIR-V pointers: {}",
+            self.message
+        )
+    }
+}
+
+impl std::error::Error for LiftError {}
+
+fn err<T>(message: impl Into<String>) -> Result<T, LiftError> {
+    Err(LiftError {
+        message: message.into(),
+    })
+}
+
+/// Lift all `QPtr`s in all functions in `module` back to SPIR-V pointers, i.e.
+/// undo [`lower_from_spv_ptrs`](super::lower::lower_from_spv_ptrs), by:
+/// * restoring the SPIR-V pointer types of all `QPtr` values, from the
+///   [`QPtrAttr::FromSpvPtr`] attributes on their definitions
+/// * replacing [`QPtrOp::Offset`]s with `OpAccessChain` (with the result type
+///   computed from the indices), and [`QPtrOp::Load`]/[`QPtrOp::Store`] with
+///   `OpLoad`/`OpStore`
+/// * adjusting pointers used with a different pointee type than they were
+///   defined with (including inputs with [`QPtrAttr::ToSpvPtrInput`]s), by
+///   adding an `OpAccessChain` into the leading component (of some aggregate)
+///   which has the expected type (e.g. the first member of a struct)
+///
+/// Any [`QPtrAttr`]s used are removed, while any `QPtr` value without its
+/// original SPIR-V pointer type (or mismatched pointer use which can't be
+/// adjusted) results in a [`LiftError`].
+pub fn lift_to_spv_ptrs(module: &mut Module) -> Result<(), LiftError> {
+    let cx = &module.cx();
+    let wk = &spv::spec::Spec::get().well_known;
+
+    let lifter = Lifter {
+        cx,
+        u32_type: cx.intern(TypeDef {
+            attrs: AttrSet::default(),
+            ctor: TypeCtor::SpvInst(spv::Inst {
+                opcode: wk.OpTypeInt,
+                imms: [
+                    spv::Imm::Short(wk.LiteralInteger, 32),
+                    spv::Imm::Short(wk.LiteralInteger, 0),
+                ]
+                .into_iter()
+                .collect(),
+            }),
+            ctor_args: [].into_iter().collect(),
+        }),
+    };
+
+    // NOTE(eddyb) all function signatures are lifted first, so that calls
+    // can use the (SPIR-V pointer) types of their callee's parameters.
+    let funcs = reachable_funcs(module);
+    let mut param_types = FxHashMap::<Func, SmallVec<[Type; 2]>>::default();
+    for &func in &funcs {
+        let func_decl = &mut module.funcs[func];
+        for param in &mut func_decl.params {
+            lifter.lift_decl_type(&mut param.attrs, &mut param.ty)?;
+        }
+        lifter.lift_decl_type(&mut func_decl.attrs, &mut func_decl.ret_type)?;
+        param_types.insert(
+            func,
+            func_decl.params.iter().map(|param| param.ty).collect(),
+        );
+    }
+    for &func in &funcs {
+        if let DeclDef::Present(func_def_body) = &mut module.funcs[func].def {
+            lifter.lift_func_body(func_def_body, &param_types)?;
+        }
+    }
+    Ok(())
+}
+
+struct Lifter<'a> {
+    cx: &'a Context,
+    u32_type: Type,
+}
+
+impl Lifter<'_> {
+    fn spv_ptr_type(&self, storage_class: u32, pointee: Type) -> Type {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        self.cx.intern(TypeDef {
+            attrs: AttrSet::default(),
+            ctor: TypeCtor::SpvInst(spv::Inst {
+                opcode: wk.OpTypePointer,
+                imms: [spv::Imm::Short(wk.StorageClass, storage_class)]
+                    .into_iter()
+                    .collect(),
+            }),
+            ctor_args: [TypeCtorArg::Type(pointee)].into_iter().collect(),
+        })
+    }
+
+    /// Get the storage class and pointee type of `ty`, if it's a SPIR-V pointer.
+    fn as_spv_ptr(&self, ty: Type) -> Option<(u32, Type)> {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        let ty_def = &self.cx[ty];
+        match (&ty_def.ctor, &ty_def.ctor_args[..]) {
+            (TypeCtor::SpvInst(inst), &[TypeCtorArg::Type(pointee)])
+                if inst.opcode == wk.OpTypePointer =>
+            {
+                match inst.imms[..] {
+                    [spv::Imm::Short(_, storage_class)] => Some((storage_class, pointee)),
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    fn is_qptr(&self, ty: Type) -> bool {
+        matches!(self.cx[ty].ctor, TypeCtor::QPtr)
+    }
+
+    /// Remove all [`QPtrAttr`]s matching `f` from `attrs`, returning them.
+    fn take_attrs(
+        &self,
+        attrs: &mut AttrSet,
+        f: impl Fn(&QPtrAttr) -> bool,
+    ) -> SmallVec<[QPtrAttr; 1]> {
+        let mut taken = SmallVec::new();
+        let mut remaining = self.cx[*attrs].attrs.clone();
+        remaining.retain(|attr| match attr {
+            Attr::QPtr(attr) if f(attr) => {
+                taken.push(attr.clone());
+                false
+            }
+            _ => true,
+        });
+        if !taken.is_empty() {
+            *attrs = self.cx.intern(AttrSetDef { attrs: remaining });
+        }
+        taken
+    }
+
+    /// Replace `ty` with the SPIR-V pointer type recorded in `attrs` (removing
+    /// that [`QPtrAttr::FromSpvPtr`] attribute), if it's a `QPtr`.
+    fn lift_decl_type(&self, attrs: &mut AttrSet, ty: &mut Type) -> Result<(), LiftError> {
+        if !self.is_qptr(*ty) {
+            return Ok(());
+        }
+        match self.take_attrs(attrs, |attr| matches!(attr, QPtrAttr::FromSpvPtr { .. }))[..] {
+            [
+                QPtrAttr::FromSpvPtr {
+                    storage_class,
+                    pointee,
+                },
+            ] => {
+                *ty = self.spv_ptr_type(storage_class, pointee.0);
+                Ok(())
+            }
+            [] => err("`qptr` value without its original SPIR-V pointer type"),
+            _ => err("`qptr` value with more than one original SPIR-V pointer type"),
+        }
+    }
+
+    fn lift_const(&self, ct: Const) -> Result<Option<Const>, LiftError> {
+        let ct_def = &self.cx[ct];
+        let mut attrs = ct_def.attrs;
+        let mut ty = ct_def.ty;
+        self.lift_decl_type(&mut attrs, &mut ty)?;
+        if ty == ct_def.ty {
+            return Ok(None);
+        }
+        Ok(Some(self.cx.intern(ConstDef {
+            attrs,
+            ty,
+            ctor: ct_def.ctor.clone(),
+            ctor_args: ct_def.ctor_args.clone(),
+        })))
+    }
+
+    fn lift_func_body(
+        &self,
+        func_def_body: &mut FuncDefBody,
+        param_types: &FxHashMap<Func, SmallVec<[Type; 2]>>,
+    ) -> Result<(), LiftError> {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        let defs = FuncBodyDefs::collect(func_def_body);
+
+        for &region in &defs.regions {
+            for input_decl in &mut func_def_body.control_regions[region].inputs {
+                self.lift_decl_type(&mut input_decl.attrs, &mut input_decl.ty)?;
+            }
+        }
+        for &control_node in &defs.nodes {
+            for output_decl in &mut func_def_body.control_nodes[control_node].outputs {
+                self.lift_decl_type(&mut output_decl.attrs, &mut output_decl.ty)?;
+            }
+        }
+
+        let mut const_error = None;
+        func_def_body.inner_in_place_transform_with(&mut ReplaceValueWith(|v| match v {
+            Value::Const(ct) => match self.lift_const(ct) {
+                Ok(lifted) => lifted.map(Value::Const),
+                Err(e) => {
+                    const_error.get_or_insert(e);
+                    None
+                }
+            },
+            _ => None,
+        }));
+        if let Some(e) = const_error {
+            return Err(e);
+        }
+
+        // FIXME(eddyb) pointers flowing through region outputs, or inputs of
+        // control instructions and loops, are never adjusted (i.e. mismatches
+        // between the types of their definitions and their uses are not legal).
+        for &(block, inst) in &defs.insts {
+            let mut inst_def = func_def_body.data_insts[inst].clone();
+            match inst_def.kind {
+                DataInstKind::QPtr(QPtrOp::Offset { pointee }) => {
+                    let (base, storage_class) =
+                        self.adjust_ptr(func_def_body, block, inst, inst_def.inputs[0], pointee)?;
+                    let mut result_pointee = pointee;
+                    for &idx in &inst_def.inputs[1..] {
+                        result_pointee = self.component_type(result_pointee, Some(idx))?;
+                    }
+                    inst_def.kind = DataInstKind::SpvInst(wk.OpAccessChain.into());
+                    inst_def.output_type = Some(self.spv_ptr_type(storage_class, result_pointee));
+                    inst_def.inputs[0] = base;
+                }
+                DataInstKind::QPtr(QPtrOp::Load) => {
+                    let mut ty = inst_def.output_type.unwrap();
+                    self.lift_decl_type(&mut inst_def.attrs, &mut ty)?;
+                    inst_def.output_type = Some(ty);
+                    inst_def.inputs[0] = self
+                        .adjust_ptr(func_def_body, block, inst, inst_def.inputs[0], ty)?
+                        .0;
+                    inst_def.kind = DataInstKind::SpvInst(wk.OpLoad.into());
+                }
+                DataInstKind::QPtr(QPtrOp::Store) => {
+                    let ty = func_def_body.at(inst_def.inputs[1]).type_of(self.cx);
+                    inst_def.inputs[0] = self
+                        .adjust_ptr(func_def_body, block, inst, inst_def.inputs[0], ty)?
+                        .0;
+                    inst_def.kind = DataInstKind::SpvInst(wk.OpStore.into());
+                }
+                _ => {
+                    let expected_inputs: SmallVec<[_; 2]> = match inst_def.kind {
+                        DataInstKind::FuncCall(callee) => param_types[&callee]
+                            .iter()
+                            .enumerate()
+                            .filter_map(|(i, &ty)| Some((i, self.as_spv_ptr(ty)?.1)))
+                            .collect(),
+                        _ => self
+                            .take_attrs(&mut inst_def.attrs, |attr| {
+                                matches!(attr, QPtrAttr::ToSpvPtrInput { .. })
+                            })
+                            .into_iter()
+                            .map(|attr| match attr {
+                                QPtrAttr::ToSpvPtrInput { input_idx, pointee } => {
+                                    (input_idx as usize, pointee.0)
+                                }
+                                QPtrAttr::FromSpvPtr { .. } => unreachable!(),
+                            })
+                            .collect(),
+                    };
+                    for (input_idx, pointee) in expected_inputs {
+                        let Some(&input) = inst_def.inputs.get(input_idx) else {
+                            return err(format!(
+                                "`qptr.to_spv_ptr_input` index {input_idx} out of range"
+                            ));
+                        };
+                        inst_def.inputs[input_idx] = self
+                            .adjust_ptr(func_def_body, block, inst, input, pointee)?
+                            .0;
+                    }
+
+                    if let Some(mut ty) = inst_def.output_type {
+                        self.lift_decl_type(&mut inst_def.attrs, &mut ty)?;
+                        inst_def.output_type = Some(ty);
+                    }
+                }
+            }
+            func_def_body.data_insts[inst] = inst_def;
+        }
+
+        Ok(())
+    }
+
+    /// Get the type of the component of a `ty` value, selected by `idx`
+    /// (which is only needed for structs, and `None` means the first member).
+    fn component_type(&self, ty: Type, idx: Option<Value>) -> Result<Type, LiftError> {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        let ty_def = &self.cx[ty];
+        let component_idx = match &ty_def.ctor {
+            TypeCtor::Struct { members } => {
+                let member_idx = match idx {
+                    None => 0,
+                    Some(Value::Const(ct)) => match const_splat_value(self.cx, ct) {
+                        Some(ScalarValue::Int { bits, .. }) => bits,
+                        _ => return err("struct member index must be an integer constant"),
+                    },
+                    Some(_) => return err("struct member index must be a constant"),
+                };
+                usize::try_from(member_idx)
+                    .ok()
+                    .filter(|&i| i < members.len())
+                    .ok_or_else(|| LiftError {
+                        message: format!("struct member index {member_idx} out of range"),
+                    })?
+            }
+            TypeCtor::Matrix { .. } | TypeCtor::Array | TypeCtor::RuntimeArray => 0,
+            TypeCtor::SpvInst(inst) if inst.opcode == wk.OpTypeVector => 0,
+            _ => return err("cannot access components of a non-aggregate type"),
+        };
+        match ty_def.ctor_args[component_idx] {
+            TypeCtorArg::Type(component_type) => Ok(component_type),
+            TypeCtorArg::Const(_) => unreachable!(),
+        }
+    }
+
+    /// Adjust the SPIR-V pointer `ptr`, used by `inst` (found in `block`), to
+    /// point to `expected_pointee`, by accessing (nested) leading components,
+    /// returning the adjusted pointer (and its storage class).
+    fn adjust_ptr(
+        &self,
+        func_def_body: &mut FuncDefBody,
+        block: ControlNode,
+        inst: DataInst,
+        ptr: Value,
+        expected_pointee: Type,
+    ) -> Result<(Value, u32), LiftError> {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        let (storage_class, mut pointee) = self
+            .as_spv_ptr(func_def_body.at(ptr).type_of(self.cx))
+            .ok_or_else(|| LiftError {
+                message: "expected a SPIR-V pointer".into(),
+            })?;
+        if pointee == expected_pointee {
+            return Ok((ptr, storage_class));
+        }
+
+        let zero = Value::Const(
+            splat_const(
+                self.cx,
+                self.u32_type,
+                ScalarValue::Int { width: 32, bits: 0 },
+            )
+            .unwrap(),
+        );
+        let mut inputs: SmallVec<[_; 2]> = [ptr].into_iter().collect();
+        while pointee != expected_pointee {
+            let first_member_offset = match &self.cx[pointee].ctor {
+                TypeCtor::Struct { members } => members.first().and_then(|m| m.offset),
+                _ => None,
+            };
+            if first_member_offset.is_some_and(|offset| offset != 0) {
+                return err("cannot adjust pointer into struct with non-zero first member offset");
+            }
+            pointee = self.component_type(pointee, None)?;
+            inputs.push(zero);
+        }
+
+        let adjusted = func_def_body.data_insts.define(
+            self.cx,
+            DataInstDef {
+                attrs: AttrSet::default(),
+                kind: DataInstKind::SpvInst(wk.OpAccessChain.into()),
+                output_type: Some(self.spv_ptr_type(storage_class, expected_pointee)),
+                inputs,
+            }
+            .into(),
+        );
+        insert_before(func_def_body, block, inst, adjusted);
+        Ok((Value::DataInstOutput(adjusted), storage_class))
+    }
+}
+
+fn insert_before(
+    func_def_body: &mut FuncDefBody,
+    block: ControlNode,
+    next_inst: DataInst,
+    new_inst: DataInst,
+) {
+    let data_insts = &mut func_def_body.data_insts;
+    let insts = match &mut func_def_body.control_nodes[block].kind {
+        ControlNodeKind::Block { insts } => insts,
+        _ => unreachable!(),
+    };
+
+    // FIXME(eddyb) `EntityList` should have a way to insert in the middle.
+    let mut following_insts = EntityList::empty();
+    while let Some(next) = data_insts[next_inst].next_in_list() {
+        insts.remove(next, data_insts);
+        following_insts.insert_last(next, data_insts);
+    }
+    insts.remove(next_inst, data_insts);
+    insts.insert_last(new_inst, data_insts);
+    insts.insert_last(next_inst, data_insts);
+    insts.append(following_insts, data_insts);
+}
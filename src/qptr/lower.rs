@@ -0,0 +1,294 @@
+//! Lowering SPIR-V pointers to `QPtr`s (see [`lower_from_spv_ptrs`]).
+
+use super::{FuncBodyDefs, QPtrAttr, QPtrOp};
+use crate::passes::legalize::reachable_funcs;
+use crate::spv;
+use crate::transform::{InnerInPlaceTransform, ReplaceValueWith};
+use crate::{
+    Attr, AttrSet, AttrSetDef, Const, ConstDef, Context, ControlNodeKind, DataInst, DataInstKind,
+    DeclDef, FuncDecl, FuncDefBody, Module, OrdAssertEq, Type, TypeCtor, TypeCtorArg, TypeDef,
+    Value,
+};
+use rustc_hash::FxHashMap;
+use smallvec::SmallVec;
+
+/// Lower (most) SPIR-V pointers in all functions in `module` to `QPtr`s, i.e.:
+/// * SPIR-V pointer types (`OpTypePointer`) of values (function parameters and
+///   return types, and all values in function bodies) are replaced with
+///   [`TypeCtor::QPtr`], with the original type recorded in a
+///   [`QPtrAttr::FromSpvPtr`] attribute (on the definition of each value)
+/// * `OpLoad`/`OpStore` (without any `MemoryAccess` operands) become
+///   [`QPtrOp::Load`]/[`QPtrOp::Store`], and `Op{,InBounds}AccessChain` become
+///   [`QPtrOp::Offset`] (with chains of them merged, wherever possible)
+/// * pointer casts (`OpBitcast`/`OpCopyObject` between pointer types) are
+///   removed, with all uses of their output using their input instead
+/// * any other instruction taking SPIR-V pointers as inputs gets a
+///   [`QPtrAttr::ToSpvPtrInput`] attribute for each of them
+///
+/// Pointers in the `PhysicalStorageBuffer` storage class, and pointer types
+/// with any attributes (e.g. `ArrayStride` decorations), are left unchanged.
+///
+/// See also [`lift_to_spv_ptrs`](super::lift::lift_to_spv_ptrs), which undoes
+/// this lowering (though not necessarily producing the original SPIR-V).
+pub fn lower_from_spv_ptrs(module: &mut Module) {
+    let cx = &module.cx();
+
+    let lowerer = Lowerer {
+        cx,
+        qptr_type: cx.intern(TypeDef {
+            attrs: AttrSet::default(),
+            ctor: TypeCtor::QPtr,
+            ctor_args: [].into_iter().collect(),
+        }),
+    };
+    for func in reachable_funcs(module) {
+        lowerer.lower_func_decl(&mut module.funcs[func]);
+    }
+}
+
+struct Lowerer<'a> {
+    cx: &'a Context,
+    qptr_type: Type,
+}
+
+impl Lowerer<'_> {
+    /// Get the storage class and pointee type of `ty`, if it's a SPIR-V pointer
+    /// type which should be lowered to `QPtr` (see [`lower_from_spv_ptrs`]).
+    fn as_lowerable_spv_ptr(&self, ty: Type) -> Option<(u32, Type)> {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        let ty_def = &self.cx[ty];
+        match (&ty_def.ctor, &ty_def.ctor_args[..]) {
+            (TypeCtor::SpvInst(inst), &[TypeCtorArg::Type(pointee)])
+                if inst.opcode == wk.OpTypePointer && ty_def.attrs == AttrSet::default() =>
+            {
+                match inst.imms[..] {
+                    [spv::Imm::Short(_, storage_class)]
+                        if storage_class != wk.PhysicalStorageBuffer =>
+                    {
+                        Some((storage_class, pointee))
+                    }
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    fn with_attr(&self, attrs: AttrSet, attr: QPtrAttr) -> AttrSet {
+        let mut attrs = self.cx[attrs].attrs.clone();
+        attrs.insert(Attr::QPtr(attr));
+        self.cx.intern(AttrSetDef { attrs })
+    }
+
+    /// Replace `ty` with `QPtr` (recording the original type in `attrs`), if
+    /// it's a SPIR-V pointer type which should be lowered.
+    fn lower_decl_type(&self, attrs: &mut AttrSet, ty: &mut Type) {
+        if let Some((storage_class, pointee)) = self.as_lowerable_spv_ptr(*ty) {
+            *attrs = self.with_attr(
+                *attrs,
+                QPtrAttr::FromSpvPtr {
+                    storage_class,
+                    pointee: OrdAssertEq(pointee),
+                },
+            );
+            *ty = self.qptr_type;
+        }
+    }
+
+    fn lower_const(&self, ct: Const) -> Option<Const> {
+        let ct_def = &self.cx[ct];
+        let mut attrs = ct_def.attrs;
+        let mut ty = ct_def.ty;
+        self.lower_decl_type(&mut attrs, &mut ty);
+        if ty == ct_def.ty {
+            return None;
+        }
+        Some(self.cx.intern(ConstDef {
+            attrs,
+            ty,
+            ctor: ct_def.ctor.clone(),
+            ctor_args: ct_def.ctor_args.clone(),
+        }))
+    }
+
+    fn lower_func_decl(&self, func_decl: &mut FuncDecl) {
+        for param in &mut func_decl.params {
+            self.lower_decl_type(&mut param.attrs, &mut param.ty);
+        }
+        self.lower_decl_type(&mut func_decl.attrs, &mut func_decl.ret_type);
+
+        if let DeclDef::Present(func_def_body) = &mut func_decl.def {
+            self.lower_func_body(func_def_body);
+        }
+    }
+
+    fn lower_func_body(&self, func_def_body: &mut FuncDefBody) {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        let defs = FuncBodyDefs::collect(func_def_body);
+
+        // NOTE(eddyb) all the changes to instructions are first computed based
+        // on the original types, and only applied afterwards.
+        let mut cast_replacements = FxHashMap::<Value, Value>::default();
+        let mut removed_casts = vec![];
+        let mut offsets = FxHashMap::<DataInst, Offset>::default();
+        let mut lowered_insts = vec![];
+        for &(block, inst) in &defs.insts {
+            let resolve = |mut v| {
+                while let Some(&new) = cast_replacements.get(&v) {
+                    v = new;
+                }
+                v
+            };
+
+            let func_at_inst = func_def_body.at(inst);
+            let inst_def = func_at_inst.def();
+            let spv_ptr_input = |input_idx: usize| {
+                inst_def
+                    .inputs
+                    .get(input_idx)
+                    .and_then(|&v| self.as_lowerable_spv_ptr(func_at_inst.at(v).type_of(self.cx)))
+            };
+            let spv_ptr_output = inst_def
+                .output_type
+                .and_then(|ty| self.as_lowerable_spv_ptr(ty));
+            let spv_opcode = match &inst_def.kind {
+                DataInstKind::SpvInst(spv_inst) if spv_inst.imms.is_empty() => {
+                    Some(spv_inst.opcode)
+                }
+                _ => None,
+            };
+
+            if spv_ptr_output.is_some()
+                && inst_def.inputs.len() == 1
+                && spv_ptr_input(0).is_some()
+                && spv_opcode.is_some_and(|op| [wk.OpBitcast, wk.OpCopyObject].contains(&op))
+            {
+                cast_replacements.insert(Value::DataInstOutput(inst), resolve(inst_def.inputs[0]));
+                removed_casts.push((block, inst));
+                continue;
+            }
+
+            let mut attrs = inst_def.attrs;
+            let mut inputs: SmallVec<[_; 2]> =
+                inst_def.inputs.iter().map(|&v| resolve(v)).collect();
+            let kind = match (spv_opcode, spv_ptr_input(0)) {
+                (Some(op), Some(_)) if op == wk.OpLoad && inputs.len() == 1 => {
+                    DataInstKind::QPtr(QPtrOp::Load)
+                }
+                (Some(op), Some(_)) if op == wk.OpStore && inputs.len() == 2 => {
+                    DataInstKind::QPtr(QPtrOp::Store)
+                }
+                (Some(op), Some((_, base_pointee)))
+                    if [wk.OpAccessChain, wk.OpInBoundsAccessChain].contains(&op)
+                        && spv_ptr_output.is_some() =>
+                {
+                    let mut offset = Offset {
+                        pointee: base_pointee,
+                        inputs: inputs.iter().copied().collect(),
+                        result_pointee: spv_ptr_output.unwrap().1,
+                    };
+
+                    // Merge with the offset producing the base pointer, unless
+                    // there was a cast in between (changing the pointee type).
+                    if let Value::DataInstOutput(base_inst) = inputs[0] {
+                        if let Some(base_offset) = offsets.get(&base_inst) {
+                            if base_offset.result_pointee == base_pointee {
+                                offset.pointee = base_offset.pointee;
+                                offset.inputs = base_offset
+                                    .inputs
+                                    .iter()
+                                    .chain(&inputs[1..])
+                                    .copied()
+                                    .collect();
+                            }
+                        }
+                    }
+
+                    let pointee = offset.pointee;
+                    inputs = offset.inputs.iter().copied().collect();
+                    offsets.insert(inst, offset);
+                    DataInstKind::QPtr(QPtrOp::Offset { pointee })
+                }
+                _ => {
+                    // NOTE(eddyb) calls don't need this, as the types of the
+                    // callee's parameters are already recorded on them.
+                    if !matches!(inst_def.kind, DataInstKind::FuncCall(_)) {
+                        for input_idx in 0..inputs.len() {
+                            if let Some((_, pointee)) = spv_ptr_input(input_idx) {
+                                attrs = self.with_attr(
+                                    attrs,
+                                    QPtrAttr::ToSpvPtrInput {
+                                        input_idx: input_idx.try_into().unwrap(),
+                                        pointee: OrdAssertEq(pointee),
+                                    },
+                                );
+                            }
+                        }
+                    }
+                    inst_def.kind.clone()
+                }
+            };
+
+            // NOTE(eddyb) the result types of offsets are always recomputed
+            // when lifting, so there's no need to record them.
+            let mut output_type = inst_def.output_type;
+            if let Some(ty) = &mut output_type {
+                if let DataInstKind::QPtr(QPtrOp::Offset { .. }) = kind {
+                    *ty = self.qptr_type;
+                } else {
+                    self.lower_decl_type(&mut attrs, ty);
+                }
+            }
+
+            lowered_insts.push((inst, attrs, kind, output_type, inputs));
+        }
+
+        for (inst, attrs, kind, output_type, inputs) in lowered_insts {
+            let inst_def = &mut func_def_body.data_insts[inst];
+            inst_def.attrs = attrs;
+            inst_def.kind = kind;
+            inst_def.output_type = output_type;
+            inst_def.inputs = inputs;
+        }
+        for (block, inst) in removed_casts {
+            match &mut func_def_body.control_nodes[block].kind {
+                ControlNodeKind::Block { insts } => {
+                    insts.remove(inst, &mut func_def_body.data_insts);
+                }
+                _ => unreachable!(),
+            }
+        }
+        for &region in &defs.regions {
+            for input_decl in &mut func_def_body.control_regions[region].inputs {
+                self.lower_decl_type(&mut input_decl.attrs, &mut input_decl.ty);
+            }
+        }
+        for &control_node in &defs.nodes {
+            for output_decl in &mut func_def_body.control_nodes[control_node].outputs {
+                self.lower_decl_type(&mut output_decl.attrs, &mut output_decl.ty);
+            }
+        }
+
+        func_def_body.inner_in_place_transform_with(&mut ReplaceValueWith(|v| {
+            let mut resolved = v;
+            while let Some(&new) = cast_replacements.get(&resolved) {
+                resolved = new;
+            }
+            let lowered = match resolved {
+                Value::Const(ct) => self.lower_const(ct).map(Value::Const),
+                _ => None,
+            };
+            lowered.or((resolved != v).then_some(resolved))
+        }));
+    }
+}
+
+/// A [`QPtrOp::Offset`] (and its inputs), alongside the (original) pointee type
+/// of its output, which is needed to merge it into any offsets using it.
+struct Offset {
+    pointee: Type,
+    inputs: SmallVec<[Value; 4]>,
+    result_pointee: Type,
+}
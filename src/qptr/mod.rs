@@ -0,0 +1,134 @@
+//! [`QPtr`](crate::TypeCtor::QPtr)-related type definitions and passes.
+//!
+//! SPIR-V pointers are typed (i.e. `OpTypePointer` has a pointee type), which
+//! makes transformations that have to reinterpret memory (e.g. removing the
+//! pointer casts produced by Rust-GPU, or flattening chains of access chains)
+//! needlessly difficult, as every intermediate pointer needs a legal type.
+//!
+//! Instead, [`lower`] replaces (most) SPIR-V pointer types with the untyped
+//! [`TypeCtor::QPtr`](crate::TypeCtor::QPtr), and memory accesses/offsetting
+//! with [`QPtrOp`]s (which carry their own types), while [`lift`] turns them
+//! back into typed SPIR-V pointers (valid with logical addressing), adjusting
+//! pointers (by accessing leading components of aggregates) wherever the
+//! pointee types they were defined with differ from the ones they're used as.
+//
+// FIXME(eddyb) the name "qptr" is short for "quasi-pointer", to distinguish it
+// from SPIR-V pointers, and from the eventual (fully untyped) memory model.
+
+use crate::{
+    ControlNode, ControlNodeKind, ControlRegion, DataInst, FuncDefBody, OrdAssertEq, Type,
+};
+
+pub mod lift;
+pub mod lower;
+
+/// `QPtr`-specific attributes ([`Attr::QPtr`](crate::Attr::QPtr)), recording
+/// the original SPIR-V pointer types (erased by [`lower`]) for [`lift`] to use.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum QPtrAttr {
+    /// When applied to a definition of a `QPtr` value (i.e. a [`DataInst`]
+    /// output, [`ControlRegionInputDecl`], [`ControlNodeOutputDecl`],
+    /// [`FuncParam`] or [`Const`]), or to a [`FuncDecl`] (for its return type),
+    /// that value was originally a SPIR-V pointer (to `pointee`, in the SPIR-V
+    /// `storage_class`), and [`lift`] will turn it back into one.
+    ///
+    /// [`DataInst`]: crate::DataInst
+    /// [`ControlRegionInputDecl`]: crate::ControlRegionInputDecl
+    /// [`ControlNodeOutputDecl`]: crate::ControlNodeOutputDecl
+    /// [`FuncParam`]: crate::FuncParam
+    /// [`Const`]: crate::Const
+    /// [`FuncDecl`]: crate::FuncDecl
+    FromSpvPtr {
+        storage_class: u32,
+        pointee: OrdAssertEq<Type>,
+    },
+
+    /// When applied to a [`DataInst`](crate::DataInst) (not using [`QPtrOp`]),
+    /// its `input_idx`th input (a `QPtr` value) was originally a SPIR-V pointer
+    /// (to `pointee`), and [`lift`] will adjust it to have that type again.
+    ToSpvPtrInput {
+        input_idx: u32,
+        pointee: OrdAssertEq<Type>,
+    },
+}
+
+/// Operation on `QPtr`s ([`DataInstKind::QPtr`](crate::DataInstKind::QPtr)),
+/// always taking the `QPtr` as its first input.
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+pub enum QPtrOp {
+    /// Offset a `QPtr` (assumed to point to a value of the `pointee` type),
+    /// to point to the component of that value selected by the remaining inputs,
+    /// (i.e. integer indices, with struct member indices being constants).
+    ///
+    /// Unlike SPIR-V `OpAccessChain`, the `QPtr` input doesn't have to have been
+    /// defined as pointing to `pointee` (e.g. it could be a pointer to a struct,
+    /// with `pointee` being the type of its first member, see also [`lift`]).
+    Offset { pointee: Type },
+
+    /// Read a value (of the output type) from a `QPtr`.
+    Load,
+
+    /// Write a value (the second input) to a `QPtr`.
+    Store,
+}
+
+impl QPtrOp {
+    pub fn name(self) -> &'static str {
+        match self {
+            QPtrOp::Offset { .. } => "offset",
+            QPtrOp::Load => "load",
+            QPtrOp::Store => "store",
+        }
+    }
+}
+
+/// All the [`ControlRegion`]s, [`ControlNode`]s and [`DataInst`]s (the latter
+/// alongside the `Block` they're found in) of a function body, in an order in
+/// which definitions are found before their uses (other than region inputs,
+/// which may be e.g. loop-carried values, or phis in an unstructured CFG).
+struct FuncBodyDefs {
+    regions: Vec<ControlRegion>,
+    nodes: Vec<ControlNode>,
+    insts: Vec<(ControlNode, DataInst)>,
+}
+
+impl FuncBodyDefs {
+    fn collect(func_def_body: &FuncDefBody) -> Self {
+        let mut defs = FuncBodyDefs {
+            regions: vec![],
+            nodes: vec![],
+            insts: vec![],
+        };
+        defs.collect_in_region(func_def_body, func_def_body.body);
+        if let Some(cfg) = &func_def_body.unstructured_cfg {
+            for region in cfg.rev_post_order(func_def_body) {
+                if region != func_def_body.body {
+                    defs.collect_in_region(func_def_body, region);
+                }
+            }
+        }
+        defs
+    }
+
+    fn collect_in_region(&mut self, func_def_body: &FuncDefBody, region: ControlRegion) {
+        self.regions.push(region);
+        for func_at_control_node in func_def_body.at(region).at_children() {
+            let control_node = func_at_control_node.position;
+            self.nodes.push(control_node);
+            match &func_at_control_node.def().kind {
+                &ControlNodeKind::Block { insts } => {
+                    for func_at_inst in func_def_body.at(insts) {
+                        self.insts.push((control_node, func_at_inst.position));
+                    }
+                }
+                ControlNodeKind::Select { cases, .. } => {
+                    for &case in cases {
+                        self.collect_in_region(func_def_body, case);
+                    }
+                }
+                &ControlNodeKind::Loop { body, .. } => self.collect_in_region(func_def_body, body),
+                ControlNodeKind::ExitInvocation { .. } => {}
+            }
+        }
+    }
+}
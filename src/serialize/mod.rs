@@ -0,0 +1,1884 @@
+//! Serialization support for whole [`Module`]s, i.e. a compact binary format
+//! for caching (see [`Module::serialize_cache`]), and [`serde`] support
+//! (this whole module is only available with the `serde` feature enabled).
+//!
+//! All the handles used in SPIR-T (both [`Context`]-interned ones, like [`Type`],
+//! and entities, like [`Func`]) are only meaningful in the [`Context`] (and/or
+//! the [`Module`]) they were created in, so they can't be (de)serialized on
+//! their own - instead, a [`Module`] is serialized as a whole, with handles
+//! replaced by indices into tables of definitions (also part of the serialized
+//! form), which get re-interned (or, for entities, redefined) on deserialization.
+//!
+//! Deserializing a [`Module`] (e.g. through [`serde::Deserialize`]) will create
+//! a new [`Context`] for it, while `ModuleSeed` allows providing the [`Context`]
+//! (e.g. to combine the deserialized [`Module`] with other [`Module`]s).
+//!
+//! Like with printing, only definitions reachable from the [`Module`]'s exports
+//! are kept (i.e. anything unused is removed as part of serialization).
+
+use crate::func_at::FuncAt;
+use crate::qptr::{QPtrAttr, QPtrOp};
+use crate::spv::{self, spec};
+use crate::{
+    cfg, AddrSpace, AtomicOp, Attr, AttrSet, AttrSetDef, Const, ConstCtor, ConstDef, Context,
+    ControlNode, ControlNodeDef, ControlNodeKind, ControlNodeOutputDecl, ControlRegion,
+    ControlRegionDef, ControlRegionInputDecl, DataInst, DataInstDef, DataInstKind, DeclDef,
+    DiagnosticSeverity, EntityDefs, EntityList, ExportKey, Exportee, Func, FuncDecl, FuncDefBody,
+    FuncParam, FxIndexMap, FxIndexSet, GlobalVar, GlobalVarDecl, GlobalVarDefBody, GroupOp,
+    ImageType, Import, Module, ModuleDebugInfo, ModuleDialect, OrdAssertEq, SelectionKind,
+    StructMember, Type, TypeCtor, TypeCtorArg, TypeDef, Value,
+};
+use serde::{Deserialize, Serialize};
+use smallvec::SmallVec;
+use std::collections::{BTreeMap, BTreeSet};
+use std::num::NonZeroU32;
+use std::rc::Rc;
+
+mod cache;
+
+impl Serialize for Module {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        ModuleToSerialized::convert(self).serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Module {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::de::DeserializeSeed;
+
+        ModuleSeed(Rc::new(Context::new())).deserialize(deserializer)
+    }
+}
+
+/// [`DeserializeSeed`](serde::de::DeserializeSeed) for deserializing a [`Module`]
+/// in an existing [`Context`].
+pub struct ModuleSeed(pub Rc<Context>);
+
+impl<'de> serde::de::DeserializeSeed<'de> for ModuleSeed {
+    type Value = Module;
+
+    fn deserialize<D: serde::Deserializer<'de>>(self, deserializer: D) -> Result<Module, D::Error> {
+        use serde::de::Error as _;
+
+        let serialized = SerializedModule::deserialize(deserializer)?;
+        SerializedToModule::convert(self.0, serialized).map_err(D::Error::custom)
+    }
+}
+
+// Serialized forms of the IR, with all handles replaced by indices (`u32`),
+// into either `SerializedModule`'s tables, or `SerializedFuncDefBody`'s ones.
+
+#[derive(Serialize, Deserialize)]
+struct SerializedModule {
+    dialect: SerializedDialect,
+    debug_info: SerializedDebugInfo,
+
+    /// All the interned definitions, with each definition only referring to
+    /// earlier ones (i.e. in the order they need to be interned in).
+    interned: Vec<SerializedInterned>,
+
+    global_vars: Vec<SerializedGlobalVarDecl>,
+    funcs: Vec<SerializedFuncDecl>,
+
+    exports: Vec<(SerializedExportKey, SerializedExportee)>,
+}
+
+#[derive(Serialize, Deserialize)]
+enum SerializedDialect {
+    Spv {
+        version: (u8, u8),
+        capabilities: BTreeSet<u32>,
+        extensions: BTreeSet<String>,
+        addressing_model: u32,
+        memory_model: u32,
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+enum SerializedDebugInfo {
+    Spv {
+        original_generator_magic: Option<NonZeroU32>,
+        source_languages: Vec<SerializedDebugSources>,
+        source_extensions: Vec<String>,
+        module_processes: Vec<String>,
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+struct SerializedDebugSources {
+    lang: u32,
+    version: u32,
+    file_contents: Vec<(String, String)>,
+}
+
+#[derive(Serialize, Deserialize)]
+enum SerializedInterned {
+    AttrSet(Vec<SerializedAttr>),
+    Type {
+        attrs: u32,
+        ctor: SerializedTypeCtor,
+        ctor_args: Vec<SerializedTypeCtorArg>,
+    },
+    Const {
+        attrs: u32,
+        ty: u32,
+        ctor: SerializedConstCtor,
+        ctor_args: Vec<u32>,
+    },
+}
+
+// NOTE(eddyb) mirrors `Attr` (with `Attr::QPtr` flattened into it).
+#[derive(Serialize, Deserialize)]
+enum SerializedAttr {
+    SpvAnnotation(SerializedSpvInst),
+    SpvExecutionModeId {
+        inst: SerializedSpvInst,
+        consts: Vec<u32>,
+    },
+    SpvDebugLine {
+        file_path: String,
+        line: u32,
+        col: u32,
+    },
+    SpvBitflagsOperand(Vec<SerializedSpvImm>),
+    SpvOriginalId(NonZeroU32),
+    SpvUnsupported(String),
+    SpvShaderDebugScope {
+        scope: u32,
+        inlined_at: Option<u32>,
+    },
+    SpvShaderDebugLine {
+        source: u32,
+        line_start: u32,
+        line_end: u32,
+        col_start: u32,
+        col_end: u32,
+    },
+    QPtrFromSpvPtr {
+        storage_class: u32,
+        pointee: u32,
+    },
+    QPtrToSpvPtrInput {
+        input_idx: u32,
+        pointee: u32,
+    },
+    Diagnostic {
+        severity: String,
+        message: String,
+        related: Vec<String>,
+    },
+}
+
+/// [`spv::Inst`], with opcode and operand kinds referred to by their names
+/// (to avoid depending on the exact SPIR-V grammar in use).
+#[derive(Serialize, Deserialize)]
+struct SerializedSpvInst {
+    opcode: String,
+    imms: Vec<SerializedSpvImm>,
+}
+
+#[derive(Serialize, Deserialize)]
+enum SerializedSpvImm {
+    Short(String, u32),
+    LongStart(String, u32),
+    LongCont(String, u32),
+}
+
+#[derive(Serialize, Deserialize)]
+enum SerializedTypeCtor {
+    SpvInst(SerializedSpvInst),
+    SpvStringLiteralForExtInst,
+    Struct {
+        /// `(attrs, offset)` for each member.
+        members: Vec<(u32, Option<u32>)>,
+    },
+    Matrix {
+        column_count: u32,
+    },
+    Array,
+    RuntimeArray,
+    Image {
+        dim: u32,
+        depth: Option<bool>,
+        arrayed: bool,
+        multisampled: bool,
+        sampled: Option<bool>,
+        format: u32,
+        access_qualifier: Option<u32>,
+    },
+    RecursivePtr {
+        storage_class: u32,
+    },
+    RecursivePtrSelf {
+        depth: u32,
+    },
+    Sampler,
+    SampledImage,
+    QPtr,
+}
+
+#[derive(Serialize, Deserialize)]
+enum SerializedTypeCtorArg {
+    Type(u32),
+    Const(u32),
+}
+
+#[derive(Serialize, Deserialize)]
+enum SerializedConstCtor {
+    PtrToGlobalVar(u32),
+    SpvInst(SerializedSpvInst),
+    SpvStringLiteralForExtInst(String),
+    SpecConst {
+        spec_id: u32,
+        default: SerializedSpvInst,
+    },
+    SpecConstOp(SerializedSpvInst),
+    Undef,
+    SpvExtInst {
+        ext_set: String,
+        inst: u32,
+    },
+    SpvTypeOperand,
+}
+
+#[derive(Serialize, Deserialize)]
+enum SerializedDeclDef<T> {
+    Imported(SerializedImport),
+    Present(T),
+}
+
+#[derive(Serialize, Deserialize)]
+enum SerializedImport {
+    LinkName(String),
+}
+
+#[derive(Serialize, Deserialize)]
+struct SerializedGlobalVarDecl {
+    attrs: u32,
+    type_of_ptr_to: u32,
+    addr_space: SerializedAddrSpace,
+    def: SerializedDeclDef<Option<u32>>,
+}
+
+#[derive(Serialize, Deserialize)]
+enum SerializedAddrSpace {
+    SpvStorageClass(u32),
+    PhysicalStorageBuffer,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SerializedFuncDecl {
+    attrs: u32,
+    ret_type: u32,
+    params: Vec<(u32, u32)>,
+    def: SerializedDeclDef<SerializedFuncDefBody>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SerializedFuncDefBody {
+    control_regions: Vec<SerializedControlRegionDef>,
+    control_nodes: Vec<SerializedControlNodeDef>,
+    data_insts: Vec<SerializedDataInstDef>,
+    body: u32,
+    unstructured_cfg: Option<Vec<(u32, SerializedControlInst)>>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SerializedControlRegionDef {
+    inputs: Vec<(u32, u32)>,
+    children: Vec<u32>,
+    outputs: Vec<SerializedValue>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SerializedControlNodeDef {
+    attrs: u32,
+    kind: SerializedControlNodeKind,
+    outputs: Vec<(u32, u32)>,
+}
+
+#[derive(Serialize, Deserialize)]
+enum SerializedControlNodeKind {
+    Block {
+        insts: Vec<u32>,
+    },
+    Select {
+        kind: SerializedSelectionKind,
+        scrutinee: SerializedValue,
+        cases: Vec<u32>,
+    },
+    Loop {
+        initial_inputs: Vec<SerializedValue>,
+        body: u32,
+        repeat_condition: SerializedValue,
+    },
+    ExitInvocation {
+        kind: SerializedExitInvocationKind,
+        inputs: Vec<SerializedValue>,
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+enum SerializedSelectionKind {
+    BoolCond,
+    SpvInst(SerializedSpvInst),
+}
+
+#[derive(Serialize, Deserialize)]
+struct SerializedDataInstDef {
+    attrs: u32,
+    kind: SerializedDataInstKind,
+    output_type: Option<u32>,
+    inputs: Vec<SerializedValue>,
+}
+
+#[derive(Serialize, Deserialize)]
+enum SerializedDataInstKind {
+    FuncCall(u32),
+    SpvInst(SerializedSpvInst),
+    SpvExtInst {
+        ext_set: String,
+        inst: u32,
+    },
+    SpvGlslStd450(String),
+    Atomic {
+        op: String,
+        scope: u32,
+        semantics: u32,
+        unequal_semantics: Option<u32>,
+    },
+    Barrier {
+        execution_scope: Option<u32>,
+        memory_scope: u32,
+        semantics: u32,
+    },
+    Group {
+        op: String,
+        scope: u32,
+        group_operation: Option<u32>,
+    },
+    QPtr(SerializedQPtrOp),
+}
+
+#[derive(Serialize, Deserialize)]
+enum SerializedQPtrOp {
+    Offset { pointee: u32 },
+    Load,
+    Store,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SerializedControlInst {
+    attrs: u32,
+    kind: SerializedControlInstKind,
+    inputs: Vec<SerializedValue>,
+    targets: Vec<u32>,
+    target_inputs: Vec<(u32, Vec<SerializedValue>)>,
+}
+
+#[derive(Serialize, Deserialize)]
+enum SerializedControlInstKind {
+    Unreachable,
+    Return,
+    ExitInvocation(SerializedExitInvocationKind),
+    Branch,
+    SelectBranch(SerializedSelectionKind),
+}
+
+#[derive(Serialize, Deserialize)]
+enum SerializedExitInvocationKind {
+    SpvInst(SerializedSpvInst),
+    EmitMeshTasks,
+}
+
+#[derive(Copy, Clone, Serialize, Deserialize)]
+enum SerializedValue {
+    Const(u32),
+    ControlRegionInput { region: u32, input_idx: u32 },
+    ControlNodeOutput { control_node: u32, output_idx: u32 },
+    DataInstOutput(u32),
+}
+
+#[derive(Serialize, Deserialize)]
+enum SerializedExportKey {
+    LinkName(String),
+    SpvEntryPoint {
+        imms: Vec<SerializedSpvImm>,
+        interface_global_vars: Vec<u32>,
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+enum SerializedExportee {
+    GlobalVar(u32),
+    Func(u32),
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+enum InternedKey {
+    AttrSet(AttrSet),
+    Type(Type),
+    Const(Const),
+}
+
+fn idx_to_u32(idx: usize) -> u32 {
+    idx.try_into().unwrap()
+}
+
+struct ModuleToSerialized<'a> {
+    cx: &'a Context,
+
+    interned: FxIndexSet<InternedKey>,
+    global_vars: FxIndexSet<GlobalVar>,
+    funcs: FxIndexSet<Func>,
+
+    out: SerializedModule,
+}
+
+impl<'a> ModuleToSerialized<'a> {
+    fn convert(module: &'a Module) -> SerializedModule {
+        let dialect = match &module.dialect {
+            ModuleDialect::Spv(dialect) => SerializedDialect::Spv {
+                version: (dialect.version_major, dialect.version_minor),
+                capabilities: dialect.capabilities.clone(),
+                extensions: dialect.extensions.clone(),
+                addressing_model: dialect.addressing_model,
+                memory_model: dialect.memory_model,
+            },
+        };
+        let debug_info = match &module.debug_info {
+            ModuleDebugInfo::Spv(debug_info) => SerializedDebugInfo::Spv {
+                original_generator_magic: debug_info.original_generator_magic,
+                source_languages: debug_info
+                    .source_languages
+                    .iter()
+                    .map(|(lang, sources)| SerializedDebugSources {
+                        lang: lang.lang,
+                        version: lang.version,
+                        file_contents: sources
+                            .file_contents
+                            .iter()
+                            .map(|(&file, contents)| {
+                                (module.cx_ref()[file].to_string(), contents.clone())
+                            })
+                            .collect(),
+                    })
+                    .collect(),
+                source_extensions: debug_info.source_extensions.clone(),
+                module_processes: debug_info.module_processes.clone(),
+            },
+        };
+
+        let mut this = Self {
+            cx: module.cx_ref(),
+
+            interned: FxIndexSet::default(),
+            global_vars: FxIndexSet::default(),
+            funcs: FxIndexSet::default(),
+
+            out: SerializedModule {
+                dialect,
+                debug_info,
+                interned: vec![],
+                global_vars: vec![],
+                funcs: vec![],
+                exports: vec![],
+            },
+        };
+
+        for (export_key, &exportee) in &module.exports {
+            let export_key = match export_key {
+                &ExportKey::LinkName(name) => SerializedExportKey::LinkName(this.cx[name].into()),
+                ExportKey::SpvEntryPoint {
+                    imms,
+                    interface_global_vars,
+                } => SerializedExportKey::SpvEntryPoint {
+                    imms: imms.iter().map(|&imm| spv_imm_to_serialized(imm)).collect(),
+                    interface_global_vars: interface_global_vars
+                        .iter()
+                        .map(|&gv| this.global_var(gv))
+                        .collect(),
+                },
+            };
+            let exportee = match exportee {
+                Exportee::GlobalVar(gv) => SerializedExportee::GlobalVar(this.global_var(gv)),
+                Exportee::Func(func) => SerializedExportee::Func(this.func(func)),
+            };
+            this.out.exports.push((export_key, exportee));
+        }
+
+        // NOTE(eddyb) entities are only given an index when first used, with
+        // their definitions converted later (as those may use more entities).
+        loop {
+            if let Some(&gv) = this.global_vars.get_index(this.out.global_vars.len()) {
+                let gv_decl = this.global_var_decl(&module.global_vars[gv]);
+                this.out.global_vars.push(gv_decl);
+            } else if let Some(&func) = this.funcs.get_index(this.out.funcs.len()) {
+                let func_decl = this.func_decl(&module.funcs[func]);
+                this.out.funcs.push(func_decl);
+            } else {
+                break;
+            }
+        }
+
+        this.out
+    }
+
+    fn global_var(&mut self, gv: GlobalVar) -> u32 {
+        idx_to_u32(self.global_vars.insert_full(gv).0)
+    }
+
+    fn func(&mut self, func: Func) -> u32 {
+        idx_to_u32(self.funcs.insert_full(func).0)
+    }
+
+    fn attrs(&mut self, attrs: AttrSet) -> u32 {
+        if let Some(idx) = self.interned.get_index_of(&InternedKey::AttrSet(attrs)) {
+            return idx_to_u32(idx);
+        }
+        let cx = self.cx;
+        let attrs_def = SerializedInterned::AttrSet(
+            cx[attrs]
+                .attrs
+                .iter()
+                .map(|attr| match attr {
+                    Attr::SpvAnnotation(inst) => {
+                        SerializedAttr::SpvAnnotation(spv_inst_to_serialized(inst))
+                    }
+                    Attr::SpvExecutionModeId { inst, consts } => {
+                        SerializedAttr::SpvExecutionModeId {
+                            inst: spv_inst_to_serialized(inst),
+                            consts: consts.iter().map(|ct| self.ct(ct.0)).collect(),
+                        }
+                    }
+                    &Attr::SpvDebugLine {
+                        file_path,
+                        line,
+                        col,
+                    } => SerializedAttr::SpvDebugLine {
+                        file_path: cx[file_path.0].into(),
+                        line,
+                        col,
+                    },
+                    Attr::SpvBitflagsOperand(imms) => SerializedAttr::SpvBitflagsOperand(
+                        imms.iter().copied().map(spv_imm_to_serialized).collect(),
+                    ),
+                    &Attr::SpvOriginalId(id) => SerializedAttr::SpvOriginalId(id),
+                    Attr::SpvUnsupported(message) => {
+                        SerializedAttr::SpvUnsupported(message.clone())
+                    }
+                    &Attr::SpvShaderDebugScope { scope, inlined_at } => {
+                        SerializedAttr::SpvShaderDebugScope {
+                            scope: self.ct(scope.0),
+                            inlined_at: inlined_at.map(|ct| self.ct(ct.0)),
+                        }
+                    }
+                    &Attr::SpvShaderDebugLine {
+                        source,
+                        line_start,
+                        line_end,
+                        col_start,
+                        col_end,
+                    } => SerializedAttr::SpvShaderDebugLine {
+                        source: self.ct(source.0),
+                        line_start,
+                        line_end,
+                        col_start,
+                        col_end,
+                    },
+                    &Attr::QPtr(QPtrAttr::FromSpvPtr {
+                        storage_class,
+                        pointee,
+                    }) => SerializedAttr::QPtrFromSpvPtr {
+                        storage_class,
+                        pointee: self.ty(pointee.0),
+                    },
+                    &Attr::QPtr(QPtrAttr::ToSpvPtrInput { input_idx, pointee }) => {
+                        SerializedAttr::QPtrToSpvPtrInput {
+                            input_idx,
+                            pointee: self.ty(pointee.0),
+                        }
+                    }
+                    Attr::Diagnostic {
+                        severity,
+                        message,
+                        related,
+                    } => SerializedAttr::Diagnostic {
+                        severity: severity.name().to_string(),
+                        message: message.clone(),
+                        related: related.clone(),
+                    },
+                })
+                .collect(),
+        );
+        self.push_interned(InternedKey::AttrSet(attrs), attrs_def)
+    }
+
+    fn ty(&mut self, ty: Type) -> u32 {
+        if let Some(idx) = self.interned.get_index_of(&InternedKey::Type(ty)) {
+            return idx_to_u32(idx);
+        }
+        let cx = self.cx;
+        let TypeDef {
+            attrs,
+            ctor,
+            ctor_args,
+        } = &cx[ty];
+        let ty_def = SerializedInterned::Type {
+            attrs: self.attrs(*attrs),
+            ctor: match ctor {
+                TypeCtor::SpvInst(inst) => {
+                    SerializedTypeCtor::SpvInst(spv_inst_to_serialized(inst))
+                }
+                TypeCtor::SpvStringLiteralForExtInst => {
+                    SerializedTypeCtor::SpvStringLiteralForExtInst
+                }
+                &TypeCtor::Matrix { column_count } => SerializedTypeCtor::Matrix { column_count },
+                TypeCtor::Array => SerializedTypeCtor::Array,
+                TypeCtor::RuntimeArray => SerializedTypeCtor::RuntimeArray,
+                &TypeCtor::Image(ImageType {
+                    dim,
+                    depth,
+                    arrayed,
+                    multisampled,
+                    sampled,
+                    format,
+                    access_qualifier,
+                }) => SerializedTypeCtor::Image {
+                    dim,
+                    depth,
+                    arrayed,
+                    multisampled,
+                    sampled,
+                    format,
+                    access_qualifier,
+                },
+                &TypeCtor::RecursivePtr { storage_class } => {
+                    SerializedTypeCtor::RecursivePtr { storage_class }
+                }
+                &TypeCtor::RecursivePtrSelf { depth } => {
+                    SerializedTypeCtor::RecursivePtrSelf { depth }
+                }
+                TypeCtor::QPtr => SerializedTypeCtor::QPtr,
+                TypeCtor::Sampler => SerializedTypeCtor::Sampler,
+                TypeCtor::SampledImage => SerializedTypeCtor::SampledImage,
+                TypeCtor::Struct { members } => SerializedTypeCtor::Struct {
+                    members: members
+                        .iter()
+                        .map(|member| (self.attrs(member.attrs), member.offset))
+                        .collect(),
+                },
+            },
+            ctor_args: ctor_args
+                .iter()
+                .map(|&arg| match arg {
+                    TypeCtorArg::Type(ty) => SerializedTypeCtorArg::Type(self.ty(ty)),
+                    TypeCtorArg::Const(ct) => SerializedTypeCtorArg::Const(self.ct(ct)),
+                })
+                .collect(),
+        };
+        self.push_interned(InternedKey::Type(ty), ty_def)
+    }
+
+    fn ct(&mut self, ct: Const) -> u32 {
+        if let Some(idx) = self.interned.get_index_of(&InternedKey::Const(ct)) {
+            return idx_to_u32(idx);
+        }
+        let cx = self.cx;
+        let ConstDef {
+            attrs,
+            ty,
+            ctor,
+            ctor_args,
+        } = &cx[ct];
+        let ct_def = SerializedInterned::Const {
+            attrs: self.attrs(*attrs),
+            ty: self.ty(*ty),
+            ctor: match ctor {
+                &ConstCtor::PtrToGlobalVar(gv) => {
+                    SerializedConstCtor::PtrToGlobalVar(self.global_var(gv))
+                }
+                ConstCtor::Undef => SerializedConstCtor::Undef,
+                ConstCtor::SpvInst(inst) => {
+                    SerializedConstCtor::SpvInst(spv_inst_to_serialized(inst))
+                }
+                &ConstCtor::SpecConst {
+                    spec_id,
+                    ref default,
+                } => SerializedConstCtor::SpecConst {
+                    spec_id,
+                    default: spv_inst_to_serialized(default),
+                },
+                ConstCtor::SpecConstOp(inst) => {
+                    SerializedConstCtor::SpecConstOp(spv_inst_to_serialized(inst))
+                }
+                &ConstCtor::SpvExtInst { ext_set, inst } => SerializedConstCtor::SpvExtInst {
+                    ext_set: cx[ext_set].into(),
+                    inst,
+                },
+                &ConstCtor::SpvStringLiteralForExtInst(s) => {
+                    SerializedConstCtor::SpvStringLiteralForExtInst(cx[s].into())
+                }
+                ConstCtor::SpvTypeOperand => SerializedConstCtor::SpvTypeOperand,
+            },
+            ctor_args: ctor_args.iter().map(|&ct| self.ct(ct)).collect(),
+        };
+        self.push_interned(InternedKey::Const(ct), ct_def)
+    }
+
+    /// Add `def` to the interned definitions, only after all of its own
+    /// dependencies (which are all guaranteed to be earlier in the table).
+    fn push_interned(&mut self, key: InternedKey, def: SerializedInterned) -> u32 {
+        let (idx, new) = self.interned.insert_full(key);
+        assert!(new);
+        self.out.interned.push(def);
+        idx_to_u32(idx)
+    }
+
+    fn decl_def<T, U>(
+        &mut self,
+        def: &DeclDef<T>,
+        present: impl FnOnce(&mut Self, &T) -> U,
+    ) -> SerializedDeclDef<U> {
+        match def {
+            DeclDef::Imported(Import::LinkName(name)) => {
+                SerializedDeclDef::Imported(SerializedImport::LinkName(self.cx[*name].into()))
+            }
+            DeclDef::Present(def) => SerializedDeclDef::Present(present(self, def)),
+        }
+    }
+
+    fn global_var_decl(&mut self, gv_decl: &GlobalVarDecl) -> SerializedGlobalVarDecl {
+        let GlobalVarDecl {
+            attrs,
+            type_of_ptr_to,
+            addr_space,
+            def,
+        } = gv_decl;
+        SerializedGlobalVarDecl {
+            attrs: self.attrs(*attrs),
+            type_of_ptr_to: self.ty(*type_of_ptr_to),
+            addr_space: match *addr_space {
+                AddrSpace::SpvStorageClass(sc) => SerializedAddrSpace::SpvStorageClass(sc),
+                AddrSpace::PhysicalStorageBuffer => SerializedAddrSpace::PhysicalStorageBuffer,
+            },
+            def: self.decl_def(def, |this, def| def.initializer.map(|ct| this.ct(ct))),
+        }
+    }
+
+    fn func_decl(&mut self, func_decl: &FuncDecl) -> SerializedFuncDecl {
+        let FuncDecl {
+            attrs,
+            ret_type,
+            params,
+            def,
+        } = func_decl;
+        SerializedFuncDecl {
+            attrs: self.attrs(*attrs),
+            ret_type: self.ty(*ret_type),
+            params: params
+                .iter()
+                .map(|param| (self.attrs(param.attrs), self.ty(param.ty)))
+                .collect(),
+            def: self.decl_def(def, |this, func_def_body| this.func_def_body(func_def_body)),
+        }
+    }
+
+    fn func_def_body(&mut self, func_def_body: &FuncDefBody) -> SerializedFuncDefBody {
+        // Assign indices to all the definitions reachable from the body.
+        let mut regions = FxIndexSet::default();
+        let mut nodes = FxIndexSet::default();
+        let mut data_insts = FxIndexSet::default();
+        {
+            fn visit_region(
+                func_at_region: FuncAt<'_, ControlRegion>,
+                regions: &mut FxIndexSet<ControlRegion>,
+                nodes: &mut FxIndexSet<ControlNode>,
+                data_insts: &mut FxIndexSet<DataInst>,
+            ) {
+                regions.insert(func_at_region.position);
+                for func_at_node in func_at_region.at_children() {
+                    nodes.insert(func_at_node.position);
+                    match &func_at_node.def().kind {
+                        ControlNodeKind::Block { insts } => {
+                            data_insts.extend(
+                                func_at_node
+                                    .at(*insts)
+                                    .into_iter()
+                                    .map(|func_at_inst| func_at_inst.position),
+                            );
+                        }
+                        ControlNodeKind::Select { cases, .. } => {
+                            for &case in cases {
+                                visit_region(func_at_node.at(case), regions, nodes, data_insts);
+                            }
+                        }
+                        ControlNodeKind::Loop { body, .. } => {
+                            visit_region(func_at_node.at(*body), regions, nodes, data_insts);
+                        }
+                        ControlNodeKind::ExitInvocation { .. } => {}
+                    }
+                }
+            }
+            match &func_def_body.unstructured_cfg {
+                None => visit_region(
+                    func_def_body.at_body(),
+                    &mut regions,
+                    &mut nodes,
+                    &mut data_insts,
+                ),
+                Some(cfg) => {
+                    for region in cfg.rev_post_order(func_def_body) {
+                        visit_region(
+                            func_def_body.at(region),
+                            &mut regions,
+                            &mut nodes,
+                            &mut data_insts,
+                        );
+                    }
+                }
+            }
+        }
+
+        let region_idx = |region| idx_to_u32(regions.get_index_of(&region).unwrap());
+        let node_idx = |node| idx_to_u32(nodes.get_index_of(&node).unwrap());
+        let inst_idx = |inst| idx_to_u32(data_insts.get_index_of(&inst).unwrap());
+        let value = |this: &mut Self, v: Value| match v {
+            Value::Const(ct) => SerializedValue::Const(this.ct(ct)),
+            Value::ControlRegionInput { region, input_idx } => {
+                SerializedValue::ControlRegionInput {
+                    region: region_idx(region),
+                    input_idx,
+                }
+            }
+            Value::ControlNodeOutput {
+                control_node,
+                output_idx,
+            } => SerializedValue::ControlNodeOutput {
+                control_node: node_idx(control_node),
+                output_idx,
+            },
+            Value::DataInstOutput(inst) => SerializedValue::DataInstOutput(inst_idx(inst)),
+        };
+        let selection_kind = |kind: &SelectionKind| match kind {
+            SelectionKind::BoolCond => SerializedSelectionKind::BoolCond,
+            SelectionKind::SpvInst(inst) => {
+                SerializedSelectionKind::SpvInst(spv_inst_to_serialized(inst))
+            }
+        };
+        let exit_invocation_kind = |kind: &cfg::ExitInvocationKind| match kind {
+            cfg::ExitInvocationKind::SpvInst(inst) => {
+                SerializedExitInvocationKind::SpvInst(spv_inst_to_serialized(inst))
+            }
+            cfg::ExitInvocationKind::EmitMeshTasks => SerializedExitInvocationKind::EmitMeshTasks,
+        };
+
+        let control_regions = regions
+            .iter()
+            .map(|&region| {
+                let ControlRegionDef {
+                    inputs,
+                    children,
+                    outputs,
+                } = &func_def_body.control_regions[region];
+                SerializedControlRegionDef {
+                    inputs: inputs
+                        .iter()
+                        .map(|input| (self.attrs(input.attrs), self.ty(input.ty)))
+                        .collect(),
+                    children: func_def_body
+                        .at(*children)
+                        .into_iter()
+                        .map(|func_at_node| node_idx(func_at_node.position))
+                        .collect(),
+                    outputs: outputs.iter().map(|&v| value(self, v)).collect(),
+                }
+            })
+            .collect();
+
+        let control_nodes = nodes
+            .iter()
+            .map(|&node| {
+                let ControlNodeDef {
+                    attrs,
+                    kind,
+                    outputs,
+                } = &*func_def_body.control_nodes[node];
+                let kind = match kind {
+                    ControlNodeKind::Block { insts } => SerializedControlNodeKind::Block {
+                        insts: func_def_body
+                            .at(*insts)
+                            .into_iter()
+                            .map(|func_at_inst| inst_idx(func_at_inst.position))
+                            .collect(),
+                    },
+                    ControlNodeKind::Select {
+                        kind,
+                        scrutinee,
+                        cases,
+                    } => SerializedControlNodeKind::Select {
+                        kind: selection_kind(kind),
+                        scrutinee: value(self, *scrutinee),
+                        cases: cases.iter().map(|&case| region_idx(case)).collect(),
+                    },
+                    ControlNodeKind::Loop {
+                        initial_inputs,
+                        body,
+                        repeat_condition,
+                    } => SerializedControlNodeKind::Loop {
+                        initial_inputs: initial_inputs.iter().map(|&v| value(self, v)).collect(),
+                        body: region_idx(*body),
+                        repeat_condition: value(self, *repeat_condition),
+                    },
+                    ControlNodeKind::ExitInvocation { kind, inputs } => {
+                        SerializedControlNodeKind::ExitInvocation {
+                            kind: exit_invocation_kind(kind),
+                            inputs: inputs.iter().map(|&v| value(self, v)).collect(),
+                        }
+                    }
+                };
+                SerializedControlNodeDef {
+                    attrs: self.attrs(*attrs),
+                    kind,
+                    outputs: outputs
+                        .iter()
+                        .map(|output| (self.attrs(output.attrs), self.ty(output.ty)))
+                        .collect(),
+                }
+            })
+            .collect();
+
+        let data_insts = data_insts
+            .iter()
+            .map(|&inst| {
+                let DataInstDef {
+                    attrs,
+                    kind,
+                    output_type,
+                    inputs,
+                } = &*func_def_body.data_insts[inst];
+                SerializedDataInstDef {
+                    attrs: self.attrs(*attrs),
+                    kind: match kind {
+                        &DataInstKind::FuncCall(func) => {
+                            SerializedDataInstKind::FuncCall(self.func(func))
+                        }
+                        DataInstKind::SpvInst(inst) => {
+                            SerializedDataInstKind::SpvInst(spv_inst_to_serialized(inst))
+                        }
+                        &DataInstKind::SpvExtInst { ext_set, inst } => {
+                            SerializedDataInstKind::SpvExtInst {
+                                ext_set: self.cx[ext_set].into(),
+                                inst,
+                            }
+                        }
+                        &DataInstKind::SpvGlslStd450(op) => {
+                            SerializedDataInstKind::SpvGlslStd450(op.name().into())
+                        }
+                        &DataInstKind::Atomic {
+                            op,
+                            scope,
+                            semantics,
+                        } => SerializedDataInstKind::Atomic {
+                            op: op.name().into(),
+                            scope,
+                            semantics,
+                            unequal_semantics: match op {
+                                AtomicOp::CompareExchange {
+                                    unequal_semantics, ..
+                                } => Some(unequal_semantics),
+                                _ => None,
+                            },
+                        },
+                        &DataInstKind::Barrier {
+                            execution_scope,
+                            memory_scope,
+                            semantics,
+                        } => SerializedDataInstKind::Barrier {
+                            execution_scope,
+                            memory_scope,
+                            semantics,
+                        },
+                        &DataInstKind::Group {
+                            op,
+                            scope,
+                            group_operation,
+                        } => SerializedDataInstKind::Group {
+                            op: op.name().into(),
+                            scope,
+                            group_operation,
+                        },
+                        &DataInstKind::QPtr(op) => SerializedDataInstKind::QPtr(match op {
+                            QPtrOp::Offset { pointee } => SerializedQPtrOp::Offset {
+                                pointee: self.ty(pointee),
+                            },
+                            QPtrOp::Load => SerializedQPtrOp::Load,
+                            QPtrOp::Store => SerializedQPtrOp::Store,
+                        }),
+                    },
+                    output_type: output_type.map(|ty| self.ty(ty)),
+                    inputs: inputs.iter().map(|&v| value(self, v)).collect(),
+                }
+            })
+            .collect();
+
+        let unstructured_cfg = func_def_body.unstructured_cfg.as_ref().map(|cfg| {
+            regions
+                .iter()
+                .filter_map(|&source| {
+                    let cfg::ControlInst {
+                        attrs,
+                        kind,
+                        inputs,
+                        targets,
+                        target_inputs,
+                    } = cfg.control_inst_on_exit_from.get(source)?;
+                    let kind = match kind {
+                        cfg::ControlInstKind::Unreachable => SerializedControlInstKind::Unreachable,
+                        cfg::ControlInstKind::Return => SerializedControlInstKind::Return,
+                        cfg::ControlInstKind::ExitInvocation(kind) => {
+                            SerializedControlInstKind::ExitInvocation(exit_invocation_kind(kind))
+                        }
+                        cfg::ControlInstKind::Branch => SerializedControlInstKind::Branch,
+                        cfg::ControlInstKind::SelectBranch(kind) => {
+                            SerializedControlInstKind::SelectBranch(selection_kind(kind))
+                        }
+                    };
+                    Some((
+                        region_idx(source),
+                        SerializedControlInst {
+                            attrs: self.attrs(*attrs),
+                            kind,
+                            inputs: inputs.iter().map(|&v| value(self, v)).collect(),
+                            targets: targets.iter().map(|&target| region_idx(target)).collect(),
+                            target_inputs: target_inputs
+                                .iter()
+                                .map(|(&target, inputs)| {
+                                    (
+                                        region_idx(target),
+                                        inputs.iter().map(|&v| value(self, v)).collect(),
+                                    )
+                                })
+                                .collect(),
+                        },
+                    ))
+                })
+                .collect()
+        });
+
+        SerializedFuncDefBody {
+            control_regions,
+            control_nodes,
+            data_insts,
+            body: region_idx(func_def_body.body),
+            unstructured_cfg,
+        }
+    }
+}
+
+fn spv_imm_to_serialized(imm: spv::Imm) -> SerializedSpvImm {
+    match imm {
+        spv::Imm::Short(kind, word) => SerializedSpvImm::Short(kind.name().into(), word),
+        spv::Imm::LongStart(kind, word) => SerializedSpvImm::LongStart(kind.name().into(), word),
+        spv::Imm::LongCont(kind, word) => SerializedSpvImm::LongCont(kind.name().into(), word),
+    }
+}
+
+fn spv_inst_to_serialized(inst: &spv::Inst) -> SerializedSpvInst {
+    SerializedSpvInst {
+        opcode: inst.opcode.name().into(),
+        imms: inst
+            .imms
+            .iter()
+            .map(|&imm| spv_imm_to_serialized(imm))
+            .collect(),
+    }
+}
+
+fn spv_imm_from_serialized(imm: &SerializedSpvImm) -> Result<spv::Imm, String> {
+    let (ctor, kind_name, word): (fn(_, _) -> _, _, _) = match imm {
+        SerializedSpvImm::Short(kind, word) => (spv::Imm::Short, kind, *word),
+        SerializedSpvImm::LongStart(kind, word) => (spv::Imm::LongStart, kind, *word),
+        SerializedSpvImm::LongCont(kind, word) => (spv::Imm::LongCont, kind, *word),
+    };
+    let kind = spec::Spec::get()
+        .operand_kinds
+        .lookup(kind_name)
+        .ok_or_else(|| format!("unknown SPIR-V operand kind `{kind_name}`"))?;
+    Ok(ctor(kind, word))
+}
+
+fn spv_imms_from_serialized<C: FromIterator<spv::Imm>>(
+    imms: &[SerializedSpvImm],
+) -> Result<C, String> {
+    imms.iter().map(spv_imm_from_serialized).collect()
+}
+
+fn spv_inst_from_serialized(inst: &SerializedSpvInst) -> Result<spv::Inst, String> {
+    Ok(spv::Inst {
+        opcode: spec::Spec::get()
+            .instructions
+            .lookup(&inst.opcode)
+            .ok_or_else(|| format!("unknown SPIR-V opcode `{}`", inst.opcode))?,
+        imms: spv_imms_from_serialized(&inst.imms)?,
+    })
+}
+
+fn get_idx<T: Copy>(table: &[T], idx: u32, what: &str) -> Result<T, String> {
+    table
+        .get(idx as usize)
+        .copied()
+        .ok_or_else(|| format!("{what} index {idx} out of range"))
+}
+
+/// Interned handle, as (re-)interned by [`SerializedToModule`].
+#[derive(Copy, Clone)]
+enum InternedHandle {
+    AttrSet(AttrSet),
+    Type(Type),
+    Const(Const),
+}
+
+struct SerializedToModule {
+    cx: Rc<Context>,
+
+    interned: Vec<InternedHandle>,
+    global_vars: Vec<GlobalVar>,
+    funcs: Vec<Func>,
+}
+
+impl SerializedToModule {
+    fn convert(cx: Rc<Context>, serialized: SerializedModule) -> Result<Module, String> {
+        let SerializedModule {
+            dialect,
+            debug_info,
+            interned,
+            global_vars,
+            funcs,
+            exports,
+        } = serialized;
+
+        let dialect = match dialect {
+            SerializedDialect::Spv {
+                version: (version_major, version_minor),
+                capabilities,
+                extensions,
+                addressing_model,
+                memory_model,
+            } => ModuleDialect::Spv(spv::Dialect {
+                version_major,
+                version_minor,
+                capabilities,
+                extensions,
+                addressing_model,
+                memory_model,
+            }),
+        };
+        let debug_info = match debug_info {
+            SerializedDebugInfo::Spv {
+                original_generator_magic,
+                source_languages,
+                source_extensions,
+                module_processes,
+            } => {
+                let mut source_languages_map = BTreeMap::new();
+                for sources in source_languages {
+                    let spv::DebugSources { file_contents } = source_languages_map
+                        .entry(spv::DebugSourceLang {
+                            lang: sources.lang,
+                            version: sources.version,
+                        })
+                        .or_default();
+                    file_contents.extend(
+                        sources
+                            .file_contents
+                            .into_iter()
+                            .map(|(file, contents)| (cx.intern(file), contents)),
+                    );
+                }
+                ModuleDebugInfo::Spv(spv::ModuleDebugInfo {
+                    original_generator_magic,
+                    source_languages: source_languages_map,
+                    source_extensions,
+                    module_processes,
+                })
+            }
+        };
+        let mut module = Module::new(cx.clone(), dialect, debug_info);
+
+        // HACK(eddyb) all entities are defined upfront (using placeholders),
+        // so that they can be referred to (e.g. by constants) before their
+        // actual definitions are converted (and replace the placeholders).
+        let placeholder_type = cx.intern(TypeDef {
+            attrs: AttrSet::default(),
+            ctor: TypeCtor::SpvStringLiteralForExtInst,
+            ctor_args: SmallVec::new(),
+        });
+        let placeholder_import = || Import::LinkName(cx.intern(""));
+        let mut this = Self {
+            cx: cx.clone(),
+
+            interned: Vec::with_capacity(interned.len()),
+            global_vars: global_vars
+                .iter()
+                .map(|_| {
+                    module.global_vars.define(
+                        &cx,
+                        GlobalVarDecl {
+                            attrs: AttrSet::default(),
+                            type_of_ptr_to: placeholder_type,
+                            addr_space: AddrSpace::SpvStorageClass(0),
+                            def: DeclDef::Imported(placeholder_import()),
+                        },
+                    )
+                })
+                .collect(),
+            funcs: funcs
+                .iter()
+                .map(|_| {
+                    module.funcs.define(
+                        &cx,
+                        FuncDecl {
+                            attrs: AttrSet::default(),
+                            ret_type: placeholder_type,
+                            params: SmallVec::new(),
+                            def: DeclDef::Imported(placeholder_import()),
+                        },
+                    )
+                })
+                .collect(),
+        };
+
+        for def in interned {
+            let handle = this.interned_def(def)?;
+            this.interned.push(handle);
+        }
+
+        for (&gv, gv_decl) in this.global_vars.iter().zip(global_vars) {
+            module.global_vars[gv] = this.global_var_decl(gv_decl)?;
+        }
+        for (&func, func_decl) in this.funcs.iter().zip(funcs) {
+            module.funcs[func] = this.func_decl(func_decl)?;
+        }
+
+        for (export_key, exportee) in exports {
+            let export_key = match export_key {
+                SerializedExportKey::LinkName(name) => ExportKey::LinkName(cx.intern(name)),
+                SerializedExportKey::SpvEntryPoint {
+                    imms,
+                    interface_global_vars,
+                } => ExportKey::SpvEntryPoint {
+                    imms: spv_imms_from_serialized(&imms)?,
+                    interface_global_vars: interface_global_vars
+                        .into_iter()
+                        .map(|gv| this.global_var(gv))
+                        .collect::<Result<_, _>>()?,
+                },
+            };
+            let exportee = match exportee {
+                SerializedExportee::GlobalVar(gv) => Exportee::GlobalVar(this.global_var(gv)?),
+                SerializedExportee::Func(func) => Exportee::Func(this.func(func)?),
+            };
+            module.exports.insert(export_key, exportee);
+        }
+
+        Ok(module)
+    }
+
+    fn global_var(&self, idx: u32) -> Result<GlobalVar, String> {
+        get_idx(&self.global_vars, idx, "global variable")
+    }
+
+    fn func(&self, idx: u32) -> Result<Func, String> {
+        get_idx(&self.funcs, idx, "function")
+    }
+
+    fn attrs(&self, idx: u32) -> Result<AttrSet, String> {
+        match get_idx(&self.interned, idx, "interned definition")? {
+            InternedHandle::AttrSet(attrs) => Ok(attrs),
+            _ => Err(format!("interned definition {idx} is not an attribute set")),
+        }
+    }
+
+    fn ty(&self, idx: u32) -> Result<Type, String> {
+        match get_idx(&self.interned, idx, "interned definition")? {
+            InternedHandle::Type(ty) => Ok(ty),
+            _ => Err(format!("interned definition {idx} is not a type")),
+        }
+    }
+
+    fn ct(&self, idx: u32) -> Result<Const, String> {
+        match get_idx(&self.interned, idx, "interned definition")? {
+            InternedHandle::Const(ct) => Ok(ct),
+            _ => Err(format!("interned definition {idx} is not a constant")),
+        }
+    }
+
+    fn interned_def(&self, def: SerializedInterned) -> Result<InternedHandle, String> {
+        let cx = &self.cx;
+        Ok(match def {
+            SerializedInterned::AttrSet(attrs) => InternedHandle::AttrSet(
+                cx.intern(AttrSetDef {
+                    attrs: attrs
+                        .into_iter()
+                        .map(|attr| {
+                            Ok(match attr {
+                                SerializedAttr::SpvAnnotation(inst) => {
+                                    Attr::SpvAnnotation(spv_inst_from_serialized(&inst)?)
+                                }
+                                SerializedAttr::SpvExecutionModeId { inst, consts } => {
+                                    Attr::SpvExecutionModeId {
+                                        inst: spv_inst_from_serialized(&inst)?,
+                                        consts: consts
+                                            .into_iter()
+                                            .map(|ct| self.ct(ct).map(OrdAssertEq))
+                                            .collect::<Result<_, _>>()?,
+                                    }
+                                }
+                                SerializedAttr::SpvDebugLine {
+                                    file_path,
+                                    line,
+                                    col,
+                                } => Attr::SpvDebugLine {
+                                    file_path: OrdAssertEq(cx.intern(file_path)),
+                                    line,
+                                    col,
+                                },
+                                SerializedAttr::SpvBitflagsOperand(imms) => {
+                                    Attr::SpvBitflagsOperand(
+                                        imms.iter()
+                                            .map(spv_imm_from_serialized)
+                                            .collect::<Result<_, _>>()?,
+                                    )
+                                }
+                                SerializedAttr::SpvOriginalId(id) => Attr::SpvOriginalId(id),
+                                SerializedAttr::SpvUnsupported(message) => {
+                                    Attr::SpvUnsupported(message)
+                                }
+                                SerializedAttr::SpvShaderDebugScope { scope, inlined_at } => {
+                                    Attr::SpvShaderDebugScope {
+                                        scope: OrdAssertEq(self.ct(scope)?),
+                                        inlined_at: inlined_at
+                                            .map(|ct| self.ct(ct).map(OrdAssertEq))
+                                            .transpose()?,
+                                    }
+                                }
+                                SerializedAttr::SpvShaderDebugLine {
+                                    source,
+                                    line_start,
+                                    line_end,
+                                    col_start,
+                                    col_end,
+                                } => Attr::SpvShaderDebugLine {
+                                    source: OrdAssertEq(self.ct(source)?),
+                                    line_start,
+                                    line_end,
+                                    col_start,
+                                    col_end,
+                                },
+                                SerializedAttr::QPtrFromSpvPtr {
+                                    storage_class,
+                                    pointee,
+                                } => Attr::QPtr(QPtrAttr::FromSpvPtr {
+                                    storage_class,
+                                    pointee: OrdAssertEq(self.ty(pointee)?),
+                                }),
+                                SerializedAttr::QPtrToSpvPtrInput { input_idx, pointee } => {
+                                    Attr::QPtr(QPtrAttr::ToSpvPtrInput {
+                                        input_idx,
+                                        pointee: OrdAssertEq(self.ty(pointee)?),
+                                    })
+                                }
+                                SerializedAttr::Diagnostic {
+                                    severity,
+                                    message,
+                                    related,
+                                } => Attr::Diagnostic {
+                                    severity: DiagnosticSeverity::from_name(&severity).ok_or_else(
+                                        || format!("invalid diagnostic severity `{severity}`"),
+                                    )?,
+                                    message,
+                                    related,
+                                },
+                            })
+                        })
+                        .collect::<Result<_, String>>()?,
+                }),
+            ),
+            SerializedInterned::Type {
+                attrs,
+                ctor,
+                ctor_args,
+            } => InternedHandle::Type(
+                cx.intern(TypeDef {
+                    attrs: self.attrs(attrs)?,
+                    ctor: match ctor {
+                        SerializedTypeCtor::SpvInst(inst) => {
+                            TypeCtor::SpvInst(spv_inst_from_serialized(&inst)?)
+                        }
+                        SerializedTypeCtor::SpvStringLiteralForExtInst => {
+                            TypeCtor::SpvStringLiteralForExtInst
+                        }
+                        SerializedTypeCtor::Matrix { column_count } => {
+                            TypeCtor::Matrix { column_count }
+                        }
+                        SerializedTypeCtor::Array => TypeCtor::Array,
+                        SerializedTypeCtor::RuntimeArray => TypeCtor::RuntimeArray,
+                        SerializedTypeCtor::Image {
+                            dim,
+                            depth,
+                            arrayed,
+                            multisampled,
+                            sampled,
+                            format,
+                            access_qualifier,
+                        } => TypeCtor::Image(ImageType {
+                            dim,
+                            depth,
+                            arrayed,
+                            multisampled,
+                            sampled,
+                            format,
+                            access_qualifier,
+                        }),
+                        SerializedTypeCtor::RecursivePtr { storage_class } => {
+                            TypeCtor::RecursivePtr { storage_class }
+                        }
+                        SerializedTypeCtor::RecursivePtrSelf { depth } => {
+                            TypeCtor::RecursivePtrSelf { depth }
+                        }
+                        SerializedTypeCtor::QPtr => TypeCtor::QPtr,
+                        SerializedTypeCtor::Sampler => TypeCtor::Sampler,
+                        SerializedTypeCtor::SampledImage => TypeCtor::SampledImage,
+                        SerializedTypeCtor::Struct { members } => TypeCtor::Struct {
+                            members: members
+                                .into_iter()
+                                .map(|(attrs, offset)| {
+                                    Ok(StructMember {
+                                        attrs: self.attrs(attrs)?,
+                                        offset,
+                                    })
+                                })
+                                .collect::<Result<_, String>>()?,
+                        },
+                    },
+                    ctor_args: ctor_args
+                        .into_iter()
+                        .map(|arg| {
+                            Ok(match arg {
+                                SerializedTypeCtorArg::Type(ty) => TypeCtorArg::Type(self.ty(ty)?),
+                                SerializedTypeCtorArg::Const(ct) => {
+                                    TypeCtorArg::Const(self.ct(ct)?)
+                                }
+                            })
+                        })
+                        .collect::<Result<_, String>>()?,
+                }),
+            ),
+            SerializedInterned::Const {
+                attrs,
+                ty,
+                ctor,
+                ctor_args,
+            } => InternedHandle::Const(
+                cx.intern(ConstDef {
+                    attrs: self.attrs(attrs)?,
+                    ty: self.ty(ty)?,
+                    ctor: match ctor {
+                        SerializedConstCtor::PtrToGlobalVar(gv) => {
+                            ConstCtor::PtrToGlobalVar(self.global_var(gv)?)
+                        }
+                        SerializedConstCtor::Undef => ConstCtor::Undef,
+                        SerializedConstCtor::SpvInst(inst) => {
+                            ConstCtor::SpvInst(spv_inst_from_serialized(&inst)?)
+                        }
+                        SerializedConstCtor::SpecConst { spec_id, default } => {
+                            ConstCtor::SpecConst {
+                                spec_id,
+                                default: spv_inst_from_serialized(&default)?,
+                            }
+                        }
+                        SerializedConstCtor::SpecConstOp(inst) => {
+                            ConstCtor::SpecConstOp(spv_inst_from_serialized(&inst)?)
+                        }
+                        SerializedConstCtor::SpvExtInst { ext_set, inst } => {
+                            ConstCtor::SpvExtInst {
+                                ext_set: cx.intern(ext_set),
+                                inst,
+                            }
+                        }
+                        SerializedConstCtor::SpvStringLiteralForExtInst(s) => {
+                            ConstCtor::SpvStringLiteralForExtInst(cx.intern(s))
+                        }
+                        SerializedConstCtor::SpvTypeOperand => ConstCtor::SpvTypeOperand,
+                    },
+                    ctor_args: ctor_args
+                        .into_iter()
+                        .map(|ct| self.ct(ct))
+                        .collect::<Result<_, _>>()?,
+                }),
+            ),
+        })
+    }
+
+    fn decl_def<T, U>(
+        &self,
+        def: SerializedDeclDef<T>,
+        present: impl FnOnce(&Self, T) -> Result<U, String>,
+    ) -> Result<DeclDef<U>, String> {
+        Ok(match def {
+            SerializedDeclDef::Imported(SerializedImport::LinkName(name)) => {
+                DeclDef::Imported(Import::LinkName(self.cx.intern(name)))
+            }
+            SerializedDeclDef::Present(def) => DeclDef::Present(present(self, def)?),
+        })
+    }
+
+    fn global_var_decl(&self, gv_decl: SerializedGlobalVarDecl) -> Result<GlobalVarDecl, String> {
+        let SerializedGlobalVarDecl {
+            attrs,
+            type_of_ptr_to,
+            addr_space,
+            def,
+        } = gv_decl;
+        Ok(GlobalVarDecl {
+            attrs: self.attrs(attrs)?,
+            type_of_ptr_to: self.ty(type_of_ptr_to)?,
+            addr_space: match addr_space {
+                SerializedAddrSpace::SpvStorageClass(sc) => AddrSpace::SpvStorageClass(sc),
+                SerializedAddrSpace::PhysicalStorageBuffer => AddrSpace::PhysicalStorageBuffer,
+            },
+            def: self.decl_def(def, |this, initializer| {
+                Ok(GlobalVarDefBody {
+                    initializer: initializer.map(|ct| this.ct(ct)).transpose()?,
+                })
+            })?,
+        })
+    }
+
+    fn func_decl(&self, func_decl: SerializedFuncDecl) -> Result<FuncDecl, String> {
+        let SerializedFuncDecl {
+            attrs,
+            ret_type,
+            params,
+            def,
+        } = func_decl;
+        let params = params
+            .into_iter()
+            .map(|(attrs, ty)| {
+                Ok(FuncParam {
+                    attrs: self.attrs(attrs)?,
+                    ty: self.ty(ty)?,
+                })
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(FuncDecl {
+            attrs: self.attrs(attrs)?,
+            ret_type: self.ty(ret_type)?,
+            params,
+            def: self.decl_def(def, Self::func_def_body)?,
+        })
+    }
+
+    fn func_def_body(&self, func_def_body: SerializedFuncDefBody) -> Result<FuncDefBody, String> {
+        let SerializedFuncDefBody {
+            control_regions: serialized_regions,
+            control_nodes: serialized_nodes,
+            data_insts: serialized_insts,
+            body,
+            unstructured_cfg,
+        } = func_def_body;
+        let cx = &self.cx;
+
+        // All the entities are defined first (with their values/links missing,
+        // and filled in later), to allow arbitrary references between them.
+        let mut control_regions = EntityDefs::<ControlRegion>::new();
+        let mut control_nodes = EntityDefs::<ControlNode>::new();
+        let mut data_insts = EntityDefs::<DataInst>::new();
+        let regions = serialized_regions
+            .iter()
+            .map(|region_def| {
+                let inputs = region_def
+                    .inputs
+                    .iter()
+                    .map(|&(attrs, ty)| {
+                        Ok(ControlRegionInputDecl {
+                            attrs: self.attrs(attrs)?,
+                            ty: self.ty(ty)?,
+                        })
+                    })
+                    .collect::<Result<_, String>>()?;
+                Ok(control_regions.define(
+                    cx,
+                    ControlRegionDef {
+                        inputs,
+                        children: EntityList::empty(),
+                        outputs: SmallVec::new(),
+                    },
+                ))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        let nodes = serialized_nodes
+            .iter()
+            .map(|node_def| {
+                let outputs = node_def
+                    .outputs
+                    .iter()
+                    .map(|&(attrs, ty)| {
+                        Ok(ControlNodeOutputDecl {
+                            attrs: self.attrs(attrs)?,
+                            ty: self.ty(ty)?,
+                        })
+                    })
+                    .collect::<Result<_, String>>()?;
+                Ok(control_nodes.define(
+                    cx,
+                    ControlNodeDef {
+                        attrs: self.attrs(node_def.attrs)?,
+                        kind: ControlNodeKind::Block {
+                            insts: EntityList::empty(),
+                        },
+                        outputs,
+                    }
+                    .into(),
+                ))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        let insts = serialized_insts
+            .iter()
+            .map(|inst_def| {
+                let kind = match &inst_def.kind {
+                    &SerializedDataInstKind::FuncCall(func) => {
+                        DataInstKind::FuncCall(self.func(func)?)
+                    }
+                    SerializedDataInstKind::SpvInst(inst) => {
+                        DataInstKind::SpvInst(spv_inst_from_serialized(inst)?)
+                    }
+                    SerializedDataInstKind::SpvExtInst { ext_set, inst } => {
+                        DataInstKind::SpvExtInst {
+                            ext_set: cx.intern(&ext_set[..]),
+                            inst: *inst,
+                        }
+                    }
+                    SerializedDataInstKind::SpvGlslStd450(name) => DataInstKind::SpvGlslStd450(
+                        spv::glsl_std_450::Op::from_name(name).ok_or_else(|| {
+                            format!("unknown `GLSL.std.450` instruction `{name}`")
+                        })?,
+                    ),
+                    &SerializedDataInstKind::Atomic {
+                        ref op,
+                        scope,
+                        semantics,
+                        unequal_semantics,
+                    } => DataInstKind::Atomic {
+                        op: AtomicOp::from_name(op, unequal_semantics).ok_or_else(|| {
+                            format!("invalid atomic operation `{op}` (or unequal semantics)")
+                        })?,
+                        scope,
+                        semantics,
+                    },
+                    &SerializedDataInstKind::Barrier {
+                        execution_scope,
+                        memory_scope,
+                        semantics,
+                    } => DataInstKind::Barrier {
+                        execution_scope,
+                        memory_scope,
+                        semantics,
+                    },
+                    &SerializedDataInstKind::Group {
+                        ref op,
+                        scope,
+                        group_operation,
+                    } => {
+                        let op = GroupOp::from_name(op)
+                            .ok_or_else(|| format!("unknown group operation `{op}`"))?;
+                        if op.takes_group_operation() != group_operation.is_some() {
+                            return Err(format!(
+                                "group operation `{}` {} a `GroupOperation`",
+                                op.name(),
+                                if op.takes_group_operation() {
+                                    "requires"
+                                } else {
+                                    "cannot have"
+                                }
+                            ));
+                        }
+                        DataInstKind::Group {
+                            op,
+                            scope,
+                            group_operation,
+                        }
+                    }
+                    SerializedDataInstKind::QPtr(ref op) => DataInstKind::QPtr(match *op {
+                        SerializedQPtrOp::Offset { pointee } => QPtrOp::Offset {
+                            pointee: self.ty(pointee)?,
+                        },
+                        SerializedQPtrOp::Load => QPtrOp::Load,
+                        SerializedQPtrOp::Store => QPtrOp::Store,
+                    }),
+                };
+                Ok(data_insts.define(
+                    cx,
+                    DataInstDef {
+                        attrs: self.attrs(inst_def.attrs)?,
+                        kind,
+                        output_type: inst_def.output_type.map(|ty| self.ty(ty)).transpose()?,
+                        inputs: SmallVec::new(),
+                    }
+                    .into(),
+                ))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+
+        let region = |idx| get_idx(&regions, idx, "control region");
+        let node = |idx| get_idx(&nodes, idx, "control node");
+        let inst = |idx| get_idx(&insts, idx, "data instruction");
+        let value = |v: &SerializedValue| {
+            Ok(match *v {
+                SerializedValue::Const(ct) => Value::Const(self.ct(ct)?),
+                SerializedValue::ControlRegionInput {
+                    region: input_region,
+                    input_idx,
+                } => Value::ControlRegionInput {
+                    region: region(input_region)?,
+                    input_idx,
+                },
+                SerializedValue::ControlNodeOutput {
+                    control_node,
+                    output_idx,
+                } => Value::ControlNodeOutput {
+                    control_node: node(control_node)?,
+                    output_idx,
+                },
+                SerializedValue::DataInstOutput(idx) => Value::DataInstOutput(inst(idx)?),
+            })
+        };
+        let values = |vs: &[SerializedValue]| vs.iter().map(value).collect::<Result<_, String>>();
+        let selection_kind = |kind: &SerializedSelectionKind| {
+            Ok::<_, String>(match kind {
+                SerializedSelectionKind::BoolCond => SelectionKind::BoolCond,
+                SerializedSelectionKind::SpvInst(inst) => {
+                    SelectionKind::SpvInst(spv_inst_from_serialized(inst)?)
+                }
+            })
+        };
+        let exit_invocation_kind = |kind: &SerializedExitInvocationKind| {
+            Ok::<_, String>(match kind {
+                SerializedExitInvocationKind::SpvInst(inst) => {
+                    cfg::ExitInvocationKind::SpvInst(spv_inst_from_serialized(inst)?)
+                }
+                SerializedExitInvocationKind::EmitMeshTasks => {
+                    cfg::ExitInvocationKind::EmitMeshTasks
+                }
+            })
+        };
+
+        for (&inst, inst_def) in insts.iter().zip(&serialized_insts) {
+            data_insts[inst].inputs = values(&inst_def.inputs)?;
+        }
+        for (&node, node_def) in nodes.iter().zip(&serialized_nodes) {
+            let kind = match &node_def.kind {
+                SerializedControlNodeKind::Block {
+                    insts: serialized_block_insts,
+                } => {
+                    let mut block_insts = EntityList::empty();
+                    for &idx in serialized_block_insts {
+                        block_insts.insert_last(inst(idx)?, &mut data_insts);
+                    }
+                    ControlNodeKind::Block { insts: block_insts }
+                }
+                SerializedControlNodeKind::Select {
+                    kind,
+                    scrutinee,
+                    cases,
+                } => ControlNodeKind::Select {
+                    kind: selection_kind(kind)?,
+                    scrutinee: value(scrutinee)?,
+                    cases: cases
+                        .iter()
+                        .map(|&case| region(case))
+                        .collect::<Result<_, _>>()?,
+                },
+                SerializedControlNodeKind::Loop {
+                    initial_inputs,
+                    body,
+                    repeat_condition,
+                } => ControlNodeKind::Loop {
+                    initial_inputs: values(initial_inputs)?,
+                    body: region(*body)?,
+                    repeat_condition: value(repeat_condition)?,
+                },
+                SerializedControlNodeKind::ExitInvocation { kind, inputs } => {
+                    ControlNodeKind::ExitInvocation {
+                        kind: exit_invocation_kind(kind)?,
+                        inputs: values(inputs)?,
+                    }
+                }
+            };
+            control_nodes[node].kind = kind;
+        }
+        for (&region, region_def) in regions.iter().zip(&serialized_regions) {
+            let mut children = EntityList::empty();
+            for &idx in &region_def.children {
+                children.insert_last(node(idx)?, &mut control_nodes);
+            }
+            let outputs = values(&region_def.outputs)?;
+
+            let region_def = &mut control_regions[region];
+            region_def.children = children;
+            region_def.outputs = outputs;
+        }
+
+        let unstructured_cfg = unstructured_cfg
+            .map(|control_insts| {
+                let mut cfg = cfg::ControlFlowGraph::default();
+                for (source, control_inst) in control_insts {
+                    let SerializedControlInst {
+                        attrs,
+                        kind,
+                        inputs,
+                        targets,
+                        target_inputs,
+                    } = control_inst;
+                    let kind = match kind {
+                        SerializedControlInstKind::Unreachable => cfg::ControlInstKind::Unreachable,
+                        SerializedControlInstKind::Return => cfg::ControlInstKind::Return,
+                        SerializedControlInstKind::ExitInvocation(kind) => {
+                            cfg::ControlInstKind::ExitInvocation(exit_invocation_kind(&kind)?)
+                        }
+                        SerializedControlInstKind::Branch => cfg::ControlInstKind::Branch,
+                        SerializedControlInstKind::SelectBranch(kind) => {
+                            cfg::ControlInstKind::SelectBranch(selection_kind(&kind)?)
+                        }
+                    };
+                    cfg.control_inst_on_exit_from.insert(
+                        region(source)?,
+                        cfg::ControlInst {
+                            attrs: self.attrs(attrs)?,
+                            kind,
+                            inputs: values(&inputs)?,
+                            targets: targets
+                                .iter()
+                                .map(|&target| region(target))
+                                .collect::<Result<_, _>>()?,
+                            target_inputs: target_inputs
+                                .iter()
+                                .map(|(target, inputs)| Ok((region(*target)?, values(inputs)?)))
+                                .collect::<Result<FxIndexMap<_, _>, String>>()?,
+                        },
+                    );
+                }
+                Ok::<_, String>(cfg)
+            })
+            .transpose()?;
+
+        Ok(FuncDefBody {
+            control_regions,
+            control_nodes,
+            data_insts,
+            body: region(body)?,
+            unstructured_cfg,
+        })
+    }
+}
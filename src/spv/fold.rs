@@ -0,0 +1,802 @@
+//! Folding of SPIR-V specialization constants (into ordinary constants), and
+//! evaluation of SPIR-V instructions on constant operands (see [`eval_spv_inst`]).
+
+use crate::spv::{self, spec};
+use crate::{AttrSet, Const, ConstCtor, ConstDef, Context, Type, TypeCtor, TypeCtorArg};
+use rustc_hash::FxHashMap;
+use smallvec::SmallVec;
+use std::iter;
+
+/// Values to use for specialization constants (i.e. [`ConstCtor::SpecConst`]s),
+/// keyed by their `SpecId`, instead of their default values.
+#[derive(Clone, Default)]
+pub struct SpecConstValues {
+    /// Raw bits of the value for each `SpecId`, zero-extended to 64 bits
+    /// (with `false`/`true` as `0`/`1`, and floats as their bit patterns).
+    pub by_spec_id: FxHashMap<u32, u64>,
+}
+
+/// Folder of constants which depend on specialization constants, replacing
+/// [`ConstCtor::SpecConst`]s with ordinary constants (of either the value from
+/// [`SpecConstValues`], or the default value), and evaluating the operations
+/// of [`ConstCtor::SpecConstOp`]s (where supported) on the resulting constants.
+///
+/// Results are cached, so the same [`SpecConstFolder`] should be reused for
+/// all the constants (e.g. of a whole module) that need folding.
+pub struct SpecConstFolder<'a> {
+    cx: &'a Context,
+    values: &'a SpecConstValues,
+
+    folded: FxHashMap<Const, Const>,
+}
+
+/// Scalar type, of the kinds supported by [`SpecConstFolder`].
+#[derive(Copy, Clone)]
+enum ScalarType {
+    Bool,
+    Int { width: u32 },
+    Float { width: u32 },
+}
+
+impl ScalarType {
+    fn from_type(cx: &Context, ty: Type) -> Option<Self> {
+        let wk = &spec::Spec::get().well_known;
+
+        let (opcode, imms) = match &cx[ty].ctor {
+            TypeCtor::SpvInst(spv::Inst { opcode, imms }) => (*opcode, imms),
+            TypeCtor::SpvStringLiteralForExtInst
+            | TypeCtor::Matrix { .. }
+            | TypeCtor::Array
+            | TypeCtor::RuntimeArray
+            | TypeCtor::Struct { .. }
+            | TypeCtor::RecursivePtr { .. }
+            | TypeCtor::RecursivePtrSelf { .. }
+            | TypeCtor::QPtr
+            | TypeCtor::Image(_)
+            | TypeCtor::Sampler
+            | TypeCtor::SampledImage => return None,
+        };
+        let scalar_type = if opcode == wk.OpTypeBool {
+            Self::Bool
+        } else if opcode == wk.OpTypeInt {
+            match imms[..] {
+                [spv::Imm::Short(_, width), _] => Self::Int { width },
+                _ => return None,
+            }
+        } else if opcode == wk.OpTypeFloat {
+            match imms[..] {
+                [spv::Imm::Short(_, width), ..] => Self::Float { width },
+                _ => return None,
+            }
+        } else {
+            return None;
+        };
+        // HACK(eddyb) only values which fit in `u64` are supported, for now.
+        match scalar_type {
+            Self::Int { width } | Self::Float { width } if !(1..=64).contains(&width) => None,
+            _ => Some(scalar_type),
+        }
+    }
+
+    fn width(self) -> u32 {
+        match self {
+            Self::Bool => 1,
+            Self::Int { width } | Self::Float { width } => width,
+        }
+    }
+
+    /// Truncate `bits` to the width of this type (i.e. zero all other bits).
+    fn truncate(self, bits: u64) -> u64 {
+        bits & (u64::MAX >> (64 - self.width()))
+    }
+
+    /// Sign-extend `bits` (assumed to already be truncated to this type).
+    fn sign_extend(self, bits: u64) -> i64 {
+        let shift = 64 - self.width();
+        ((bits << shift) as i64) >> shift
+    }
+
+    /// Convert `bits` to `f64`, if this is a floating-point type (of a width
+    /// that Rust supports, i.e. 32-bit or 64-bit).
+    fn bits_to_f64(self, bits: u64) -> Option<f64> {
+        match self {
+            Self::Float { width: 32 } => Some(f32::from_bits(bits as u32).into()),
+            Self::Float { width: 64 } => Some(f64::from_bits(bits)),
+            _ => None,
+        }
+    }
+
+    /// Convert `x` to the raw bits of this type, if it's a floating-point type
+    /// (of a width that Rust supports, i.e. 32-bit or 64-bit), rounding if needed.
+    fn f64_to_bits(self, x: f64) -> Option<u64> {
+        match self {
+            Self::Float { width: 32 } => Some(u64::from((x as f32).to_bits())),
+            Self::Float { width: 64 } => Some(x.to_bits()),
+            _ => None,
+        }
+    }
+}
+
+impl<'a> SpecConstFolder<'a> {
+    pub fn new(cx: &'a Context, values: &'a SpecConstValues) -> Self {
+        Self {
+            cx,
+            values,
+            folded: FxHashMap::default(),
+        }
+    }
+
+    /// Fold `ct` (and, recursively, all the constants it's constructed from),
+    /// returning an equivalent constant that depends on as few specialization
+    /// constants as possible (ideally none, i.e. an ordinary constant).
+    ///
+    /// Any [`ConstCtor::SpecConstOp`]s which can't be evaluated (e.g. due to
+    /// unsupported operations or types, or undefined behavior such as division
+    /// by zero) are kept, but with their operands folded.
+    pub fn fold_const(&mut self, ct: Const) -> Const {
+        if let Some(&folded) = self.folded.get(&ct) {
+            return folded;
+        }
+
+        let wk = &spec::Spec::get().well_known;
+
+        let cx = self.cx;
+        let ct_def = &cx[ct];
+        let ctor_args: SmallVec<[_; 2]> = ct_def
+            .ctor_args
+            .iter()
+            .map(|&arg| self.fold_const(arg))
+            .collect();
+
+        let folded = match &ct_def.ctor {
+            &ConstCtor::SpecConst {
+                spec_id,
+                ref default,
+            } => match (
+                self.values.by_spec_id.get(&spec_id),
+                ScalarType::from_type(cx, ct_def.ty),
+            ) {
+                (Some(&bits), Some(scalar_type)) => {
+                    scalar_const(cx, ct_def.attrs, ct_def.ty, scalar_type, bits)
+                }
+                _ => cx.intern(ConstDef {
+                    attrs: ct_def.attrs,
+                    ty: ct_def.ty,
+                    ctor: ConstCtor::SpvInst(default.clone()),
+                    ctor_args: SmallVec::new(),
+                }),
+            },
+
+            ConstCtor::SpecConstOp(inst) => match eval_spv_inst(cx, inst, ct_def.ty, &ctor_args) {
+                Some(evaluated) => {
+                    let evaluated_def = &cx[evaluated];
+                    cx.intern(ConstDef {
+                        attrs: ct_def.attrs,
+                        ty: evaluated_def.ty,
+                        ctor: evaluated_def.ctor.clone(),
+                        ctor_args: evaluated_def.ctor_args.clone(),
+                    })
+                }
+                None => cx.intern(ConstDef {
+                    attrs: ct_def.attrs,
+                    ty: ct_def.ty,
+                    ctor: ct_def.ctor.clone(),
+                    ctor_args,
+                }),
+            },
+
+            ConstCtor::SpvInst(inst)
+                if inst.opcode == wk.OpSpecConstantComposite
+                    && !ctor_args
+                        .iter()
+                        .any(|&arg| self.depends_on_spec_consts(arg)) =>
+            {
+                cx.intern(ConstDef {
+                    attrs: ct_def.attrs,
+                    ty: ct_def.ty,
+                    ctor: ConstCtor::SpvInst(spv::Inst {
+                        opcode: wk.OpConstantComposite,
+                        imms: inst.imms.clone(),
+                    }),
+                    ctor_args,
+                })
+            }
+
+            _ if ctor_args == ct_def.ctor_args => ct,
+            _ => cx.intern(ConstDef {
+                attrs: ct_def.attrs,
+                ty: ct_def.ty,
+                ctor: ct_def.ctor.clone(),
+                ctor_args,
+            }),
+        };
+
+        self.folded.insert(ct, folded);
+        folded
+    }
+
+    /// Returns `true` if `ct` (assumed to be already folded) still depends on
+    /// any specialization constants.
+    fn depends_on_spec_consts(&self, ct: Const) -> bool {
+        let wk = &spec::Spec::get().well_known;
+
+        // NOTE(eddyb) there's no need to recurse into `ctor_args`, as folding
+        // would've already turned e.g. `OpSpecConstantComposite` into
+        // `OpConstantComposite`, if none of its operands were spec constants.
+        match &self.cx[ct].ctor {
+            ConstCtor::SpecConst { .. } | ConstCtor::SpecConstOp(_) => true,
+            ConstCtor::SpvInst(inst) => inst.opcode == wk.OpSpecConstantComposite,
+            ConstCtor::PtrToGlobalVar(_)
+            | ConstCtor::Undef
+            | ConstCtor::SpvExtInst { .. }
+            | ConstCtor::SpvStringLiteralForExtInst(_)
+            | ConstCtor::SpvTypeOperand => false,
+        }
+    }
+}
+
+/// Evaluate the SPIR-V instruction `inst` (with a result of type `ty`) on the
+/// constant operands `args`, returning the constant result, if possible.
+///
+/// Supported are scalar integer/floating-point arithmetic, bitwise and logical
+/// operations, comparisons and conversions, and their component-wise vector
+/// counterparts, as well as composite construction/extraction and vector shuffles.
+///
+/// Only ordinary constants (i.e. not [`ConstCtor::SpecConst`] or [`ConstCtor::Undef`],
+/// or anything else depending on them) can be evaluated, and any operations with
+/// undefined behavior (e.g. division by zero, or shifting by the bit-width of
+/// the result, or more) are never evaluated.
+pub fn eval_spv_inst(cx: &Context, inst: &spv::Inst, ty: Type, args: &[Const]) -> Option<Const> {
+    let wk = &spec::Spec::get().well_known;
+
+    let opcode = inst.opcode;
+
+    if !args.iter().all(|&arg| is_ordinary_const(cx, arg)) {
+        return None;
+    }
+
+    if opcode == wk.OpCompositeConstruct {
+        // NOTE(eddyb) vectors can also be constructed out of smaller vectors.
+        let elements = if vector_type(cx, ty).is_some() {
+            let mut elements = SmallVec::new();
+            for &arg in args {
+                match vector_type(cx, cx[arg].ty) {
+                    Some(_) => elements.extend(composite_elements(cx, arg)?),
+                    None => elements.push(arg),
+                }
+            }
+            elements
+        } else {
+            args.iter().copied().collect()
+        };
+        return Some(composite_const(cx, ty, elements));
+    }
+
+    if opcode == wk.OpCompositeExtract {
+        let mut ct = match args {
+            &[composite] => composite,
+            _ => return None,
+        };
+        for &imm in &inst.imms {
+            let idx = match imm {
+                spv::Imm::Short(_, idx) => usize::try_from(idx).ok()?,
+                spv::Imm::LongStart(..) | spv::Imm::LongCont(..) => return None,
+            };
+            ct = *composite_elements(cx, ct)?.get(idx)?;
+        }
+        return (cx[ct].ty == ty).then_some(ct);
+    }
+
+    if opcode == wk.OpVectorShuffle {
+        let elements: SmallVec<[_; 8]> = match args {
+            &[a, b] => composite_elements(cx, a)?
+                .into_iter()
+                .chain(composite_elements(cx, b)?)
+                .collect(),
+            _ => return None,
+        };
+        let shuffled = inst
+            .imms
+            .iter()
+            .map(|&imm| match imm {
+                // NOTE(eddyb) `0xFFFFFFFF` indicates an undefined component.
+                spv::Imm::Short(_, idx) if idx != u32::MAX => {
+                    elements.get(usize::try_from(idx).ok()?).copied()
+                }
+                _ => None,
+            })
+            .collect::<Option<_>>()?;
+        return Some(composite_const(cx, ty, shuffled));
+    }
+
+    // Component-wise vector operations (with any scalar operands, e.g. the
+    // condition of an `OpSelect`, being used for all of the components).
+    if let Some((elem_type, count)) = vector_type(cx, ty) {
+        let args_elements = args
+            .iter()
+            .map(|&arg| match vector_type(cx, cx[arg].ty) {
+                Some(_) => composite_elements(cx, arg),
+                None => Some(iter::repeat_n(arg, count as usize).collect()),
+            })
+            .collect::<Option<SmallVec<[_; 3]>>>()?;
+        let elements = (0..count as usize)
+            .map(|i| {
+                let elem_args = args_elements
+                    .iter()
+                    .map(|arg_elements| arg_elements.get(i).copied())
+                    .collect::<Option<SmallVec<[_; 3]>>>()?;
+                eval_spv_inst(cx, inst, elem_type, &elem_args)
+            })
+            .collect::<Option<_>>()?;
+        return Some(composite_const(cx, ty, elements));
+    }
+
+    let result_type = ScalarType::from_type(cx, ty)?;
+    let args = args
+        .iter()
+        .map(|&arg| scalar_value(cx, arg))
+        .collect::<Option<SmallVec<[_; 3]>>>()?;
+    let bits = eval_scalar_op(opcode, result_type, &args)?;
+    Some(scalar_const(cx, AttrSet::default(), ty, result_type, bits))
+}
+
+/// Value of an ordinary scalar constant (see [`const_splat_value`]).
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum ScalarValue {
+    Bool(bool),
+
+    /// Integer of `width` bits, as its raw bits (zero-extended to 64 bits).
+    Int {
+        width: u32,
+        bits: u64,
+    },
+
+    Float(f64),
+}
+
+/// Get the value of `ct`, if it's an ordinary scalar constant, or a vector
+/// constant with all of its components equal (i.e. a "splat") to one.
+pub fn const_splat_value(cx: &Context, ct: Const) -> Option<ScalarValue> {
+    let scalar = match vector_type(cx, cx[ct].ty) {
+        Some(_) => {
+            let elements = composite_elements(cx, ct)?;
+            let first = *elements.first()?;
+            if !elements.iter().all(|&elem| elem == first) {
+                return None;
+            }
+            first
+        }
+        None => ct,
+    };
+    let (scalar_type, bits) = scalar_value(cx, scalar)?;
+    Some(match scalar_type {
+        ScalarType::Bool => ScalarValue::Bool(bits != 0),
+        ScalarType::Int { width } => ScalarValue::Int { width, bits },
+        ScalarType::Float { .. } => ScalarValue::Float(scalar_type.bits_to_f64(bits)?),
+    })
+}
+
+/// Intern an ordinary constant of type `ty`, with the value `value`, for all of
+/// its components if `ty` is a vector type (i.e. the inverse of [`const_splat_value`]).
+///
+/// Returns `None` if `ty` isn't a scalar (or vector) type of the same kind as
+/// `value` (e.g. `ScalarValue::Int` with a floating-point `ty`).
+pub fn splat_const(cx: &Context, ty: Type, value: ScalarValue) -> Option<Const> {
+    if let Some((elem_type, count)) = vector_type(cx, ty) {
+        let elem = splat_const(cx, elem_type, value)?;
+        return Some(composite_const(
+            cx,
+            ty,
+            iter::repeat_n(elem, count as usize).collect(),
+        ));
+    }
+    let scalar_type = ScalarType::from_type(cx, ty)?;
+    let bits = match (scalar_type, value) {
+        (ScalarType::Bool, ScalarValue::Bool(b)) => b.into(),
+        (ScalarType::Int { width }, ScalarValue::Int { width: w, bits }) if w == width => bits,
+        (ScalarType::Float { .. }, ScalarValue::Float(x)) => scalar_type.f64_to_bits(x)?,
+        _ => return None,
+    };
+    Some(scalar_const(cx, AttrSet::default(), ty, scalar_type, bits))
+}
+
+/// Returns `true` if `ct` is an ordinary constant, i.e. one that doesn't depend
+/// on specialization constants, and is fully defined (i.e. not `undef`).
+pub(crate) fn is_ordinary_const(cx: &Context, ct: Const) -> bool {
+    let wk = &spec::Spec::get().well_known;
+
+    let ct_def = &cx[ct];
+    match &ct_def.ctor {
+        ConstCtor::SpvInst(inst) => {
+            [
+                wk.OpConstantFalse,
+                wk.OpConstantTrue,
+                wk.OpConstant,
+                wk.OpConstantNull,
+            ]
+            .contains(&inst.opcode)
+                || inst.opcode == wk.OpConstantComposite
+                    && ct_def
+                        .ctor_args
+                        .iter()
+                        .all(|&arg| is_ordinary_const(cx, arg))
+        }
+        ConstCtor::PtrToGlobalVar(_)
+        | ConstCtor::SpecConst { .. }
+        | ConstCtor::SpecConstOp(_)
+        | ConstCtor::Undef
+        | ConstCtor::SpvExtInst { .. }
+        | ConstCtor::SpvStringLiteralForExtInst(_)
+        | ConstCtor::SpvTypeOperand => false,
+    }
+}
+
+/// Get the component type and count of `ty`, if it's a SPIR-V vector type.
+pub(crate) fn vector_type(cx: &Context, ty: Type) -> Option<(Type, u32)> {
+    let wk = &spec::Spec::get().well_known;
+
+    let ty_def = &cx[ty];
+    match (&ty_def.ctor, &ty_def.ctor_args[..]) {
+        (TypeCtor::SpvInst(inst), &[TypeCtorArg::Type(elem_type)])
+            if inst.opcode == wk.OpTypeVector =>
+        {
+            match inst.imms[..] {
+                [spv::Imm::Short(_, count)] => Some((elem_type, count)),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Get the elements of the composite constant `ct`, if it's either constructed
+/// from them (i.e. `OpConstantComposite`), or a vector `OpConstantNull`.
+pub(crate) fn composite_elements(cx: &Context, ct: Const) -> Option<SmallVec<[Const; 4]>> {
+    let wk = &spec::Spec::get().well_known;
+
+    let ct_def = &cx[ct];
+    match &ct_def.ctor {
+        ConstCtor::SpvInst(inst) if inst.opcode == wk.OpConstantComposite => {
+            Some(ct_def.ctor_args.iter().copied().collect())
+        }
+        ConstCtor::SpvInst(inst) if inst.opcode == wk.OpConstantNull => {
+            let (elem_type, count) = vector_type(cx, ct_def.ty)?;
+            let elem_null = cx.intern(ConstDef {
+                attrs: AttrSet::default(),
+                ty: elem_type,
+                ctor: ConstCtor::SpvInst(wk.OpConstantNull.into()),
+                ctor_args: SmallVec::new(),
+            });
+            Some(iter::repeat_n(elem_null, count as usize).collect())
+        }
+        _ => None,
+    }
+}
+
+/// Intern an `OpConstantComposite` of type `ty`, with the elements `elements`.
+pub(crate) fn composite_const(cx: &Context, ty: Type, elements: SmallVec<[Const; 4]>) -> Const {
+    let wk = &spec::Spec::get().well_known;
+
+    cx.intern(ConstDef {
+        attrs: AttrSet::default(),
+        ty,
+        ctor: ConstCtor::SpvInst(wk.OpConstantComposite.into()),
+        ctor_args: elements.into_iter().collect(),
+    })
+}
+
+/// Get the raw bits of `ct`, if it's an ordinary scalar constant.
+fn scalar_value(cx: &Context, ct: Const) -> Option<(ScalarType, u64)> {
+    let wk = &spec::Spec::get().well_known;
+
+    let ct_def = &cx[ct];
+    let scalar_type = ScalarType::from_type(cx, ct_def.ty)?;
+    let inst = match &ct_def.ctor {
+        ConstCtor::SpvInst(inst) => inst,
+        _ => return None,
+    };
+    let bits = if inst.opcode == wk.OpConstantFalse || inst.opcode == wk.OpConstantNull {
+        0
+    } else if inst.opcode == wk.OpConstantTrue {
+        1
+    } else if inst.opcode == wk.OpConstant {
+        match inst.imms[..] {
+            [spv::Imm::Short(_, x)] => u64::from(x),
+            [spv::Imm::LongStart(_, lo), spv::Imm::LongCont(_, hi)] => {
+                u64::from(lo) | (u64::from(hi) << 32)
+            }
+            _ => return None,
+        }
+    } else {
+        return None;
+    };
+    Some((scalar_type, scalar_type.truncate(bits)))
+}
+
+/// Intern an ordinary scalar constant of type `ty`, with the value `bits`.
+fn scalar_const(
+    cx: &Context,
+    attrs: AttrSet,
+    ty: Type,
+    scalar_type: ScalarType,
+    bits: u64,
+) -> Const {
+    let wk = &spec::Spec::get().well_known;
+
+    let bits = scalar_type.truncate(bits);
+    let inst = match scalar_type {
+        ScalarType::Bool => if bits != 0 {
+            wk.OpConstantTrue
+        } else {
+            wk.OpConstantFalse
+        }
+        .into(),
+        ScalarType::Int { width } | ScalarType::Float { width } => {
+            let imm_kind = wk.LiteralContextDependentNumber;
+            spv::Inst {
+                opcode: wk.OpConstant,
+                imms: if width <= 32 {
+                    [spv::Imm::Short(imm_kind, bits as u32)]
+                        .into_iter()
+                        .collect()
+                } else {
+                    [
+                        spv::Imm::LongStart(imm_kind, bits as u32),
+                        spv::Imm::LongCont(imm_kind, (bits >> 32) as u32),
+                    ]
+                    .into_iter()
+                    .collect()
+                },
+            }
+        }
+    };
+    cx.intern(ConstDef {
+        attrs,
+        ty,
+        ctor: ConstCtor::SpvInst(inst),
+        ctor_args: SmallVec::new(),
+    })
+}
+
+/// Evaluate the scalar operation `opcode` on the raw bits of its operands
+/// (`args`), returning the raw bits of the result (of type `result_type`).
+fn eval_scalar_op(
+    opcode: spec::Opcode,
+    result_type: ScalarType,
+    args: &[(ScalarType, u64)],
+) -> Option<u64> {
+    let wk = &spec::Spec::get().well_known;
+
+    let bits = match (result_type, args) {
+        (_, &[(a_type, a)]) if opcode == wk.OpBitcast => {
+            if a_type.width() != result_type.width() {
+                return None;
+            }
+            a
+        }
+
+        (ScalarType::Float { width }, &[(a_type, a)]) => {
+            if opcode == wk.OpFNegate {
+                result_type.f64_to_bits(-a_type.bits_to_f64(a)?)?
+            } else if opcode == wk.OpFConvert {
+                result_type.f64_to_bits(a_type.bits_to_f64(a)?)?
+            } else if opcode == wk.OpConvertSToF || opcode == wk.OpConvertUToF {
+                if !matches!(a_type, ScalarType::Int { .. }) {
+                    return None;
+                }
+                let signed = opcode == wk.OpConvertSToF;
+                let sa = a_type.sign_extend(a);
+
+                // NOTE(eddyb) converting directly to the result type avoids
+                // the double rounding that going through `f64` could cause.
+                match (width, signed) {
+                    (32, true) => u64::from((sa as f32).to_bits()),
+                    (32, false) => u64::from((a as f32).to_bits()),
+                    (64, true) => (sa as f64).to_bits(),
+                    (64, false) => (a as f64).to_bits(),
+                    _ => return None,
+                }
+            } else {
+                return None;
+            }
+        }
+
+        (ScalarType::Int { width }, &[(a_type @ ScalarType::Float { .. }, a)]) => {
+            // NOTE(eddyb) converting values which don't fit in the result
+            // type is undefined (this also applies to NaNs and infinities).
+            let a = a_type.bits_to_f64(a)?.trunc();
+            if opcode == wk.OpConvertFToS {
+                let max = 2f64.powi(width as i32 - 1);
+                if !(-max..max).contains(&a) {
+                    return None;
+                }
+                a as i64 as u64
+            } else if opcode == wk.OpConvertFToU {
+                if !(0.0..2f64.powi(width as i32)).contains(&a) {
+                    return None;
+                }
+                a as u64
+            } else {
+                return None;
+            }
+        }
+
+        (ScalarType::Int { .. }, &[(a_type, a)]) => {
+            if opcode == wk.OpSConvert {
+                a_type.sign_extend(a) as u64
+            } else if opcode == wk.OpUConvert {
+                a
+            } else if opcode == wk.OpSNegate {
+                a.wrapping_neg()
+            } else if opcode == wk.OpNot {
+                !a
+            } else {
+                return None;
+            }
+        }
+        (ScalarType::Bool, &[(_, a)]) if opcode == wk.OpLogicalNot => (a == 0).into(),
+
+        (_, &[(cond_type, cond), (_, a), (_, b)]) if opcode == wk.OpSelect => {
+            if !matches!(cond_type, ScalarType::Bool) {
+                return None;
+            }
+            if cond != 0 { a } else { b }
+        }
+
+        (ScalarType::Bool, &[(a_type @ ScalarType::Float { .. }, a), (b_type, b)]) => {
+            let (a, b) = (a_type.bits_to_f64(a)?, b_type.bits_to_f64(b)?);
+            let unordered = a.is_nan() || b.is_nan();
+            let (ordered_cmp, unordered_result) = if opcode == wk.OpFOrdEqual {
+                (a == b, false)
+            } else if opcode == wk.OpFUnordEqual {
+                (a == b, true)
+            } else if opcode == wk.OpFOrdNotEqual {
+                (a != b, false)
+            } else if opcode == wk.OpFUnordNotEqual {
+                (a != b, true)
+            } else if opcode == wk.OpFOrdLessThan {
+                (a < b, false)
+            } else if opcode == wk.OpFUnordLessThan {
+                (a < b, true)
+            } else if opcode == wk.OpFOrdGreaterThan {
+                (a > b, false)
+            } else if opcode == wk.OpFUnordGreaterThan {
+                (a > b, true)
+            } else if opcode == wk.OpFOrdLessThanEqual {
+                (a <= b, false)
+            } else if opcode == wk.OpFUnordLessThanEqual {
+                (a <= b, true)
+            } else if opcode == wk.OpFOrdGreaterThanEqual {
+                (a >= b, false)
+            } else if opcode == wk.OpFUnordGreaterThanEqual {
+                (a >= b, true)
+            } else {
+                return None;
+            };
+            (if unordered {
+                unordered_result
+            } else {
+                ordered_cmp
+            })
+            .into()
+        }
+
+        (ScalarType::Bool, &[(a_type, a), (b_type, b)]) => {
+            let (sa, sb) = (a_type.sign_extend(a), b_type.sign_extend(b));
+            if opcode == wk.OpLogicalEqual || opcode == wk.OpIEqual {
+                a == b
+            } else if opcode == wk.OpLogicalNotEqual || opcode == wk.OpINotEqual {
+                a != b
+            } else if opcode == wk.OpLogicalOr {
+                a != 0 || b != 0
+            } else if opcode == wk.OpLogicalAnd {
+                a != 0 && b != 0
+            } else if opcode == wk.OpUGreaterThan {
+                a > b
+            } else if opcode == wk.OpSGreaterThan {
+                sa > sb
+            } else if opcode == wk.OpUGreaterThanEqual {
+                a >= b
+            } else if opcode == wk.OpSGreaterThanEqual {
+                sa >= sb
+            } else if opcode == wk.OpULessThan {
+                a < b
+            } else if opcode == wk.OpSLessThan {
+                sa < sb
+            } else if opcode == wk.OpULessThanEqual {
+                a <= b
+            } else if opcode == wk.OpSLessThanEqual {
+                sa <= sb
+            } else {
+                return None;
+            }
+            .into()
+        }
+
+        (ScalarType::Float { .. }, &[(a_type, a), (b_type, b)]) => {
+            let (a, b) = (a_type.bits_to_f64(a)?, b_type.bits_to_f64(b)?);
+
+            // NOTE(eddyb) computing 32-bit results as `f64` first (and only
+            // then rounding them) is exact for these operations.
+            let result = if opcode == wk.OpFAdd {
+                a + b
+            } else if opcode == wk.OpFSub {
+                a - b
+            } else if opcode == wk.OpFMul {
+                a * b
+            } else if opcode == wk.OpFDiv {
+                a / b
+            } else if opcode == wk.OpFRem {
+                a % b
+            } else if opcode == wk.OpFMod {
+                // NOTE(eddyb) unlike `OpFRem`, the sign of the result
+                // matches the sign of the divisor (`b`), not the dividend.
+                let r = a % b;
+                if r != 0.0 && (r < 0.0) != (b < 0.0) {
+                    r + b
+                } else {
+                    r
+                }
+            } else {
+                return None;
+            };
+            result_type.f64_to_bits(result)?
+        }
+
+        (ScalarType::Int { width }, &[(a_type, a), (b_type, b)]) => {
+            let (sa, sb) = (a_type.sign_extend(a), b_type.sign_extend(b));
+            if opcode == wk.OpIAdd {
+                a.wrapping_add(b)
+            } else if opcode == wk.OpISub {
+                a.wrapping_sub(b)
+            } else if opcode == wk.OpIMul {
+                a.wrapping_mul(b)
+            } else if opcode == wk.OpUDiv {
+                a.checked_div(b)?
+            } else if opcode == wk.OpSDiv {
+                sa.checked_div(sb)? as u64
+            } else if opcode == wk.OpUMod {
+                a.checked_rem(b)?
+            } else if opcode == wk.OpSRem {
+                sa.checked_rem(sb)? as u64
+            } else if opcode == wk.OpSMod {
+                // NOTE(eddyb) unlike `OpSRem`, the sign of the result
+                // matches the sign of the divisor (`b`), not the dividend.
+                let r = sa.checked_rem(sb)?;
+                (if r != 0 && (r < 0) != (sb < 0) {
+                    r + sb
+                } else {
+                    r
+                }) as u64
+            } else if opcode == wk.OpShiftRightLogical
+                || opcode == wk.OpShiftRightArithmetic
+                || opcode == wk.OpShiftLeftLogical
+            {
+                // NOTE(eddyb) shifting by the bit-width (or more) of the
+                // result is undefined, so it can't be folded.
+                if b >= u64::from(width) {
+                    return None;
+                }
+                if opcode == wk.OpShiftRightLogical {
+                    a >> b
+                } else if opcode == wk.OpShiftRightArithmetic {
+                    (sa >> b) as u64
+                } else {
+                    a << b
+                }
+            } else if opcode == wk.OpBitwiseOr {
+                a | b
+            } else if opcode == wk.OpBitwiseXor {
+                a ^ b
+            } else if opcode == wk.OpBitwiseAnd {
+                a & b
+            } else {
+                return None;
+            }
+        }
+
+        _ => return None,
+    };
+
+    Some(result_type.truncate(bits))
+}
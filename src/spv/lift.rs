@@ -4,16 +4,16 @@ use crate::func_at::FuncAt;
 use crate::spv::{self, spec};
 use crate::visit::{InnerVisit, Visitor};
 use crate::{
-    cfg, AddrSpace, Attr, AttrSet, Const, ConstCtor, ConstDef, Context, ControlNode,
+    cfg, AtomicOp, Attr, AttrSet, Const, ConstCtor, ConstDef, Context, ControlNode,
     ControlNodeKind, ControlNodeOutputDecl, ControlRegion, ControlRegionInputDecl, DataInst,
     DataInstDef, DataInstKind, DeclDef, EntityList, ExportKey, Exportee, Func, FuncDecl, FuncParam,
-    FxIndexMap, FxIndexSet, GlobalVar, GlobalVarDefBody, Import, Module, ModuleDebugInfo,
-    ModuleDialect, SelectionKind, Type, TypeCtor, TypeCtorArg, TypeDef, Value,
+    FxIndexMap, FxIndexSet, GlobalVar, GlobalVarDefBody, ImageType, Import, Module,
+    ModuleDebugInfo, ModuleDialect, SelectionKind, Type, TypeCtor, TypeCtorArg, TypeDef, Value,
 };
-use rustc_hash::FxHashMap;
+use rustc_hash::{FxHashMap, FxHashSet};
 use smallvec::SmallVec;
 use std::borrow::Cow;
-use std::collections::{BTreeMap, BTreeSet};
+use std::collections::{hash_map, BTreeMap, BTreeSet};
 use std::num::NonZeroU32;
 use std::path::Path;
 use std::{io, iter, mem, slice};
@@ -93,6 +93,94 @@ impl FuncDecl {
     }
 }
 
+// NOTE(eddyb) `Attr::SpvShaderDebugScope`/`Attr::SpvShaderDebugLine` are lifted
+// to `OpExtInst`s, which need a `void` result type, and (for `DebugLine`) have
+// their line/column operands as `uint` (i.e. 32-bit unsigned integer) constants.
+fn shader_debuginfo_void_type(cx: &Context) -> Type {
+    let wk = &spec::Spec::get().well_known;
+
+    cx.intern(TypeDef {
+        attrs: AttrSet::default(),
+        ctor: TypeCtor::SpvInst(wk.OpTypeVoid.into()),
+        ctor_args: [].into_iter().collect(),
+    })
+}
+
+fn u32_const(cx: &Context, x: u32) -> Const {
+    let wk = &spec::Spec::get().well_known;
+
+    let u32_type = cx.intern(TypeDef {
+        attrs: AttrSet::default(),
+        ctor: TypeCtor::SpvInst(spv::Inst {
+            opcode: wk.OpTypeInt,
+            imms: [
+                spv::Imm::Short(wk.LiteralInteger, 32),
+                spv::Imm::Short(wk.LiteralInteger, 0),
+            ]
+            .into_iter()
+            .collect(),
+        }),
+        ctor_args: [].into_iter().collect(),
+    });
+    cx.intern(ConstDef {
+        attrs: AttrSet::default(),
+        ty: u32_type,
+        ctor: ConstCtor::SpvInst(spv::Inst {
+            opcode: wk.OpConstant,
+            imms: [spv::Imm::Short(wk.LiteralContextDependentNumber, x)]
+                .into_iter()
+                .collect(),
+        }),
+        ctor_args: [].into_iter().collect(),
+    })
+}
+
+/// Get the scope and semantics operands SPIR-V requires (as 32-bit integer
+/// constants) for atomics, barriers and group operations, which SPIR-T keeps
+/// in `DataInstKind`, alongside the position they have to be inserted at, in
+/// the SPIR-V operands.
+fn decoded_u32_const_operands(kind: &DataInstKind) -> (usize, SmallVec<[u32; 3]>) {
+    match *kind {
+        // NOTE(eddyb) atomics' scope and semantics come after the pointer.
+        DataInstKind::Atomic {
+            op,
+            scope,
+            semantics,
+        } => {
+            let unequal_semantics = match op {
+                AtomicOp::CompareExchange {
+                    unequal_semantics, ..
+                } => Some(unequal_semantics),
+                _ => None,
+            };
+            (
+                1,
+                [scope, semantics]
+                    .into_iter()
+                    .chain(unequal_semantics)
+                    .collect(),
+            )
+        }
+        DataInstKind::Barrier {
+            execution_scope,
+            memory_scope,
+            semantics,
+        } => (
+            0,
+            execution_scope
+                .into_iter()
+                .chain([memory_scope, semantics])
+                .collect(),
+        ),
+        DataInstKind::Group { scope, .. } => (0, [scope].into_iter().collect()),
+        DataInstKind::FuncCall(_)
+        | DataInstKind::SpvInst(_)
+        | DataInstKind::SpvExtInst { .. }
+        | DataInstKind::SpvGlslStd450(_)
+        | DataInstKind::QPtr(_) => (0, SmallVec::new()),
+    }
+}
+
 struct NeedsIdsCollector<'a> {
     cx: &'a Context,
     module: &'a Module,
@@ -109,6 +197,11 @@ struct NeedsIdsCollector<'a> {
 enum Global {
     Type(Type),
     Const(Const),
+
+    /// `OpTypeForwardPointer` for a [`TypeCtor::RecursivePtr`] type, which
+    /// has to precede (the definitions of) any types using the pointer type,
+    /// including its own (unrolled) pointee type, and shares its ID.
+    ForwardPtrDecl(Type),
 }
 
 impl Visitor<'_> for NeedsIdsCollector<'_> {
@@ -122,13 +215,43 @@ impl Visitor<'_> for NeedsIdsCollector<'_> {
         }
         let ty_def = &self.cx[ty];
         match ty_def.ctor {
-            TypeCtor::SpvInst(_) => {}
+            TypeCtor::SpvInst(_)
+            | TypeCtor::Matrix { .. }
+            | TypeCtor::Array
+            | TypeCtor::RuntimeArray
+            | TypeCtor::Struct { .. }
+            | TypeCtor::Image(_)
+            | TypeCtor::Sampler
+            | TypeCtor::SampledImage => {}
+
+            // NOTE(eddyb) the forward declaration is inserted first, to
+            // both precede any uses, and to prevent infinite recursion.
+            TypeCtor::RecursivePtr { .. } => {
+                if self.globals.insert(Global::ForwardPtrDecl(ty)) {
+                    self.visit_attr_set_use(ty_def.attrs);
+                    self.visit_type_use(ty.pointee_type(self.cx).unwrap());
+                    self.globals.insert(global);
+                }
+                return;
+            }
+            TypeCtor::RecursivePtrSelf { .. } => {
+                unreachable!(
+                    "`TypeCtor::RecursivePtrSelf` should not be used \
+                     outside of the pointee type of `TypeCtor::RecursivePtr`"
+                );
+            }
             TypeCtor::SpvStringLiteralForExtInst => {
                 unreachable!(
                     "`TypeCtor::SpvStringLiteralForExtInst` should not be used \
                      as a type outside of `ConstCtor::SpvStringLiteralForExtInst`"
                 );
             }
+            TypeCtor::QPtr => {
+                unreachable!(
+                    "`TypeCtor::QPtr` should be replaced with SPIR-V pointer types \
+                     (see `qptr::lift`) before lifting to SPIR-V"
+                );
+            }
         }
         self.visit_type_def(ty_def);
         self.globals.insert(global);
@@ -140,10 +263,34 @@ impl Visitor<'_> for NeedsIdsCollector<'_> {
         }
         let ct_def = &self.cx[ct];
         match ct_def.ctor {
-            ConstCtor::PtrToGlobalVar(_) | ConstCtor::SpvInst(_) => {
+            ConstCtor::PtrToGlobalVar(_)
+            | ConstCtor::Undef
+            | ConstCtor::SpvInst(_)
+            | ConstCtor::SpecConst { .. }
+            | ConstCtor::SpecConstOp(_) => {
                 self.visit_const_def(ct_def);
                 self.globals.insert(global);
             }
+            ConstCtor::SpvExtInst { ext_set, .. } => {
+                self.ext_inst_imports.insert(&self.cx[ext_set]);
+                self.visit_const_def(ct_def);
+                self.globals.insert(global);
+            }
+
+            // NOTE(eddyb) this only needs its type, without an entry in `self.globals`.
+            ConstCtor::SpvTypeOperand => {
+                let ConstDef {
+                    attrs,
+                    ty,
+                    ctor: _,
+                    ctor_args,
+                } = ct_def;
+
+                assert!(*attrs == AttrSet::default());
+                assert!(ctor_args.is_empty());
+
+                self.visit_type_use(*ty);
+            }
 
             // HACK(eddyb) because this is an `OpString` and needs to go earlier
             // in the module than any `OpConstant*`, it needs to be special-cased,
@@ -201,11 +348,35 @@ impl Visitor<'_> for NeedsIdsCollector<'_> {
     }
     fn visit_attr(&mut self, attr: &Attr) {
         match *attr {
-            Attr::SpvAnnotation { .. } | Attr::SpvBitflagsOperand(_) => {}
+            Attr::SpvAnnotation { .. }
+            | Attr::SpvExecutionModeId { .. }
+            | Attr::SpvBitflagsOperand(_)
+            | Attr::SpvOriginalId(_)
+            | Attr::SpvUnsupported(_)
+            | Attr::QPtr(_)
+            | Attr::Diagnostic { .. } => {}
             Attr::SpvDebugLine { file_path, .. } => {
                 self.debug_strings.insert(&self.cx[file_path.0]);
             }
+            Attr::SpvShaderDebugScope { .. } | Attr::SpvShaderDebugLine { .. } => {
+                self.ext_inst_imports
+                    .insert(spv::shader_debuginfo::EXT_INST_SET_NAME);
+                self.visit_type_use(shader_debuginfo_void_type(self.cx));
+            }
+        }
+        if let Attr::SpvShaderDebugLine {
+            line_start,
+            line_end,
+            col_start,
+            col_end,
+            ..
+        } = *attr
+        {
+            for x in [line_start, line_end, col_start, col_end] {
+                self.visit_const_use(u32_const(self.cx, x));
+            }
         }
+        attr.inner_visit_with(self);
     }
 
     fn visit_data_inst_def(&mut self, data_inst_def: &DataInstDef) {
@@ -217,6 +388,24 @@ impl Visitor<'_> for NeedsIdsCollector<'_> {
             DataInstKind::SpvExtInst { ext_set, .. } => {
                 self.ext_inst_imports.insert(&self.cx[ext_set]);
             }
+            DataInstKind::SpvGlslStd450(_) => {
+                self.ext_inst_imports
+                    .insert(spv::glsl_std_450::EXT_INST_SET_NAME);
+            }
+            DataInstKind::Atomic { .. }
+            | DataInstKind::Barrier { .. }
+            | DataInstKind::Group { .. } => {
+                let (_, operands) = decoded_u32_const_operands(&data_inst_def.kind);
+                for x in operands {
+                    self.visit_const_use(u32_const(self.cx, x));
+                }
+            }
+            DataInstKind::QPtr(_) => {
+                unreachable!(
+                    "`DataInstKind::QPtr` should be replaced with SPIR-V instructions \
+                     (see `qptr::lift`) before lifting to SPIR-V"
+                );
+            }
         }
         data_inst_def.inner_visit_with(self);
     }
@@ -360,10 +549,23 @@ impl<'a> NeedsIdsCollector<'a> {
                 .into_iter()
                 .map(|s| Ok((s, alloc_id()?)))
                 .collect::<Result<_, _>>()?,
-            globals: globals
-                .into_iter()
-                .map(|g| Ok((g, alloc_id()?)))
-                .collect::<Result<_, _>>()?,
+            globals: {
+                let mut allocated = FxIndexMap::default();
+                for global in globals {
+                    // NOTE(eddyb) forward declarations share the ID of the type
+                    // they declare (which always comes after them).
+                    let id = match global {
+                        Global::Type(ty) => allocated.get(&Global::ForwardPtrDecl(ty)).copied(),
+                        _ => None,
+                    };
+                    let id = match id {
+                        Some(id) => id,
+                        None => alloc_id()?,
+                    };
+                    allocated.insert(global, id);
+                }
+                allocated
+            },
             funcs: funcs
                 .into_iter()
                 .map(|func| {
@@ -422,7 +624,9 @@ impl<'a, 'p> FuncAt<'a, CfgCursor<'p>> {
                     parent: cursor.parent,
                 }),
 
-                ControlNodeKind::Select { .. } | ControlNodeKind::Loop { .. } => None,
+                ControlNodeKind::Select { .. }
+                | ControlNodeKind::Loop { .. }
+                | ControlNodeKind::ExitInvocation { .. } => None,
             },
 
             // Exiting a `ControlNode` chains to a sibling/parent.
@@ -499,7 +703,7 @@ impl<'a> FuncAt<'a, ControlNode> {
         parent: &CfgCursor<'_, ControlParent>,
     ) -> Result<(), E> {
         let child_regions: &[_] = match &self.def().kind {
-            ControlNodeKind::Block { .. } => &[],
+            ControlNodeKind::Block { .. } | ControlNodeKind::ExitInvocation { .. } => &[],
             ControlNodeKind::Select { cases, .. } => cases,
             ControlNodeKind::Loop { body, .. } => slice::from_ref(body),
         };
@@ -657,6 +861,25 @@ impl<'a> FuncLifting<'a> {
 
             // Get the terminator, or reconstruct it from structured control-flow.
             let terminator = match (point, func_def_body.at(point_cursor).unique_successor()) {
+                // Exiting an `ExitInvocation` is impossible (and the resulting
+                // block, along with any others only reachable through it, will
+                // be removed later, as nothing can branch to it).
+                (CfgPoint::ControlNodeExit(control_node), _)
+                    if matches!(
+                        func_def_body.at(control_node).def().kind,
+                        ControlNodeKind::ExitInvocation { .. }
+                    ) =>
+                {
+                    Terminator {
+                        attrs: AttrSet::default(),
+                        kind: Cow::Owned(cfg::ControlInstKind::Unreachable),
+                        inputs: [].into_iter().collect(),
+                        targets: [].into_iter().collect(),
+                        target_phi_values: FxIndexMap::default(),
+                        merge: None,
+                    }
+                }
+
                 // Exiting a `ControlRegion` w/o a structured parent.
                 (CfgPoint::RegionExit(region), None) => {
                     let unstructured_terminator = func_def_body
@@ -702,7 +925,8 @@ impl<'a> FuncLifting<'a> {
                     }
                 }
 
-                // Entering a `ControlNode` with child `ControlRegion`s.
+                // Entering a `ControlNode` with child `ControlRegion`s
+                // (or an `ExitInvocation`, which never exits).
                 (CfgPoint::ControlNodeEntry(control_node), None) => {
                     let control_node_def = func_def_body.at(control_node).def();
                     match &control_node_def.kind {
@@ -710,12 +934,21 @@ impl<'a> FuncLifting<'a> {
                             unreachable!()
                         }
 
+                        ControlNodeKind::ExitInvocation { kind, inputs } => Terminator {
+                            attrs: control_node_def.attrs,
+                            kind: Cow::Owned(cfg::ControlInstKind::ExitInvocation(kind.clone())),
+                            inputs: inputs.clone(),
+                            targets: [].into_iter().collect(),
+                            target_phi_values: FxIndexMap::default(),
+                            merge: None,
+                        },
+
                         ControlNodeKind::Select {
                             kind,
                             scrutinee,
                             cases,
                         } => Terminator {
-                            attrs: AttrSet::default(),
+                            attrs: control_node_def.attrs,
                             kind: Cow::Owned(cfg::ControlInstKind::SelectBranch(kind.clone())),
                             inputs: [*scrutinee].into_iter().collect(),
                             targets: cases
@@ -731,7 +964,7 @@ impl<'a> FuncLifting<'a> {
                             body,
                             repeat_condition: _,
                         } => Terminator {
-                            attrs: AttrSet::default(),
+                            attrs: control_node_def.attrs,
                             kind: Cow::Owned(cfg::ControlInstKind::Branch),
                             inputs: [].into_iter().collect(),
                             targets: [CfgPoint::RegionEntry(*body)].into_iter().collect(),
@@ -763,7 +996,7 @@ impl<'a> FuncLifting<'a> {
                     };
 
                     match func_def_body.at(parent_node).def().kind {
-                        ControlNodeKind::Block { .. } => {
+                        ControlNodeKind::Block { .. } | ControlNodeKind::ExitInvocation { .. } => {
                             unreachable!()
                         }
 
@@ -970,8 +1203,9 @@ impl<'a> FuncLifting<'a> {
             }
         }
 
-        // Remove now-unused blocks.
-        blocks.retain(|point, _| use_counts[point] > 0);
+        // Remove now-unused blocks (including unreachable ones, which were never
+        // targeted in the first place, e.g. the exit of an `ExitInvocation`).
+        blocks.retain(|point, _| use_counts.get(point).is_some_and(|&count| count > 0));
 
         // Collect `OpPhi`s from other blocks' edges into each block.
         //
@@ -1063,7 +1297,12 @@ enum LazyInst<'a, 'b> {
         result_id: Option<spv::Id>,
         data_inst_def: &'a DataInstDef,
     },
-    Merge(Merge<spv::Id>),
+    Merge {
+        /// The attributes of the [`Terminator`] the merge instruction precedes
+        /// (which can contain `SelectionControl`/`LoopControl` operands).
+        attrs: AttrSet,
+        merge: Merge<spv::Id>,
+    },
     Terminator {
         parent_func: &'b FuncLifting<'a>,
         terminator: &'b Terminator<'a>,
@@ -1084,6 +1323,11 @@ impl LazyInst<'_, '_> {
             Self::Global(global) => {
                 let (attrs, import) = match global {
                     Global::Type(ty) => (cx[ty].attrs, None),
+
+                    // NOTE(eddyb) `OpTypeForwardPointer` has no result ID, and
+                    // all attributes belong to the `OpTypePointer` instead.
+                    Global::ForwardPtrDecl(_) => return (None, AttrSet::default(), None),
+
                     Global::Const(ct) => {
                         let ct_def = &cx[ct];
                         match ct_def.ctor {
@@ -1095,10 +1339,15 @@ impl LazyInst<'_, '_> {
                                 };
                                 (gv_decl.attrs, import)
                             }
-                            ConstCtor::SpvInst { .. } => (ct_def.attrs, None),
+                            ConstCtor::Undef
+                            | ConstCtor::SpvInst(_)
+                            | ConstCtor::SpecConst { .. }
+                            | ConstCtor::SpecConstOp(_)
+                            | ConstCtor::SpvExtInst { .. } => (ct_def.attrs, None),
 
                             // Not inserted into `globals` while visiting.
-                            ConstCtor::SpvStringLiteralForExtInst(_) => unreachable!(),
+                            ConstCtor::SpvStringLiteralForExtInst(_)
+                            | ConstCtor::SpvTypeOperand => unreachable!(),
                         }
                     }
                 };
@@ -1122,7 +1371,7 @@ impl LazyInst<'_, '_> {
                 result_id,
                 data_inst_def,
             } => (result_id, data_inst_def.attrs, None),
-            Self::Merge(_) => (None, AttrSet::default(), None),
+            Self::Merge { .. } => (None, AttrSet::default(), None),
             Self::Terminator {
                 parent_func: _,
                 terminator,
@@ -1142,6 +1391,7 @@ impl LazyInst<'_, '_> {
         let value_to_id = |parent_func: &FuncLifting<'_>, v| match v {
             Value::Const(ct) => match cx[ct].ctor {
                 ConstCtor::SpvStringLiteralForExtInst(s) => ids.debug_strings[&cx[s]],
+                ConstCtor::SpvTypeOperand => ids.globals[&Global::Type(cx[ct].ty)],
 
                 _ => ids.globals[&Global::Const(ct)],
             },
@@ -1169,30 +1419,133 @@ impl LazyInst<'_, '_> {
             Value::DataInstOutput(inst) => parent_func.data_inst_output_ids[&inst],
         };
 
+        // FIXME(eddyb) make this less of a search and more of a
+        // lookup by splitting attrs into key and value parts.
+        let merge_control_imms = |attrs: AttrSet, kind| {
+            cx[attrs]
+                .attrs
+                .iter()
+                .find_map(|attr| match attr {
+                    Attr::SpvBitflagsOperand(imms) => match imms[..] {
+                        [spv::Imm::Short(imm_kind, _), ..] if imm_kind == kind => {
+                            Some(imms.iter().copied().collect())
+                        }
+                        _ => None,
+                    },
+                    _ => None,
+                })
+                .unwrap_or_else(|| [spv::Imm::Short(kind, 0)].into_iter().collect())
+        };
+
         let (result_id, attrs, _) = self.result_id_attrs_and_import(module, ids);
         let inst = match self {
             Self::Global(global) => match global {
                 Global::Type(ty) => {
                     let ty_def = &cx[ty];
-                    match &ty_def.ctor {
-                        TypeCtor::SpvInst(inst) => spv::InstWithIds {
-                            without_ids: inst.clone(),
-                            result_type_id: None,
-                            result_id,
-                            ids: ty_def
-                                .ctor_args
-                                .iter()
-                                .map(|&arg| {
-                                    ids.globals[&match arg {
-                                        TypeCtorArg::Type(ty) => Global::Type(ty),
-                                        TypeCtorArg::Const(ct) => Global::Const(ct),
-                                    }]
-                                })
+                    let inst = match &ty_def.ctor {
+                        TypeCtor::SpvInst(inst) => inst.clone(),
+                        &TypeCtor::Matrix { column_count } => spv::Inst {
+                            opcode: wk.OpTypeMatrix,
+                            imms: [spv::Imm::Short(wk.LiteralInteger, column_count)]
+                                .into_iter()
+                                .collect(),
+                        },
+                        TypeCtor::Array => wk.OpTypeArray.into(),
+                        TypeCtor::RuntimeArray => wk.OpTypeRuntimeArray.into(),
+
+                        // NOTE(eddyb) member decorations are emitted separately
+                        // (alongside all other annotations).
+                        TypeCtor::Struct { .. } => wk.OpTypeStruct.into(),
+
+                        &TypeCtor::Image(ImageType {
+                            dim,
+                            depth,
+                            arrayed,
+                            multisampled,
+                            sampled,
+                            format,
+                            access_qualifier,
+                        }) => spv::Inst {
+                            opcode: wk.OpTypeImage,
+                            imms: [
+                                spv::Imm::Short(wk.Dim, dim),
+                                spv::Imm::Short(
+                                    wk.LiteralInteger,
+                                    depth.map_or(2, |depth| depth as u32),
+                                ),
+                                spv::Imm::Short(wk.LiteralInteger, arrayed as u32),
+                                spv::Imm::Short(wk.LiteralInteger, multisampled as u32),
+                                spv::Imm::Short(
+                                    wk.LiteralInteger,
+                                    match sampled {
+                                        None => 0,
+                                        Some(true) => 1,
+                                        Some(false) => 2,
+                                    },
+                                ),
+                                spv::Imm::Short(wk.ImageFormat, format),
+                            ]
+                            .into_iter()
+                            .chain(access_qualifier.map(|access_qualifier| {
+                                spv::Imm::Short(wk.AccessQualifier, access_qualifier)
+                            }))
+                            .collect(),
+                        },
+                        TypeCtor::Sampler => wk.OpTypeSampler.into(),
+                        TypeCtor::SampledImage => wk.OpTypeSampledImage.into(),
+
+                        // NOTE(eddyb) the pointee type is replaced below.
+                        &TypeCtor::RecursivePtr { storage_class } => spv::Inst {
+                            opcode: wk.OpTypePointer,
+                            imms: [spv::Imm::Short(wk.StorageClass, storage_class)]
+                                .into_iter()
                                 .collect(),
                         },
 
                         // Not inserted into `globals` while visiting.
-                        TypeCtor::SpvStringLiteralForExtInst => unreachable!(),
+                        TypeCtor::RecursivePtrSelf { .. }
+                        | TypeCtor::SpvStringLiteralForExtInst
+                        | TypeCtor::QPtr => unreachable!(),
+                    };
+                    let ids = match ty_def.ctor {
+                        TypeCtor::RecursivePtr { .. } => {
+                            [ids.globals[&Global::Type(ty.pointee_type(cx).unwrap())]]
+                                .into_iter()
+                                .collect()
+                        }
+                        _ => ty_def
+                            .ctor_args
+                            .iter()
+                            .map(|&arg| {
+                                ids.globals[&match arg {
+                                    TypeCtorArg::Type(ty) => Global::Type(ty),
+                                    TypeCtorArg::Const(ct) => Global::Const(ct),
+                                }]
+                            })
+                            .collect(),
+                    };
+                    spv::InstWithIds {
+                        without_ids: inst,
+                        result_type_id: None,
+                        result_id,
+                        ids,
+                    }
+                }
+                Global::ForwardPtrDecl(ty) => {
+                    let storage_class = match cx[ty].ctor {
+                        TypeCtor::RecursivePtr { storage_class } => storage_class,
+                        _ => unreachable!(),
+                    };
+                    spv::InstWithIds {
+                        without_ids: spv::Inst {
+                            opcode: wk.OpTypeForwardPointer,
+                            imms: [spv::Imm::Short(wk.StorageClass, storage_class)]
+                                .into_iter()
+                                .collect(),
+                        },
+                        result_type_id: None,
+                        result_id: None,
+                        ids: [ids.globals[&global]].into_iter().collect(),
                     }
                 }
                 Global::Const(ct) => {
@@ -1206,11 +1559,10 @@ impl LazyInst<'_, '_> {
 
                             assert!(ct_def.ty == gv_decl.type_of_ptr_to);
 
-                            let storage_class = match gv_decl.addr_space {
-                                AddrSpace::SpvStorageClass(sc) => {
-                                    spv::Imm::Short(wk.StorageClass, sc)
-                                }
-                            };
+                            let storage_class = spv::Imm::Short(
+                                wk.StorageClass,
+                                gv_decl.addr_space.to_spv_storage_class(),
+                            );
                             let initializer = match gv_decl.def {
                                 DeclDef::Imported(_) => None,
                                 DeclDef::Present(GlobalVarDefBody { initializer }) => initializer
@@ -1227,6 +1579,16 @@ impl LazyInst<'_, '_> {
                             }
                         }
 
+                        ConstCtor::Undef => {
+                            assert!(ct_def.ctor_args.is_empty());
+                            spv::InstWithIds {
+                                without_ids: wk.OpUndef.into(),
+                                result_type_id: Some(ids.globals[&Global::Type(ct_def.ty)]),
+                                result_id,
+                                ids: [].into_iter().collect(),
+                            }
+                        }
+
                         ConstCtor::SpvInst(inst) => spv::InstWithIds {
                             without_ids: inst.clone(),
                             result_type_id: Some(ids.globals[&Global::Type(ct_def.ty)]),
@@ -1238,8 +1600,77 @@ impl LazyInst<'_, '_> {
                                 .collect(),
                         },
 
+                        // NOTE(eddyb) the `SpecId` decoration is emitted
+                        // separately, alongside all the other decorations.
+                        ConstCtor::SpecConst {
+                            spec_id: _,
+                            default,
+                        } => {
+                            assert!(ct_def.ctor_args.is_empty());
+
+                            let opcode = if default.opcode == wk.OpConstantTrue {
+                                wk.OpSpecConstantTrue
+                            } else if default.opcode == wk.OpConstantFalse {
+                                wk.OpSpecConstantFalse
+                            } else if default.opcode == wk.OpConstant {
+                                wk.OpSpecConstant
+                            } else {
+                                unreachable!()
+                            };
+                            spv::InstWithIds {
+                                without_ids: spv::Inst {
+                                    opcode,
+                                    imms: default.imms.clone(),
+                                },
+                                result_type_id: Some(ids.globals[&Global::Type(ct_def.ty)]),
+                                result_id,
+                                ids: [].into_iter().collect(),
+                            }
+                        }
+
+                        ConstCtor::SpecConstOp(inst) => spv::InstWithIds {
+                            without_ids: spv::Inst {
+                                opcode: wk.OpSpecConstantOp,
+                                imms: [spv::Imm::Short(
+                                    wk.LiteralSpecConstantOpInteger,
+                                    inst.opcode.as_u16().into(),
+                                )]
+                                .into_iter()
+                                .chain(inst.imms.iter().copied())
+                                .collect(),
+                            },
+                            result_type_id: Some(ids.globals[&Global::Type(ct_def.ty)]),
+                            result_id,
+                            ids: ct_def
+                                .ctor_args
+                                .iter()
+                                .map(|&ct| ids.globals[&Global::Const(ct)])
+                                .collect(),
+                        },
+
+                        &ConstCtor::SpvExtInst { ext_set, inst } => spv::InstWithIds {
+                            without_ids: spv::Inst {
+                                opcode: wk.OpExtInst,
+                                imms: iter::once(spv::Imm::Short(wk.LiteralExtInstInteger, inst))
+                                    .collect(),
+                            },
+                            result_type_id: Some(ids.globals[&Global::Type(ct_def.ty)]),
+                            result_id,
+                            ids: iter::once(ids.ext_inst_imports[&cx[ext_set]])
+                                .chain(ct_def.ctor_args.iter().map(|&ct| match cx[ct].ctor {
+                                    ConstCtor::SpvStringLiteralForExtInst(s) => {
+                                        ids.debug_strings[&cx[s]]
+                                    }
+
+                                    _ => ids.globals[&Global::Const(ct)],
+                                }))
+                                .collect(),
+                        },
+
                         // Not inserted into `globals` while visiting.
-                        ConstCtor::SpvStringLiteralForExtInst(_) => unreachable!(),
+                        ConstCtor::SpvStringLiteralForExtInst(_) | ConstCtor::SpvTypeOperand => {
+                            unreachable!()
+                        }
                     }
                 }
             },
@@ -1252,12 +1683,13 @@ impl LazyInst<'_, '_> {
                 let func_ctrl = cx[attrs]
                     .attrs
                     .iter()
-                    .find_map(|attr| match *attr {
-                        Attr::SpvBitflagsOperand(spv::Imm::Short(kind, word))
-                            if kind == wk.FunctionControl =>
-                        {
-                            Some(word)
-                        }
+                    .find_map(|attr| match attr {
+                        Attr::SpvBitflagsOperand(imms) => match imms[..] {
+                            [spv::Imm::Short(kind, word)] if kind == wk.FunctionControl => {
+                                Some(word)
+                            }
+                            _ => None,
+                        },
                         _ => None,
                     })
                     .unwrap_or(0);
@@ -1318,42 +1750,95 @@ impl LazyInst<'_, '_> {
                         },
                         Some(ids.ext_inst_imports[&cx[ext_set]]),
                     ),
+                    &DataInstKind::SpvGlslStd450(op) => (
+                        spv::Inst {
+                            opcode: wk.OpExtInst,
+                            imms: iter::once(spv::Imm::Short(wk.LiteralExtInstInteger, op as u32))
+                                .collect(),
+                        },
+                        Some(ids.ext_inst_imports[spv::glsl_std_450::EXT_INST_SET_NAME]),
+                    ),
+                    &DataInstKind::Atomic { op, .. } => (op.spv_opcode().into(), None),
+                    &DataInstKind::Barrier {
+                        execution_scope, ..
+                    } => (
+                        if execution_scope.is_some() {
+                            wk.OpControlBarrier
+                        } else {
+                            wk.OpMemoryBarrier
+                        }
+                        .into(),
+                        None,
+                    ),
+                    &DataInstKind::Group {
+                        op,
+                        scope: _,
+                        group_operation,
+                    } => (
+                        spv::Inst {
+                            opcode: op.spv_opcode(),
+                            imms: group_operation
+                                .map(|x| spv::Imm::Short(wk.GroupOperation, x))
+                                .into_iter()
+                                .collect(),
+                        },
+                        None,
+                    ),
+
+                    // Not allowed while visiting.
+                    DataInstKind::QPtr(_) => unreachable!(),
                 };
+                let mut operand_ids: SmallVec<[_; 4]> = extra_initial_id_operand
+                    .into_iter()
+                    .chain(
+                        data_inst_def
+                            .inputs
+                            .iter()
+                            .map(|&v| value_to_id(parent_func, v)),
+                    )
+                    .collect();
+
+                let (const_operands_idx, const_operands) =
+                    decoded_u32_const_operands(&data_inst_def.kind);
+                operand_ids.insert_many(
+                    const_operands_idx,
+                    const_operands
+                        .into_iter()
+                        .map(|x| ids.globals[&Global::Const(u32_const(cx, x))]),
+                );
+
                 spv::InstWithIds {
                     without_ids: inst,
                     result_type_id: data_inst_def
                         .output_type
                         .map(|ty| ids.globals[&Global::Type(ty)]),
                     result_id,
-                    ids: extra_initial_id_operand
-                        .into_iter()
-                        .chain(
-                            data_inst_def
-                                .inputs
-                                .iter()
-                                .map(|&v| value_to_id(parent_func, v)),
-                        )
-                        .collect(),
+                    ids: operand_ids,
                 }
             }
-            Self::Merge(Merge::Selection(merge_label_id)) => spv::InstWithIds {
+            Self::Merge {
+                attrs: merge_attrs,
+                merge: Merge::Selection(merge_label_id),
+            } => spv::InstWithIds {
                 without_ids: spv::Inst {
                     opcode: wk.OpSelectionMerge,
-                    imms: [spv::Imm::Short(wk.SelectionControl, 0)]
-                        .into_iter()
-                        .collect(),
+                    imms: merge_control_imms(merge_attrs, wk.SelectionControl),
                 },
                 result_type_id: None,
                 result_id: None,
                 ids: [merge_label_id].into_iter().collect(),
             },
-            Self::Merge(Merge::Loop {
-                loop_merge: merge_label_id,
-                loop_continue: continue_label_id,
-            }) => spv::InstWithIds {
+            Self::Merge {
+                attrs: merge_attrs,
+                merge:
+                    Merge::Loop {
+                        loop_merge: merge_label_id,
+                        loop_continue: continue_label_id,
+                    },
+            } => spv::InstWithIds {
                 without_ids: spv::Inst {
                     opcode: wk.OpLoopMerge,
-                    imms: [spv::Imm::Short(wk.LoopControl, 0)].into_iter().collect(),
+                    imms: merge_control_imms(merge_attrs, wk.LoopControl),
                 },
                 result_type_id: None,
                 result_id: None,
@@ -1375,6 +1860,9 @@ impl LazyInst<'_, '_> {
                     cfg::ControlInstKind::ExitInvocation(cfg::ExitInvocationKind::SpvInst(
                         inst,
                     )) => inst.clone(),
+                    cfg::ControlInstKind::ExitInvocation(
+                        cfg::ExitInvocationKind::EmitMeshTasks,
+                    ) => wk.OpEmitMeshTasksEXT.into(),
 
                     cfg::ControlInstKind::Branch => wk.OpBranch.into(),
 
@@ -1413,12 +1901,351 @@ impl LazyInst<'_, '_> {
     }
 }
 
+/// Side table mapping instructions in a SPIR-V module, emitted by
+/// [`Module::lift_to_spv_module_emitter_with_source_map`], to the SPIR-T
+/// definitions they were lifted from.
+pub struct SourceMap {
+    /// All the emitted instructions, in order (i.e. indexed by "instruction index").
+    pub insts: Vec<EmittedInst>,
+
+    /// Total size (in words) of the module, including the header.
+    pub total_word_count: usize,
+}
+
+pub struct EmittedInst {
+    /// Offset (in words) of the instruction, from the start of the module
+    /// (i.e. the first instruction is at [`spec::HEADER_LEN`], after the header).
+    pub word_offset: usize,
+
+    /// What the instruction was lifted from, if it corresponds to any single
+    /// SPIR-T definition (which e.g. `OpCapability` or `OpLine` don't).
+    pub lifted_from: Option<LiftedFrom>,
+}
+
+/// SPIR-T definition that SPIR-V instructions can be lifted from.
+///
+/// Note that [`ControlRegion`]s and [`ControlNode`]s can produce several
+/// instructions (e.g. `OpLabel`, `OpPhi`, merges and branches).
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+pub enum LiftedFrom {
+    Type(Type),
+    Const(Const),
+    GlobalVar(GlobalVar),
+
+    /// Function-level instructions (`OpFunction`, `OpFunctionParameter`,
+    /// `OpFunctionEnd`).
+    Func(Func),
+
+    ControlRegion(ControlRegion),
+    ControlNode(ControlNode),
+    DataInst(DataInst),
+}
+
+impl SourceMap {
+    /// Get the index of the instruction that contains the word at `word_offset`,
+    /// or `None` if `word_offset` is in the header (or out of bounds).
+    pub fn inst_idx_at_word_offset(&self, word_offset: usize) -> Option<usize> {
+        if word_offset >= self.total_word_count {
+            return None;
+        }
+        self.insts
+            .partition_point(|inst| inst.word_offset <= word_offset)
+            .checked_sub(1)
+    }
+
+    /// Get the (indices of) instructions lifted from each SPIR-T definition,
+    /// i.e. the reverse of the mapping contained in the [`SourceMap`].
+    pub fn inst_idxs_by_lifted_from(&self) -> FxIndexMap<LiftedFrom, SmallVec<[usize; 1]>> {
+        let mut inst_idxs_by_lifted_from = FxIndexMap::<_, SmallVec<_>>::default();
+        for (inst_idx, inst) in self.insts.iter().enumerate() {
+            if let Some(lifted_from) = inst.lifted_from {
+                inst_idxs_by_lifted_from
+                    .entry(lifted_from)
+                    .or_default()
+                    .push(inst_idx);
+            }
+        }
+        inst_idxs_by_lifted_from
+    }
+}
+
+/// Options for SPIR-V lifting (see [`Module::lift_to_spv_module_emitter_with_options`]).
+#[derive(Clone, Default)]
+pub struct LiftOptions {
+    /// Whether to automatically increase the SPIR-V version (in the header of
+    /// the emitted module) beyond the one in the module's [`spv::Dialect`],
+    /// whenever instructions (or enumerands) from newer SPIR-V versions are
+    /// used (and not made available by extensions), instead of failing with
+    /// an error that describes the first such use of the newest version needed.
+    pub bump_version_as_needed: bool,
+
+    /// Whether to replace the capabilities declared by the module's
+    /// [`spv::Dialect`] with a minimal set, computed from what the emitted
+    /// instructions actually require (see [`spv::Dialect::minimal_capabilities_for`]),
+    /// instead of emitting the original (and potentially stale) capabilities.
+    pub minimize_capabilities: bool,
+
+    /// Whether to emit all the extensions declared by the module's
+    /// [`spv::Dialect`], instead of only those that the emitted instructions
+    /// actually require (see [`spv::Dialect::minimal_extensions_for`]).
+    pub preserve_declared_extensions: bool,
+
+    /// Whether to renumber all IDs (after emission), such that they're densely
+    /// allocated in the order of their definitions (and therefore independent
+    /// of any ordering used internally while lifting), so that lifting the
+    /// same module always produces the same (byte-for-byte) SPIR-V binary.
+    pub ids_in_emission_order: bool,
+
+    /// Whether to renumber IDs (after emission) such that definitions lowered
+    /// from SPIR-V reuse their original IDs (see [`Attr::SpvOriginalId`]), to
+    /// keep the emitted SPIR-V easier to compare against the original one.
+    ///
+    /// All other IDs (e.g. for types and constants, which are deduplicated,
+    /// so their original IDs aren't kept) are allocated as if by
+    /// [`ids_in_emission_order`](LiftOptions::ids_in_emission_order),
+    /// while avoiding all the original IDs.
+    pub reuse_original_ids: bool,
+}
+
+impl LiftOptions {
+    /// Get the name of the first option (if any) which requires buffering the
+    /// whole module in memory (i.e. preventing [`Module::lift_to_spv_writer`]).
+    fn first_requiring_buffering(&self) -> Option<&'static str> {
+        if self.ids_in_emission_order {
+            Some("ids_in_emission_order")
+        } else if self.reuse_original_ids {
+            Some("reuse_original_ids")
+        } else {
+            None
+        }
+    }
+
+    /// Whether the declared capabilities and/or extensions are to be replaced
+    /// with minimal sets (see [`minimal_dialect_for`]).
+    fn minimizes_declared_dialect(&self) -> bool {
+        self.minimize_capabilities || !self.preserve_declared_extensions
+    }
+}
+
+/// Compute the [`spv::Dialect`] with the minimal capabilities and/or extensions
+/// (as requested by `options`) required by `insts`, which shouldn't include
+/// any `OpCapability` or `OpExtension` instructions.
+fn minimal_dialect_for<'a>(
+    dialect: &spv::Dialect,
+    options: &LiftOptions,
+    insts: impl IntoIterator<Item = &'a spv::Inst> + Copy,
+) -> spv::Dialect {
+    let mut minimal_dialect = dialect.clone();
+    if options.minimize_capabilities {
+        minimal_dialect.capabilities = dialect.minimal_capabilities_for(insts);
+    }
+    if !options.preserve_declared_extensions {
+        minimal_dialect.extensions = minimal_dialect.minimal_extensions_for(insts);
+    }
+    minimal_dialect
+}
+
+// HACK(eddyb) only needed for `dyn Write + Seek`.
+trait WriteSeek: io::Write + io::Seek {}
+impl<W: io::Write + io::Seek> WriteSeek for W {}
+
+/// Destination for the instructions emitted while lifting a [`Module`].
+enum LiftEmitter<'a, 'w> {
+    /// Accumulate the whole module in memory, allowing it to be post-processed
+    /// (and a [`SourceMap`] to be produced) once all instructions are emitted.
+    Buffered(spv::write::ModuleEmitter),
+
+    /// Write out every instruction as soon as it's emitted (see [`Module::lift_to_spv_writer`]).
+    Streaming {
+        emitter: spv::write::StreamingModuleEmitter<&'w mut dyn WriteSeek>,
+        dialect: &'a spv::Dialect,
+        required_version: RequiredVersion,
+    },
+
+    /// Write nothing, and only collect the distinct instructions (without IDs)
+    /// that would be emitted, for [`Module::lift_to_spv_writer`] to compute
+    /// the minimal capabilities/extensions (see [`LiftOptions`]) before streaming.
+    DryRun {
+        distinct_insts: &'w mut FxIndexSet<spv::Inst>,
+        header: [u32; spec::HEADER_LEN],
+    },
+}
+
+/// Where [`Module::lift_to_spv_impl`] sends instructions, instead of
+/// accumulating the whole module in memory.
+enum LiftStream<'a, 'w> {
+    /// Only collect the distinct instructions (see [`LiftEmitter::DryRun`]).
+    DryRun(&'w mut FxIndexSet<spv::Inst>),
+
+    /// Write out every instruction (see [`LiftEmitter::Streaming`]), declaring
+    /// the capabilities and extensions of `minimal_dialect`, if present (as
+    /// computed ahead of time, from a dry run), instead of the module's own.
+    Writer {
+        writer: &'w mut dyn WriteSeek,
+        minimal_dialect: Option<&'a spv::Dialect>,
+    },
+}
+
+impl LiftEmitter<'_, '_> {
+    fn push_inst(&mut self, inst: &spv::InstWithIds) -> io::Result<()> {
+        match self {
+            LiftEmitter::Buffered(emitter) => emitter.push_inst(inst),
+            LiftEmitter::Streaming {
+                emitter,
+                dialect,
+                required_version,
+            } => {
+                required_version.add_inst(dialect, inst);
+                emitter.push_inst(inst)
+            }
+            LiftEmitter::DryRun { distinct_insts, .. } => {
+                // NOTE(eddyb) the declared capabilities and extensions are
+                // exactly what the dry run is used to replace.
+                let wk = &spec::Spec::get().well_known;
+                if ![wk.OpCapability, wk.OpExtension].contains(&inst.opcode) {
+                    distinct_insts.insert(inst.without_ids.clone());
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn header_mut(&mut self) -> &mut [u32] {
+        match self {
+            LiftEmitter::Buffered(emitter) => &mut emitter.words[..spec::HEADER_LEN],
+            LiftEmitter::Streaming { emitter, .. } => &mut emitter.header,
+            LiftEmitter::DryRun { header, .. } => header,
+        }
+    }
+}
+
+/// The newest SPIR-V version required by any emitted instruction (if newer
+/// than the declared one), along with a description of what required it.
+#[derive(Default)]
+struct RequiredVersion(Option<((u8, u8), String)>);
+
+impl RequiredVersion {
+    fn add_inst(&mut self, dialect: &spv::Dialect, inst: &spv::Inst) {
+        if let Some((version, descr)) = dialect.min_version_required_by(inst) {
+            let max_version = match &self.0 {
+                Some((max_version, _)) => *max_version,
+                None => (dialect.version_major, dialect.version_minor),
+            };
+            if version > max_version {
+                self.0 = Some((version, descr));
+            }
+        }
+    }
+
+    /// Update the version in `header` (if allowed by `options`, and erroring otherwise).
+    fn apply_to_header(
+        self,
+        dialect: &spv::Dialect,
+        options: &LiftOptions,
+        header: &mut [u32],
+    ) -> io::Result<()> {
+        if let Some(((major, minor), descr)) = self.0 {
+            if !options.bump_version_as_needed {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "{descr} requires SPIR-V {major}.{minor}, but the module \
+                         is SPIR-V {}.{} (see `LiftOptions::bump_version_as_needed`)",
+                        dialect.version_major, dialect.version_minor,
+                    ),
+                ));
+            }
+            header[1] = (u32::from(major) << 16) | (u32::from(minor) << 8);
+        }
+        Ok(())
+    }
+}
+
 impl Module {
     pub fn lift_to_spv_file(&self, path: impl AsRef<Path>) -> io::Result<()> {
         self.lift_to_spv_module_emitter()?.write_to_spv_file(path)
     }
 
     pub fn lift_to_spv_module_emitter(&self) -> io::Result<spv::write::ModuleEmitter> {
+        self.lift_to_spv_module_emitter_with_source_map()
+            .map(|(emitter, _)| emitter)
+    }
+
+    /// Like [`lift_to_spv_module_emitter`](Module::lift_to_spv_module_emitter),
+    /// but also returning a [`SourceMap`], to allow mapping e.g. errors about
+    /// the emitted SPIR-V instructions back to their SPIR-T definitions.
+    pub fn lift_to_spv_module_emitter_with_source_map(
+        &self,
+    ) -> io::Result<(spv::write::ModuleEmitter, SourceMap)> {
+        self.lift_to_spv_module_emitter_with_options(&LiftOptions::default())
+    }
+
+    /// Like [`Module::lift_to_spv_module_emitter_with_source_map`], but with
+    /// [`LiftOptions`] to control the lifting process.
+    pub fn lift_to_spv_module_emitter_with_options(
+        &self,
+        options: &LiftOptions,
+    ) -> io::Result<(spv::write::ModuleEmitter, SourceMap)> {
+        Ok(self.lift_to_spv_impl(options, None)?.unwrap())
+    }
+
+    /// Lift to SPIR-V, writing each instruction to `writer` as soon as it's
+    /// emitted, instead of accumulating the whole module in memory, and only
+    /// seeking back to the start of the module at the very end, to finalize
+    /// its header (e.g. the ID bound), returning `writer` afterwards.
+    ///
+    /// As there is no opportunity to post-process the whole module, `options`
+    /// must not require renumbering IDs (i.e. neither `ids_in_emission_order`
+    /// nor `reuse_original_ids` can be set), and replacing the declared
+    /// capabilities/extensions with minimal sets (as done by default, see
+    /// [`LiftOptions`]) requires lifting the module twice: first as a dry run
+    /// (only keeping the distinct instructions, without IDs, in memory), and
+    /// then again, to actually write out the module.
+    pub fn lift_to_spv_writer<W: io::Write + io::Seek>(
+        &self,
+        mut writer: W,
+        options: &LiftOptions,
+    ) -> io::Result<W> {
+        if let Some(option) = options.first_requiring_buffering() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("`LiftOptions {{ {option}, .. }}` is not supported when streaming"),
+            ));
+        }
+
+        let minimal_dialect = if options.minimizes_declared_dialect() {
+            let mut distinct_insts = FxIndexSet::default();
+            let result =
+                self.lift_to_spv_impl(options, Some(LiftStream::DryRun(&mut distinct_insts)))?;
+            assert!(result.is_none());
+            match &self.dialect {
+                ModuleDialect::Spv(dialect) => {
+                    Some(minimal_dialect_for(dialect, options, &distinct_insts))
+                }
+            }
+        } else {
+            None
+        };
+
+        let result = self.lift_to_spv_impl(
+            options,
+            Some(LiftStream::Writer {
+                writer: &mut writer,
+                minimal_dialect: minimal_dialect.as_ref(),
+            }),
+        )?;
+        assert!(result.is_none());
+        Ok(writer)
+    }
+
+    /// Implementation detail of [`Module::lift_to_spv_module_emitter_with_options`]
+    /// (and [`Module::lift_to_spv_writer`], when `stream` is `Some`, in which
+    /// case `Ok(None)` is returned, as nothing was kept in memory).
+    fn lift_to_spv_impl(
+        &self,
+        options: &LiftOptions,
+        stream: Option<LiftStream<'_, '_>>,
+    ) -> io::Result<Option<(spv::write::ModuleEmitter, SourceMap)>> {
         let spv_spec = spec::Spec::get();
         let wk = &spv_spec.well_known;
 
@@ -1473,13 +2300,13 @@ impl Module {
         // IDs can be allocated once we have the full sets needing them, whether
         // sorted by contents, or ordered by the first occurence in the module.
         let mut id_bound = NonZeroU32::new(1).unwrap();
-        let ids = needs_ids_collector.alloc_ids(|| {
-            let id = id_bound;
+        let alloc_id = |id_bound: &mut NonZeroU32| {
+            let id = *id_bound;
 
             // FIXME(eddyb) use `id_bound.checked_add(1)` once that's stabilized.
             match id_bound.get().checked_add(1).and_then(NonZeroU32::new) {
                 Some(new_bound) => {
-                    id_bound = new_bound;
+                    *id_bound = new_bound;
                     Ok(id)
                 }
                 None => Err(io::Error::new(
@@ -1487,17 +2314,28 @@ impl Module {
                     "ID bound of SPIR-V module doesn't fit in 32 bits",
                 )),
             }
-        })?;
+        };
+        let ids = needs_ids_collector.alloc_ids(|| alloc_id(&mut id_bound))?;
 
         // HACK(eddyb) allow `move` closures below to reference `cx` or `ids`
         // without causing unwanted moves out of them.
         let (cx, ids) = (&*cx, &ids);
 
+        // NOTE(eddyb) every instruction is paired with what it was lifted from,
+        // for the optional `SourceMap` (see `lift_to_spv_module_emitter_with_source_map`).
         let global_and_func_insts =
             ids.globals
                 .keys()
-                .copied()
-                .map(LazyInst::Global)
+                .map(|&global| {
+                    let lifted_from = match global {
+                        Global::Type(ty) | Global::ForwardPtrDecl(ty) => LiftedFrom::Type(ty),
+                        Global::Const(ct) => match cx[ct].ctor {
+                            ConstCtor::PtrToGlobalVar(gv) => LiftedFrom::GlobalVar(gv),
+                            _ => LiftedFrom::Const(ct),
+                        },
+                    };
+                    (lifted_from, LazyInst::Global(global))
+                })
                 .chain(ids.funcs.iter().flat_map(|(&func, func_lifting)| {
                     let func_decl = &self.funcs[func];
                     let func_def_body = match &func_decl.def {
@@ -1512,6 +2350,7 @@ impl Module {
                     .chain(func_lifting.param_ids.iter().zip(&func_decl.params).map(
                         |(&param_id, param)| LazyInst::OpFunctionParameter { param_id, param },
                     ))
+                    .map(move |lazy_inst| (LiftedFrom::Func(func), lazy_inst))
                     .chain(func_lifting.blocks.iter().flat_map(move |(point, block)| {
                         let BlockLifting {
                             phis,
@@ -1519,12 +2358,30 @@ impl Module {
                             terminator,
                         } = block;
 
-                        iter::once(LazyInst::OpLabel {
-                            label_id: func_lifting.label_ids[point],
-                        })
-                        .chain(phis.iter().map(|phi| LazyInst::OpPhi {
-                            parent_func: func_lifting,
-                            phi,
+                        let block_lifted_from = match *point {
+                            CfgPoint::RegionEntry(region) | CfgPoint::RegionExit(region) => {
+                                LiftedFrom::ControlRegion(region)
+                            }
+                            CfgPoint::ControlNodeEntry(control_node)
+                            | CfgPoint::ControlNodeExit(control_node) => {
+                                LiftedFrom::ControlNode(control_node)
+                            }
+                        };
+
+                        iter::once((
+                            block_lifted_from,
+                            LazyInst::OpLabel {
+                                label_id: func_lifting.label_ids[point],
+                            },
+                        ))
+                        .chain(phis.iter().map(move |phi| {
+                            (
+                                block_lifted_from,
+                                LazyInst::OpPhi {
+                                    parent_func: func_lifting,
+                                    phi,
+                                },
+                            )
                         }))
                         .chain(
                             insts
@@ -1533,36 +2390,48 @@ impl Module {
                                 .flat_map(move |insts| func_def_body.unwrap().at(insts))
                                 .map(move |func_at_inst| {
                                     let data_inst_def = func_at_inst.def();
-                                    LazyInst::DataInst {
-                                        parent_func: func_lifting,
-                                        result_id: data_inst_def.output_type.map(|_| {
-                                            func_lifting.data_inst_output_ids
-                                                [&func_at_inst.position]
-                                        }),
-                                        data_inst_def,
-                                    }
+                                    (
+                                        LiftedFrom::DataInst(func_at_inst.position),
+                                        LazyInst::DataInst {
+                                            parent_func: func_lifting,
+                                            result_id: data_inst_def.output_type.map(|_| {
+                                                func_lifting.data_inst_output_ids
+                                                    [&func_at_inst.position]
+                                            }),
+                                            data_inst_def,
+                                        },
+                                    )
                                 }),
                         )
                         .chain(terminator.merge.map(|merge| {
-                            LazyInst::Merge(match merge {
-                                Merge::Selection(merge) => {
-                                    Merge::Selection(func_lifting.label_ids[&merge])
-                                }
-                                Merge::Loop {
-                                    loop_merge,
-                                    loop_continue,
-                                } => Merge::Loop {
-                                    loop_merge: func_lifting.label_ids[&loop_merge],
-                                    loop_continue: func_lifting.label_ids[&loop_continue],
+                            (
+                                block_lifted_from,
+                                LazyInst::Merge {
+                                    attrs: terminator.attrs,
+                                    merge: match merge {
+                                        Merge::Selection(merge) => {
+                                            Merge::Selection(func_lifting.label_ids[&merge])
+                                        }
+                                        Merge::Loop {
+                                            loop_merge,
+                                            loop_continue,
+                                        } => Merge::Loop {
+                                            loop_merge: func_lifting.label_ids[&loop_merge],
+                                            loop_continue: func_lifting.label_ids[&loop_continue],
+                                        },
+                                    },
                                 },
-                            })
+                            )
                         }))
-                        .chain([LazyInst::Terminator {
-                            parent_func: func_lifting,
-                            terminator,
-                        }])
+                        .chain([(
+                            block_lifted_from,
+                            LazyInst::Terminator {
+                                parent_func: func_lifting,
+                                terminator,
+                            },
+                        )])
                     }))
-                    .chain([LazyInst::OpFunctionEnd])
+                    .chain([(LiftedFrom::Func(func), LazyInst::OpFunctionEnd)])
                 }));
 
         let reserved_inst_schema = 0;
@@ -1574,12 +2443,32 @@ impl Module {
             reserved_inst_schema,
         ];
 
-        let mut emitter = spv::write::ModuleEmitter::with_header(header);
+        // NOTE(eddyb) when buffering, the declared capabilities and extensions
+        // can be replaced afterwards, but streaming has to declare them upfront.
+        let declared_dialect = match stream {
+            Some(LiftStream::Writer {
+                minimal_dialect: Some(minimal_dialect),
+                ..
+            }) => minimal_dialect,
+            _ => dialect,
+        };
+        let mut emitter = match stream {
+            None => LiftEmitter::Buffered(spv::write::ModuleEmitter::with_header(header)),
+            Some(LiftStream::DryRun(distinct_insts)) => LiftEmitter::DryRun {
+                distinct_insts,
+                header,
+            },
+            Some(LiftStream::Writer { writer, .. }) => LiftEmitter::Streaming {
+                emitter: spv::write::StreamingModuleEmitter::with_header(writer, header)?,
+                dialect: declared_dialect,
+                required_version: RequiredVersion::default(),
+            },
+        };
 
-        for cap_inst in dialect.capability_insts() {
+        for cap_inst in declared_dialect.capability_insts() {
             emitter.push_inst(&cap_inst)?;
         }
-        for ext_inst in dialect.extension_insts() {
+        for ext_inst in declared_dialect.extension_insts() {
             emitter.push_inst(&ext_inst)?;
         }
         for (&name, &id) in &ids.ext_inst_imports {
@@ -1614,9 +2503,71 @@ impl Module {
         let mut debug_name_insts = vec![];
         let mut decoration_insts = vec![];
 
-        for lazy_inst in global_and_func_insts.clone() {
+        for (_, lazy_inst) in global_and_func_insts.clone() {
             let (result_id, attrs, import) = lazy_inst.result_id_attrs_and_import(self, ids);
 
+            if let LazyInst::Global(Global::Const(ct)) = lazy_inst {
+                if let ConstCtor::SpecConst { spec_id, .. } = cx[ct].ctor {
+                    decoration_insts.push(spv::InstWithIds {
+                        without_ids: spv::Inst {
+                            opcode: wk.OpDecorate,
+                            imms: [
+                                spv::Imm::Short(wk.Decoration, wk.SpecId),
+                                spv::Imm::Short(wk.LiteralInteger, spec_id),
+                            ]
+                            .into_iter()
+                            .collect(),
+                        },
+                        result_type_id: None,
+                        result_id: None,
+                        ids: result_id.into_iter().collect(),
+                    });
+                }
+            }
+
+            if let LazyInst::Global(Global::Type(ty)) = lazy_inst {
+                if let TypeCtor::Struct { members } = &cx[ty].ctor {
+                    for (member_idx, member) in members.iter().enumerate() {
+                        let member_idx =
+                            spv::Imm::Short(wk.LiteralInteger, member_idx.try_into().unwrap());
+                        let member_inst = |opcode, imms: &[spv::Imm]| spv::InstWithIds {
+                            without_ids: spv::Inst {
+                                opcode,
+                                imms: iter::once(member_idx).chain(imms.iter().copied()).collect(),
+                            },
+                            result_type_id: None,
+                            result_id: None,
+                            ids: result_id.into_iter().collect(),
+                        };
+
+                        if let Some(offset) = member.offset {
+                            decoration_insts.push(member_inst(
+                                wk.OpMemberDecorate,
+                                &[
+                                    spv::Imm::Short(wk.Decoration, wk.Offset),
+                                    spv::Imm::Short(wk.LiteralInteger, offset),
+                                ],
+                            ));
+                        }
+
+                        // NOTE(eddyb) member attributes are stored as the
+                        // non-member equivalents (see `StructMember`'s docs).
+                        for attr in &cx[member.attrs].attrs {
+                            if let Attr::SpvAnnotation(spv::Inst { opcode, imms }) = attr {
+                                if *opcode == wk.OpName {
+                                    debug_name_insts.push(member_inst(wk.OpMemberName, imms));
+                                } else if *opcode == wk.OpDecorateString {
+                                    decoration_insts
+                                        .push(member_inst(wk.OpMemberDecorateString, imms));
+                                } else {
+                                    decoration_insts.push(member_inst(wk.OpMemberDecorate, imms));
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+
             for attr in cx[attrs].attrs.iter() {
                 match attr {
                     Attr::SpvAnnotation(inst @ spv::Inst { opcode, .. }) => {
@@ -1632,7 +2583,7 @@ impl Module {
                             ids: iter::once(target_id).collect(),
                         };
 
-                        if [wk.OpExecutionMode, wk.OpExecutionModeId].contains(opcode) {
+                        if *opcode == wk.OpExecutionMode {
                             execution_mode_insts.push(inst);
                         } else if [wk.OpName, wk.OpMemberName].contains(opcode) {
                             debug_name_insts.push(inst);
@@ -1640,7 +2591,29 @@ impl Module {
                             decoration_insts.push(inst);
                         }
                     }
-                    Attr::SpvDebugLine { .. } | Attr::SpvBitflagsOperand(_) => {}
+                    Attr::SpvExecutionModeId { inst, consts } => {
+                        let target_id = result_id.expect(
+                            "FIXME: it shouldn't be possible to attach \
+                                 attributes to instructions without an output",
+                        );
+
+                        execution_mode_insts.push(spv::InstWithIds {
+                            without_ids: inst.clone(),
+                            result_type_id: None,
+                            result_id: None,
+                            ids: iter::once(target_id)
+                                .chain(consts.iter().map(|ct| ids.globals[&Global::Const(ct.0)]))
+                                .collect(),
+                        });
+                    }
+                    Attr::SpvDebugLine { .. }
+                    | Attr::SpvBitflagsOperand(_)
+                    | Attr::SpvOriginalId(_)
+                    | Attr::SpvUnsupported(_)
+                    | Attr::SpvShaderDebugScope { .. }
+                    | Attr::SpvShaderDebugLine { .. }
+                    | Attr::QPtr(_)
+                    | Attr::Diagnostic { .. } => {}
                 }
 
                 if let Some(import) = import {
@@ -1807,11 +2780,25 @@ impl Module {
             emitter.push_inst(&decoration_inst)?;
         }
 
+        let mut lifted_from_by_word_offset = vec![];
+        let mut original_ids = FxHashMap::default();
         let mut current_debug_line = None;
+        let mut current_shader_debug_scope = None;
+        let mut current_shader_debug_line = None;
         let mut current_block_id = None; // HACK(eddyb) for `current_debug_line` resets.
-        for lazy_inst in global_and_func_insts {
+        for (lifted_from, lazy_inst) in global_and_func_insts {
             let (inst, attrs) = lazy_inst.to_inst_and_attrs(self, ids);
 
+            if let (true, Some(result_id)) = (options.reuse_original_ids, inst.result_id) {
+                let original_id = cx[attrs].attrs.iter().find_map(|attr| match *attr {
+                    Attr::SpvOriginalId(id) => Some(id),
+                    _ => None,
+                });
+                if let Some(original_id) = original_id {
+                    original_ids.insert(result_id, original_id);
+                }
+            }
+
             // Reset line debuginfo when crossing/leaving blocks.
             let new_block_id = if inst.opcode == wk.OpLabel {
                 Some(inst.result_id.unwrap())
@@ -1822,6 +2809,8 @@ impl Module {
             };
             if current_block_id != new_block_id {
                 current_debug_line = None;
+                current_shader_debug_scope = None;
+                current_shader_debug_line = None;
             }
             current_block_id = new_block_id;
 
@@ -1864,9 +2853,235 @@ impl Module {
             }
             current_debug_line = new_debug_line;
 
+            // Determine whether to emit `NonSemantic.Shader.DebugInfo.100`
+            // scope/line instructions before `inst`, similar to `OpLine` above.
+            // NOTE(eddyb) merge instructions must be immediately followed by
+            // their terminator, and `OpPhi`s must be at the start of a block,
+            // so they're skipped (leaving the state for the next instruction).
+            let is_merge_or_phi =
+                [wk.OpSelectionMerge, wk.OpLoopMerge, wk.OpPhi].contains(&inst.opcode);
+            if !is_merge_or_phi {
+                let mut new_shader_debug_scope = None;
+                let mut new_shader_debug_line = None;
+                for attr in &cx[attrs].attrs {
+                    match *attr {
+                        Attr::SpvShaderDebugScope { scope, inlined_at } => {
+                            new_shader_debug_scope = Some((scope.0, inlined_at.map(|ct| ct.0)));
+                        }
+                        Attr::SpvShaderDebugLine {
+                            source,
+                            line_start,
+                            line_end,
+                            col_start,
+                            col_end,
+                        } => {
+                            new_shader_debug_line =
+                                Some((source.0, [line_start, line_end, col_start, col_end]));
+                        }
+                        _ => {}
+                    }
+                }
+
+                let mut shader_debuginfo_insts =
+                    SmallVec::<[(u32, SmallVec<[Const; 5]>); 2]>::new();
+                if current_shader_debug_scope != new_shader_debug_scope {
+                    shader_debuginfo_insts.push(match new_shader_debug_scope {
+                        Some((scope, inlined_at)) => (
+                            spv::shader_debuginfo::DEBUG_SCOPE,
+                            iter::once(scope).chain(inlined_at).collect(),
+                        ),
+                        None => (spv::shader_debuginfo::DEBUG_NO_SCOPE, SmallVec::new()),
+                    });
+                }
+                if current_shader_debug_line != new_shader_debug_line {
+                    shader_debuginfo_insts.push(match new_shader_debug_line {
+                        Some((source, lines_and_cols)) => (
+                            spv::shader_debuginfo::DEBUG_LINE,
+                            iter::once(source)
+                                .chain(lines_and_cols.into_iter().map(|x| u32_const(cx, x)))
+                                .collect(),
+                        ),
+                        None => (spv::shader_debuginfo::DEBUG_NO_LINE, SmallVec::new()),
+                    });
+                }
+                for (ext_inst, operands) in shader_debuginfo_insts {
+                    emitter.push_inst(&spv::InstWithIds {
+                        without_ids: spv::Inst {
+                            opcode: wk.OpExtInst,
+                            imms: iter::once(spv::Imm::Short(wk.LiteralExtInstInteger, ext_inst))
+                                .collect(),
+                        },
+                        result_type_id: Some(
+                            ids.globals[&Global::Type(shader_debuginfo_void_type(cx))],
+                        ),
+                        result_id: Some(alloc_id(&mut id_bound)?),
+                        ids: iter::once(
+                            ids.ext_inst_imports[spv::shader_debuginfo::EXT_INST_SET_NAME],
+                        )
+                        .chain(operands.iter().map(|&ct| ids.globals[&Global::Const(ct)]))
+                        .collect(),
+                    })?;
+                }
+                current_shader_debug_scope = new_shader_debug_scope;
+                current_shader_debug_line = new_shader_debug_line;
+            }
+
+            // NOTE(eddyb) only the buffered module gets a `SourceMap`.
+            if let LiftEmitter::Buffered(emitter) = &emitter {
+                lifted_from_by_word_offset.push((emitter.words.len(), lifted_from));
+            }
             emitter.push_inst(&inst)?;
         }
 
-        Ok(emitter)
+        // HACK(eddyb) the instructions emitted above for `Attr::SpvShaderDebugScope`
+        // and `Attr::SpvShaderDebugLine` need result IDs (even if unused), which
+        // are allocated on the fly, so the ID bound in the header needs updating.
+        emitter.header_mut()[3] = id_bound.get();
+
+        let mut emitter = match emitter {
+            LiftEmitter::Buffered(emitter) => emitter,
+            LiftEmitter::Streaming {
+                mut emitter,
+                dialect,
+                required_version,
+            } => {
+                required_version.apply_to_header(dialect, options, &mut emitter.header)?;
+                emitter.finish()?;
+                return Ok(None);
+            }
+            LiftEmitter::DryRun { .. } => return Ok(None),
+        };
+
+        // Replace the declared capabilities and extensions with the minimal
+        // sets required by what was emitted, if requested (see `LiftOptions`).
+        let mut emitted_dialect = Cow::Borrowed(dialect);
+        if options.minimizes_declared_dialect() {
+            let insts = spv::read::ModuleParser::read_from_spv_word_slice(&emitter.words)?
+                .map(|inst| inst.map(|inst| inst.without_ids))
+                .filter(|inst| match inst {
+                    Ok(inst) => ![wk.OpCapability, wk.OpExtension].contains(&inst.opcode),
+                    Err(_) => true,
+                })
+                .collect::<io::Result<Vec<_>>>()?;
+            let minimal_dialect = minimal_dialect_for(dialect, options, &insts);
+
+            // NOTE(eddyb) `OpCapability` and `OpExtension` instructions always
+            // come first, so they can be replaced without affecting anything
+            // but the offsets of all the instructions emitted after them.
+            let dialect_words = |dialect: &spv::Dialect| {
+                let mut emitter = spv::write::ModuleEmitter::with_header([0; spec::HEADER_LEN]);
+                for inst in dialect.capability_insts().chain(dialect.extension_insts()) {
+                    emitter.push_inst(&inst)?;
+                }
+                emitter.words.drain(..spec::HEADER_LEN);
+                Ok::<_, io::Error>(emitter.words)
+            };
+            let old_end = spec::HEADER_LEN + dialect_words(dialect)?.len();
+            let new_words = dialect_words(&minimal_dialect)?;
+            let new_end = spec::HEADER_LEN + new_words.len();
+            emitter.words.splice(spec::HEADER_LEN..old_end, new_words);
+            for (word_offset, _) in &mut lifted_from_by_word_offset {
+                *word_offset = *word_offset - old_end + new_end;
+            }
+
+            emitted_dialect = Cow::Owned(minimal_dialect);
+        }
+        let dialect = &*emitted_dialect;
+
+        // Ensure the SPIR-V version in the header is new enough for everything
+        // that was emitted, by parsing the emitted instructions back (as they
+        // can come from many places, and only whole instructions have enough
+        // context to correctly interpret all of their operands).
+        let mut required_version = RequiredVersion::default();
+        for inst in spv::read::ModuleParser::read_from_spv_word_slice(&emitter.words)? {
+            required_version.add_inst(dialect, &inst?.without_ids);
+        }
+        required_version.apply_to_header(dialect, options, &mut emitter.words)?;
+
+        // Renumber all IDs (reusing original IDs where possible, and otherwise
+        // allocating them in the order of their definitions), if requested (see
+        // `LiftOptions`), which only rewrites words in-place (as every ID
+        // operand takes up exactly one word), leaving all offsets unchanged.
+        if options.ids_in_emission_order || options.reuse_original_ids {
+            let insts = spv::read::ModuleParser::read_from_spv_word_slice(&emitter.words)?
+                .collect::<io::Result<Vec<_>>>()?;
+
+            // NOTE(eddyb) original IDs are never used for anything else, even
+            // when they're not reused (e.g. for all but the first of several
+            // definitions which were lowered from the same original definition).
+            let reserved_ids: FxHashSet<_> = original_ids.values().copied().collect();
+            let mut new_ids = FxHashMap::default();
+            let mut reused_ids = FxHashSet::default();
+            let mut new_id_bound = NonZeroU32::new(1).unwrap();
+            let defined_ids = insts.iter().filter_map(|inst| {
+                if inst.opcode == wk.OpTypeForwardPointer {
+                    inst.ids.first().copied()
+                } else {
+                    inst.result_id
+                }
+            });
+            // NOTE(eddyb) IDs used without any definition shouldn't exist, but
+            // they're still renumbered (in the order of their first use).
+            let used_ids = insts.iter().flat_map(|inst| {
+                inst.result_type_id
+                    .into_iter()
+                    .chain(inst.ids.iter().copied())
+            });
+            for id in defined_ids.chain(used_ids) {
+                if let hash_map::Entry::Vacant(entry) = new_ids.entry(id) {
+                    let new_id = match original_ids.get(&id) {
+                        Some(&original_id) if reused_ids.insert(original_id) => original_id,
+                        _ => loop {
+                            let new_id = alloc_id(&mut new_id_bound)?;
+                            if !reserved_ids.contains(&new_id) {
+                                break new_id;
+                            }
+                        },
+                    };
+                    entry.insert(new_id);
+                }
+            }
+            let new_id_bound = new_ids.values().map(|id| id.get() + 1).max().unwrap_or(1);
+
+            let mut header: [u32; spec::HEADER_LEN] =
+                emitter.words[..spec::HEADER_LEN].try_into().unwrap();
+            header[3] = new_id_bound;
+            let mut renumbered_emitter = spv::write::ModuleEmitter::with_header(header);
+            for mut inst in insts {
+                for id in inst
+                    .result_type_id
+                    .iter_mut()
+                    .chain(&mut inst.result_id)
+                    .chain(&mut inst.ids)
+                {
+                    *id = new_ids[id];
+                }
+                renumbered_emitter.push_inst(&inst)?;
+            }
+            assert_eq!(renumbered_emitter.words.len(), emitter.words.len());
+            emitter = renumbered_emitter;
+        }
+
+        // Instruction indices can only be determined after the fact, as many
+        // instructions (e.g. `OpLine`) are emitted without a SPIR-T counterpart.
+        let mut source_map = SourceMap {
+            insts: vec![],
+            total_word_count: emitter.words.len(),
+        };
+        let mut lifted_from_by_word_offset = lifted_from_by_word_offset.into_iter().peekable();
+        let mut word_offset = spec::HEADER_LEN;
+        while word_offset < emitter.words.len() {
+            let lifted_from = lifted_from_by_word_offset
+                .next_if(|&(lifted_word_offset, _)| lifted_word_offset == word_offset)
+                .map(|(_, lifted_from)| lifted_from);
+            source_map.insts.push(EmittedInst {
+                word_offset,
+                lifted_from,
+            });
+            word_offset += usize::try_from(emitter.words[word_offset] >> 16).unwrap();
+        }
+        assert!(lifted_from_by_word_offset.next().is_none());
+
+        Ok(Some((emitter, source_map)))
     }
 }
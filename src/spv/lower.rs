@@ -1,13 +1,15 @@
 //! SPIR-V to SPIR-T lowering.
 
-use crate::spv::{self, spec};
+use crate::spv::{self, shader_debuginfo, spec};
+use crate::transform::{SubstTypes, Transformed, Transformer};
 // FIXME(eddyb) import more to avoid `crate::` everywhere.
 use crate::{
-    cfg, print, AddrSpace, Attr, AttrSet, Const, ConstCtor, ConstDef, Context, ControlNodeDef,
-    ControlNodeKind, ControlRegion, ControlRegionDef, ControlRegionInputDecl, DataInstDef,
-    DataInstKind, DeclDef, EntityDefs, EntityList, ExportKey, Exportee, Func, FuncDecl,
-    FuncDefBody, FuncParam, FxIndexMap, GlobalVarDecl, GlobalVarDefBody, Import, InternedStr,
-    Module, SelectionKind, Type, TypeCtor, TypeCtorArg, TypeDef, Value,
+    cfg, print, AddrSpace, AtomicOp, Attr, AttrSet, AttrSetDef, Const, ConstCtor, ConstDef,
+    Context, ControlNodeDef, ControlNodeKind, ControlRegion, ControlRegionDef,
+    ControlRegionInputDecl, DataInstDef, DataInstKind, DeclDef, EntityDefs, EntityList, ExportKey,
+    Exportee, Func, FuncDecl, FuncDefBody, FuncParam, FxIndexMap, GlobalVarDecl, GlobalVarDefBody,
+    GroupOp, ImageType, Import, InternedStr, Module, SelectionKind, StructMember, Type, TypeCtor,
+    TypeCtorArg, TypeDef, Value,
 };
 use rustc_hash::FxHashMap;
 use smallvec::SmallVec;
@@ -15,7 +17,7 @@ use std::collections::{BTreeMap, BTreeSet};
 use std::num::NonZeroU32;
 use std::path::Path;
 use std::rc::Rc;
-use std::{io, mem};
+use std::{fmt, io, iter, mem};
 
 /// SPIR-T definition of a SPIR-V ID.
 enum IdDef {
@@ -49,10 +51,16 @@ impl IdDef {
 /// Deferred export, needed because the IDs are initially forward refs.
 enum Export {
     Linkage {
+        /// Index of the `OpDecorate` instruction (for error reporting).
+        inst_idx: usize,
+
         name: InternedStr,
         target_id: spv::Id,
     },
     EntryPoint {
+        /// Index of the `OpEntryPoint` instruction (for error reporting).
+        inst_idx: usize,
+
         func_id: spv::Id,
         imms: SmallVec<[spv::Imm; 2]>,
         interface_ids: SmallVec<[spv::Id; 4]>,
@@ -61,12 +69,18 @@ enum Export {
 
 /// Deferred [`FuncDefBody`], needed because some IDs are initially forward refs.
 struct FuncBody {
+    /// Index of the `OpFunction` instruction (for error reporting).
+    inst_idx: usize,
+
     func_id: spv::Id,
     func: Func,
     insts: Vec<IntraFuncInst>,
 }
 
 struct IntraFuncInst {
+    /// Index of the instruction in the module (for error reporting).
+    inst_idx: usize,
+
     // Instruction aspects that can be pre-lowered:
     attrs: AttrSet,
     result_type: Option<Type>,
@@ -80,12 +94,310 @@ struct IntraFuncInst {
     ids: SmallVec<[spv::Id; 4]>,
 }
 
-// FIXME(eddyb) stop abusing `io::Error` for error reporting.
-fn invalid(reason: &str) -> io::Error {
-    io::Error::new(
-        io::ErrorKind::InvalidData,
-        format!("malformed SPIR-V ({reason})"),
-    )
+/// Error encountered while lowering a SPIR-V module.
+pub enum LowerError {
+    /// Failed to read the SPIR-V module (e.g. from a file), or to decode its
+    /// instructions (see [`spv::read::ModuleParser`]).
+    Read(io::Error),
+
+    /// The SPIR-V module as a whole is malformed or unsupported (e.g. its header,
+    /// or some check that can only be done after seeing every instruction).
+    Module { message: String },
+
+    /// One specific instruction is malformed or unsupported.
+    Inst {
+        /// Index of the instruction in the module (i.e. `0` is the first
+        /// instruction after the header).
+        inst_idx: usize,
+
+        opcode: spec::Opcode,
+
+        /// The operand of the instruction at fault (if it could be pinpointed).
+        operand: Option<LowerErrorOperand>,
+
+        message: String,
+    },
+}
+
+/// Operand (of the instruction at fault) in a [`LowerError::Inst`].
+#[derive(Clone)]
+pub enum LowerErrorOperand {
+    Id(spv::Id),
+
+    /// One single ("logical") immediate operand, which may consist of several
+    /// [`spv::Imm`]s (e.g. long literals, or enumerands with parameters).
+    Imms(SmallVec<[spv::Imm; 2]>),
+}
+
+impl fmt::Display for LowerErrorOperand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LowerErrorOperand::Id(id) => write!(f, "%{id}"),
+            LowerErrorOperand::Imms(imms) => f.write_str(
+                &spv::print::operand_from_imms(imms.iter().copied()).concat_to_plain_text(),
+            ),
+        }
+    }
+}
+
+// NOTE(eddyb) `Debug` can't be derived, as `spec::Opcode` and `spv::Imm` lack it.
+impl fmt::Debug for LowerErrorOperand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LowerErrorOperand::Id(id) => f.debug_tuple("Id").field(id).finish(),
+            LowerErrorOperand::Imms(_) => f
+                .debug_tuple("Imms")
+                .field(&format_args!("{self}"))
+                .finish(),
+        }
+    }
+}
+
+impl fmt::Debug for LowerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LowerError::Read(e) => f.debug_tuple("Read").field(e).finish(),
+            LowerError::Module { message } => {
+                f.debug_struct("Module").field("message", message).finish()
+            }
+            LowerError::Inst {
+                inst_idx,
+                opcode,
+                operand,
+                message,
+            } => f
+                .debug_struct("Inst")
+                .field("inst_idx", inst_idx)
+                .field("opcode", &format_args!("{}", opcode.name()))
+                .field("operand", operand)
+                .field("message", message)
+                .finish(),
+        }
+    }
+}
+
+impl fmt::Display for LowerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LowerError::Read(e) => e.fmt(f),
+            LowerError::Module { message } => write!(f, "malformed SPIR-V ({message})"),
+            LowerError::Inst {
+                inst_idx,
+                opcode,
+                operand,
+                message,
+            } => {
+                write!(
+                    f,
+                    "malformed SPIR-V (in {} (instruction #{inst_idx})",
+                    opcode.name()
+                )?;
+                if let Some(operand) = operand {
+                    write!(f, ", at operand `{operand}`")?;
+                }
+                write!(f, ": {message})")
+            }
+        }
+    }
+}
+
+impl std::error::Error for LowerError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LowerError::Read(e) => Some(e),
+            LowerError::Module { .. } | LowerError::Inst { .. } => None,
+        }
+    }
+}
+
+// HACK(eddyb) allows using `?` with `LowerError` in `io::Result`-returning code
+// (the original `LowerError` can still be recovered via `io::Error::get_ref`).
+impl From<LowerError> for io::Error {
+    fn from(e: LowerError) -> Self {
+        match e {
+            LowerError::Read(e) => e,
+            _ => io::Error::new(io::ErrorKind::InvalidData, e),
+        }
+    }
+}
+
+fn invalid(reason: &str) -> LowerError {
+    LowerError::Module {
+        message: reason.to_string(),
+    }
+}
+
+/// Options for SPIR-V lowering (see [`Module::lower_from_spv_module_parser_with_options`]).
+#[derive(Clone, Default)]
+pub struct LowerOptions {
+    /// Whether to keep going after encountering (some kinds of) unsupported or
+    /// unknown SPIR-V constructs, instead of failing, e.g. to still be able to
+    /// inspect (or even partially transform) modules using newer extensions.
+    ///
+    /// Offending instructions are lowered as opaquely as possible (e.g. types
+    /// and constants without their problematic operands), or skipped entirely
+    /// (e.g. instructions with unknown opcodes, inside functions), and the
+    /// definitions they affect get an [`Attr::SpvUnsupported`] diagnostic.
+    ///
+    /// Errors which can't be attached to any definition (e.g. in the module's
+    /// header, or with the overall structure of the module) are still fatal.
+    pub permissive: bool,
+}
+
+/// Return `attrs` with an added [`Attr::SpvUnsupported`] diagnostic for `error`.
+fn attrs_with_unsupported(cx: &Context, attrs: AttrSet, error: &LowerError) -> AttrSet {
+    let mut attrs = cx[attrs].attrs.clone();
+    attrs.insert(Attr::SpvUnsupported(error.to_string()));
+    cx.intern(crate::AttrSetDef { attrs })
+}
+
+/// Return the [`ConstCtor::SpvStringLiteralForExtInst`] constant for `s`.
+//
+// HACK(eddyb) `OpString`s are interned as `Const`s on the fly, as their use
+// as `OpExtInst` operands is less likely than the `OpLine` one.
+pub(crate) fn const_spv_string_literal_for_ext_inst(cx: &Context, s: InternedStr) -> Const {
+    let ty = cx.intern(TypeDef {
+        attrs: AttrSet::default(),
+        ctor: TypeCtor::SpvStringLiteralForExtInst,
+        ctor_args: [].into_iter().collect(),
+    });
+    cx.intern(ConstDef {
+        attrs: AttrSet::default(),
+        ty,
+        ctor: ConstCtor::SpvStringLiteralForExtInst(s),
+        ctor_args: [].into_iter().collect(),
+    })
+}
+
+/// Split the member annotations (i.e. `OpMemberDecorate`, `OpMemberDecorateString`
+/// and `OpMemberName`) out of `attrs` (the attributes of an `OpTypeStruct`),
+/// returning them as the [`StructMember`]s of a [`TypeCtor::Struct`].
+fn struct_members_from_attrs(
+    cx: &Context,
+    attrs: &mut AttrSet,
+    member_count: usize,
+) -> Result<SmallVec<[StructMember; 4]>, String> {
+    let wk = &spec::Spec::get().well_known;
+
+    let mut struct_attrs = BTreeSet::new();
+    let mut members: SmallVec<[_; 4]> =
+        (0..member_count).map(|_| (BTreeSet::new(), None)).collect();
+    for attr in &cx[*attrs].attrs {
+        let (opcode, imms) = match attr {
+            Attr::SpvAnnotation(spv::Inst { opcode, imms }) => (*opcode, imms),
+            _ => {
+                struct_attrs.insert(attr.clone());
+                continue;
+            }
+        };
+        let non_member_opcode = if opcode == wk.OpMemberDecorate {
+            wk.OpDecorate
+        } else if opcode == wk.OpMemberDecorateString {
+            wk.OpDecorateString
+        } else if opcode == wk.OpMemberName {
+            wk.OpName
+        } else {
+            struct_attrs.insert(attr.clone());
+            continue;
+        };
+
+        let (member_idx, imms) = match imms[..] {
+            [spv::Imm::Short(_, member_idx), ref imms @ ..] => (member_idx, imms),
+            _ => unreachable!(),
+        };
+        let (member_attrs, member_offset) = usize::try_from(member_idx)
+            .ok()
+            .and_then(|i| members.get_mut(i))
+            .ok_or_else(|| {
+                format!(
+                    "member index {member_idx} out of range for `OpTypeStruct` \
+                     with {member_count} members"
+                )
+            })?;
+        match *imms {
+            [
+                spv::Imm::Short(decoration_kind, decoration),
+                spv::Imm::Short(_, offset),
+            ] if opcode == wk.OpMemberDecorate
+                && decoration_kind == wk.Decoration
+                && decoration == wk.Offset =>
+            {
+                if member_offset.replace(offset).is_some() {
+                    return Err(format!(
+                        "`OpTypeStruct` member #{member_idx} has more than one `Offset`"
+                    ));
+                }
+            }
+            _ => {
+                member_attrs.insert(Attr::SpvAnnotation(spv::Inst {
+                    opcode: non_member_opcode,
+                    imms: imms.iter().copied().collect(),
+                }));
+            }
+        }
+    }
+
+    *attrs = cx.intern(crate::AttrSetDef {
+        attrs: struct_attrs,
+    });
+    Ok(members
+        .into_iter()
+        .map(|(attrs, offset)| StructMember {
+            attrs: cx.intern(crate::AttrSetDef { attrs }),
+            offset,
+        })
+        .collect())
+}
+
+/// Decode the immediate operands of an `OpTypeImage` into an [`ImageType`],
+/// returning `None` if any of them are out of range (or otherwise malformed).
+fn image_type_from_imms(imms: &[spv::Imm]) -> Option<ImageType> {
+    let bool_from_imm = |x| match x {
+        0 => Some(false),
+        1 => Some(true),
+        _ => None,
+    };
+    let (dim, depth, arrayed, multisampled, sampled, format, access_qualifier) = match *imms {
+        [
+            spv::Imm::Short(_, dim),
+            spv::Imm::Short(_, depth),
+            spv::Imm::Short(_, arrayed),
+            spv::Imm::Short(_, multisampled),
+            spv::Imm::Short(_, sampled),
+            spv::Imm::Short(_, format),
+            ref access_qualifier @ ..,
+        ] => (
+            dim,
+            depth,
+            arrayed,
+            multisampled,
+            sampled,
+            format,
+            access_qualifier,
+        ),
+        _ => return None,
+    };
+    Some(ImageType {
+        dim,
+        depth: match depth {
+            2 => None,
+            _ => Some(bool_from_imm(depth)?),
+        },
+        arrayed: bool_from_imm(arrayed)?,
+        multisampled: bool_from_imm(multisampled)?,
+        sampled: match sampled {
+            0 => None,
+            1 => Some(true),
+            2 => Some(false),
+            _ => return None,
+        },
+        format,
+        access_qualifier: match *access_qualifier {
+            [] => None,
+            [spv::Imm::Short(_, access_qualifier)] => Some(access_qualifier),
+            _ => return None,
+        },
+    })
 }
 
 // FIXME(eddyb) provide more information about any normalization that happened:
@@ -95,21 +407,53 @@ fn invalid(reason: &str) -> io::Error {
 // (and more directproducers) can keep around errors in the SPIR-T IR, and still
 // have the opportunity of silencing them e.g. by removing dead code.
 impl Module {
-    pub fn lower_from_spv_file(cx: Rc<Context>, path: impl AsRef<Path>) -> io::Result<Self> {
-        Self::lower_from_spv_module_parser(cx, spv::read::ModuleParser::read_from_spv_file(path)?)
+    pub fn lower_from_spv_file(
+        cx: Rc<Context>,
+        path: impl AsRef<Path>,
+    ) -> Result<Self, LowerError> {
+        Self::lower_from_spv_module_parser(
+            cx,
+            spv::read::ModuleParser::read_from_spv_file(path).map_err(LowerError::Read)?,
+        )
     }
 
-    pub fn lower_from_spv_bytes(cx: Rc<Context>, spv_bytes: Vec<u8>) -> io::Result<Self> {
+    /// Lower an in-memory SPIR-V module, from its bytes, without copying them (see
+    /// also [`ModuleParser::read_from_spv_byte_slice`](spv::read::ModuleParser)).
+    pub fn lower_from_spv_bytes(
+        cx: Rc<Context>,
+        spv_bytes: impl AsRef<[u8]>,
+    ) -> Result<Self, LowerError> {
         Self::lower_from_spv_module_parser(
             cx,
-            spv::read::ModuleParser::read_from_spv_bytes(spv_bytes)?,
+            spv::read::ModuleParser::read_from_spv_byte_slice(spv_bytes.as_ref())
+                .map_err(LowerError::Read)?,
+        )
+    }
+
+    /// Lower an in-memory SPIR-V module, from its words (see also
+    /// [`ModuleParser::read_from_spv_words`](spv::read::ModuleParser::read_from_spv_words)).
+    pub fn lower_from_spv_words(
+        cx: Rc<Context>,
+        spv_words: impl Into<Vec<u32>>,
+    ) -> Result<Self, LowerError> {
+        Self::lower_from_spv_module_parser(
+            cx,
+            spv::read::ModuleParser::read_from_spv_words(spv_words).map_err(LowerError::Read)?,
         )
     }
 
     pub fn lower_from_spv_module_parser(
         cx: Rc<Context>,
-        parser: spv::read::ModuleParser,
-    ) -> io::Result<Self> {
+        parser: spv::read::ModuleParser<'_>,
+    ) -> Result<Self, LowerError> {
+        Self::lower_from_spv_module_parser_with_options(cx, parser, &LowerOptions::default())
+    }
+
+    pub fn lower_from_spv_module_parser_with_options(
+        cx: Rc<Context>,
+        parser: spv::read::ModuleParser<'_>,
+        options: &LowerOptions,
+    ) -> Result<Self, LowerError> {
         let spv_spec = spec::Spec::get();
         let wk = &spv_spec.well_known;
 
@@ -196,19 +540,57 @@ impl Module {
 
         let mut has_memory_model = false;
         let mut pending_attrs = FxHashMap::<spv::Id, crate::AttrSetDef>::default();
+        let mut decoration_groups = FxHashMap::<spv::Id, BTreeSet<Attr>>::default();
         let mut pending_imports = FxHashMap::<spv::Id, Import>::default();
         let mut pending_exports = vec![];
+        let mut pending_execution_mode_ids = vec![];
         let mut current_debug_line = None;
+        let mut current_shader_debug_scope = None;
+        let mut current_shader_debug_line = None;
         let mut current_block_id = None; // HACK(eddyb) for `current_debug_line` resets.
         let mut id_defs = FxHashMap::default();
+        let mut forward_ptr_placeholders = FxHashMap::default();
         let mut pending_func_bodies = vec![];
-        let mut current_func_body = None;
+        let mut current_func_body = None::<FuncBody>;
+
+        // In permissive mode, some errors can be "deferred", by attaching them
+        // to the `attrs` of the definition being lowered, instead of failing.
+        let defer_error = |attrs: &mut AttrSet, e: LowerError| {
+            if options.permissive {
+                *attrs = attrs_with_unsupported(&cx, *attrs, &e);
+                Ok(())
+            } else {
+                Err(e)
+            }
+        };
 
-        let mut spv_insts = parser.peekable();
-        while let Some(mut inst) = spv_insts.next().transpose()? {
+        let mut spv_insts = parser.enumerate().peekable();
+        while let Some((inst_idx, inst)) = spv_insts.next() {
+            let mut inst = match inst {
+                Ok(inst) => inst,
+
+                // NOTE(eddyb) the parser skips past instructions it can't
+                // decode (e.g. due to unknown opcodes), so they can be ignored,
+                // as long as there's a function to attach the error to.
+                Err(e) => match &current_func_body {
+                    Some(func_body) => {
+                        let func_attrs = &mut module.funcs[func_body.func].attrs;
+                        defer_error(func_attrs, LowerError::Read(e))?;
+                        continue;
+                    }
+                    None => return Err(LowerError::Read(e)),
+                },
+            };
             let opcode = inst.opcode;
 
-            let invalid = |msg: &str| invalid(&format!("in {}: {}", opcode.name(), msg));
+            let invalid_operand =
+                |operand: Option<LowerErrorOperand>, msg: &str| LowerError::Inst {
+                    inst_idx,
+                    opcode,
+                    operand,
+                    message: msg.to_string(),
+                };
+            let invalid = |msg: &str| invalid_operand(None, msg);
 
             // Handle line debuginfo early, as it doesn't have its own section,
             // but rather can go almost anywhere among globals and functions.
@@ -225,9 +607,10 @@ impl Module {
                             let file_path = match id_defs.get(&file_path_id) {
                                 Some(&IdDef::SpvDebugString(s)) => s,
                                 _ => {
-                                    return Err(invalid(&format!(
-                                        "%{file_path_id} is not an OpString"
-                                    )));
+                                    return Err(invalid_operand(
+                                        Some(LowerErrorOperand::Id(file_path_id)),
+                                        &format!("%{file_path_id} is not an OpString"),
+                                    ));
                                 }
                             };
                             Some((file_path, line, col))
@@ -245,6 +628,109 @@ impl Module {
                 continue;
             }
 
+            // Handle `NonSemantic.Shader.DebugInfo.100` scope/line debuginfo
+            // early as well, as (like `OpLine`) it only affects the following
+            // instructions (but it can only appear inside functions).
+            let shader_debuginfo_inst = match (&inst.imms[..], inst.ids.first()) {
+                (&[spv::Imm::Short(_, ext_inst)], Some(ext_set_id))
+                    if opcode == wk.OpExtInst && current_func_body.is_some() =>
+                {
+                    match id_defs.get(ext_set_id) {
+                        Some(&IdDef::SpvExtInstImport(name))
+                            if cx[name] == *shader_debuginfo::EXT_INST_SET_NAME =>
+                        {
+                            Some(ext_inst)
+                        }
+                        _ => None,
+                    }
+                }
+                _ => None,
+            }
+            .filter(|ext_inst| {
+                [
+                    shader_debuginfo::DEBUG_SCOPE,
+                    shader_debuginfo::DEBUG_NO_SCOPE,
+                    shader_debuginfo::DEBUG_LINE,
+                    shader_debuginfo::DEBUG_NO_LINE,
+                ]
+                .contains(ext_inst)
+            });
+            if let Some(ext_inst) = shader_debuginfo_inst {
+                let operands = inst.ids[1..]
+                    .iter()
+                    .map(|&id| {
+                        match id_defs.get(&id) {
+                            Some(&IdDef::Const(ct)) => Ok(ct),
+                            Some(id_def) => Err(id_def.descr(&cx)),
+                            None => Err(format!("a forward reference to %{id}")),
+                        }
+                        .map_err(|descr| {
+                            invalid_operand(
+                                Some(LowerErrorOperand::Id(id)),
+                                &format!("unsupported use of {descr} in debuginfo"),
+                            )
+                        })
+                    })
+                    .collect::<Result<SmallVec<[_; 5]>, _>>();
+
+                // Line/column operands are (unsigned) 32-bit integer constants.
+                let const_u32 = |ct: Const| match &cx[ct].ctor {
+                    ConstCtor::SpvInst(spv::Inst { opcode, imms }) if *opcode == wk.OpConstant => {
+                        match imms[..] {
+                            [spv::Imm::Short(_, x)] => Some(x),
+                            _ => None,
+                        }
+                    }
+                    _ => None,
+                };
+
+                let result = operands.and_then(|operands| match (ext_inst, &operands[..]) {
+                    (shader_debuginfo::DEBUG_SCOPE, &[scope]) => {
+                        current_shader_debug_scope = Some((scope, None));
+                        Ok(())
+                    }
+                    (shader_debuginfo::DEBUG_SCOPE, &[scope, inlined_at]) => {
+                        current_shader_debug_scope = Some((scope, Some(inlined_at)));
+                        Ok(())
+                    }
+                    (shader_debuginfo::DEBUG_NO_SCOPE, &[]) => {
+                        current_shader_debug_scope = None;
+                        Ok(())
+                    }
+                    (
+                        shader_debuginfo::DEBUG_LINE,
+                        &[source, line_start, line_end, col_start, col_end],
+                    ) => match [line_start, line_end, col_start, col_end].map(const_u32) {
+                        [
+                            Some(line_start),
+                            Some(line_end),
+                            Some(col_start),
+                            Some(col_end),
+                        ] => {
+                            current_shader_debug_line =
+                                Some((source, line_start, line_end, col_start, col_end));
+                            Ok(())
+                        }
+                        _ => Err(invalid(
+                            "`DebugLine` line/column operands must be 32-bit integer constants",
+                        )),
+                    },
+                    (shader_debuginfo::DEBUG_NO_LINE, &[]) => {
+                        current_shader_debug_line = None;
+                        Ok(())
+                    }
+                    _ => Err(invalid(
+                        "wrong number of operands for debuginfo instruction",
+                    )),
+                });
+                if let Err(e) = result {
+                    let func_attrs =
+                        &mut module.funcs[current_func_body.as_ref().unwrap().func].attrs;
+                    defer_error(func_attrs, e)?;
+                }
+                continue;
+            }
+
             // Reset line debuginfo when crossing/leaving blocks.
             let new_block_id = if opcode == wk.OpLabel {
                 Some(inst.result_id.unwrap())
@@ -255,6 +741,8 @@ impl Module {
             };
             if current_block_id != new_block_id {
                 current_debug_line = None;
+                current_shader_debug_scope = None;
+                current_shader_debug_line = None;
             }
             current_block_id = new_block_id;
 
@@ -271,19 +759,50 @@ impl Module {
                     col,
                 });
             }
+            if let Some((scope, inlined_at)) = current_shader_debug_scope {
+                attrs.attrs.insert(Attr::SpvShaderDebugScope {
+                    scope: crate::OrdAssertEq(scope),
+                    inlined_at: inlined_at.map(crate::OrdAssertEq),
+                });
+            }
+            if let Some((source, line_start, line_end, col_start, col_end)) =
+                current_shader_debug_line
+            {
+                attrs.attrs.insert(Attr::SpvShaderDebugLine {
+                    source: crate::OrdAssertEq(source),
+                    line_start,
+                    line_end,
+                    col_start,
+                    col_end,
+                });
+            }
 
             // Take certain bitflags operands out of the instruction and rewrite
             // them into attributes instead.
             inst.imms.retain(|imm| match *imm {
                 spv::Imm::Short(kind, word) if kind == wk.FunctionControl => {
                     if word != 0 {
-                        attrs.attrs.insert(Attr::SpvBitflagsOperand(*imm));
+                        attrs
+                            .attrs
+                            .insert(Attr::SpvBitflagsOperand([*imm].into_iter().collect()));
                     }
                     false
                 }
                 _ => true,
             });
 
+            // Record the original result ID of definitions that don't get
+            // deduplicated (i.e. neither types nor constants), and which have
+            // somewhere to keep their attributes (i.e. not `OpLabel`).
+            if let Some(id) = inst.result_id {
+                let is_module_level_def = [wk.OpVariable, wk.OpFunction].contains(&opcode);
+                let is_func_local_def =
+                    current_func_body.is_some() && ![wk.OpLabel, wk.OpUndef].contains(&opcode);
+                if is_module_level_def || is_func_local_def {
+                    attrs.attrs.insert(Attr::SpvOriginalId(id));
+                }
+            }
+
             let mut attrs = cx.intern(attrs);
 
             // FIXME(eddyb) move this kind of lookup into methods on some sort
@@ -292,12 +811,18 @@ impl Module {
                 .result_type_id
                 .map(|type_id| match id_defs.get(&type_id) {
                     Some(&IdDef::Type(ty)) => Ok(ty),
-                    Some(id_def) => Err(invalid(&format!(
-                        "result type %{} should be a type, not a {}",
-                        type_id,
-                        id_def.descr(&cx)
-                    ))),
-                    None => Err(invalid(&format!("result type %{type_id} not defined"))),
+                    Some(id_def) => Err(invalid_operand(
+                        Some(LowerErrorOperand::Id(type_id)),
+                        &format!(
+                            "result type %{} should be a type, not {}",
+                            type_id,
+                            id_def.descr(&cx)
+                        ),
+                    )),
+                    None => Err(invalid_operand(
+                        Some(LowerErrorOperand::Id(type_id)),
+                        &format!("result type %{type_id} not defined"),
+                    )),
                 })
                 .transpose()?;
 
@@ -405,9 +930,10 @@ impl Module {
                         let file_path = match id_defs.get(&file_path_id) {
                             Some(&IdDef::SpvDebugString(s)) => s,
                             _ => {
-                                return Err(invalid(&format!(
-                                    "%{file_path_id} is not an OpString"
-                                )));
+                                return Err(invalid_operand(
+                                    Some(LowerErrorOperand::Id(file_path_id)),
+                                    &format!("%{file_path_id} is not an OpString"),
+                                ));
                             }
                         };
                         let mut contents = if contents.is_empty() {
@@ -418,11 +944,11 @@ impl Module {
                         };
 
                         // Absorb all following `OpSourceContinued` into `contents`.
-                        while let Some(Ok(cont_inst)) = spv_insts.peek() {
+                        while let Some((_, Ok(cont_inst))) = spv_insts.peek() {
                             if cont_inst.opcode != wk.OpSourceContinued {
                                 break;
                             }
-                            let cont_inst = spv_insts.next().unwrap().unwrap();
+                            let cont_inst = spv_insts.next().unwrap().1.unwrap();
 
                             assert!(
                                 cont_inst.result_type_id.is_none()
@@ -485,15 +1011,23 @@ impl Module {
                 assert!(inst.result_type_id.is_none() && inst.result_id.is_none());
 
                 pending_exports.push(Export::EntryPoint {
+                    inst_idx,
                     func_id: inst.ids[0],
                     imms: inst.without_ids.imms,
                     interface_ids: inst.ids[1..].iter().copied().collect(),
                 });
 
                 Seq::EntryPoint
+            } else if opcode == wk.OpExecutionModeId {
+                assert!(inst.result_type_id.is_none() && inst.result_id.is_none());
+
+                // NOTE(eddyb) the constants used as operands can only be defined
+                // later in the module, so this is resolved after all instructions.
+                pending_execution_mode_ids.push((inst_idx, inst));
+
+                Seq::ExecutionMode
             } else if [
                 wk.OpExecutionMode,
-                wk.OpExecutionModeId, // FIXME(eddyb) not actually supported
                 wk.OpName,
                 wk.OpMemberName,
                 wk.OpDecorate,
@@ -508,7 +1042,20 @@ impl Module {
 
                 let target_id = inst.ids[0];
                 if inst.ids.len() > 1 {
-                    return Err(invalid("unsupported decoration with ID"));
+                    // NOTE(eddyb) the decoration is kept (only in permissive
+                    // mode), just without its extra ID operands.
+                    let target_attrs = pending_attrs.entry(target_id).or_default();
+                    let mut diag_attrs = AttrSet::default();
+                    defer_error(
+                        &mut diag_attrs,
+                        invalid_operand(
+                            Some(LowerErrorOperand::Id(inst.ids[1])),
+                            "unsupported decoration with ID",
+                        ),
+                    )?;
+                    target_attrs
+                        .attrs
+                        .extend(cx[diag_attrs].attrs.iter().cloned());
                 }
 
                 match inst.imms[..] {
@@ -529,7 +1076,11 @@ impl Module {
                         if linkage_type == wk.Import {
                             pending_imports.insert(target_id, Import::LinkName(name));
                         } else {
-                            pending_exports.push(Export::Linkage { name, target_id });
+                            pending_exports.push(Export::Linkage {
+                                inst_idx,
+                                name,
+                                target_id,
+                            });
                         }
                     }
 
@@ -542,7 +1093,7 @@ impl Module {
                     }
                 };
 
-                if [wk.OpExecutionMode, wk.OpExecutionModeId].contains(&opcode) {
+                if opcode == wk.OpExecutionMode {
                     Seq::ExecutionMode
                 } else if [wk.OpName, wk.OpMemberName].contains(&opcode) {
                     Seq::DebugName
@@ -556,9 +1107,85 @@ impl Module {
             ]
             .contains(&opcode)
             {
-                return Err(invalid(
-                    "unsupported decoration groups (officially deprecated)",
-                ));
+                // NOTE(eddyb) decoration groups are expanded, i.e. all the
+                // decorations applied to a group (which have to precede its
+                // `OpDecorationGroup`) are applied directly to its targets.
+                //
+                // FIXME(eddyb) consider regrouping decorations when lifting
+                // (but decoration groups are officially deprecated anyway).
+                if opcode == wk.OpDecorationGroup {
+                    let group_id = inst.result_id.unwrap();
+                    let group_attrs: AttrSet = mem::take(&mut attrs);
+                    let group_attrs: BTreeSet<_> = cx[group_attrs]
+                        .attrs
+                        .iter()
+                        .filter(|attr| match attr {
+                            Attr::SpvAnnotation(spv::Inst { opcode, .. }) => {
+                                [wk.OpDecorate, wk.OpDecorateId, wk.OpDecorateString]
+                                    .contains(opcode)
+                            }
+                            Attr::SpvUnsupported(_) => true,
+                            _ => false,
+                        })
+                        .cloned()
+                        .collect();
+                    decoration_groups.insert(group_id, group_attrs);
+                } else {
+                    let group_id = inst.ids[0];
+                    let group_attrs: &BTreeSet<_> =
+                        decoration_groups.get(&group_id).ok_or_else(|| {
+                            invalid_operand(
+                                Some(LowerErrorOperand::Id(group_id)),
+                                "expected `OpDecorationGroup`",
+                            )
+                        })?;
+                    if opcode == wk.OpGroupDecorate {
+                        for &target_id in &inst.ids[1..] {
+                            pending_attrs
+                                .entry(target_id)
+                                .or_default()
+                                .attrs
+                                .extend(group_attrs.iter().cloned());
+                        }
+                    } else {
+                        // `OpGroupMemberDecorate` alternates target IDs and
+                        // member indices, the latter kept as immediates.
+                        if inst.ids.len() - 1 != inst.imms.len() {
+                            return Err(invalid("mismatched target IDs and member indices"));
+                        }
+                        for (&target_id, &member_idx) in inst.ids[1..].iter().zip(&inst.imms) {
+                            let member_idx = match member_idx {
+                                spv::Imm::Short(_, member_idx) => {
+                                    spv::Imm::Short(wk.LiteralInteger, member_idx)
+                                }
+                                _ => unreachable!(),
+                            };
+                            let member_attrs = group_attrs.iter().map(|attr| match attr {
+                                Attr::SpvAnnotation(spv::Inst { opcode, imms }) => {
+                                    let member_opcode = if *opcode == wk.OpDecorateString {
+                                        wk.OpMemberDecorateString
+                                    } else {
+                                        wk.OpMemberDecorate
+                                    };
+                                    Attr::SpvAnnotation(spv::Inst {
+                                        opcode: member_opcode,
+                                        imms: iter::once(member_idx)
+                                            .chain(imms.iter().copied())
+                                            .collect(),
+                                    })
+                                }
+                                _ => attr.clone(),
+                            });
+                            pending_attrs
+                                .entry(target_id)
+                                .or_default()
+                                .attrs
+                                .extend(member_attrs);
+                        }
+                    }
+                }
+
+                Seq::Decoration
             } else if opcode == wk.OpTypeForwardPointer {
                 assert!(inst.result_type_id.is_none() && inst.result_id.is_none());
                 let (id, sc) = match (&inst.imms[..], &inst.ids[..]) {
@@ -566,68 +1193,263 @@ impl Module {
                     _ => unreachable!(),
                 };
 
-                // HACK(eddyb) this is not a proper implementation - one would
-                // require fixpoint (aka "μ" aka "mu") types - but for now this
-                // serves as a first approximation for a "deferred error".
-                let ty = cx.intern(TypeDef {
-                    attrs: mem::take(&mut attrs),
+                // NOTE(eddyb) until the `OpTypePointer` defining `id` is found,
+                // a unique placeholder type is used in its stead, to be replaced
+                // with the actual pointer type, which itself will be recursive
+                // (see `TypeCtor::RecursivePtr`) if its pointee uses `id`.
+                // If that `OpTypePointer` is never found, the placeholder remains,
+                // as a first approximation for a "deferred error".
+                let mut placeholder_attrs = cx[mem::take(&mut attrs)].attrs.clone();
+                placeholder_attrs.insert(Attr::SpvOriginalId(id));
+                let placeholder = cx.intern(TypeDef {
+                    attrs: cx.intern(AttrSetDef {
+                        attrs: placeholder_attrs,
+                    }),
                     ctor: TypeCtor::SpvInst(spv::Inst {
                         opcode,
                         imms: [sc].into_iter().collect(),
                     }),
                     ctor_args: [].into_iter().collect(),
                 });
-                id_defs.insert(id, IdDef::Type(ty));
+                id_defs.insert(id, IdDef::Type(placeholder));
+                forward_ptr_placeholders.insert(id, placeholder);
 
                 Seq::TypeConstOrGlobalVar
             } else if inst_category == spec::InstructionCategory::Type {
                 assert!(inst.result_type_id.is_none());
                 let id = inst.result_id.unwrap();
-                let type_ctor_args = inst
+                let mut type_ctor_args: SmallVec<[_; 2]> = match inst
                     .ids
                     .iter()
-                    .map(|&id| match id_defs.get(&id) {
-                        Some(&IdDef::Type(ty)) => Ok(TypeCtorArg::Type(ty)),
-                        Some(&IdDef::Const(ct)) => Ok(TypeCtorArg::Const(ct)),
-                        Some(id_def) => Err(id_def.descr(&cx)),
-                        None => Err(format!("a forward reference to %{id}")),
-                    })
-                    .map(|result| {
-                        result.map_err(|descr| {
-                            invalid(&format!("unsupported use of {descr} in a type"))
+                    .map(|&id| {
+                        match id_defs.get(&id) {
+                            Some(&IdDef::Type(ty)) => Ok(TypeCtorArg::Type(ty)),
+                            Some(&IdDef::Const(ct)) => Ok(TypeCtorArg::Const(ct)),
+                            Some(id_def) => Err(id_def.descr(&cx)),
+                            None => Err(format!("a forward reference to %{id}")),
+                        }
+                        .map_err(|descr| {
+                            invalid_operand(
+                                Some(LowerErrorOperand::Id(id)),
+                                &format!("unsupported use of {descr} in a type"),
+                            )
                         })
                     })
-                    .collect::<Result<_, _>>()?;
+                    .collect::<Result<_, _>>()
+                {
+                    Ok(type_ctor_args) => type_ctor_args,
+                    Err(e) => {
+                        // Opaque type (without any operands), in permissive mode.
+                        defer_error(&mut attrs, e)?;
+                        [].into_iter().collect()
+                    }
+                };
+
+                // NOTE(eddyb) `OpTypeStruct`s whose operands couldn't all be
+                // lowered (i.e. opaque, in permissive mode) are left as-is.
+                let all_ctor_args_lowered = type_ctor_args.len() == inst.ids.len();
+
+                // Pointer types declared by `OpTypeForwardPointer` become
+                // recursive if their own placeholder is used in their pointee.
+                let mut recursive_ptr_placeholder = None;
+                if let (&[spv::Imm::Short(..)], [TypeCtorArg::Type(pointee)]) =
+                    (&inst.imms[..], &mut type_ctor_args[..])
+                {
+                    if let Some(placeholder) = forward_ptr_placeholders.remove(&id) {
+                        let recursive_ptr_self = |depth| {
+                            cx.intern(TypeDef {
+                                attrs: AttrSet::default(),
+                                ctor: TypeCtor::RecursivePtrSelf { depth },
+                                ctor_args: [].into_iter().collect(),
+                            })
+                        };
+                        let folded_pointee = SubstTypes::new(&cx, |ty, depth| {
+                            (ty == placeholder).then(|| recursive_ptr_self(depth))
+                        })
+                        .transform_type_use(*pointee);
+                        if let Transformed::Changed(folded_pointee) = folded_pointee {
+                            *pointee = folded_pointee;
+                            recursive_ptr_placeholder = Some(placeholder);
+                        }
+                    }
+                }
+
+                let ctor = match (&inst.imms[..], &type_ctor_args[..]) {
+                    (&[spv::Imm::Short(_, storage_class)], [TypeCtorArg::Type(_)])
+                        if recursive_ptr_placeholder.is_some() =>
+                    {
+                        TypeCtor::RecursivePtr { storage_class }
+                    }
+                    (&[spv::Imm::Short(_, column_count)], [TypeCtorArg::Type(_)])
+                        if opcode == wk.OpTypeMatrix =>
+                    {
+                        TypeCtor::Matrix { column_count }
+                    }
+                    ([], [TypeCtorArg::Type(_), TypeCtorArg::Const(_)])
+                        if opcode == wk.OpTypeArray =>
+                    {
+                        TypeCtor::Array
+                    }
+                    ([], [TypeCtorArg::Type(_)]) if opcode == wk.OpTypeRuntimeArray => {
+                        TypeCtor::RuntimeArray
+                    }
+                    (imms, [TypeCtorArg::Type(_)]) if opcode == wk.OpTypeImage => {
+                        match image_type_from_imms(imms) {
+                            Some(image_type) => TypeCtor::Image(image_type),
+                            None => TypeCtor::SpvInst(inst.without_ids),
+                        }
+                    }
+                    ([], []) if opcode == wk.OpTypeSampler => TypeCtor::Sampler,
+                    ([], [TypeCtorArg::Type(image_type)])
+                        if opcode == wk.OpTypeSampledImage
+                            && matches!(cx[*image_type].ctor, TypeCtor::Image(_)) =>
+                    {
+                        TypeCtor::SampledImage
+                    }
+                    _ if opcode == wk.OpTypeStruct && all_ctor_args_lowered => {
+                        match struct_members_from_attrs(&cx, &mut attrs, type_ctor_args.len()) {
+                            Ok(members) => TypeCtor::Struct { members },
+                            Err(e) => {
+                                defer_error(&mut attrs, invalid(&e))?;
+                                TypeCtor::SpvInst(inst.without_ids)
+                            }
+                        }
+                    }
+                    _ => TypeCtor::SpvInst(inst.without_ids),
+                };
 
                 let ty = cx.intern(TypeDef {
                     attrs: mem::take(&mut attrs),
-                    ctor: TypeCtor::SpvInst(inst.without_ids),
+                    ctor,
                     ctor_args: type_ctor_args,
                 });
+
+                // Replace the placeholder in all the types/consts defined since
+                // the `OpTypeForwardPointer` (with the pointer type itself).
+                //
+                // FIXME(eddyb) this doesn't handle e.g. `GlobalVar`s declared
+                // before the `OpTypePointer`, nor is it very efficient.
+                if let Some(placeholder) = recursive_ptr_placeholder {
+                    let mut replace_placeholder =
+                        SubstTypes::new(&cx, |old_ty, _| (old_ty == placeholder).then_some(ty));
+                    for id_def in id_defs.values_mut() {
+                        match id_def {
+                            IdDef::Type(old_ty) => {
+                                replace_placeholder
+                                    .transform_type_use(*old_ty)
+                                    .apply_to(old_ty);
+                            }
+                            IdDef::Const(ct) => {
+                                replace_placeholder.transform_const_use(*ct).apply_to(ct);
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+
                 id_defs.insert(id, IdDef::Type(ty));
 
                 Seq::TypeConstOrGlobalVar
             } else if inst_category == spec::InstructionCategory::Const || opcode == wk.OpUndef {
                 let id = inst.result_id.unwrap();
-                let const_ctor_args = inst
+                let const_ctor_args = match inst
                     .ids
                     .iter()
-                    .map(|&id| match id_defs.get(&id) {
-                        Some(&IdDef::Const(ct)) => Ok(ct),
-                        Some(id_def) => Err(id_def.descr(&cx)),
-                        None => Err(format!("a forward reference to %{id}")),
-                    })
-                    .map(|result| {
-                        result.map_err(|descr| {
-                            invalid(&format!("unsupported use of {descr} in a constant"))
+                    .map(|&id| {
+                        match id_defs.get(&id) {
+                            Some(&IdDef::Const(ct)) => Ok(ct),
+                            Some(id_def) => Err(id_def.descr(&cx)),
+                            None => Err(format!("a forward reference to %{id}")),
+                        }
+                        .map_err(|descr| {
+                            invalid_operand(
+                                Some(LowerErrorOperand::Id(id)),
+                                &format!("unsupported use of {descr} in a constant"),
+                            )
                         })
                     })
-                    .collect::<Result<_, _>>()?;
+                    .collect::<Result<_, _>>()
+                {
+                    Ok(const_ctor_args) => const_ctor_args,
+                    Err(e) => {
+                        // Opaque constant (without any operands), in permissive mode.
+                        defer_error(&mut attrs, e)?;
+                        [].into_iter().collect()
+                    }
+                };
+
+                // Spec constants (with a `SpecId`) get their own `ConstCtor`,
+                // which the `SpecId` decoration is moved into.
+                let default_opcode = if opcode == wk.OpSpecConstantTrue {
+                    Some(wk.OpConstantTrue)
+                } else if opcode == wk.OpSpecConstantFalse {
+                    Some(wk.OpConstantFalse)
+                } else if opcode == wk.OpSpecConstant {
+                    Some(wk.OpConstant)
+                } else {
+                    None
+                };
+                let spec_id_attr_and_value = default_opcode.and_then(|_| {
+                    cx[attrs].attrs.iter().find_map(|attr| match attr {
+                        Attr::SpvAnnotation(spv::Inst { opcode, imms })
+                            if *opcode == wk.OpDecorate =>
+                        {
+                            match imms[..] {
+                                [spv::Imm::Short(_, decoration), spv::Imm::Short(_, spec_id)]
+                                    if decoration == wk.SpecId =>
+                                {
+                                    Some((attr.clone(), spec_id))
+                                }
+                                _ => None,
+                            }
+                        }
+                        _ => None,
+                    })
+                });
+                let ctor = match (default_opcode, spec_id_attr_and_value) {
+                    (Some(default_opcode), Some((spec_id_attr, spec_id))) => {
+                        let mut attrs_without_spec_id = cx[attrs].attrs.clone();
+                        attrs_without_spec_id.remove(&spec_id_attr);
+                        attrs = cx.intern(crate::AttrSetDef {
+                            attrs: attrs_without_spec_id,
+                        });
+
+                        ConstCtor::SpecConst {
+                            spec_id,
+                            default: spv::Inst {
+                                opcode: default_opcode,
+                                imms: inst.without_ids.imms,
+                            },
+                        }
+                    }
+                    _ if opcode == wk.OpUndef => ConstCtor::Undef,
+                    _ if opcode == wk.OpSpecConstantOp => {
+                        let mut imms = inst.without_ids.imms.into_iter();
+                        let op_opcode = match imms.next() {
+                            Some(spv::Imm::Short(kind, word))
+                                if kind == wk.LiteralSpecConstantOpInteger =>
+                            {
+                                // NOTE(eddyb) already validated by `spv::read`.
+                                u16::try_from(word)
+                                    .ok()
+                                    .and_then(spec::Opcode::try_from_u16_with_name_and_def)
+                                    .unwrap()
+                                    .0
+                            }
+                            _ => unreachable!(),
+                        };
+                        ConstCtor::SpecConstOp(spv::Inst {
+                            opcode: op_opcode,
+                            imms: imms.collect(),
+                        })
+                    }
+                    _ => ConstCtor::SpvInst(inst.without_ids),
+                };
 
                 let ct = cx.intern(ConstDef {
                     attrs: mem::take(&mut attrs),
                     ty: result_type.unwrap(),
-                    ctor: ConstCtor::SpvInst(inst.without_ids),
+                    ctor,
                     ctor_args: const_ctor_args,
                 });
                 id_defs.insert(id, IdDef::Const(ct));
@@ -639,12 +1461,103 @@ impl Module {
                 } else {
                     Seq::TypeConstOrGlobalVar
                 }
+            } else if opcode == wk.OpExtInst && current_func_body.is_none() {
+                let id = inst.result_id.unwrap();
+                let ext_inst = match inst.imms[..] {
+                    [spv::Imm::Short(kind, ext_inst)] => {
+                        assert!(kind == wk.LiteralExtInstInteger);
+                        ext_inst
+                    }
+                    _ => unreachable!(),
+                };
+                let ext_set_id = inst.ids[0];
+                let ext_set = match id_defs.get(&ext_set_id) {
+                    Some(&IdDef::SpvExtInstImport(name)) => name,
+                    Some(id_def) => {
+                        return Err(invalid_operand(
+                            Some(LowerErrorOperand::Id(ext_set_id)),
+                            &format!(
+                                "unsupported use of {} as the `OpExtInst` \
+                                 extended instruction set ID",
+                                id_def.descr(&cx)
+                            ),
+                        ));
+                    }
+                    None => {
+                        return Err(invalid_operand(
+                            Some(LowerErrorOperand::Id(ext_set_id)),
+                            &format!("unknown ID %{ext_set_id}"),
+                        ));
+                    }
+                };
+
+                // NOTE(eddyb) only non-semantic extended instruction sets are
+                // allowed outside functions (by the SPIR-V specification).
+                if !cx[ext_set].starts_with("NonSemantic.") {
+                    return Err(invalid(&format!(
+                        "`OpExtInst` of {:?} outside function (only allowed for \
+                         non-semantic extended instruction sets)",
+                        &cx[ext_set]
+                    )));
+                }
+
+                // FIXME(eddyb) support references to functions (used by e.g.
+                // `DebugFunction` in `NonSemantic.Shader.DebugInfo.100`, only
+                // through `DebugFunctionDefinition` inside the function), and
+                // forward references (allowed for some operands).
+                let const_ctor_args = match inst.ids[1..]
+                    .iter()
+                    .map(|&id| {
+                        match id_defs.get(&id) {
+                            Some(&IdDef::Const(ct)) => Ok(ct),
+                            Some(&IdDef::SpvDebugString(s)) => {
+                                Ok(const_spv_string_literal_for_ext_inst(&cx, s))
+                            }
+                            Some(id_def) => Err(id_def.descr(&cx)),
+                            None => Err(format!("a forward reference to %{id}")),
+                        }
+                        .map_err(|descr| {
+                            invalid_operand(
+                                Some(LowerErrorOperand::Id(id)),
+                                &format!(
+                                    "unsupported use of {descr} in a module-level `OpExtInst`"
+                                ),
+                            )
+                        })
+                    })
+                    .collect::<Result<_, _>>()
+                {
+                    Ok(const_ctor_args) => const_ctor_args,
+                    Err(e) => {
+                        // Opaque `OpExtInst` (without any operands), in permissive mode.
+                        defer_error(&mut attrs, e)?;
+                        [].into_iter().collect()
+                    }
+                };
+
+                let ct = cx.intern(ConstDef {
+                    attrs: mem::take(&mut attrs),
+                    ty: result_type.unwrap(),
+                    ctor: ConstCtor::SpvExtInst {
+                        ext_set,
+                        inst: ext_inst,
+                    },
+                    ctor_args: const_ctor_args,
+                });
+                id_defs.insert(id, IdDef::Const(ct));
+
+                Seq::TypeConstOrGlobalVar
             } else if opcode == wk.OpVariable && current_func_body.is_none() {
                 let global_var_id = inst.result_id.unwrap();
                 let type_of_ptr_to_global_var = result_type.unwrap();
 
                 if inst.imms[0] == storage_class_function_imm {
-                    return Err(invalid("`Function` storage class outside function"));
+                    return Err(invalid_operand(
+                        Some(LowerErrorOperand::Imms(
+                            [inst.imms[0]].into_iter().collect(),
+                        )),
+                        "`Function` storage class outside function",
+                    ));
                 }
 
                 let storage_class = match inst.imms[..] {
@@ -660,18 +1573,31 @@ impl Module {
                     _ => unreachable!(),
                 };
 
-                let initializer = initializer
-                    .map(|id| match id_defs.get(&id) {
-                        Some(&IdDef::Const(ct)) => Ok(ct),
-                        Some(id_def) => Err(id_def.descr(&cx)),
-                        None => Err(format!("a forward reference to %{id}")),
+                let initializer = match initializer
+                    .map(|id| {
+                        match id_defs.get(&id) {
+                            Some(&IdDef::Const(ct)) => Ok(ct),
+                            Some(id_def) => Err(id_def.descr(&cx)),
+                            None => Err(format!("a forward reference to %{id}")),
+                        }
+                        .map_err(|descr| {
+                            invalid_operand(
+                                Some(LowerErrorOperand::Id(id)),
+                                &format!(
+                                    "unsupported use of {descr} as the initializer of a global variable"
+                                ),
+                            )
+                        })
                     })
                     .transpose()
-                    .map_err(|descr| {
-                        invalid(&format!(
-                            "unsupported use of {descr} as the initializer of a global variable"
-                        ))
-                    })?;
+                {
+                    Ok(initializer) => initializer,
+                    Err(e) => {
+                        // No initializer, in permissive mode.
+                        defer_error(&mut attrs, e)?;
+                        None
+                    }
+                };
 
                 let def = match pending_imports.remove(&global_var_id) {
                     Some(import @ Import::LinkName(name)) => {
@@ -691,7 +1617,7 @@ impl Module {
                     GlobalVarDecl {
                         attrs: mem::take(&mut attrs),
                         type_of_ptr_to: type_of_ptr_to_global_var,
-                        addr_space: AddrSpace::SpvStorageClass(storage_class),
+                        addr_space: AddrSpace::from_spv_storage_class(storage_class),
                         def,
                     },
                 );
@@ -739,9 +1665,10 @@ impl Module {
                         _ => None,
                     }
                     .ok_or_else(|| {
-                        invalid(&format!(
-                            "function type %{func_type_id} not an `OpTypeFunction`"
-                        ))
+                        invalid_operand(
+                            Some(LowerErrorOperand::Id(func_type_id)),
+                            &format!("function type %{func_type_id} not an `OpTypeFunction`"),
+                        )
                     })?;
 
                 if func_ret_type != func_type_ret_type {
@@ -801,6 +1728,7 @@ impl Module {
                 id_defs.insert(func_id, IdDef::Func(func));
 
                 current_func_body = Some(FuncBody {
+                    inst_idx,
                     func_id,
                     func,
                     insts: vec![],
@@ -825,6 +1753,8 @@ impl Module {
                 assert_eq!(seq, Some(Seq::Function));
 
                 func_body.insts.push(IntraFuncInst {
+                    inst_idx,
+
                     attrs: mem::take(&mut attrs),
                     result_type,
 
@@ -868,11 +1798,19 @@ impl Module {
         // Process function bodies, having seen the whole module.
         for func_body in pending_func_bodies {
             let FuncBody {
+                inst_idx: func_inst_idx,
                 func_id,
                 func,
                 insts: raw_insts,
             } = func_body;
 
+            let invalid = |msg: &str| LowerError::Inst {
+                inst_idx: func_inst_idx,
+                opcode: wk.OpFunction,
+                operand: None,
+                message: msg.to_string(),
+            };
+
             let func_decl = &mut module.funcs[func];
 
             #[derive(Copy, Clone)]
@@ -1045,62 +1983,80 @@ impl Module {
                 };
 
                 let IntraFuncInst {
-                    attrs,
+                    inst_idx,
+                    mut attrs,
                     result_type,
                     without_ids: spv::Inst { opcode, ref imms },
                     result_id,
                     ref ids,
                 } = *raw_inst;
 
-                let invalid = |msg: &str| invalid(&format!("in {}: {}", opcode.name(), msg));
+                let invalid_operand =
+                    |operand: Option<LowerErrorOperand>, msg: &str| LowerError::Inst {
+                        inst_idx,
+                        opcode,
+                        operand,
+                        message: msg.to_string(),
+                    };
+                let invalid = |msg: &str| invalid_operand(None, msg);
 
                 // FIXME(eddyb) find a more compact name and/or make this a method.
                 // FIXME(eddyb) this returns `LocalIdDef` even for global values.
                 let lookup_global_or_local_id_for_data_or_control_inst_input =
                     |id| match id_defs.get(&id) {
                         Some(&IdDef::Const(ct)) => Ok(LocalIdDef::Value(Value::Const(ct))),
-                        Some(id_def @ IdDef::Type(_)) => Err(invalid(&format!(
-                            "unsupported use of {} as an operand for \
-                             an instruction in a function",
-                            id_def.descr(&cx),
-                        ))),
-                        Some(id_def @ IdDef::Func(_)) => Err(invalid(&format!(
-                            "unsupported use of {} outside `OpFunctionCall`",
-                            id_def.descr(&cx),
-                        ))),
+                        Some(&IdDef::Type(ty)) if opcode == wk.OpCooperativeMatrixLengthKHR => {
+                            Ok(LocalIdDef::Value(Value::Const(cx.intern(ConstDef {
+                                attrs: AttrSet::default(),
+                                ty,
+                                ctor: ConstCtor::SpvTypeOperand,
+                                ctor_args: [].into_iter().collect(),
+                            }))))
+                        }
+                        Some(id_def @ IdDef::Type(_)) => Err(invalid_operand(
+                            Some(LowerErrorOperand::Id(id)),
+                            &format!(
+                                "unsupported use of {} as an operand for \
+                                 an instruction in a function",
+                                id_def.descr(&cx),
+                            ),
+                        )),
+                        Some(id_def @ IdDef::Func(_)) => Err(invalid_operand(
+                            Some(LowerErrorOperand::Id(id)),
+                            &format!(
+                                "unsupported use of {} outside `OpFunctionCall`",
+                                id_def.descr(&cx),
+                            ),
+                        )),
                         Some(id_def @ IdDef::SpvDebugString(s)) => {
                             if opcode == wk.OpExtInst {
-                                // HACK(eddyb) intern `OpString`s as `Const`s on
-                                // the fly, as it's a less likely usage than the
-                                // `OpLine` one.
-                                let ty = cx.intern(TypeDef {
-                                    attrs: AttrSet::default(),
-                                    ctor: TypeCtor::SpvStringLiteralForExtInst,
-                                    ctor_args: [].into_iter().collect(),
-                                });
-                                let ct = cx.intern(ConstDef {
-                                    attrs: AttrSet::default(),
-                                    ty,
-                                    ctor: ConstCtor::SpvStringLiteralForExtInst(*s),
-                                    ctor_args: [].into_iter().collect(),
-                                });
-                                Ok(LocalIdDef::Value(Value::Const(ct)))
-                            } else {
-                                Err(invalid(&format!(
-                                    "unsupported use of {} outside `OpSource`, \
-                                     `OpLine`, or `OpExtInst`",
-                                    id_def.descr(&cx),
+                                Ok(LocalIdDef::Value(Value::Const(
+                                    const_spv_string_literal_for_ext_inst(&cx, *s),
                                 )))
+                            } else {
+                                Err(invalid_operand(
+                                    Some(LowerErrorOperand::Id(id)),
+                                    &format!(
+                                        "unsupported use of {} outside `OpSource`, \
+                                         `OpLine`, or `OpExtInst`",
+                                        id_def.descr(&cx),
+                                    ),
+                                ))
                             }
                         }
-                        Some(id_def @ IdDef::SpvExtInstImport(_)) => Err(invalid(&format!(
-                            "unsupported use of {} outside `OpExtInst`",
-                            id_def.descr(&cx),
-                        ))),
-                        None => local_id_defs
-                            .get(&id)
-                            .copied()
-                            .ok_or_else(|| invalid(&format!("undefined ID %{id}",))),
+                        Some(id_def @ IdDef::SpvExtInstImport(_)) => Err(invalid_operand(
+                            Some(LowerErrorOperand::Id(id)),
+                            &format!(
+                                "unsupported use of {} outside `OpExtInst`",
+                                id_def.descr(&cx),
+                            ),
+                        )),
+                        None => local_id_defs.get(&id).copied().ok_or_else(|| {
+                            invalid_operand(
+                                Some(LowerErrorOperand::Id(id)),
+                                &format!("undefined ID %{id}"),
+                            )
+                        }),
                     };
 
                 if opcode == wk.OpFunctionParameter {
@@ -1183,7 +2139,7 @@ impl Module {
                             ))),
                         }
                     };
-                    let mut record_cfg_edge = |target_block| -> io::Result<()> {
+                    let mut record_cfg_edge = |target_block| -> Result<(), LowerError> {
                         use indexmap::map::Entry;
 
                         let target_block_details = &block_details[&target_block];
@@ -1251,6 +2207,14 @@ impl Module {
                     } else if [wk.OpReturn, wk.OpReturnValue].contains(&opcode) {
                         assert!(targets.is_empty() && inputs.len() <= 1);
                         cfg::ControlInstKind::Return
+                    } else if opcode == wk.OpEmitMeshTasksEXT {
+                        if !(targets.is_empty() && (3..=4).contains(&inputs.len())) {
+                            return Err(invalid(
+                                "expected group counts (and optional payload) \
+                                 as the only operands",
+                            ));
+                        }
+                        cfg::ControlInstKind::ExitInvocation(cfg::ExitInvocationKind::EmitMeshTasks)
                     } else if targets.is_empty() {
                         cfg::ControlInstKind::ExitInvocation(cfg::ExitInvocationKind::SpvInst(
                             raw_inst.without_ids.clone(),
@@ -1269,6 +2233,29 @@ impl Module {
                         return Err(invalid("unsupported control-flow instruction"));
                     };
 
+                    // The `SelectionControl`/`LoopControl` operands (and their
+                    // parameters) of a preceding merge instruction are kept
+                    // as an attribute on the terminator, for `cfg::Structurizer`
+                    // to move onto the resulting `Select`/`Loop` `ControlNode`.
+                    let merge_imms = raw_inst_idx
+                        .checked_sub(1)
+                        .map(|i| &raw_insts[i].without_ids)
+                        .filter(|merge_inst| {
+                            [wk.OpSelectionMerge, wk.OpLoopMerge].contains(&merge_inst.opcode)
+                        })
+                        .map(|merge_inst| &merge_inst.imms[..]);
+                    if let Some(merge_imms) = merge_imms {
+                        if !matches!(merge_imms, [] | [spv::Imm::Short(_, 0)]) {
+                            let mut attrs_with_merge_control = cx[attrs].attrs.clone();
+                            attrs_with_merge_control.insert(Attr::SpvBitflagsOperand(
+                                merge_imms.iter().copied().collect(),
+                            ));
+                            attrs = cx.intern(crate::AttrSetDef {
+                                attrs: attrs_with_merge_control,
+                            });
+                        }
+                    }
+
                     func_def_body
                         .unstructured_cfg
                         .as_mut()
@@ -1311,16 +2298,37 @@ impl Module {
                         ));
                     }
 
-                    // HACK(eddyb) merges are ignored - this may be lossy,
-                    // especially wrt the `SelectionControl` and `LoopControl`
-                    // operands, but it's not obvious how they should map to
-                    // some "structured regions" replacement for the CFG.
+                    // HACK(eddyb) merges are otherwise ignored (their merge
+                    // and continue targets are recomputed by `cfg::Structurizer`
+                    // and when lifting), with only the `SelectionControl` and
+                    // `LoopControl` operands being kept (see the terminator
+                    // handling above).
                 } else {
+                    let filtered_ids: SmallVec<[spv::Id; 4]>;
                     let mut ids = &ids[..];
+
+                    // Scope and semantics operands (of atomics and barriers) are
+                    // (unsigned) 32-bit integer constants (anything else, e.g.
+                    // specialization constants, keeps the instruction as `SpvInst`).
+                    let const_u32 = |id: spv::Id| match id_defs.get(&id)? {
+                        &IdDef::Const(ct) => match &cx[ct].ctor {
+                            ConstCtor::SpvInst(spv::Inst { opcode, imms })
+                                if *opcode == wk.OpConstant =>
+                            {
+                                match imms[..] {
+                                    [spv::Imm::Short(_, x)] => Some(x),
+                                    _ => None,
+                                }
+                            }
+                            _ => None,
+                        },
+                        _ => None,
+                    };
+
                     let kind = if opcode == wk.OpFunctionCall {
                         assert!(imms.is_empty());
                         let callee_id = ids[0];
-                        let maybe_callee = id_defs
+                        let maybe_callee = match id_defs
                             .get(&callee_id)
                             .map(|id_def| match *id_def {
                                 IdDef::Func(func) => Ok(func),
@@ -1328,10 +2336,20 @@ impl Module {
                             })
                             .transpose()
                             .map_err(|descr| {
-                                invalid(&format!(
-                                    "unsupported use of {descr} as the `OpFunctionCall` callee"
-                                ))
-                            })?;
+                                invalid_operand(
+                                    Some(LowerErrorOperand::Id(callee_id)),
+                                    &format!(
+                                        "unsupported use of {descr} as the `OpFunctionCall` callee"
+                                    ),
+                                )
+                            }) {
+                            Ok(maybe_callee) => maybe_callee,
+                            Err(e) => {
+                                // Opaque `OpFunctionCall`, in permissive mode.
+                                defer_error(&mut attrs, e)?;
+                                None
+                            }
+                        };
 
                         match maybe_callee {
                             Some(callee) => {
@@ -1361,17 +2379,159 @@ impl Module {
                             None => Err(format!("unknown ID %{ext_set_id}")),
                         }
                         .map_err(|descr| {
-                            invalid(&format!(
-                                "unsupported use of {descr} as the `OpExtInst` \
-                                 extended instruction set ID"
-                            ))
-                        })?;
+                            invalid_operand(
+                                Some(LowerErrorOperand::Id(ext_set_id)),
+                                &format!(
+                                    "unsupported use of {descr} as the `OpExtInst` \
+                                     extended instruction set ID"
+                                ),
+                            )
+                        });
 
-                        DataInstKind::SpvExtInst { ext_set, inst }
+                        let glsl_std_450_op = match ext_set {
+                            Ok(ext_set) if &cx[ext_set] == spv::glsl_std_450::EXT_INST_SET_NAME => {
+                                spv::glsl_std_450::Op::from_u32(inst)
+                            }
+                            _ => None,
+                        };
+
+                        match ext_set {
+                            Ok(_) if glsl_std_450_op.is_some() => {
+                                DataInstKind::SpvGlslStd450(glsl_std_450_op.unwrap())
+                            }
+                            Ok(ext_set) => DataInstKind::SpvExtInst { ext_set, inst },
+                            Err(e) => {
+                                // Opaque `OpExtInst`, in permissive mode.
+                                defer_error(&mut attrs, e)?;
+                                DataInstKind::SpvInst(raw_inst.without_ids.clone())
+                            }
+                        }
+                    } else if let Some(op_template) = AtomicOp::ALL_SIMPLE
+                        .iter()
+                        .copied()
+                        .find(|op| op.spv_opcode() == opcode)
+                        .or_else(|| {
+                            [false, true]
+                                .into_iter()
+                                .map(|weak| AtomicOp::CompareExchange {
+                                    weak,
+                                    unequal_semantics: 0,
+                                })
+                                .find(|op| op.spv_opcode() == opcode)
+                        })
+                    {
+                        let operand_count = match op_template {
+                            AtomicOp::CompareExchange { .. } => 3,
+                            _ => 2,
+                        };
+                        let decoded = if ids.len() > operand_count {
+                            ids[1..=operand_count]
+                                .iter()
+                                .map(|&id| const_u32(id))
+                                .collect::<Option<SmallVec<[_; 3]>>>()
+                        } else {
+                            None
+                        };
+
+                        match decoded.as_deref() {
+                            Some(&[scope, semantics, ref rest @ ..]) => {
+                                let op = match (op_template, rest) {
+                                    (AtomicOp::CompareExchange { weak, .. }, &[unequal]) => {
+                                        AtomicOp::CompareExchange {
+                                            weak,
+                                            unequal_semantics: unequal,
+                                        }
+                                    }
+                                    _ => op_template,
+                                };
+                                filtered_ids = iter::once(ids[0])
+                                    .chain(ids[1 + operand_count..].iter().copied())
+                                    .collect();
+                                ids = &filtered_ids;
+                                DataInstKind::Atomic {
+                                    op,
+                                    scope,
+                                    semantics,
+                                }
+                            }
+                            _ => DataInstKind::SpvInst(raw_inst.without_ids.clone()),
+                        }
+                    } else if opcode == wk.OpControlBarrier || opcode == wk.OpMemoryBarrier {
+                        let decoded = ids
+                            .iter()
+                            .map(|&id| const_u32(id))
+                            .collect::<Option<SmallVec<[_; 3]>>>();
+                        let kind = match (opcode == wk.OpControlBarrier, decoded.as_deref()) {
+                            (true, Some(&[execution_scope, memory_scope, semantics])) => {
+                                Some(DataInstKind::Barrier {
+                                    execution_scope: Some(execution_scope),
+                                    memory_scope,
+                                    semantics,
+                                })
+                            }
+                            (false, Some(&[memory_scope, semantics])) => {
+                                Some(DataInstKind::Barrier {
+                                    execution_scope: None,
+                                    memory_scope,
+                                    semantics,
+                                })
+                            }
+                            _ => None,
+                        };
+                        match kind {
+                            Some(kind) => {
+                                ids = &[];
+                                kind
+                            }
+                            None => DataInstKind::SpvInst(raw_inst.without_ids.clone()),
+                        }
+                    } else if let Some(op) = GroupOp::ALL
+                        .iter()
+                        .copied()
+                        .find(|op| op.spv_opcode() == opcode)
+                    {
+                        let scope = ids.first().and_then(|&id| const_u32(id));
+                        let group_operation = match (op.takes_group_operation(), &imms[..]) {
+                            (true, &[spv::Imm::Short(_, group_operation)]) => {
+                                Some(Some(group_operation))
+                            }
+                            (false, []) => Some(None),
+                            _ => None,
+                        };
+                        match (scope, group_operation) {
+                            (Some(scope), Some(group_operation)) => {
+                                ids = &ids[1..];
+                                DataInstKind::Group {
+                                    op,
+                                    scope,
+                                    group_operation,
+                                }
+                            }
+                            _ => DataInstKind::SpvInst(raw_inst.without_ids.clone()),
+                        }
                     } else {
                         DataInstKind::SpvInst(raw_inst.without_ids.clone())
                     };
 
+                    let mut inputs = SmallVec::with_capacity(ids.len());
+                    for &id in ids {
+                        let input = lookup_global_or_local_id_for_data_or_control_inst_input(id)
+                            .and_then(|local_id_def| match local_id_def {
+                                LocalIdDef::Value(v) => Ok(v),
+                                LocalIdDef::BlockLabel { .. } => Err(invalid_operand(
+                                    Some(LowerErrorOperand::Id(id)),
+                                    "unsupported use of block label as a value, \
+                                     in non-terminator instruction",
+                                )),
+                            });
+                        match input {
+                            Ok(v) => inputs.push(v),
+
+                            // Input omitted, in permissive mode.
+                            Err(e) => defer_error(&mut attrs, e)?,
+                        }
+                    }
+
                     let data_inst_def = DataInstDef {
                         attrs,
                         kind,
@@ -1384,19 +2544,7 @@ impl Module {
                                 })
                             })
                             .transpose()?,
-                        inputs: ids
-                            .iter()
-                            .map(|&id| {
-                                match lookup_global_or_local_id_for_data_or_control_inst_input(id)?
-                                {
-                                    LocalIdDef::Value(v) => Ok(v),
-                                    LocalIdDef::BlockLabel { .. } => Err(invalid(
-                                        "unsupported use of block label as a value, \
-                                         in non-terminator instruction",
-                                    )),
-                                }
-                            })
-                            .collect::<io::Result<_>>()?,
+                        inputs,
                     };
                     let inst = match result_id {
                         Some(id) => match local_id_defs[&id] {
@@ -1426,6 +2574,7 @@ impl Module {
                             let block_node = func_def_body.control_nodes.define(
                                 &cx,
                                 ControlNodeDef {
+                                    attrs: AttrSet::default(),
                                     kind: ControlNodeKind::Block {
                                         insts: EntityList::empty(),
                                     },
@@ -1515,11 +2664,59 @@ impl Module {
             }
         }
 
+        for (inst_idx, inst) in pending_execution_mode_ids {
+            let invalid_operand = |id, msg: String| LowerError::Inst {
+                inst_idx,
+                opcode: wk.OpExecutionModeId,
+                operand: Some(LowerErrorOperand::Id(id)),
+                message: msg,
+            };
+
+            let (&func_id, const_ids) = inst.ids.split_first().unwrap();
+            let func = match id_defs.get(&func_id) {
+                Some(&IdDef::Func(func)) => Ok(func),
+                Some(id_def) => Err(id_def.descr(&cx)),
+                None => Err(format!("unknown ID %{func_id}")),
+            }
+            .map_err(|descr| {
+                invalid_operand(
+                    func_id,
+                    format!("unsupported use of {descr} as the `OpExecutionModeId` target"),
+                )
+            })?;
+            let consts = const_ids
+                .iter()
+                .map(|&id| match id_defs.get(&id) {
+                    Some(&IdDef::Const(ct)) => Ok(crate::OrdAssertEq(ct)),
+                    Some(id_def) => Err(invalid_operand(
+                        id,
+                        format!(
+                            "unsupported use of {} as an `OpExecutionModeId` operand",
+                            id_def.descr(&cx)
+                        ),
+                    )),
+                    None => Err(invalid_operand(id, format!("unknown ID %{id}"))),
+                })
+                .collect::<Result<_, _>>()?;
+
+            let func_attrs = &mut module.funcs[func].attrs;
+            let mut attrs = cx[*func_attrs].attrs.clone();
+            attrs.insert(Attr::SpvExecutionModeId {
+                inst: inst.without_ids,
+                consts,
+            });
+            *func_attrs = cx.intern(crate::AttrSetDef { attrs });
+        }
+
         assert!(module.exports.is_empty());
         module.exports = pending_exports
             .into_iter()
             .map(|export| match export {
-                Export::Linkage { name, target_id } => {
+                Export::Linkage {
+                    inst_idx,
+                    name,
+                    target_id,
+                } => {
                     let exportee = match id_defs.get(&target_id) {
                         Some(id_def @ &IdDef::Const(ct)) => match cx[ct].ctor {
                             ConstCtor::PtrToGlobalVar(gv) => Ok(Exportee::GlobalVar(gv)),
@@ -1529,45 +2726,60 @@ impl Module {
                         Some(id_def) => Err(id_def.descr(&cx)),
                         None => Err(format!("unknown ID %{target_id}")),
                     }
-                    .map_err(|descr| {
-                        invalid(&format!(
+                    .map_err(|descr| LowerError::Inst {
+                        inst_idx,
+                        opcode: wk.OpDecorate,
+                        operand: Some(LowerErrorOperand::Id(target_id)),
+                        message: format!(
                             "unsupported use of {descr} as the `LinkageAttributes` target"
-                        ))
+                        ),
                     })?;
 
                     Ok((ExportKey::LinkName(name), exportee))
                 }
 
                 Export::EntryPoint {
+                    inst_idx,
                     func_id,
                     imms,
                     interface_ids,
                 } => {
+                    let invalid_operand = |id, msg: String| LowerError::Inst {
+                        inst_idx,
+                        opcode: wk.OpEntryPoint,
+                        operand: Some(LowerErrorOperand::Id(id)),
+                        message: msg,
+                    };
+
                     let func = match id_defs.get(&func_id) {
                         Some(&IdDef::Func(func)) => Ok(func),
                         Some(id_def) => Err(id_def.descr(&cx)),
                         None => Err(format!("unknown ID %{func_id}")),
                     }
                     .map_err(|descr| {
-                        invalid(&format!(
-                            "unsupported use of {descr} as the `OpEntryPoint` target"
-                        ))
+                        invalid_operand(
+                            func_id,
+                            format!("unsupported use of {descr} as the `OpEntryPoint` target"),
+                        )
                     })?;
                     let interface_global_vars = interface_ids
                         .into_iter()
-                        .map(|id| match id_defs.get(&id) {
-                            Some(id_def @ &IdDef::Const(ct)) => match cx[ct].ctor {
-                                ConstCtor::PtrToGlobalVar(gv) => Ok(gv),
-                                _ => Err(id_def.descr(&cx)),
-                            },
-                            Some(id_def) => Err(id_def.descr(&cx)),
-                            None => Err(format!("unknown ID %{id}")),
-                        })
-                        .map(|result| {
-                            result.map_err(|descr| {
-                                invalid(&format!(
-                                    "unsupported use of {descr} as an `OpEntryPoint` interface variable"
-                                ))
+                        .map(|id| {
+                            match id_defs.get(&id) {
+                                Some(id_def @ &IdDef::Const(ct)) => match cx[ct].ctor {
+                                    ConstCtor::PtrToGlobalVar(gv) => Ok(gv),
+                                    _ => Err(id_def.descr(&cx)),
+                                },
+                                Some(id_def) => Err(id_def.descr(&cx)),
+                                None => Err(format!("unknown ID %{id}")),
+                            }
+                            .map_err(|descr| {
+                                invalid_operand(
+                                    id,
+                                    format!(
+                                        "unsupported use of {descr} as an `OpEntryPoint` interface variable"
+                                    ),
+                                )
                             })
                         })
                         .collect::<Result<_, _>>()?;
@@ -1580,7 +2792,7 @@ impl Module {
                     ))
                 }
             })
-            .collect::<io::Result<_>>()?;
+            .collect::<Result<_, LowerError>>()?;
 
         Ok(module)
     }
@@ -2,14 +2,18 @@
 
 // NOTE(eddyb) all the modules are declared here, but they're documented "inside"
 // (i.e. using inner doc comments).
+pub mod fold;
+pub mod glsl_std_450;
 pub mod lift;
 pub mod lower;
 pub mod print;
 pub mod read;
+pub mod shader_debuginfo;
 pub mod spec;
+pub mod target_env;
 pub mod write;
 
-use crate::{FxIndexMap, InternedStr};
+use crate::{ConstCtor, Context, FxIndexMap, InternedStr, Type, TypeCtor, TypeCtorArg};
 use smallvec::SmallVec;
 use std::collections::{BTreeMap, BTreeSet};
 use std::iter;
@@ -29,6 +33,695 @@ pub struct Dialect {
     pub memory_model: u32,
 }
 
+impl Dialect {
+    /// Return the minimum SPIR-V version (as `(major, minor)`) that `inst` (or
+    /// any of its enumerand operands) requires, if newer than SPIR-V 1.0, and
+    /// not already made available by an extension declared in this dialect,
+    /// paired with a description of the construct requiring that version.
+    //
+    // FIXME(eddyb) this ignores requirements that cannot be satisfied by any
+    // SPIR-V version, i.e. those that require extensions (not declared here).
+    pub fn min_version_required_by(&self, inst: &Inst) -> Option<((u8, u8), String)> {
+        let version_required_by = |reqs: &spec::Requirements| {
+            let enabled_by_ext = reqs
+                .extensions
+                .iter()
+                .any(|&ext| self.extensions.contains(ext));
+            reqs.min_version
+                .filter(|&version| version > (1, 0) && !enabled_by_ext)
+        };
+
+        let mut max_required = None;
+        let mut require = |version: Option<(u8, u8)>, describe: &dyn Fn() -> String| {
+            if let Some(version) = version {
+                let is_new_max = match max_required {
+                    Some((max, _)) => version > max,
+                    None => true,
+                };
+                if is_new_max {
+                    max_required = Some((version, describe()));
+                }
+            }
+        };
+
+        let (opcode_name, opcode_def) = inst.opcode.name_and_def();
+        require(version_required_by(&opcode_def.reqs), &|| {
+            format!("`{opcode_name}`")
+        });
+
+        for &imm in &inst.imms {
+            let (kind, word) = match imm {
+                Imm::Short(kind, word) => (kind, word),
+                Imm::LongStart(..) | Imm::LongCont(..) => continue,
+            };
+            let (kind_name, kind_def) = kind.name_and_def();
+            let describe_enumerand = |name: &'static str| {
+                move || format!("`{kind_name}.{name}` (used by `{opcode_name}`)")
+            };
+            match kind_def {
+                spec::OperandKindDef::BitEnum { bits, .. } => {
+                    for bit_idx in spec::BitIdx::of_all_set_bits(word) {
+                        if let Some((name, enumerant)) = bits.get_named(bit_idx) {
+                            require(
+                                version_required_by(&enumerant.reqs),
+                                &describe_enumerand(name),
+                            );
+                        }
+                    }
+                }
+                spec::OperandKindDef::ValueEnum { variants } => {
+                    let variant = u16::try_from(word)
+                        .ok()
+                        .and_then(|word| variants.get_named(word));
+                    if let Some((name, enumerant)) = variant {
+                        require(
+                            version_required_by(&enumerant.reqs),
+                            &describe_enumerand(name),
+                        );
+                    }
+                }
+                spec::OperandKindDef::Id | spec::OperandKindDef::Literal { .. } => {}
+            }
+        }
+
+        max_required
+    }
+
+    /// Compute a minimal set of capabilities sufficient for all of `insts`
+    /// (i.e. every instruction of a module, other than `OpCapability` and
+    /// `OpExtension`), based on the requirements of their opcodes and enumerand
+    /// operands, as well as the widths of any integer/floating-point types.
+    ///
+    /// When several capabilities would satisfy some requirement, one which is
+    /// declared in this dialect (or implied by one which is) is preferred.
+    ///
+    /// Declared capabilities that are never required by any instruction or
+    /// enumerant (e.g. `StorageImageWriteWithoutFormat`) are always kept, as
+    /// they can only be required by validation rules not encoded in the grammar.
+    //
+    // FIXME(eddyb) this ignores requirements from extended instruction sets.
+    pub fn minimal_capabilities_for<'a>(
+        &self,
+        insts: impl IntoIterator<Item = &'a Inst>,
+    ) -> BTreeSet<u32> {
+        let spv_spec = spec::Spec::get();
+        let wk = &spv_spec.well_known;
+
+        let cap_variants = match wk.Capability.def() {
+            spec::OperandKindDef::ValueEnum { variants } => variants,
+            _ => unreachable!(),
+        };
+        let cap_by_name = |name: &str| cap_variants.lookup(name).map(u32::from);
+
+        // Capabilities implicitly declared by declaring `cap` (including itself).
+        let implied_by = |cap: u32| {
+            let mut implied = BTreeSet::new();
+            let mut queue = vec![cap];
+            while let Some(cap) = queue.pop() {
+                if implied.insert(cap) {
+                    let enumerant = u16::try_from(cap)
+                        .ok()
+                        .and_then(|cap| cap_variants.get(cap));
+                    if let Some(enumerant) = enumerant {
+                        queue.extend(
+                            enumerant
+                                .reqs
+                                .capabilities
+                                .iter()
+                                .filter_map(|&name| cap_by_name(name)),
+                        );
+                    }
+                }
+            }
+            implied
+        };
+
+        // Every capability that the grammar can ever require (even only as
+        // something implicitly declared by another capability).
+        let grammar_required: BTreeSet<_> = spv_spec
+            .all_requirements()
+            .flat_map(|reqs| &reqs.capabilities)
+            .filter_map(|&name| cap_by_name(name))
+            .collect();
+
+        let declared_and_implied: BTreeSet<_> = self
+            .capabilities
+            .iter()
+            .flat_map(|&cap| implied_by(cap))
+            .collect();
+
+        let mut required: BTreeSet<_> = self
+            .capabilities
+            .iter()
+            .copied()
+            .filter(|cap| !grammar_required.contains(cap))
+            .collect();
+        let mut enabled: BTreeSet<_> = required.iter().flat_map(|&cap| implied_by(cap)).collect();
+        let mut require_any_of = |names: &[&str]| {
+            let caps: SmallVec<[u32; 4]> =
+                names.iter().filter_map(|&name| cap_by_name(name)).collect();
+            if caps.is_empty() || caps.iter().any(|cap| enabled.contains(cap)) {
+                return;
+            }
+            let cap = caps
+                .iter()
+                .copied()
+                .find(|cap| declared_and_implied.contains(cap))
+                .unwrap_or(caps[0]);
+            required.insert(cap);
+            enabled.extend(implied_by(cap));
+        };
+
+        for inst in insts {
+            for reqs in inst.requirements() {
+                require_any_of(&reqs.capabilities);
+            }
+
+            require_any_of(&type_width_capabilities(inst));
+        }
+
+        // Remove capabilities implicitly declared by other required ones.
+        let implied_by_others: BTreeSet<_> = required
+            .iter()
+            .flat_map(|&cap| {
+                implied_by(cap)
+                    .into_iter()
+                    .filter(move |&implied| implied != cap)
+            })
+            .collect();
+        required.retain(|cap| !implied_by_others.contains(cap));
+
+        required
+    }
+
+    /// Compute a minimal set of extensions sufficient for all of `insts` (i.e.
+    /// every instruction of a module, other than `OpCapability` and `OpExtension`),
+    /// and all the capabilities declared in this dialect, based on which of
+    /// their requirements aren't already met by this dialect's SPIR-V version.
+    ///
+    /// When several extensions would satisfy some requirement, one which is
+    /// declared in this dialect is preferred.
+    ///
+    /// Declared extensions that are never required by any instruction or
+    /// enumerant (e.g. `SPV_KHR_non_semantic_info`) are always kept, as their
+    /// uses can't be determined from the grammar alone.
+    //
+    // FIXME(eddyb) this ignores requirements from extended instruction sets.
+    pub fn minimal_extensions_for<'a>(
+        &self,
+        insts: impl IntoIterator<Item = &'a Inst>,
+    ) -> BTreeSet<String> {
+        let spv_spec = spec::Spec::get();
+        let wk = &spv_spec.well_known;
+
+        let version = (self.version_major, self.version_minor);
+
+        // Every extension that the grammar can ever require.
+        let grammar_required: BTreeSet<_> = spv_spec
+            .all_requirements()
+            .flat_map(|reqs| reqs.extensions.iter().copied())
+            .collect();
+
+        let mut required: BTreeSet<String> = self
+            .extensions
+            .iter()
+            .filter(|ext| !grammar_required.contains(&ext[..]))
+            .cloned()
+            .collect();
+        let mut require = |reqs: &spec::Requirements| {
+            let available_in_version = reqs.min_version.is_some_and(|min| min <= version);
+            if available_in_version
+                || reqs.extensions.is_empty()
+                || reqs.extensions.iter().any(|&ext| required.contains(ext))
+            {
+                return;
+            }
+            let ext = reqs
+                .extensions
+                .iter()
+                .copied()
+                .find(|&ext| self.extensions.contains(ext))
+                .unwrap_or(reqs.extensions[0]);
+            required.insert(ext.to_string());
+        };
+
+        let cap_variants = match wk.Capability.def() {
+            spec::OperandKindDef::ValueEnum { variants } => variants,
+            _ => unreachable!(),
+        };
+        for &cap in &self.capabilities {
+            let enumerant = u16::try_from(cap)
+                .ok()
+                .and_then(|cap| cap_variants.get(cap));
+            if let Some(enumerant) = enumerant {
+                require(&enumerant.reqs);
+            }
+        }
+
+        for inst in insts {
+            for reqs in inst.requirements() {
+                require(reqs);
+            }
+        }
+
+        required
+    }
+}
+
+/// Get the capabilities (by name), any of which, if declared, allows using
+/// the integer/floating-point type `inst` declares, based on its width.
+///
+/// Type widths are only constrained by validation rules (not the grammar),
+/// which also allow narrow types to be enabled by storage capabilities.
+pub(crate) fn type_width_capabilities(inst: &Inst) -> SmallVec<[&'static str; 5]> {
+    let wk = &spec::Spec::get().well_known;
+
+    let width = match inst.imms.first() {
+        Some(&Imm::Short(_, width)) => width,
+        _ => return SmallVec::new(),
+    };
+    const STORAGE_8BIT: [&str; 3] = [
+        "StorageBuffer8BitAccess",
+        "UniformAndStorageBuffer8BitAccess",
+        "StoragePushConstant8",
+    ];
+    const STORAGE_16BIT: [&str; 4] = [
+        "StorageBuffer16BitAccess",
+        "UniformAndStorageBuffer16BitAccess",
+        "StoragePushConstant16",
+        "StorageInputOutput16",
+    ];
+    if inst.opcode == wk.OpTypeInt {
+        match width {
+            8 => iter::once("Int8").chain(STORAGE_8BIT).collect(),
+            16 => iter::once("Int16").chain(STORAGE_16BIT).collect(),
+            64 => ["Int64"].into_iter().collect(),
+            _ => SmallVec::new(),
+        }
+    } else if inst.opcode == wk.OpTypeFloat {
+        match width {
+            16 => ["Float16", "Float16Buffer"]
+                .into_iter()
+                .chain(STORAGE_16BIT)
+                .collect(),
+            64 => ["Float64"].into_iter().collect(),
+            _ => SmallVec::new(),
+        }
+    } else {
+        SmallVec::new()
+    }
+}
+
+impl crate::AddrSpace {
+    /// Get the [`AddrSpace`](crate::AddrSpace) for a SPIR-V `StorageClass`
+    /// (using dedicated variants where they exist).
+    pub fn from_spv_storage_class(storage_class: u32) -> Self {
+        let wk = &spec::Spec::get().well_known;
+
+        if storage_class == wk.PhysicalStorageBuffer {
+            Self::PhysicalStorageBuffer
+        } else {
+            Self::SpvStorageClass(storage_class)
+        }
+    }
+
+    /// Get the SPIR-V `StorageClass` for this [`AddrSpace`](crate::AddrSpace).
+    pub fn to_spv_storage_class(self) -> u32 {
+        let wk = &spec::Spec::get().well_known;
+
+        match self {
+            Self::SpvStorageClass(storage_class) => storage_class,
+            Self::PhysicalStorageBuffer => wk.PhysicalStorageBuffer,
+        }
+    }
+
+    /// Get the [`AddrSpace`](crate::AddrSpace) pointed into by the pointer type
+    /// `ty` (or `None`, if `ty` isn't a pointer type).
+    pub fn of_ptr_type(cx: &Context, ty: Type) -> Option<Self> {
+        let wk = &spec::Spec::get().well_known;
+
+        let storage_class = match &cx[ty].ctor {
+            TypeCtor::SpvInst(inst) if inst.opcode == wk.OpTypePointer => match inst.imms[..] {
+                [Imm::Short(_, storage_class)] => storage_class,
+                _ => return None,
+            },
+            &TypeCtor::RecursivePtr { storage_class } => storage_class,
+            _ => return None,
+        };
+        Some(Self::from_spv_storage_class(storage_class))
+    }
+
+    /// Whether pointers into this [`AddrSpace`](crate::AddrSpace) are "physical",
+    /// i.e. plain integer addresses, which can be freely converted from/to
+    /// integers, or chosen between dynamically (without `VariablePointers`).
+    pub fn has_physical_ptrs(self) -> bool {
+        matches!(self, Self::PhysicalStorageBuffer)
+    }
+
+    /// Whether this [`AddrSpace`](crate::AddrSpace) is used by ray tracing
+    /// shaders to communicate with the shaders they invoke (or are invoked by),
+    /// i.e. any memory in it can be accessed by any instruction which
+    /// [invokes ray tracing shaders](crate::DataInstKind::invokes_ray_tracing_shaders)
+    /// (without having to be passed a pointer to it).
+    ///
+    /// Note that `ShaderRecordBufferKHR` isn't included, as it's read-only.
+    pub fn is_ray_tracing_interface(self) -> bool {
+        let wk = &spec::Spec::get().well_known;
+
+        match self {
+            Self::SpvStorageClass(storage_class) => [
+                wk.RayPayloadKHR,
+                wk.IncomingRayPayloadKHR,
+                wk.HitAttributeKHR,
+                wk.CallableDataKHR,
+                wk.IncomingCallableDataKHR,
+            ]
+            .contains(&storage_class),
+            Self::PhysicalStorageBuffer => false,
+        }
+    }
+
+    /// Whether this [`AddrSpace`](crate::AddrSpace) can only be read from
+    /// (currently only the case for the ray tracing `ShaderRecordBufferKHR`).
+    pub fn is_read_only(self) -> bool {
+        let wk = &spec::Spec::get().well_known;
+
+        self == Self::SpvStorageClass(wk.ShaderRecordBufferKHR)
+    }
+}
+
+impl crate::DataInstKind {
+    /// Whether this is a SPIR-V ray tracing instruction which invokes other
+    /// shaders (i.e. `OpTraceRayKHR`, `OpExecuteCallableKHR` or
+    /// `OpReportIntersectionKHR`), which can read from and write to memory in
+    /// [`AddrSpace`](crate::AddrSpace)s for which
+    /// [`AddrSpace::is_ray_tracing_interface`](crate::AddrSpace::is_ray_tracing_interface)
+    /// returns `true`, so it has to be treated like a call to an unknown function.
+    pub fn invokes_ray_tracing_shaders(&self) -> bool {
+        let wk = &spec::Spec::get().well_known;
+
+        match self {
+            Self::SpvInst(inst) => [
+                wk.OpTraceRayKHR,
+                wk.OpExecuteCallableKHR,
+                wk.OpReportIntersectionKHR,
+            ]
+            .contains(&inst.opcode),
+            _ => false,
+        }
+    }
+
+    /// Whether this instruction is "convergent", i.e. its behavior depends on
+    /// which other invocations are executing it at the same time (e.g. group
+    /// operations, derivatives, or execution barriers), so it must not be moved
+    /// across (or duplicated into) control-flow that could change that set of
+    /// invocations (i.e. any code motion has to preserve control dependence).
+    ///
+    /// Function calls are conservatively assumed to be convergent, as is any
+    /// unknown SPIR-V instruction which looks like it could be convergent.
+    pub fn is_convergent(&self) -> bool {
+        match self {
+            Self::FuncCall(_) | Self::Group { .. } => true,
+            Self::Barrier {
+                execution_scope, ..
+            } => execution_scope.is_some(),
+            Self::SpvExtInst { .. }
+            | Self::SpvGlslStd450(_)
+            | Self::Atomic { .. }
+            | Self::QPtr(_) => false,
+
+            // NOTE(eddyb) this is based on the opcode name, to also cover
+            // vendor extensions (and avoid listing every single opcode), e.g.
+            // `OpGroup*`/`OpSubgroup*` operations, `OpDPdx`/`OpFwidth`, and
+            // image sampling which uses implicit derivatives to compute LOD.
+            Self::SpvInst(inst) => {
+                let wk = &spec::Spec::get().well_known;
+
+                let name = inst.opcode.name();
+                inst.opcode == wk.OpControlBarrier
+                    || [
+                        "OpGroup",
+                        "OpSubgroup",
+                        "OpDPd",
+                        "OpFwidth",
+                        "OpCooperativeMatrix",
+                    ]
+                    .iter()
+                    .any(|prefix| name.starts_with(prefix))
+                    || name.contains("ImplicitLod")
+                    || name == "OpImageQueryLod"
+            }
+        }
+    }
+}
+
+impl crate::DataInstDef {
+    /// Whether this instruction may have side-effects (i.e. other than producing
+    /// its output value), so it has to be kept even if its output is unused.
+    ///
+    /// This is conservative, i.e. function calls and unknown extended instructions
+    /// are always assumed to have side-effects, as are SPIR-V instructions which
+    /// don't produce a (non-`OpTypeVoid`) value, or which look like they could
+    /// write to memory (or otherwise mutate state) despite producing a value.
+    pub fn has_side_effects(&self, cx: &Context) -> bool {
+        use crate::qptr::QPtrOp;
+        use crate::{AtomicOp, DataInstKind};
+        use glsl_std_450::Op as GlslOp;
+
+        let wk = &spec::Spec::get().well_known;
+
+        match &self.kind {
+            DataInstKind::FuncCall(_)
+            | DataInstKind::SpvExtInst { .. }
+            | DataInstKind::Barrier { .. } => true,
+            DataInstKind::Atomic { op, .. } => {
+                !matches!(op, AtomicOp::Load) || self.kind.is_fence()
+            }
+            DataInstKind::Group { .. } => false,
+            DataInstKind::QPtr(op) => matches!(op, QPtrOp::Store),
+            DataInstKind::SpvGlslStd450(op) => matches!(op, GlslOp::Modf | GlslOp::Frexp),
+            DataInstKind::SpvInst(inst) => {
+                let produces_value = self.output_type.is_some_and(|ty| match &cx[ty].ctor {
+                    TypeCtor::SpvInst(ty_inst) => ty_inst.opcode != wk.OpTypeVoid,
+                    _ => true,
+                });
+                if !produces_value || self.kind.invokes_ray_tracing_shaders() {
+                    return true;
+                }
+
+                // NOTE(eddyb) `Volatile` is the lowest bit of `MemoryAccess`.
+                if inst.opcode == wk.OpLoad {
+                    return matches!(inst.imms[..], [Imm::Short(_, mask), ..] if mask & 1 != 0);
+                }
+
+                // NOTE(eddyb) this is based on the opcode name, to also cover
+                // vendor extensions (see also `DataInstKind::is_convergent`).
+                let name = inst.opcode.name();
+                [
+                    "OpAtomic",
+                    "OpRayQueryProceed",
+                    "OpReadPipe",
+                    "OpWritePipe",
+                    "OpReserve",
+                    "OpGroupAsyncCopy",
+                    "OpEnqueue",
+                    "OpCreateUserEvent",
+                ]
+                .iter()
+                .any(|prefix| name.starts_with(prefix))
+            }
+        }
+    }
+
+    /// Whether this instruction is "pure", i.e. its output value depends only on
+    /// its inputs, so that two instructions which are identical (other than in
+    /// their position) always produce the same output value, if one dominates
+    /// the other (which is what allows e.g. common subexpression elimination).
+    ///
+    /// Besides not having side-effects (see [`DataInstDef::has_side_effects`]),
+    /// this requires not reading any mutable state (memory, clocks, etc.), not
+    /// being convergent (see [`DataInstKind::is_convergent`]), and not creating
+    /// distinct values on every execution (i.e. `OpVariable` pointers).
+    ///
+    /// [`DataInstDef::has_side_effects`]: crate::DataInstDef::has_side_effects
+    /// [`DataInstKind::is_convergent`]: crate::DataInstKind::is_convergent
+    pub fn is_pure(&self, cx: &Context) -> bool {
+        use crate::DataInstKind;
+        use crate::qptr::QPtrOp;
+
+        if self.has_side_effects(cx) || self.kind.is_convergent() {
+            return false;
+        }
+        match &self.kind {
+            DataInstKind::Atomic { .. } | DataInstKind::QPtr(QPtrOp::Load) => false,
+            DataInstKind::SpvInst(inst) => {
+                // NOTE(eddyb) this is based on the opcode name, to also cover
+                // vendor extensions (see also `DataInstKind::is_convergent`),
+                // with `OpImage*` covering both image reads and sampling, while
+                // `OpSampledImage` isn't allowed to be used across blocks.
+                let name = inst.opcode.name();
+                ![
+                    "OpVariable",
+                    "OpLoad",
+                    "OpImage",
+                    "OpSampledImage",
+                    "OpRayQuery",
+                    "OpReadClock",
+                    "OpIsHelperInvocation",
+                    "OpCooperativeMatrixLoad",
+                ]
+                .iter()
+                .any(|prefix| name.starts_with(prefix))
+            }
+            _ => true,
+        }
+    }
+}
+
+impl crate::AtomicOp {
+    /// Get the SPIR-V `OpAtomic*` opcode for this operation.
+    pub fn spv_opcode(self) -> spec::Opcode {
+        let wk = &spec::Spec::get().well_known;
+
+        match self {
+            Self::Load => wk.OpAtomicLoad,
+            Self::Store => wk.OpAtomicStore,
+            Self::Exchange => wk.OpAtomicExchange,
+            Self::CompareExchange { weak: false, .. } => wk.OpAtomicCompareExchange,
+            Self::CompareExchange { weak: true, .. } => wk.OpAtomicCompareExchangeWeak,
+            Self::IIncrement => wk.OpAtomicIIncrement,
+            Self::IDecrement => wk.OpAtomicIDecrement,
+            Self::IAdd => wk.OpAtomicIAdd,
+            Self::ISub => wk.OpAtomicISub,
+            Self::SMin => wk.OpAtomicSMin,
+            Self::UMin => wk.OpAtomicUMin,
+            Self::SMax => wk.OpAtomicSMax,
+            Self::UMax => wk.OpAtomicUMax,
+            Self::And => wk.OpAtomicAnd,
+            Self::Or => wk.OpAtomicOr,
+            Self::Xor => wk.OpAtomicXor,
+            Self::FlagTestAndSet => wk.OpAtomicFlagTestAndSet,
+            Self::FlagClear => wk.OpAtomicFlagClear,
+            Self::FAdd => wk.OpAtomicFAddEXT,
+            Self::FMin => wk.OpAtomicFMinEXT,
+            Self::FMax => wk.OpAtomicFMaxEXT,
+        }
+    }
+}
+
+impl crate::GroupOp {
+    /// Get the SPIR-V `OpGroupNonUniform*` opcode for this operation.
+    pub fn spv_opcode(self) -> spec::Opcode {
+        let wk = &spec::Spec::get().well_known;
+
+        match self {
+            Self::Elect => wk.OpGroupNonUniformElect,
+            Self::All => wk.OpGroupNonUniformAll,
+            Self::Any => wk.OpGroupNonUniformAny,
+            Self::AllEqual => wk.OpGroupNonUniformAllEqual,
+            Self::Broadcast => wk.OpGroupNonUniformBroadcast,
+            Self::BroadcastFirst => wk.OpGroupNonUniformBroadcastFirst,
+            Self::Ballot => wk.OpGroupNonUniformBallot,
+            Self::InverseBallot => wk.OpGroupNonUniformInverseBallot,
+            Self::BallotBitExtract => wk.OpGroupNonUniformBallotBitExtract,
+            Self::BallotBitCount => wk.OpGroupNonUniformBallotBitCount,
+            Self::BallotFindLSB => wk.OpGroupNonUniformBallotFindLSB,
+            Self::BallotFindMSB => wk.OpGroupNonUniformBallotFindMSB,
+            Self::Shuffle => wk.OpGroupNonUniformShuffle,
+            Self::ShuffleXor => wk.OpGroupNonUniformShuffleXor,
+            Self::ShuffleUp => wk.OpGroupNonUniformShuffleUp,
+            Self::ShuffleDown => wk.OpGroupNonUniformShuffleDown,
+            Self::IAdd => wk.OpGroupNonUniformIAdd,
+            Self::FAdd => wk.OpGroupNonUniformFAdd,
+            Self::IMul => wk.OpGroupNonUniformIMul,
+            Self::FMul => wk.OpGroupNonUniformFMul,
+            Self::SMin => wk.OpGroupNonUniformSMin,
+            Self::UMin => wk.OpGroupNonUniformUMin,
+            Self::FMin => wk.OpGroupNonUniformFMin,
+            Self::SMax => wk.OpGroupNonUniformSMax,
+            Self::UMax => wk.OpGroupNonUniformUMax,
+            Self::FMax => wk.OpGroupNonUniformFMax,
+            Self::BitwiseAnd => wk.OpGroupNonUniformBitwiseAnd,
+            Self::BitwiseOr => wk.OpGroupNonUniformBitwiseOr,
+            Self::BitwiseXor => wk.OpGroupNonUniformBitwiseXor,
+            Self::LogicalAnd => wk.OpGroupNonUniformLogicalAnd,
+            Self::LogicalOr => wk.OpGroupNonUniformLogicalOr,
+            Self::LogicalXor => wk.OpGroupNonUniformLogicalXor,
+            Self::QuadBroadcast => wk.OpGroupNonUniformQuadBroadcast,
+            Self::QuadSwap => wk.OpGroupNonUniformQuadSwap,
+        }
+    }
+}
+
+/// Cooperative matrix type (i.e. SPIR-V `OpTypeCooperativeMatrixKHR`), with its
+/// operands decoded (see [`CoopMatrixType::of_type`]).
+///
+/// Only `component_type` is always known, as the other operands are constants
+/// which may be specialization constants (most commonly `rows`/`columns`).
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct CoopMatrixType {
+    pub component_type: Type,
+
+    /// SPIR-V `Scope` enumerand, for the invocations sharing the matrix.
+    pub scope: Option<u32>,
+
+    pub rows: Option<u32>,
+    pub columns: Option<u32>,
+
+    /// SPIR-V `CooperativeMatrixUse` enumerand (`MatrixAKHR`, `MatrixBKHR`,
+    /// or `MatrixAccumulatorKHR`), i.e. which operand of a multiply-add it
+    /// can be used as (as matrices are `A * B + Accumulator`).
+    pub matrix_use: Option<u32>,
+}
+
+impl CoopMatrixType {
+    /// Decode `ty`, if it's a cooperative matrix type.
+    pub fn of_type(cx: &Context, ty: Type) -> Option<Self> {
+        let wk = &spec::Spec::get().well_known;
+
+        let ty_def = &cx[ty];
+        match &ty_def.ctor {
+            TypeCtor::SpvInst(inst) if inst.opcode == wk.OpTypeCooperativeMatrixKHR => {}
+            _ => return None,
+        }
+
+        let const_u32 = |arg: &TypeCtorArg| match *arg {
+            TypeCtorArg::Const(ct) => match &cx[ct].ctor {
+                ConstCtor::SpvInst(Inst { opcode, imms }) if *opcode == wk.OpConstant => {
+                    match imms[..] {
+                        [Imm::Short(_, x)] => Some(x),
+                        _ => None,
+                    }
+                }
+                _ => None,
+            },
+            TypeCtorArg::Type(_) => None,
+        };
+        match &ty_def.ctor_args[..] {
+            [
+                TypeCtorArg::Type(component_type),
+                scope,
+                rows,
+                columns,
+                matrix_use,
+            ] => Some(Self {
+                component_type: *component_type,
+                scope: const_u32(scope),
+                rows: const_u32(rows),
+                columns: const_u32(columns),
+                matrix_use: const_u32(matrix_use),
+            }),
+            _ => None,
+        }
+    }
+
+    /// Whether `self` and `other` have the same shape (i.e. the same number of
+    /// `rows` and `columns`), if that's known for both, or `None` otherwise.
+    pub fn same_shape_as(&self, other: &Self) -> Option<bool> {
+        Some((self.rows?, self.columns?) == (other.rows?, other.columns?))
+    }
+}
+
 /// Non-semantic details (i.e. debuginfo) of a SPIR-V module (not tied to any IDs).
 #[derive(Clone)]
 pub struct ModuleDebugInfo {
@@ -70,6 +763,37 @@ impl From<spec::Opcode> for Inst {
     }
 }
 
+impl Inst {
+    /// Iterate over the requirements of the opcode, and of every enumerand
+    /// (i.e. `BitEnum` bit or `ValueEnum` variant) in the immediate operands.
+    fn requirements(&self) -> impl Iterator<Item = &'static spec::Requirements> + '_ {
+        let enumerand_reqs = self.imms.iter().flat_map(|&imm| {
+            let (kind, word) = match imm {
+                Imm::Short(kind, word) => (Some(kind), word),
+                Imm::LongStart(..) | Imm::LongCont(..) => (None, 0),
+            };
+            let reqs: SmallVec<[_; 2]> = match kind.map(|kind| kind.def()) {
+                Some(spec::OperandKindDef::BitEnum { bits, .. }) => {
+                    spec::BitIdx::of_all_set_bits(word)
+                        .filter_map(|bit_idx| Some(&bits.get(bit_idx)?.reqs))
+                        .collect()
+                }
+                Some(spec::OperandKindDef::ValueEnum { variants }) => u16::try_from(word)
+                    .ok()
+                    .and_then(|word| variants.get(word))
+                    .map(|enumerant| &enumerant.reqs)
+                    .into_iter()
+                    .collect(),
+                Some(spec::OperandKindDef::Id | spec::OperandKindDef::Literal { .. }) | None => {
+                    SmallVec::new()
+                }
+            };
+            reqs
+        });
+        iter::once(&self.opcode.def().reqs).chain(enumerand_reqs)
+    }
+}
+
 /// A full SPIR-V instruction (like [`Inst`], but including input/output ID operands).
 pub struct InstWithIds {
     pub without_ids: Inst,
@@ -115,7 +839,7 @@ pub type Id = NonZeroU32;
 /// Given a single `LiteralString` (as one [`Imm::Short`] or a [`Imm::LongStart`]
 /// followed by some number of [`Imm::LongCont`] - will panic otherwise), returns a
 /// Rust [`String`] if the literal is valid UTF-8, or the validation error otherwise.
-fn extract_literal_string(imms: &[Imm]) -> Result<String, FromUtf8Error> {
+pub(crate) fn extract_literal_string(imms: &[Imm]) -> Result<String, FromUtf8Error> {
     let wk = &spec::Spec::get().well_known;
 
     let mut words = match *imms {
@@ -146,7 +870,7 @@ fn extract_literal_string(imms: &[Imm]) -> Result<String, FromUtf8Error> {
 }
 
 // FIXME(eddyb) this shouldn't just panic when `s.contains('\0')`.
-fn encode_literal_string(s: &str) -> impl Iterator<Item = Imm> + '_ {
+pub(crate) fn encode_literal_string(s: &str) -> impl Iterator<Item = Imm> + '_ {
     let wk = &spec::Spec::get().well_known;
 
     let bytes = s.as_bytes();
@@ -5,6 +5,7 @@ use rustc_hash::FxHashMap;
 use smallvec::SmallVec;
 use std::borrow::Cow;
 use std::num::NonZeroU32;
+use std::ops::Range;
 use std::path::Path;
 use std::{fs, io, iter, slice};
 
@@ -54,6 +55,9 @@ enum InstParseError {
     /// Unsupported enumerand value.
     UnsupportedEnumerand(spec::OperandKind, u32),
 
+    /// Unsupported opcode for the operation of an `OpSpecConstantOp`.
+    UnsupportedSpecConstantOpOpcode(u32),
+
     /// An `IdResultType` ID referring to an ID not already defined.
     UnknownResultTypeId(spv::Id),
 
@@ -90,6 +94,9 @@ impl InstParseError {
                     _ => unreachable!(),
                 }
             }
+            Self::UnsupportedSpecConstantOpOpcode(word) => {
+                format!("unsupported opcode {word} for OpSpecConstantOp").into()
+            }
             Self::UnknownResultTypeId(id) => {
                 format!("ID %{id} used as result type before definition").into()
             }
@@ -121,6 +128,8 @@ impl InstParser<'_> {
     fn operand(&mut self, kind: spec::OperandKind) -> Result<(), InstParseError> {
         use InstParseError as Error;
 
+        let wk = &spec::Spec::get().well_known;
+
         let word = self.words.next().ok_or(Error::NotEnoughWords)?;
         match kind.def() {
             spec::OperandKindDef::BitEnum { bits, .. } => {
@@ -153,6 +162,23 @@ impl InstParser<'_> {
                 size: spec::LiteralSize::Word,
             } => {
                 self.inst.imms.push(spv::Imm::Short(kind, word));
+
+                // NOTE(eddyb) `OpSpecConstantOp` is the only instruction with
+                // operands not described by its own definition, and instead
+                // they're those of the instruction its `Opcode` operand names
+                // (without `IdResultType`/`IdResult`, which it has itself).
+                if kind == wk.LiteralSpecConstantOpInteger {
+                    let (_, _, def) = u16::try_from(word)
+                        .ok()
+                        .and_then(spec::Opcode::try_from_u16_with_name_and_def)
+                        .ok_or(Error::UnsupportedSpecConstantOpOpcode(word))?;
+                    for (mode, kind) in def.all_operands() {
+                        if mode == spec::OperandMode::Optional && self.is_exhausted() {
+                            break;
+                        }
+                        self.operand(kind)?;
+                    }
+                }
             }
             spec::OperandKindDef::Literal {
                 size: spec::LiteralSize::NulTerminated,
@@ -251,14 +277,67 @@ impl InstParser<'_> {
     }
 }
 
-pub struct ModuleParser {
+/// The words of a SPIR-V module, either already in native endianness, or left
+/// as bytes (of any alignment and endianness), to be decoded lazily (i.e. only
+/// as each instruction is parsed), avoiding the need to copy the whole module.
+enum ModuleWords<'a> {
+    Native(Cow<'a, [u32]>),
+    Bytes {
+        bytes: Cow<'a, [u8]>,
+
+        /// Whether each word needs to be byte-swapped (after being read from
+        /// its bytes in native endianness).
+        swap: bool,
+    },
+}
+
+impl ModuleWords<'_> {
+    fn len(&self) -> usize {
+        match self {
+            ModuleWords::Native(words) => words.len(),
+            ModuleWords::Bytes { bytes, .. } => bytes.len() / 4,
+        }
+    }
+
+    fn word(&self, idx: usize) -> Option<u32> {
+        match self {
+            ModuleWords::Native(words) => words.get(idx).copied(),
+            ModuleWords::Bytes { bytes, swap } => {
+                let word = u32::from_ne_bytes(bytes.get(idx * 4..)?.get(..4)?.try_into().unwrap());
+                Some(if *swap { word.swap_bytes() } else { word })
+            }
+        }
+    }
+
+    /// Get the words in `range`, decoding them into `buf` first, if necessary.
+    fn words<'b>(&'b self, range: Range<usize>, buf: &'b mut Vec<u32>) -> &'b [u32] {
+        match self {
+            ModuleWords::Native(words) => &words[range],
+            ModuleWords::Bytes { bytes, swap } => {
+                buf.clear();
+                buf.extend(bytes[range.start * 4..range.end * 4].chunks_exact(4).map(
+                    |word_bytes| {
+                        let word = u32::from_ne_bytes(word_bytes.try_into().unwrap());
+                        if *swap { word.swap_bytes() } else { word }
+                    },
+                ));
+                buf
+            }
+        }
+    }
+}
+
+pub struct ModuleParser<'a> {
     /// Copy of the header words (for convenience).
     // FIXME(eddyb) add a `spec::Header` or `spv::Header` struct with named fields.
     pub header: [u32; spec::HEADER_LEN],
 
-    /// The entire module's bytes, representing "native endian" SPIR-V words.
-    // FIXME(eddyb) could this be allocated as `Vec<u32>` in the first place?
-    word_bytes: Vec<u8>,
+    /// The entire module's SPIR-V words.
+    words: ModuleWords<'a>,
+
+    /// Buffer reused for decoding the words of each instruction, if needed
+    /// (i.e. only when `words` isn't already in native endianness).
+    inst_words_buf: Vec<u32>,
 
     /// Next (instructions') word position in the module.
     next_word: usize,
@@ -275,43 +354,111 @@ fn invalid(reason: &str) -> io::Error {
     )
 }
 
-impl ModuleParser {
+impl ModuleParser<'static> {
     pub fn read_from_spv_file(path: impl AsRef<Path>) -> io::Result<Self> {
-        Self::read_from_spv_bytes(fs::read(path)?)
+        Self::read_from_spv_bytes_cow(Cow::Owned(fs::read(path)?))
     }
 
-    // FIXME(eddyb) also add `from_spv_words`.
-    pub fn read_from_spv_bytes(spv_bytes: Vec<u8>) -> io::Result<Self> {
-        let spv_spec = spec::Spec::get();
+    /// Read a SPIR-V module from its bytes (e.g. the contents of a `.spv` file),
+    /// in either endianness (which is detected from the magic number).
+    ///
+    /// See also [`ModuleParser::read_from_spv_byte_slice`], which avoids copying.
+    pub fn read_from_spv_bytes(spv_bytes: impl AsRef<[u8]>) -> io::Result<Self> {
+        Self::read_from_spv_bytes_cow(Cow::Owned(spv_bytes.as_ref().to_vec()))
+    }
 
-        if spv_bytes.len() % 4 != 0 {
-            return Err(invalid("not a multiple of 4 bytes"));
-        }
-        // May need to mutate the bytes (to normalize endianness) later below.
-        let mut spv_bytes = spv_bytes;
-        let spv_words = bytemuck::cast_slice_mut::<u8, u32>(&mut spv_bytes);
+    /// Read a SPIR-V module from its words (which are all byte-swapped first,
+    /// if that's needed for the magic number to be correct).
+    ///
+    /// See also [`ModuleParser::read_from_spv_word_slice`], which avoids copying.
+    pub fn read_from_spv_words(spv_words: impl Into<Vec<u32>>) -> io::Result<Self> {
+        let spv_spec = spec::Spec::get();
 
-        if spv_words.len() < spec::HEADER_LEN {
-            return Err(invalid("truncated header"));
-        }
+        // May need to mutate the words (to normalize endianness) later below.
+        let mut spv_words = spv_words.into();
 
         // Check the magic, and swap endianness of all words if we have to.
-        {
-            let magic = spv_words[0];
-            if magic == spv_spec.magic {
-                // Nothing to do, all words already match native endianness.
-            } else if magic.swap_bytes() == spv_spec.magic {
+        if let Some(&magic) = spv_words.first() {
+            if magic != spv_spec.magic && magic.swap_bytes() == spv_spec.magic {
                 for word in &mut spv_words[..] {
                     *word = word.swap_bytes();
                 }
-            } else {
-                return Err(invalid("incorrect magic number"));
             }
         }
 
+        Self::from_module_words(ModuleWords::Native(Cow::Owned(spv_words)))
+    }
+}
+
+impl<'a> ModuleParser<'a> {
+    /// Read a SPIR-V module from its bytes (e.g. a memory-mapped `.spv` file),
+    /// in either endianness (which is detected from the magic number), without
+    /// copying them (with each word only decoded when its instruction is parsed).
+    pub fn read_from_spv_byte_slice(spv_bytes: &'a [u8]) -> io::Result<Self> {
+        Self::read_from_spv_bytes_cow(Cow::Borrowed(spv_bytes))
+    }
+
+    /// Read a SPIR-V module from its words, in either endianness (which is
+    /// detected from the magic number), without copying them (with each word
+    /// only byte-swapped, if needed, when its instruction is parsed).
+    pub fn read_from_spv_word_slice(spv_words: &'a [u32]) -> io::Result<Self> {
+        let spv_spec = spec::Spec::get();
+
+        match spv_words.first() {
+            Some(&magic) if magic != spv_spec.magic && magic.swap_bytes() == spv_spec.magic => {
+                Self::from_module_words(ModuleWords::Bytes {
+                    bytes: Cow::Borrowed(bytemuck::cast_slice(spv_words)),
+                    swap: true,
+                })
+            }
+            _ => Self::from_module_words(ModuleWords::Native(Cow::Borrowed(spv_words))),
+        }
+    }
+
+    fn read_from_spv_bytes_cow(spv_bytes: Cow<'a, [u8]>) -> io::Result<Self> {
+        let spv_spec = spec::Spec::get();
+
+        if spv_bytes.len() % 4 != 0 {
+            return Err(invalid("not a multiple of 4 bytes"));
+        }
+        let magic = match spv_bytes.get(..4) {
+            Some(magic_bytes) => u32::from_ne_bytes(magic_bytes.try_into().unwrap()),
+            None => return Err(invalid("truncated header")),
+        };
+        let swap = magic != spv_spec.magic && magic.swap_bytes() == spv_spec.magic;
+
+        // NOTE(eddyb) borrowed bytes can only be reinterpreted as `[u32]`
+        // in-place, if they happen to be sufficiently aligned.
+        let words = match spv_bytes {
+            Cow::Borrowed(bytes) if !swap => match bytemuck::try_cast_slice(bytes) {
+                Ok(words) => ModuleWords::Native(Cow::Borrowed(words)),
+                Err(_) => ModuleWords::Bytes {
+                    bytes: Cow::Borrowed(bytes),
+                    swap,
+                },
+            },
+            bytes => ModuleWords::Bytes { bytes, swap },
+        };
+        Self::from_module_words(words)
+    }
+
+    /// Create a [`ModuleParser`] from `words`, which must already be set up to
+    /// produce native endianness words (as this only checks the magic number).
+    fn from_module_words(words: ModuleWords<'a>) -> io::Result<Self> {
+        let spv_spec = spec::Spec::get();
+
+        if words.len() < spec::HEADER_LEN {
+            return Err(invalid("truncated header"));
+        }
+        let header: [u32; spec::HEADER_LEN] = std::array::from_fn(|i| words.word(i).unwrap());
+        if header[0] != spv_spec.magic {
+            return Err(invalid("incorrect magic number"));
+        }
+
         Ok(Self {
-            header: spv_words[..spec::HEADER_LEN].try_into().unwrap(),
-            word_bytes: spv_bytes,
+            header,
+            words,
+            inst_words_buf: vec![],
             next_word: spec::HEADER_LEN,
 
             known_ids: FxHashMap::default(),
@@ -319,17 +466,27 @@ impl ModuleParser {
     }
 }
 
-impl Iterator for ModuleParser {
+impl Iterator for ModuleParser<'_> {
     type Item = io::Result<spv::InstWithIds>;
     fn next(&mut self) -> Option<Self::Item> {
         let spv_spec = spec::Spec::get();
         let wk = &spv_spec.well_known;
 
-        let words = &bytemuck::cast_slice::<u8, u32>(&self.word_bytes)[self.next_word..];
-        let &opcode = words.first()?;
+        let inst_start = self.next_word;
+        let opcode = self.words.word(inst_start)?;
 
         let (inst_len, opcode) = ((opcode >> 16) as usize, opcode as u16);
 
+        // Move past the instruction early (if its word count is usable), so that
+        // any errors below only affect this one instruction, and iteration may
+        // continue after them (e.g. for permissive lowering, which can skip them).
+        let has_valid_len = inst_len > 0 && self.words.len() - inst_start >= inst_len;
+        self.next_word = if has_valid_len {
+            inst_start + inst_len
+        } else {
+            self.words.len()
+        };
+
         let (opcode, inst_name, def) = match spec::Opcode::try_from_u16_with_name_and_def(opcode) {
             Some(opcode_name_and_def) => opcode_name_and_def,
             None => return Some(Err(invalid(&format!("unsupported opcode {opcode}")))),
@@ -337,13 +494,20 @@ impl Iterator for ModuleParser {
 
         let invalid = |msg: &str| invalid(&format!("in {inst_name}: {msg}"));
 
-        if words.len() < inst_len {
+        if !has_valid_len {
             return Some(Err(invalid("truncated instruction")));
         }
 
         let parser = InstParser {
             known_ids: &self.known_ids,
-            words: words[1..inst_len].iter().copied(),
+            words: self
+                .words
+                .words(
+                    inst_start + 1..inst_start + inst_len,
+                    &mut self.inst_words_buf,
+                )
+                .iter()
+                .copied(),
             inst: spv::InstWithIds {
                 without_ids: opcode.into(),
                 result_type_id: None,
@@ -399,8 +563,6 @@ impl Iterator for ModuleParser {
             return Some(Err(e));
         }
 
-        self.next_word += inst_len;
-
         Some(Ok(inst))
     }
 }
@@ -0,0 +1,25 @@
+//! Support for the `NonSemantic.Shader.DebugInfo.100` extended instruction set
+//! (i.e. debuginfo richer than e.g. `OpLine`, such as scopes and variables).
+//!
+//! Most of its instructions are module-level definitions (which are lowered to
+//! [`ConstCtor::SpvExtInst`](crate::ConstCtor::SpvExtInst)s), or are kept as
+//! [`DataInstKind::SpvExtInst`](crate::DataInstKind::SpvExtInst)s in functions
+//! (e.g. `DebugDeclare`/`DebugValue`), while the ones which only indicate the
+//! debuginfo for the instructions following them, in the same block (i.e.
+//! `DebugScope`/`DebugLine` and their "no" counterparts), are lowered to
+//! attributes on those instructions (see [`Attr::SpvShaderDebugScope`] and
+//! [`Attr::SpvShaderDebugLine`]), like `OpLine` is (see [`Attr::SpvDebugLine`]).
+//!
+//! [`Attr::SpvShaderDebugScope`]: crate::Attr::SpvShaderDebugScope
+//! [`Attr::SpvShaderDebugLine`]: crate::Attr::SpvShaderDebugLine
+//! [`Attr::SpvDebugLine`]: crate::Attr::SpvDebugLine
+
+/// Name of the extended instruction set (i.e. the `OpExtInstImport` operand).
+pub const EXT_INST_SET_NAME: &str = "NonSemantic.Shader.DebugInfo.100";
+
+// NOTE(eddyb) only the instructions with special handling are listed here,
+// see the specification for the full set.
+pub const DEBUG_SCOPE: u32 = 23;
+pub const DEBUG_NO_SCOPE: u32 = 24;
+pub const DEBUG_LINE: u32 = 103;
+pub const DEBUG_NO_LINE: u32 = 104;
@@ -3,6 +3,7 @@
 use arrayvec::ArrayVec;
 use lazy_static::lazy_static;
 use rustc_hash::FxHashMap;
+use smallvec::SmallVec;
 use std::iter;
 
 use self::indexed::FlatIdx as _;
@@ -81,10 +82,18 @@ def_well_known! {
         OpLine,
         OpNoLine,
 
+        OpTypeVoid,
         OpTypeBool,
         OpTypeInt,
         OpTypeFloat,
         OpTypeVector,
+        OpTypeMatrix,
+        OpTypeArray,
+        OpTypeRuntimeArray,
+        OpTypeStruct,
+        OpTypeImage,
+        OpTypeSampler,
+        OpTypeSampledImage,
         OpTypeForwardPointer,
         OpTypePointer,
         OpTypeFunction,
@@ -92,9 +101,21 @@ def_well_known! {
         OpConstantFalse,
         OpConstantTrue,
         OpConstant,
+        OpConstantComposite,
+        OpConstantNull,
+        OpSpecConstantFalse,
+        OpSpecConstantTrue,
+        OpSpecConstant,
+        OpSpecConstantComposite,
+        OpSpecConstantOp,
         OpUndef,
 
         OpVariable,
+        OpLoad,
+        OpStore,
+        OpCopyObject,
+        OpAccessChain,
+        OpInBoundsAccessChain,
 
         OpFunction,
         OpFunctionParameter,
@@ -113,6 +134,171 @@ def_well_known! {
         OpSwitch,
 
         OpFunctionCall,
+
+        // Address conversions (see `AddrSpace::PhysicalStorageBuffer`).
+        OpConvertPtrToU,
+        OpConvertUToPtr,
+        OpBitcast,
+
+        // Atomic operations (see `DataInstKind::Atomic`).
+        OpAtomicLoad,
+        OpAtomicStore,
+        OpAtomicExchange,
+        OpAtomicCompareExchange,
+        OpAtomicCompareExchangeWeak,
+        OpAtomicIIncrement,
+        OpAtomicIDecrement,
+        OpAtomicIAdd,
+        OpAtomicISub,
+        OpAtomicSMin,
+        OpAtomicUMin,
+        OpAtomicSMax,
+        OpAtomicUMax,
+        OpAtomicAnd,
+        OpAtomicOr,
+        OpAtomicXor,
+        OpAtomicFlagTestAndSet,
+        OpAtomicFlagClear,
+        OpAtomicFAddEXT,
+        OpAtomicFMinEXT,
+        OpAtomicFMaxEXT,
+
+        // Barriers (see `DataInstKind::Barrier`).
+        OpControlBarrier,
+        OpMemoryBarrier,
+
+        // Group operations (see `DataInstKind::Group`).
+        OpGroupNonUniformElect,
+        OpGroupNonUniformAll,
+        OpGroupNonUniformAny,
+        OpGroupNonUniformAllEqual,
+        OpGroupNonUniformBroadcast,
+        OpGroupNonUniformBroadcastFirst,
+        OpGroupNonUniformBallot,
+        OpGroupNonUniformInverseBallot,
+        OpGroupNonUniformBallotBitExtract,
+        OpGroupNonUniformBallotBitCount,
+        OpGroupNonUniformBallotFindLSB,
+        OpGroupNonUniformBallotFindMSB,
+        OpGroupNonUniformShuffle,
+        OpGroupNonUniformShuffleXor,
+        OpGroupNonUniformShuffleUp,
+        OpGroupNonUniformShuffleDown,
+        OpGroupNonUniformIAdd,
+        OpGroupNonUniformFAdd,
+        OpGroupNonUniformIMul,
+        OpGroupNonUniformFMul,
+        OpGroupNonUniformSMin,
+        OpGroupNonUniformUMin,
+        OpGroupNonUniformFMin,
+        OpGroupNonUniformSMax,
+        OpGroupNonUniformUMax,
+        OpGroupNonUniformFMax,
+        OpGroupNonUniformBitwiseAnd,
+        OpGroupNonUniformBitwiseOr,
+        OpGroupNonUniformBitwiseXor,
+        OpGroupNonUniformLogicalAnd,
+        OpGroupNonUniformLogicalOr,
+        OpGroupNonUniformLogicalXor,
+        OpGroupNonUniformQuadBroadcast,
+        OpGroupNonUniformQuadSwap,
+
+        // Ray tracing (see `DataInstKind::invokes_ray_tracing_shaders`, and
+        // `ControlNodeKind::ExitInvocation` for `OpIgnoreIntersectionKHR`
+        // and `OpTerminateRayKHR`).
+        OpTraceRayKHR,
+        OpExecuteCallableKHR,
+        OpReportIntersectionKHR,
+        OpIgnoreIntersectionKHR,
+        OpTerminateRayKHR,
+
+        // Mesh shading (see `cfg::ExitInvocationKind::EmitMeshTasks`).
+        OpEmitMeshTasksEXT,
+
+        // Cooperative matrices (see `spv::CoopMatrixType`).
+        OpTypeCooperativeMatrixKHR,
+        // NOTE(eddyb) this takes a type operand (see `ConstCtor::SpvTypeOperand`).
+        OpCooperativeMatrixLengthKHR,
+
+        // Operations supported by constant folding (see `spv::fold`).
+        OpSConvert,
+        OpUConvert,
+        OpSNegate,
+        OpNot,
+        OpIAdd,
+        OpISub,
+        OpIMul,
+        OpUDiv,
+        OpSDiv,
+        OpUMod,
+        OpSRem,
+        OpSMod,
+        OpShiftRightLogical,
+        OpShiftRightArithmetic,
+        OpShiftLeftLogical,
+        OpBitwiseOr,
+        OpBitwiseXor,
+        OpBitwiseAnd,
+        OpLogicalEqual,
+        OpLogicalNotEqual,
+        OpLogicalOr,
+        OpLogicalAnd,
+        OpLogicalNot,
+        OpSelect,
+        OpIEqual,
+        OpINotEqual,
+        OpUGreaterThan,
+        OpSGreaterThan,
+        OpUGreaterThanEqual,
+        OpSGreaterThanEqual,
+        OpULessThan,
+        OpSLessThan,
+        OpULessThanEqual,
+        OpSLessThanEqual,
+        OpFNegate,
+        OpFAdd,
+        OpFSub,
+        OpFMul,
+        OpFDiv,
+        OpFRem,
+        OpFMod,
+        OpConvertFToU,
+        OpConvertFToS,
+        OpConvertSToF,
+        OpConvertUToF,
+        OpFConvert,
+        OpFOrdEqual,
+        OpFUnordEqual,
+        OpFOrdNotEqual,
+        OpFUnordNotEqual,
+        OpFOrdLessThan,
+        OpFUnordLessThan,
+        OpFOrdGreaterThan,
+        OpFUnordGreaterThan,
+        OpFOrdLessThanEqual,
+        OpFUnordLessThanEqual,
+        OpFOrdGreaterThanEqual,
+        OpFUnordGreaterThanEqual,
+        OpCompositeConstruct,
+        OpCompositeExtract,
+        OpVectorShuffle,
+
+        // Used by strength reduction (see `passes::strength_reduce`).
+        OpUMulExtended,
+        OpSMulExtended,
+
+        // Used by scalarization (see `passes::scalarize`).
+        OpCompositeInsert,
+
+        // Used by combined image splitting (see `passes::image_split`).
+        OpSampledImage,
+
+        // Used by bounds checking (see `passes::bounds_check`).
+        OpArrayLength,
+
+        // Used by 64-bit float emulation (see `passes::int64_emulation`).
+        OpIsNan,
+        OpIsInf,
     ],
     operand_kind: OperandKind = [
         Capability,
@@ -125,22 +311,111 @@ def_well_known! {
         LinkageType,
         SelectionControl,
         LoopControl,
+        Dim,
+        ImageFormat,
+        AccessQualifier,
+        Scope,
+        MemorySemantics,
+        GroupOperation,
+
+        // Used by workgroup size specialization (see `passes::workgroup_size`).
+        ExecutionMode,
 
         LiteralInteger,
         LiteralExtInstInteger,
         LiteralString,
+        LiteralContextDependentNumber,
+        LiteralSpecConstantOpInteger,
     ],
     // FIXME(eddyb) find a way to namespace these to avoid conflicts.
     storage_class: u32 = [
         Function,
+        Private,
+        PhysicalStorageBuffer,
+
+        // Shader stage interfaces (see `passes::io_locations`).
+        Input,
+        Output,
+
+        // Resources (see e.g. `passes::image_split`).
+        UniformConstant,
+
+        // Device limits (see `spv::target_env::Limits`).
+        Uniform,
+        StorageBuffer,
+        PushConstant,
+
+        // Used by zero-initialization (see `passes::zero_init`).
+        Workgroup,
+
+        // Ray tracing (see `AddrSpace::is_ray_tracing_interface`).
+        RayPayloadKHR,
+        IncomingRayPayloadKHR,
+        HitAttributeKHR,
+        CallableDataKHR,
+        IncomingCallableDataKHR,
+        ShaderRecordBufferKHR,
     ],
     decoration: u32 = [
+        SpecId,
+        Offset,
         LinkageAttributes,
+
+        // Used by relaxed precision lowering (see `passes::relaxed_precision`).
+        RelaxedPrecision,
+
+        // Used by alias analysis (see `alias`).
+        Restrict,
+        Aliased,
+
+        // Explicit memory layouts (see `layout`).
+        ArrayStride,
+        MatrixStride,
+        RowMajor,
+        ColMajor,
+
+        // Resource bindings (see `passes::binding_remap`).
+        DescriptorSet,
+        Binding,
+
+        // Shader stage interfaces (see `passes::io_locations`).
+        BuiltIn,
+        Location,
+        Patch,
+    ],
+    // Used by workgroup size specialization (see `passes::workgroup_size`).
+    builtin: u32 = [
+        WorkgroupSize,
     ],
     linkage_type: u32 = [
         Import,
         Export,
     ],
+    capability: u32 = [
+        // Used by relaxed precision lowering (see `passes::relaxed_precision`).
+        Float16,
+
+        // Used by 64-bit integer/float emulation (see `passes::int64_emulation`).
+        Int64,
+        Float64,
+    ],
+    execution_model: u32 = [
+        // Device limits (see `spv::target_env::Limits`).
+        Vertex,
+        Fragment,
+        GLCompute,
+
+        TessellationControl,
+        TessellationEvaluation,
+        Geometry,
+        MeshNV,
+        MeshEXT,
+    ],
+    // Used by workgroup size specialization (see `passes::workgroup_size`).
+    execution_mode: u32 = [
+        LocalSize,
+        LocalSizeId,
+    ],
 }
 
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -197,6 +472,74 @@ pub struct InstructionDef {
     pub req_operands: ArrayVec<OperandKind, 16>,
     pub opt_operands: ArrayVec<OperandKind, 2>,
     pub rest_operands: Option<RestOperandsUnit>,
+
+    pub reqs: Requirements,
+}
+
+/// Requirements for using an instruction or enumerant, in a SPIR-V module.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Requirements {
+    /// The SPIR-V version (as `(major, minor)`) which first included this
+    /// in the core specification, or `None` if only available via extensions.
+    pub min_version: Option<(u8, u8)>,
+
+    /// Extensions which can be enabled to gain access to this, as an alternative
+    /// to a SPIR-V version of at least `min_version` (for the same effect).
+    pub extensions: SmallVec<[&'static str; 1]>,
+
+    /// Capabilities (by name), any of which, if declared, allows using this
+    /// (or, for `Capability` enumerants, those which it implicitly declares).
+    pub capabilities: SmallVec<[&'static str; 1]>,
+}
+
+impl Requirements {
+    /// Combine the requirements of two aliases, i.e. names for the same opcode
+    /// (or enumerant value), which differ only in how they're made available
+    /// (e.g. an extension having been included in a later SPIR-V version).
+    fn merge_aliases(&self, other: &Self) -> Self {
+        let min_version = match (self.min_version, other.min_version) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        let mut extensions = self.extensions.clone();
+        for &ext in &other.extensions {
+            if !extensions.contains(&ext) {
+                extensions.push(ext);
+            }
+        }
+        let mut capabilities = self.capabilities.clone();
+        for &cap in &other.capabilities {
+            if !capabilities.contains(&cap) {
+                capabilities.push(cap);
+            }
+        }
+        Self {
+            min_version,
+            extensions,
+            capabilities,
+        }
+    }
+
+    fn from_raw(
+        version: Option<&str>,
+        extensions: &[&'static str],
+        capabilities: &[&'static str],
+    ) -> Self {
+        Self {
+            // NOTE(eddyb) a missing version is implicitly SPIR-V 1.0, but the
+            // string `None` indicates lack of inclusion in any core version.
+            min_version: match version {
+                None => Some((1, 0)),
+                Some("None") => None,
+                Some(version) => {
+                    let (major, minor) = version.split_once('.').unwrap();
+                    Some((major.parse().unwrap(), minor.parse().unwrap()))
+                }
+            },
+            extensions: extensions.iter().copied().collect(),
+            capabilities: capabilities.iter().copied().collect(),
+        }
+    }
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
@@ -346,6 +689,8 @@ impl indexed::FlatIdx for BitIdx {
 pub struct Enumerant {
     pub req_params: ArrayVec<OperandKind, 4>,
     pub rest_params: Option<OperandKind>,
+
+    pub reqs: Requirements,
 }
 
 impl Enumerant {
@@ -404,6 +749,27 @@ impl Spec {
         &SPEC
     }
 
+    /// Iterate over the requirements of every instruction and enumerant (with
+    /// aliases possibly resulting in the same requirements being repeated).
+    pub fn all_requirements(&'static self) -> impl Iterator<Item = &'static Requirements> {
+        let instructions = self.instructions.iter().map(|(_, _, def)| &def.reqs);
+        let enumerants = self.operand_kinds.iter().flat_map(|(_, _, kind_def)| {
+            let reqs: Vec<_> = match kind_def {
+                OperandKindDef::BitEnum { bits, .. } => bits
+                    .iter()
+                    .map(|(_, _, enumerant)| &enumerant.reqs)
+                    .collect(),
+                OperandKindDef::ValueEnum { variants } => variants
+                    .iter()
+                    .map(|(_, _, enumerant)| &enumerant.reqs)
+                    .collect(),
+                OperandKindDef::Id | OperandKindDef::Literal { .. } => vec![],
+            };
+            reqs
+        });
+        instructions.chain(enumerants)
+    }
+
     /// Implementation detail of [`Spec::get`], indexes the raw data to produce a [`Spec`].
     fn from_raw(raw_core_grammar: raw::CoreGrammar<'static>) -> Self {
         /// Helper for picking a name when the same index has multiple names.
@@ -437,7 +803,7 @@ impl Spec {
             .operand_kinds
             .iter()
             .filter_map(|o| {
-                let enumerant_from_raw = |e: &raw::OperandKindEnumerant<'_>| {
+                let enumerant_from_raw = |e: &raw::OperandKindEnumerant<'static>| {
                     let mut all_params = e
                         .parameters
                         .iter()
@@ -460,6 +826,7 @@ impl Spec {
                     Enumerant {
                         req_params,
                         rest_params,
+                        reqs: Requirements::from_raw(e.version, &e.extensions, &e.capabilities),
                     }
                 };
 
@@ -469,7 +836,7 @@ impl Spec {
 
                         let enumerants = o.enumerants.as_ref().unwrap();
                         let mut empty_name = None;
-                        let mut bits = vec![];
+                        let mut bits: Vec<Option<(_, Enumerant)>> = vec![];
                         for e in enumerants {
                             let new_name = e.enumerant;
 
@@ -502,7 +869,13 @@ impl Spec {
 
                             *slot = Some(match slot.take() {
                                 None => (new_name, new_enumerant),
-                                Some((prev_name, prev_enumerant)) => {
+                                Some((prev_name, mut prev_enumerant)) => {
+                                    let mut new_enumerant = new_enumerant;
+                                    let reqs =
+                                        prev_enumerant.reqs.merge_aliases(&new_enumerant.reqs);
+                                    prev_enumerant.reqs = reqs.clone();
+                                    new_enumerant.reqs = reqs;
+
                                     // Only allow aliases that do not meaningfully differ.
                                     assert!(
                                         prev_enumerant == new_enumerant,
@@ -548,7 +921,11 @@ impl Spec {
                                 )
                             }),
                             // `merge_duplicates` closure:
-                            |(prev_name, prev_enumerant), (new_name, new_enumerant)| {
+                            |(prev_name, mut prev_enumerant), (new_name, mut new_enumerant)| {
+                                let reqs = prev_enumerant.reqs.merge_aliases(&new_enumerant.reqs);
+                                prev_enumerant.reqs = reqs.clone();
+                                new_enumerant.reqs = reqs;
+
                                 // Only allow aliases that do not meaningfully differ.
                                 assert!(
                                     prev_enumerant == new_enumerant,
@@ -712,6 +1089,12 @@ impl Spec {
                     req_operands: ArrayVec::new(),
                     opt_operands: ArrayVec::new(),
                     rest_operands: None,
+
+                    reqs: Requirements::from_raw(
+                        inst.version,
+                        &inst.extensions,
+                        &inst.capabilities,
+                    ),
                 };
 
                 #[derive(Copy, Clone, Debug, PartialEq, PartialOrd)]
@@ -768,7 +1151,11 @@ impl Spec {
                 (inst.opcode, (inst.opname, def))
             }),
             // `merge_duplicates` closure:
-            |(prev_name, prev_def), (new_name, new_def)| {
+            |(prev_name, mut prev_def), (new_name, mut new_def)| {
+                let reqs = prev_def.reqs.merge_aliases(&new_def.reqs);
+                prev_def.reqs = reqs.clone();
+                new_def.reqs = reqs;
+
                 // Only allow aliases that do not meaningfully differ.
                 assert!(
                     prev_def == new_def,
@@ -801,6 +1188,23 @@ impl Spec {
             OperandKindDef::ValueEnum { variants } => variants,
             _ => unreachable!(),
         };
+        let capabilities = match &operand_kinds[operand_kinds.lookup("Capability").unwrap()] {
+            OperandKindDef::ValueEnum { variants } => variants,
+            _ => unreachable!(),
+        };
+        let builtins = match &operand_kinds[operand_kinds.lookup("BuiltIn").unwrap()] {
+            OperandKindDef::ValueEnum { variants } => variants,
+            _ => unreachable!(),
+        };
+        let execution_models = match &operand_kinds[operand_kinds.lookup("ExecutionModel").unwrap()]
+        {
+            OperandKindDef::ValueEnum { variants } => variants,
+            _ => unreachable!(),
+        };
+        let execution_modes = match &operand_kinds[operand_kinds.lookup("ExecutionMode").unwrap()] {
+            OperandKindDef::ValueEnum { variants } => variants,
+            _ => unreachable!(),
+        };
 
         // FIXME(eddyb) if this is computed earlier, `IdResultType` and `IdResult`
         // wouldn't be looked up twice - but for now, this is mildly cleaner.
@@ -809,7 +1213,11 @@ impl Spec {
             operand_kind: |name| operand_kinds.lookup(name).unwrap(),
             storage_class: |name| storage_classes.lookup(name).unwrap().into(),
             decoration: |name| decorations.lookup(name).unwrap().into(),
+            builtin: |name| builtins.lookup(name).unwrap().into(),
             linkage_type: |name| linkage_types.lookup(name).unwrap().into(),
+            capability: |name| capabilities.lookup(name).unwrap().into(),
+            execution_model: |name| execution_models.lookup(name).unwrap().into(),
+            execution_mode: |name| execution_modes.lookup(name).unwrap().into(),
         });
 
         Self {
@@ -1191,6 +1599,18 @@ pub mod indexed {
             let (_name, value) = self.get_named(idx)?;
             Some(value)
         }
+
+        /// Iterate over all `(index, name, value)` entries, in no particular
+        /// order (and with aliases sharing the same index and value).
+        pub fn iter(&self) -> impl Iterator<Item = (I, &'static str, &T)> + '_
+        where
+            I: Copy,
+        {
+            self.idx_by_name.values().map(|&idx| {
+                let (name, value) = self.get_named(idx).unwrap();
+                (idx, name, value)
+            })
+        }
     }
 
     impl<I, T, S: StorageShape<I, (&'static str, T)>> std::ops::Index<I> for NamedIdxMap<I, T, S> {
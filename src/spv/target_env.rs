@@ -0,0 +1,940 @@
+//! Target environments (e.g. Vulkan), and checking that a module only uses
+//! SPIR-V features (capabilities and extensions) available in one.
+
+use crate::cfg::{ControlInstKind, ExitInvocationKind};
+use crate::func_at::FuncAt;
+use crate::layout::{LayoutCx, LayoutRules};
+use crate::passes::io_locations::{IoDirection, IoVar};
+use crate::passes::legalize::{
+    reachable_global_vars_and_funcs, reachable_global_vars_and_funcs_from_exports,
+};
+use crate::spv::fold::{const_splat_value, ScalarValue};
+use crate::spv::{self, spec};
+use crate::visit::{InnerVisit, Visitor};
+use crate::{
+    AddrSpace, Attr, AttrSet, Const, ConstCtor, Context, ControlNodeKind, ControlRegion,
+    DataInstDef, DataInstKind, DeclDef, ExportKey, Exportee, Func, FuncDecl, GlobalVar,
+    GlobalVarDecl, Module, ModuleDialect, SelectionKind, Type, TypeCtor, TypeCtorArg,
+};
+use rustc_hash::FxHashSet;
+use smallvec::SmallVec;
+use std::collections::BTreeSet;
+use std::fmt;
+use std::iter;
+
+/// The SPIR-V features available in some target environment (e.g. a specific
+/// Vulkan version, on a device supporting some additional features).
+#[derive(Clone)]
+pub struct TargetEnv {
+    /// The newest SPIR-V version (as `(major, minor)`) supported.
+    pub spv_version: (u8, u8),
+
+    /// Capabilities which can be declared (any capabilities implicitly declared
+    /// by these, as per the SPIR-V grammar, are also available).
+    pub capabilities: BTreeSet<u32>,
+
+    /// Extensions which can be declared.
+    pub extensions: BTreeSet<String>,
+
+    /// Device limits (e.g. the maximum size of push constants).
+    pub limits: Limits,
+}
+
+impl TargetEnv {
+    /// Vulkan 1.0, supporting SPIR-V 1.0, and only the capabilities that every
+    /// Vulkan 1.0 implementation must support (see also [`TargetEnv::with_capabilities`]).
+    pub fn vulkan_1_0() -> Self {
+        Self {
+            spv_version: (1, 0),
+            capabilities: BTreeSet::new(),
+            extensions: BTreeSet::new(),
+            limits: Limits::default(),
+        }
+        .with_capabilities([
+            "Matrix",
+            "Shader",
+            "InputAttachment",
+            "Sampled1D",
+            "Image1D",
+            "SampledBuffer",
+            "ImageBuffer",
+            "ImageQuery",
+            "DerivativeControl",
+        ])
+    }
+
+    /// Vulkan 1.1, supporting SPIR-V 1.3, and only the capabilities that every
+    /// Vulkan 1.1 implementation must support (see also [`TargetEnv::with_capabilities`]).
+    pub fn vulkan_1_1() -> Self {
+        Self {
+            spv_version: (1, 3),
+            ..Self::vulkan_1_0()
+        }
+        .with_capabilities(["DeviceGroup", "MultiView", "GroupNonUniform"])
+    }
+
+    /// Vulkan 1.2, supporting SPIR-V 1.5, and only the capabilities that every
+    /// Vulkan 1.2 implementation must support (see also [`TargetEnv::with_capabilities`]).
+    pub fn vulkan_1_2() -> Self {
+        Self {
+            spv_version: (1, 5),
+            ..Self::vulkan_1_1()
+        }
+    }
+
+    /// Vulkan 1.3, supporting SPIR-V 1.6, and only the capabilities that every
+    /// Vulkan 1.3 implementation must support (see also [`TargetEnv::with_capabilities`]).
+    pub fn vulkan_1_3() -> Self {
+        Self {
+            spv_version: (1, 6),
+            ..Self::vulkan_1_2()
+        }
+        .with_capabilities([
+            "VulkanMemoryModel",
+            "DemoteToHelperInvocation",
+            "DotProduct",
+        ])
+    }
+
+    /// Make additional capabilities (by name, e.g. `"Int64"`) available, which
+    /// is needed for optional features supported by a specific device.
+    ///
+    /// Capability names not found in the SPIR-V grammar are ignored.
+    pub fn with_capabilities<'a>(mut self, names: impl IntoIterator<Item = &'a str>) -> Self {
+        let cap_variants = capability_variants();
+        self.capabilities.extend(
+            names
+                .into_iter()
+                .filter_map(|name| cap_variants.lookup(name).map(u32::from)),
+        );
+        self
+    }
+
+    /// Make additional extensions (by name, e.g. `"SPV_KHR_ray_tracing"`) available.
+    pub fn with_extensions<'a>(mut self, names: impl IntoIterator<Item = &'a str>) -> Self {
+        self.extensions
+            .extend(names.into_iter().map(|name| name.to_string()));
+        self
+    }
+
+    /// Replace the device limits (which default to the Vulkan minimums, see [`Limits`]).
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Get all the available capabilities, i.e. including those implicitly
+    /// declared by the ones in `self.capabilities`.
+    fn enabled_capabilities(&self) -> BTreeSet<u32> {
+        let cap_variants = capability_variants();
+
+        let mut enabled = BTreeSet::new();
+        let mut queue: Vec<_> = self.capabilities.iter().copied().collect();
+        while let Some(cap) = queue.pop() {
+            if enabled.insert(cap) {
+                let enumerant = u16::try_from(cap)
+                    .ok()
+                    .and_then(|cap| cap_variants.get(cap));
+                if let Some(enumerant) = enumerant {
+                    queue.extend(
+                        enumerant
+                            .reqs
+                            .capabilities
+                            .iter()
+                            .filter_map(|&name| cap_variants.lookup(name).map(u32::from)),
+                    );
+                }
+            }
+        }
+        enabled
+    }
+}
+
+/// Device limits (mirroring some of Vulkan's `VkPhysicalDeviceLimits`), which
+/// a module must stay within, to be usable on a specific device.
+///
+/// The [`Default`] values are the minimums required of all Vulkan implementations.
+#[derive(Clone)]
+pub struct Limits {
+    /// `maxPushConstantsSize`: the maximum size (in bytes) of push constants.
+    pub max_push_constants_size: u32,
+
+    /// `maxPerStageResources`: the maximum number of descriptors (i.e. resources
+    /// in the `UniformConstant`, `Uniform` or `StorageBuffer` storage classes,
+    /// counting each element of arrays separately) used by one entry-point.
+    pub max_per_stage_resources: u32,
+
+    /// `maxComputeWorkGroupSize`: the maximum `LocalSize` of compute shaders.
+    pub max_compute_workgroup_size: [u32; 3],
+
+    /// `maxComputeWorkGroupInvocations`: the maximum number of invocations in
+    /// a workgroup (i.e. the product of the `LocalSize` dimensions).
+    pub max_compute_workgroup_invocations: u32,
+
+    /// `maxComputeSharedMemorySize`: the maximum total size (in bytes) of the
+    /// `Workgroup` variables used by one compute shader.
+    pub max_compute_shared_memory_size: u32,
+
+    /// `maxVertexInputAttributes`: the maximum number of vertex shader input `Location`s.
+    pub max_vertex_input_locations: u32,
+
+    /// `maxFragmentOutputAttachments`: the maximum number of fragment shader
+    /// output `Location`s.
+    pub max_fragment_output_locations: u32,
+
+    /// The maximum number of `Location`s for any other shader stage interface
+    /// (i.e. `maxVertexOutputComponents`, `maxFragmentInputComponents` etc.,
+    /// divided by the 4 components in each `Location`).
+    pub max_interface_locations: u32,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_push_constants_size: 128,
+            max_per_stage_resources: 128,
+            max_compute_workgroup_size: [128, 128, 64],
+            max_compute_workgroup_invocations: 128,
+            max_compute_shared_memory_size: 16384,
+            max_vertex_input_locations: 16,
+            max_fragment_output_locations: 4,
+            max_interface_locations: 16,
+        }
+    }
+}
+
+fn capability_variants()
+-> &'static spec::indexed::NamedIdxMap<u16, spec::Enumerant, spec::indexed::KhrSegmented> {
+    let wk = &spec::Spec::get().well_known;
+
+    match wk.Capability.def() {
+        spec::OperandKindDef::ValueEnum { variants } => variants,
+        _ => unreachable!(),
+    }
+}
+
+/// The entity (in a [`Module`]) a [`TargetEnvDiagnostic`] is about.
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+pub enum TargetEnvAnchor {
+    /// The module itself (e.g. its [`spv::Dialect`], or its entry-points).
+    Module,
+
+    GlobalVar(GlobalVar),
+    Func(Func),
+
+    /// An entry-point (exporting this function), e.g. exceeding some [`Limits`]
+    /// across all the global variables it uses.
+    EntryPoint(Func),
+}
+
+impl fmt::Display for TargetEnvAnchor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Module => "module",
+            Self::GlobalVar(_) => "global variable",
+            Self::Func(_) => "function",
+            Self::EntryPoint(_) => "entry-point",
+        })
+    }
+}
+
+/// Use of a SPIR-V feature not available in a [`TargetEnv`] (see [`check_module`]).
+#[derive(Clone)]
+pub struct TargetEnvDiagnostic {
+    /// The global variable or function using the feature (types, constants and
+    /// decorations are only reported for the first one that uses them).
+    pub anchor: TargetEnvAnchor,
+
+    pub message: String,
+}
+
+impl fmt::Display for TargetEnvDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.anchor, self.message)
+    }
+}
+
+/// Error produced by [`check_module`], with all the unavailable features used.
+pub struct TargetEnvError {
+    pub diagnostics: Vec<TargetEnvDiagnostic>,
+}
+
+impl fmt::Display for TargetEnvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "module not supported by the target environment:")?;
+        for diag in &self.diagnostics {
+            write!(f, "\n  {diag}")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for TargetEnvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl std::error::Error for TargetEnvError {}
+
+/// Check that `module` only uses SPIR-V features available in `target_env`,
+/// reporting every instruction, type, constant or decoration (as well as any
+/// capability/extension declared by the module's [`spv::Dialect`]) requiring
+/// a capability, extension, or SPIR-V version, not available in `target_env`.
+///
+/// Only the global variables and functions reachable from the module's exports
+/// are checked (i.e. the ones that would be kept when lifting to SPIR-V).
+///
+/// The module is also checked against the device [`Limits`] of `target_env`.
+//
+// FIXME(eddyb) this ignores requirements from extended instruction sets, and
+// from validation rules not encoded in the grammar (other than type widths).
+pub fn check_module(module: &Module, target_env: &TargetEnv) -> Result<(), TargetEnvError> {
+    let wk = &spec::Spec::get().well_known;
+    let cx = module.cx();
+
+    let mut checker = RequirementChecker {
+        cx: &cx,
+        target_env,
+        enabled_capabilities: target_env.enabled_capabilities(),
+        anchor: TargetEnvAnchor::Module,
+        seen_attrs: FxHashSet::default(),
+        seen_types: FxHashSet::default(),
+        seen_consts: FxHashSet::default(),
+        diagnostics: vec![],
+    };
+
+    match &module.dialect {
+        ModuleDialect::Spv(dialect) => {
+            let version = (dialect.version_major, dialect.version_minor);
+            if version > target_env.spv_version {
+                checker.report(format!(
+                    "SPIR-V {}.{} is newer than the supported SPIR-V {}.{}",
+                    version.0, version.1, target_env.spv_version.0, target_env.spv_version.1
+                ));
+            }
+            for &cap in &dialect.capabilities {
+                checker.check_inst(&spv::Inst {
+                    opcode: wk.OpCapability,
+                    imms: [spv::Imm::Short(wk.Capability, cap)].into_iter().collect(),
+                });
+            }
+            for ext in &dialect.extensions {
+                if !target_env.extensions.contains(ext) {
+                    checker.report(format!("extension `{ext}` is not available"));
+                }
+            }
+            checker.check_inst(&spv::Inst {
+                opcode: wk.OpMemoryModel,
+                imms: [
+                    spv::Imm::Short(wk.AddressingModel, dialect.addressing_model),
+                    spv::Imm::Short(wk.MemoryModel, dialect.memory_model),
+                ]
+                .into_iter()
+                .collect(),
+            });
+        }
+    }
+
+    for export_key in module.exports.keys() {
+        if let ExportKey::SpvEntryPoint { imms, .. } = export_key {
+            checker.check_inst(&spv::Inst {
+                opcode: wk.OpEntryPoint,
+                imms: imms.clone(),
+            });
+        }
+    }
+
+    let (global_vars, funcs) = reachable_global_vars_and_funcs(module);
+    for gv in global_vars {
+        checker.anchor = TargetEnvAnchor::GlobalVar(gv);
+        checker.visit_global_var_decl(&module.global_vars[gv]);
+    }
+    for func in funcs {
+        checker.anchor = TargetEnvAnchor::Func(func);
+        checker.visit_func_decl(&module.funcs[func]);
+    }
+
+    check_limits(module, &target_env.limits, &mut checker.diagnostics);
+
+    if checker.diagnostics.is_empty() {
+        Ok(())
+    } else {
+        Err(TargetEnvError {
+            diagnostics: checker.diagnostics,
+        })
+    }
+}
+
+/// Check that `module` stays within `limits`, reporting push constants which
+/// are too large, and entry-points using too many descriptors, `Location`s,
+/// workgroup invocations, or too much shared memory.
+fn check_limits(module: &Module, limits: &Limits, diagnostics: &mut Vec<TargetEnvDiagnostic>) {
+    let wk = &spec::Spec::get().well_known;
+    let cx = &module.cx();
+
+    // FIXME(eddyb) this only matters for `Workgroup` variables, which have no
+    // explicit layout, so their size may differ between implementations.
+    let layout_cx = LayoutCx::new(cx, LayoutRules::Std430);
+    let size_of_global_var = |gv: GlobalVar| {
+        let pointee = module.global_vars[gv].type_of_ptr_to.pointee_type(cx)?;
+        layout_cx.layout_of(pointee).ok()?.size
+    };
+    let has_storage_class = |gv: GlobalVar, storage_classes: &[u32]| {
+        storage_classes
+            .iter()
+            .any(|&sc| module.global_vars[gv].addr_space == AddrSpace::SpvStorageClass(sc))
+    };
+
+    let mut report = |anchor: TargetEnvAnchor, message: String| {
+        diagnostics.push(TargetEnvDiagnostic { anchor, message });
+    };
+
+    let (global_vars, _) = reachable_global_vars_and_funcs(module);
+    for gv in global_vars {
+        if !has_storage_class(gv, &[wk.PushConstant]) {
+            continue;
+        }
+        if let Some(size) = size_of_global_var(gv) {
+            if size > limits.max_push_constants_size {
+                report(
+                    TargetEnvAnchor::GlobalVar(gv),
+                    format!(
+                        "push constants take up {size} bytes, more than the maximum of {}",
+                        limits.max_push_constants_size
+                    ),
+                );
+            }
+        }
+    }
+
+    for (export_key, exportee) in &module.exports {
+        let (imms, interface_global_vars) = match export_key {
+            ExportKey::SpvEntryPoint {
+                imms,
+                interface_global_vars,
+            } => (imms, interface_global_vars),
+            ExportKey::LinkName(_) => continue,
+        };
+        let func = match *exportee {
+            Exportee::Func(func) => func,
+            Exportee::GlobalVar(_) => continue,
+        };
+        let execution_model = match imms.first() {
+            Some(&spv::Imm::Short(_, execution_model)) => execution_model,
+            _ => continue,
+        };
+        let name = spv::extract_literal_string(&imms[1..]).unwrap_or_default();
+        let anchor = TargetEnvAnchor::EntryPoint(func);
+
+        let (used_global_vars, _) =
+            reachable_global_vars_and_funcs_from_exports(module, [(export_key, exportee)]);
+
+        let descriptor_count = used_global_vars
+            .iter()
+            .filter(|&&gv| {
+                has_storage_class(gv, &[wk.UniformConstant, wk.Uniform, wk.StorageBuffer])
+            })
+            .map(|&gv| descriptor_count(cx, module.global_vars[gv].type_of_ptr_to))
+            .fold(0, u32::saturating_add);
+        if descriptor_count > limits.max_per_stage_resources {
+            report(
+                anchor,
+                format!(
+                    "`{name}` uses {descriptor_count} descriptors, more than the maximum of {}",
+                    limits.max_per_stage_resources
+                ),
+            );
+        }
+
+        if execution_model == wk.GLCompute {
+            if let Some(size) = local_size(cx, module.funcs[func].attrs) {
+                let max_size = limits.max_compute_workgroup_size;
+                if size.iter().zip(max_size).any(|(&x, max)| x > max) {
+                    report(
+                        anchor,
+                        format!(
+                            "`{name}` has a workgroup size of {size:?}, \
+                             larger than the maximum of {max_size:?}"
+                        ),
+                    );
+                }
+                let invocations = size.iter().map(|&x| u64::from(x)).product::<u64>();
+                if invocations > limits.max_compute_workgroup_invocations.into() {
+                    report(
+                        anchor,
+                        format!(
+                            "`{name}` has {invocations} invocations per workgroup, \
+                             more than the maximum of {}",
+                            limits.max_compute_workgroup_invocations
+                        ),
+                    );
+                }
+            }
+
+            let shared_memory_size = used_global_vars
+                .iter()
+                .filter(|&&gv| has_storage_class(gv, &[wk.Workgroup]))
+                .filter_map(|&gv| size_of_global_var(gv))
+                .fold(0, u32::saturating_add);
+            if shared_memory_size > limits.max_compute_shared_memory_size {
+                report(
+                    anchor,
+                    format!(
+                        "`{name}` uses {shared_memory_size} bytes of shared memory, \
+                         more than the maximum of {}",
+                        limits.max_compute_shared_memory_size
+                    ),
+                );
+            }
+        }
+
+        for direction in [IoDirection::Input, IoDirection::Output] {
+            let (direction_name, max_locations) = match direction {
+                IoDirection::Input if execution_model == wk.Vertex => {
+                    ("input", limits.max_vertex_input_locations)
+                }
+                IoDirection::Output if execution_model == wk.Fragment => {
+                    ("output", limits.max_fragment_output_locations)
+                }
+                IoDirection::Input => ("input", limits.max_interface_locations),
+                IoDirection::Output => ("output", limits.max_interface_locations),
+            };
+            let arrayed = direction.is_arrayed_for(execution_model);
+
+            let mut total_locations = 0u32;
+            let mut any_out_of_range = false;
+            for &gv in interface_global_vars {
+                let gv_decl = &module.global_vars[gv];
+                if gv_decl.addr_space != AddrSpace::SpvStorageClass(direction.storage_class()) {
+                    continue;
+                }
+                let io_var =
+                    match IoVar::new(cx, gv, gv_decl.attrs, gv_decl.type_of_ptr_to, arrayed) {
+                        Some(io_var) => io_var,
+                        None => continue,
+                    };
+                let count = io_var.location_count.unwrap_or(1);
+                total_locations = total_locations.saturating_add(count);
+                if let Some(location) = io_var.location {
+                    let end = location.saturating_add(count);
+                    if end > max_locations {
+                        any_out_of_range = true;
+                        report(
+                            TargetEnvAnchor::GlobalVar(gv),
+                            format!(
+                                "{direction_name} variable (of `{name}`) uses `Location`s \
+                                 {location}..{end}, beyond the maximum of {max_locations}"
+                            ),
+                        );
+                    }
+                }
+            }
+            // NOTE(eddyb) this is only reported separately when no individual
+            // variable was already reported above, to avoid redundant errors.
+            if total_locations > max_locations && !any_out_of_range {
+                report(
+                    anchor,
+                    format!(
+                        "`{name}` uses {total_locations} {direction_name} `Location`s, \
+                         more than the maximum of {max_locations}"
+                    ),
+                );
+            }
+        }
+    }
+}
+
+/// Get the number of descriptors taken up by a (resource) global variable
+/// with the pointer type `type_of_ptr_to`, i.e. the array length, for arrays.
+//
+// FIXME(eddyb) runtime arrays (i.e. "descriptor indexing") are counted as one
+// descriptor, as their actual length is only known at pipeline creation time.
+fn descriptor_count(cx: &Context, type_of_ptr_to: Type) -> u32 {
+    let ty = match type_of_ptr_to.pointee_type(cx) {
+        Some(ty) => ty,
+        None => return 1,
+    };
+    let ty_def = &cx[ty];
+    match (&ty_def.ctor, &ty_def.ctor_args[..]) {
+        (TypeCtor::Array, &[_, TypeCtorArg::Const(len)]) => match const_splat_value(cx, len) {
+            Some(ScalarValue::Int { bits, .. }) => u32::try_from(bits).unwrap_or(u32::MAX),
+            _ => 1,
+        },
+        _ => 1,
+    }
+}
+
+/// Get the `LocalSize` execution mode (of an entry-point function) from `attrs`.
+fn local_size(cx: &Context, attrs: AttrSet) -> Option<[u32; 3]> {
+    let wk = &spec::Spec::get().well_known;
+
+    cx[attrs].attrs.iter().find_map(|attr| match attr {
+        Attr::SpvAnnotation(spv::Inst { opcode, imms }) if *opcode == wk.OpExecutionMode => {
+            match imms[..] {
+                [
+                    spv::Imm::Short(_, mode),
+                    spv::Imm::Short(_, x),
+                    spv::Imm::Short(_, y),
+                    spv::Imm::Short(_, z),
+                ] if mode == wk.LocalSize => Some([x, y, z]),
+                _ => None,
+            }
+        }
+        _ => None,
+    })
+}
+
+/// [`Visitor`] checking the requirements of every SPIR-V instruction (or its
+/// equivalent in SPIR-T, e.g. [`TypeCtor::Matrix`] for `OpTypeMatrix`).
+struct RequirementChecker<'a> {
+    cx: &'a Context,
+    target_env: &'a TargetEnv,
+
+    /// All the capabilities available in `target_env` (see [`TargetEnv::enabled_capabilities`]).
+    enabled_capabilities: BTreeSet<u32>,
+
+    /// The entity currently being visited, which any diagnostics are about.
+    anchor: TargetEnvAnchor,
+
+    seen_attrs: FxHashSet<AttrSet>,
+    seen_types: FxHashSet<Type>,
+    seen_consts: FxHashSet<Const>,
+
+    diagnostics: Vec<TargetEnvDiagnostic>,
+}
+
+impl RequirementChecker<'_> {
+    fn report(&mut self, message: String) {
+        self.diagnostics.push(TargetEnvDiagnostic {
+            anchor: self.anchor,
+            message,
+        });
+    }
+
+    /// Check the requirements of the opcode of `inst`, and of every enumerand
+    /// (i.e. `BitEnum` bit or `ValueEnum` variant) in its immediate operands.
+    fn check_inst(&mut self, inst: &spv::Inst) {
+        let (opcode_name, opcode_def) = inst.opcode.name_and_def();
+        self.check_reqs(&format!("`{opcode_name}`"), &opcode_def.reqs);
+        self.check_imms(&inst.imms, Some(opcode_name));
+
+        let width_caps = spv::type_width_capabilities(inst);
+        if !width_caps.is_empty() {
+            let width = match inst.imms.first() {
+                Some(&spv::Imm::Short(_, width)) => width,
+                _ => 0,
+            };
+            self.check_any_capability(&format!("{width}-bit `{opcode_name}`"), &width_caps);
+        }
+    }
+
+    /// Check the requirements of every enumerand in `imms` (which are used by
+    /// the instruction named `used_by`, if any, for diagnostics).
+    fn check_imms(&mut self, imms: &[spv::Imm], used_by: Option<&str>) {
+        let wk = &spec::Spec::get().well_known;
+
+        for &imm in imms {
+            let (kind, word) = match imm {
+                spv::Imm::Short(kind, word) => (kind, word),
+                spv::Imm::LongStart(..) | spv::Imm::LongCont(..) => continue,
+            };
+            let (kind_name, kind_def) = kind.name_and_def();
+            let describe = |name: &str| match used_by {
+                Some(used_by) => format!("`{kind_name}.{name}` (used by `{used_by}`)"),
+                None => format!("`{kind_name}.{name}`"),
+            };
+            let enumerants: SmallVec<[_; 2]> = match kind_def {
+                spec::OperandKindDef::BitEnum { bits, .. } => spec::BitIdx::of_all_set_bits(word)
+                    .filter_map(|bit_idx| bits.get_named(bit_idx))
+                    .collect(),
+                spec::OperandKindDef::ValueEnum { variants } => u16::try_from(word)
+                    .ok()
+                    .and_then(|word| variants.get_named(word))
+                    .into_iter()
+                    .collect(),
+                spec::OperandKindDef::Id | spec::OperandKindDef::Literal { .. } => continue,
+            };
+            for (name, enumerant) in enumerants {
+                if kind == wk.Capability {
+                    // NOTE(eddyb) the capabilities listed as requirements of
+                    // `Capability` enumerants are those they implicitly declare,
+                    // so only the declared capability itself needs to be available.
+                    if !self.enabled_capabilities.contains(&word) {
+                        self.report(format!("capability `{name}` is not available"));
+                    }
+                    self.check_version_or_extensions(&describe(name), &enumerant.reqs);
+                } else {
+                    self.check_reqs(&describe(name), &enumerant.reqs);
+                }
+            }
+        }
+    }
+
+    fn check_reqs(&mut self, what: &str, reqs: &spec::Requirements) {
+        self.check_any_capability(what, &reqs.capabilities);
+        self.check_version_or_extensions(what, reqs);
+    }
+
+    fn check_any_capability(&mut self, what: &str, names: &[&str]) {
+        let cap_variants = capability_variants();
+
+        let caps: SmallVec<[u32; 4]> = names
+            .iter()
+            .filter_map(|&name| cap_variants.lookup(name).map(u32::from))
+            .collect();
+        if caps.is_empty()
+            || caps
+                .iter()
+                .any(|cap| self.enabled_capabilities.contains(cap))
+        {
+            return;
+        }
+        let names = names
+            .iter()
+            .map(|name| format!("`{name}`"))
+            .collect::<Vec<_>>()
+            .join(", ");
+        self.report(if caps.len() == 1 {
+            format!("{what} requires the capability {names}, which is not available")
+        } else {
+            format!("{what} requires one of the capabilities {names}, none of which are available")
+        });
+    }
+
+    fn check_version_or_extensions(&mut self, what: &str, reqs: &spec::Requirements) {
+        let target_env = self.target_env;
+
+        let available_in_version = reqs
+            .min_version
+            .is_some_and(|min| min <= target_env.spv_version);
+        let available_in_extension = reqs
+            .extensions
+            .iter()
+            .any(|&ext| target_env.extensions.contains(ext));
+        let unconstrained = reqs.min_version.is_none() && reqs.extensions.is_empty();
+        if available_in_version || available_in_extension || unconstrained {
+            return;
+        }
+
+        let extensions = reqs
+            .extensions
+            .iter()
+            .map(|ext| format!("`{ext}`"))
+            .collect::<Vec<_>>()
+            .join(", ");
+        self.report(match reqs.min_version {
+            Some((major, minor)) if reqs.extensions.is_empty() => {
+                format!("{what} requires SPIR-V {major}.{minor}")
+            }
+            Some((major, minor)) => {
+                format!(
+                    "{what} requires SPIR-V {major}.{minor}, or one of the extensions {extensions}"
+                )
+            }
+            None => format!("{what} requires one of the extensions {extensions}"),
+        });
+    }
+
+    /// Get the SPIR-V instruction declaring a type defined with `ty_ctor`.
+    ///
+    /// Only the opcode and enumerand immediates (i.e. the parts which have any
+    /// requirements) are included, for types which don't use [`TypeCtor::SpvInst`].
+    fn type_ctor_as_spv_inst(ty_ctor: &TypeCtor) -> Option<spv::Inst> {
+        let wk = &spec::Spec::get().well_known;
+
+        Some(match ty_ctor {
+            TypeCtor::SpvInst(inst) => inst.clone(),
+            TypeCtor::Matrix { .. } => wk.OpTypeMatrix.into(),
+            TypeCtor::Array => wk.OpTypeArray.into(),
+            TypeCtor::RuntimeArray => wk.OpTypeRuntimeArray.into(),
+            TypeCtor::Struct { .. } => wk.OpTypeStruct.into(),
+            &TypeCtor::RecursivePtr { storage_class } => spv::Inst {
+                opcode: wk.OpTypePointer,
+                imms: [spv::Imm::Short(wk.StorageClass, storage_class)]
+                    .into_iter()
+                    .collect(),
+            },
+            TypeCtor::Image(image_type) => {
+                spv::Inst {
+                    opcode: wk.OpTypeImage,
+                    imms: [
+                        spv::Imm::Short(wk.Dim, image_type.dim),
+                        spv::Imm::Short(wk.ImageFormat, image_type.format),
+                    ]
+                    .into_iter()
+                    .chain(image_type.access_qualifier.map(|access_qualifier| {
+                        spv::Imm::Short(wk.AccessQualifier, access_qualifier)
+                    }))
+                    .collect(),
+                }
+            }
+            TypeCtor::Sampler => wk.OpTypeSampler.into(),
+            TypeCtor::SampledImage => wk.OpTypeSampledImage.into(),
+
+            TypeCtor::RecursivePtrSelf { .. }
+            | TypeCtor::QPtr
+            | TypeCtor::SpvStringLiteralForExtInst => return None,
+        })
+    }
+
+    /// Check the instructions used in the body of `func_at_region` which aren't
+    /// [`DataInst`](crate::DataInst)s (i.e. `Select`s and `ExitInvocation`s).
+    fn check_control_nodes_in(&mut self, func_at_region: FuncAt<'_, ControlRegion>) {
+        for func_at_control_node in func_at_region.at_children() {
+            match &func_at_control_node.def().kind {
+                ControlNodeKind::Select {
+                    kind: SelectionKind::SpvInst(inst),
+                    ..
+                }
+                | ControlNodeKind::ExitInvocation {
+                    kind: ExitInvocationKind::SpvInst(inst),
+                    ..
+                } => self.check_inst(inst),
+                ControlNodeKind::ExitInvocation {
+                    kind: ExitInvocationKind::EmitMeshTasks,
+                    ..
+                } => {
+                    let wk = &spec::Spec::get().well_known;
+                    self.check_inst(&wk.OpEmitMeshTasksEXT.into());
+                }
+                ControlNodeKind::Block { .. }
+                | ControlNodeKind::Select {
+                    kind: SelectionKind::BoolCond,
+                    ..
+                }
+                | ControlNodeKind::Loop { .. } => {}
+            }
+        }
+    }
+}
+
+impl<'a> Visitor<'a> for RequirementChecker<'a> {
+    fn visit_attr_set_use(&mut self, attrs: AttrSet) {
+        if self.seen_attrs.insert(attrs) {
+            self.visit_attr_set_def(&self.cx[attrs]);
+        }
+    }
+    fn visit_type_use(&mut self, ty: Type) {
+        if self.seen_types.insert(ty) {
+            let ty_def = &self.cx[ty];
+            if let Some(inst) = Self::type_ctor_as_spv_inst(&ty_def.ctor) {
+                self.check_inst(&inst);
+            }
+            self.visit_type_def(ty_def);
+        }
+    }
+    fn visit_const_use(&mut self, ct: Const) {
+        if self.seen_consts.insert(ct) {
+            let ct_def = &self.cx[ct];
+            if let ConstCtor::SpvInst(inst) = &ct_def.ctor {
+                self.check_inst(inst);
+            }
+            self.visit_const_def(ct_def);
+        }
+    }
+
+    // NOTE(eddyb) all the reachable global variables and functions are visited
+    // separately (see `check_module`), to keep track of the current anchor.
+    fn visit_global_var_use(&mut self, _gv: GlobalVar) {}
+    fn visit_func_use(&mut self, _func: Func) {}
+
+    fn visit_attr(&mut self, attr: &'a Attr) {
+        match attr {
+            Attr::SpvAnnotation(inst) => self.check_inst(inst),
+            Attr::SpvBitflagsOperand(imms) => self.check_imms(imms, None),
+            _ => {}
+        }
+        attr.inner_visit_with(self);
+    }
+
+    fn visit_global_var_decl(&mut self, gv_decl: &'a GlobalVarDecl) {
+        let wk = &spec::Spec::get().well_known;
+
+        self.check_inst(&spv::Inst {
+            opcode: wk.OpVariable,
+            imms: [spv::Imm::Short(
+                wk.StorageClass,
+                gv_decl.addr_space.to_spv_storage_class(),
+            )]
+            .into_iter()
+            .collect(),
+        });
+        gv_decl.inner_visit_with(self);
+    }
+
+    fn visit_func_decl(&mut self, func_decl: &'a FuncDecl) {
+        if let DeclDef::Present(func_def_body) = &func_decl.def {
+            if let Some(cfg) = &func_def_body.unstructured_cfg {
+                for region in cfg.rev_post_order(func_def_body) {
+                    match &cfg.control_inst_on_exit_from[region].kind {
+                        ControlInstKind::SelectBranch(SelectionKind::SpvInst(inst))
+                        | ControlInstKind::ExitInvocation(ExitInvocationKind::SpvInst(inst)) => {
+                            self.check_inst(inst);
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+        func_decl.inner_visit_with(self);
+    }
+
+    fn visit_control_region_def(&mut self, func_at_control_region: FuncAt<'a, ControlRegion>) {
+        self.check_control_nodes_in(func_at_control_region);
+        func_at_control_region.inner_visit_with(self);
+    }
+
+    fn visit_data_inst_def(&mut self, data_inst_def: &'a DataInstDef) {
+        let wk = &spec::Spec::get().well_known;
+
+        let scope_and_semantics = |scope, semantics| {
+            [
+                spv::Imm::Short(wk.Scope, scope),
+                spv::Imm::Short(wk.MemorySemantics, semantics),
+            ]
+        };
+        match data_inst_def.kind {
+            DataInstKind::SpvInst(ref inst) => self.check_inst(inst),
+            DataInstKind::Atomic {
+                op,
+                scope,
+                semantics,
+            } => self.check_inst(&spv::Inst {
+                opcode: op.spv_opcode(),
+                imms: scope_and_semantics(scope, semantics).into_iter().collect(),
+            }),
+            DataInstKind::Barrier {
+                execution_scope,
+                memory_scope,
+                semantics,
+            } => self.check_inst(&spv::Inst {
+                opcode: if execution_scope.is_some() {
+                    wk.OpControlBarrier
+                } else {
+                    wk.OpMemoryBarrier
+                },
+                imms: execution_scope
+                    .map(|scope| spv::Imm::Short(wk.Scope, scope))
+                    .into_iter()
+                    .chain(scope_and_semantics(memory_scope, semantics))
+                    .collect(),
+            }),
+            DataInstKind::Group {
+                op,
+                scope,
+                group_operation,
+            } => self.check_inst(&spv::Inst {
+                opcode: op.spv_opcode(),
+                imms: iter::once(spv::Imm::Short(wk.Scope, scope))
+                    .chain(group_operation.map(|x| spv::Imm::Short(wk.GroupOperation, x)))
+                    .collect(),
+            }),
+            DataInstKind::FuncCall(_)
+            | DataInstKind::SpvExtInst { .. }
+            | DataInstKind::SpvGlslStd450(_)
+            | DataInstKind::QPtr(_) => {}
+        }
+        data_inst_def.inner_visit_with(self);
+    }
+}
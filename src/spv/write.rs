@@ -2,6 +2,7 @@
 
 use crate::spv::{self, spec};
 use std::borrow::Cow;
+use std::io::{Seek, Write};
 use std::path::Path;
 use std::{fs, io, iter, slice};
 
@@ -32,6 +33,9 @@ enum OperandEmitError {
 
     /// Unsupported enumerand value.
     UnsupportedEnumerand(spec::OperandKind, u32),
+
+    /// Unsupported opcode for the operation of an `OpSpecConstantOp`.
+    UnsupportedSpecConstantOpOpcode(u32),
 }
 
 impl OperandEmitError {
@@ -60,6 +64,9 @@ impl OperandEmitError {
                     _ => unreachable!(),
                 }
             }
+            Self::UnsupportedSpecConstantOpOpcode(word) => {
+                format!("unsupported opcode {word} for OpSpecConstantOp").into()
+            }
         }
     }
 }
@@ -85,6 +92,8 @@ impl OperandEmitter<'_> {
     fn operand(&mut self, kind: spec::OperandKind) -> Result<(), OperandEmitError> {
         use OperandEmitError as Error;
 
+        let wk = &spec::Spec::get().well_known;
+
         let mut get_enum_word = || match self.imms.next() {
             Some(spv::Imm::Short(found_kind, word)) => {
                 assert!(kind == found_kind);
@@ -125,6 +134,21 @@ impl OperandEmitter<'_> {
                     spv::Imm::Short(found_kind, word) => {
                         assert!(kind == found_kind);
                         self.out.push(word);
+
+                        // NOTE(eddyb) see the comment in `spv::read` about the
+                        // operands of `OpSpecConstantOp`.
+                        if kind == wk.LiteralSpecConstantOpInteger {
+                            let (_, _, def) = u16::try_from(word)
+                                .ok()
+                                .and_then(spec::Opcode::try_from_u16_with_name_and_def)
+                                .ok_or(Error::UnsupportedSpecConstantOpOpcode(word))?;
+                            for (mode, kind) in def.all_operands() {
+                                if mode == spec::OperandMode::Optional && self.is_exhausted() {
+                                    break;
+                                }
+                                self.operand(kind)?;
+                            }
+                        }
                     }
                     spv::Imm::LongStart(found_kind, word) => {
                         assert!(kind == found_kind);
@@ -175,7 +199,8 @@ impl OperandEmitter<'_> {
 
 pub struct ModuleEmitter {
     /// Output SPIR-V words.
-    // FIXME(eddyb) try to write bytes to an `impl io::Write` directly.
+    // NOTE(eddyb) see `StreamingModuleEmitter` for writing to an `impl io::Write`
+    // directly, instead of accumulating all the words in memory first.
     pub words: Vec<u32>,
 }
 
@@ -187,6 +212,56 @@ fn invalid(reason: &str) -> io::Error {
     )
 }
 
+/// Append the encoding (as SPIR-V words) of `inst` to `out`.
+//
+// FIXME(eddyb) sanity-check the operands against the definition of `inst.opcode`.
+fn encode_inst(inst: &spv::InstWithIds, out: &mut Vec<u32>) -> io::Result<()> {
+    let (inst_name, def) = inst.opcode.name_and_def();
+    let invalid = |msg: &str| invalid(&format!("in {inst_name}: {msg}"));
+
+    // FIXME(eddyb) make these errors clearer (or turn them into asserts?).
+    if inst.result_type_id.is_some() != def.has_result_type_id {
+        return Err(invalid("result type ID (`IdResultType`) mismatch"));
+    }
+    if inst.result_id.is_some() != def.has_result_id {
+        return Err(invalid("result ID (`IdResult`) mismatch"));
+    }
+
+    let total_word_count = 1
+        + (inst.result_type_id.is_some() as usize)
+        + (inst.result_id.is_some() as usize)
+        + inst.imms.len()
+        + inst.ids.len();
+
+    out.reserve(total_word_count);
+    let expected_final_pos = out.len() + total_word_count;
+
+    let opcode =
+        u32::from(inst.opcode.as_u16())
+            | u32::from(u16::try_from(total_word_count).ok().ok_or_else(|| {
+                invalid("word count of SPIR-V instruction doesn't fit in 16 bits")
+            })?) << 16;
+    out.extend(
+        iter::once(opcode)
+            .chain(inst.result_type_id.map(|id| id.get()))
+            .chain(inst.result_id.map(|id| id.get())),
+    );
+
+    OperandEmitter {
+        imms: inst.imms.iter().copied(),
+        ids: inst.ids.iter().copied(),
+        out,
+    }
+    .inst_operands(def)
+    .map_err(|e| invalid(&e.message()))?;
+
+    // If no error was produced so far, `OperandEmitter` should've pushed
+    // the exact number of words.
+    assert_eq!(out.len(), expected_final_pos);
+
+    Ok(())
+}
+
 impl ModuleEmitter {
     pub fn with_header(header: [u32; spec::HEADER_LEN]) -> Self {
         // FIXME(eddyb) sanity-check the provided header words.
@@ -195,54 +270,72 @@ impl ModuleEmitter {
         }
     }
 
-    // FIXME(eddyb) sanity-check the operands against the definition of `inst.opcode`.
     pub fn push_inst(&mut self, inst: &spv::InstWithIds) -> io::Result<()> {
-        let (inst_name, def) = inst.opcode.name_and_def();
-        let invalid = |msg: &str| invalid(&format!("in {inst_name}: {msg}"));
+        encode_inst(inst, &mut self.words)
+    }
 
-        // FIXME(eddyb) make these errors clearer (or turn them into asserts?).
-        if inst.result_type_id.is_some() != def.has_result_type_id {
-            return Err(invalid("result type ID (`IdResultType`) mismatch"));
-        }
-        if inst.result_id.is_some() != def.has_result_id {
-            return Err(invalid("result ID (`IdResult`) mismatch"));
-        }
+    pub fn write_to_spv_file(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        fs::write(path, bytemuck::cast_slice::<u32, u8>(&self.words))
+    }
+}
 
-        let total_word_count = 1
-            + (inst.result_type_id.is_some() as usize)
-            + (inst.result_id.is_some() as usize)
-            + inst.imms.len()
-            + inst.ids.len();
+/// Like [`ModuleEmitter`], but writing each instruction to an [`io::Write`] as
+/// soon as it's emitted (instead of accumulating the whole module in memory),
+/// with the header only finalized by [`StreamingModuleEmitter::finish`], which
+/// seeks back to the start of the module to overwrite it (as e.g. the ID bound
+/// can still change after instructions using those IDs were already written).
+pub struct StreamingModuleEmitter<W: io::Write + io::Seek> {
+    writer: io::BufWriter<W>,
 
-        self.words.reserve(total_word_count);
-        let expected_final_pos = self.words.len() + total_word_count;
+    /// Header words (only written out by [`StreamingModuleEmitter::finish`]).
+    pub header: [u32; spec::HEADER_LEN],
 
-        let opcode = u32::from(inst.opcode.as_u16())
-            | u32::from(u16::try_from(total_word_count).ok().ok_or_else(|| {
-                invalid("word count of SPIR-V instruction doesn't fit in 16 bits")
-            })?) << 16;
-        self.words.extend(
-            iter::once(opcode)
-                .chain(inst.result_type_id.map(|id| id.get()))
-                .chain(inst.result_id.map(|id| id.get())),
-        );
-
-        OperandEmitter {
-            imms: inst.imms.iter().copied(),
-            ids: inst.ids.iter().copied(),
-            out: &mut self.words,
-        }
-        .inst_operands(def)
-        .map_err(|e| invalid(&e.message()))?;
+    /// Position in `writer` at which the module (i.e. its header) starts.
+    start_pos: u64,
 
-        // If no error was produced so far, `OperandEmitter` should've pushed
-        // the exact number of words.
-        assert_eq!(self.words.len(), expected_final_pos);
+    /// Total size (in words) of the module written so far, including the header.
+    word_count: usize,
 
+    /// Buffer reused for encoding each instruction.
+    inst_words: Vec<u32>,
+}
+
+impl<W: io::Write + io::Seek> StreamingModuleEmitter<W> {
+    pub fn with_header(writer: W, header: [u32; spec::HEADER_LEN]) -> io::Result<Self> {
+        let mut writer = io::BufWriter::new(writer);
+        let start_pos = writer.stream_position()?;
+        writer.write_all(bytemuck::cast_slice::<u32, u8>(&header))?;
+        Ok(Self {
+            writer,
+            header,
+            start_pos,
+            word_count: spec::HEADER_LEN,
+            inst_words: vec![],
+        })
+    }
+
+    /// Total size (in words) of the module written so far, including the header.
+    pub fn word_count(&self) -> usize {
+        self.word_count
+    }
+
+    pub fn push_inst(&mut self, inst: &spv::InstWithIds) -> io::Result<()> {
+        self.inst_words.clear();
+        encode_inst(inst, &mut self.inst_words)?;
+        self.writer
+            .write_all(bytemuck::cast_slice::<u32, u8>(&self.inst_words))?;
+        self.word_count += self.inst_words.len();
         Ok(())
     }
 
-    pub fn write_to_spv_file(&self, path: impl AsRef<Path>) -> io::Result<()> {
-        fs::write(path, bytemuck::cast_slice::<u32, u8>(&self.words))
+    /// Overwrite the header (with the final value of `self.header`), and return
+    /// the original `writer`, positioned after the end of the module.
+    pub fn finish(mut self) -> io::Result<W> {
+        let end_pos = self.start_pos + u64::try_from(self.word_count * 4).unwrap();
+        self.writer.seek(io::SeekFrom::Start(self.start_pos))?;
+        self.writer
+            .write_all(bytemuck::cast_slice::<u32, u8>(&self.header))?;
+        self.writer.seek(io::SeekFrom::Start(end_pos))?;
+        self.writer.into_inner().map_err(|e| e.into_error())
     }
 }
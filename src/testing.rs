@@ -0,0 +1,344 @@
+//! Helpers for testing (e.g. passes), by matching pretty-printed SPIR-T against
+//! FileCheck-style patterns.
+//!
+//! Patterns are given as "directives" (on separate lines, which can only have
+//! whitespace and comment syntax, e.g. `//` or `#`, before the directive):
+//! * `CHECK: pattern` - `pattern` must appear on some line after the previous match
+//! * `CHECK-NEXT: pattern` - `pattern` must appear on the line right after the previous match
+//! * `CHECK-NOT: pattern` - `pattern` must not appear between the previous and next matches
+//!   (or until the end, if there are no more matches after it)
+//!
+//! Patterns are plain text (no regexes), matched as substrings of a single line,
+//! with all whitespace (in both the pattern and the line) collapsed to single spaces.
+//!
+//! When testing multiple versions of a module (see [`run_passes_and_check`]),
+//! directives can be limited to specific versions, by naming the version in
+//! parentheses (e.g. `CHECK(structurize): ...`), with unnamed directives only
+//! applying to the last version.
+//!
+//! Separately, [`check_spv_roundtrip`] can be used to check that SPIR-V modules
+//! are losslessly lowered to (and lifted from) SPIR-T.
+
+use crate::print::{NodeOrder, Plan, PrintOptions, Versions};
+use crate::{Context, Module};
+use std::fmt::Write;
+use std::rc::Rc;
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum DirectiveKind {
+    Check,
+    CheckNext,
+    CheckNot,
+}
+
+struct Directive<'a> {
+    /// Line number (1-based) of the directive in the original checks text.
+    line: usize,
+
+    kind: DirectiveKind,
+
+    /// Name of the version this directive applies to (if limited to one).
+    version: Option<&'a str>,
+
+    pattern: String,
+}
+
+impl Directive<'_> {
+    fn describe(&self) -> String {
+        let kind = match self.kind {
+            DirectiveKind::Check => "CHECK",
+            DirectiveKind::CheckNext => "CHECK-NEXT",
+            DirectiveKind::CheckNot => "CHECK-NOT",
+        };
+        let version = self
+            .version
+            .map(|version| format!("({version})"))
+            .unwrap_or_default();
+        format!(
+            "`{kind}{version}: {}` (line {} of checks)",
+            self.pattern, self.line
+        )
+    }
+}
+
+fn normalize_whitespace(s: &str) -> String {
+    s.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Strip any leading whitespace and comment syntax (e.g. `//` or `#`) from `line`.
+fn strip_comment_prefix(line: &str) -> &str {
+    line.trim_start()
+        .trim_start_matches(['/', '!', '#', ';', '-', '*'])
+        .trim_start()
+}
+
+fn parse_directives(checks: &str) -> Result<Vec<Directive<'_>>, String> {
+    let mut directives = vec![];
+    for (i, line) in checks.lines().enumerate() {
+        let rest = match strip_comment_prefix(line).strip_prefix("CHECK") {
+            Some(rest) => rest,
+            None => continue,
+        };
+        let malformed = || format!("malformed directive on line {} of checks", i + 1);
+
+        let (kind, rest) = if let Some(rest) = rest.strip_prefix("-NEXT") {
+            (DirectiveKind::CheckNext, rest)
+        } else if let Some(rest) = rest.strip_prefix("-NOT") {
+            (DirectiveKind::CheckNot, rest)
+        } else {
+            (DirectiveKind::Check, rest)
+        };
+
+        // NOTE(eddyb) lines which merely start with e.g. `CHECK` as a word
+        // (i.e. not followed by `:`, or a version in parentheses) are ignored.
+        if !rest.starts_with([':', '(']) {
+            continue;
+        }
+
+        let (version, rest) = match rest.strip_prefix('(') {
+            Some(rest) => {
+                let (version, rest) = rest.split_once(')').ok_or_else(malformed)?;
+                (Some(version), rest)
+            }
+            None => (None, rest),
+        };
+        let pattern = normalize_whitespace(rest.strip_prefix(':').ok_or_else(malformed)?);
+        if pattern.is_empty() {
+            return Err(malformed());
+        }
+
+        directives.push(Directive {
+            line: i + 1,
+            kind,
+            version,
+            pattern,
+        });
+    }
+    Ok(directives)
+}
+
+/// Match `output` against `directives` (which should all apply to `output`).
+fn match_directives<'a>(
+    output: &str,
+    directives: impl IntoIterator<Item = &'a Directive<'a>>,
+) -> Result<(), String> {
+    let lines: Vec<_> = output.lines().map(normalize_whitespace).collect();
+
+    let check_nots = |nots: &mut Vec<&Directive<'_>>, search_lines: std::ops::Range<usize>| {
+        for not in nots.drain(..) {
+            if let Some(i) = search_lines
+                .clone()
+                .find(|&i| lines[i].contains(&not.pattern))
+            {
+                return Err(format!(
+                    "{} matched (on line {} of output)",
+                    not.describe(),
+                    i + 1
+                ));
+            }
+        }
+        Ok(())
+    };
+
+    // Index of the line right after the previous match (if any).
+    let mut after_last_match = 0;
+    let mut any_matches = false;
+    let mut pending_nots = vec![];
+    for directive in directives {
+        let found = match directive.kind {
+            DirectiveKind::CheckNot => {
+                pending_nots.push(directive);
+                continue;
+            }
+            DirectiveKind::Check => {
+                (after_last_match..lines.len()).find(|&i| lines[i].contains(&directive.pattern))
+            }
+            DirectiveKind::CheckNext => {
+                if !any_matches {
+                    return Err(format!(
+                        "{} has no previous match to follow",
+                        directive.describe()
+                    ));
+                }
+                Some(after_last_match)
+                    .filter(|&i| i < lines.len() && lines[i].contains(&directive.pattern))
+            }
+        };
+        let i = found.ok_or_else(|| {
+            format!(
+                "{} not found (searching from line {} of output)",
+                directive.describe(),
+                after_last_match + 1
+            )
+        })?;
+
+        check_nots(&mut pending_nots, after_last_match..i)?;
+        after_last_match = i + 1;
+        any_matches = true;
+    }
+    check_nots(&mut pending_nots, after_last_match..lines.len())
+}
+
+/// Match `output` (e.g. pretty-printed SPIR-T) against all the directives in
+/// `checks` that aren't limited to a specific version, returning a description
+/// of the first failure (if any).
+pub fn check_output(output: &str, checks: &str) -> Result<(), String> {
+    let directives = parse_directives(checks)?;
+    if let Some(directive) = directives.iter().find(|d| d.version.is_some()) {
+        return Err(format!(
+            "{} is limited to a version, but there's only one",
+            directive.describe()
+        ));
+    }
+    match_directives(output, &directives)
+}
+
+/// Run `passes` (in order) on `module`, and match each version of the module
+/// (i.e. `"input"`, and the output of every pass, named after the pass) against
+/// the directives in `checks`, panicking on the first failure.
+///
+/// All the versions are pretty-printed together (see [`Plan::for_versions`]),
+/// so that the names of e.g. values are consistent across versions (only
+/// the definitions which differ between versions are printed separately).
+///
+/// Returns `module` after all the `passes` have been applied to it.
+pub fn run_passes_and_check<'a>(
+    mut module: Module,
+    passes: impl IntoIterator<Item = (&'a str, fn(&mut Module))>,
+    checks: &str,
+) -> Module {
+    let mut versions = vec![("input", module.clone())];
+    for (pass_name, pass) in passes {
+        pass(&mut module);
+        versions.push((pass_name, module.clone()));
+    }
+
+    let cx = module.cx();
+    let plan = Plan::for_versions(&cx, versions.iter().map(|(name, module)| (*name, module)));
+
+    // Reconstruct the output of each version from the combined printing.
+    let mut per_version_output = vec![String::new(); versions.len()];
+    match plan.pretty_print() {
+        Versions::Single(fragment) => per_version_output[0] = fragment.to_string(),
+        Versions::Multiple {
+            version_names: _,
+            per_node_versions_with_repeat_count,
+        } => {
+            for versions_with_repeat_count in per_node_versions_with_repeat_count {
+                let mut next_version_idx = 0;
+                for (fragment, repeat_count) in versions_with_repeat_count {
+                    let fragment = fragment.to_string();
+                    let version_idxs = next_version_idx..next_version_idx + repeat_count;
+                    next_version_idx = version_idxs.end;
+
+                    if fragment.trim().is_empty() {
+                        continue;
+                    }
+                    for output in &mut per_version_output[version_idxs] {
+                        if !output.is_empty() {
+                            output.push_str("\n\n");
+                        }
+                        output.push_str(&fragment);
+                    }
+                }
+            }
+        }
+    }
+
+    let directives = parse_directives(checks).unwrap_or_else(|e| panic!("{e}"));
+    if let Some(directive) = directives.iter().find(|d| {
+        matches!(d.version, Some(version) if !versions.iter().any(|&(name, _)| name == version))
+    }) {
+        panic!("{} refers to an unknown version", directive.describe());
+    }
+
+    let last_version_idx = versions.len() - 1;
+    for (version_idx, ((version_name, _), output)) in
+        versions.iter().zip(&per_version_output).enumerate()
+    {
+        let result = match_directives(
+            output,
+            directives.iter().filter(|d| match d.version {
+                Some(version) => version == *version_name,
+                None => version_idx == last_version_idx,
+            }),
+        );
+        if let Err(e) = result {
+            let mut message = format!("check failed for version `{version_name}`: {e}\n");
+            writeln!(message, "--- output for version `{version_name}` ---").unwrap();
+            message += output;
+            panic!("{message}");
+        }
+    }
+
+    module
+}
+
+/// Check that lowering the SPIR-V module `spv_bytes` to SPIR-T, lifting it back
+/// to SPIR-V, and lowering the result again, produces a SPIR-T module that is
+/// structurally identical to the one originally lowered, returning a description
+/// of the first difference (or of any lowering/lifting error) otherwise.
+///
+/// The two SPIR-T modules are compared through their pretty-printed forms,
+/// which ignore SPIR-V IDs (see [`PrintOptions::show_spv_original_ids`]), and
+/// only depend on the relative order of definitions (not their identities),
+/// with nothing elided (see [`PrintOptions::max_const_aggregate_elements`]).
+///
+/// This is intended for asserting (e.g. in CI) that a specific corpus of SPIR-V
+/// modules round-trips through SPIR-T without any loss of information.
+pub fn check_spv_roundtrip(spv_bytes: &[u8]) -> Result<(), String> {
+    // NOTE(eddyb) both modules share one `Context`, so that any differences in
+    // interned types/constants are guaranteed to come from the modules themselves.
+    let cx = Rc::new(Context::new());
+
+    let original = Module::lower_from_spv_bytes(cx.clone(), spv_bytes)
+        .map_err(|e| format!("failed to lower original SPIR-V: {e}"))?;
+    let lifted = original
+        .lift_to_spv_module_emitter()
+        .map_err(|e| format!("failed to lift to SPIR-V: {e}"))?;
+    let roundtripped = Module::lower_from_spv_words(cx, lifted.words)
+        .map_err(|e| format!("failed to lower lifted SPIR-V: {e}"))?;
+
+    // NOTE(eddyb) all the options are listed (instead of starting from the
+    // defaults), so that any new ones (e.g. eliding more details) have to be
+    // considered here, as any detail not printed can't be compared.
+    let print_options = PrintOptions {
+        show_spv_original_ids: false,
+        max_const_aggregate_elements: None,
+        node_order: NodeOrder::DefsBeforeUses,
+        spv_source_map: None,
+    };
+    let original = Plan::for_module(&original)
+        .pretty_print_with_options(&print_options)
+        .to_string();
+    let roundtripped = Plan::for_module(&roundtripped)
+        .pretty_print_with_options(&print_options)
+        .to_string();
+    if original.lines().eq(roundtripped.lines()) {
+        return Ok(());
+    }
+
+    // Find the first line that differs (which may be missing on one side).
+    let (original_lines, roundtripped_lines) = (
+        original.lines().chain(std::iter::repeat("<end of output>")),
+        roundtripped
+            .lines()
+            .chain(std::iter::repeat("<end of output>")),
+    );
+    let (i, (expected, found)) = original_lines
+        .zip(roundtripped_lines)
+        .enumerate()
+        .find(|(_, (expected, found))| expected != found)
+        .unwrap();
+    let mut message = format!(
+        "round-trip through SPIR-V changed the module (on line {}):\n",
+        i + 1
+    );
+    writeln!(message, "  expected: {}", expected.trim()).unwrap();
+    writeln!(message, "     found: {}", found.trim()).unwrap();
+    writeln!(message, "--- original module ---").unwrap();
+    message += &original;
+    writeln!(message, "\n--- round-tripped module ---").unwrap();
+    message += &roundtripped;
+    Err(message)
+}
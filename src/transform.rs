@@ -1,14 +1,16 @@
 //! Mutable IR traversal.
 
 use crate::func_at::FuncAtMut;
+use crate::qptr::{QPtrAttr, QPtrOp};
 use crate::{
-    cfg, spv, AddrSpace, Attr, AttrSet, AttrSetDef, Const, ConstCtor, ConstDef, ControlNode,
-    ControlNodeDef, ControlNodeKind, ControlNodeOutputDecl, ControlRegion, ControlRegionDef,
-    ControlRegionInputDecl, DataInstDef, DataInstKind, DeclDef, EntityListIter, ExportKey,
-    Exportee, Func, FuncDecl, FuncDefBody, FuncParam, GlobalVar, GlobalVarDecl, GlobalVarDefBody,
-    Import, Module, ModuleDebugInfo, ModuleDialect, SelectionKind, Type, TypeCtor, TypeCtorArg,
-    TypeDef, Value,
+    cfg, spv, AddrSpace, Attr, AttrSet, AttrSetDef, Const, ConstCtor, ConstDef, Context,
+    ControlNode, ControlNodeDef, ControlNodeKind, ControlNodeOutputDecl, ControlRegion,
+    ControlRegionDef, ControlRegionInputDecl, DataInstDef, DataInstKind, DeclDef, EntityListIter,
+    ExportKey, Exportee, Func, FuncDecl, FuncDefBody, FuncParam, GlobalVar, GlobalVarDecl,
+    GlobalVarDefBody, Import, Module, ModuleDebugInfo, ModuleDialect, OrdAssertEq, SelectionKind,
+    StructMember, Type, TypeCtor, TypeCtorArg, TypeDef, Value,
 };
+use rustc_hash::FxHashMap;
 use std::cmp::Ordering;
 use std::slice;
 
@@ -152,11 +154,6 @@ pub trait Transformer: Sized {
         Transformed::Unchanged
     }
 
-    // Leaves (noop default behavior).
-    fn transform_attr(&mut self, _attr: &Attr) -> Transformed<Attr> {
-        Transformed::Unchanged
-    }
-
     // Leaves transformed in-place (noop default behavior).
     fn in_place_transform_spv_dialect(&mut self, _dialect: &mut spv::Dialect) {}
     fn in_place_transform_spv_module_debug_info(&mut self, _debug_info: &mut spv::ModuleDebugInfo) {
@@ -166,6 +163,9 @@ pub trait Transformer: Sized {
     fn transform_attr_set_def(&mut self, attrs_def: &AttrSetDef) -> Transformed<AttrSetDef> {
         attrs_def.inner_transform_with(self)
     }
+    fn transform_attr(&mut self, attr: &Attr) -> Transformed<Attr> {
+        attr.inner_transform_with(self)
+    }
     fn transform_type_def(&mut self, ty_def: &TypeDef) -> Transformed<TypeDef> {
         ty_def.inner_transform_with(self)
     }
@@ -328,6 +328,80 @@ impl InnerTransform for AttrSetDef {
     }
 }
 
+impl InnerTransform for Attr {
+    fn inner_transform_with(&self, transformer: &mut impl Transformer) -> Transformed<Self> {
+        match self {
+            Attr::SpvAnnotation(_)
+            | Attr::SpvDebugLine { .. }
+            | Attr::SpvBitflagsOperand(_)
+            | Attr::SpvOriginalId(_)
+            | Attr::SpvUnsupported(_)
+            | Attr::Diagnostic { .. } => Transformed::Unchanged,
+
+            Attr::SpvExecutionModeId { inst, consts } => transform!({
+                consts -> Transformed::map_iter(
+                    consts.iter(),
+                    |&OrdAssertEq(ct)| transformer.transform_const_use(ct).map(OrdAssertEq),
+                ).map(|new_iter| new_iter.collect()),
+            } => Attr::SpvExecutionModeId {
+                inst: inst.clone(),
+                consts,
+            }),
+
+            &Attr::SpvShaderDebugScope {
+                scope: OrdAssertEq(scope),
+                inlined_at,
+            } => transform!({
+                scope -> transformer.transform_const_use(scope),
+                inlined_at -> match inlined_at {
+                    Some(OrdAssertEq(ct)) => transformer
+                        .transform_const_use(ct)
+                        .map(|ct| Some(OrdAssertEq(ct))),
+                    None => Transformed::Unchanged,
+                },
+            } => Attr::SpvShaderDebugScope {
+                scope: OrdAssertEq(scope),
+                inlined_at,
+            }),
+
+            &Attr::SpvShaderDebugLine {
+                source: OrdAssertEq(source),
+                line_start,
+                line_end,
+                col_start,
+                col_end,
+            } => transform!({
+                source -> transformer.transform_const_use(source),
+            } => Attr::SpvShaderDebugLine {
+                source: OrdAssertEq(source),
+                line_start,
+                line_end,
+                col_start,
+                col_end,
+            }),
+
+            &Attr::QPtr(QPtrAttr::FromSpvPtr {
+                storage_class,
+                pointee: OrdAssertEq(pointee),
+            }) => transform!({
+                pointee -> transformer.transform_type_use(pointee),
+            } => Attr::QPtr(QPtrAttr::FromSpvPtr {
+                storage_class,
+                pointee: OrdAssertEq(pointee),
+            })),
+            &Attr::QPtr(QPtrAttr::ToSpvPtrInput {
+                input_idx,
+                pointee: OrdAssertEq(pointee),
+            }) => transform!({
+                pointee -> transformer.transform_type_use(pointee),
+            } => Attr::QPtr(QPtrAttr::ToSpvPtrInput {
+                input_idx,
+                pointee: OrdAssertEq(pointee),
+            })),
+        }
+    }
+}
+
 impl InnerTransform for TypeDef {
     fn inner_transform_with(&self, transformer: &mut impl Transformer) -> Transformed<Self> {
         let Self {
@@ -340,7 +414,23 @@ impl InnerTransform for TypeDef {
             attrs -> transformer.transform_attr_set_use(*attrs),
             ctor -> match ctor {
                 TypeCtor::SpvInst(_)
-                | TypeCtor::SpvStringLiteralForExtInst => Transformed::Unchanged,
+                | TypeCtor::SpvStringLiteralForExtInst
+                | TypeCtor::Matrix { .. }
+                | TypeCtor::Array
+                | TypeCtor::RuntimeArray
+                | TypeCtor::RecursivePtr { .. }
+                | TypeCtor::RecursivePtrSelf { .. }
+                | TypeCtor::QPtr
+                | TypeCtor::Image(_)
+                | TypeCtor::Sampler
+                | TypeCtor::SampledImage => Transformed::Unchanged,
+
+                TypeCtor::Struct { members } => Transformed::map_iter(
+                    members.iter(),
+                    |&StructMember { attrs, offset }| transform!({
+                        attrs -> transformer.transform_attr_set_use(attrs),
+                    } => StructMember { attrs, offset }),
+                ).map(|new_iter| TypeCtor::Struct { members: new_iter.collect() }),
             },
             ctor_args -> Transformed::map_iter(ctor_args.iter(), |arg| match *arg {
                 TypeCtorArg::Type(ty) => transform!({
@@ -376,8 +466,13 @@ impl InnerTransform for ConstDef {
                     gv -> transformer.transform_global_var_use(*gv),
                 } => ConstCtor::PtrToGlobalVar(gv)),
 
-                ConstCtor::SpvInst(_)
-                | ConstCtor::SpvStringLiteralForExtInst(_) => Transformed::Unchanged
+                ConstCtor::Undef
+                | ConstCtor::SpvInst(_)
+                | ConstCtor::SpecConst { .. }
+                | ConstCtor::SpecConstOp(_)
+                | ConstCtor::SpvExtInst { .. }
+                | ConstCtor::SpvStringLiteralForExtInst(_)
+                | ConstCtor::SpvTypeOperand => Transformed::Unchanged
             },
             ctor_args -> Transformed::map_iter(
                 ctor_args.iter(),
@@ -417,7 +512,7 @@ impl InnerInPlaceTransform for GlobalVarDecl {
             .transform_type_use(*type_of_ptr_to)
             .apply_to(type_of_ptr_to);
         match addr_space {
-            AddrSpace::SpvStorageClass(_) => {}
+            AddrSpace::SpvStorageClass(_) | AddrSpace::PhysicalStorageBuffer => {}
         }
         def.inner_in_place_transform_with(transformer);
     }
@@ -546,7 +641,7 @@ impl InnerInPlaceTransform for FuncAtMut<'_, EntityListIter<ControlNode>> {
 impl FuncAtMut<'_, ControlNode> {
     fn child_regions(&mut self) -> &mut [ControlRegion] {
         match &mut self.reborrow().def().kind {
-            ControlNodeKind::Block { .. } => &mut [][..],
+            ControlNodeKind::Block { .. } | ControlNodeKind::ExitInvocation { .. } => &mut [][..],
 
             ControlNodeKind::Select { cases, .. } => cases,
             ControlNodeKind::Loop { body, .. } => slice::from_mut(body),
@@ -556,6 +651,9 @@ impl FuncAtMut<'_, ControlNode> {
 
 impl InnerInPlaceTransform for FuncAtMut<'_, ControlNode> {
     fn inner_in_place_transform_with(&mut self, transformer: &mut impl Transformer) {
+        let attrs = &mut self.reborrow().def().attrs;
+        transformer.transform_attr_set_use(*attrs).apply_to(attrs);
+
         // HACK(eddyb) handle pre-child-regions parts of `kind` separately to
         // allow reborrowing `FuncAtMut` (for the child region recursion).
         match &mut self.reborrow().def().kind {
@@ -583,6 +681,14 @@ impl InnerInPlaceTransform for FuncAtMut<'_, ControlNode> {
                     transformer.transform_value_use(v).apply_to(v);
                 }
             }
+            ControlNodeKind::ExitInvocation {
+                kind: cfg::ExitInvocationKind::SpvInst(_) | cfg::ExitInvocationKind::EmitMeshTasks,
+                inputs,
+            } => {
+                for v in inputs {
+                    transformer.transform_value_use(v).apply_to(v);
+                }
+            }
         }
 
         // FIXME(eddyb) represent the list of child regions without having them
@@ -594,7 +700,11 @@ impl InnerInPlaceTransform for FuncAtMut<'_, ControlNode> {
                 .inner_in_place_transform_with(transformer);
         }
 
-        let ControlNodeDef { kind, outputs } = self.reborrow().def();
+        let ControlNodeDef {
+            attrs: _,
+            kind,
+            outputs,
+        } = self.reborrow().def();
 
         match kind {
             // Fully handled above, before recursing into any child regions.
@@ -603,7 +713,8 @@ impl InnerInPlaceTransform for FuncAtMut<'_, ControlNode> {
                 kind: _,
                 scrutinee: _,
                 cases: _,
-            } => {}
+            }
+            | ControlNodeKind::ExitInvocation { kind: _, inputs: _ } => {}
 
             ControlNodeKind::Loop {
                 initial_inputs: _,
@@ -648,7 +759,16 @@ impl InnerInPlaceTransform for DataInstDef {
         transformer.transform_attr_set_use(*attrs).apply_to(attrs);
         match kind {
             DataInstKind::FuncCall(func) => transformer.transform_func_use(*func).apply_to(func),
-            DataInstKind::SpvInst(_) | DataInstKind::SpvExtInst { .. } => {}
+            DataInstKind::SpvInst(_)
+            | DataInstKind::SpvExtInst { .. }
+            | DataInstKind::SpvGlslStd450(_)
+            | DataInstKind::Atomic { .. }
+            | DataInstKind::Barrier { .. }
+            | DataInstKind::Group { .. }
+            | DataInstKind::QPtr(QPtrOp::Load | QPtrOp::Store) => {}
+            DataInstKind::QPtr(QPtrOp::Offset { pointee }) => {
+                transformer.transform_type_use(*pointee).apply_to(pointee);
+            }
         }
         if let Some(ty) = output_type {
             transformer.transform_type_use(*ty).apply_to(ty);
@@ -673,7 +793,9 @@ impl InnerInPlaceTransform for cfg::ControlInst {
         match kind {
             cfg::ControlInstKind::Unreachable
             | cfg::ControlInstKind::Return
-            | cfg::ControlInstKind::ExitInvocation(cfg::ExitInvocationKind::SpvInst(_))
+            | cfg::ControlInstKind::ExitInvocation(
+                cfg::ExitInvocationKind::SpvInst(_) | cfg::ExitInvocationKind::EmitMeshTasks,
+            )
             | cfg::ControlInstKind::Branch
             | cfg::ControlInstKind::SelectBranch(
                 SelectionKind::BoolCond | SelectionKind::SpvInst(_),
@@ -709,3 +831,81 @@ impl InnerTransform for Value {
         }
     }
 }
+
+/// [`Transformer`] replacing [`Value`] uses, based on the wrapped function,
+/// which is called with every [`Value`] used, and returns its replacement
+/// (or `None`, to leave that use unchanged).
+pub struct ReplaceValueWith<F: FnMut(Value) -> Option<Value>>(pub F);
+
+impl<F: FnMut(Value) -> Option<Value>> Transformer for ReplaceValueWith<F> {
+    fn transform_value_use(&mut self, v: &Value) -> Transformed<Value> {
+        (self.0)(*v).map_or(Transformed::Unchanged, Transformed::Changed)
+    }
+}
+
+/// [`Transformer`] replacing [`Type`]s anywhere within other types/constants,
+/// based on `subst`, which is called with every [`Type`] encountered, and the
+/// number of [`TypeCtor::RecursivePtr`]s it's nested in (relative to where the
+/// transformation started), allowing the correct handling of de Bruijn indices
+/// in [`TypeCtor::RecursivePtrSelf`] (i.e. the `depth` field).
+pub(crate) struct SubstTypes<'a, F: FnMut(Type, u32) -> Option<Type>> {
+    cx: &'a Context,
+    subst: F,
+
+    recursive_ptr_depth: u32,
+
+    // FIXME(eddyb) build some automation to avoid ever repeating these.
+    transformed_types: FxHashMap<(Type, u32), Transformed<Type>>,
+    transformed_consts: FxHashMap<(Const, u32), Transformed<Const>>,
+}
+
+impl<'a, F: FnMut(Type, u32) -> Option<Type>> SubstTypes<'a, F> {
+    pub(crate) fn new(cx: &'a Context, subst: F) -> Self {
+        Self {
+            cx,
+            subst,
+            recursive_ptr_depth: 0,
+            transformed_types: FxHashMap::default(),
+            transformed_consts: FxHashMap::default(),
+        }
+    }
+}
+
+impl<F: FnMut(Type, u32) -> Option<Type>> Transformer for SubstTypes<'_, F> {
+    fn transform_type_use(&mut self, ty: Type) -> Transformed<Type> {
+        let key = (ty, self.recursive_ptr_depth);
+        if let Some(&cached) = self.transformed_types.get(&key) {
+            return cached;
+        }
+        let transformed = match (self.subst)(ty, self.recursive_ptr_depth) {
+            Some(new_ty) => Transformed::Changed(new_ty),
+            None => {
+                let ty_def = &self.cx[ty];
+                let is_recursive_ptr = matches!(ty_def.ctor, TypeCtor::RecursivePtr { .. });
+                if is_recursive_ptr {
+                    self.recursive_ptr_depth += 1;
+                }
+                let transformed = self
+                    .transform_type_def(ty_def)
+                    .map(|ty_def| self.cx.intern(ty_def));
+                if is_recursive_ptr {
+                    self.recursive_ptr_depth -= 1;
+                }
+                transformed
+            }
+        };
+        self.transformed_types.insert(key, transformed);
+        transformed
+    }
+    fn transform_const_use(&mut self, ct: Const) -> Transformed<Const> {
+        let key = (ct, self.recursive_ptr_depth);
+        if let Some(&cached) = self.transformed_consts.get(&key) {
+            return cached;
+        }
+        let transformed = self
+            .transform_const_def(&self.cx[ct])
+            .map(|ct_def| self.cx.intern(ct_def));
+        self.transformed_consts.insert(key, transformed);
+        transformed
+    }
+}
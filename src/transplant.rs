@@ -0,0 +1,344 @@
+//! Deep-copying [`Module`]s between [`Context`]s (see [`Module::clone_into`]).
+
+use crate::passes::inline::BodyCloner;
+use crate::passes::legalize::reachable_global_vars_and_funcs_from_exports;
+use crate::transform::{InnerInPlaceTransform, InnerTransform, Transformed, Transformer};
+use crate::{
+    cfg, Attr, AttrSet, AttrSetDef, Const, ConstCtor, ConstDef, Context, DataInstDef, DataInstKind,
+    DeclDef, EntityDefs, ExportKey, Exportee, Func, FuncDecl, FuncDefBody, FxIndexMap, GlobalVar,
+    Import, InternedStr, Module, ModuleDebugInfo, OrdAssertEq, Type, TypeDef,
+};
+use rustc_hash::FxHashMap;
+use smallvec::SmallVec;
+use std::mem;
+use std::rc::Rc;
+
+impl Module {
+    /// Deep-copy this module into `cx`, i.e. re-intern all of its types, constants
+    /// and attributes (and strings) into `cx`, and define all of its entities
+    /// (global variables, functions, and everything in function bodies) anew,
+    /// in the returned module (which uses `cx`).
+    ///
+    /// This allows e.g. caching modules independently of any [`Context`] (which
+    /// can only ever grow), by keeping them in a [`Context`] of their own, and
+    /// copying them into the [`Context`] of each new (short-lived) compilation.
+    ///
+    /// Only the definitions reachable from exports (see [`Module::exports`])
+    /// are copied, as nothing else can be observed from outside the module.
+    pub fn clone_into(&self, cx: &Rc<Context>) -> Module {
+        self.clone_exports_into(cx, &self.exports)
+    }
+
+    /// Split this module into one module per SPIR-V entry-point (i.e. each
+    /// [`ExportKey::SpvEntryPoint`] export), each containing only that one
+    /// entry-point (as its only export), and the definitions reachable from it.
+    ///
+    /// This is useful for drivers and tools that only accept SPIR-V modules
+    /// with a single entry-point (all other exports, i.e. [`ExportKey::LinkName`],
+    /// are never kept, as they can't be meaningfully attributed to one module).
+    pub fn split_per_entry_point(&self) -> Vec<Module> {
+        let cx = self.cx();
+        self.exports
+            .iter()
+            .filter(|(export_key, _)| matches!(export_key, ExportKey::SpvEntryPoint { .. }))
+            .map(|(export_key, exportee)| {
+                let exports: FxIndexMap<_, _> =
+                    [(export_key.clone(), *exportee)].into_iter().collect();
+                self.clone_exports_into(&cx, &exports)
+            })
+            .collect()
+    }
+
+    /// Deep-copy only `exports` (which should be a subset of `self.exports`),
+    /// and everything reachable from them, into `cx` (see [`Module::clone_into`]).
+    //
+    // NOTE(eddyb) `cx` can also be the same `Context` as `self` uses, in which
+    // case everything interned stays the same, and only entities are redefined.
+    fn clone_exports_into(
+        &self,
+        cx: &Rc<Context>,
+        exports: &FxIndexMap<ExportKey, Exportee>,
+    ) -> Module {
+        let mut transplanter = Transplanter {
+            from_cx: self.cx_ref(),
+            to_cx: cx,
+
+            global_vars: FxHashMap::default(),
+            funcs: FxHashMap::default(),
+
+            transformed_attr_sets: FxHashMap::default(),
+            transformed_types: FxHashMap::default(),
+            transformed_consts: FxHashMap::default(),
+        };
+
+        let mut module = Module::new(cx.clone(), self.dialect.clone(), self.debug_info.clone());
+        match &mut module.debug_info {
+            ModuleDebugInfo::Spv(debug_info) => {
+                for sources in debug_info.source_languages.values_mut() {
+                    sources.file_contents = mem::take(&mut sources.file_contents)
+                        .into_iter()
+                        .map(|(file, contents)| (transplanter.transplant_str(file), contents))
+                        .collect();
+                }
+            }
+        }
+
+        // NOTE(eddyb) all definitions are first copied as-is (other than function
+        // bodies, which need all of their entities defined anew, from `cx`), to
+        // allocate their new entities, and only then are they transformed.
+        let (global_vars, funcs) = reachable_global_vars_and_funcs_from_exports(self, exports);
+        for &gv in &global_vars {
+            let new_gv = module.global_vars.define(cx, self.global_vars[gv].clone());
+            transplanter.global_vars.insert(gv, new_gv);
+        }
+        for &func in &funcs {
+            let func_decl = &self.funcs[func];
+            let new_func = module.funcs.define(
+                cx,
+                FuncDecl {
+                    attrs: func_decl.attrs,
+                    ret_type: func_decl.ret_type,
+                    params: func_decl.params.clone(),
+                    def: match &func_decl.def {
+                        &DeclDef::Imported(import) => DeclDef::Imported(import),
+                        DeclDef::Present(func_def_body) => {
+                            DeclDef::Present(clone_func_def_body(cx, func_def_body))
+                        }
+                    },
+                },
+            );
+            transplanter.funcs.insert(func, new_func);
+        }
+        for gv in global_vars {
+            let gv_decl = &mut module.global_vars[transplanter.global_vars[&gv]];
+            transplanter.in_place_transform_global_var_decl(gv_decl);
+            if let DeclDef::Imported(import) = &mut gv_decl.def {
+                *import = transplanter.transplant_import(*import);
+            }
+        }
+        for func in funcs {
+            let func_decl = &mut module.funcs[transplanter.funcs[&func]];
+            transplanter.in_place_transform_func_decl(func_decl);
+            if let DeclDef::Imported(import) = &mut func_decl.def {
+                *import = transplanter.transplant_import(*import);
+            }
+        }
+
+        module.exports = exports
+            .iter()
+            .map(|(export_key, exportee)| {
+                let export_key = match *export_key {
+                    ExportKey::LinkName(name) => {
+                        ExportKey::LinkName(transplanter.transplant_str(name))
+                    }
+                    ExportKey::SpvEntryPoint { .. } => {
+                        let mut export_key = export_key.clone();
+                        export_key
+                            .inner_transform_with(&mut transplanter)
+                            .apply_to(&mut export_key);
+                        export_key
+                    }
+                };
+                let mut exportee = *exportee;
+                exportee
+                    .inner_transform_with(&mut transplanter)
+                    .apply_to(&mut exportee);
+                (export_key, exportee)
+            })
+            .collect();
+
+        module
+    }
+}
+
+/// Copy `func_def_body`, with all of its entities defined anew, from `cx`
+/// (but without changing anything else, see [`Transplanter`] for that).
+fn clone_func_def_body(cx: &Context, func_def_body: &FuncDefBody) -> FuncDefBody {
+    let mut control_regions = EntityDefs::new();
+    let mut control_nodes = EntityDefs::new();
+    let mut data_insts = EntityDefs::new();
+    let mut cloner = BodyCloner {
+        cx,
+        from: func_def_body,
+        control_regions: &mut control_regions,
+        control_nodes: &mut control_nodes,
+        data_insts: &mut data_insts,
+        values: FxHashMap::default(),
+    };
+
+    let (body, unstructured_cfg) = match &func_def_body.unstructured_cfg {
+        None => (cloner.clone_region(func_def_body.body), None),
+        Some(cfg) => {
+            // NOTE(eddyb) regions are copied in reverse post-order, so that all
+            // values are copied before any of their uses (other than the uses
+            // in the control instructions, which are copied last, after all the
+            // regions they can target).
+            let rpo: SmallVec<[_; 8]> = cfg.rev_post_order(func_def_body).collect();
+            let new_regions: FxHashMap<_, _> = rpo
+                .iter()
+                .map(|&region| (region, cloner.clone_region(region)))
+                .collect();
+
+            let mut new_cfg = cfg::ControlFlowGraph::default();
+            for region in rpo {
+                let control_inst = match cfg.control_inst_on_exit_from.get(region) {
+                    Some(control_inst) => control_inst,
+                    None => continue,
+                };
+                let remap_all = |values: &SmallVec<[_; 2]>| -> SmallVec<[_; 2]> {
+                    values.iter().map(|&v| cloner.remap(v)).collect()
+                };
+                let new_control_inst = cfg::ControlInst {
+                    attrs: control_inst.attrs,
+                    kind: control_inst.kind.clone(),
+                    inputs: remap_all(&control_inst.inputs),
+                    targets: control_inst
+                        .targets
+                        .iter()
+                        .map(|target| new_regions[target])
+                        .collect(),
+                    target_inputs: control_inst
+                        .target_inputs
+                        .iter()
+                        .map(|(target, inputs)| (new_regions[target], remap_all(inputs)))
+                        .collect(),
+                };
+                new_cfg
+                    .control_inst_on_exit_from
+                    .insert(new_regions[&region], new_control_inst);
+            }
+            (new_regions[&func_def_body.body], Some(new_cfg))
+        }
+    };
+
+    FuncDefBody {
+        control_regions,
+        control_nodes,
+        data_insts,
+        body,
+        unstructured_cfg,
+    }
+}
+
+/// [`Transformer`] re-interning everything from `from_cx` into `to_cx`, and
+/// remapping [`GlobalVar`]s and [`Func`]s (see [`Module::clone_into`]).
+//
+// FIXME(eddyb) strings (i.e. `InternedStr`s) aren't handled by `Transformer`,
+// so they have to be found (and transplanted) manually.
+struct Transplanter<'a> {
+    from_cx: &'a Context,
+    to_cx: &'a Context,
+
+    global_vars: FxHashMap<GlobalVar, GlobalVar>,
+    funcs: FxHashMap<Func, Func>,
+
+    // FIXME(eddyb) build some automation to avoid ever repeating these.
+    transformed_attr_sets: FxHashMap<AttrSet, AttrSet>,
+    transformed_types: FxHashMap<Type, Type>,
+    transformed_consts: FxHashMap<Const, Const>,
+}
+
+impl Transplanter<'_> {
+    fn transplant_str(&self, s: InternedStr) -> InternedStr {
+        self.to_cx.intern(&self.from_cx[s])
+    }
+
+    fn transplant_import(&self, import: Import) -> Import {
+        match import {
+            Import::LinkName(name) => Import::LinkName(self.transplant_str(name)),
+        }
+    }
+}
+
+impl Transformer for Transplanter<'_> {
+    // NOTE(eddyb) unlike most `Transformer`s, this always returns `Changed`,
+    // for all the interned leaves, as the result is always from `to_cx`.
+    fn transform_attr_set_use(&mut self, attrs: AttrSet) -> Transformed<AttrSet> {
+        if let Some(&cached) = self.transformed_attr_sets.get(&attrs) {
+            return Transformed::Changed(cached);
+        }
+        let attrs_def = &self.from_cx[attrs];
+        let new_attrs_def = match self.transform_attr_set_def(attrs_def) {
+            Transformed::Changed(new_attrs_def) => new_attrs_def,
+            Transformed::Unchanged => AttrSetDef {
+                attrs: attrs_def.attrs.clone(),
+            },
+        };
+        let new_attrs = self.to_cx.intern(new_attrs_def);
+        self.transformed_attr_sets.insert(attrs, new_attrs);
+        Transformed::Changed(new_attrs)
+    }
+    fn transform_attr(&mut self, attr: &Attr) -> Transformed<Attr> {
+        match *attr {
+            Attr::SpvDebugLine {
+                file_path: OrdAssertEq(file_path),
+                line,
+                col,
+            } => Transformed::Changed(Attr::SpvDebugLine {
+                file_path: OrdAssertEq(self.transplant_str(file_path)),
+                line,
+                col,
+            }),
+            _ => attr.inner_transform_with(self),
+        }
+    }
+    fn transform_type_use(&mut self, ty: Type) -> Transformed<Type> {
+        if let Some(&cached) = self.transformed_types.get(&ty) {
+            return Transformed::Changed(cached);
+        }
+        let ty_def = &self.from_cx[ty];
+        let new_ty_def = match self.transform_type_def(ty_def) {
+            Transformed::Changed(new_ty_def) => new_ty_def,
+            Transformed::Unchanged => TypeDef {
+                attrs: ty_def.attrs,
+                ctor: ty_def.ctor.clone(),
+                ctor_args: ty_def.ctor_args.clone(),
+            },
+        };
+        let new_ty = self.to_cx.intern(new_ty_def);
+        self.transformed_types.insert(ty, new_ty);
+        Transformed::Changed(new_ty)
+    }
+    fn transform_const_use(&mut self, ct: Const) -> Transformed<Const> {
+        if let Some(&cached) = self.transformed_consts.get(&ct) {
+            return Transformed::Changed(cached);
+        }
+        let ct_def = &self.from_cx[ct];
+        let mut new_ct_def = match self.transform_const_def(ct_def) {
+            Transformed::Changed(new_ct_def) => new_ct_def,
+            Transformed::Unchanged => ConstDef {
+                attrs: ct_def.attrs,
+                ty: ct_def.ty,
+                ctor: ct_def.ctor.clone(),
+                ctor_args: ct_def.ctor_args.clone(),
+            },
+        };
+        match &mut new_ct_def.ctor {
+            ConstCtor::SpvExtInst { ext_set, .. } => *ext_set = self.transplant_str(*ext_set),
+            ConstCtor::SpvStringLiteralForExtInst(s) => *s = self.transplant_str(*s),
+            ConstCtor::PtrToGlobalVar(_)
+            | ConstCtor::Undef
+            | ConstCtor::SpvInst(_)
+            | ConstCtor::SpecConst { .. }
+            | ConstCtor::SpecConstOp(_)
+            | ConstCtor::SpvTypeOperand => {}
+        }
+        let new_ct = self.to_cx.intern(new_ct_def);
+        self.transformed_consts.insert(ct, new_ct);
+        Transformed::Changed(new_ct)
+    }
+
+    fn transform_global_var_use(&mut self, gv: GlobalVar) -> Transformed<GlobalVar> {
+        Transformed::Changed(self.global_vars[&gv])
+    }
+    fn transform_func_use(&mut self, func: Func) -> Transformed<Func> {
+        Transformed::Changed(self.funcs[&func])
+    }
+
+    fn in_place_transform_data_inst_def(&mut self, data_inst_def: &mut DataInstDef) {
+        data_inst_def.inner_in_place_transform_with(self);
+        if let DataInstKind::SpvExtInst { ext_set, .. } = &mut data_inst_def.kind {
+            *ext_set = self.transplant_str(*ext_set);
+        }
+    }
+}
@@ -0,0 +1,543 @@
+//! Value-range (and known-bits) analysis, i.e. conservatively tracking which
+//! values (scalar) integers can take, through (structured) control-flow, for
+//! optimizations to take advantage of (e.g. to remove provably-unnecessary
+//! bounds checks, or use cheaper unsigned operations for non-negative values).
+
+use crate::spv::fold::{const_splat_value, ScalarValue};
+use crate::spv::{self, glsl_std_450};
+use crate::{
+    ControlNodeKind, ControlRegion, Context, DataInstDef, DataInstKind, FuncDefBody, Type,
+    TypeCtor, Value,
+};
+use rustc_hash::FxHashMap;
+use smallvec::SmallVec;
+use std::rc::Rc;
+
+/// Known facts about the values an integer (of `width` bits) can take, i.e. a
+/// range of values (interpreted as unsigned), and bits known to be `0` or `1`.
+///
+/// Both the range and the known bits are always kept consistent with each
+/// other (e.g. a range of `0..=7` implies all bits other than the low 3 are `0`).
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct IntRange {
+    pub width: u32,
+
+    /// Smallest possible value (interpreted as unsigned).
+    pub min: u64,
+
+    /// Largest possible value (interpreted as unsigned).
+    pub max: u64,
+
+    /// Bits known to always be `0`.
+    pub known_zeros: u64,
+
+    /// Bits known to always be `1`.
+    pub known_ones: u64,
+}
+
+impl IntRange {
+    /// No known facts, i.e. any value of `width` bits is possible.
+    pub fn full(width: u32) -> Self {
+        Self {
+            width,
+            min: 0,
+            max: width_mask(width),
+            known_zeros: 0,
+            known_ones: 0,
+        }
+    }
+
+    /// Only the constant value `bits` is possible.
+    pub fn constant(width: u32, bits: u64) -> Self {
+        let bits = bits & width_mask(width);
+        Self {
+            width,
+            min: bits,
+            max: bits,
+            known_zeros: !bits & width_mask(width),
+            known_ones: bits,
+        }
+    }
+
+    /// Only values in `min..=max` are possible.
+    pub fn from_min_max(width: u32, min: u64, max: u64) -> Self {
+        Self {
+            min,
+            max,
+            ..Self::full(width)
+        }
+        .normalize()
+    }
+
+    /// Only values with the `known_zeros` bits all `0`, and `known_ones` bits
+    /// all `1`, are possible.
+    pub fn from_known_bits(width: u32, known_zeros: u64, known_ones: u64) -> Self {
+        Self {
+            known_zeros: known_zeros & width_mask(width),
+            known_ones: known_ones & width_mask(width),
+            ..Self::full(width)
+        }
+        .normalize()
+    }
+
+    /// Get the only possible value, if there is only one.
+    pub fn as_const(self) -> Option<u64> {
+        (self.min == self.max).then_some(self.min)
+    }
+
+    /// Returns `true` if the value `bits` is possible.
+    pub fn contains(self, bits: u64) -> bool {
+        (self.min..=self.max).contains(&bits)
+            && bits & self.known_zeros == 0
+            && bits & self.known_ones == self.known_ones
+    }
+
+    /// Returns `true` if the sign bit is known to be `0` (i.e. the value is
+    /// non-negative, when interpreted as signed).
+    pub fn is_known_non_negative(self) -> bool {
+        (self.known_zeros >> (self.width - 1)) & 1 != 0
+    }
+
+    /// Combine the possible values of `self` and `other` (e.g. for values which
+    /// may come from either, like the outputs of a `Select`).
+    pub fn union(self, other: Self) -> Self {
+        assert_eq!(self.width, other.width);
+        Self {
+            width: self.width,
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+            known_zeros: self.known_zeros & other.known_zeros,
+            known_ones: self.known_ones & other.known_ones,
+        }
+        .normalize()
+    }
+
+    /// Make the range and the known bits consistent with each other.
+    fn normalize(mut self) -> Self {
+        let mask = width_mask(self.width);
+
+        self.min = self.min.max(self.known_ones);
+        self.max = self.max.min(mask & !self.known_zeros);
+
+        // NOTE(eddyb) all the bits above the highest bit that differs between
+        // `min` and `max` are the same for all the values in the range.
+        let differing_bits = self.min ^ self.max;
+        let common_high_bits = match differing_bits.checked_ilog2() {
+            Some(highest_differing_bit) => mask & !(u64::MAX >> (63 - highest_differing_bit)),
+            None => mask,
+        };
+        self.known_zeros |= !self.min & common_high_bits;
+        self.known_ones |= self.min & common_high_bits;
+
+        self
+    }
+
+    /// Number of low bits known to be `0`.
+    fn known_trailing_zeros(self) -> u32 {
+        self.known_zeros.trailing_ones().min(self.width)
+    }
+
+    /// Like [`IntRange::full`], but keeping `trailing_zeros` low bits `0`.
+    fn full_with_trailing_zeros(width: u32, trailing_zeros: u32) -> Self {
+        Self::from_known_bits(width, width_mask(trailing_zeros.min(width)), 0)
+    }
+
+    fn add(self, other: Self) -> Self {
+        let max = self
+            .max
+            .checked_add(other.max)
+            .filter(|&max| max <= width_mask(self.width));
+        let trailing_zeros = self
+            .known_trailing_zeros()
+            .min(other.known_trailing_zeros());
+        match max {
+            Some(max) => Self::from_min_max(self.width, self.min + other.min, max)
+                .intersect(Self::full_with_trailing_zeros(self.width, trailing_zeros)),
+            None => Self::full_with_trailing_zeros(self.width, trailing_zeros),
+        }
+    }
+
+    fn sub(self, other: Self) -> Self {
+        let trailing_zeros = self
+            .known_trailing_zeros()
+            .min(other.known_trailing_zeros());
+        if self.min >= other.max {
+            Self::from_min_max(self.width, self.min - other.max, self.max - other.min)
+                .intersect(Self::full_with_trailing_zeros(self.width, trailing_zeros))
+        } else {
+            Self::full_with_trailing_zeros(self.width, trailing_zeros)
+        }
+    }
+
+    fn mul(self, other: Self) -> Self {
+        let max = self
+            .max
+            .checked_mul(other.max)
+            .filter(|&max| max <= width_mask(self.width));
+        let trailing_zeros = self.known_trailing_zeros() + other.known_trailing_zeros();
+        match max {
+            Some(max) => Self::from_min_max(self.width, self.min * other.min, max)
+                .intersect(Self::full_with_trailing_zeros(self.width, trailing_zeros)),
+            None => Self::full_with_trailing_zeros(self.width, trailing_zeros),
+        }
+    }
+
+    fn udiv(self, other: Self) -> Self {
+        // NOTE(eddyb) division by `0` is undefined behavior.
+        if other.min == 0 {
+            return Self::from_min_max(self.width, 0, self.max);
+        }
+        Self::from_min_max(self.width, self.min / other.max, self.max / other.min)
+    }
+
+    fn umod(self, other: Self) -> Self {
+        let max = if other.max == 0 {
+            self.max
+        } else {
+            self.max.min(other.max - 1)
+        };
+        Self::from_min_max(self.width, 0, max)
+    }
+
+    fn bitwise_and(self, other: Self) -> Self {
+        Self {
+            width: self.width,
+            min: 0,
+            max: self.max.min(other.max),
+            known_zeros: self.known_zeros | other.known_zeros,
+            known_ones: self.known_ones & other.known_ones,
+        }
+        .normalize()
+    }
+
+    fn bitwise_or(self, other: Self) -> Self {
+        Self {
+            width: self.width,
+            min: self.min.max(other.min),
+            max: width_mask(self.width),
+            known_zeros: self.known_zeros & other.known_zeros,
+            known_ones: self.known_ones | other.known_ones,
+        }
+        .normalize()
+    }
+
+    fn bitwise_xor(self, other: Self) -> Self {
+        Self::from_known_bits(
+            self.width,
+            (self.known_zeros & other.known_zeros) | (self.known_ones & other.known_ones),
+            (self.known_zeros & other.known_ones) | (self.known_ones & other.known_zeros),
+        )
+    }
+
+    fn shl(self, shift: u32) -> Self {
+        let mask = width_mask(self.width);
+        let known_bits = Self::from_known_bits(
+            self.width,
+            (self.known_zeros << shift) | width_mask(shift),
+            self.known_ones << shift,
+        );
+        if self.max <= mask >> shift {
+            Self::from_min_max(self.width, self.min << shift, self.max << shift)
+                .intersect(known_bits)
+        } else {
+            known_bits
+        }
+    }
+
+    fn shr(self, shift: u32) -> Self {
+        Self::from_min_max(self.width, self.min >> shift, self.max >> shift).intersect(
+            Self::from_known_bits(
+                self.width,
+                (self.known_zeros >> shift) | !(width_mask(self.width) >> shift),
+                self.known_ones >> shift,
+            ),
+        )
+    }
+
+    fn umin(self, other: Self) -> Self {
+        Self::from_min_max(self.width, self.min.min(other.min), self.max.min(other.max))
+    }
+
+    fn umax(self, other: Self) -> Self {
+        Self::from_min_max(self.width, self.min.max(other.min), self.max.max(other.max))
+    }
+
+    /// Convert to `width` bits (by zero-extension, or truncation).
+    fn resize(self, width: u32) -> Self {
+        let extended_bits = width_mask(width) & !width_mask(self.width);
+        let known_bits =
+            Self::from_known_bits(width, self.known_zeros | extended_bits, self.known_ones);
+        if self.max <= width_mask(width) {
+            Self::from_min_max(width, self.min, self.max).intersect(known_bits)
+        } else {
+            known_bits
+        }
+    }
+
+    /// Combine the facts known about the same value, from `self` and `other`.
+    fn intersect(self, other: Self) -> Self {
+        let intersection = Self {
+            width: self.width,
+            min: self.min.max(other.min),
+            max: self.max.min(other.max),
+            known_zeros: self.known_zeros | other.known_zeros,
+            known_ones: self.known_ones | other.known_ones,
+        };
+        // NOTE(eddyb) contradictions can only happen for unreachable values,
+        // for which keeping either side is still correct.
+        if intersection.min > intersection.max
+            || intersection.known_zeros & intersection.known_ones != 0
+        {
+            return self;
+        }
+        intersection.normalize()
+    }
+}
+
+/// All the bits of an integer of `width` bits.
+fn width_mask(width: u32) -> u64 {
+    if width == 0 {
+        0
+    } else {
+        u64::MAX >> (64 - width)
+    }
+}
+
+/// Get the width of `ty`, if it's a scalar integer type (of at most 64 bits).
+fn int_width(cx: &Context, ty: Type) -> Option<u32> {
+    let wk = &spv::spec::Spec::get().well_known;
+
+    match &cx[ty].ctor {
+        TypeCtor::SpvInst(inst) if inst.opcode == wk.OpTypeInt => match inst.imms[..] {
+            [spv::Imm::Short(_, width), _] if (1..=64).contains(&width) => Some(width),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// The maximum number of times a `Loop` body is analyzed, before giving up on
+/// its inputs converging, and assuming they can take any value instead.
+const MAX_LOOP_ITERATIONS: usize = 8;
+
+/// Value-range (and known-bits) analysis results for all the (scalar) integer
+/// values in a function body, computed by propagating [`IntRange`]s forward,
+/// through the instructions (and control-flow) defining each value.
+///
+/// `Loop` body inputs are iterated until they converge (for a bounded number
+/// of iterations), while inputs of unstructured control-flow regions (and the
+/// function parameters) are assumed to be able to take any value.
+pub struct ValueRanges {
+    cx: Rc<Context>,
+
+    ranges: FxHashMap<Value, IntRange>,
+}
+
+impl ValueRanges {
+    pub fn compute(cx: Rc<Context>, func_def_body: &FuncDefBody) -> Self {
+        let mut value_ranges = Self {
+            cx,
+            ranges: FxHashMap::default(),
+        };
+        match &func_def_body.unstructured_cfg {
+            None => value_ranges.compute_region(func_def_body, func_def_body.body),
+            Some(cfg) => {
+                for region in cfg.rev_post_order(func_def_body) {
+                    value_ranges.compute_region(func_def_body, region);
+                }
+            }
+        }
+        value_ranges
+    }
+
+    /// Get the [`IntRange`] of `v`, or `None` if it's not a scalar integer (or
+    /// it's one which wasn't defined in the function body this was computed for).
+    ///
+    /// Values defined after [`ValueRanges::compute`] was called (e.g. by a
+    /// transformation making use of these results) also return `None`.
+    pub fn range_of(&self, v: Value) -> Option<IntRange> {
+        match v {
+            Value::Const(ct) => {
+                let width = int_width(&self.cx, self.cx[ct].ty)?;
+                match const_splat_value(&self.cx, ct)? {
+                    ScalarValue::Int { bits, .. } => Some(IntRange::constant(width, bits)),
+                    _ => None,
+                }
+            }
+            _ => self.ranges.get(&v).copied(),
+        }
+    }
+
+    fn compute_region(&mut self, func_def_body: &FuncDefBody, region: ControlRegion) {
+        for func_at_control_node in func_def_body.at(region).at_children() {
+            let control_node = func_at_control_node.position;
+            let control_node_def = func_at_control_node.def();
+            match &control_node_def.kind {
+                &ControlNodeKind::Block { insts } => {
+                    for func_at_inst in func_def_body.at(insts) {
+                        let inst_def = func_at_inst.def();
+                        let width =
+                            match inst_def.output_type.and_then(|ty| int_width(&self.cx, ty)) {
+                                Some(width) => width,
+                                None => continue,
+                            };
+                        let range = self.eval_inst(func_def_body, inst_def, width);
+                        self.ranges
+                            .insert(Value::DataInstOutput(func_at_inst.position), range);
+                    }
+                }
+                ControlNodeKind::Select { cases, .. } => {
+                    for &case in cases {
+                        self.compute_region(func_def_body, case);
+                    }
+                    for (output_idx, output_decl) in control_node_def.outputs.iter().enumerate() {
+                        let width = match int_width(&self.cx, output_decl.ty) {
+                            Some(width) => width,
+                            None => continue,
+                        };
+                        let range = cases
+                            .iter()
+                            .map(|&case| {
+                                let v = func_def_body.at(case).def().outputs[output_idx];
+                                self.range_of_or_full(v, width)
+                            })
+                            .reduce(IntRange::union)
+                            .unwrap_or(IntRange::full(width));
+                        self.ranges.insert(
+                            Value::ControlNodeOutput {
+                                control_node,
+                                output_idx: output_idx.try_into().unwrap(),
+                            },
+                            range,
+                        );
+                    }
+                }
+                ControlNodeKind::Loop {
+                    initial_inputs,
+                    body,
+                    ..
+                } => {
+                    let body = *body;
+                    let body_def = func_def_body.at(body).def();
+                    let widths: SmallVec<[_; 2]> = body_def
+                        .inputs
+                        .iter()
+                        .map(|input_decl| int_width(&self.cx, input_decl.ty))
+                        .collect();
+                    let input = |input_idx: usize| Value::ControlRegionInput {
+                        region: body,
+                        input_idx: input_idx.try_into().unwrap(),
+                    };
+
+                    for (input_idx, (&v, &width)) in initial_inputs.iter().zip(&widths).enumerate()
+                    {
+                        if let Some(width) = width {
+                            let range = self.range_of_or_full(v, width);
+                            self.ranges.insert(input(input_idx), range);
+                        }
+                    }
+                    for iteration in 0.. {
+                        self.compute_region(func_def_body, body);
+
+                        let mut changed = false;
+                        for (input_idx, &width) in widths.iter().enumerate() {
+                            let width = match width {
+                                Some(width) => width,
+                                None => continue,
+                            };
+                            let old_range = self.ranges[&input(input_idx)];
+                            let new_range = if iteration < MAX_LOOP_ITERATIONS {
+                                old_range.union(
+                                    self.range_of_or_full(body_def.outputs[input_idx], width),
+                                )
+                            } else {
+                                IntRange::full(width)
+                            };
+                            if new_range != old_range {
+                                self.ranges.insert(input(input_idx), new_range);
+                                changed = true;
+                            }
+                        }
+                        if !changed {
+                            break;
+                        }
+                    }
+                }
+                ControlNodeKind::ExitInvocation { .. } => {}
+            }
+        }
+    }
+
+    fn range_of_or_full(&self, v: Value, width: u32) -> IntRange {
+        self.range_of(v)
+            .filter(|range| range.width == width)
+            .unwrap_or(IntRange::full(width))
+    }
+
+    /// Compute the [`IntRange`] of the output of `inst_def` (of `width` bits).
+    fn eval_inst(
+        &self,
+        func_def_body: &FuncDefBody,
+        inst_def: &DataInstDef,
+        width: u32,
+    ) -> IntRange {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        let full = IntRange::full(width);
+        let input = |i: usize| self.range_of_or_full(inst_def.inputs[i], width);
+        let const_shift = |i: usize| {
+            self.range_of(inst_def.inputs[i])
+                .and_then(|range| range.as_const())
+                .filter(|&shift| shift < u64::from(width))
+                .map(|shift| shift as u32)
+        };
+
+        match &inst_def.kind {
+            DataInstKind::SpvInst(spv_inst) => {
+                let opcode = spv_inst.opcode;
+                if opcode == wk.OpCopyObject || opcode == wk.OpBitcast {
+                    input(0)
+                } else if opcode == wk.OpIAdd {
+                    input(0).add(input(1))
+                } else if opcode == wk.OpISub {
+                    input(0).sub(input(1))
+                } else if opcode == wk.OpIMul {
+                    input(0).mul(input(1))
+                } else if opcode == wk.OpUDiv {
+                    input(0).udiv(input(1))
+                } else if opcode == wk.OpUMod {
+                    input(0).umod(input(1))
+                } else if opcode == wk.OpBitwiseAnd {
+                    input(0).bitwise_and(input(1))
+                } else if opcode == wk.OpBitwiseOr {
+                    input(0).bitwise_or(input(1))
+                } else if opcode == wk.OpBitwiseXor {
+                    input(0).bitwise_xor(input(1))
+                } else if opcode == wk.OpShiftLeftLogical {
+                    const_shift(1).map_or(full, |shift| input(0).shl(shift))
+                } else if opcode == wk.OpShiftRightLogical {
+                    const_shift(1).map_or(full, |shift| input(0).shr(shift))
+                } else if opcode == wk.OpSelect {
+                    input(1).union(input(2))
+                } else if opcode == wk.OpUConvert {
+                    let input_type = func_def_body.at(inst_def.inputs[0]).type_of(&self.cx);
+                    match int_width(&self.cx, input_type) {
+                        Some(input_width) => self
+                            .range_of_or_full(inst_def.inputs[0], input_width)
+                            .resize(width),
+                        None => full,
+                    }
+                } else {
+                    full
+                }
+            }
+            &DataInstKind::SpvGlslStd450(op) => match op {
+                glsl_std_450::Op::UMin => input(0).umin(input(1)),
+                glsl_std_450::Op::UMax => input(0).umax(input(1)),
+                glsl_std_450::Op::UClamp => input(0).umax(input(1)).umin(input(2)),
+                _ => full,
+            },
+            _ => full,
+        }
+    }
+}
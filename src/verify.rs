@@ -0,0 +1,1195 @@
+//! IR verification, i.e. checking that a [`Module`] upholds the structural
+//! invariants of SPIR-T, which all passes rely on (and must maintain).
+
+use crate::cfg::{ControlInst, DominatorTree};
+use crate::func_at::FuncAt;
+use crate::passes::legalize::reachable_funcs;
+use crate::{
+    print, spv, Context, ControlNode, ControlNodeKind, ControlRegion, DataInst, DataInstDef,
+    DataInstKind, DeclDef, EntityListIter, Func, FuncDefBody, FxIndexMap, Module, SelectionKind,
+    Type, TypeCtor, TypeCtorArg, Value,
+};
+use rustc_hash::FxHashSet;
+use smallvec::SmallVec;
+use std::fmt;
+use std::hash::Hash;
+
+/// The entity (in a [`Module`]) a [`VerifyDiagnostic`] is about.
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+pub enum DiagnosticAnchor {
+    Func(Func),
+    ControlRegion(Func, ControlRegion),
+    ControlNode(Func, ControlNode),
+    DataInst(Func, DataInst),
+}
+
+impl DiagnosticAnchor {
+    /// Get the function this anchor is in (or is, for [`DiagnosticAnchor::Func`]).
+    pub fn func(self) -> Func {
+        match self {
+            Self::Func(func)
+            | Self::ControlRegion(func, _)
+            | Self::ControlNode(func, _)
+            | Self::DataInst(func, _) => func,
+        }
+    }
+}
+
+impl fmt::Display for DiagnosticAnchor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Func(_) => "function",
+            Self::ControlRegion(..) => "control region",
+            Self::ControlNode(..) => "control node",
+            Self::DataInst(..) => "instruction",
+        })
+    }
+}
+
+/// Invariant violation found by [`verify_module`].
+#[derive(Clone)]
+pub struct VerifyDiagnostic {
+    pub anchor: DiagnosticAnchor,
+    pub message: String,
+}
+
+impl fmt::Display for VerifyDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.anchor, self.message)
+    }
+}
+
+/// Error produced by [`verify_module`], with all the invariant violations found.
+pub struct VerifyError {
+    pub diagnostics: Vec<VerifyDiagnostic>,
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid module:")?;
+        for diag in &self.diagnostics {
+            write!(f, "\n  {diag}")?;
+        }
+        Ok(())
+    }
+}
+
+impl VerifyError {
+    /// Pretty-print all the diagnostics, grouped by the function they're in,
+    /// with each group followed by that function's definition (printed using
+    /// [`print::Plan`]), to provide the context necessary to locate them.
+    pub fn pretty_print(&self, module: &Module) -> String {
+        let mut diagnostics_per_func = FxIndexMap::<_, Vec<_>>::default();
+        for diag in &self.diagnostics {
+            diagnostics_per_func
+                .entry(diag.anchor.func())
+                .or_default()
+                .push(diag);
+        }
+
+        let mut out = String::new();
+        for (func, diagnostics) in diagnostics_per_func {
+            let func_decl = &module.funcs[func];
+
+            out += "invalid function:";
+            for diag in diagnostics {
+                out += &format!("\n  {diag}");
+            }
+            out += &format!(
+                "\n\n{}\n\n",
+                print::Plan::for_root(module.cx_ref(), func_decl).pretty_print()
+            );
+        }
+        out
+    }
+}
+
+impl fmt::Debug for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl std::error::Error for VerifyError {}
+
+/// Check the structural invariants of every function in `module` (reachable
+/// from its exports), reporting all of the violations found, in a [`VerifyError`].
+///
+/// The invariants checked are:
+/// * every [`Value`] used is valid (i.e. refers to an existing input/output),
+///   and defined before (i.e. in a position that dominates) its use
+/// * the `outputs` of each [`ControlRegion`] match those expected by its parent
+///   (e.g. `Select` cases provide the `Select`'s outputs, `Loop` bodies provide
+///   their own inputs, for the next iteration)
+/// * the function body's `inputs` match the function's parameters
+/// * in unstructured CFGs, the values passed to each target region (through
+///   `target_inputs`) match that region's `inputs`
+/// * all [`EntityList`](crate::EntityList)s are well-formed (i.e. their links
+///   are consistent, and without cycles), and no entity appears in more than
+///   one of them (or, for [`ControlRegion`]s, in more than one place)
+/// * structured control-flow is well-formed, i.e. `Block`s have no outputs,
+///   `Select`s have the number of cases required by their [`SelectionKind`],
+///   and `Loop`s provide initial values for all of their body's inputs
+/// * calls (i.e. [`DataInstKind::FuncCall`]) match the callee's signature
+/// * SPIR-V instructions (i.e. [`DataInstKind::SpvInst`]) have the operands and
+///   result required by the SPIR-V grammar, and (for the common arithmetic,
+///   composite and memory instructions) inputs/output of the expected types
+///
+/// This can be used to verify the output of each pass, with
+/// [`PassManager::verify_with`](crate::passes::manager::PassManager::verify_with)
+/// (using [`VerifyError::pretty_print`] to also show the invalid functions).
+pub fn verify_module(module: &Module) -> Result<(), VerifyError> {
+    let cx = module.cx();
+
+    let mut diagnostics = vec![];
+    for func in reachable_funcs(module) {
+        let func_decl = &module.funcs[func];
+        let func_def_body = match &func_decl.def {
+            DeclDef::Present(func_def_body) => func_def_body,
+            DeclDef::Imported(_) => continue,
+        };
+
+        let mut verifier = FuncVerifier {
+            cx: &cx,
+            module,
+            func,
+            func_def_body,
+            in_scope: FxHashSet::default(),
+            scope_stack: vec![],
+            seen_regions: FxHashSet::default(),
+            seen_control_nodes: FxHashSet::default(),
+            seen_data_insts: FxHashSet::default(),
+            diagnostics: &mut diagnostics,
+        };
+
+        let body_inputs = &func_def_body.at_body().def().inputs;
+        if body_inputs.len() != func_decl.params.len() {
+            verifier.report(
+                DiagnosticAnchor::Func(func),
+                format!(
+                    "function body has {} inputs, but the function has {} parameters",
+                    body_inputs.len(),
+                    func_decl.params.len()
+                ),
+            );
+        }
+        for (param, input) in func_decl.params.iter().zip(body_inputs) {
+            if param.ty != input.ty {
+                let message = verifier.type_mismatch("function parameter", param.ty, input.ty);
+                verifier.report(DiagnosticAnchor::Func(func), message);
+            }
+        }
+
+        verifier.verify_body();
+    }
+
+    if diagnostics.is_empty() {
+        Ok(())
+    } else {
+        Err(VerifyError { diagnostics })
+    }
+}
+
+struct FuncVerifier<'a> {
+    cx: &'a Context,
+    module: &'a Module,
+    func: Func,
+    func_def_body: &'a FuncDefBody,
+
+    /// All the [`Value`]s which can be used at the current position.
+    in_scope: FxHashSet<Value>,
+
+    /// All the [`Value`]s in `in_scope`, in the order they were defined, to
+    /// allow removing those defined in a region, when leaving it.
+    scope_stack: Vec<Value>,
+
+    /// All the entities already visited, to detect any that appear in multiple
+    /// places (e.g. the same [`ControlNode`] in the `children` of two regions).
+    seen_regions: FxHashSet<ControlRegion>,
+    seen_control_nodes: FxHashSet<ControlNode>,
+    seen_data_insts: FxHashSet<DataInst>,
+
+    diagnostics: &'a mut Vec<VerifyDiagnostic>,
+}
+
+impl FuncVerifier<'_> {
+    fn report(&mut self, anchor: DiagnosticAnchor, message: String) {
+        self.diagnostics.push(VerifyDiagnostic { anchor, message });
+    }
+
+    fn type_mismatch(&self, what: &str, expected: Type, found: Type) -> String {
+        format!(
+            "{what} type mismatch:\n\n{}",
+            print::Plan::for_root(self.cx, &print::ExpectedVsFound { expected, found })
+                .pretty_print()
+        )
+    }
+
+    fn define(&mut self, v: Value) {
+        if self.in_scope.insert(v) {
+            self.scope_stack.push(v);
+        }
+    }
+
+    /// Leave the scope entered when `scope_stack` had length `scope_start`,
+    /// making all the values defined since then unavailable.
+    fn leave_scope(&mut self, scope_start: usize) {
+        for v in self.scope_stack.drain(scope_start..) {
+            self.in_scope.remove(&v);
+        }
+    }
+
+    fn verify_body(&mut self) {
+        let func_def_body = self.func_def_body;
+        let cfg = match &func_def_body.unstructured_cfg {
+            None => return self.verify_region(func_def_body.body),
+            Some(cfg) => cfg,
+        };
+
+        // NOTE(eddyb) values defined in a region are available in all the
+        // regions it dominates, so the regions of the CFG are visited in the
+        // pre-order of the dominator tree, leaving the scope of each region
+        // (i.e. removing its values) only after all the regions it dominates.
+        //
+        // FIXME(eddyb) regions unreachable in the CFG are not verified at all.
+        enum Step {
+            Enter(ControlRegion),
+            Leave { scope_start: usize },
+        }
+        let dom_tree = DominatorTree::compute(func_def_body);
+        let mut steps = vec![Step::Enter(dom_tree.root())];
+        while let Some(step) = steps.pop() {
+            let region = match step {
+                Step::Enter(region) => region,
+                Step::Leave { scope_start } => {
+                    self.leave_scope(scope_start);
+                    continue;
+                }
+            };
+            steps.push(Step::Leave {
+                scope_start: self.scope_stack.len(),
+            });
+
+            self.verify_region(region);
+            self.verify_control_inst(region, &cfg.control_inst_on_exit_from[region]);
+
+            // NOTE(eddyb) the dominator tree also contains the regions nested
+            // in structured control-flow, but those were already visited above.
+            steps.extend(
+                dom_tree
+                    .children(region)
+                    .iter()
+                    .rev()
+                    .filter(|&&child| cfg.control_inst_on_exit_from.get(child).is_some())
+                    .map(|&child| Step::Enter(child)),
+            );
+        }
+    }
+
+    /// Verify the [`ControlInst`] on exit from `region` (in an unstructured CFG),
+    /// including the values it passes to the inputs of its target regions.
+    fn verify_control_inst(&mut self, region: ControlRegion, control_inst: &ControlInst) {
+        let func_def_body = self.func_def_body;
+        let anchor = DiagnosticAnchor::ControlRegion(self.func, region);
+
+        for &v in &control_inst.inputs {
+            self.verify_use(anchor, v);
+        }
+
+        for &target in &control_inst.targets {
+            let target_input_count = func_def_body.at(target).def().inputs.len();
+            if target_input_count > 0 && !control_inst.target_inputs.contains_key(&target) {
+                self.report(
+                    anchor,
+                    format!(
+                        "branch to a region with {target_input_count} inputs, \
+                         without any `target_inputs` for it"
+                    ),
+                );
+            }
+        }
+        for (&target, inputs) in &control_inst.target_inputs {
+            if !control_inst.targets.contains(&target) {
+                self.report(
+                    anchor,
+                    "`target_inputs` for a region not in `targets`".to_string(),
+                );
+            }
+
+            let target_inputs = &func_def_body.at(target).def().inputs;
+            if inputs.len() != target_inputs.len() {
+                self.report(
+                    anchor,
+                    format!(
+                        "branch passes {} values to a region with {} inputs",
+                        inputs.len(),
+                        target_inputs.len()
+                    ),
+                );
+            }
+            for &v in inputs {
+                self.verify_use(anchor, v);
+            }
+            for (target_input, &v) in target_inputs.iter().zip(inputs) {
+                // NOTE(eddyb) invalid values were already reported above.
+                if !self.is_valid(v) {
+                    continue;
+                }
+                let ty = func_def_body.at(v).type_of(self.cx);
+                if ty != target_input.ty {
+                    let message = self.type_mismatch("target region input", target_input.ty, ty);
+                    self.report(anchor, message);
+                }
+            }
+        }
+    }
+
+    /// Verify `region`, leaving all the values defined in it in scope (for the
+    /// caller to remove with [`FuncVerifier::leave_scope`], if needed).
+    fn verify_region(&mut self, region: ControlRegion) {
+        let func_def_body = self.func_def_body;
+        let region_def = func_def_body.at(region).def();
+        let anchor = DiagnosticAnchor::ControlRegion(self.func, region);
+
+        if !self.seen_regions.insert(region) {
+            self.report(anchor, "region used in multiple places".to_string());
+            return;
+        }
+
+        for input_idx in 0..region_def.inputs.len() {
+            self.define(Value::ControlRegionInput {
+                region,
+                input_idx: input_idx as u32,
+            });
+        }
+
+        let EntityListIter { first, last } = region_def.children.iter();
+        let well_formed = check_entity_list(first, last, |node| {
+            let node_def = &func_def_body.control_nodes[node];
+            (node_def.prev_in_list(), node_def.next_in_list())
+        });
+        match well_formed {
+            Ok(()) => {
+                for func_at_node in func_def_body.at(region_def.children) {
+                    self.verify_control_node(func_at_node);
+                }
+            }
+            Err(message) => self.report(anchor, format!("invalid `children` list: {message}")),
+        }
+
+        for &v in &region_def.outputs {
+            self.verify_use(anchor, v);
+        }
+    }
+
+    fn verify_control_node(&mut self, func_at_node: FuncAt<'_, ControlNode>) {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        let node = func_at_node.position;
+        let node_def = func_at_node.def();
+        let anchor = DiagnosticAnchor::ControlNode(self.func, node);
+
+        if !self.seen_control_nodes.insert(node) {
+            self.report(anchor, "control node appears in multiple lists".to_string());
+            return;
+        }
+
+        match &node_def.kind {
+            &ControlNodeKind::Block { insts } => {
+                if !node_def.outputs.is_empty() {
+                    self.report(anchor, "`Block` has outputs".to_string());
+                }
+
+                let EntityListIter { first, last } = insts.iter();
+                let well_formed = check_entity_list(first, last, |inst| {
+                    let inst_def = &self.func_def_body.data_insts[inst];
+                    (inst_def.prev_in_list(), inst_def.next_in_list())
+                });
+                match well_formed {
+                    Ok(()) => {
+                        for func_at_inst in func_at_node.at(insts) {
+                            self.verify_data_inst(func_at_inst);
+                        }
+                    }
+                    Err(message) => self.report(anchor, format!("invalid `insts` list: {message}")),
+                }
+            }
+            ControlNodeKind::Select {
+                kind,
+                scrutinee,
+                cases,
+            } => {
+                self.verify_use(anchor, *scrutinee);
+
+                let expected_case_count = match kind {
+                    SelectionKind::BoolCond => Some(2),
+                    // NOTE(eddyb) the first case is the `OpSwitch` default,
+                    // followed by one case for each (possibly "long") literal.
+                    SelectionKind::SpvInst(spv_inst) if spv_inst.opcode == wk.OpSwitch => Some(
+                        1 + spv_inst
+                            .imms
+                            .iter()
+                            .filter(|imm| !matches!(imm, spv::Imm::LongCont(..)))
+                            .count(),
+                    ),
+                    SelectionKind::SpvInst(_) => None,
+                };
+                if let Some(expected_case_count) = expected_case_count {
+                    if cases.len() != expected_case_count {
+                        self.report(
+                            anchor,
+                            format!(
+                                "`Select` has {} cases, but its selection kind requires {}",
+                                cases.len(),
+                                expected_case_count
+                            ),
+                        );
+                    }
+                }
+
+                for &case in cases {
+                    let scope_start = self.scope_stack.len();
+                    self.verify_region(case);
+
+                    let case_outputs = &func_at_node.at(case).def().outputs;
+                    if case_outputs.len() != node_def.outputs.len() {
+                        self.report(
+                            DiagnosticAnchor::ControlRegion(self.func, case),
+                            format!(
+                                "`Select` case has {} outputs, but the `Select` has {} outputs",
+                                case_outputs.len(),
+                                node_def.outputs.len()
+                            ),
+                        );
+                    }
+
+                    // NOTE(eddyb) values defined in the only case of a `Select`
+                    // remain available after it (see also `ControlRegion` docs).
+                    if cases.len() > 1 {
+                        self.leave_scope(scope_start);
+                    }
+                }
+            }
+            ControlNodeKind::Loop {
+                initial_inputs,
+                body,
+                repeat_condition,
+            } => {
+                for &v in initial_inputs {
+                    self.verify_use(anchor, v);
+                }
+
+                let body_inputs = &func_at_node.at(*body).def().inputs;
+                if initial_inputs.len() != body_inputs.len() {
+                    self.report(
+                        anchor,
+                        format!(
+                            "`Loop` has {} initial inputs, but its body has {} inputs",
+                            initial_inputs.len(),
+                            body_inputs.len()
+                        ),
+                    );
+                }
+                for (body_input, &v) in body_inputs.iter().zip(initial_inputs) {
+                    // NOTE(eddyb) invalid values were already reported above.
+                    if !self.is_valid(v) {
+                        continue;
+                    }
+                    let ty = func_at_node.at(v).type_of(self.cx);
+                    if ty != body_input.ty {
+                        let message = self.type_mismatch("`Loop` initial input", body_input.ty, ty);
+                        self.report(anchor, message);
+                    }
+                }
+
+                // NOTE(eddyb) values defined in the body of a `Loop` remain
+                // available after it (see also `ControlRegion` docs).
+                self.verify_region(*body);
+                self.verify_use(anchor, *repeat_condition);
+
+                let body_def = func_at_node.at(*body).def();
+                if body_def.outputs.len() != body_def.inputs.len() {
+                    self.report(
+                        DiagnosticAnchor::ControlRegion(self.func, *body),
+                        format!(
+                            "`Loop` body has {} outputs, but {} inputs (for the next iteration)",
+                            body_def.outputs.len(),
+                            body_def.inputs.len()
+                        ),
+                    );
+                }
+            }
+            ControlNodeKind::ExitInvocation { kind: _, inputs } => {
+                if !node_def.outputs.is_empty() {
+                    self.report(anchor, "`ExitInvocation` has outputs".to_string());
+                }
+
+                for &v in inputs {
+                    self.verify_use(anchor, v);
+                }
+            }
+        }
+
+        for output_idx in 0..node_def.outputs.len() {
+            self.define(Value::ControlNodeOutput {
+                control_node: node,
+                output_idx: output_idx as u32,
+            });
+        }
+    }
+
+    fn verify_data_inst(&mut self, func_at_inst: FuncAt<'_, DataInst>) {
+        let inst = func_at_inst.position;
+        let inst_def = func_at_inst.def();
+        let anchor = DiagnosticAnchor::DataInst(self.func, inst);
+
+        if !self.seen_data_insts.insert(inst) {
+            self.report(anchor, "instruction appears in multiple lists".to_string());
+            return;
+        }
+
+        for &v in &inst_def.inputs {
+            self.verify_use(anchor, v);
+        }
+
+        if let DataInstKind::FuncCall(callee) = inst_def.kind {
+            let callee_decl = &self.module.funcs[callee];
+            if inst_def.inputs.len() != callee_decl.params.len() {
+                self.report(
+                    anchor,
+                    format!(
+                        "call has {} arguments, but the callee has {} parameters",
+                        inst_def.inputs.len(),
+                        callee_decl.params.len()
+                    ),
+                );
+            }
+            for (param, &arg) in callee_decl.params.iter().zip(&inst_def.inputs) {
+                // NOTE(eddyb) invalid arguments were already reported above.
+                if !self.is_valid(arg) {
+                    continue;
+                }
+                let arg_ty = func_at_inst.at(arg).type_of(self.cx);
+                if arg_ty != param.ty {
+                    let message = self.type_mismatch("call argument", param.ty, arg_ty);
+                    self.report(anchor, message);
+                }
+            }
+            if let Some(output_type) = inst_def.output_type {
+                if output_type != callee_decl.ret_type {
+                    let message =
+                        self.type_mismatch("call result", callee_decl.ret_type, output_type);
+                    self.report(anchor, message);
+                }
+            }
+        }
+
+        if let DataInstKind::SpvInst(spv_inst) = &inst_def.kind {
+            // NOTE(eddyb) invalid inputs were already reported above.
+            if inst_def.inputs.iter().all(|&v| self.is_valid(v)) {
+                let input_types: SmallVec<[_; 4]> = inst_def
+                    .inputs
+                    .iter()
+                    .map(|&v| func_at_inst.at(v).type_of(self.cx))
+                    .collect();
+                for message in self.spv_inst_type_errors(spv_inst, &input_types, inst_def) {
+                    self.report(anchor, message);
+                }
+            }
+        }
+
+        if inst_def.output_type.is_some() {
+            self.define(Value::DataInstOutput(inst));
+        }
+    }
+
+    /// Check the inputs and output of a [`DataInstKind::SpvInst`] against the
+    /// SPIR-V grammar (i.e. the presence of the result type, and the number of
+    /// required ID operands), and, for the common arithmetic, composite and
+    /// memory instructions, also their types against those expected by the
+    /// SPIR-V specification, returning all the problems found.
+    //
+    // FIXME(eddyb) cover more instructions (e.g. conversions and images).
+    fn spv_inst_type_errors(
+        &self,
+        spv_inst: &spv::Inst,
+        input_types: &[Type],
+        inst_def: &DataInstDef,
+    ) -> Vec<String> {
+        let wk = &spv::spec::Spec::get().well_known;
+        let cx = self.cx;
+
+        let (name, def) = spv_inst.opcode.name_and_def();
+
+        let mut errors = vec![];
+
+        if def.has_result_type_id != inst_def.output_type.is_some() {
+            errors.push(if def.has_result_type_id {
+                format!("`{name}` requires a result type, but has no output type")
+            } else {
+                format!("`{name}` has no result type, but has an output type")
+            });
+        }
+        let req_id_operands = def
+            .req_operands
+            .iter()
+            .filter(|kind| matches!(kind.def(), spv::spec::OperandKindDef::Id))
+            .count();
+        if input_types.len() < req_id_operands {
+            errors.push(format!(
+                "`{name}` requires at least {req_id_operands} ID operands, but has {} inputs",
+                input_types.len()
+            ));
+        }
+        if !errors.is_empty() {
+            return errors;
+        }
+
+        let output_type = inst_def.output_type;
+        let opcode = spv_inst.opcode;
+        let mut type_mismatches = vec![];
+        let mut expect_type = |what: &str, expected: Type, found: Type| {
+            if expected != found {
+                type_mismatches.push(self.type_mismatch(
+                    &format!("`{name}` {what}"),
+                    expected,
+                    found,
+                ));
+            }
+        };
+
+        // NOTE(eddyb) the scalar "shape" of the output and inputs, used for
+        // instructions which only require e.g. the same width (but not the
+        // same signedness), or the same number of components.
+        let output_shape = output_type.and_then(|ty| ScalarShape::of(cx, ty));
+        let input_shapes: SmallVec<[_; 4]> = input_types
+            .iter()
+            .map(|&ty| ScalarShape::of(cx, ty))
+            .collect();
+        let mut shape_error = None;
+
+        if [
+            wk.OpIAdd,
+            wk.OpISub,
+            wk.OpIMul,
+            wk.OpUDiv,
+            wk.OpSDiv,
+            wk.OpUMod,
+            wk.OpSRem,
+            wk.OpSMod,
+            wk.OpBitwiseOr,
+            wk.OpBitwiseXor,
+            wk.OpBitwiseAnd,
+            wk.OpSNegate,
+            wk.OpNot,
+        ]
+        .contains(&opcode)
+        {
+            let all_same_int_shape = match output_shape {
+                Some(ScalarShape {
+                    kind: ScalarKind::Int { .. },
+                    ..
+                }) => input_shapes.iter().all(|&shape| shape == output_shape),
+                _ => false,
+            };
+            if !all_same_int_shape {
+                shape_error = Some(
+                    "requires integer inputs and output, all with the same width \
+                     and component count",
+                );
+            }
+        } else if [
+            wk.OpShiftRightLogical,
+            wk.OpShiftRightArithmetic,
+            wk.OpShiftLeftLogical,
+        ]
+        .contains(&opcode)
+        {
+            let valid = match (output_shape, &input_shapes[..]) {
+                (
+                    Some(ScalarShape {
+                        kind: ScalarKind::Int { .. },
+                        count,
+                    }),
+                    &[base, Some(shift), ..],
+                ) => {
+                    base == output_shape
+                        && matches!(shift.kind, ScalarKind::Int { .. })
+                        && shift.count == count
+                }
+                _ => false,
+            };
+            if !valid {
+                shape_error = Some(
+                    "requires integer inputs and output, with the base having \
+                     the same width as the output, and all the same component count",
+                );
+            }
+        } else if [
+            wk.OpFNegate,
+            wk.OpFAdd,
+            wk.OpFSub,
+            wk.OpFMul,
+            wk.OpFDiv,
+            wk.OpFRem,
+            wk.OpFMod,
+            wk.OpLogicalEqual,
+            wk.OpLogicalNotEqual,
+            wk.OpLogicalOr,
+            wk.OpLogicalAnd,
+            wk.OpLogicalNot,
+        ]
+        .contains(&opcode)
+        {
+            let expected_kind_is_float = ![
+                wk.OpLogicalEqual,
+                wk.OpLogicalNotEqual,
+                wk.OpLogicalOr,
+                wk.OpLogicalAnd,
+                wk.OpLogicalNot,
+            ]
+            .contains(&opcode);
+            let valid_output = match output_shape {
+                Some(ScalarShape {
+                    kind: ScalarKind::Float { .. },
+                    ..
+                }) => expected_kind_is_float,
+                Some(ScalarShape {
+                    kind: ScalarKind::Bool,
+                    ..
+                }) => !expected_kind_is_float,
+                _ => false,
+            };
+            match output_type {
+                Some(output_type) if valid_output => {
+                    for &input_type in input_types {
+                        expect_type("input", output_type, input_type);
+                    }
+                }
+                _ => {
+                    shape_error = Some(if expected_kind_is_float {
+                        "requires a floating-point output"
+                    } else {
+                        "requires a boolean output"
+                    });
+                }
+            }
+        } else if [
+            wk.OpIEqual,
+            wk.OpINotEqual,
+            wk.OpUGreaterThan,
+            wk.OpSGreaterThan,
+            wk.OpUGreaterThanEqual,
+            wk.OpSGreaterThanEqual,
+            wk.OpULessThan,
+            wk.OpSLessThan,
+            wk.OpULessThanEqual,
+            wk.OpSLessThanEqual,
+        ]
+        .contains(&opcode)
+        {
+            let valid = match (output_shape, &input_shapes[..]) {
+                (
+                    Some(ScalarShape {
+                        kind: ScalarKind::Bool,
+                        count,
+                    }),
+                    &[Some(a), b],
+                ) => matches!(a.kind, ScalarKind::Int { .. }) && a.count == count && b == Some(a),
+                _ => false,
+            };
+            if !valid {
+                shape_error = Some(
+                    "requires integer inputs with the same width, and a boolean \
+                     output, all with the same component count",
+                );
+            }
+        } else if [
+            wk.OpFOrdEqual,
+            wk.OpFUnordEqual,
+            wk.OpFOrdNotEqual,
+            wk.OpFUnordNotEqual,
+            wk.OpFOrdLessThan,
+            wk.OpFUnordLessThan,
+            wk.OpFOrdGreaterThan,
+            wk.OpFUnordGreaterThan,
+            wk.OpFOrdLessThanEqual,
+            wk.OpFUnordLessThanEqual,
+            wk.OpFOrdGreaterThanEqual,
+            wk.OpFUnordGreaterThanEqual,
+        ]
+        .contains(&opcode)
+        {
+            let valid = match (output_shape, &input_shapes[..]) {
+                (
+                    Some(ScalarShape {
+                        kind: ScalarKind::Bool,
+                        count,
+                    }),
+                    &[Some(a), _],
+                ) => matches!(a.kind, ScalarKind::Float { .. }) && a.count == count,
+                _ => false,
+            };
+            if valid {
+                expect_type("second input", input_types[0], input_types[1]);
+            } else {
+                shape_error = Some(
+                    "requires floating-point inputs, and a boolean output, \
+                     all with the same component count",
+                );
+            }
+        } else if opcode == wk.OpSelect {
+            match (output_type, input_types) {
+                (Some(output_type), &[cond, a, b]) => {
+                    let valid_cond = match ScalarShape::of(cx, cond) {
+                        Some(ScalarShape {
+                            kind: ScalarKind::Bool,
+                            count,
+                        }) => count == 1 || output_shape.is_some_and(|shape| shape.count == count),
+                        _ => false,
+                    };
+                    if !valid_cond {
+                        shape_error = Some(
+                            "requires a boolean condition, either scalar or with \
+                             the same component count as the output",
+                        );
+                    }
+                    expect_type("first case input", output_type, a);
+                    expect_type("second case input", output_type, b);
+                }
+                _ => shape_error = Some("requires a condition and two case inputs"),
+            }
+        } else if opcode == wk.OpCompositeExtract || opcode == wk.OpCompositeInsert {
+            let (composite_type, component) = if opcode == wk.OpCompositeExtract {
+                (input_types[0], output_type)
+            } else {
+                if let Some(output_type) = output_type {
+                    expect_type("composite input", output_type, input_types[1]);
+                }
+                (input_types[1], Some(input_types[0]))
+            };
+            let mut expected_component = Some(composite_type);
+            for imm in &spv_inst.imms {
+                let idx = match *imm {
+                    spv::Imm::Short(_, idx) => idx,
+                    spv::Imm::LongStart(..) | spv::Imm::LongCont(..) => {
+                        expected_component = None;
+                        break;
+                    }
+                };
+                expected_component =
+                    expected_component.and_then(|ty| component_type(cx, ty, Some(u64::from(idx))));
+            }
+            match (expected_component, component) {
+                (Some(expected), Some(found)) => expect_type("component", expected, found),
+                (None, _) => shape_error = Some("has indices out of bounds of the composite type"),
+                (Some(_), None) => {}
+            }
+        } else if opcode == wk.OpCompositeConstruct {
+            // NOTE(eddyb) the grammar check above ensures this is present.
+            let output_type = output_type.unwrap();
+            match spv::fold::vector_type(cx, output_type) {
+                Some((elem_type, count)) => {
+                    let mut total = 0;
+                    for &input_type in input_types {
+                        match spv::fold::vector_type(cx, input_type) {
+                            Some((input_elem_type, input_count)) => {
+                                expect_type("input component", elem_type, input_elem_type);
+                                total += input_count;
+                            }
+                            None => {
+                                expect_type("input", elem_type, input_type);
+                                total += 1;
+                            }
+                        }
+                    }
+                    if total != count {
+                        errors.push(format!(
+                            "`{name}` has {total} input components, \
+                             but its output has {count} components"
+                        ));
+                    }
+                }
+                None => match component_count(cx, output_type) {
+                    Some(count) if count == input_types.len() as u64 => {
+                        for (i, &input_type) in input_types.iter().enumerate() {
+                            if let Some(expected) = component_type(cx, output_type, Some(i as u64))
+                            {
+                                expect_type("input", expected, input_type);
+                            }
+                        }
+                    }
+                    Some(count) => errors.push(format!(
+                        "`{name}` has {} inputs, but its output has {count} components",
+                        input_types.len()
+                    )),
+                    None => shape_error = Some("requires a composite output"),
+                },
+            }
+        } else if opcode == wk.OpVectorShuffle {
+            let valid = match (output_type, input_types) {
+                (Some(output_type), &[a, b]) => {
+                    match (
+                        spv::fold::vector_type(cx, output_type),
+                        spv::fold::vector_type(cx, a),
+                        spv::fold::vector_type(cx, b),
+                    ) {
+                        (Some((elem, count)), Some((a_elem, _)), Some((b_elem, _))) => {
+                            expect_type("first input component", elem, a_elem);
+                            expect_type("second input component", elem, b_elem);
+                            count as usize == spv_inst.imms.len()
+                        }
+                        _ => false,
+                    }
+                }
+                _ => false,
+            };
+            if !valid {
+                shape_error = Some(
+                    "requires vector inputs, and a vector output with as many \
+                     components as there are component selectors",
+                );
+            }
+        } else if opcode == wk.OpLoad {
+            if let (Some(pointee), Some(output_type)) =
+                (input_types[0].pointee_type(cx), output_type)
+            {
+                expect_type("pointer input (pointee type)", output_type, pointee);
+            }
+        } else if opcode == wk.OpStore {
+            if let Some(pointee) = input_types[0].pointee_type(cx) {
+                expect_type("stored value", pointee, input_types[1]);
+            }
+        } else if opcode == wk.OpAccessChain || opcode == wk.OpInBoundsAccessChain {
+            let base_pointee = input_types[0].pointee_type(cx);
+            let output_pointee = output_type.and_then(|ty| ty.pointee_type(cx));
+            if let (Some(base_pointee), Some(output_pointee)) = (base_pointee, output_pointee) {
+                let mut expected_pointee = Some(base_pointee);
+                for &idx in &inst_def.inputs[1..] {
+                    let idx = match idx {
+                        Value::Const(ct) => match spv::fold::const_splat_value(cx, ct) {
+                            Some(spv::fold::ScalarValue::Int { bits, .. }) => Some(bits),
+                            _ => None,
+                        },
+                        _ => None,
+                    };
+                    expected_pointee = expected_pointee.and_then(|ty| component_type(cx, ty, idx));
+                }
+                match expected_pointee {
+                    Some(expected) => {
+                        expect_type("output (pointee type)", expected, output_pointee);
+                    }
+                    None => {
+                        shape_error = Some(
+                            "has indices out of bounds of the pointee type (or \
+                             non-constant indices into structs)",
+                        );
+                    }
+                }
+            }
+        } else if opcode == wk.OpVariable {
+            let output_pointee = output_type.and_then(|ty| ty.pointee_type(cx));
+            if let (Some(pointee), Some(&initializer_type)) = (output_pointee, input_types.first())
+            {
+                expect_type("initializer", pointee, initializer_type);
+            }
+        }
+
+        if let Some(shape_error) = shape_error {
+            errors.push(format!("`{name}` {shape_error}"));
+        }
+        errors.extend(type_mismatches);
+        errors
+    }
+
+    /// Returns `true` if `v` refers to an input/output that actually exists
+    /// (in this function, i.e. not in the body of some other function).
+    fn is_valid(&self, v: Value) -> bool {
+        let func_def_body = self.func_def_body;
+        match v {
+            Value::Const(_) => true,
+            Value::ControlRegionInput { region, input_idx } => func_def_body
+                .control_regions
+                .get(region)
+                .is_some_and(|region_def| (input_idx as usize) < region_def.inputs.len()),
+            Value::ControlNodeOutput {
+                control_node,
+                output_idx,
+            } => func_def_body
+                .control_nodes
+                .get(control_node)
+                .is_some_and(|node_def| (output_idx as usize) < node_def.outputs.len()),
+            Value::DataInstOutput(inst) => func_def_body
+                .data_insts
+                .get(inst)
+                .is_some_and(|inst_def| inst_def.output_type.is_some()),
+        }
+    }
+
+    /// Returns `true` if `v` refers to an entity defined in this function
+    /// (even if the input/output of that entity doesn't actually exist).
+    fn is_defined_in_this_func(&self, v: Value) -> bool {
+        let func_def_body = self.func_def_body;
+        match v {
+            Value::Const(_) => true,
+            Value::ControlRegionInput { region, .. } => {
+                func_def_body.control_regions.get(region).is_some()
+            }
+            Value::ControlNodeOutput { control_node, .. } => {
+                func_def_body.control_nodes.get(control_node).is_some()
+            }
+            Value::DataInstOutput(inst) => func_def_body.data_insts.get(inst).is_some(),
+        }
+    }
+
+    fn verify_use(&mut self, anchor: DiagnosticAnchor, v: Value) {
+        if matches!(v, Value::Const(_)) || self.in_scope.contains(&v) {
+            return;
+        }
+
+        let what = match v {
+            Value::Const(_) => unreachable!(),
+            Value::ControlRegionInput { input_idx, .. } => format!("region input #{input_idx}"),
+            Value::ControlNodeOutput { output_idx, .. } => {
+                format!("control node output #{output_idx}")
+            }
+            Value::DataInstOutput(_) => "instruction output".to_string(),
+        };
+        let message = if self.is_valid(v) {
+            format!("use of {what} not dominated by its definition")
+        } else if !self.is_defined_in_this_func(v) {
+            format!("use of {what} not defined in this function")
+        } else {
+            format!("use of nonexistent {what}")
+        };
+        self.report(anchor, message);
+    }
+}
+
+/// Check that the [`EntityList`](crate::EntityList) spanning `first..=last`
+/// is well-formed, given the `(prev, next)` links of each of its nodes.
+//
+// HACK(eddyb) this takes the list "unpacked", and the links through a closure,
+// to avoid depending on `EntityList` internals (and the entity traits).
+fn check_entity_list<E: Copy + Eq + Hash>(
+    first: Option<E>,
+    last: Option<E>,
+    links_of: impl Fn(E) -> (Option<E>, Option<E>),
+) -> Result<(), &'static str> {
+    let mut current = match (first, last) {
+        (Some(first), Some(_)) => first,
+        (None, None) => return Ok(()),
+        _ => return Err("only one of `first` and `last` present"),
+    };
+    if links_of(current).0.is_some() {
+        return Err("`first->prev != None`");
+    }
+
+    let mut seen = FxHashSet::default();
+    loop {
+        if !seen.insert(current) {
+            return Err("cycle in `next` links");
+        }
+        match links_of(current).1 {
+            Some(next) => {
+                if links_of(next).0 != Some(current) {
+                    return Err("`node->next->prev != node`");
+                }
+                current = next;
+            }
+            None => {
+                return if Some(current) == last {
+                    Ok(())
+                } else {
+                    Err("`first->next->...->next != last`")
+                };
+            }
+        }
+    }
+}
+
+/// Scalar "shape" of a SPIR-V scalar or vector type, i.e. the kind of its
+/// scalar components (ignoring integer signedness), and their count.
+#[derive(Copy, Clone, PartialEq, Eq)]
+struct ScalarShape {
+    kind: ScalarKind,
+
+    /// Component count (always `1` for scalar types).
+    count: u32,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum ScalarKind {
+    Bool,
+    Int { width: u32 },
+    Float { width: u32 },
+}
+
+impl ScalarShape {
+    fn of(cx: &Context, ty: Type) -> Option<Self> {
+        let wk = &spv::spec::Spec::get().well_known;
+
+        if let Some((elem_type, count)) = spv::fold::vector_type(cx, ty) {
+            return Some(Self {
+                count,
+                ..Self::of(cx, elem_type)?
+            });
+        }
+        let kind = match &cx[ty].ctor {
+            TypeCtor::SpvInst(inst) if inst.opcode == wk.OpTypeBool => ScalarKind::Bool,
+            TypeCtor::SpvInst(inst) if inst.opcode == wk.OpTypeInt => match inst.imms[..] {
+                [spv::Imm::Short(_, width), ..] => ScalarKind::Int { width },
+                _ => return None,
+            },
+            TypeCtor::SpvInst(inst) if inst.opcode == wk.OpTypeFloat => match inst.imms[..] {
+                [spv::Imm::Short(_, width), ..] => ScalarKind::Float { width },
+                _ => return None,
+            },
+            _ => return None,
+        };
+        Some(Self { kind, count: 1 })
+    }
+}
+
+/// Get the number of components of the composite type `ty` (if it has a
+/// statically known number of components).
+fn component_count(cx: &Context, ty: Type) -> Option<u64> {
+    let ty_def = &cx[ty];
+    match (&ty_def.ctor, &ty_def.ctor_args[..]) {
+        (&TypeCtor::Matrix { column_count }, _) => Some(column_count.into()),
+        (TypeCtor::Array, &[_, TypeCtorArg::Const(len)]) => {
+            match spv::fold::const_splat_value(cx, len)? {
+                spv::fold::ScalarValue::Int { bits, .. } => Some(bits),
+                _ => None,
+            }
+        }
+        (TypeCtor::Struct { members }, _) => Some(members.len() as u64),
+        _ => spv::fold::vector_type(cx, ty).map(|(_, count)| count.into()),
+    }
+}
+
+/// Get the type of the `idx`th component of the composite type `ty`, where
+/// `idx` is `None` for dynamic indices (which aren't allowed for structs).
+///
+/// Returns `None` if `ty` isn't a composite type, or `idx` is out of bounds.
+fn component_type(cx: &Context, ty: Type, idx: Option<u64>) -> Option<Type> {
+    let ty_def = &cx[ty];
+    let elem_type = match (&ty_def.ctor, &ty_def.ctor_args[..]) {
+        (TypeCtor::Struct { .. }, member_types) => {
+            return match member_types.get(usize::try_from(idx?).ok()?)? {
+                &TypeCtorArg::Type(member_type) => Some(member_type),
+                TypeCtorArg::Const(_) => None,
+            };
+        }
+        (TypeCtor::RuntimeArray, &[TypeCtorArg::Type(elem_type)]) => return Some(elem_type),
+
+        (TypeCtor::Matrix { .. } | TypeCtor::Array, &[TypeCtorArg::Type(elem_type), ..]) => {
+            elem_type
+        }
+        _ => spv::fold::vector_type(cx, ty)?.0,
+    };
+    match (idx, component_count(cx, ty)) {
+        (Some(idx), Some(count)) if idx >= count => None,
+        _ => Some(elem_type),
+    }
+}
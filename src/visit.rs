@@ -1,12 +1,13 @@
 //! Immutable IR traversal.
 
 use crate::func_at::FuncAt;
+use crate::qptr::{QPtrAttr, QPtrOp};
 use crate::{
     cfg, spv, AddrSpace, Attr, AttrSet, AttrSetDef, Const, ConstCtor, ConstDef, ControlNode,
     ControlNodeDef, ControlNodeKind, ControlNodeOutputDecl, ControlRegion, ControlRegionDef,
     ControlRegionInputDecl, DataInstDef, DataInstKind, DeclDef, EntityListIter, ExportKey,
     Exportee, Func, FuncDecl, FuncDefBody, FuncParam, GlobalVar, GlobalVarDecl, GlobalVarDefBody,
-    Import, Module, ModuleDebugInfo, ModuleDialect, SelectionKind, Type, TypeCtor, TypeCtorArg,
+    Import, Module, ModuleDebugInfo, ModuleDialect, OrdAssertEq, SelectionKind, Type, TypeCtor, TypeCtorArg,
     TypeDef, Value,
 };
 
@@ -27,7 +28,6 @@ pub trait Visitor<'a>: Sized {
     // Leaves (noop default behavior).
     fn visit_spv_dialect(&mut self, _dialect: &spv::Dialect) {}
     fn visit_spv_module_debug_info(&mut self, _debug_info: &spv::ModuleDebugInfo) {}
-    fn visit_attr(&mut self, _attr: &Attr) {}
     fn visit_import(&mut self, _import: &Import) {}
 
     // Non-leaves (defaulting to calling `.inner_visit_with(self)`).
@@ -43,6 +43,9 @@ pub trait Visitor<'a>: Sized {
     fn visit_attr_set_def(&mut self, attrs_def: &'a AttrSetDef) {
         attrs_def.inner_visit_with(self);
     }
+    fn visit_attr(&mut self, attr: &'a Attr) {
+        attr.inner_visit_with(self);
+    }
     fn visit_type_def(&mut self, ty_def: &'a TypeDef) {
         ty_def.inner_visit_with(self);
     }
@@ -239,6 +242,49 @@ impl InnerVisit for AttrSetDef {
     }
 }
 
+impl InnerVisit for Attr {
+    fn inner_visit_with<'a>(&'a self, visitor: &mut impl Visitor<'a>) {
+        match self {
+            Attr::SpvAnnotation(_)
+            | Attr::SpvDebugLine { .. }
+            | Attr::SpvBitflagsOperand(_)
+            | Attr::SpvOriginalId(_)
+            | Attr::SpvUnsupported(_)
+            | Attr::Diagnostic { .. } => {}
+
+            Attr::SpvExecutionModeId { inst: _, consts } => {
+                for &OrdAssertEq(ct) in consts {
+                    visitor.visit_const_use(ct);
+                }
+            }
+            &Attr::SpvShaderDebugScope {
+                scope: OrdAssertEq(scope),
+                inlined_at,
+            } => {
+                visitor.visit_const_use(scope);
+                if let Some(OrdAssertEq(inlined_at)) = inlined_at {
+                    visitor.visit_const_use(inlined_at);
+                }
+            }
+            &Attr::SpvShaderDebugLine {
+                source: OrdAssertEq(source),
+                ..
+            } => visitor.visit_const_use(source),
+
+            &Attr::QPtr(
+                QPtrAttr::FromSpvPtr {
+                    pointee: OrdAssertEq(pointee),
+                    ..
+                }
+                | QPtrAttr::ToSpvPtrInput {
+                    pointee: OrdAssertEq(pointee),
+                    ..
+                },
+            ) => visitor.visit_type_use(pointee),
+        }
+    }
+}
+
 impl InnerVisit for TypeDef {
     fn inner_visit_with<'a>(&'a self, visitor: &mut impl Visitor<'a>) {
         let Self {
@@ -249,7 +295,22 @@ impl InnerVisit for TypeDef {
 
         visitor.visit_attr_set_use(*attrs);
         match ctor {
-            TypeCtor::SpvInst(_) | TypeCtor::SpvStringLiteralForExtInst => {}
+            TypeCtor::SpvInst(_)
+            | TypeCtor::SpvStringLiteralForExtInst
+            | TypeCtor::Matrix { .. }
+            | TypeCtor::Array
+            | TypeCtor::RuntimeArray
+            | TypeCtor::RecursivePtr { .. }
+            | TypeCtor::RecursivePtrSelf { .. }
+            | TypeCtor::QPtr
+            | TypeCtor::Image(_)
+            | TypeCtor::Sampler
+            | TypeCtor::SampledImage => {}
+            TypeCtor::Struct { members } => {
+                for member in members {
+                    visitor.visit_attr_set_use(member.attrs);
+                }
+            }
         }
         for &arg in ctor_args {
             match arg {
@@ -273,7 +334,13 @@ impl InnerVisit for ConstDef {
         visitor.visit_type_use(*ty);
         match *ctor {
             ConstCtor::PtrToGlobalVar(gv) => visitor.visit_global_var_use(gv),
-            ConstCtor::SpvInst(_) | ConstCtor::SpvStringLiteralForExtInst(_) => {}
+            ConstCtor::Undef
+            | ConstCtor::SpvInst(_)
+            | ConstCtor::SpecConst { .. }
+            | ConstCtor::SpecConstOp(_)
+            | ConstCtor::SpvExtInst { .. }
+            | ConstCtor::SpvStringLiteralForExtInst(_)
+            | ConstCtor::SpvTypeOperand => {}
         }
         for &ct in ctor_args {
             visitor.visit_const_use(ct);
@@ -302,7 +369,7 @@ impl InnerVisit for GlobalVarDecl {
         visitor.visit_attr_set_use(*attrs);
         visitor.visit_type_use(*type_of_ptr_to);
         match addr_space {
-            AddrSpace::SpvStorageClass(_) => {}
+            AddrSpace::SpvStorageClass(_) | AddrSpace::PhysicalStorageBuffer => {}
         }
         def.inner_visit_with(visitor);
     }
@@ -404,9 +471,14 @@ impl<'a> FuncAt<'a, EntityListIter<ControlNode>> {
 // FIXME(eddyb) this can't implement `InnerVisit` because of the `&'a self`
 // requirement, whereas this has `'a` in `self: FuncAt<'a, ControlNode>`.
 impl<'a> FuncAt<'a, ControlNode> {
-    fn inner_visit_with(self, visitor: &mut impl Visitor<'a>) {
-        let ControlNodeDef { kind, outputs } = self.def();
+    pub fn inner_visit_with(self, visitor: &mut impl Visitor<'a>) {
+        let ControlNodeDef {
+            attrs,
+            kind,
+            outputs,
+        } = self.def();
 
+        visitor.visit_attr_set_use(*attrs);
         match kind {
             ControlNodeKind::Block { insts } => {
                 for func_at_inst in self.at(*insts) {
@@ -434,6 +506,14 @@ impl<'a> FuncAt<'a, ControlNode> {
                 visitor.visit_control_region_def(self.at(*body));
                 visitor.visit_value_use(repeat_condition);
             }
+            ControlNodeKind::ExitInvocation {
+                kind: cfg::ExitInvocationKind::SpvInst(_) | cfg::ExitInvocationKind::EmitMeshTasks,
+                inputs,
+            } => {
+                for v in inputs {
+                    visitor.visit_value_use(v);
+                }
+            }
         }
         for output in outputs {
             output.inner_visit_with(visitor);
@@ -462,7 +542,14 @@ impl InnerVisit for DataInstDef {
         visitor.visit_attr_set_use(*attrs);
         match *kind {
             DataInstKind::FuncCall(func) => visitor.visit_func_use(func),
-            DataInstKind::SpvInst(_) | DataInstKind::SpvExtInst { .. } => {}
+            DataInstKind::SpvInst(_)
+            | DataInstKind::SpvExtInst { .. }
+            | DataInstKind::SpvGlslStd450(_)
+            | DataInstKind::Atomic { .. }
+            | DataInstKind::Barrier { .. }
+            | DataInstKind::Group { .. }
+            | DataInstKind::QPtr(QPtrOp::Load | QPtrOp::Store) => {}
+            DataInstKind::QPtr(QPtrOp::Offset { pointee }) => visitor.visit_type_use(pointee),
         }
         if let Some(ty) = *output_type {
             visitor.visit_type_use(ty);
@@ -487,7 +574,9 @@ impl InnerVisit for cfg::ControlInst {
         match kind {
             cfg::ControlInstKind::Unreachable
             | cfg::ControlInstKind::Return
-            | cfg::ControlInstKind::ExitInvocation(cfg::ExitInvocationKind::SpvInst(_))
+            | cfg::ControlInstKind::ExitInvocation(
+                cfg::ExitInvocationKind::SpvInst(_) | cfg::ExitInvocationKind::EmitMeshTasks,
+            )
             | cfg::ControlInstKind::Branch
             | cfg::ControlInstKind::SelectBranch(
                 SelectionKind::BoolCond | SelectionKind::SpvInst(_),
@@ -0,0 +1,74 @@
+//! Caching modules (see `Module::serialize_cache`), and reading the caches back.
+
+#![cfg(feature = "serde")]
+
+mod common;
+
+use common::mesh_shading_module_words;
+use spirt::passes::legalize::structurize_func_cfgs;
+use spirt::print::Plan;
+use spirt::{Context, Module};
+use std::rc::Rc;
+
+fn lower_and_structurize(cx: Rc<Context>) -> Module {
+    let mut module = Module::lower_from_spv_words(cx, mesh_shading_module_words()).unwrap();
+    structurize_func_cfgs(&mut module);
+    module
+}
+
+#[test]
+fn cache_roundtrips_across_contexts() {
+    let module = lower_and_structurize(Rc::new(Context::new()));
+    let printed = Plan::for_module(&module).pretty_print().to_string();
+    let cache = module.serialize_cache();
+
+    // Reading the cache back into an unrelated `Context` (with other types and
+    // constants already interned) must not affect the module.
+    let cx = Rc::new(Context::new());
+    lower_and_structurize(cx.clone());
+    let deserialized = Module::deserialize_cache(cx, &cache).unwrap();
+    assert_eq!(
+        printed,
+        Plan::for_module(&deserialized).pretty_print().to_string()
+    );
+    assert_eq!(cache, deserialized.serialize_cache());
+}
+
+#[test]
+fn malformed_caches_are_rejected() {
+    let cache = lower_and_structurize(Rc::new(Context::new())).serialize_cache();
+    let deserialize_err = |cache: &[u8]| {
+        Module::deserialize_cache(Rc::new(Context::new()), cache)
+            .err()
+            .unwrap()
+            .to_string()
+    };
+
+    assert_eq!(
+        deserialize_err(&cache[1..]),
+        "malformed SPIR-T cache (missing header)"
+    );
+
+    // The format version (LEB128-encoded) follows the 8 bytes of magic.
+    let mut other_version = cache.clone();
+    other_version[8] += 1;
+    let err = deserialize_err(&other_version);
+    assert!(
+        err.contains("unsupported format version"),
+        "unexpected error: {err}"
+    );
+
+    let err = deserialize_err(&cache[..cache.len() - 1]);
+    assert!(
+        err.starts_with("malformed SPIR-T cache"),
+        "unexpected error: {err}"
+    );
+
+    let mut trailing = cache.clone();
+    trailing.push(0);
+    let err = deserialize_err(&trailing);
+    assert!(
+        err.starts_with("malformed SPIR-T cache"),
+        "unexpected error: {err}"
+    );
+}
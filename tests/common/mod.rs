@@ -0,0 +1,180 @@
+//! Helpers shared between tests (each `tests/*.rs` file only uses some of them).
+#![allow(dead_code)]
+
+use spirt::spv::spec::{self, Spec};
+
+/// Encode `s` as a SPIR-V literal string (nul-terminated, padded to whole words).
+pub fn str_words(s: &str) -> Vec<u32> {
+    let mut bytes = s.as_bytes().to_vec();
+    bytes.push(0);
+    bytes.resize(bytes.len().next_multiple_of(4), 0);
+    bytes
+        .chunks(4)
+        .map(|chunk| u32::from_le_bytes(chunk.try_into().unwrap()))
+        .collect()
+}
+
+/// Minimal SPIR-V "assembler", taking instructions as their opcode name and
+/// already-encoded operands (including any result type and result IDs).
+#[derive(Default)]
+pub struct Assembler {
+    insts: Vec<u32>,
+    bound: u32,
+}
+
+impl Assembler {
+    pub fn inst(&mut self, opcode_name: &str, operands: impl IntoIterator<Item = u32>) {
+        let opcode = Spec::get().instructions.lookup(opcode_name).unwrap();
+        let operands: Vec<_> = operands.into_iter().collect();
+        let word_count = u32::try_from(1 + operands.len()).unwrap();
+        self.insts
+            .push((word_count << 16) | u32::from(opcode.as_u16()));
+        self.insts.extend(operands);
+    }
+
+    pub fn id(&mut self) -> u32 {
+        self.bound += 1;
+        self.bound
+    }
+
+    pub fn finish(self) -> Vec<u32> {
+        let header: [u32; spec::HEADER_LEN] =
+            [Spec::get().magic, 0x0001_0000, 0, self.bound + 1, 0];
+        header.into_iter().chain(self.insts).collect()
+    }
+}
+
+/// Build a compute shader storing a constant array with `len` elements (e.g.
+/// for testing how large constant aggregates are printed).
+pub fn large_const_array_module_words(len: u32) -> Vec<u32> {
+    let mut asm = Assembler::default();
+    let [void, u32, c_len, arr, ptr_arr, var] = [(); 6].map(|()| asm.id());
+    let [fn_void, main, main_entry] = [(); 3].map(|()| asm.id());
+
+    // `Shader` capability, and `Logical` addressing with `GLSL450` memory model.
+    asm.inst("OpCapability", [1]);
+    asm.inst("OpMemoryModel", [0, 1]);
+    // `GLCompute` execution model, with `LocalSize 1 1 1`.
+    asm.inst(
+        "OpEntryPoint",
+        [[5, main].as_slice(), &str_words("main")].concat(),
+    );
+    asm.inst("OpExecutionMode", [main, 17, 1, 1, 1]);
+
+    asm.inst("OpTypeVoid", [void]);
+    asm.inst("OpTypeInt", [u32, 32, 0]);
+    asm.inst("OpConstant", [u32, c_len, len]);
+    asm.inst("OpTypeArray", [arr, u32, c_len]);
+    // `Private` storage class.
+    asm.inst("OpTypePointer", [ptr_arr, 6, arr]);
+    let elems: Vec<_> = (0..len)
+        .map(|i| {
+            let ct = asm.id();
+            asm.inst("OpConstant", [u32, ct, i * 3]);
+            ct
+        })
+        .collect();
+    let c_arr = asm.id();
+    asm.inst(
+        "OpConstantComposite",
+        [[arr, c_arr].as_slice(), &elems].concat(),
+    );
+    asm.inst("OpVariable", [ptr_arr, var, 6]);
+    asm.inst("OpTypeFunction", [fn_void, void]);
+
+    asm.inst("OpFunction", [void, main, 0, fn_void]);
+    asm.inst("OpLabel", [main_entry]);
+    asm.inst("OpStore", [var, c_arr]);
+    asm.inst("OpReturn", []);
+    asm.inst("OpFunctionEnd", []);
+
+    asm.finish()
+}
+
+/// Encode SPIR-V `words` as bytes (e.g. for writing to a `.spv` file).
+pub fn words_to_bytes(words: &[u32]) -> Vec<u8> {
+    words.iter().flat_map(|w| w.to_le_bytes()).collect()
+}
+
+/// Build a task shader (`"task"`) launching mesh shader (`"mesh"`) workgroups
+/// through a shared payload, with the mesh shader writing out one triangle,
+/// along with a `PerPrimitiveEXT` output (which doesn't have a `Location`).
+pub fn mesh_shading_module_words() -> Vec<u32> {
+    let mut asm = Assembler::default();
+    let [void, fn_void, u32, uvec3, c0, c1, c3, c_tri] = [(); 8].map(|()| asm.id());
+    let [u32_x1, uvec3_x1, ptr_payload, ptr_out_u32, ptr_out_uvec3] = [(); 5].map(|()| asm.id());
+    let [ptr_out_u32_x1, ptr_out_uvec3_x1] = [(); 2].map(|()| asm.id());
+    let [payload, indices, prim_id] = [(); 3].map(|()| asm.id());
+    let [task, task_entry, mesh, mesh_entry] = [(); 4].map(|()| asm.id());
+    let [payload_value, indices_0, prim_id_0] = [(); 3].map(|()| asm.id());
+
+    // `Shader` and `MeshShadingEXT` capabilities.
+    asm.inst("OpCapability", [1]);
+    asm.inst("OpCapability", [5283]);
+    asm.inst("OpExtension", str_words("SPV_EXT_mesh_shader"));
+    asm.inst("OpMemoryModel", [0, 1]);
+    // `TaskEXT` and `MeshEXT` execution models.
+    asm.inst(
+        "OpEntryPoint",
+        [[5364, task].as_slice(), &str_words("task"), &[payload]].concat(),
+    );
+    asm.inst(
+        "OpEntryPoint",
+        [
+            [5365, mesh].as_slice(),
+            &str_words("mesh"),
+            &[payload, indices, prim_id],
+        ]
+        .concat(),
+    );
+    // `LocalSize 1 1 1` for both, and `OutputVertices 3`, `OutputPrimitivesEXT 1`
+    // and `OutputTrianglesEXT` for the mesh shader.
+    asm.inst("OpExecutionMode", [task, 17, 1, 1, 1]);
+    asm.inst("OpExecutionMode", [mesh, 17, 1, 1, 1]);
+    asm.inst("OpExecutionMode", [mesh, 26, 3]);
+    asm.inst("OpExecutionMode", [mesh, 5270, 1]);
+    asm.inst("OpExecutionMode", [mesh, 5298]);
+
+    // `BuiltIn PrimitiveTriangleIndicesEXT`, and `PerPrimitiveEXT`.
+    asm.inst("OpDecorate", [indices, 11, 5296]);
+    asm.inst("OpDecorate", [prim_id, 5271]);
+
+    asm.inst("OpTypeVoid", [void]);
+    asm.inst("OpTypeFunction", [fn_void, void]);
+    asm.inst("OpTypeInt", [u32, 32, 0]);
+    asm.inst("OpTypeVector", [uvec3, u32, 3]);
+    asm.inst("OpConstant", [u32, c0, 0]);
+    asm.inst("OpConstant", [u32, c1, 1]);
+    asm.inst("OpConstant", [u32, c3, 3]);
+    asm.inst("OpConstantComposite", [uvec3, c_tri, c0, c1, c3]);
+    asm.inst("OpTypeArray", [u32_x1, u32, c1]);
+    asm.inst("OpTypeArray", [uvec3_x1, uvec3, c1]);
+    // `TaskPayloadWorkgroupEXT` and `Output` storage classes.
+    asm.inst("OpTypePointer", [ptr_payload, 5402, u32]);
+    asm.inst("OpTypePointer", [ptr_out_u32, 3, u32]);
+    asm.inst("OpTypePointer", [ptr_out_uvec3, 3, uvec3]);
+    asm.inst("OpTypePointer", [ptr_out_u32_x1, 3, u32_x1]);
+    asm.inst("OpTypePointer", [ptr_out_uvec3_x1, 3, uvec3_x1]);
+    asm.inst("OpVariable", [ptr_payload, payload, 5402]);
+    asm.inst("OpVariable", [ptr_out_uvec3_x1, indices, 3]);
+    asm.inst("OpVariable", [ptr_out_u32_x1, prim_id, 3]);
+
+    asm.inst("OpFunction", [void, task, 0, fn_void]);
+    asm.inst("OpLabel", [task_entry]);
+    asm.inst("OpStore", [payload, c3]);
+    asm.inst("OpEmitMeshTasksEXT", [c1, c1, c1, payload]);
+    asm.inst("OpFunctionEnd", []);
+
+    asm.inst("OpFunction", [void, mesh, 0, fn_void]);
+    asm.inst("OpLabel", [mesh_entry]);
+    asm.inst("OpSetMeshOutputsEXT", [c3, c1]);
+    asm.inst("OpLoad", [u32, payload_value, payload]);
+    asm.inst("OpAccessChain", [ptr_out_uvec3, indices_0, indices, c0]);
+    asm.inst("OpStore", [indices_0, c_tri]);
+    asm.inst("OpAccessChain", [ptr_out_u32, prim_id_0, prim_id, c0]);
+    asm.inst("OpStore", [prim_id_0, payload_value]);
+    asm.inst("OpReturn", []);
+    asm.inst("OpFunctionEnd", []);
+
+    asm.finish()
+}
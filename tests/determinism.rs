@@ -0,0 +1,96 @@
+//! Printing the same module must always produce the same output, regardless of
+//! which `Context` it was lowered into (or what else was interned before it).
+
+mod common;
+
+use common::{str_words, Assembler};
+use spirt::print::Plan;
+use spirt::{Context, Module};
+use std::rc::Rc;
+
+/// Build a small (but not trivial) SPIR-V module, with several definitions of
+/// each kind (types, constants, global variables and functions), and names.
+fn test_module_words() -> Vec<u32> {
+    let mut asm = Assembler::default();
+    let [void, f32, u32, v4, ptr_out, out] = [(); 6].map(|()| asm.id());
+    let [c1, c2, c3, vc] = [(); 4].map(|()| asm.id());
+    let [fn_void, fn_helper, helper, x, helper_entry, y, z] = [(); 7].map(|()| asm.id());
+    let [main, main_entry, r] = [(); 3].map(|()| asm.id());
+
+    // `Shader` capability, and `Logical` addressing with `GLSL450` memory model.
+    asm.inst("OpCapability", [1]);
+    asm.inst("OpMemoryModel", [0, 1]);
+    // `Fragment` execution model, with `OriginUpperLeft`.
+    asm.inst(
+        "OpEntryPoint",
+        [[4, main].as_slice(), &str_words("main"), &[out]].concat(),
+    );
+    asm.inst("OpExecutionMode", [main, 7]);
+    for (target, name) in [(main, "main"), (helper, "helper"), (out, "out"), (x, "x")] {
+        asm.inst("OpName", [[target].as_slice(), &str_words(name)].concat());
+    }
+    // `Location 0`.
+    asm.inst("OpDecorate", [out, 30, 0]);
+
+    asm.inst("OpTypeVoid", [void]);
+    asm.inst("OpTypeFloat", [f32, 32]);
+    asm.inst("OpTypeInt", [u32, 32, 0]);
+    asm.inst("OpTypeVector", [v4, f32, 4]);
+    // `Output` storage class.
+    asm.inst("OpTypePointer", [ptr_out, 3, v4]);
+    asm.inst("OpVariable", [ptr_out, out, 3]);
+    asm.inst("OpConstant", [f32, c1, 1.0f32.to_bits()]);
+    asm.inst("OpConstant", [f32, c2, 2.0f32.to_bits()]);
+    asm.inst("OpConstant", [u32, c3, 3]);
+    asm.inst("OpConstantComposite", [v4, vc, c1, c2, c1, c2]);
+    asm.inst("OpTypeFunction", [fn_void, void]);
+    asm.inst("OpTypeFunction", [fn_helper, v4, v4]);
+
+    asm.inst("OpFunction", [v4, helper, 0, fn_helper]);
+    asm.inst("OpFunctionParameter", [v4, x]);
+    asm.inst("OpLabel", [helper_entry]);
+    asm.inst("OpFAdd", [v4, y, x, vc]);
+    asm.inst("OpFMul", [v4, z, y, x]);
+    asm.inst("OpReturnValue", [z]);
+    asm.inst("OpFunctionEnd", []);
+
+    asm.inst("OpFunction", [void, main, 0, fn_void]);
+    asm.inst("OpLabel", [main_entry]);
+    asm.inst("OpFunctionCall", [v4, r, helper, vc]);
+    asm.inst("OpStore", [out, r]);
+    asm.inst("OpReturn", []);
+    asm.inst("OpFunctionEnd", []);
+
+    asm.finish()
+}
+
+fn lower_and_print(cx: Rc<Context>) -> String {
+    let module = Module::lower_from_spv_words(cx, test_module_words()).unwrap();
+    Plan::for_module(&module).pretty_print().to_string()
+}
+
+#[test]
+fn print_is_deterministic_across_contexts() {
+    let first = lower_and_print(Rc::new(Context::new()));
+    let second = lower_and_print(Rc::new(Context::new()));
+    assert_eq!(first, second);
+
+    // Interning other definitions first (by lowering another module, with
+    // its own, unrelated, types and constants) shifts all interned handles.
+    let cx = Rc::new(Context::new());
+    let mut asm = Assembler::default();
+    let [u32, u64] = [(); 2].map(|()| asm.id());
+    // `Shader` and `Int64` capabilities.
+    asm.inst("OpCapability", [1]);
+    asm.inst("OpCapability", [11]);
+    asm.inst("OpMemoryModel", [0, 1]);
+    asm.inst("OpTypeInt", [u64, 64, 0]);
+    asm.inst("OpTypeInt", [u32, 32, 0]);
+    for value in [7, 3, 5] {
+        let ct = asm.id();
+        asm.inst("OpConstant", [u32, ct, value]);
+    }
+    Module::lower_from_spv_words(cx.clone(), asm.finish()).unwrap();
+    let shifted = lower_and_print(cx);
+    assert_eq!(first, shifted);
+}
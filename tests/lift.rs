@@ -0,0 +1,94 @@
+//! Lifting modules to SPIR-V (buffered in memory, or streamed to a writer).
+
+mod common;
+
+use common::{large_const_array_module_words, str_words, words_to_bytes};
+use spirt::spv::lift::LiftOptions;
+use spirt::spv::spec::{self, Spec};
+use spirt::{Context, Module, ModuleDialect};
+use std::io::{self, Cursor};
+use std::rc::Rc;
+
+/// Lower a module which declares an extension it doesn't actually need.
+fn module_with_stale_extension(cx: Rc<Context>) -> Module {
+    let mut words = large_const_array_module_words(4);
+
+    // Insert `OpExtension` right after the (only) `OpCapability`.
+    let ext = str_words("SPV_KHR_terminate_invocation");
+    let opcode = Spec::get().instructions.lookup("OpExtension").unwrap();
+    let ext_inst = [(u32::try_from(1 + ext.len()).unwrap() << 16) | u32::from(opcode.as_u16())]
+        .into_iter()
+        .chain(ext);
+    let after_capability = spec::HEADER_LEN + 2;
+    words.splice(after_capability..after_capability, ext_inst);
+
+    let module = Module::lower_from_spv_words(cx, words).unwrap();
+    let ModuleDialect::Spv(dialect) = &module.dialect;
+    assert!(dialect.extensions.contains("SPV_KHR_terminate_invocation"));
+    module
+}
+
+fn lift_buffered(module: &Module, options: &LiftOptions) -> Vec<u8> {
+    let (emitter, _) = module
+        .lift_to_spv_module_emitter_with_options(options)
+        .unwrap();
+    words_to_bytes(&emitter.words)
+}
+
+fn lift_streaming(module: &Module, options: &LiftOptions) -> io::Result<Vec<u8>> {
+    Ok(module
+        .lift_to_spv_writer(Cursor::new(vec![]), options)?
+        .into_inner())
+}
+
+#[test]
+fn streaming_with_default_options_matches_buffered() {
+    let cx = Rc::new(Context::new());
+    let module = module_with_stale_extension(cx.clone());
+
+    let options = LiftOptions::default();
+    let streamed = lift_streaming(&module, &options).unwrap();
+    assert!(streamed == lift_buffered(&module, &options));
+
+    // The stale extension shouldn't have been declared by the streamed module.
+    let relowered = Module::lower_from_spv_bytes(cx, streamed).unwrap();
+    let ModuleDialect::Spv(dialect) = &relowered.dialect;
+    assert!(dialect.extensions.is_empty());
+}
+
+#[test]
+fn streaming_with_minimal_capabilities_matches_buffered() {
+    let cx = Rc::new(Context::new());
+    let module = module_with_stale_extension(cx);
+
+    for preserve_declared_extensions in [false, true] {
+        let options = LiftOptions {
+            minimize_capabilities: true,
+            preserve_declared_extensions,
+            ..LiftOptions::default()
+        };
+        let streamed = lift_streaming(&module, &options).unwrap();
+        assert!(streamed == lift_buffered(&module, &options));
+    }
+}
+
+#[test]
+fn streaming_rejects_renumbering_ids() {
+    let cx = Rc::new(Context::new());
+    let module = module_with_stale_extension(cx);
+
+    for options in [
+        LiftOptions {
+            ids_in_emission_order: true,
+            ..LiftOptions::default()
+        },
+        LiftOptions {
+            reuse_original_ids: true,
+            ..LiftOptions::default()
+        },
+    ] {
+        let err = lift_streaming(&module, &options).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(err.to_string().contains("not supported when streaming"));
+    }
+}
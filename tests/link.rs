@@ -0,0 +1,209 @@
+//! Linking modules together (see `spirt::passes::link`).
+
+use spirt::passes::link::{
+    link_modules, prefix_link_names, DuplicateExports, LinkDiagnostic, LinkOptions,
+};
+use spirt::print::Plan;
+use spirt::testing::check_output;
+use spirt::{Context, Module};
+use std::rc::Rc;
+
+/// Parse `defs` (SPIR-T text, e.g. function definitions and their exports),
+/// into a `Shader` module in `cx` (also declaring the `Linkage` capability).
+fn shader_module(cx: &Rc<Context>, defs: &str) -> Module {
+    let dialect = r#"
+        module.dialect = SPIR-V {
+          version: 1.3,
+          extensions: {},
+          capabilities: {spv.Capability.Shader, spv.Capability.Linkage},
+          addressing_model: spv.AddressingModel.Logical,
+          memory_model: spv.MemoryModel.GLSL450,
+        }
+    "#;
+    spirt::ir!(cx, &(dialect.to_string() + defs))
+}
+
+/// Module exporting `f`, which calls the imported `g`.
+fn caller_module(cx: &Rc<Context>) -> Module {
+    shader_module(
+        cx,
+        r#"
+        func0(v0: s32) -> s32 = import "g"
+
+        func1(v0: s32) -> s32 {
+          v1 = call func0(v0): s32
+          v1
+        }
+
+        export { "f": func1 }
+        "#,
+    )
+}
+
+/// Module exporting `g`, which multiplies its parameter by `factor`, with
+/// `debuginfo` (SPIR-T text, e.g. a `// at ...` comment, or empty) on the
+/// multiplication.
+fn callee_module(cx: &Rc<Context>, factor: &str, debuginfo: &str) -> Module {
+    shader_module(
+        cx,
+        &format!(
+            r#"
+        func0(v0: s32) -> s32 {{
+          {debuginfo}
+          v1 = spv.OpIMul(v0, {factor}): s32
+          v1
+        }}
+
+        export {{ "g": func0 }}
+        "#
+        ),
+    )
+}
+
+fn diagnostic_messages(diagnostics: &[LinkDiagnostic]) -> Vec<String> {
+    diagnostics.iter().map(|diag| diag.to_string()).collect()
+}
+
+#[test]
+fn link_resolves_imports_across_modules() {
+    let cx = Rc::new(Context::new());
+    let linked = link_modules(
+        vec![caller_module(&cx), callee_module(&cx, "3s32", "")],
+        &LinkOptions::default(),
+    )
+    .unwrap();
+
+    check_output(
+        &Plan::for_module(&linked).pretty_print().to_string(),
+        r#"
+        CHECK-NOT: import
+        CHECK: func0(v0: s32) -> s32 {
+        CHECK-NEXT: v1 = spv.OpIMul(v0, 3s32): s32
+        CHECK: func1(v0: s32) -> s32 {
+        CHECK-NEXT: v1 = call func0(v0): s32
+        CHECK: "f": func1,
+        CHECK-NEXT: "g": func0,
+        "#,
+    )
+    .unwrap();
+}
+
+#[test]
+fn link_reports_unresolved_imports() {
+    let cx = Rc::new(Context::new());
+    let err = link_modules(vec![caller_module(&cx)], &LinkOptions::default())
+        .err()
+        .unwrap();
+    assert_eq!(
+        diagnostic_messages(&err.diagnostics),
+        ["unresolved import `g`"]
+    );
+
+    let linked = link_modules(
+        vec![caller_module(&cx)],
+        &LinkOptions {
+            allow_unresolved_imports: true,
+            ..LinkOptions::default()
+        },
+    )
+    .unwrap();
+    check_output(
+        &Plan::for_module(&linked).pretty_print().to_string(),
+        r#"
+        CHECK: func0(_: s32) -> s32 = import "g"
+        "#,
+    )
+    .unwrap();
+}
+
+#[test]
+fn link_handles_duplicate_exports() {
+    let cx = Rc::new(Context::new());
+    let modules = |second_factor, second_debuginfo| {
+        vec![
+            caller_module(&cx),
+            callee_module(&cx, "3s32", "// at a.glsl:1:1"),
+            callee_module(&cx, second_factor, second_debuginfo),
+        ]
+    };
+    let options = |duplicate_exports| LinkOptions {
+        duplicate_exports,
+        ..LinkOptions::default()
+    };
+
+    let err = link_modules(modules("3s32", ""), &options(DuplicateExports::Conflict))
+        .err()
+        .unwrap();
+    assert_eq!(
+        diagnostic_messages(&err.diagnostics),
+        ["`g` is exported by multiple modules"]
+    );
+
+    // Differences only in debuginfo don't make duplicates non-identical.
+    link_modules(
+        modules("3s32", "// at b.glsl:2:1"),
+        &options(DuplicateExports::KeepFirstIfIdentical),
+    )
+    .unwrap();
+
+    let err = link_modules(
+        modules("5s32", ""),
+        &options(DuplicateExports::KeepFirstIfIdentical),
+    )
+    .err()
+    .unwrap();
+    assert_eq!(
+        diagnostic_messages(&err.diagnostics),
+        ["`g` is exported by multiple modules, with different definitions"]
+    );
+
+    let linked = link_modules(modules("5s32", ""), &options(DuplicateExports::KeepFirst)).unwrap();
+    check_output(
+        &Plan::for_module(&linked).pretty_print().to_string(),
+        r#"
+        CHECK: spv.OpIMul(v0, 3s32): s32
+        CHECK-NOT: spv.OpIMul(v0, 5s32): s32
+        "#,
+    )
+    .unwrap();
+}
+
+#[test]
+fn prefix_link_names_keeps_foreign_imports() {
+    let cx = Rc::new(Context::new());
+    let mut module = shader_module(
+        &cx,
+        r#"
+        func0(v0: s32) -> s32 = import "h"
+
+        func1(v0: s32) -> s32 = import "g"
+
+        func2(v0: s32) -> s32 {
+          v1 = call func0(v0): s32
+          v2 = call func1(v1): s32
+          v2
+        }
+
+        func3(v0: s32) -> s32 {
+          v0
+        }
+
+        export {
+          "f": func2,
+          "g": func3,
+        }
+        "#,
+    );
+    prefix_link_names(&mut module, "lib_");
+
+    check_output(
+        &Plan::for_module(&module).pretty_print().to_string(),
+        r#"
+        CHECK: = import "h"
+        CHECK: = import "lib_g"
+        CHECK: "lib_f": func
+        CHECK-NEXT: "lib_g": func
+        "#,
+    )
+    .unwrap();
+}
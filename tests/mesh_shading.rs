@@ -0,0 +1,81 @@
+//! Task/mesh shaders (i.e. `SPV_EXT_mesh_shader`), through lowering, passes and lifting.
+
+mod common;
+
+use common::{mesh_shading_module_words, words_to_bytes};
+use spirt::passes::io_locations::{assign_io_locations, IoLocationOptions};
+use spirt::passes::legalize::structurize_func_cfgs;
+use spirt::print::Plan;
+use spirt::spv::target_env::{check_module, TargetEnv};
+use spirt::{Context, DeclDef, Exportee, Module};
+use std::rc::Rc;
+
+fn lower_and_structurize(cx: Rc<Context>) -> Module {
+    let mut module = Module::lower_from_spv_words(cx, mesh_shading_module_words()).unwrap();
+    structurize_func_cfgs(&mut module);
+    module
+}
+
+#[test]
+fn task_and_mesh_shaders_roundtrip() {
+    spirt::testing::check_spv_roundtrip(&words_to_bytes(&mesh_shading_module_words())).unwrap();
+
+    // Also round-trip the lifted form of the structurized module.
+    let module = lower_and_structurize(Rc::new(Context::new()));
+    let emitter = module.lift_to_spv_module_emitter().unwrap();
+    spirt::testing::check_spv_roundtrip(&words_to_bytes(&emitter.words)).unwrap();
+}
+
+#[test]
+fn task_shaders_are_structurized() {
+    let module = lower_and_structurize(Rc::new(Context::new()));
+
+    // NOTE(eddyb) task shaders can never return, so this requires structurizing
+    // function bodies that always end in `ExitInvocationKind::EmitMeshTasks`.
+    for &exportee in module.exports.values() {
+        if let Exportee::Func(func) = exportee {
+            if let DeclDef::Present(func_def_body) = &module.funcs[func].def {
+                assert!(func_def_body.unstructured_cfg.is_none());
+            }
+        }
+    }
+
+    spirt::testing::check_output(
+        &Plan::for_module(&module).pretty_print().to_string(),
+        r#"
+        CHECK: func0() -> spv.OpTypeVoid {
+        CHECK-NEXT: spv.OpStore(&global_var0, 3u32)
+        CHECK-NEXT: exit mesh.emit_tasks(1u32, 1u32, 1u32, &global_var0)
+        CHECK-NEXT: }
+        CHECK: func1() -> spv.OpTypeVoid {
+        CHECK-NEXT: spv.OpSetMeshOutputsEXT(3u32, 1u32)
+        CHECK: spv.OpEntryPoint<spv.ExecutionModel.TaskEXT, "task">: func0,
+        CHECK-NEXT: spv.OpEntryPoint<spv.ExecutionModel.MeshEXT, "mesh">: func1,
+        "#,
+    )
+    .unwrap();
+}
+
+#[test]
+fn per_primitive_mesh_outputs_get_locations() {
+    let mut module = lower_and_structurize(Rc::new(Context::new()));
+    assign_io_locations(&mut module, &IoLocationOptions::default());
+
+    spirt::testing::check_output(
+        &Plan::for_module(&module).pretty_print().to_string(),
+        r#"
+        CHECK: spv.OpDecorate<spv.Decoration.Location(0)>,
+        CHECK-NEXT: spv.OpDecorate<spv.Decoration.PerPrimitiveEXT>,
+        CHECK-NEXT: }
+        CHECK-NEXT: global_var2 in spv.StorageClass.Output: [u32; 1u32]
+        "#,
+    )
+    .unwrap();
+
+    let target_env = TargetEnv::vulkan_1_3()
+        .with_capabilities(["MeshShadingEXT"])
+        .with_extensions(["SPV_EXT_mesh_shader"]);
+    if let Err(e) = check_module(&module, &target_env) {
+        panic!("{e}");
+    }
+}
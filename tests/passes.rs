@@ -0,0 +1,1135 @@
+//! Individual passes (see `spirt::passes`), on small hand-written modules.
+
+mod common;
+
+use common::{str_words, Assembler};
+use spirt::passes::binding_remap::{remap_descriptor_bindings, DescriptorBinding};
+use spirt::passes::bounds_check::{insert_bounds_checks, BoundsCheckMode, BoundsCheckOptions};
+use spirt::passes::cfg_simplify::simplify_func_cfgs;
+use spirt::passes::const_fold::fold_consts;
+use spirt::passes::cse::eliminate_common_subexprs;
+use spirt::passes::dce::remove_dead_code;
+use spirt::passes::debug_printf::{inject_debug_printf, InjectionPoint};
+use spirt::passes::if_convert::{convert_ifs_to_selects, IfConversionOptions};
+use spirt::passes::image_split::split_combined_image_samplers;
+use spirt::passes::inline::{inline_calls, InlineOptions};
+use spirt::passes::int64_emulation::{emulate_float64, emulate_int64};
+use spirt::passes::interface_prune::{prune_dead_interface_global_vars, prune_entry_point_interface};
+use spirt::passes::legalize::structurize_func_cfgs;
+use spirt::passes::manager::PassManager;
+use spirt::passes::mem2reg::promote_vars_to_values;
+use spirt::passes::peephole::{apply_peephole_rules, PeepholeRules};
+use spirt::passes::prune::prune_unreachable;
+use spirt::passes::relaxed_precision::{lower_relaxed_precision, RelaxedPrecisionOptions};
+use spirt::passes::scalarize::{scalarize_vectors, ScalarizeOptions};
+use spirt::passes::specialize::{specialize_consts, SpecializeOptions};
+use spirt::passes::strength_reduce::reduce_strength;
+use spirt::passes::switch_to_if::{lower_switches_to_ifs, SwitchToIfOptions};
+use spirt::passes::unreachable::remove_unreachable_code;
+use spirt::passes::unroll::{unroll_loops, UnrollOptions};
+use spirt::passes::vectorize::vectorize_scalar_ops;
+use spirt::passes::workgroup_size::specialize_workgroup_size;
+use spirt::passes::zero_init::{zero_initialize_vars, ZeroInitOptions};
+use spirt::spv::fold::SpecConstValues;
+use spirt::testing::run_passes_and_check;
+use spirt::verify::verify_module;
+use spirt::{Context, ExportKey, Module};
+use std::rc::Rc;
+
+/// Parse `funcs` (SPIR-T text, e.g. function definitions and their exports),
+/// into a `Shader` module (also declaring the `Linkage` capability, for exports).
+fn shader_module(funcs: &str) -> Module {
+    let cx = Rc::new(Context::new());
+    let dialect = r#"
+        module.dialect = SPIR-V {
+          version: 1.3,
+          extensions: {},
+          capabilities: {spv.Capability.Shader, spv.Capability.Linkage},
+          addressing_model: spv.AddressingModel.Logical,
+          memory_model: spv.MemoryModel.GLSL450,
+        }
+    "#;
+    spirt::ir!(cx, &(dialect.to_string() + funcs))
+}
+
+/// Run the single pass `pass` on `module`, checking its output against `checks`
+/// (see [`run_passes_and_check`], with the version before the pass being `input`).
+fn run_pass_and_check(module: Module, pass: fn(&mut Module), checks: &str) -> Module {
+    run_passes_and_check(module, [("pass", pass)], checks)
+}
+
+#[test]
+fn dce_removes_unused_pure_insts() {
+    let module = shader_module(
+        r#"
+        func0(v0: s32) -> s32 {
+          v1 = spv.OpIAdd(v0, 1s32): s32
+          v2 = spv.OpIMul(v1, 2s32): s32
+          v3 = spv.OpISub(v0, 3s32): s32
+          v3
+        }
+
+        export { "f": func0 }
+        "#,
+    );
+    run_pass_and_check(
+        module,
+        remove_dead_code,
+        r#"
+        CHECK(input): spv.OpIMul
+        CHECK: func0(v0: s32) -> s32 {
+        CHECK-NOT: spv.OpIAdd
+        CHECK-NOT: spv.OpIMul
+        CHECK: spv.OpISub(v0, 3s32): s32
+        "#,
+    );
+}
+
+#[test]
+fn dce_removes_unused_select_outputs() {
+    let module = shader_module(
+        r#"
+        func0(v0: bool, v1: s32) -> s32 {
+          (v2: s32, v3: s32) = if v0 {
+            v4 = spv.OpIAdd(v1, 1s32): s32
+            v5 = spv.OpIAdd(v1, 2s32): s32
+            (v4, v5)
+          } else {
+            (v1, v1)
+          }
+          v3
+        }
+
+        export { "f": func0 }
+        "#,
+    );
+    run_pass_and_check(
+        module,
+        remove_dead_code,
+        r#"
+        CHECK: v2: s32 = if v0 {
+        CHECK-NOT: 1s32
+        CHECK: spv.OpIAdd(v1, 2s32): s32
+        "#,
+    );
+}
+
+#[test]
+fn cse_ignores_debuginfo_attrs() {
+    let module = shader_module(
+        r#"
+        func0(v0: s32) -> s32 {
+          // at a.glsl:1:1
+          v1 = spv.OpIAdd(v0, 1s32): s32
+          // at a.glsl:2:1
+          v2 = spv.OpIAdd(v0, 1s32): s32
+          v3 = spv.OpIMul(v1, v2): s32
+          v3
+        }
+
+        export { "f": func0 }
+        "#,
+    );
+    run_pass_and_check(
+        module,
+        eliminate_common_subexprs,
+        r#"
+        CHECK(input): v2 = spv.OpIAdd(v0, 1s32): s32
+        CHECK: // at a.glsl:1:1
+        CHECK-NEXT: v1 = spv.OpIAdd(v0, 1s32): s32
+        CHECK-NOT: spv.OpIAdd
+        CHECK: spv.OpIMul(v1, v1): s32
+        "#,
+    );
+}
+
+#[test]
+fn const_fold_folds_insts_and_selects() {
+    let module = shader_module(
+        r#"
+        func0(v0: s32) -> s32 {
+          v1 = spv.OpIAdd(2s32, 3s32): s32
+          v2 = spv.OpSLessThan(v1, 4s32): bool
+          v3: s32 = if v2 {
+            v0
+          } else {
+            v4 = spv.OpIMul(v0, v1): s32
+            v4
+          }
+          v3
+        }
+
+        export { "f": func0 }
+        "#,
+    );
+    run_pass_and_check(
+        module,
+        fold_consts,
+        r#"
+        CHECK: func0(v0: s32) -> s32 {
+        CHECK-NOT: if
+        CHECK: spv.OpIMul(v0, 5s32): s32
+        "#,
+    );
+}
+
+#[test]
+fn peephole_simplifies_identities() {
+    let module = shader_module(
+        r#"
+        func0(v0: s32) -> s32 {
+          v1 = spv.OpIAdd(v0, 0s32): s32
+          v2 = spv.OpSNegate(v1): s32
+          v3 = spv.OpSNegate(v2): s32
+          v4 = spv.OpIMul(v3, 7s32): s32
+          v4
+        }
+
+        export { "f": func0 }
+        "#,
+    );
+    run_pass_and_check(
+        module,
+        |module| apply_peephole_rules(module, &PeepholeRules::default()),
+        r#"
+        CHECK: func0(v0: s32) -> s32 {
+        CHECK-NOT: spv.OpIAdd
+        CHECK: v4 = spv.OpIMul(v0, 7s32): s32
+        "#,
+    );
+}
+
+#[test]
+fn strength_reduce_uses_shifts_for_powers_of_two() {
+    let module = shader_module(
+        r#"
+        func0(v0: u32) -> u32 {
+          v1 = spv.OpIMul(v0, 8u32): u32
+          v2 = spv.OpUDiv(v1, 4u32): u32
+          v3 = spv.OpUMod(v2, 16u32): u32
+          v3
+        }
+
+        export { "f": func0 }
+        "#,
+    );
+    run_pass_and_check(
+        module,
+        reduce_strength,
+        r#"
+        CHECK: spv.OpShiftLeftLogical(v0, 3u32): u32
+        CHECK: spv.OpShiftRightLogical(v1, 2u32): u32
+        CHECK: spv.OpBitwiseAnd(v2, 15u32): u32
+        CHECK-NOT: spv.OpUMod
+        "#,
+    );
+}
+
+#[test]
+fn if_convert_replaces_tiny_ifs_with_op_select() {
+    let module = shader_module(
+        r#"
+        func0(v0: bool, v1: s32) -> s32 {
+          v2: s32 = if v0 {
+            v3 = spv.OpIAdd(v1, 1s32): s32
+            v3
+          } else {
+            v1
+          }
+          v2
+        }
+
+        export { "f": func0 }
+        "#,
+    );
+    run_pass_and_check(
+        module,
+        |module| convert_ifs_to_selects(module, &IfConversionOptions::default()),
+        r#"
+        CHECK: func0(v0: bool, v1: s32) -> s32 {
+        CHECK-NOT: if
+        CHECK: v3 = spv.OpIAdd(v1, 1s32): s32
+        CHECK-NEXT: v4 = spv.OpSelect(v0, v3, v1): s32
+        "#,
+    );
+}
+
+#[test]
+fn if_convert_respects_max_case_inst_count() {
+    let module = shader_module(
+        r#"
+        func0(v0: bool, v1: s32) -> s32 {
+          v2: s32 = if v0 {
+            v3 = spv.OpIAdd(v1, 1s32): s32
+            v4 = spv.OpIAdd(v3, 1s32): s32
+            v4
+          } else {
+            v1
+          }
+          v2
+        }
+
+        export { "f": func0 }
+        "#,
+    );
+    run_pass_and_check(
+        module,
+        |module| {
+            convert_ifs_to_selects(
+                module,
+                &IfConversionOptions {
+                    max_case_inst_count: 1,
+                },
+            )
+        },
+        r#"
+        CHECK: v2: s32 = if v0 {
+        CHECK-NOT: spv.OpSelect
+        "#,
+    );
+}
+
+#[test]
+fn unreachable_code_is_removed() {
+    let module = shader_module(
+        r#"
+        func0(v0: bool, v1: s32) -> s32 {
+          if v0 {
+            branch label1
+          } else {
+            branch label2
+          }
+        label1:
+          v2 = spv.OpIAdd(v1, 1s32): s32
+          unreachable
+        label2:
+          return v1
+        }
+
+        export { "f": func0 }
+        "#,
+    );
+    run_pass_and_check(
+        module,
+        remove_unreachable_code,
+        r#"
+        CHECK: func0(v0: bool, v1: s32) -> s32 {
+        CHECK-NOT: spv.OpIAdd
+        CHECK: return v1
+        "#,
+    );
+}
+
+#[test]
+fn vectorize_fuses_lockstep_scalar_ops() {
+    let module = shader_module(
+        r#"
+        func0(v0: f32×2, v1: f32×2) -> f32×2 {
+          v2 = spv.OpCompositeExtract<0>(v0): f32
+          v3 = spv.OpCompositeExtract<0>(v1): f32
+          v4 = spv.OpFAdd(v2, v3): f32
+          v5 = spv.OpCompositeExtract<1>(v0): f32
+          v6 = spv.OpCompositeExtract<1>(v1): f32
+          v7 = spv.OpFAdd(v5, v6): f32
+          v8 = spv.OpCompositeConstruct(v4, v7): f32×2
+          v8
+        }
+
+        export { "f": func0 }
+        "#,
+    );
+    run_passes_and_check(
+        module,
+        [
+            ("vectorize", vectorize_scalar_ops as fn(&mut Module)),
+            ("dce", remove_dead_code),
+        ],
+        r#"
+        CHECK(vectorize): spv.OpFAdd(v0, v1): f32×2
+        CHECK: func0(v0: f32×2, v1: f32×2) -> f32×2 {
+        CHECK-NEXT: v8 = spv.OpFAdd(v0, v1): f32×2
+        CHECK-NEXT: v8
+        "#,
+    );
+}
+
+#[test]
+fn scalarize_splits_ops_on_constructed_vectors() {
+    let module = shader_module(
+        r#"
+        func0(v0: f32, v1: f32) -> f32 {
+          v2 = spv.OpCompositeConstruct(v0, v1): f32×2
+          v3 = spv.OpFMul(v2, v2): f32×2
+          v4 = spv.OpCompositeExtract<1>(v3): f32
+          v4
+        }
+
+        export { "f": func0 }
+        "#,
+    );
+    run_passes_and_check(
+        module,
+        [
+            (
+                "scalarize",
+                (|module: &mut Module| scalarize_vectors(module, &ScalarizeOptions::default()))
+                    as fn(&mut Module),
+            ),
+            ("dce", remove_dead_code),
+        ],
+        r#"
+        CHECK: func0(v0: f32, v1: f32) -> f32 {
+        CHECK-NEXT: v6 = spv.OpFMul(v1, v1): f32
+        CHECK-NEXT: v6
+        "#,
+    );
+}
+
+#[test]
+fn mem2reg_promotes_local_vars() {
+    let module = shader_module(
+        r#"
+        func0(v0: bool, v1: s32) -> s32 {
+          v2 = spv.OpVariable<spv.StorageClass.Function>: spv.OpTypePointer<spv.StorageClass.Function>(s32)
+          spv.OpStore(v2, v1)
+          if v0 {
+            spv.OpStore(v2, 5s32)
+          } else {
+          }
+          v3 = spv.OpLoad(v2): s32
+          v3
+        }
+
+        export { "f": func0 }
+        "#,
+    );
+    run_pass_and_check(
+        module,
+        promote_vars_to_values,
+        r#"
+        CHECK: func0(v0: bool, v1: s32) -> s32 {
+        CHECK-NEXT: v4: s32 = if v0 {
+        CHECK-NEXT: 5s32
+        CHECK-NEXT: } else {
+        CHECK-NEXT: v1
+        CHECK-NEXT: }
+        CHECK-NEXT: v4
+        "#,
+    );
+}
+
+#[test]
+fn inline_copies_callee_bodies() {
+    let module = shader_module(
+        r#"
+        func0(v0: s32) -> s32 {
+          v1 = spv.OpIMul(v0, 3s32): s32
+          v1
+        }
+
+        func1(v0: s32) -> s32 {
+          v1 = call func0(v0): s32
+          v2 = spv.OpIAdd(v1, 1s32): s32
+          v2
+        }
+
+        export { "f": func1 }
+        "#,
+    );
+    run_pass_and_check(
+        module,
+        |module| {
+            inline_calls(
+                module,
+                &InlineOptions {
+                    inline_all: true,
+                    ..InlineOptions::default()
+                },
+            )
+        },
+        r#"
+        CHECK: func1(v0: s32) -> s32 {
+        CHECK-NOT: call
+        CHECK: v3 = spv.OpIMul(v0, 3s32): s32
+        CHECK-NEXT: v2 = spv.OpIAdd(v3, 1s32): s32
+        "#,
+    );
+}
+
+#[test]
+fn unroll_fully_unrolls_short_constant_loops() {
+    let module = shader_module(
+        r#"
+        func0(v0: s32) -> s32 {
+          v1 = spv.OpVariable<spv.StorageClass.Function>: spv.OpTypePointer<spv.StorageClass.Function>(s32)
+          spv.OpStore(v1, v0)
+          loop(v2: s32 <- 0s32) {
+            v3 = spv.OpLoad(v1): s32
+            v4 = spv.OpIMul(v3, 2s32): s32
+            spv.OpStore(v1, v4)
+            v5 = spv.OpIAdd(v2, 1s32): s32
+            v6 = spv.OpSLessThan(v5, 3s32): bool
+            v5 -> v2
+          } while v6
+          v7 = spv.OpLoad(v1): s32
+          v7
+        }
+
+        export { "f": func0 }
+        "#,
+    );
+    run_pass_and_check(
+        module,
+        |module| unroll_loops(module, &UnrollOptions::default()),
+        r#"
+        CHECK: func0(v0: s32) -> s32 {
+        CHECK-NOT: loop
+        CHECK: spv.OpIMul
+        CHECK: spv.OpIMul
+        CHECK: spv.OpIMul
+        CHECK-NOT: spv.OpIMul
+        "#,
+    );
+}
+
+#[test]
+fn switch_to_if_lowers_switch_cases() {
+    let module = shader_module(
+        r#"
+        func0(v0: s32) -> s32 {
+          v1: s32 = spv.OpSwitch<1, 2>(v0) {
+            case => {
+              0s32
+            }
+            case => {
+              10s32
+            }
+            case => {
+              20s32
+            }
+          }
+          v1
+        }
+
+        export { "f": func0 }
+        "#,
+    );
+    run_pass_and_check(
+        module,
+        |module| lower_switches_to_ifs(module, &SwitchToIfOptions::default()),
+        r#"
+        CHECK: func0(v0: s32) -> s32 {
+        CHECK-NOT: spv.OpSwitch
+        CHECK: spv.OpIEqual(v0, 1s32): bool
+        CHECK: spv.OpIEqual(v0, 2s32): bool
+        CHECK: if
+        CHECK-NEXT: 10s32
+        CHECK: if
+        CHECK-NEXT: 20s32
+        CHECK: else {
+        CHECK-NEXT: 0s32
+        "#,
+    );
+}
+
+#[test]
+fn cfg_simplify_merges_linear_regions() {
+    let module = shader_module(
+        r#"
+        func0(v0: s32) -> s32 {
+          branch label1
+        label1:
+          v1 = spv.OpIAdd(v0, 1s32): s32
+          branch label2(v1)
+        label2(v2: s32):
+          return v2
+        }
+
+        export { "f": func0 }
+        "#,
+    );
+    run_pass_and_check(
+        module,
+        simplify_func_cfgs,
+        r#"
+        CHECK(input): branch label1
+        CHECK: func0(v0: s32) -> s32 {
+        CHECK-NEXT: v1 = spv.OpIAdd(v0, 1s32): s32
+        CHECK-NEXT: return v1
+        CHECK-NEXT: }
+        "#,
+    );
+}
+
+#[test]
+fn structurize_turns_branches_into_ifs() {
+    let module = shader_module(
+        r#"
+        func0(v0: bool, v1: s32) -> s32 {
+          if v0 {
+            branch label1
+          } else {
+            branch label2
+          }
+        label1:
+          v2 = spv.OpIAdd(v1, 1s32): s32
+          branch label3(v2)
+        label2:
+          branch label3(v1)
+        label3(v3: s32):
+          return v3
+        }
+
+        export { "f": func0 }
+        "#,
+    );
+    run_pass_and_check(
+        module,
+        structurize_func_cfgs,
+        r#"
+        CHECK: func0(v0: bool, v1: s32) -> s32 {
+        CHECK-NOT: branch
+        CHECK: = if v0 {
+        CHECK: spv.OpIAdd(v1, 1s32): s32
+        CHECK: } else {
+        CHECK-NOT: return
+        "#,
+    );
+}
+
+#[test]
+fn prune_removes_unexported_defs() {
+    let module = shader_module(
+        r#"
+        func0(v0: s32) -> s32 {
+          v0
+        }
+
+        func1(v0: s32) -> s32 {
+          v1 = spv.OpIAdd(v0, 1s32): s32
+          v1
+        }
+
+        export { "f": func1 }
+        "#,
+    );
+    run_pass_and_check(
+        module,
+        prune_unreachable,
+        r#"
+        CHECK(input): func0(v0: s32) -> s32 {
+        CHECK-NOT: func0
+        CHECK: func1(v0: s32) -> s32 {
+        CHECK-NEXT: v1 = spv.OpIAdd(v0, 1s32): s32
+        "#,
+    );
+}
+
+#[test]
+fn specialize_replaces_spec_consts() {
+    let module = shader_module(
+        r#"
+        func0(v0: s32) -> s32 {
+          v1 = spv.OpIMul(v0, spec(3) = 1s32): s32
+          v1
+        }
+
+        export { "f": func0 }
+        "#,
+    );
+    run_pass_and_check(
+        module,
+        |module| {
+            let values = SpecConstValues {
+                by_spec_id: [(3, 7)].into_iter().collect(),
+            };
+            specialize_consts(module, &values, &SpecializeOptions::default());
+        },
+        r#"
+        CHECK(input): spv.OpIMul(v0, spec(3) = 1s32): s32
+        CHECK-NOT: spec(3)
+        CHECK: spv.OpIMul(v0, 7s32): s32
+        "#,
+    );
+}
+
+#[test]
+fn workgroup_size_replaces_local_size() {
+    let module = shader_module(
+        r#"
+        #{spv.OpExecutionMode<spv.ExecutionMode.LocalSize(1, 1, 1)>}
+        func0() -> spv.OpTypeVoid {
+        }
+
+        export {
+          spv.OpEntryPoint<spv.ExecutionModel.GLCompute, "main">: func0,
+        }
+        "#,
+    );
+    run_pass_and_check(
+        module,
+        |module| specialize_workgroup_size(module, [8, 4, 2], &SpecializeOptions::default()),
+        r#"
+        CHECK: #{spv.OpExecutionMode<spv.ExecutionMode.LocalSize(8, 4, 2)>}
+        CHECK-NEXT: func0() -> spv.OpTypeVoid {
+        "#,
+    );
+}
+
+#[test]
+fn zero_init_initializes_function_vars() {
+    let module = shader_module(
+        r#"
+        func0() -> s32 {
+          v0 = spv.OpVariable<spv.StorageClass.Function>: spv.OpTypePointer<spv.StorageClass.Function>(s32)
+          v1 = spv.OpLoad(v0): s32
+          v1
+        }
+
+        export { "f": func0 }
+        "#,
+    );
+    run_pass_and_check(
+        module,
+        |module| zero_initialize_vars(module, &ZeroInitOptions::default()),
+        r#"
+        CHECK(input): v0 = spv.OpVariable<spv.StorageClass.Function>: spv.OpTypePointer<spv.StorageClass.Function>(s32)
+        CHECK: v0 = spv.OpVariable<spv.StorageClass.Function>(spv.OpConstantNull: s32):
+        "#,
+    );
+}
+
+#[test]
+fn bounds_check_clamps_dynamic_indices() {
+    let module = shader_module(
+        r#"
+        func0(v0: u32) -> s32 {
+          v1 = spv.OpVariable<spv.StorageClass.Function>: spv.OpTypePointer<spv.StorageClass.Function>([s32; 4u32])
+          v2 = spv.OpAccessChain(v1, v0): spv.OpTypePointer<spv.StorageClass.Function>(s32)
+          v3 = spv.OpLoad(v2): s32
+          v3
+        }
+
+        export { "f": func0 }
+        "#,
+    );
+    run_pass_and_check(
+        module,
+        |module| insert_bounds_checks(module, &BoundsCheckOptions::default()),
+        r#"
+        CHECK: glsl.ext.UMin(v0, 3u32): u32
+        CHECK: spv.OpAccessChain(v1, v4)
+        "#,
+    );
+
+    let module = shader_module(
+        r#"
+        func0(v0: u32) -> s32 {
+          v1 = spv.OpVariable<spv.StorageClass.Function>: spv.OpTypePointer<spv.StorageClass.Function>([s32; 4u32])
+          v2 = spv.OpAccessChain(v1, v0): spv.OpTypePointer<spv.StorageClass.Function>(s32)
+          v3 = spv.OpLoad(v2): s32
+          v3
+        }
+
+        export { "f": func0 }
+        "#,
+    );
+    run_pass_and_check(
+        module,
+        |module| {
+            insert_bounds_checks(
+                module,
+                &BoundsCheckOptions {
+                    mode: BoundsCheckMode::Predicate,
+                    ..BoundsCheckOptions::default()
+                },
+            )
+        },
+        r#"
+        CHECK: v4 = spv.OpULessThan(v0, 4u32): bool
+        CHECK: v6: s32 = if v4 {
+        CHECK-NEXT: v7 = spv.OpLoad(v2): s32
+        CHECK: } else {
+        CHECK-NEXT: spv.OpConstantNull: s32
+        "#,
+    );
+}
+
+#[test]
+fn relaxed_precision_uses_f16() {
+    let module = shader_module(
+        r#"
+        func0(v0: f32, v1: f32) -> f32 {
+          #{spv.OpDecorate<spv.Decoration.RelaxedPrecision>}
+          v2 = spv.OpFMul(v0, v1): f32
+          v2
+        }
+
+        export { "f": func0 }
+        "#,
+    );
+    run_pass_and_check(
+        module,
+        |module| {
+            lower_relaxed_precision(
+                module,
+                &RelaxedPrecisionOptions {
+                    add_float16_capability: true,
+                },
+            )
+        },
+        r#"
+        CHECK: spv.Capability.Float16
+        CHECK: spv.OpFConvert(v0): f16
+        CHECK: spv.OpFConvert(v1): f16
+        CHECK: spv.OpFMul(v3, v4): f16
+        "#,
+    );
+}
+
+#[test]
+fn int64_emulation_uses_u32_pairs() {
+    let cx = Rc::new(Context::new());
+    let module = spirt::ir!(
+        cx,
+        r#"
+        module.dialect = SPIR-V {
+          version: 1.3,
+          extensions: {},
+          capabilities: {spv.Capability.Shader, spv.Capability.Linkage, spv.Capability.Int64},
+          addressing_model: spv.AddressingModel.Logical,
+          memory_model: spv.MemoryModel.GLSL450,
+        }
+
+        func0(v0: u64, v1: u64) -> u64 {
+          v2 = spv.OpIAdd(v0, v1): u64
+          v2
+        }
+
+        export { "f": func0 }
+        "#
+    );
+    run_pass_and_check(
+        module,
+        |module| emulate_int64(module).unwrap(),
+        r#"
+        CHECK-NOT: spv.Capability.Int64
+        CHECK: func0(v0: u32×2, v1: u32×2) -> u32×2 {
+        CHECK-NOT: u64
+        CHECK: spv.OpULessThan(v7, v3): bool
+        CHECK: spv.OpCompositeConstruct(v7, v11): u32×2
+        "#,
+    );
+}
+
+#[test]
+fn float64_emulation_rejects_unsupported_ops() {
+    let cx = Rc::new(Context::new());
+    let mut module = spirt::ir!(
+        cx,
+        r#"
+        module.dialect = SPIR-V {
+          version: 1.3,
+          extensions: {},
+          capabilities: {spv.Capability.Shader, spv.Capability.Linkage, spv.Capability.Float64},
+          addressing_model: spv.AddressingModel.Logical,
+          memory_model: spv.MemoryModel.GLSL450,
+        }
+
+        func0(v0: f64, v1: f64) -> f64 {
+          v2 = spv.OpFRem(v0, v1): f64
+          v2
+        }
+
+        export { "f": func0 }
+        "#
+    );
+    let err = emulate_float64(&mut module).unwrap_err();
+    assert!(
+        err.to_string().contains("OpFRem"),
+        "unexpected error: {err}"
+    );
+}
+
+#[test]
+fn image_split_splits_combined_image_samplers() {
+    let module = shader_module(
+        r#"
+        type0 = image(
+          f32,
+          dim: spv.Dim.2D,
+          depth: false,
+          arrayed: false,
+          multisampled: false,
+          sampled: true,
+          format: spv.ImageFormat.Unknown,
+        )
+
+        #{
+          spv.OpDecorate<spv.Decoration.DescriptorSet(0)>,
+          spv.OpDecorate<spv.Decoration.Binding(1)>,
+        }
+        global_var0 in spv.StorageClass.UniformConstant: sampled_image(type0)
+
+        func0(v0: f32×2) -> f32×4 {
+          v1 = spv.OpLoad(&global_var0): sampled_image(type0)
+          v2 = spv.OpImageSampleImplicitLod(v1, v0): f32×4
+          v2
+        }
+
+        export { "f": func0 }
+        "#,
+    );
+    run_pass_and_check(
+        module,
+        |module| {
+            let split = split_combined_image_samplers(module);
+            assert_eq!(split.len(), 1);
+            assert_eq!(
+                (split[0].image_binding, split[0].sampler_binding),
+                (
+                    Some(DescriptorBinding { set: 0, binding: 1 }),
+                    Some(DescriptorBinding { set: 0, binding: 2 })
+                )
+            );
+        },
+        r#"
+        CHECK: v3 = spv.OpLoad(&global_var1): type0
+        CHECK-NEXT: v4 = spv.OpLoad(&global_var2): sampler
+        CHECK-NEXT: v1 = spv.OpSampledImage(v3, v4): sampled_image(type0)
+        CHECK-NEXT: v2 = spv.OpImageSampleImplicitLod(v1, v0): f32×4
+        CHECK: spv.OpDecorate<spv.Decoration.Binding(2)>,
+        CHECK: global_var2 in spv.StorageClass.UniformConstant: sampler
+        "#,
+    );
+}
+
+#[test]
+fn binding_remap_flattens_descriptor_sets() {
+    let mut module = shader_module(
+        r#"
+        #{
+          spv.OpDecorate<spv.Decoration.DescriptorSet(0)>,
+          spv.OpDecorate<spv.Decoration.Binding(4)>,
+        }
+        global_var0 in spv.StorageClass.Uniform: u32
+
+        #{
+          spv.OpDecorate<spv.Decoration.DescriptorSet(1)>,
+          spv.OpDecorate<spv.Decoration.Binding(0)>,
+        }
+        global_var1 in spv.StorageClass.Uniform: u32
+
+        #{
+          spv.OpDecorate<spv.Decoration.DescriptorSet(1)>,
+          spv.OpDecorate<spv.Decoration.Binding(1)>,
+        }
+        global_var2 in spv.StorageClass.Uniform: u32
+
+        export {
+          "a": global_var0,
+          "b": global_var1,
+          "c": global_var2,
+        }
+        "#,
+    );
+    let collisions = remap_descriptor_bindings(&mut module, |binding| binding.flattened(4));
+    assert_eq!(collisions.len(), 1);
+    assert_eq!(
+        collisions[0].binding,
+        DescriptorBinding { set: 0, binding: 4 }
+    );
+    assert_eq!(collisions[0].global_vars.len(), 2);
+
+    spirt::testing::check_output(
+        &spirt::print::Plan::for_module(&module)
+            .pretty_print()
+            .to_string(),
+        r#"
+        CHECK: attrs0 = #{
+        CHECK-NEXT: spv.OpDecorate<spv.Decoration.Binding(4)>,
+        CHECK-NEXT: spv.OpDecorate<spv.Decoration.DescriptorSet(0)>,
+        CHECK: #attrs0
+        CHECK-NEXT: global_var0 in
+        CHECK: #attrs0
+        CHECK-NEXT: global_var1 in
+        CHECK: spv.OpDecorate<spv.Decoration.Binding(5)>,
+        CHECK-NEXT: spv.OpDecorate<spv.Decoration.DescriptorSet(0)>,
+        CHECK: global_var2 in
+        "#,
+    )
+    .unwrap();
+}
+
+#[test]
+fn debug_printf_prints_func_params() {
+    let module = shader_module(
+        r#"
+        func0(v0: s32, v1: f32) -> s32 {
+          v0
+        }
+
+        export { "f": func0 }
+        "#,
+    );
+    run_pass_and_check(
+        module,
+        |module| inject_debug_printf(module, |_, point| point == InjectionPoint::FuncEntry),
+        r#"
+        CHECK: SPV_KHR_non_semantic_info
+        CHECK: func0(v0: s32, v1: f32) -> s32 {
+        CHECK-NEXT: NonSemantic.DebugPrintf
+        "#,
+    );
+}
+
+/// Build a `Fragment` entry-point (named `main`), listing two `Output` variables
+/// in its interface, but only writing to the first one.
+fn fragment_with_unused_output_words() -> Vec<u32> {
+    let mut asm = Assembler::default();
+    let [void, f32, ptr_out, used, unused, fn_void, main, entry, one] = [(); 9].map(|()| asm.id());
+
+    // `Shader` capability, and `Logical` addressing with `GLSL450` memory model.
+    asm.inst("OpCapability", [1]);
+    asm.inst("OpMemoryModel", [0, 1]);
+    asm.inst(
+        "OpEntryPoint",
+        [[4, main].as_slice(), &str_words("main"), &[used, unused]].concat(),
+    );
+    // `OriginUpperLeft`.
+    asm.inst("OpExecutionMode", [main, 7]);
+    // `Location 0` and `Location 1`.
+    asm.inst("OpDecorate", [used, 30, 0]);
+    asm.inst("OpDecorate", [unused, 30, 1]);
+
+    asm.inst("OpTypeVoid", [void]);
+    asm.inst("OpTypeFloat", [f32, 32]);
+    // `Output` storage class.
+    asm.inst("OpTypePointer", [ptr_out, 3, f32]);
+    asm.inst("OpVariable", [ptr_out, used, 3]);
+    asm.inst("OpVariable", [ptr_out, unused, 3]);
+    asm.inst("OpConstant", [f32, one, 1.0f32.to_bits()]);
+    asm.inst("OpTypeFunction", [fn_void, void]);
+
+    asm.inst("OpFunction", [void, main, 0, fn_void]);
+    asm.inst("OpLabel", [entry]);
+    asm.inst("OpStore", [used, one]);
+    asm.inst("OpReturn", []);
+    asm.inst("OpFunctionEnd", []);
+
+    asm.finish()
+}
+
+fn interface_global_var_count(module: &Module) -> usize {
+    module
+        .exports
+        .keys()
+        .map(|export_key| match export_key {
+            ExportKey::SpvEntryPoint {
+                interface_global_vars,
+                ..
+            } => interface_global_vars.len(),
+            ExportKey::LinkName(_) => 0,
+        })
+        .sum()
+}
+
+#[test]
+fn interface_prune_removes_unused_outputs() {
+    let cx = Rc::new(Context::new());
+    let words = fragment_with_unused_output_words();
+
+    let mut module = Module::lower_from_spv_words(cx.clone(), words.clone()).unwrap();
+    assert_eq!(prune_entry_point_interface(&mut module, "other"), 0);
+    assert_eq!(interface_global_var_count(&module), 2);
+    assert_eq!(prune_entry_point_interface(&mut module, "main"), 1);
+    assert_eq!(interface_global_var_count(&module), 1);
+    spirt::testing::check_output(
+        &spirt::print::Plan::for_module(&module)
+            .pretty_print()
+            .to_string(),
+        r#"
+        CHECK: spv.OpDecorate<spv.Decoration.Location(0)>
+        CHECK-NOT: spv.Decoration.Location(1)
+        CHECK: spv.OpEntryPoint<spv.ExecutionModel.Fragment, "main">
+        "#,
+    )
+    .unwrap();
+
+    let mut module = Module::lower_from_spv_words(cx, words).unwrap();
+    assert_eq!(prune_dead_interface_global_vars(&mut module), 1);
+    assert_eq!(interface_global_var_count(&module), 1);
+}
+
+#[test]
+fn pass_manager_reports_timings_and_snapshots() {
+    let mut module = shader_module(
+        r#"
+        func0(v0: s32) -> s32 {
+          v1 = spv.OpIAdd(v0, 1s32): s32
+          v2 = spv.OpIMul(v0, 8s32): s32
+          v2
+        }
+
+        export { "f": func0 }
+        "#,
+    );
+    let report = PassManager::with_default_optimizations()
+        .verify_with(verify_module)
+        .capture_snapshots(true)
+        .run(&mut module)
+        .unwrap();
+
+    let names: Vec<_> = report.versions().map(|(name, _)| name).collect();
+    assert_eq!(names[0], "input");
+    assert_eq!(names.len(), report.timings.len() + 1);
+    assert_eq!(
+        names[1..],
+        report
+            .timings
+            .iter()
+            .map(|(name, _)| &name[..])
+            .collect::<Vec<_>>()[..]
+    );
+
+    spirt::testing::check_output(
+        &spirt::print::Plan::for_module(&module)
+            .pretty_print()
+            .to_string(),
+        r#"
+        CHECK: func0(v0: s32) -> s32 {
+        CHECK-NOT: spv.OpIAdd
+        CHECK: spv.OpShiftLeftLogical(v0, 3s32): s32
+        "#,
+    )
+    .unwrap();
+}
+
+#[test]
+fn pass_manager_stops_at_rejected_pass() {
+    let mut module = shader_module(
+        r#"
+        func0(v0: s32) -> s32 {
+          v0
+        }
+
+        export { "f": func0 }
+        "#,
+    );
+    let err = PassManager::new()
+        .add("remove_dead_code", remove_dead_code)
+        .add("break_everything", |module| module.exports.clear())
+        .add("prune_unreachable", prune_unreachable)
+        .verify_with(|module: &Module| {
+            if module.exports.is_empty() {
+                Err("no exports")
+            } else {
+                Ok(())
+            }
+        })
+        .run(&mut module)
+        .err()
+        .unwrap();
+    assert_eq!(err.pass_name, "break_everything");
+    assert_eq!(err.message, "no exports");
+}
@@ -0,0 +1,105 @@
+//! Printing modules (and parsing the printed plain text back).
+
+mod common;
+
+use common::{large_const_array_module_words, mesh_shading_module_words, str_words, Assembler};
+use spirt::passes::legalize::structurize_func_cfgs;
+use spirt::print::{Plan, PrintOptions};
+use spirt::{Context, Module};
+use std::rc::Rc;
+
+#[test]
+fn default_options_print_parseable_large_const_aggregates() {
+    let cx = Rc::new(Context::new());
+    let module =
+        Module::lower_from_spv_words(cx.clone(), large_const_array_module_words(100)).unwrap();
+
+    let printed = Plan::for_module(&module).pretty_print().to_string();
+    assert!(!printed.contains('…'), "elided elements in:\n{printed}");
+
+    let parsed = Module::parse_from_spirt_text(cx, &printed).unwrap();
+    assert_eq!(
+        printed,
+        Plan::for_module(&parsed).pretty_print().to_string()
+    );
+}
+
+#[test]
+fn eliding_const_aggregate_elements_is_opt_in_and_unparseable() {
+    let cx = Rc::new(Context::new());
+    let module =
+        Module::lower_from_spv_words(cx.clone(), large_const_array_module_words(100)).unwrap();
+
+    let options = PrintOptions {
+        max_const_aggregate_elements: Some(64),
+        ..PrintOptions::default()
+    };
+    let printed = Plan::for_module(&module)
+        .pretty_print_with_options(&options)
+        .to_string();
+    assert!(
+        printed.contains("… (36 more)"),
+        "no elided elements in:\n{printed}"
+    );
+
+    let err = Module::parse_from_spirt_text(cx, &printed).err().unwrap();
+    assert!(
+        err.to_string().contains("elided elements"),
+        "unexpected error: {err}"
+    );
+}
+
+#[test]
+fn image_types_roundtrip_through_text() {
+    let mut asm = Assembler::default();
+    let [f32, image, sampled_image, ptr, var] = [(); 5].map(|()| asm.id());
+
+    // `Shader` and `Linkage` capabilities, and `Logical` addressing with
+    // `GLSL450` memory model.
+    asm.inst("OpCapability", [1]);
+    asm.inst("OpCapability", [5]);
+    asm.inst("OpMemoryModel", [0, 1]);
+    // `LinkageAttributes "tex" Export`.
+    asm.inst(
+        "OpDecorate",
+        [[var, 41].as_slice(), &str_words("tex"), &[0]].concat(),
+    );
+
+    asm.inst("OpTypeFloat", [f32, 32]);
+    // `2D`, not depth, not arrayed, not multisampled, sampled, `Unknown` format.
+    asm.inst("OpTypeImage", [image, f32, 1, 0, 0, 0, 1, 0]);
+    asm.inst("OpTypeSampledImage", [sampled_image, image]);
+    // `UniformConstant` storage class.
+    asm.inst("OpTypePointer", [ptr, 0, sampled_image]);
+    asm.inst("OpVariable", [ptr, var, 0]);
+
+    let cx = Rc::new(Context::new());
+    let module = Module::lower_from_spv_words(cx.clone(), asm.finish()).unwrap();
+
+    let printed = Plan::for_module(&module).pretty_print().to_string();
+    assert!(printed.contains("image("), "no image type in:\n{printed}");
+
+    let parsed = Module::parse_from_spirt_text(cx, &printed).unwrap();
+    assert_eq!(
+        printed,
+        Plan::for_module(&parsed).pretty_print().to_string()
+    );
+}
+
+#[test]
+fn unstructured_and_structured_funcs_roundtrip_through_text() {
+    let cx = Rc::new(Context::new());
+    let mut module = Module::lower_from_spv_words(cx.clone(), mesh_shading_module_words()).unwrap();
+
+    for structurize in [false, true] {
+        if structurize {
+            structurize_func_cfgs(&mut module);
+        }
+        let printed = Plan::for_module(&module).pretty_print().to_string();
+        let parsed = Module::parse_from_spirt_text(cx.clone(), &printed).unwrap();
+        assert_eq!(
+            printed,
+            Plan::for_module(&parsed).pretty_print().to_string()
+        );
+    }
+}
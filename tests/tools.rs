@@ -0,0 +1,48 @@
+//! The `spirt-dis` and `spirt-as` command-line tools, used together.
+
+mod common;
+
+use common::{large_const_array_module_words, words_to_bytes};
+use spirt::print::Plan;
+use spirt::{Context, Module};
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+use std::rc::Rc;
+
+fn lower_and_print(spv_path: &PathBuf) -> String {
+    let module = Module::lower_from_spv_file(Rc::new(Context::new()), spv_path).unwrap();
+    Plan::for_module(&module).pretty_print().to_string()
+}
+
+fn run(tool: &str, args: &[&PathBuf]) {
+    let output = Command::new(tool).args(args).output().unwrap();
+    assert!(
+        output.status.success(),
+        "`{tool}` failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+#[test]
+fn dis_then_as_roundtrips_large_const_aggregates() {
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("dis_then_as");
+    fs::create_dir_all(&dir).unwrap();
+    let (original, text, reassembled) = (
+        dir.join("original.spv"),
+        dir.join("original.spirt"),
+        dir.join("reassembled.spv"),
+    );
+    fs::write(
+        &original,
+        words_to_bytes(&large_const_array_module_words(100)),
+    )
+    .unwrap();
+
+    run(env!("CARGO_BIN_EXE_spirt-dis"), &[&original, &text]);
+    let printed = fs::read_to_string(&text).unwrap();
+    assert!(!printed.contains('…'), "elided elements in:\n{printed}");
+
+    run(env!("CARGO_BIN_EXE_spirt-as"), &[&text, &reassembled]);
+    assert_eq!(lower_and_print(&original), lower_and_print(&reassembled));
+}
@@ -0,0 +1,67 @@
+//! Type helpers (e.g. `Type::pointee_type`), on types lowered from SPIR-V.
+
+mod common;
+
+use common::{Assembler, str_words};
+use spirt::{Context, Exportee, Module, TypeCtor, TypeCtorArg};
+use std::rc::Rc;
+
+/// Build a module exporting a function (`"f"`) taking a pointer to a linked
+/// list node (i.e. a `struct` containing a pointer to its own type).
+fn linked_list_module_words() -> Vec<u32> {
+    let mut asm = Assembler::default();
+    let [void, u32, node, ptr_node, fn_void_ptr] = [(); 5].map(|()| asm.id());
+    let [f, f_param, f_entry] = [(); 3].map(|()| asm.id());
+
+    // `Shader`, `Linkage` and `PhysicalStorageBufferAddresses` capabilities,
+    // and `PhysicalStorageBuffer64` addressing with `GLSL450` memory model.
+    asm.inst("OpCapability", [1]);
+    asm.inst("OpCapability", [5]);
+    asm.inst("OpCapability", [5347]);
+    asm.inst("OpMemoryModel", [5348, 1]);
+    // `LinkageAttributes "f" Export`.
+    asm.inst(
+        "OpDecorate",
+        [[f, 41].as_slice(), &str_words("f"), &[0]].concat(),
+    );
+
+    asm.inst("OpTypeVoid", [void]);
+    asm.inst("OpTypeInt", [u32, 32, 0]);
+    // `PhysicalStorageBuffer` storage class.
+    asm.inst("OpTypeForwardPointer", [ptr_node, 5349]);
+    asm.inst("OpTypeStruct", [node, u32, ptr_node]);
+    asm.inst("OpTypePointer", [ptr_node, 5349, node]);
+    asm.inst("OpTypeFunction", [fn_void_ptr, void, ptr_node]);
+
+    asm.inst("OpFunction", [void, f, 0, fn_void_ptr]);
+    asm.inst("OpFunctionParameter", [ptr_node, f_param]);
+    asm.inst("OpLabel", [f_entry]);
+    asm.inst("OpReturn", []);
+    asm.inst("OpFunctionEnd", []);
+
+    asm.finish()
+}
+
+#[test]
+fn pointee_type_unrolls_recursive_ptrs() {
+    let cx = Rc::new(Context::new());
+    let module = Module::lower_from_spv_words(cx.clone(), linked_list_module_words()).unwrap();
+
+    let f = match module.exports.values().next().unwrap() {
+        &Exportee::Func(func) => func,
+        Exportee::GlobalVar(_) => unreachable!(),
+    };
+    let ptr_node = module.funcs[f].params[0].ty;
+    assert!(matches!(cx[ptr_node].ctor, TypeCtor::RecursivePtr { .. }));
+
+    // The pointee should refer back to the pointer type itself, not to
+    // `TypeCtor::RecursivePtrSelf` (which is only valid inside `ptr_node`).
+    let node = ptr_node.pointee_type(&cx).unwrap();
+    assert!(matches!(
+        cx[node].ctor_args[..],
+        [TypeCtorArg::Type(_), TypeCtorArg::Type(next)] if next == ptr_node
+    ));
+
+    // Non-pointer types have no pointee.
+    assert!(node.pointee_type(&cx).is_none());
+}
@@ -0,0 +1,144 @@
+//! Verifying the structural invariants of modules (see `spirt::verify`).
+
+use spirt::verify::verify_module;
+use spirt::{
+    Context, ControlNodeKind, DataInst, DeclDef, ExportKey, Exportee, Func, FuncDefBody, Module,
+    Value,
+};
+use std::rc::Rc;
+
+/// Two (otherwise identical) functions, exported as `"f"` and `"g"`.
+fn two_funcs_module(cx: &Rc<Context>) -> Module {
+    spirt::ir!(
+        cx,
+        r#"
+        module.dialect = SPIR-V {
+          version: 1.0,
+          extensions: {},
+          capabilities: {spv.Capability.Shader, spv.Capability.Linkage},
+          addressing_model: spv.AddressingModel.Logical,
+          memory_model: spv.MemoryModel.GLSL450,
+        }
+
+        func0(v0: u32) -> u32 {
+          v1 = spv.OpIAdd(v0, v0): u32
+          v1
+        }
+
+        func1(v0: u32) -> u32 {
+          v1 = spv.OpIAdd(v0, v0): u32
+          v1
+        }
+
+        export {
+          "f": func0,
+          "g": func1,
+        }
+        "#
+    )
+}
+
+fn exported_func(module: &Module, name: &str) -> Func {
+    let cx = module.cx();
+    match module.exports[&ExportKey::LinkName(cx.intern(name))] {
+        Exportee::Func(func) => func,
+        Exportee::GlobalVar(_) => unreachable!(),
+    }
+}
+
+fn func_def_body_mut(module: &mut Module, func: Func) -> &mut FuncDefBody {
+    match &mut module.funcs[func].def {
+        DeclDef::Present(func_def_body) => func_def_body,
+        DeclDef::Imported(_) => unreachable!(),
+    }
+}
+
+/// Get the first instruction in the body of `func`.
+fn first_inst(module: &Module, func: Func) -> DataInst {
+    let func_def_body = match &module.funcs[func].def {
+        DeclDef::Present(func_def_body) => func_def_body,
+        DeclDef::Imported(_) => unreachable!(),
+    };
+    func_def_body
+        .at_body()
+        .at_children()
+        .into_iter()
+        .find_map(|func_at_node| match func_at_node.def().kind {
+            ControlNodeKind::Block { insts } => insts.iter().first,
+            _ => None,
+        })
+        .unwrap()
+}
+
+fn verify_messages(module: &Module) -> Vec<String> {
+    match verify_module(module) {
+        Ok(()) => vec![],
+        Err(e) => e.diagnostics.iter().map(|d| d.to_string()).collect(),
+    }
+}
+
+#[test]
+fn valid_module_verifies() {
+    let cx = Rc::new(Context::new());
+    assert_eq!(
+        verify_messages(&two_funcs_module(&cx)),
+        Vec::<String>::new()
+    );
+}
+
+#[test]
+fn use_of_another_funcs_inst_output_is_reported() {
+    let cx = Rc::new(Context::new());
+    let mut module = two_funcs_module(&cx);
+    let (f, g) = (exported_func(&module, "f"), exported_func(&module, "g"));
+
+    // Make `g` use the output of the instruction in `f`, instead of its own.
+    let f_inst = first_inst(&module, f);
+    let g_inst = first_inst(&module, g);
+    func_def_body_mut(&mut module, g).data_insts[g_inst].inputs[0] = Value::DataInstOutput(f_inst);
+
+    assert_eq!(
+        verify_messages(&module),
+        ["instruction: use of instruction output not defined in this function"]
+    );
+}
+
+#[test]
+fn use_of_another_funcs_region_input_is_reported() {
+    let cx = Rc::new(Context::new());
+    let mut module = two_funcs_module(&cx);
+    let (f, g) = (exported_func(&module, "f"), exported_func(&module, "g"));
+
+    // Make `g` return the parameter of `f`, instead of its own result.
+    let f_param = Value::ControlRegionInput {
+        region: func_def_body_mut(&mut module, f).body,
+        input_idx: 0,
+    };
+    let g_func_def_body = func_def_body_mut(&mut module, g);
+    let g_body = g_func_def_body.body;
+    g_func_def_body.control_regions[g_body].outputs[0] = f_param;
+
+    assert_eq!(
+        verify_messages(&module),
+        ["control region: use of region input #0 not defined in this function"]
+    );
+}
+
+#[test]
+fn use_of_nonexistent_output_is_reported() {
+    let cx = Rc::new(Context::new());
+    let mut module = two_funcs_module(&cx);
+    let f = exported_func(&module, "f");
+
+    let f_func_def_body = func_def_body_mut(&mut module, f);
+    let f_body = f_func_def_body.body;
+    f_func_def_body.control_regions[f_body].outputs[0] = Value::ControlRegionInput {
+        region: f_body,
+        input_idx: 1,
+    };
+
+    assert_eq!(
+        verify_messages(&module),
+        ["control region: use of nonexistent region input #1"]
+    );
+}